@@ -0,0 +1,85 @@
+//! Compares running one module over many inputs through the batch paths,
+//! which bootstrap a worker and evaluate the module once, with independent
+//! runs that pay for both on every call. Run with `cargo bench --bench
+//! batch`; fails when batching isn't at least ten times faster.
+
+use experimental_runtime::{run_batch, run_insecure, run_many, BatchOptions, Inputs, RunOptions};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+const INVOCATIONS: usize = 1000;
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let function = dir.path().join("add.js");
+    std::fs::write(
+        &function,
+        "export function main({ a, b }) { return a + b; }",
+    )
+    .unwrap();
+    let inputs = (0..INVOCATIONS)
+        .map(|i| json!({ "a": i, "b": 1 }))
+        .collect::<Vec<_>>();
+    let expected = (1..=INVOCATIONS).map(Value::from).collect::<Vec<_>>();
+
+    let (results, many) = timed(|| {
+        run_many(
+            function.clone(),
+            inputs
+                .iter()
+                .map(|value| Inputs::from_serializable(value).unwrap()),
+            RunOptions::default(),
+        )
+        .unwrap()
+    });
+    let results = results.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(results, expected);
+
+    let (report, batch) =
+        timed(|| run_batch(function.clone(), inputs.clone(), BatchOptions::default()).unwrap());
+    let results = report
+        .items
+        .into_iter()
+        .map(|item| item.result.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(results, expected);
+
+    let (results, independent) = timed(|| {
+        inputs
+            .iter()
+            .map(|value| {
+                let inputs = Inputs::from_serializable(value).unwrap();
+                run_insecure(function.clone(), inputs).unwrap()
+            })
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(results, expected);
+
+    for (name, elapsed) in [
+        ("run_many", many),
+        ("run_batch", batch),
+        ("run_insecure", independent),
+    ] {
+        println!(
+            "{:<13} {:>10.1?} total {:>10.1?} per invocation",
+            name,
+            elapsed,
+            elapsed / INVOCATIONS as u32
+        );
+    }
+    for (name, elapsed) in [("run_many", many), ("run_batch", batch)] {
+        assert!(
+            elapsed * 10 <= independent,
+            "{} took {:?}, not ten times less than {:?} for independent runs",
+            name,
+            elapsed,
+            independent
+        );
+    }
+}
+
+fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let started = Instant::now();
+    let result = f();
+    (result, started.elapsed())
+}
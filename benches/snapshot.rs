@@ -0,0 +1,49 @@
+//! Compares cold starts of workers bootstrapped from scratch with workers
+//! started from a snapshot. Run with `cargo bench --bench snapshot`; fails
+//! when the snapshot doesn't make them faster.
+
+use experimental_runtime::{run_with_options, Inputs, RunOptions, Snapshot};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 50;
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let function = dir.path().join("hello.js");
+    std::fs::write(&function, "export function main() { return 'hello'; }").unwrap();
+
+    let started = Instant::now();
+    let snapshot = Snapshot::build().unwrap();
+    println!("building the snapshot took {:.1?}", started.elapsed());
+
+    let fresh = cold_starts(&function, RunOptions::default());
+    let snapshotted = cold_starts(
+        &function,
+        RunOptions {
+            snapshot: Some(snapshot),
+            ..Default::default()
+        },
+    );
+    for (name, elapsed) in [("fresh", fresh), ("snapshot", snapshotted)] {
+        println!("{:<9} {:>10.1?} per run", name, elapsed / RUNS);
+    }
+    assert!(
+        snapshotted < fresh,
+        "runs from the snapshot took {:?}, runs without {:?}",
+        snapshotted,
+        fresh
+    );
+}
+
+fn cold_starts(function: &Path, options: RunOptions) -> Duration {
+    // The first run warms up the platform and the file cache.
+    run_with_options(function.to_path_buf(), Inputs::new(), options.clone()).unwrap();
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        let value =
+            run_with_options(function.to_path_buf(), Inputs::new(), options.clone()).unwrap();
+        assert_eq!(value, "hello");
+    }
+    started.elapsed()
+}
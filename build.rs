@@ -0,0 +1,51 @@
+// Records the versions of the embedded engines and the target triple for
+// `runtime_info()`.
+fn main() {
+    let lockfile =
+        std::path::Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.lock");
+    println!("cargo:rerun-if-changed={}", lockfile.display());
+    let lock = std::fs::read_to_string(&lockfile).unwrap_or_default();
+
+    for (krate, var) in [
+        ("deno_core", "RUNTIME_DENO_CORE_VERSION"),
+        ("deno_runtime", "RUNTIME_DENO_RUNTIME_VERSION"),
+        ("deno_ast", "RUNTIME_DENO_AST_VERSION"),
+    ] {
+        let version = locked_version(&lock, krate).unwrap_or("unknown");
+        println!("cargo:rustc-env={}={}", var, version);
+    }
+    println!(
+        "cargo:rustc-env=RUNTIME_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+// Generates the gRPC service, with a vendored protoc so none needs to be
+// installed.
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    println!("cargo:rerun-if-changed=proto/invocation.proto");
+    let protoc = protoc_bin_vendored::protoc_bin_path().unwrap();
+    let include = protoc_bin_vendored::include_path().unwrap();
+    std::env::set_var("PROTOC", protoc);
+    let out_dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    tonic_build::configure()
+        .file_descriptor_set_path(out_dir.join("invocation_descriptor.bin"))
+        .compile_protos(
+            &["proto/invocation.proto"],
+            &[std::path::Path::new("proto"), &include],
+        )
+        .unwrap();
+}
+
+fn locked_version<'a>(lock: &'a str, krate: &str) -> Option<&'a str> {
+    let name = format!("name = \"{}\"", krate);
+    let mut lines = lock.lines();
+    lines.find(|line| *line == name)?;
+    lines
+        .next()?
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+}
@@ -0,0 +1,275 @@
+use anyhow::{anyhow, bail, Context, Error};
+use std::path::Path;
+
+use crate::embedded::EmbeddedModules;
+
+const BLOCK: usize = 512;
+
+/// Reads a `.tar`, `.tar.gz`/`.tgz` or `.zip` archive into modules served
+/// as `archive:///path/in/archive.ts`. Set the result as
+/// `RunOptions::embedded` and name the entry module, relative to the
+/// archive root, as the function path. Nothing is unpacked to disk.
+///
+/// Entries escaping the archive root and corrupted archives are rejected
+/// here rather than when a module is first imported.
+pub fn open_archive(path: &Path) -> Result<EmbeddedModules, Error> {
+    let data = std::fs::read(path).with_context(|| format!("could not read {}", path.display()))?;
+    let name = path.to_string_lossy().to_ascii_lowercase();
+
+    let entries = if name.ends_with(".zip") {
+        read_zip(&data)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        gunzip(&data).and_then(|data| read_tar(&data))
+    } else {
+        read_tar(&data)
+    }
+    .with_context(|| format!("invalid module archive {}", path.display()))?;
+
+    let mut modules = EmbeddedModules::with_scheme("archive");
+    for (name, data) in entries {
+        let Some(name) = entry_path(&name)? else {
+            continue;
+        };
+        match String::from_utf8(data) {
+            Ok(code) => {
+                modules.insert(&name, code);
+            }
+            Err(_) => log::debug!("skipping non-utf-8 archive entry {}", name),
+        }
+    }
+    Ok(modules)
+}
+
+/// Path of a file entry relative to the archive root, `None` for
+/// directories.
+pub(crate) fn entry_path(name: &str) -> Result<Option<String>, Error> {
+    let name = name.replace('\\', "/");
+    if name.ends_with('/') {
+        return Ok(None);
+    }
+    let mut parts = vec![];
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => bail!("archive entry {} escapes the archive root", name),
+            part => parts.push(part),
+        }
+    }
+    if parts.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(parts.join("/")))
+}
+
+pub(crate) fn read_tar(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let mut entries = vec![];
+    let mut offset = 0;
+    let mut long_name = None;
+    while offset + BLOCK <= data.len() {
+        let header = &data[offset..offset + BLOCK];
+        if header.iter().all(|b| *b == 0) {
+            return Ok(entries);
+        }
+        let checksum = octal(&header[148..156])?;
+        let sum = header
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                if (148..156).contains(&i) {
+                    32
+                } else {
+                    *b as u64
+                }
+            })
+            .sum::<u64>();
+        if checksum != sum {
+            bail!("tar header at offset {} has a bad checksum", offset);
+        }
+
+        let size = octal(&header[124..136])? as usize;
+        let start = offset + BLOCK;
+        let end = start
+            .checked_add(size)
+            .filter(|end| *end <= data.len())
+            .ok_or_else(|| anyhow!("tar entry at offset {} is truncated", offset))?;
+        let body = &data[start..end];
+        offset = start + size.div_ceil(BLOCK) * BLOCK;
+
+        match header[156] {
+            // GNU long name, applies to the next entry.
+            b'L' => long_name = Some(c_string(body)),
+            b'x' => long_name = pax_path(body).or(long_name),
+            b'0' | 0 => {
+                let name = long_name.take().unwrap_or_else(|| {
+                    let name = c_string(&header[0..100]);
+                    let prefix = c_string(&header[345..500]);
+                    if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                        format!("{}/{}", prefix, name)
+                    } else {
+                        name
+                    }
+                });
+                entries.push((name, body.to_vec()));
+            }
+            _ => long_name = None,
+        }
+    }
+    bail!("tar archive ends without an end-of-archive marker")
+}
+
+/// Writes `entries` as a ustar archive, with GNU long name records for
+/// paths that don't fit the header.
+#[cfg(feature = "typescript")]
+pub(crate) fn write_tar<'a>(entries: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> Vec<u8> {
+    let mut out = vec![];
+    for (name, body) in entries {
+        if name.len() > 100 {
+            let mut long_name = name.as_bytes().to_vec();
+            long_name.push(0);
+            write_entry(&mut out, "././@LongLink", b'L', &long_name);
+        }
+        write_entry(&mut out, name, b'0', body);
+    }
+    out.resize(out.len() + 2 * BLOCK, 0);
+    out
+}
+
+#[cfg(feature = "typescript")]
+fn write_entry(out: &mut Vec<u8>, name: &str, kind: u8, body: &[u8]) {
+    let mut header = [0u8; BLOCK];
+    let name = &name.as_bytes()[..name.len().min(100)];
+    header[..name.len()].copy_from_slice(name);
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{:011o}\0", body.len()).as_bytes());
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[156] = kind;
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[148..156].fill(b' ');
+    let checksum = header.iter().map(|b| *b as u64).sum::<u64>();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    out.extend_from_slice(&header);
+    out.extend_from_slice(body);
+    out.resize(out.len().div_ceil(BLOCK) * BLOCK, 0);
+}
+
+fn octal(field: &[u8]) -> Result<u64, Error> {
+    let text = c_string(field);
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| anyhow!("invalid tar number {:?}", text))
+}
+
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// The `path` record of a pax extended header.
+fn pax_path(body: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(body).ok()?;
+    text.lines().find_map(|line| {
+        let (_, record) = line.split_once(' ')?;
+        record.strip_prefix("path=").map(String::from)
+    })
+}
+
+#[cfg(feature = "gzip")]
+pub(crate) fn gunzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    use std::io::Read;
+    let mut out = vec![];
+    flate2::read::GzDecoder::new(data)
+        .read_to_end(&mut out)
+        .context("could not decompress gzip data")?;
+    Ok(out)
+}
+
+#[cfg(all(feature = "gzip", feature = "typescript"))]
+pub(crate) fn gzip(data: &[u8]) -> Result<Vec<u8>, Error> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(all(not(feature = "gzip"), feature = "typescript"))]
+pub(crate) fn gzip(_data: &[u8]) -> Result<Vec<u8>, Error> {
+    bail!("gzip archives need the gzip feature")
+}
+
+#[cfg(not(feature = "gzip"))]
+pub(crate) fn gunzip(_data: &[u8]) -> Result<Vec<u8>, Error> {
+    bail!("gzip archives need the gzip feature")
+}
+
+#[cfg(feature = "zip")]
+fn read_zip(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    use std::io::Read;
+
+    let u16_at = |at: usize| -> Result<usize, Error> {
+        data.get(at..at + 2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]) as usize)
+            .ok_or_else(|| anyhow!("zip archive is truncated"))
+    };
+    let u32_at = |at: usize| -> Result<usize, Error> {
+        data.get(at..at + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .ok_or_else(|| anyhow!("zip archive is truncated"))
+    };
+
+    // The end of central directory record sits before an optional comment.
+    let eocd = (0..data.len().saturating_sub(21))
+        .rev()
+        .find(|at| data[*at..].starts_with(&[0x50, 0x4b, 0x05, 0x06]))
+        .ok_or_else(|| anyhow!("no zip end of central directory record"))?;
+    let count = u16_at(eocd + 10)?;
+    let mut at = u32_at(eocd + 16)?;
+
+    let mut entries = vec![];
+    for _ in 0..count {
+        if u32_at(at)? != 0x0201_4b50 {
+            bail!("bad zip central directory entry at offset {}", at);
+        }
+        let method = u16_at(at + 10)?;
+        let compressed = u32_at(at + 20)?;
+        let size = u32_at(at + 24)?;
+        let name_len = u16_at(at + 28)?;
+        let extra_len = u16_at(at + 30)?;
+        let comment_len = u16_at(at + 32)?;
+        let local = u32_at(at + 42)?;
+        let name = data
+            .get(at + 46..at + 46 + name_len)
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .ok_or_else(|| anyhow!("zip archive is truncated"))?;
+        at += 46 + name_len + extra_len + comment_len;
+
+        if u32_at(local)? != 0x0403_4b50 {
+            bail!("bad zip local header for {}", name);
+        }
+        let start = local + 30 + u16_at(local + 26)? + u16_at(local + 28)?;
+        let body = data
+            .get(start..start + compressed)
+            .ok_or_else(|| anyhow!("zip entry {} is truncated", name))?;
+        let body = match method {
+            0 => body.to_vec(),
+            8 => {
+                let mut out = Vec::with_capacity(size);
+                flate2::read::DeflateDecoder::new(body)
+                    .read_to_end(&mut out)
+                    .with_context(|| format!("could not decompress zip entry {}", name))?;
+                out
+            }
+            method => bail!("zip entry {} uses unsupported compression {}", name, method),
+        };
+        entries.push((name, body));
+    }
+    Ok(entries)
+}
+
+#[cfg(not(feature = "zip"))]
+fn read_zip(_data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    bail!("zip archives need the zip feature")
+}
@@ -0,0 +1,183 @@
+use anyhow::{anyhow, Error};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::console::{ConsoleEvent, ConsoleSink};
+use crate::error::RuntimeError;
+use crate::inputs::Inputs;
+use crate::options::RunOptions;
+use crate::{redact, schema, uncaught, worker};
+
+type Checkpoint = dyn FnMut(&BatchReport) + Send;
+
+#[derive(Default)]
+pub struct BatchOptions {
+    pub run: RunOptions,
+    /// Time limit for each item, `run.timeout` when unset.
+    pub timeout: Option<Duration>,
+    /// Stop at the first failed item.
+    pub fail_fast: bool,
+    /// Collect each item's console calls into its outcome. Replaces
+    /// `run.console` for the batch.
+    pub capture_console: bool,
+    /// Called with the report so far after every `checkpoint_every` items.
+    pub checkpoint: Option<Box<Checkpoint>>,
+    pub checkpoint_every: usize,
+}
+
+impl fmt::Debug for BatchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchOptions")
+            .field("run", &self.run)
+            .field("timeout", &self.timeout)
+            .field("fail_fast", &self.fail_fast)
+            .field("capture_console", &self.capture_console)
+            .field("checkpoint_every", &self.checkpoint_every)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchItem {
+    pub index: usize,
+    pub result: Result<Value, Error>,
+    pub duration: Duration,
+    pub console: Vec<ConsoleEvent>,
+}
+
+#[derive(Debug, Default)]
+pub struct BatchReport {
+    pub items: Vec<BatchItem>,
+    /// Index of the first item that failed in a way that may have left
+    /// module-level state inconsistent. Later results ran on that state, or
+    /// on a reloaded module if the worker had to be replaced.
+    pub state_corrupted_at: Option<usize>,
+    /// Whether `fail_fast` stopped the batch before all items ran.
+    pub stopped_early: bool,
+}
+
+/// Runs the function on each item in order on one evaluated module, so
+/// module-level state carries over between items. Item failures are
+/// recorded in the report, only loading the module fails the batch.
+pub fn run_batch(
+    function: PathBuf,
+    inputs: impl IntoIterator<Item = Value>,
+    mut options: BatchOptions,
+) -> Result<BatchReport, Error> {
+    let output_schema = options
+        .run
+        .output_schema
+        .as_ref()
+        .map(|s| schema::compile(s, options.run.strict_schema))
+        .transpose()?;
+
+    let events = Arc::new(Mutex::new(vec![]));
+    if options.capture_console {
+        let events = events.clone();
+        options.run.console = Some(ConsoleSink::callback(move |event| {
+            events.lock().unwrap().push(event)
+        }));
+    }
+    let run = &options.run;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let mut module = worker::load(&function, run).await?;
+        let mut report = BatchReport::default();
+        for (index, item) in inputs.into_iter().enumerate() {
+            events.lock().unwrap().clear();
+            let started = Instant::now();
+
+            if let Err(e) = worker::take_uncaught(&mut module).await {
+                let event = uncaught::UncaughtEvent::new(&e, module.id, false, true);
+                uncaught::report(run.on_uncaught.as_ref(), event);
+                report.state_corrupted_at.get_or_insert(index);
+                worker::reload(&mut module, run).await?;
+            }
+
+            let inputs = batch_inputs(item).and_then(|inputs| {
+                inputs.check(run)?;
+                Ok(inputs)
+            });
+            let result = match inputs {
+                Ok(inputs) => {
+                    let redactor = redact::Redactor::new(run, &inputs);
+                    let result = match worker::call_with_timeout(
+                        &mut module,
+                        inputs,
+                        run,
+                        options.timeout.or(run.timeout),
+                    )
+                    .await
+                    {
+                        Ok(f) => {
+                            crate::output(&mut module.worker, f, run, output_schema.as_deref())
+                        }
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = &result {
+                        if corrupts_state(e) {
+                            report.state_corrupted_at.get_or_insert(index);
+                        }
+                        if worker::corrupts_worker(e) {
+                            let event = uncaught::UncaughtEvent::new(e, module.id, true, true);
+                            uncaught::report(run.on_uncaught.as_ref(), event);
+                            worker::reload(&mut module, run).await?;
+                        }
+                    }
+                    result.map_err(|e| redactor.redact_error(e))
+                }
+                Err(e) => Err(e),
+            };
+
+            let failed = result.is_err();
+            report.items.push(BatchItem {
+                index,
+                result,
+                duration: started.elapsed(),
+                console: std::mem::take(&mut *events.lock().unwrap()),
+            });
+            if options.checkpoint_every > 0 && report.items.len() % options.checkpoint_every == 0 {
+                if let Some(checkpoint) = &mut options.checkpoint {
+                    checkpoint(&report);
+                }
+            }
+            if failed && options.fail_fast {
+                report.stopped_early = true;
+                break;
+            }
+        }
+        Ok(report)
+    })
+}
+
+fn batch_inputs(item: Value) -> Result<Inputs, Error> {
+    match item {
+        Value::Object(map) => Ok(map.into_iter().collect::<HashMap<_, _>>().into()),
+        _ => Err(anyhow!("batch item must be a JSON object of inputs")),
+    }
+}
+
+/// Errors raised while checking a result the function completed normally
+/// leave its state as the function left it. Exceptions, timeouts and
+/// worker failures may have interrupted it halfway.
+fn corrupts_state(error: &Error) -> bool {
+    match error.downcast_ref::<RuntimeError>() {
+        Some(error) => matches!(
+            error,
+            RuntimeError::Timeout { .. }
+                | RuntimeError::BudgetExceeded { .. }
+                | RuntimeError::HeapLimitExceeded { .. }
+                | RuntimeError::JsException { .. }
+                | RuntimeError::PermissionDenied { .. }
+                | RuntimeError::Cancelled
+        ),
+        None => true,
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::{bail, Context, Error};
+use deno_ast::dep::{DependencyDescriptor, DependencyKind, DynamicArgument, ImportAttributes};
+use deno_ast::{MediaType, ParseParams, TextChange};
+use deno_core::{
+    ModuleLoadResponse, ModuleLoader, ModuleSpecifier, ModuleType, RequestedModuleType,
+    ResolutionKind,
+};
+use sha2::Digest;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::archive;
+use crate::embedded::EmbeddedModules;
+use crate::imports::ImportGraph;
+use crate::options::RunOptions;
+use crate::transpile::SourceKind;
+use crate::worker;
+
+/// A function's import graph, remote and npm modules included, compiled to
+/// JavaScript with every import pointing inside the bundle, so it runs
+/// without the network.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    /// Path of the function module inside the bundle.
+    pub entry: String,
+    /// Module sources by their path inside the bundle. Modules outside the
+    /// function's directory are kept under `vendor/`.
+    pub modules: BTreeMap<String, String>,
+}
+
+impl Bundle {
+    /// The modules to set as `RunOptions::embedded`, with
+    /// [`entry`](Self::entry) as the function path.
+    pub fn to_embedded(&self) -> EmbeddedModules {
+        let mut modules = EmbeddedModules::new();
+        for (path, code) in &self.modules {
+            modules.insert_transpiled(path, code.clone());
+        }
+        modules
+    }
+
+    /// The bundle as a tar archive, which [`open_archive`](crate::open_archive)
+    /// reads back.
+    pub fn to_tar(&self) -> Vec<u8> {
+        archive::write_tar(
+            self.modules
+                .iter()
+                .map(|(path, code)| (path.as_str(), code.as_bytes())),
+        )
+    }
+
+    /// Writes the archive to `path`, gzipped when it ends in `.tar.gz` or
+    /// `.tgz`.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let name = path.to_string_lossy().to_ascii_lowercase();
+        let data = if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            archive::gzip(&self.to_tar())?
+        } else if name.ends_with(".zip") {
+            bail!("bundles are written as tar archives, not zip")
+        } else {
+            self.to_tar()
+        };
+        std::fs::write(path, data).with_context(|| format!("could not write {}", path.display()))
+    }
+}
+
+/// A module as loaded, and the ranges of its import specifiers with what
+/// they resolved to.
+struct Module {
+    specifier: ModuleSpecifier,
+    found: ModuleSpecifier,
+    module_type: ModuleType,
+    code: String,
+    imports: Vec<(Range<usize>, ModuleSpecifier)>,
+}
+
+/// Loads the function's import graph through its configured loader,
+/// without evaluating anything, and packs it into a [`Bundle`]. Dynamic
+/// imports of string literals are bundled too, computed ones are left as
+/// they are and logged.
+pub fn bundle(function: &Path, options: &RunOptions) -> Result<Bundle, Error> {
+    let (entry, loader, imports) = worker::module_loader(function, options, None)?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let graph = runtime.block_on(walk(&entry, loader.as_ref(), &imports))?;
+
+    let root = entry.join("./")?;
+    let mut used = HashSet::new();
+    let paths = graph
+        .iter()
+        .map(|module| {
+            let path = bundle_path(module, &root, &mut used);
+            (module.specifier.clone(), path)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut modules = BTreeMap::new();
+    for module in graph {
+        let changes = module
+            .imports
+            .iter()
+            .map(|(range, target)| TextChange {
+                range: range.clone(),
+                new_text: serde_json::to_string(&format!("/{}", paths[target])).unwrap(),
+            })
+            .collect();
+        let code = deno_ast::apply_text_changes(&module.code, changes);
+        modules.insert(paths[&module.specifier].clone(), code);
+    }
+    Ok(Bundle {
+        entry: paths[&entry].clone(),
+        modules,
+    })
+}
+
+async fn walk(
+    entry: &ModuleSpecifier,
+    loader: &dyn ModuleLoader,
+    imports: &ImportGraph,
+) -> Result<Vec<Module>, Error> {
+    let mut graph = vec![];
+    let mut seen = HashSet::from([entry.clone()]);
+    let mut queue = VecDeque::from([(entry.clone(), RequestedModuleType::None, None)]);
+    while let Some((specifier, requested, referrer)) = queue.pop_front() {
+        log::debug!("bundling {}", specifier);
+        let source = match loader.load(&specifier, referrer.as_ref(), false, requested) {
+            ModuleLoadResponse::Sync(source) => source,
+            ModuleLoadResponse::Async(source) => source.await,
+        }?;
+        let found = imports
+            .found(&specifier)
+            .unwrap_or_else(|| specifier.clone());
+        let code = String::from_utf8(source.code.as_bytes().to_vec())
+            .with_context(|| format!("{} is not utf-8", specifier))?;
+
+        let mut module = Module {
+            specifier,
+            found,
+            module_type: source.module_type,
+            code,
+            imports: vec![],
+        };
+        match &module.module_type {
+            ModuleType::JavaScript => {
+                for (range, raw, requested, kind) in dependencies(&module.specifier, &module.code)?
+                {
+                    let target = loader.resolve(&raw, module.found.as_str(), kind)?;
+                    if seen.insert(target.clone()) {
+                        queue.push_back((
+                            target.clone(),
+                            requested,
+                            Some(module.specifier.clone()),
+                        ));
+                    }
+                    module.imports.push((range, target));
+                }
+            }
+            ModuleType::Json => {}
+            other => bail!(
+                "{} is a {} module, which can't be bundled",
+                module.specifier,
+                other
+            ),
+        }
+        graph.push(module);
+    }
+    Ok(graph)
+}
+
+/// Range of the specifier literal, the specifier, and how it is imported.
+type Dependency = (Range<usize>, String, RequestedModuleType, ResolutionKind);
+
+/// Imports of a compiled module.
+fn dependencies(specifier: &ModuleSpecifier, code: &str) -> Result<Vec<Dependency>, Error> {
+    let parsed = deno_ast::parse_module(ParseParams {
+        specifier: specifier.clone(),
+        text: code.into(),
+        media_type: MediaType::JavaScript,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })?;
+    let start = parsed.text_info_lazy().range().start;
+    let mut dependencies = vec![];
+    for dependency in parsed.analyze_dependencies() {
+        match dependency {
+            DependencyDescriptor::Static(dependency)
+                if matches!(
+                    dependency.kind,
+                    DependencyKind::Import | DependencyKind::Export
+                ) =>
+            {
+                dependencies.push((
+                    dependency.specifier_range.as_byte_range(start),
+                    dependency.specifier.to_string(),
+                    requested_type(&dependency.import_attributes),
+                    ResolutionKind::Import,
+                ));
+            }
+            DependencyDescriptor::Dynamic(dependency) => match &dependency.argument {
+                DynamicArgument::String(raw) => dependencies.push((
+                    dependency.argument_range.as_byte_range(start),
+                    raw.to_string(),
+                    requested_type(&dependency.import_attributes),
+                    ResolutionKind::DynamicImport,
+                )),
+                _ => log::warn!(
+                    "computed dynamic import in {} is left out of the bundle",
+                    specifier
+                ),
+            },
+            DependencyDescriptor::Static(_) => {}
+        }
+    }
+    Ok(dependencies)
+}
+
+fn requested_type(attributes: &ImportAttributes) -> RequestedModuleType {
+    match attributes.get("type").map(String::as_str) {
+        Some("json") => RequestedModuleType::Json,
+        _ => RequestedModuleType::None,
+    }
+}
+
+/// Where a module goes in the bundle: relative to the function's directory
+/// when it is inside it, under `vendor/` otherwise. Compiled modules get a
+/// `.js` suffix so they aren't compiled again when loaded.
+fn bundle_path(module: &Module, root: &ModuleSpecifier, used: &mut HashSet<String>) -> String {
+    let url = &module.found;
+    let base = match url.as_str().strip_prefix(root.as_str()) {
+        Some(relative) if url.query().is_none() && url.fragment().is_none() => relative.to_string(),
+        _ => match (url.scheme(), url.host_str()) {
+            ("file", _) => format!("vendor/files{}", url.path()),
+            (scheme, Some(host)) => {
+                let host = match url.port() {
+                    Some(port) => format!("{}_{}", host, port),
+                    None => host.to_string(),
+                };
+                let mut path = format!("vendor/{}/{}{}", scheme, host, url.path());
+                if let Some(query) = url.query() {
+                    path = format!("{}_{}", path, short_hash(query));
+                }
+                path
+            }
+            (scheme, None) => format!("vendor/{}/{}", scheme, short_hash(url.as_str())),
+        },
+    };
+    let base = match base.ends_with('/') || base.is_empty() {
+        true => format!("{}index", base),
+        false => base,
+    };
+
+    for n in 1.. {
+        let mut path = match n {
+            1 => base.clone(),
+            n => format!("{}-{}", base, n),
+        };
+        if module.module_type == ModuleType::JavaScript
+            && SourceKind::from_path(&path) != SourceKind::JavaScript
+        {
+            path.push_str(".js");
+        }
+        if used.insert(path.clone()) {
+            return path;
+        }
+    }
+    unreachable!()
+}
+
+fn short_hash(text: &str) -> String {
+    let digest = format!("{:x}", sha2::Sha256::digest(text.as_bytes()));
+    digest[..12].to_string()
+}
@@ -0,0 +1,288 @@
+use anyhow::{anyhow, Context, Error};
+use deno_core::ModuleSpecifier;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::compress::{self, DiskUsage, Encoding};
+use crate::fetch::{self, Fetched, Validators};
+use crate::stats::{CacheCounters, CacheEvent, CacheStats, StatsCollector};
+
+/// How the [`ModuleCache`] is consulted for remote modules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// Serve cached modules, fetching only those not cached yet.
+    #[default]
+    UseCache,
+    /// Revalidate modules cached longer ago than the duration, with their
+    /// `ETag` or `Last-Modified` when the server sent one. The stale copy
+    /// is served when revalidating fails.
+    RevalidateIfStale(Duration),
+    /// Never fetch, modules that aren't cached fail to load.
+    Offline,
+    /// Always fetch, without reading or writing the cache.
+    Bypass,
+}
+
+/// Remote modules kept on disk between runs, keyed by the SHA-256 of their
+/// URL. Each entry is the body plus a JSON file with the URLs visited
+/// through redirects, the `Content-Type` and when it was fetched. Large
+/// bodies are compressed with the `zstd` feature.
+#[derive(Debug, Clone)]
+pub struct ModuleCache {
+    dir: PathBuf,
+    policy: CachePolicy,
+    counters: CacheCounters,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Metadata {
+    urls: Vec<String>,
+    content_type: Option<String>,
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+    #[serde(default)]
+    validators: Validators,
+    #[serde(default, skip_serializing_if = "Encoding::is_plain")]
+    encoding: Encoding,
+    /// Length of the body before encoding.
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+struct Cached {
+    fetched: Fetched,
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+    encoding: Encoding,
+}
+
+impl ModuleCache {
+    /// Cache in `$XDG_CACHE_HOME/experimental_runtime/deps`, falling back to
+    /// `~/.cache` when the variable is unset.
+    pub fn new(policy: CachePolicy) -> Result<Self, Error> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .ok_or_else(|| anyhow!("no cache directory, neither XDG_CACHE_HOME nor HOME is set"))?;
+        Ok(Self::in_dir(base.join("experimental_runtime/deps"), policy))
+    }
+
+    pub fn in_dir(dir: impl Into<PathBuf>, policy: CachePolicy) -> Self {
+        Self {
+            dir: dir.into(),
+            policy,
+            counters: CacheCounters::default(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn policy(&self) -> CachePolicy {
+        self.policy
+    }
+
+    /// Lookups since the cache was created or last reset, and what it
+    /// holds on disk. Clones share the counts.
+    pub fn stats(&self) -> CacheStats {
+        let usage = self.disk_usage().unwrap_or_else(|e| {
+            log::debug!("could not measure the module cache: {:#}", e);
+            DiskUsage::default()
+        });
+        self.counters.stats(usage.entries as u64, usage.disk_bytes)
+    }
+
+    pub fn reset_stats(&self) {
+        self.counters.reset()
+    }
+
+    /// The cache, also counting lookups into the stats of a run.
+    pub(crate) fn for_run(&self, stats: Option<&StatsCollector>) -> Self {
+        Self {
+            counters: self
+                .counters
+                .for_run(stats, |stats| &mut stats.module_cache),
+            ..self.clone()
+        }
+    }
+
+    pub(crate) async fn fetch(
+        &self,
+        client: &fetch::HttpClient,
+        specifier: &ModuleSpecifier,
+    ) -> Result<Fetched, Error> {
+        let max_age = match self.policy {
+            CachePolicy::Bypass => return fetch::fetch(client, specifier).await,
+            CachePolicy::UseCache | CachePolicy::Offline => None,
+            CachePolicy::RevalidateIfStale(max_age) => Some(max_age),
+        };
+        let cached = self.read(specifier).await;
+        let stale = match &cached {
+            Some(cached) => max_age.is_some_and(|max_age| age(cached.fetched_at) > max_age),
+            None if self.policy == CachePolicy::Offline => {
+                self.counters.record(CacheEvent::Miss);
+                return Err(anyhow!(
+                    "{} is not cached and the cache is offline",
+                    specifier
+                ));
+            }
+            None => true,
+        };
+        if !stale {
+            log::debug!("serving {} from the module cache", specifier);
+            self.counters.record(CacheEvent::Hit);
+            return Ok(cached.unwrap().fetched);
+        }
+
+        let refetched = match &cached {
+            Some(cached) => {
+                fetch::fetch_if_modified(client, specifier, &cached.fetched.validators).await
+            }
+            None => fetch::fetch(client, specifier).await.map(Some),
+        };
+        match refetched {
+            Ok(Some(fetched)) => {
+                self.counters.record(CacheEvent::Miss);
+                match self.write(specifier, &fetched).await {
+                    Ok(()) => {
+                        if cached.is_some() {
+                            self.counters.record(CacheEvent::Eviction);
+                        }
+                        self.counters.record(CacheEvent::Insertion);
+                    }
+                    Err(e) => log::warn!("could not cache {}: {:#}", specifier, e),
+                }
+                Ok(fetched)
+            }
+            Ok(None) => {
+                log::debug!("{} was not modified", specifier);
+                self.counters.record(CacheEvent::Hit);
+                let cached = cached.unwrap();
+                let written = self
+                    .write_metadata(specifier, &cached.fetched, cached.encoding)
+                    .await;
+                if let Err(e) = written {
+                    log::warn!("could not cache {}: {:#}", specifier, e);
+                }
+                Ok(cached.fetched)
+            }
+            Err(e) => match cached {
+                Some(cached) => {
+                    log::warn!("serving stale {}, refetching failed: {:#}", specifier, e);
+                    self.counters.record(CacheEvent::Hit);
+                    Ok(cached.fetched)
+                }
+                None => {
+                    self.counters.record(CacheEvent::Miss);
+                    Err(e)
+                }
+            },
+        }
+    }
+
+    fn paths(&self, specifier: &ModuleSpecifier) -> (PathBuf, PathBuf) {
+        let key = format!("{:x}", sha2::Sha256::digest(specifier.as_str()));
+        (
+            self.dir.join(&key),
+            self.dir.join(format!("{}.metadata.json", key)),
+        )
+    }
+
+    /// Entry count and sizes of the bodies in the cache, as stored and as
+    /// they were fetched.
+    pub fn disk_usage(&self) -> Result<DiskUsage, Error> {
+        let mut usage = DiskUsage::default();
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(usage),
+            Err(e) => return Err(e).context(format!("could not read {}", self.dir.display())),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let Some(key) = path.to_str().and_then(|p| p.strip_suffix(".metadata.json")) else {
+                continue;
+            };
+            let Ok(body) = std::fs::metadata(key) else {
+                continue;
+            };
+            let metadata: Option<Metadata> = std::fs::read(&path)
+                .ok()
+                .and_then(|m| serde_json::from_slice(&m).ok());
+            usage.entries += 1;
+            usage.disk_bytes += body.len();
+            usage.logical_bytes += metadata.and_then(|m| m.size).unwrap_or(body.len());
+        }
+        Ok(usage)
+    }
+
+    async fn read(&self, specifier: &ModuleSpecifier) -> Option<Cached> {
+        let (body_path, metadata_path) = self.paths(specifier);
+        let metadata = tokio::fs::read(&metadata_path).await.ok()?;
+        let metadata: Metadata = serde_json::from_slice(&metadata).ok()?;
+        let body = tokio::fs::read(&body_path).await.ok()?;
+        let body = compress::decode(metadata.encoding, body)?;
+        let urls = metadata
+            .urls
+            .iter()
+            .map(|url| ModuleSpecifier::parse(url))
+            .collect::<Result<Vec<_>, _>>()
+            .ok()?;
+        if urls.first() != Some(specifier) {
+            return None;
+        }
+        let fetched = Fetched {
+            urls,
+            body,
+            content_type: metadata.content_type,
+            validators: metadata.validators,
+        };
+        Some(Cached {
+            fetched,
+            fetched_at: metadata.fetched_at,
+            encoding: metadata.encoding,
+        })
+    }
+
+    async fn write(&self, specifier: &ModuleSpecifier, fetched: &Fetched) -> Result<(), Error> {
+        let (body_path, _) = self.paths(specifier);
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("could not create {}", self.dir.display()))?;
+        let (encoding, body) = compress::encode(&fetched.body);
+        tokio::fs::write(&body_path, body).await?;
+        self.write_metadata(specifier, fetched, encoding).await
+    }
+
+    /// Also marks the entry as fetched now.
+    async fn write_metadata(
+        &self,
+        specifier: &ModuleSpecifier,
+        fetched: &Fetched,
+        encoding: Encoding,
+    ) -> Result<(), Error> {
+        let (_, metadata_path) = self.paths(specifier);
+        let metadata = Metadata {
+            urls: fetched.urls.iter().map(|url| url.to_string()).collect(),
+            content_type: fetched.content_type.clone(),
+            fetched_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            validators: fetched.validators.clone(),
+            encoding,
+            size: Some(fetched.body.len() as u64),
+        };
+        tokio::fs::write(&metadata_path, serde_json::to_vec(&metadata)?).await?;
+        Ok(())
+    }
+}
+
+fn age(fetched_at: u64) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    now.saturating_sub(Duration::from_secs(fetched_at))
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use deno_core::v8;
+use tokio::sync::Notify;
+
+/// Cancels a run from any thread. Set it as `RunOptions::cancellation`;
+/// `cancel()` stops the running script and the run fails with
+/// `RuntimeError::Cancelled`. Cancelling before the run starts makes it
+/// fail straight away, cancelling after it finished does nothing. Once
+/// cancelled, a handle stays cancelled, so use a new one per run.
+#[derive(Clone, Default)]
+pub struct CancellationHandle(Arc<Inner>);
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    isolate: Mutex<Option<v8::IsolateHandle>>,
+    notify: Notify,
+}
+
+impl CancellationHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        if self.0.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Some(isolate) = &*self.0.isolate.lock().unwrap() {
+            isolate.terminate_execution();
+        }
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Makes `cancel()` terminate `isolate`, which runs the script from now
+    /// on, until the returned guard is dropped.
+    pub(crate) fn attach(&self, isolate: v8::IsolateHandle) -> Attached<'_> {
+        *self.0.isolate.lock().unwrap() = Some(isolate);
+        Attached(self)
+    }
+
+    fn detach(&self) {
+        self.0.isolate.lock().unwrap().take();
+    }
+
+    /// Resolves once the handle is cancelled.
+    pub(crate) async fn cancelled(&self) {
+        let notified = self.0.notify.notified();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// Detaches the isolate from its handle when dropped, as a warm worker
+/// outlives the run and a late `cancel()` must not stop whatever it runs
+/// next.
+pub(crate) struct Attached<'a>(&'a CancellationHandle);
+
+impl Drop for Attached<'_> {
+    fn drop(&mut self) {
+        self.0.detach();
+    }
+}
+
+impl fmt::Debug for CancellationHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CancellationHandle")
+            .field(&self.is_cancelled())
+            .finish()
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Error};
+use deno_core::ModuleSpecifier;
+use encoding_rs::{Encoding, UTF_8};
+
+/// `charset` parameter of a `Content-Type` header value.
+#[cfg(feature = "net-loader")]
+pub(crate) fn from_content_type(content_type: &str) -> Option<&str> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Decodes module source text. A byte order mark wins over the declared
+/// charset, sources with neither are read as UTF-8. Latin-1 labels decode as
+/// windows-1252, as browsers do.
+pub(crate) fn decode(
+    bytes: &[u8],
+    charset: Option<&str>,
+    specifier: &ModuleSpecifier,
+) -> Result<String, Error> {
+    let encoding = match charset {
+        Some(label) => Encoding::for_label(label.as_bytes())
+            .ok_or_else(|| anyhow!("unsupported charset {:?} for {}", label, specifier))?,
+        None => UTF_8,
+    };
+    let (text, encoding, _) = encoding.decode(bytes);
+    log::debug!("decoded {} as {}", specifier, encoding.name());
+    Ok(text.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specifier() -> ModuleSpecifier {
+        ModuleSpecifier::parse("https://example.com/mod.ts").unwrap()
+    }
+
+    fn utf16(text: &str, big_endian: bool, bom: bool) -> Vec<u8> {
+        let mut bytes = vec![];
+        for unit in bom.then_some(0xfeff).into_iter().chain(text.encode_utf16()) {
+            match big_endian {
+                true => bytes.extend(unit.to_be_bytes()),
+                false => bytes.extend(unit.to_le_bytes()),
+            }
+        }
+        bytes
+    }
+
+    #[cfg(feature = "net-loader")]
+    #[test]
+    fn charset_parameter() {
+        let charset = from_content_type("application/typescript; charset=utf-16le");
+        assert_eq!(charset, Some("utf-16le"));
+        let charset = from_content_type("text/javascript;Charset=\"ISO-8859-1\"");
+        assert_eq!(charset, Some("ISO-8859-1"));
+        assert_eq!(from_content_type("application/javascript"), None);
+    }
+
+    #[test]
+    fn declared_charsets() {
+        let text = "export const greeting = \"héllo wörld\";";
+        for (bytes, charset) in [
+            (utf16(text, false, false), "utf-16le"),
+            (utf16(text, true, false), "UTF-16BE"),
+            (text.as_bytes().to_vec(), "utf-8"),
+        ] {
+            assert_eq!(decode(&bytes, Some(charset), &specifier()).unwrap(), text);
+        }
+        let latin1 = b"export const name = \"caf\xe9\";";
+        assert_eq!(
+            decode(latin1, Some("latin1"), &specifier()).unwrap(),
+            "export const name = \"café\";"
+        );
+    }
+
+    #[test]
+    fn byte_order_marks_win() {
+        let text = "export const greeting = \"héllo\";";
+        for big_endian in [false, true] {
+            let bytes = utf16(text, big_endian, true);
+            assert_eq!(decode(&bytes, None, &specifier()).unwrap(), text);
+            assert_eq!(decode(&bytes, Some("utf-8"), &specifier()).unwrap(), text);
+        }
+        let bytes = [b"\xef\xbb\xbf", text.as_bytes()].concat();
+        assert_eq!(decode(&bytes, None, &specifier()).unwrap(), text);
+    }
+
+    #[test]
+    fn unknown_charsets_name_the_module() {
+        let error = decode(b"", Some("klingon"), &specifier()).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("klingon"), "{}", message);
+        assert!(
+            message.contains("https://example.com/mod.ts"),
+            "{}",
+            message
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use anyhow::{anyhow, Error};
+use deno_core::error::JsError;
+use deno_core::{
+    v8, JsRuntime, ModuleId, ModuleLoadResponse, ModuleLoader, ModuleSource, ModuleSourceCode,
+    ModuleSpecifier, ModuleType, RequestedModuleType, ResolutionKind, RuntimeOptions,
+};
+use futures::FutureExt;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::error::RuntimeError;
+use crate::options::{Entrypoint, RunOptions};
+use crate::platform::PlatformGuard;
+use crate::worker;
+
+/// Stands in for imports that could not be resolved, so loading carries on
+/// with the rest of the graph.
+const UNRESOLVED: &str = "experimental-runtime:unresolved";
+
+/// Outcome of [`check`].
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckReport {
+    pub entry: ModuleSpecifier,
+    /// Modules of the static import graph that loaded, sorted.
+    pub modules: Vec<ModuleSpecifier>,
+    /// Export the entrypoint would use, `None` when the module lacks it or
+    /// could not be linked.
+    pub export: Option<String>,
+    /// Whether the export is a function. Only function declarations are
+    /// initialized before evaluation, `None` for exports bound otherwise.
+    pub callable: Option<bool>,
+    pub problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// Module the problem is in, when it is known.
+    pub specifier: Option<ModuleSpecifier>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    Resolution,
+    Load,
+    Syntax,
+    Entrypoint,
+}
+
+/// Loads, compiles and links the function's static import graph and looks
+/// up the entrypoint export, without evaluating any module. Problems are
+/// collected rather than returned as errors: a module that fails to
+/// resolve, load or parse is replaced by an empty one and the rest of the
+/// graph is still checked. Parse errors in JavaScript are only collected
+/// for every module with the `typescript` feature, otherwise V8 reports
+/// the first one.
+pub fn check(function: &Path, options: &RunOptions) -> Result<CheckReport, Error> {
+    let (entry, inner, _) = worker::module_loader(function, options, None)?;
+    let loader = Rc::new(CheckingLoader {
+        inner,
+        problems: Rc::default(),
+        modules: Rc::default(),
+    });
+
+    let _platform = PlatformGuard::acquire()?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (export, callable) = runtime.block_on(async {
+        let mut js_runtime = JsRuntime::new(RuntimeOptions {
+            module_loader: Some(loader.clone()),
+            ..Default::default()
+        });
+        let loaded = js_runtime.load_main_es_module(&entry).await;
+        if !loader.problems.borrow().is_empty() {
+            // Linking against the stand-ins fails for reasons already reported.
+            return Ok((None, None));
+        }
+        match loaded {
+            Ok(mod_id) => entrypoint(&mut js_runtime, mod_id, &entry, options, &loader),
+            Err(e) => {
+                let kind = match e.is::<JsError>() {
+                    true => ProblemKind::Syntax,
+                    false => ProblemKind::Load,
+                };
+                loader.report(kind, None, &e);
+                Ok((None, None))
+            }
+        }
+    })?;
+
+    let mut modules = loader.modules.take();
+    modules.sort();
+    Ok(CheckReport {
+        entry,
+        modules,
+        export,
+        callable,
+        problems: loader.problems.take(),
+    })
+}
+
+/// Finds the export the entrypoint uses in the linked module and whether
+/// it can be called.
+fn entrypoint(
+    js_runtime: &mut JsRuntime,
+    mod_id: ModuleId,
+    entry: &ModuleSpecifier,
+    options: &RunOptions,
+    loader: &CheckingLoader,
+) -> Result<(Option<String>, Option<bool>), Error> {
+    let namespace = js_runtime.get_module_namespace(mod_id)?;
+    let scope = &mut js_runtime.handle_scope();
+    let namespace = v8::Local::new(scope, namespace);
+    let available = worker::export_names(scope, namespace);
+
+    let mut export = options.entrypoint.export_name();
+    // `main` falls back to the default export, as in a run.
+    if matches!(options.entrypoint, Entrypoint::MainFunction)
+        && !available.iter().any(|name| name == export)
+    {
+        export = "default";
+    }
+    if !available.iter().any(|name| name == export) {
+        let missing = RuntimeError::MissingEntrypoint {
+            export: options.entrypoint.export_name().to_string(),
+            available,
+        };
+        loader.report(ProblemKind::Entrypoint, Some(entry), &missing.into());
+        return Ok((None, None));
+    }
+
+    let key = v8::String::new(scope, export).ok_or(anyhow!("could not create key"))?;
+    // Reading a binding that is not initialized yet throws.
+    let scope = &mut v8::TryCatch::new(scope);
+    let callable = match namespace.get(scope, key.into()) {
+        Some(value) if value.is_function() => Some(true),
+        Some(value) if value.is_undefined() || value.is_object() => None,
+        Some(_) => Some(false),
+        None => None,
+    };
+    if callable == Some(false) && options.entrypoint.arguments().is_some() {
+        let message = anyhow!("{} export is not a function", export);
+        loader.report(ProblemKind::Entrypoint, Some(entry), &message);
+    }
+    Ok((Some(export.to_string()), callable))
+}
+
+/// Loads through the function's own loader, recording what fails and
+/// substituting an empty module for it.
+struct CheckingLoader {
+    inner: Rc<dyn ModuleLoader>,
+    problems: Rc<RefCell<Vec<Problem>>>,
+    modules: Rc<RefCell<Vec<ModuleSpecifier>>>,
+}
+
+impl CheckingLoader {
+    fn report(&self, kind: ProblemKind, specifier: Option<&ModuleSpecifier>, error: &Error) {
+        report(&self.problems, kind, specifier, error);
+    }
+}
+
+fn report(
+    problems: &RefCell<Vec<Problem>>,
+    kind: ProblemKind,
+    specifier: Option<&ModuleSpecifier>,
+    error: &Error,
+) {
+    problems.borrow_mut().push(Problem {
+        kind,
+        specifier: specifier.cloned(),
+        message: format!("{:#}", error),
+    });
+}
+
+impl ModuleLoader for CheckingLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+        self.inner.resolve(specifier, referrer, kind).or_else(|e| {
+            let referrer = ModuleSpecifier::parse(referrer).ok();
+            self.report(ProblemKind::Resolution, referrer.as_ref(), &e);
+            Ok(ModuleSpecifier::parse(UNRESOLVED)?)
+        })
+    }
+
+    fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        maybe_referrer: Option<&ModuleSpecifier>,
+        is_dyn_import: bool,
+        requested_module_type: RequestedModuleType,
+    ) -> ModuleLoadResponse {
+        if module_specifier.as_str() == UNRESOLVED {
+            return ModuleLoadResponse::Sync(Ok(empty_module(
+                module_specifier,
+                &requested_module_type,
+            )));
+        }
+        let loading = self.inner.load(
+            module_specifier,
+            maybe_referrer,
+            is_dyn_import,
+            requested_module_type.clone(),
+        );
+        let specifier = module_specifier.clone();
+        let problems = self.problems.clone();
+        let modules = self.modules.clone();
+        ModuleLoadResponse::Async(
+            async move {
+                let loaded = match loading {
+                    ModuleLoadResponse::Sync(loaded) => loaded,
+                    ModuleLoadResponse::Async(loading) => loading.await,
+                };
+                let problem = match &loaded {
+                    Ok(source) => {
+                        syntax_error(&specifier, source).map(|e| (ProblemKind::Syntax, e))
+                    }
+                    Err(e) => Some((load_kind(e), anyhow!("{:#}", e))),
+                };
+                if let Some((kind, e)) = problem {
+                    report(&problems, kind, Some(&specifier), &e);
+                    return Ok(empty_module(&specifier, &requested_module_type));
+                }
+                modules.borrow_mut().push(specifier);
+                loaded
+            }
+            .boxed_local(),
+        )
+    }
+
+    fn get_source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+        self.inner.get_source_map(file_name)
+    }
+}
+
+fn empty_module(specifier: &ModuleSpecifier, requested: &RequestedModuleType) -> ModuleSource {
+    let (module_type, code) = match requested {
+        RequestedModuleType::Json => (ModuleType::Json, "null"),
+        _ => (ModuleType::JavaScript, "export {};"),
+    };
+    ModuleSource::new(
+        module_type,
+        ModuleSourceCode::Bytes(code.as_bytes().to_vec().into_boxed_slice().into()),
+        specifier,
+        None,
+    )
+}
+
+/// Loads fail with a parse error when compiling TypeScript or JSX does.
+fn load_kind(error: &Error) -> ProblemKind {
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::Transpile { .. }) => ProblemKind::Syntax,
+        _ => ProblemKind::Load,
+    }
+}
+
+fn syntax_error(specifier: &ModuleSpecifier, source: &ModuleSource) -> Option<Error> {
+    let code = source.code.as_bytes();
+    match source.module_type {
+        ModuleType::Json => serde_json::from_slice::<serde::de::IgnoredAny>(code)
+            .err()
+            .map(|e| anyhow!("{} is not valid JSON: {}", specifier, e)),
+        ModuleType::JavaScript => javascript_syntax_error(specifier, code),
+        _ => None,
+    }
+}
+
+#[cfg(feature = "typescript")]
+fn javascript_syntax_error(specifier: &ModuleSpecifier, code: &[u8]) -> Option<Error> {
+    use deno_ast::{MediaType, ParseParams};
+
+    let parsed = deno_ast::parse_module(ParseParams {
+        specifier: specifier.clone(),
+        text: String::from_utf8_lossy(code).into(),
+        media_type: MediaType::JavaScript,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    });
+    match parsed {
+        Ok(parsed) => parsed
+            .diagnostics()
+            .first()
+            .map(|diagnostic| anyhow!("{}", diagnostic)),
+        Err(diagnostic) => Some(anyhow!("{}", diagnostic)),
+    }
+}
+
+/// Without a parser of our own V8 reports syntax errors while compiling.
+#[cfg(not(feature = "typescript"))]
+fn javascript_syntax_error(_specifier: &ModuleSpecifier, _code: &[u8]) -> Option<Error> {
+    None
+}
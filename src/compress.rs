@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Entries smaller than this are stored as they are, compressing them
+/// saves too little to pay for the work.
+#[cfg(feature = "zstd")]
+const THRESHOLD: usize = 4 * 1024;
+
+#[cfg(feature = "zstd")]
+const LEVEL: i32 = 3;
+
+/// First bytes of a zstd frame.
+const MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// How a cache entry is stored on disk, recorded next to it. Entries
+/// written before compression existed have no record and are plain.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Encoding {
+    #[default]
+    Plain,
+    Zstd,
+}
+
+impl Encoding {
+    pub(crate) fn is_plain(&self) -> bool {
+        *self == Encoding::Plain
+    }
+}
+
+/// `bytes` as they are best stored, compressed with the `zstd` feature
+/// when they are large enough.
+pub(crate) fn encode(bytes: &[u8]) -> (Encoding, Cow<'_, [u8]>) {
+    #[cfg(feature = "zstd")]
+    if bytes.len() >= THRESHOLD {
+        match zstd::bulk::compress(bytes, LEVEL) {
+            Ok(compressed) if compressed.len() < bytes.len() => {
+                return (Encoding::Zstd, Cow::Owned(compressed));
+            }
+            Ok(_) => {}
+            Err(e) => log::debug!("storing uncompressed, compressing failed: {}", e),
+        }
+    }
+    (Encoding::Plain, Cow::Borrowed(bytes))
+}
+
+/// The bytes `encode` was given, `None` when they can't be recovered,
+/// which callers treat as a cache miss.
+pub(crate) fn decode(encoding: Encoding, bytes: Vec<u8>) -> Option<Vec<u8>> {
+    match encoding {
+        Encoding::Plain => Some(bytes),
+        #[cfg(feature = "zstd")]
+        Encoding::Zstd => match zstd::stream::decode_all(bytes.as_slice()) {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                log::debug!("ignoring cache entry, decompressing failed: {}", e);
+                None
+            }
+        },
+        #[cfg(not(feature = "zstd"))]
+        Encoding::Zstd => {
+            log::debug!("ignoring compressed cache entry, the zstd feature is off");
+            None
+        }
+    }
+}
+
+/// How `bytes` are encoded, for files that carry no record of it.
+pub(crate) fn sniff(bytes: &[u8]) -> Encoding {
+    match bytes.starts_with(&MAGIC) {
+        true => Encoding::Zstd,
+        false => Encoding::Plain,
+    }
+}
+
+/// Length of `bytes` once decoded, as far as the frame header tells.
+pub(crate) fn logical_len(bytes: &[u8]) -> Option<u64> {
+    match sniff(bytes) {
+        Encoding::Plain => Some(bytes.len() as u64),
+        #[cfg(feature = "zstd")]
+        Encoding::Zstd => zstd::zstd_safe::get_frame_content_size(bytes)
+            .ok()
+            .flatten(),
+        #[cfg(not(feature = "zstd"))]
+        Encoding::Zstd => None,
+    }
+}
+
+/// Size of a disk cache, as stored and as its entries were written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub entries: usize,
+    /// Bytes the entries take on disk.
+    pub disk_bytes: u64,
+    /// Bytes the entries hold once decompressed.
+    pub logical_bytes: u64,
+}
+
+#[cfg(all(test, feature = "zstd"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_entries_round_trip_compressed() {
+        let code = "export const x = 1;\n".repeat(1000);
+        let (encoding, stored) = encode(code.as_bytes());
+        assert_eq!(encoding, Encoding::Zstd);
+        assert!(stored.len() < code.len());
+        assert_eq!(sniff(&stored), Encoding::Zstd);
+        assert_eq!(logical_len(&stored), Some(code.len() as u64));
+        assert_eq!(
+            decode(encoding, stored.into_owned()).unwrap(),
+            code.as_bytes()
+        );
+    }
+
+    #[test]
+    fn small_entries_stay_plain() {
+        let (encoding, stored) = encode(b"export const x = 1;");
+        assert!(encoding.is_plain());
+        assert_eq!(&*stored, b"export const x = 1;");
+    }
+
+    #[test]
+    fn corrupt_entries_are_misses() {
+        let mut corrupt = MAGIC.to_vec();
+        corrupt.extend_from_slice(b"not a frame");
+        assert_eq!(decode(Encoding::Zstd, corrupt), None);
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::redact::Redactor;
+use crate::warning::{Warning, Warnings};
+
+/// Per-event size cap when `RunOptions::console_event_limit` is unset.
+pub(crate) const DEFAULT_EVENT_LIMIT: usize = 64 * 1024;
+
+/// Where a console call was made, as reported by its stack frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallSite {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// A captured console call. Arguments are kept as JSON where they
+/// serialize, other values become placeholders such as
+/// `{"$type": "Function", "name": "foo"}`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsoleEvent {
+    /// The console method, `log`, `error`, `table`, `group` and so on.
+    pub level: String,
+    /// The arguments formatted the way Deno prints them, format specifiers
+    /// such as `%s` applied.
+    pub text: String,
+    pub args: Vec<Value>,
+    pub location: Option<CallSite>,
+    /// Milliseconds since the Unix epoch when the call was made.
+    pub timestamp: u64,
+    /// Counts calls on the worker, 0 while the module is evaluated.
+    pub invocation: u64,
+    /// `console.group` nesting at the time of the call.
+    pub group_depth: u32,
+    /// Data passed to `console.table`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<Value>,
+    /// Whether arguments were replaced by `{"$truncated": bytes}` markers
+    /// to fit the size cap.
+    pub truncated: bool,
+}
+
+impl ConsoleEvent {
+    /// Whether Deno would have printed the call to stderr rather than
+    /// stdout.
+    pub fn is_stderr(&self) -> bool {
+        matches!(self.level.as_str(), "warn" | "error" | "trace")
+    }
+}
+
+type Callback = dyn Fn(ConsoleEvent) + Send + Sync;
+
+/// Receives console events instead of stdout and stderr.
+#[derive(Clone)]
+pub enum ConsoleSink {
+    Callback(Arc<Callback>),
+    /// One JSON event per line.
+    Ndjson(Arc<Mutex<dyn Write + Send>>),
+    /// The formatted text on stdout or stderr, like Deno prints it.
+    Stdio,
+    /// Like `Stdio`, but all on stderr, leaving stdout to the result.
+    Stderr,
+}
+
+impl ConsoleSink {
+    pub fn callback(callback: impl Fn(ConsoleEvent) + Send + Sync + 'static) -> Self {
+        Self::Callback(Arc::new(callback))
+    }
+
+    pub fn ndjson(writer: impl Write + Send + 'static) -> Self {
+        Self::Ndjson(Arc::new(Mutex::new(writer)))
+    }
+
+    fn emit(&self, event: ConsoleEvent) -> std::io::Result<()> {
+        match self {
+            ConsoleSink::Callback(callback) => {
+                callback(event);
+                Ok(())
+            }
+            ConsoleSink::Ndjson(writer) => {
+                let mut writer = writer.lock().unwrap();
+                serde_json::to_writer(&mut *writer, &event)?;
+                writer.write_all(b"\n")
+            }
+            ConsoleSink::Stdio | ConsoleSink::Stderr => {
+                let indent = "  ".repeat(event.group_depth as usize);
+                let text = event.text.replace('\n', &format!("\n{}", indent));
+                if event.is_stderr() || matches!(self, ConsoleSink::Stderr) {
+                    writeln!(std::io::stderr(), "{}{}", indent, text)
+                } else {
+                    writeln!(std::io::stdout(), "{}{}", indent, text)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ConsoleSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsoleSink::Callback(_) => f.write_str("ConsoleSink::Callback"),
+            ConsoleSink::Ndjson(_) => f.write_str("ConsoleSink::Ndjson"),
+            ConsoleSink::Stdio => f.write_str("ConsoleSink::Stdio"),
+            ConsoleSink::Stderr => f.write_str("ConsoleSink::Stderr"),
+        }
+    }
+}
+
+/// Console capture state kept in the worker's op state.
+pub(crate) struct ConsoleCapture {
+    pub(crate) sink: Option<ConsoleSink>,
+    pub(crate) event_limit: usize,
+    pub(crate) invocation: u64,
+    pub(crate) warnings: Warnings,
+    pub(crate) redactor: Redactor,
+}
+
+/// An event as reported by the JS side.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RawEvent {
+    level: String,
+    text: String,
+    args: Vec<Value>,
+    location: Option<CallSite>,
+    group_depth: u32,
+    table: Option<Value>,
+}
+
+impl ConsoleCapture {
+    pub(crate) fn emit(&self, raw: RawEvent) -> Result<(), Error> {
+        let Some(sink) = &self.sink else {
+            return Ok(());
+        };
+        let mut budget = self.event_limit;
+        let mut truncated = false;
+        let mut fit = |value: Value| {
+            let size = serde_json::to_vec(&value).map_or(0, |v| v.len());
+            if size <= budget {
+                budget -= size;
+                value
+            } else {
+                truncated = true;
+                budget = 0;
+                json!({ "$truncated": size })
+            }
+        };
+        let mut args: Vec<Value> = raw.args.into_iter().map(&mut fit).collect();
+        let mut table = raw.table.map(&mut fit);
+        if !self.redactor.is_empty() {
+            args.iter_mut()
+                .for_each(|arg| self.redactor.redact_json(arg));
+            table
+                .iter_mut()
+                .for_each(|table| self.redactor.redact_json(table));
+        }
+        let text = if raw.text.len() > self.event_limit {
+            truncated = true;
+            let mut end = self.event_limit;
+            while !raw.text.is_char_boundary(end) {
+                end -= 1;
+            }
+            raw.text[..end].to_string()
+        } else {
+            raw.text
+        };
+        if truncated {
+            self.warnings.emit(Warning::ConsoleTruncated {
+                level: raw.level.clone(),
+                limit: self.event_limit,
+            })?;
+        }
+        let written = sink.emit(ConsoleEvent {
+            level: raw.level,
+            text: self.redactor.redact(&text),
+            args,
+            location: raw.location,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            invocation: self.invocation,
+            group_depth: raw.group_depth,
+            table,
+            truncated,
+        });
+        if let Err(e) = written {
+            self.warnings.emit(Warning::ConsoleWriteFailed {
+                message: e.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+}
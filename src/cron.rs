@@ -0,0 +1,279 @@
+use anyhow::{anyhow, bail, Error};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+/// How far ahead to look for a matching time, enough to reach the next
+/// February 29th.
+const HORIZON: u64 = 9 * 366 * 24 * 60 * 60;
+
+/// A five field cron expression, `minute hour day-of-month month
+/// day-of-week`, evaluated in UTC. Fields take `*`, values, `a-b` ranges,
+/// `/step` and comma separated lists, months and weekdays also their
+/// three letter English names. Sunday is 0 or 7. When both day fields are
+/// restricted, a day matching either one matches, as in Vixie cron, where
+/// a field starting with `*`, like `*/2`, is not restricted.
+/// `@yearly`, `@monthly`, `@weekly`, `@daily` and `@hourly` are accepted
+/// too.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expression: &str) -> Result<Self, Error> {
+        let expanded = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields = expanded.split_whitespace().collect::<Vec<_>>();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            bail!("cron expression {:?} does not have 5 fields", expression);
+        };
+        let field = |text: &str, name: &str, min: u32, max: u32, names: &[&str]| {
+            parse_field(text, min, max, names).map_err(|e| {
+                anyhow!(
+                    "invalid {} in cron expression {:?}: {}",
+                    name,
+                    expression,
+                    e
+                )
+            })
+        };
+        let mut weekdays = field(weekday, "day of week", 0, 7, &WEEKDAYS)?;
+        if weekdays & 1 << 7 != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Cron {
+            expression: expression.trim().to_string(),
+            minutes: field(minute, "minute", 0, 59, &[])?,
+            hours: field(hour, "hour", 0, 23, &[])?,
+            days: field(day, "day of month", 1, 31, &[])?,
+            months: field(month, "month", 1, 12, &MONTHS)?,
+            weekdays,
+            // Like Vixie cron, `*/2` counts as unrestricted too.
+            any_day: day.starts_with('*'),
+            any_weekday: weekday.starts_with('*'),
+        })
+    }
+
+    /// The first matching minute strictly after `after`, or `None` when
+    /// the expression never matches, like `0 0 30 2 *`.
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let start = after
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut time = (start / 60 + 1) * 60;
+        while time - start <= HORIZON {
+            let days = (time / 86_400) as i64;
+            let (year, month, day) = civil_from_days(days);
+            let hour = time % 86_400 / 3_600;
+            let minute = time % 3_600 / 60;
+            if self.months & 1 << month == 0 {
+                let (year, month) = match month {
+                    12 => (year + 1, 1),
+                    month => (year, month + 1),
+                };
+                time = days_from_civil(year, month, 1) as u64 * 86_400;
+            } else if !self.day_matches(day, (days + 4).rem_euclid(7) as u32) {
+                time = (days as u64 + 1) * 86_400;
+            } else if self.hours & 1 << hour == 0 {
+                time = (time / 3_600 + 1) * 3_600;
+            } else if self.minutes & 1 << minute == 0 {
+                time += 60;
+            } else {
+                return Some(UNIX_EPOCH + Duration::from_secs(time));
+            }
+        }
+        None
+    }
+
+    fn day_matches(&self, day: u32, weekday: u32) -> bool {
+        let by_day = self.days & 1 << day != 0;
+        let by_weekday = self.weekdays & 1 << weekday != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => by_day || by_weekday,
+            _ => by_day && by_weekday,
+        }
+    }
+}
+
+impl FromStr for Cron {
+    type Err = Error;
+
+    fn from_str(expression: &str) -> Result<Self, Error> {
+        Self::parse(expression)
+    }
+}
+
+impl fmt::Debug for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Cron").field(&self.expression).finish()
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+/// The values a field matches, as a bitset.
+fn parse_field(text: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, Error> {
+    let value = |text: &str| -> Result<u32, Error> {
+        let value = match names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(text))
+        {
+            Some(index) => index as u32 + min,
+            None => text
+                .parse()
+                .map_err(|_| anyhow!("{:?} is not a number", text))?,
+        };
+        if !(min..=max).contains(&value) {
+            bail!("{} is outside {}-{}", value, min, max);
+        }
+        Ok(value)
+    };
+
+    let mut bits = 0;
+    for item in text.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => bail!("invalid step {:?}", step),
+            },
+            None => (item, 1),
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((first, last)) => (value(first)?, value(last)?),
+                // `5/15` runs from 5 to the end of the range.
+                None if step > 1 => (value(range)?, max),
+                None => {
+                    let value = value(range)?;
+                    (value, value)
+                }
+            },
+        };
+        if first > last {
+            bail!("range {:?} is backwards", range);
+        }
+        for value in (first..=last).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+/// Year, month and day of `days` since the Unix epoch, from Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Days since the Unix epoch of a date, the inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Seconds since the epoch of a UTC date and time.
+    fn at(year: i64, month: u32, day: u32, hour: u64, minute: u64) -> SystemTime {
+        let days = days_from_civil(year, month, day) as u64;
+        UNIX_EPOCH + Duration::from_secs(days * 86_400 + hour * 3_600 + minute * 60)
+    }
+
+    fn next(expression: &str, after: SystemTime) -> Option<SystemTime> {
+        Cron::parse(expression).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn steps_ranges_and_names() {
+        let start = at(2024, 1, 1, 0, 0);
+        assert_eq!(next("*/15 * * * *", start), Some(at(2024, 1, 1, 0, 15)));
+        assert_eq!(next("5/20 * * * *", start), Some(at(2024, 1, 1, 0, 5)));
+        assert_eq!(next("0 9-17 * * *", start), Some(at(2024, 1, 1, 9, 0)));
+        assert_eq!(next("0 0 1 mar *", start), Some(at(2024, 3, 1, 0, 0)));
+        // 2024-01-01 was a Monday.
+        assert_eq!(next("30 8 * * fri", start), Some(at(2024, 1, 5, 8, 30)));
+        assert_eq!(next("0 0 * * 7", start), Some(at(2024, 1, 7, 0, 0)));
+        assert_eq!(next("@monthly", start), Some(at(2024, 2, 1, 0, 0)));
+    }
+
+    #[test]
+    fn strictly_after() {
+        let start = at(2024, 1, 1, 12, 0);
+        assert_eq!(next("0 12 * * *", start), Some(at(2024, 1, 2, 12, 0)));
+    }
+
+    #[test]
+    fn leap_days_and_impossible_dates() {
+        let start = at(2025, 1, 1, 0, 0);
+        assert_eq!(next("0 0 29 2 *", start), Some(at(2028, 2, 29, 0, 0)));
+        assert_eq!(next("0 0 30 2 *", start), None);
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // The 15th, or any Monday.
+        let start = at(2024, 1, 2, 0, 0);
+        assert_eq!(next("0 0 15 * mon", start), Some(at(2024, 1, 8, 0, 0)));
+        // A stepped field starting with `*` does not count as restricted,
+        // so both have to match: an odd day that is a Monday.
+        assert_eq!(next("0 0 */2 * mon", start), Some(at(2024, 1, 15, 0, 0)));
+        assert_eq!(next("0 0 15 * */1", start), Some(at(2024, 1, 15, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for expression in [
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "* * * 13 *",
+            "* * * * 8",
+            "*/0 * * * *",
+            "10-5 * * * *",
+            "x * * * *",
+        ] {
+            assert!(Cron::parse(expression).is_err(), "{}", expression);
+        }
+    }
+}
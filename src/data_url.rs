@@ -0,0 +1,79 @@
+use anyhow::{anyhow, bail, Error};
+use base64::Engine;
+use deno_core::ModuleSpecifier;
+
+use crate::transpile::SourceKind;
+
+/// Payload of a `data:` module specifier.
+pub(crate) struct DataUrl {
+    pub(crate) kind: SourceKind,
+    pub(crate) charset: Option<String>,
+    pub(crate) body: Vec<u8>,
+}
+
+/// Parses `data:[<media type>][;charset=<charset>][;base64],<payload>`.
+/// Modules without a recognised media type are taken as JavaScript.
+pub(crate) fn parse(specifier: &ModuleSpecifier) -> Result<DataUrl, Error> {
+    let url = specifier.as_str();
+    let url = url.split_once('#').map_or(url, |(url, _)| url);
+    let (header, payload) = url
+        .strip_prefix("data:")
+        .and_then(|url| url.split_once(','))
+        .ok_or_else(|| anyhow!("data: URL has no comma before its payload"))?;
+
+    let mut params = header.split(';');
+    let media_type = params.next().unwrap_or_default().trim();
+    let mut charset = None;
+    let mut base64 = false;
+    for param in params {
+        let param = param.trim();
+        if param.eq_ignore_ascii_case("base64") {
+            base64 = true;
+        } else if let Some((name, value)) = param.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("charset") {
+                charset = Some(value.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+
+    let payload = percent_decode(payload)?;
+    let body = if base64 {
+        let payload: Vec<u8> = payload
+            .into_iter()
+            .filter(|b| !b.is_ascii_whitespace())
+            .collect();
+        base64::engine::general_purpose::STANDARD
+            .decode(payload)
+            .map_err(|e| anyhow!("invalid base64 in data: URL: {}", e))?
+    } else {
+        payload
+    };
+    Ok(DataUrl {
+        kind: SourceKind::from_content_type(media_type).unwrap_or(SourceKind::JavaScript),
+        charset,
+        body,
+    })
+}
+
+fn percent_decode(text: &str) -> Result<Vec<u8>, Error> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = bytes
+                .get(index + 1..index + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let Some(byte) = hex else {
+                bail!("invalid percent escape at byte {} of data: URL", index);
+            };
+            decoded.push(byte);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    Ok(decoded)
+}
@@ -0,0 +1,325 @@
+use anyhow::{Context, Error};
+use deno_core::{JsRuntime, ModuleSpecifier, RuntimeOptions};
+#[cfg(feature = "net-loader")]
+use deno_semver::npm::NpmPackageReqReference;
+use serde::Serialize;
+#[cfg(feature = "net-loader")]
+use serde_json::Value;
+use std::collections::BTreeMap;
+#[cfg(feature = "net-loader")]
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::options::RunOptions;
+use crate::platform::PlatformGuard;
+#[cfg(feature = "net-loader")]
+use crate::{fetch, npm::NpmCache};
+use crate::{file_url, worker, NetworkModuleLoader};
+
+/// Remote modules in a function's import graph, for auditing.
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyReport {
+    pub entry: ModuleSpecifier,
+    pub modules: Vec<RemoteModule>,
+    /// Module count and size per origin, sorted by origin.
+    pub origins: Vec<OriginSummary>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoteModule {
+    pub specifier: ModuleSpecifier,
+    /// Where the source was served from, after redirects.
+    pub url: ModuleSpecifier,
+    pub origin: String,
+    /// Version pinned in a `name@version` path segment, `null` when the URL
+    /// carries none.
+    pub version: Option<String>,
+    pub sha256: String,
+    pub size: u64,
+    /// `null` when no license could be found.
+    pub license: Option<License>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct License {
+    pub id: String,
+    /// Guessed from the source header rather than taken from registry
+    /// metadata.
+    pub heuristic: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OriginSummary {
+    pub origin: String,
+    pub modules: usize,
+    pub bytes: u64,
+}
+
+#[cfg(feature = "net-loader")]
+impl RemoteModule {
+    pub(crate) fn new(specifier: &ModuleSpecifier, url: &ModuleSpecifier, source: &[u8]) -> Self {
+        use sha2::Digest;
+        Self {
+            specifier: specifier.clone(),
+            url: url.clone(),
+            origin: url.origin().ascii_serialization(),
+            version: pinned_version(url),
+            sha256: format!("{:x}", sha2::Sha256::digest(source)),
+            size: source.len() as u64,
+            license: header_license(source),
+        }
+    }
+}
+
+/// Fetches the function's import graph, without evaluating anything, and
+/// describes every remote module in it.
+pub fn dependency_report(entry: &Path, options: &RunOptions) -> Result<DependencyReport, Error> {
+    let entry =
+        file_url::canonical_specifier(file_url::entry_specifier(entry)?, options.deny_symlinks)?;
+    #[allow(unused_mut)]
+    let mut loader = NetworkModuleLoader::default().with_follow_symlinks(!options.deny_symlinks);
+    #[cfg(feature = "net-loader")]
+    if let Some(cache) = &options.module_cache {
+        loader = loader.with_cache(cache.clone());
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(http) = &options.http {
+        loader = loader.with_http(http)?;
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(policy) = &options.import_policy {
+        loader = loader.with_import_policy(policy.clone());
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(cache) = &options.npm_cache {
+        loader = loader.with_npm_cache(cache.clone());
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(path) = &options.lockfile {
+        loader = loader.with_lockfile(path, options.lockfile_write)?;
+    }
+    #[cfg(feature = "git")]
+    if options.allow_mutable_git_refs {
+        loader = loader.with_mutable_git_refs();
+    }
+    if let Some(import_map) = &options.import_map {
+        loader = loader.with_import_map(import_map.clone());
+    }
+    for (specifier, source) in &options.virtual_modules {
+        loader.add_virtual_module(specifier.clone(), source.clone());
+    }
+    let imports = loader.imports.clone();
+
+    let _platform = PlatformGuard::acquire()?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let mut js_runtime = JsRuntime::new(RuntimeOptions {
+            module_loader: Some(Rc::new(loader)),
+            ..Default::default()
+        });
+        js_runtime.load_main_es_module(&entry).await
+    })?;
+
+    #[allow(unused_mut)]
+    let mut modules = imports.remote_modules();
+    #[cfg(feature = "net-loader")]
+    runtime.block_on(registry_licenses(&mut modules, options))?;
+    modules.sort_by(|a, b| a.specifier.cmp(&b.specifier));
+    let mut origins = BTreeMap::<String, OriginSummary>::new();
+    for module in &modules {
+        let summary = origins
+            .entry(module.origin.clone())
+            .or_insert_with(|| OriginSummary {
+                origin: module.origin.clone(),
+                modules: 0,
+                bytes: 0,
+            });
+        summary.modules += 1;
+        summary.bytes += module.size;
+    }
+    Ok(DependencyReport {
+        entry,
+        modules,
+        origins: origins.into_values().collect(),
+    })
+}
+
+/// Loads and compiles the import graphs of `modules`, paths or URLs,
+/// without evaluating them, which fills whatever caches `options` sets up.
+pub(crate) fn preload(modules: &[&Path], options: &RunOptions) -> Result<(), Error> {
+    let _platform = PlatformGuard::acquire()?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    for module in modules {
+        let (entry, loader, _) = worker::module_loader(module, options, None)?;
+        runtime
+            .block_on(async {
+                let mut js_runtime = JsRuntime::new(RuntimeOptions {
+                    module_loader: Some(loader),
+                    ..Default::default()
+                });
+                js_runtime.load_main_es_module(&entry).await
+            })
+            .with_context(|| format!("could not preload {}", entry))?;
+        log::debug!("preloaded {}", entry);
+    }
+    Ok(())
+}
+
+#[cfg(feature = "net-loader")]
+fn pinned_version(url: &ModuleSpecifier) -> Option<String> {
+    url.path_segments()?.find_map(|segment| {
+        let (name, version) = segment.rsplit_once('@')?;
+        let pinned = !name.is_empty() && version.starts_with(|c: char| c.is_ascii_digit());
+        pinned.then(|| version.to_string())
+    })
+}
+
+/// Takes the licenses of npm packages and jsr modules from the metadata
+/// of their registry, keeping the one found in the header when there is
+/// none. npm packages are counted under the registry's origin.
+#[cfg(feature = "net-loader")]
+async fn registry_licenses(
+    modules: &mut [RemoteModule],
+    options: &RunOptions,
+) -> Result<(), Error> {
+    let client = match &options.http {
+        Some(http) => fetch::HttpClient::new(http)?,
+        None => fetch::client(),
+    };
+    let npm = options.npm_cache.clone().or_else(|| NpmCache::new().ok());
+    let mut metadata = HashMap::<ModuleSpecifier, Option<Value>>::new();
+    for module in modules {
+        let Some(url) = metadata_url(module, npm.as_ref()) else {
+            continue;
+        };
+        if module.specifier.scheme() == "npm" {
+            module.origin = url.origin().ascii_serialization();
+        }
+        if !metadata.contains_key(&url) {
+            let fetched = match fetch::fetch(&client, &url).await {
+                Ok(fetched) => serde_json::from_slice(&fetched.body).ok(),
+                Err(e) => {
+                    log::debug!("no registry metadata for {}: {:#}", module.specifier, e);
+                    None
+                }
+            };
+            metadata.insert(url.clone(), fetched);
+        }
+        let license = metadata[&url]
+            .as_ref()
+            .and_then(|meta| registry_license(meta, module.version.as_deref()));
+        if let Some(id) = license {
+            module.license = Some(License {
+                id,
+                heuristic: false,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The npm packument of `npm:` modules, or the version metadata of modules
+/// served from jsr.io, as `https://jsr.io/@scope/name/1.2.3/mod.ts`.
+#[cfg(feature = "net-loader")]
+fn metadata_url(module: &RemoteModule, npm: Option<&NpmCache>) -> Option<ModuleSpecifier> {
+    if module.specifier.scheme() == "npm" {
+        let reference = NpmPackageReqReference::from_specifier(&module.specifier).ok()?;
+        return npm?.packument_url(&reference.req().name).ok();
+    }
+    if module.url.host_str() != Some("jsr.io") {
+        return None;
+    }
+    let mut segments = module.url.path_segments()?;
+    let (scope, name, version) = (segments.next()?, segments.next()?, segments.next()?);
+    if !scope.starts_with('@') || segments.next().is_none() {
+        return None;
+    }
+    let meta = format!("/{}/{}/{}_meta.json", scope, name, version);
+    module.url.join(&meta).ok()
+}
+
+/// `license` of the version, or of the package as a whole.
+#[cfg(feature = "net-loader")]
+fn registry_license(meta: &Value, version: Option<&str>) -> Option<String> {
+    let versioned = version.and_then(|version| meta.get("versions")?.get(version));
+    [versioned, Some(meta)]
+        .into_iter()
+        .flatten()
+        .find_map(|meta| match meta.get("license")? {
+            Value::String(id) => Some(id.clone()),
+            // Older packages spell it `{ "type": "MIT", "url": ... }`.
+            license => Some(license.get("type")?.as_str()?.to_string()),
+        })
+}
+
+/// Looks for an SPDX identifier or `@license` tag near the top of the
+/// source.
+#[cfg(feature = "net-loader")]
+fn header_license(source: &[u8]) -> Option<License> {
+    let header = String::from_utf8_lossy(&source[..source.len().min(4096)]);
+    header.lines().find_map(|line| {
+        let (_, rest) = line
+            .split_once("SPDX-License-Identifier:")
+            .or_else(|| line.split_once("@license"))?;
+        let id = rest
+            .trim()
+            .trim_end_matches("*/")
+            .split_whitespace()
+            .next()?;
+        Some(License {
+            id: id.to_string(),
+            heuristic: true,
+        })
+    })
+}
+
+#[cfg(all(test, feature = "net-loader"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn module(url: &str) -> RemoteModule {
+        let url = ModuleSpecifier::parse(url).unwrap();
+        RemoteModule::new(&url, &url, b"")
+    }
+
+    #[test]
+    fn jsr_modules_are_looked_up_in_their_version_metadata() {
+        let url = metadata_url(&module("https://jsr.io/@std/path/1.0.8/join.ts"), None);
+        assert_eq!(
+            url.unwrap().as_str(),
+            "https://jsr.io/@std/path/1.0.8_meta.json"
+        );
+        assert_eq!(
+            metadata_url(&module("https://jsr.io/@std/path/meta.json"), None),
+            None
+        );
+        assert_eq!(
+            metadata_url(&module("https://deno.land/std/path/mod.ts"), None),
+            None
+        );
+    }
+
+    #[test]
+    fn licenses_of_the_version_win_over_the_package() {
+        let meta = json!({
+            "license": "MIT",
+            "versions": { "1.0.0": { "license": { "type": "ISC" } }, "2.0.0": {} },
+        });
+        assert_eq!(
+            registry_license(&meta, Some("1.0.0")).as_deref(),
+            Some("ISC")
+        );
+        assert_eq!(
+            registry_license(&meta, Some("2.0.0")).as_deref(),
+            Some("MIT")
+        );
+        assert_eq!(registry_license(&meta, None).as_deref(), Some("MIT"));
+        assert_eq!(registry_license(&json!({}), Some("1.0.0")), None);
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Makes what a function reads from the host reproducible, so the same code
+/// and inputs produce the same output. `Math.random` is drawn from `seed`,
+/// `Date` and `performance.now` read a virtual clock, and APIs that can't
+/// be made reproducible, such as `crypto.getRandomValues` or
+/// `Deno.hostname`, throw `Deno.errors.NotSupported`.
+///
+/// The generator and the clock restart at every invocation, including each
+/// call to a warm worker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Determinism {
+    pub seed: u64,
+    /// What `Date.now()` reads when the invocation starts.
+    pub start_time: SystemTime,
+    pub clock: VirtualClock,
+}
+
+impl Determinism {
+    /// Seeded with `seed`, starting at the Unix epoch with a frozen clock.
+    pub fn new(seed: u64) -> Self {
+        Determinism {
+            seed,
+            start_time: UNIX_EPOCH,
+            clock: VirtualClock::Frozen,
+        }
+    }
+
+    pub fn with_start_time(mut self, start_time: SystemTime) -> Self {
+        self.start_time = start_time;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: VirtualClock) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VirtualClock {
+    /// Time stands still at the start time.
+    #[default]
+    Frozen,
+    /// Time advances with the wall clock, rounded down to multiples of the
+    /// quantum. Reproducible as long as the timing of the code doesn't
+    /// straddle a step.
+    Quantized(Duration),
+}
+
+/// Generator and clock of a deterministic worker, kept in its op state.
+pub(crate) struct DeterministicState {
+    rng: u64,
+    start_millis: f64,
+    started: Instant,
+    clock: VirtualClock,
+}
+
+impl DeterministicState {
+    pub(crate) fn new(determinism: &Determinism) -> Self {
+        let start_millis = match determinism.start_time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_millis() as f64,
+            Err(e) => -(e.duration().as_millis() as f64),
+        };
+        DeterministicState {
+            rng: determinism.seed,
+            start_millis,
+            started: Instant::now(),
+            clock: determinism.clock,
+        }
+    }
+
+    /// Next `Math.random` value in `[0, 1)`, from SplitMix64.
+    pub(crate) fn random(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Virtual milliseconds since the invocation started.
+    pub(crate) fn elapsed(&self) -> f64 {
+        match self.clock {
+            VirtualClock::Frozen => 0.0,
+            VirtualClock::Quantized(quantum) => {
+                let quantum = quantum.as_millis().max(1);
+                let elapsed = self.started.elapsed().as_millis();
+                (elapsed - elapsed % quantum) as f64
+            }
+        }
+    }
+
+    /// Virtual time in milliseconds since the Unix epoch.
+    pub(crate) fn now(&self) -> f64 {
+        self.start_millis + self.elapsed()
+    }
+}
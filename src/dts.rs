@@ -0,0 +1,282 @@
+use serde_json::{Map, Value};
+
+use crate::host_api::method_declaration;
+use crate::options::RunOptions;
+
+/// Part of the script-facing surface an op of the host extension backs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Surface {
+    /// `host.files`.
+    Files,
+    /// `host.api`.
+    Api,
+    /// `host.onMessage` and `host.emit`.
+    Messages,
+    /// Backs a standard global the runtime patches, like `console` or
+    /// `Deno.env`, which the platform's own declarations already cover.
+    Internal,
+}
+
+/// Declaration metadata of every op the host extension registers. An op
+/// missing here fails the `every_op_is_declared` test.
+const OPS: &[(&str, Surface)] = &[
+    ("op_host_file_list", Surface::Files),
+    ("op_host_file_open", Surface::Files),
+    ("op_host_file_read", Surface::Files),
+    ("op_host_file_read_all", Surface::Files),
+    ("op_host_api_methods", Surface::Api),
+    ("op_host_api_call", Surface::Api),
+    ("op_host_api_call_async", Surface::Api),
+    ("op_host_console_capture", Surface::Internal),
+    ("op_host_console_event", Surface::Internal),
+    ("op_host_env", Surface::Internal),
+    ("op_host_emit", Surface::Messages),
+    ("op_host_message_recv", Surface::Messages),
+    ("op_host_sandboxed", Surface::Internal),
+    ("op_host_deterministic", Surface::Internal),
+    ("op_host_random", Surface::Internal),
+    ("op_host_now", Surface::Internal),
+    ("op_host_elapsed", Surface::Internal),
+    ("op_host_trace_mode", Surface::Internal),
+    ("op_host_trace_record_fetch", Surface::Internal),
+    ("op_host_trace_replay_fetch", Surface::Internal),
+    ("op_host_trace_context", Surface::Internal),
+];
+
+const HEADER: &str = "// Generated by experimental_runtime, do not edit.\n";
+
+const HOST_FILE: &str = "interface HostFile {
+  readonly name: string;
+  readonly size: number;
+  stream(): ReadableStream<Uint8Array>;
+  bytes(): Promise<Uint8Array>;
+  text(): Promise<string>;
+}
+";
+
+/// TypeScript declarations of what a run with `options` exposes to the
+/// script: the `host` global with the files, API methods and message
+/// channel it is given, and `Inputs` and `Output` types when `options`
+/// has schemas for them. Declare ops added with
+/// [`RuntimeBuilder::register_op`](crate::RuntimeBuilder::register_op) by
+/// passing the options of the built [`Runtime`](crate::Runtime).
+pub fn generate_dts(options: &RunOptions) -> String {
+    let mut out = format!("{}\n{}\ndeclare namespace host {{\n", HEADER, HOST_FILE);
+    let mut surfaces = Vec::new();
+    for (_, surface) in OPS {
+        if !surfaces.contains(surface) {
+            surfaces.push(*surface);
+            out.push_str(&declare(*surface, options));
+        }
+    }
+    out.push_str("}\n");
+    if let Some(schema) = &options.input_schema {
+        out.push_str(&format!(
+            "\ntype Inputs = {};\n",
+            ts_type(schema, options.strict_schema, 0)
+        ));
+    }
+    if let Some(schema) = &options.output_schema {
+        out.push_str(&format!(
+            "\ntype Output = {};\n",
+            ts_type(schema, options.strict_schema, 0)
+        ));
+    }
+    out
+}
+
+/// Members of the `host` namespace behind `surface`, empty when `options`
+/// leaves it unusable.
+fn declare(surface: Surface, options: &RunOptions) -> String {
+    match surface {
+        Surface::Files => {
+            let mut names: Vec<_> = options.files.keys().collect();
+            names.sort();
+            let mut out = String::from("  const files: {\n");
+            for name in names {
+                out.push_str(&format!("    readonly {}: HostFile;\n", quote(name)));
+            }
+            out.push_str("  };\n");
+            out
+        }
+        Surface::Api => {
+            let mut out = String::from("  const api: {\n");
+            for method in options.host_api.iter().flat_map(|api| api.0.methods()) {
+                out.push_str(&format!("    {};\n", method_declaration(&method)));
+            }
+            out.push_str("  };\n");
+            out
+        }
+        Surface::Messages if options.messages.is_some() => concat!(
+            "  function onMessage(listener: (message: unknown) => void): () => void;\n",
+            "  function emit(message: unknown): void;\n",
+        )
+        .into(),
+        Surface::Messages | Surface::Internal => String::new(),
+    }
+}
+
+fn quote(name: &str) -> String {
+    serde_json::to_string(name).unwrap_or_default()
+}
+
+/// The TypeScript type of the values `schema` accepts, as far as it can be
+/// told. Keywords it has no equivalent for are ignored.
+fn ts_type(schema: &Value, strict: bool, depth: usize) -> String {
+    let schema = match schema {
+        Value::Bool(true) => return "unknown".into(),
+        Value::Bool(false) => return "never".into(),
+        Value::Object(schema) => schema,
+        _ => return "unknown".into(),
+    };
+    if let Some(value) = schema.get("const") {
+        return value.to_string();
+    }
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        return union(values.iter().map(Value::to_string).collect());
+    }
+    for keyword in ["anyOf", "oneOf"] {
+        if let Some(Value::Array(schemas)) = schema.get(keyword) {
+            let types = schemas.iter().map(|s| ts_type(s, strict, depth));
+            return union(types.map(|t| format!("({})", t)).collect());
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("allOf") {
+        let types: Vec<_> = schemas
+            .iter()
+            .map(|s| format!("({})", ts_type(s, strict, depth)))
+            .collect();
+        return types.join(" & ");
+    }
+    let types = match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ if schema.contains_key("properties") => vec!["object"],
+        _ => return "unknown".into(),
+    };
+    let types = types.into_iter().map(|name| match name {
+        "string" => "string".into(),
+        "number" | "integer" => "number".into(),
+        "boolean" => "boolean".into(),
+        "null" => "null".into(),
+        "array" => match schema.get("items") {
+            Some(items) => format!("Array<{}>", ts_type(items, strict, depth)),
+            None => "Array<unknown>".into(),
+        },
+        "object" => object_type(schema, strict, depth),
+        _ => "unknown".into(),
+    });
+    union(types.collect())
+}
+
+fn object_type(schema: &Map<String, Value>, strict: bool, depth: usize) -> String {
+    let properties = schema.get("properties").and_then(Value::as_object);
+    let additional = schema.get("additionalProperties");
+    let Some(properties) = properties.filter(|p| !p.is_empty()) else {
+        return match additional {
+            Some(Value::Bool(false)) => "Record<string, never>".into(),
+            Some(additional) => format!("Record<string, {}>", ts_type(additional, strict, depth)),
+            None => "Record<string, unknown>".into(),
+        };
+    };
+    let required: Vec<_> = schema
+        .get("required")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    let indent = "  ".repeat(depth + 1);
+    let mut out = String::from("{\n");
+    for (name, property) in properties {
+        let optional = if required.contains(&name.as_str()) {
+            ""
+        } else {
+            "?"
+        };
+        out.push_str(&format!(
+            "{}{}{}: {};\n",
+            indent,
+            quote(name),
+            optional,
+            ts_type(property, strict, depth + 1)
+        ));
+    }
+    // Declared properties must fit the index signature, so other
+    // properties stay untyped.
+    let closed = match additional {
+        Some(additional) => additional == &Value::Bool(false),
+        None => strict,
+    };
+    if !closed {
+        out.push_str(&format!("{}[key: string]: unknown;\n", indent));
+    }
+    out.push_str(&"  ".repeat(depth));
+    out.push('}');
+    out
+}
+
+fn union(types: Vec<String>) -> String {
+    match types.is_empty() {
+        true => "never".into(),
+        false => types.join(" | "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::console::{self, ConsoleCapture};
+    use crate::host;
+    use crate::redact::Redactor;
+    use crate::warning::Warnings;
+
+    #[test]
+    fn every_op_is_declared() {
+        let extension = host::host::init_ops(
+            host::HostFiles {
+                paths: Default::default(),
+                read_limit: host::DEFAULT_FILE_READ_LIMIT,
+            },
+            None,
+            ConsoleCapture {
+                sink: None,
+                event_limit: console::DEFAULT_EVENT_LIMIT,
+                invocation: 0,
+                warnings: Warnings::default(),
+                redactor: Redactor::default(),
+            },
+            None,
+            None,
+        );
+        let registered: Vec<_> = extension.ops.iter().map(|op| op.name).collect();
+        for name in &registered {
+            assert!(
+                OPS.iter().any(|(op, _)| op == name),
+                "{} has no declaration metadata in dts::OPS",
+                name
+            );
+        }
+        for (op, _) in OPS {
+            assert!(registered.contains(op), "{} is not registered", op);
+        }
+    }
+
+    #[test]
+    fn schema_types() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}},
+                "size": {"type": ["integer", "null"]},
+            },
+            "required": ["name"],
+            "additionalProperties": false,
+        });
+        assert_eq!(
+            ts_type(&schema, false, 0),
+            "{\n  \"name\": string;\n  \"size\"?: number | null;\n  \"tags\"?: Array<\"a\" | \"b\">;\n}"
+        );
+    }
+}
@@ -0,0 +1,266 @@
+use anyhow::{anyhow, bail, Error};
+use deno_core::{
+    resolve_import, ModuleLoadResponse, ModuleLoader, ModuleSource, ModuleSourceCode,
+    ModuleSpecifier, ModuleType, RequestedModuleType, ResolutionKind,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::error::{self, resolution};
+use crate::imports::ImportGraph;
+use crate::transpile::{SourceKind, SourceMaps};
+
+const SCHEME: &str = "embedded";
+
+#[derive(Debug, Clone)]
+struct EmbeddedSource {
+    code: Cow<'static, str>,
+    transpiled: bool,
+}
+
+/// Module sources compiled into the host binary, keyed by their path
+/// relative to an embedded root (`main.ts`, `lib/util.ts`). Set as
+/// `RunOptions::embedded`, the function path then names the entry module
+/// and nothing is read from disk or the network.
+#[derive(Debug, Clone)]
+pub struct EmbeddedModules {
+    scheme: &'static str,
+    sources: Arc<HashMap<ModuleSpecifier, EmbeddedSource>>,
+    network_fallback: bool,
+}
+
+impl Default for EmbeddedModules {
+    fn default() -> Self {
+        Self::with_scheme(SCHEME)
+    }
+}
+
+impl EmbeddedModules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Modules served under another scheme, such as `archive:`.
+    pub(crate) fn with_scheme(scheme: &'static str) -> Self {
+        Self {
+            scheme,
+            sources: Default::default(),
+            network_fallback: false,
+        }
+    }
+
+    /// Adds a module, TypeScript and JSX are transpiled when loaded.
+    pub fn insert(&mut self, path: &str, code: impl Into<Cow<'static, str>>) -> &mut Self {
+        self.add(path, code.into(), false)
+    }
+
+    /// Adds a module that was already compiled to JavaScript, usually by
+    /// [`transpile_embedded`] in a build script, under its original path.
+    pub fn insert_transpiled(
+        &mut self,
+        path: &str,
+        code: impl Into<Cow<'static, str>>,
+    ) -> &mut Self {
+        self.add(path, code.into(), true)
+    }
+
+    /// Hands specifiers outside the embedded set to the regular file and
+    /// network loader instead of rejecting them.
+    pub fn with_network_fallback(mut self) -> Self {
+        self.network_fallback = true;
+        self
+    }
+
+    pub(crate) fn network_fallback(&self) -> bool {
+        self.network_fallback
+    }
+
+    /// Specifier of the module at `path`.
+    pub(crate) fn specifier(&self, path: &str) -> ModuleSpecifier {
+        scheme_specifier(self.scheme, path)
+    }
+
+    fn add(&mut self, path: &str, code: Cow<'static, str>, transpiled: bool) -> &mut Self {
+        let specifier = self.specifier(path);
+        Arc::make_mut(&mut self.sources).insert(specifier, EmbeddedSource { code, transpiled });
+        self
+    }
+}
+
+impl FromIterator<(&'static str, &'static str)> for EmbeddedModules {
+    fn from_iter<I: IntoIterator<Item = (&'static str, &'static str)>>(iter: I) -> Self {
+        let mut modules = Self::new();
+        for (path, code) in iter {
+            modules.insert(path, code);
+        }
+        modules
+    }
+}
+
+fn scheme_specifier(scheme: &str, path: &str) -> ModuleSpecifier {
+    let path = path.replace('\\', "/");
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    ModuleSpecifier::parse(&format!("{}:///{}", scheme, path))
+        .unwrap_or_else(|_| ModuleSpecifier::parse(&format!("{}:///", scheme)).unwrap())
+}
+
+/// Compiles an embedded TypeScript or JSX module ahead of time, for build
+/// scripts feeding [`EmbeddedModules::insert_transpiled`].
+pub fn transpile_embedded(path: &str, code: &str) -> Result<String, Error> {
+    let specifier = scheme_specifier(SCHEME, path);
+    let kind = SourceKind::from_specifier(&specifier);
+    let code = crate::transpile::transpile_module(&specifier, kind, code.to_string(), None, None)?;
+    Ok(String::from_utf8(code)?)
+}
+
+/// Loads modules from an [`EmbeddedModules`] set. Relative imports resolve
+/// among the embedded modules, anything else is rejected unless a fallback
+/// loader was chained.
+pub struct EmbeddedModuleLoader {
+    modules: EmbeddedModules,
+    fallback: Option<Rc<dyn ModuleLoader>>,
+    imports: Rc<ImportGraph>,
+    source_maps: SourceMaps,
+}
+
+impl EmbeddedModuleLoader {
+    pub fn new(modules: EmbeddedModules) -> Self {
+        Self {
+            modules,
+            fallback: None,
+            imports: Default::default(),
+            source_maps: Default::default(),
+        }
+    }
+
+    pub fn with_fallback(mut self, fallback: Rc<dyn ModuleLoader>) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    fn load_embedded(
+        &self,
+        specifier: &ModuleSpecifier,
+        requested_module_type: RequestedModuleType,
+    ) -> Result<ModuleSource, Error> {
+        let source = self
+            .modules
+            .sources
+            .get(specifier)
+            .ok_or_else(|| anyhow!("no module is embedded at {}", specifier.path()))?;
+        log::debug!("loading embedded module {}", specifier);
+
+        let code = if source.transpiled {
+            source.code.as_bytes().to_vec()
+        } else {
+            let kind = SourceKind::from_specifier(specifier);
+            if kind == SourceKind::Wasm {
+                bail!(
+                    "{} is WebAssembly, which can't be embedded",
+                    specifier.path()
+                );
+            }
+            crate::transpile::transpile_module(
+                specifier,
+                kind,
+                source.code.to_string(),
+                Some(&self.source_maps),
+                None,
+            )?
+        };
+        let module_type = match requested_module_type {
+            RequestedModuleType::None => ModuleType::JavaScript,
+            RequestedModuleType::Json => ModuleType::Json,
+            RequestedModuleType::Other(_) => {
+                bail!("Import types other than JSON are not supported")
+            }
+        };
+        Ok(ModuleSource::new(
+            module_type,
+            ModuleSourceCode::Bytes(code.into_boxed_slice().into()),
+            specifier,
+            None,
+        ))
+    }
+}
+
+impl ModuleLoader for EmbeddedModuleLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+        let scheme = self.modules.scheme;
+        let own = |s: &str| s.strip_prefix(scheme).is_some_and(|s| s.starts_with(':'));
+        if !own(referrer) && !own(specifier) {
+            return match &self.fallback {
+                Some(fallback) => fallback.resolve(specifier, referrer, kind),
+                None => Err(resolution(
+                    specifier,
+                    referrer,
+                    anyhow!("{} is not an {} module", referrer, scheme),
+                )),
+            };
+        }
+        let resolved = resolve_import(specifier, referrer)
+            .map_err(|e| resolution(specifier, referrer, e.into()))?;
+        if resolved.scheme() == scheme || self.fallback.is_some() {
+            return Ok(resolved);
+        }
+        Err(resolution(
+            specifier,
+            referrer,
+            anyhow!("{} is outside the {} modules", resolved, scheme),
+        ))
+    }
+
+    fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        maybe_referrer: Option<&ModuleSpecifier>,
+        is_dyn_import: bool,
+        requested_module_type: RequestedModuleType,
+    ) -> ModuleLoadResponse {
+        if module_specifier.scheme() != self.modules.scheme {
+            if let Some(fallback) = &self.fallback {
+                return fallback.load(
+                    module_specifier,
+                    maybe_referrer,
+                    is_dyn_import,
+                    requested_module_type,
+                );
+            }
+        }
+        if let Some(referrer) = maybe_referrer {
+            self.imports.record(module_specifier, referrer);
+        }
+        ModuleLoadResponse::Sync(
+            self.load_embedded(module_specifier, requested_module_type)
+                .map_err(|cause| {
+                    error::load(
+                        module_specifier,
+                        self.imports.chain(module_specifier),
+                        cause,
+                    )
+                }),
+        )
+    }
+    fn get_source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+        self.source_maps.source_map(file_name).or_else(|| {
+            let fallback = self.fallback.as_ref()?;
+            fallback.get_source_map(file_name)
+        })
+    }
+
+    fn get_source_mapped_source_line(&self, file_name: &str, line_number: usize) -> Option<String> {
+        self.source_maps
+            .source_line(file_name, line_number)
+            .or_else(|| {
+                let fallback = self.fallback.as_ref()?;
+                fallback.get_source_mapped_source_line(file_name, line_number)
+            })
+    }
+}
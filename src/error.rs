@@ -0,0 +1,314 @@
+use deno_core::error::JsError;
+use deno_core::ModuleSpecifier;
+use serde_json::Value;
+use std::fmt;
+
+use crate::warning::Warning;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// JSON pointer to the offending value, `""` being the document root.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: {}", path, self.message)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RuntimeError {
+    #[error("invalid schema: {0}")]
+    InvalidSchema(String),
+    #[error("function inputs do not match schema: {}", join(violations))]
+    InputValidation { violations: Vec<SchemaViolation> },
+    #[error("function output does not match schema: {}", join(violations))]
+    OutputValidation {
+        violations: Vec<SchemaViolation>,
+        /// The rejected value, only kept when `RunOptions::keep_invalid_output` is set.
+        value: Option<Value>,
+    },
+    #[error("could not pass inputs to the function: {message}")]
+    Serialization { message: String },
+    #[error("function result is not the expected type: {message}")]
+    Deserialization { message: String },
+    #[error("cannot convert {kind} at {path} to JSON")]
+    UnsupportedValue { path: String, kind: String },
+    #[error("toJSON() at {path} threw: {message}")]
+    ToJsonFailed { path: String, message: String },
+    #[error("{path} points back to {target}")]
+    CircularReference { path: String, target: String },
+    #[error("{path} is nested deeper than {limit} levels")]
+    TooDeep { path: String, limit: usize },
+    #[error("inputs are {observed} bytes, more than the limit of {limit}")]
+    InputTooLarge { limit: usize, observed: u64 },
+    #[error("function output exceeds {limit} bytes")]
+    OutputTooLarge { limit: usize },
+    #[error("function settled with work still pending: {}", join(pending))]
+    DanglingWork { pending: Vec<String> },
+    #[error("warning denied: {0}")]
+    DeniedWarning(Warning),
+    #[error("function did not finish within {limit:?}")]
+    Timeout { limit: std::time::Duration },
+    #[error("function burnt through its budget of {fuel} fuel")]
+    BudgetExceeded { fuel: u64 },
+    #[error("{specifier} threw while evaluating: {message}{}", at(location))]
+    ModuleEvaluation {
+        specifier: String,
+        message: String,
+        /// `file:line:column` of the throw, when the exception has a stack.
+        location: Option<String>,
+    },
+    #[error("{}", stack.as_deref().unwrap_or(message))]
+    JsException {
+        /// Class of the thrown value, such as `TypeError`.
+        name: Option<String>,
+        message: String,
+        stack: Option<String>,
+        /// The line of source that threw.
+        source_line: Option<String>,
+    },
+    #[error(
+        "module has no usable {export} export, it exports: {}",
+        join_with(available, ", ")
+    )]
+    MissingEntrypoint {
+        export: String,
+        available: Vec<String>,
+    },
+    #[error("permission denied: {message}")]
+    PermissionDenied { message: String },
+    #[error("worker process crashed ({status})")]
+    WorkerCrashed { status: String },
+    /// A failure reported by a `Subprocess` child, with the `kind` it had
+    /// there.
+    #[error("{message}")]
+    Subprocess { kind: String, message: String },
+    #[error("run was cancelled")]
+    Cancelled,
+    #[error("the runtime was shut down")]
+    ShutDown,
+    #[error("tenant {tenant} already has {limit} invocations queued")]
+    QueueFull { tenant: String, limit: usize },
+    #[error("heap grew to {observed} bytes, past the limit of {limit}")]
+    HeapLimitExceeded { limit: usize, observed: usize },
+    #[error("could not resolve {specifier} from {referrer}: {cause:#}")]
+    ModuleResolution {
+        specifier: String,
+        referrer: String,
+        cause: anyhow::Error,
+    },
+    #[error("could not fetch {url}: {cause:#}{}", import_chain(chain))]
+    Fetch {
+        url: String,
+        /// Status of the response, when the server answered with an error.
+        status: Option<u16>,
+        /// Modules from the entrypoint down to the one fetched, when it was
+        /// fetched to be imported.
+        chain: Vec<ModuleSpecifier>,
+        cause: anyhow::Error,
+    },
+    #[error("could not compile {specifier}: {message}{}", import_chain(chain))]
+    Transpile {
+        specifier: String,
+        message: String,
+        /// Modules from the entrypoint down to `specifier`.
+        chain: Vec<ModuleSpecifier>,
+    },
+    #[error(
+        "could not load {specifier}: {cause:#}\n    import chain: {}",
+        join_with(chain, " -> ")
+    )]
+    ModuleLoad {
+        specifier: ModuleSpecifier,
+        /// Modules from the entrypoint down to `specifier`, inclusive.
+        chain: Vec<ModuleSpecifier>,
+        cause: anyhow::Error,
+    },
+}
+
+impl RuntimeError {
+    /// Stable name of the variant, for reporting errors outside of Rust.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RuntimeError::InvalidSchema(_) => "invalid_schema",
+            RuntimeError::Serialization { .. } => "serialization",
+            RuntimeError::Deserialization { .. } => "deserialization",
+            RuntimeError::InputValidation { .. } => "input_validation",
+            RuntimeError::OutputValidation { .. } => "output_validation",
+            RuntimeError::UnsupportedValue { .. } => "unsupported_value",
+            RuntimeError::ToJsonFailed { .. } => "to_json_failed",
+            RuntimeError::CircularReference { .. } => "circular_reference",
+            RuntimeError::TooDeep { .. } => "too_deep",
+            RuntimeError::InputTooLarge { .. } => "input_too_large",
+            RuntimeError::OutputTooLarge { .. } => "output_too_large",
+            RuntimeError::DanglingWork { .. } => "dangling_work",
+            RuntimeError::DeniedWarning(_) => "denied_warning",
+            RuntimeError::Timeout { .. } => "timeout",
+            RuntimeError::BudgetExceeded { .. } => "budget_exceeded",
+            RuntimeError::ModuleEvaluation { .. } => "module_evaluation",
+            RuntimeError::JsException { .. } => "js_exception",
+            RuntimeError::MissingEntrypoint { .. } => "missing_entrypoint",
+            RuntimeError::PermissionDenied { .. } => "permission_denied",
+            RuntimeError::WorkerCrashed { .. } => "worker_crashed",
+            RuntimeError::Subprocess { .. } => "subprocess",
+            RuntimeError::Cancelled => "cancelled",
+            RuntimeError::ShutDown => "shut_down",
+            RuntimeError::QueueFull { .. } => "queue_full",
+            RuntimeError::HeapLimitExceeded { .. } => "heap_limit_exceeded",
+            RuntimeError::ModuleResolution { .. } => "module_resolution",
+            RuntimeError::Fetch { .. } => "fetch",
+            RuntimeError::Transpile { .. } => "transpile",
+            RuntimeError::ModuleLoad { .. } => "module_load",
+        }
+    }
+}
+
+impl RuntimeError {
+    pub(crate) fn js_exception(error: JsError) -> Self {
+        if error.name.as_deref() == Some("PermissionDenied") {
+            return RuntimeError::PermissionDenied {
+                message: error.message.unwrap_or_default(),
+            };
+        }
+        RuntimeError::JsException {
+            name: error.name,
+            message: error.message.unwrap_or(error.exception_message),
+            stack: error.stack,
+            source_line: error.source_line,
+        }
+    }
+}
+
+/// Turns a JS exception escaping the script into
+/// `RuntimeError::JsException`, or `RuntimeError::PermissionDenied` for
+/// `Deno.errors.PermissionDenied`. Other errors pass through.
+pub(crate) fn from_js(error: anyhow::Error) -> anyhow::Error {
+    match error.downcast::<JsError>() {
+        Ok(e) => RuntimeError::js_exception(e).into(),
+        Err(e) => e,
+    }
+}
+
+/// A JS exception thrown, or a top-level await rejected, while evaluating
+/// `specifier` as `RuntimeError::ModuleEvaluation`.
+pub(crate) fn evaluation(specifier: &ModuleSpecifier, error: anyhow::Error) -> anyhow::Error {
+    match error.downcast::<JsError>() {
+        Ok(e) if e.name.as_deref() == Some("PermissionDenied") => {
+            RuntimeError::js_exception(e).into()
+        }
+        Ok(e) => {
+            let location = e.frames.iter().find_map(|frame| {
+                Some(format!(
+                    "{}:{}:{}",
+                    frame.file_name.as_ref()?,
+                    frame.line_number?,
+                    frame.column_number?
+                ))
+            });
+            RuntimeError::ModuleEvaluation {
+                specifier: specifier.to_string(),
+                message: e.exception_message,
+                location,
+            }
+            .into()
+        }
+        Err(e) => e,
+    }
+}
+
+/// Failure to resolve `specifier` as `RuntimeError::ModuleResolution`,
+/// unless a loader this one delegated to already made it one.
+pub(crate) fn resolution(specifier: &str, referrer: &str, cause: anyhow::Error) -> anyhow::Error {
+    if matches!(
+        cause.downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::ModuleResolution { .. })
+    ) {
+        return cause;
+    }
+    RuntimeError::ModuleResolution {
+        specifier: specifier.to_string(),
+        referrer: referrer.to_string(),
+        cause,
+    }
+    .into()
+}
+
+/// Failure to load `specifier` as `RuntimeError::ModuleLoad`, or as the
+/// `RuntimeError::Fetch` or `RuntimeError::Transpile` it was, with the
+/// import chain filled in.
+pub(crate) fn load(
+    specifier: &ModuleSpecifier,
+    chain: Vec<ModuleSpecifier>,
+    cause: anyhow::Error,
+) -> anyhow::Error {
+    if matches!(
+        cause.downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::Fetch { .. } | RuntimeError::Transpile { .. })
+    ) {
+        match cause.downcast::<RuntimeError>() {
+            Ok(RuntimeError::Fetch {
+                url, status, cause, ..
+            }) => {
+                return RuntimeError::Fetch {
+                    url,
+                    status,
+                    chain,
+                    cause,
+                }
+                .into()
+            }
+            Ok(RuntimeError::Transpile {
+                specifier, message, ..
+            }) => {
+                return RuntimeError::Transpile {
+                    specifier,
+                    message,
+                    chain,
+                }
+                .into()
+            }
+            Ok(other) => return other.into(),
+            Err(cause) => return cause,
+        }
+    }
+    RuntimeError::ModuleLoad {
+        specifier: specifier.clone(),
+        chain,
+        cause,
+    }
+    .into()
+}
+
+fn import_chain(chain: &[ModuleSpecifier]) -> String {
+    match chain {
+        [] => String::new(),
+        chain => format!("\n    import chain: {}", join_with(chain, " -> ")),
+    }
+}
+
+fn at(location: &Option<String>) -> String {
+    location
+        .as_ref()
+        .map(|location| format!(" at {}", location))
+        .unwrap_or_default()
+}
+
+pub(crate) fn join<T: fmt::Display>(items: &[T]) -> String {
+    join_with(items, "; ")
+}
+
+fn join_with<T: fmt::Display>(items: &[T], separator: &str) -> String {
+    items
+        .iter()
+        .map(|item| item.to_string())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
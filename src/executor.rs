@@ -0,0 +1,314 @@
+use anyhow::{anyhow, Context, Error};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::sync::oneshot;
+
+use crate::cancel::CancellationHandle;
+use crate::inputs::Inputs;
+use crate::options::RunOptions;
+use crate::platform::PlatformGuard;
+use crate::worker::{self, LoadedModule};
+use crate::{redact, schema, stats, uncaught};
+
+struct Job {
+    function: PathBuf,
+    inputs: Inputs,
+    cancellation: Option<CancellationHandle>,
+    result: oneshot::Sender<Result<Value, Error>>,
+}
+
+/// Fixed set of threads running functions, each on its own current-thread
+/// tokio runtime. By default every job gets a fresh worker; a pool created
+/// with [`warm`](Self::warm) keeps workers loaded per function instead. The
+/// pool can be shared between threads. [`crate::shutdown`] fails until the
+/// pool is shut down.
+pub struct ExecutorPool {
+    jobs: Mutex<Option<mpsc::Sender<Job>>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl ExecutorPool {
+    pub fn new(size: usize) -> Result<Self, Error> {
+        Self::with_options(size, RunOptions::default())
+    }
+
+    /// Runs every job with `options`.
+    pub fn with_options(size: usize, options: RunOptions) -> Result<Self, Error> {
+        Self::start(size, options, None, vec![])
+    }
+
+    /// Keeps each thread's workers alive between jobs, one per function,
+    /// so only the first job for a function on a thread pays for
+    /// bootstrapping and evaluating the module. A worker is replaced after
+    /// `recycle_after` jobs, or as soon as a failure may have left it
+    /// unusable. Module state persists between the jobs a worker runs, a
+    /// temporary `FsSandbox` is emptied between them.
+    pub fn warm(size: usize, options: RunOptions, recycle_after: usize) -> Result<Self, Error> {
+        Self::start(size, options, Some(recycle_after.max(1)), vec![])
+    }
+
+    /// [`warm`](Self::warm), with every thread loading `functions` before
+    /// it takes jobs, and again right after a job that replaced their
+    /// worker, so jobs for them don't wait for a bootstrap. Jobs must name
+    /// the functions by the same paths.
+    pub fn prewarmed(
+        size: usize,
+        options: RunOptions,
+        recycle_after: usize,
+        functions: impl IntoIterator<Item = PathBuf>,
+    ) -> Result<Self, Error> {
+        let functions = functions.into_iter().collect();
+        Self::start(size, options, Some(recycle_after.max(1)), functions)
+    }
+
+    fn start(
+        size: usize,
+        options: RunOptions,
+        recycle_after: Option<usize>,
+        preload: Vec<PathBuf>,
+    ) -> Result<Self, Error> {
+        if size == 0 {
+            return Err(anyhow!("executor pool needs at least one thread"));
+        }
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = (0..size)
+            .map(|index| {
+                let receiver = receiver.clone();
+                let options = options.clone();
+                let preload = preload.clone();
+                let platform = PlatformGuard::acquire()?;
+                let thread = std::thread::Builder::new()
+                    .name(format!("executor-{}", index))
+                    .spawn(move || {
+                        let _platform = platform;
+                        work(receiver, options, recycle_after, preload)
+                    })?;
+                Ok(thread)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self {
+            jobs: Mutex::new(Some(sender)),
+            threads,
+        })
+    }
+
+    /// Queues a run of `function`. The returned future resolves once a
+    /// thread has run it.
+    pub fn submit(
+        &self,
+        function: PathBuf,
+        inputs: impl Into<Inputs>,
+    ) -> impl Future<Output = Result<Value, Error>> + Send + 'static {
+        self.queue(function, inputs.into(), None)
+    }
+
+    /// [`submit`](Self::submit) with a handle to cancel the job, whether
+    /// it is still queued or already running. The worker of a cancelled job
+    /// is not reused.
+    pub fn submit_cancellable(
+        &self,
+        function: PathBuf,
+        inputs: impl Into<Inputs>,
+        cancellation: CancellationHandle,
+    ) -> impl Future<Output = Result<Value, Error>> + Send + 'static {
+        self.queue(function, inputs.into(), Some(cancellation))
+    }
+
+    fn queue(
+        &self,
+        function: PathBuf,
+        inputs: Inputs,
+        cancellation: Option<CancellationHandle>,
+    ) -> impl Future<Output = Result<Value, Error>> + Send + 'static {
+        let (result, receiver) = oneshot::channel();
+        let job = Job {
+            function,
+            inputs,
+            cancellation,
+            result,
+        };
+        let queued = match &*self.jobs.lock().unwrap() {
+            Some(jobs) => jobs.send(job).is_ok(),
+            None => false,
+        };
+        async move {
+            if !queued {
+                return Err(anyhow!("executor pool is shut down"));
+            }
+            receiver
+                .await
+                .map_err(|_| anyhow!("executor thread stopped before finishing the job"))?
+        }
+    }
+
+    /// [`submit`](Self::submit), blocking until the result is in. Must not
+    /// be called from within an async runtime.
+    pub fn execute(&self, function: PathBuf, inputs: impl Into<Inputs>) -> Result<Value, Error> {
+        futures::executor::block_on(self.submit(function, inputs))
+    }
+
+    /// Stops taking jobs, lets the threads finish the queued ones and joins
+    /// them.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.jobs.lock().unwrap().take();
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                log::error!("executor thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for ExecutorPool {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn work(
+    jobs: Arc<Mutex<mpsc::Receiver<Job>>>,
+    options: RunOptions,
+    recycle_after: Option<usize>,
+    preload: Vec<PathBuf>,
+) {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("could not start executor runtime: {}", e);
+            return;
+        }
+    };
+    let mut warm = HashMap::new();
+    if recycle_after.is_some() {
+        runtime.block_on(load_missing(&mut warm, &preload, &options));
+    }
+    loop {
+        let job = jobs.lock().unwrap().recv();
+        let Ok(job) = job else {
+            break;
+        };
+        let options = match job.cancellation {
+            Some(cancellation) => RunOptions {
+                cancellation: Some(cancellation),
+                ..options.clone()
+            },
+            None => options.clone(),
+        };
+        let result = match recycle_after {
+            Some(recycle_after) => {
+                let function = job.function.clone();
+                let warm = &mut warm;
+                runtime.block_on(stats::metered(&function, &options, |options| async move {
+                    run_warm(warm, job.function, job.inputs, &options, recycle_after).await
+                }))
+            }
+            None => runtime.block_on(crate::run_async(&job.function, job.inputs, &options)),
+        };
+        // The caller may have stopped waiting.
+        let _ = job.result.send(result);
+        if recycle_after.is_some() {
+            runtime.block_on(load_missing(&mut warm, &preload, &options));
+        }
+    }
+}
+
+/// Loads the functions of `preload` that have no worker.
+async fn load_missing(
+    warm: &mut HashMap<PathBuf, WarmWorker>,
+    preload: &[PathBuf],
+    options: &RunOptions,
+) {
+    for function in preload {
+        if warm.contains_key(function) {
+            continue;
+        }
+        match worker::load(function, options).await {
+            Ok(module) => {
+                warm.insert(function.clone(), WarmWorker { module, jobs: 0 });
+            }
+            // The next job for it loads it again and fails with the error.
+            Err(e) => log::warn!("could not preload {}: {:#}", function.display(), e),
+        }
+    }
+}
+
+struct WarmWorker {
+    module: LoadedModule,
+    jobs: usize,
+}
+
+async fn run_warm(
+    warm: &mut HashMap<PathBuf, WarmWorker>,
+    function: PathBuf,
+    inputs: Inputs,
+    options: &RunOptions,
+    recycle_after: usize,
+) -> Result<Value, Error> {
+    let output_schema = options
+        .output_schema
+        .as_ref()
+        .map(|s| schema::compile(s, options.strict_schema))
+        .transpose()?;
+    inputs.check(options)?;
+    let redactor = redact::Redactor::new(options, &inputs);
+
+    let mut entry = match warm.remove(&function) {
+        Some(entry) => entry,
+        None => WarmWorker {
+            module: worker::load(&function, options)
+                .await
+                .map_err(|e| redactor.redact_error(e))?,
+            jobs: 0,
+        },
+    };
+    if let Err(e) = worker::take_uncaught(&mut entry.module).await {
+        let event = uncaught::UncaughtEvent::new(&e, entry.module.id, false, true);
+        uncaught::report(options.on_uncaught.as_ref(), event);
+        worker::reload(&mut entry.module, options)
+            .await
+            .map_err(|e| redactor.redact_error(e))?;
+        entry.jobs = 0;
+    }
+    if entry.jobs > 0 {
+        entry
+            .module
+            .reset_sandbox(options)
+            .context("could not reset the sandbox")?;
+    }
+    let module = &mut entry.module;
+    let result = match worker::call(module, inputs, options).await {
+        Ok(f) => crate::output(&mut module.worker, f, options, output_schema.as_deref()),
+        Err(e) => Err(e),
+    };
+    entry.jobs += 1;
+    // A `cancel()` racing the end of the call may have terminated the
+    // isolate after all.
+    let recycled = result.as_ref().is_err_and(worker::corrupts_worker)
+        || options
+            .cancellation
+            .as_ref()
+            .is_some_and(|c| c.is_cancelled());
+    if let Err(e) = &result {
+        if recycled {
+            let event = uncaught::UncaughtEvent::new(e, module.id, true, true);
+            uncaught::report(options.on_uncaught.as_ref(), event);
+        }
+    }
+    if !recycled && entry.jobs < recycle_after {
+        warm.insert(function, entry);
+    }
+    result.map_err(|e| redactor.redact_error(e))
+}
@@ -0,0 +1,524 @@
+use base64::Engine;
+use deno_core::v8;
+use serde_json::{json, Map, Number, Value};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::RuntimeError;
+use crate::options::RunOptions;
+
+#[derive(Debug, Clone, Default)]
+pub enum OutputFormat {
+    /// Strict JSON conversion, anything JSON can't represent fails.
+    #[default]
+    Json,
+    /// Converts errors, regexps, dates, binary data, maps and sets into JSON
+    /// shapes instead of failing on them.
+    Extended(ExtendedOptions),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExtendedOptions {
+    pub bytes: BytesEncoding,
+    pub dates: DatePolicy,
+    pub maps: MapPolicy,
+    pub cycles: CyclePolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BytesEncoding {
+    /// `[1, 2, 3]`
+    #[default]
+    Array,
+    /// `"AQID"`
+    Base64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DatePolicy {
+    /// `"2024-01-01T00:00:00.000Z"`, invalid dates become `null`.
+    #[default]
+    IsoString,
+    /// Milliseconds since the unix epoch.
+    EpochMillis,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MapPolicy {
+    /// `{"key": value}` with keys stringified.
+    #[default]
+    Object,
+    /// `[[key, value], ...]`, keeps non-string keys intact.
+    Entries,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CyclePolicy {
+    /// Fail with `RuntimeError::CircularReference`.
+    #[default]
+    Error,
+    /// Replace the back-reference with `{"$ref": "<path>"}`.
+    Ref,
+}
+
+type HookFn = dyn for<'s> Fn(&mut v8::HandleScope<'s>, v8::Local<'s, v8::Value>, &str) -> Option<Value>
+    + Send
+    + Sync;
+
+/// Host fallback for values the default rules can't convert, and for class
+/// instances without a `toJSON()` method. Receives the value and its path,
+/// `None` keeps the default behaviour.
+#[derive(Clone)]
+pub struct ValueHook(Arc<HookFn>);
+
+impl ValueHook {
+    pub fn new(
+        hook: impl for<'s> Fn(&mut v8::HandleScope<'s>, v8::Local<'s, v8::Value>, &str) -> Option<Value>
+            + Send
+            + Sync
+            + 'static,
+    ) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    pub(crate) fn call<'s>(
+        &self,
+        scope: &mut v8::HandleScope<'s>,
+        value: v8::Local<'s, v8::Value>,
+        path: &str,
+    ) -> Option<Value> {
+        (self.0)(scope, value, path)
+    }
+}
+
+impl fmt::Debug for ValueHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ValueHook")
+    }
+}
+
+/// Nesting allowed in returned values when `RunOptions::max_depth` is unset.
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 512;
+
+/// Objects between the root and the value being converted, with their paths.
+#[derive(Default)]
+struct Ancestors {
+    objects: Vec<(v8::Global<v8::Object>, String)>,
+}
+
+impl Ancestors {
+    /// Path of `object` if it is one of its own ancestors.
+    fn find(&self, object: v8::Local<v8::Object>) -> Option<&str> {
+        self.objects
+            .iter()
+            .find(|(ancestor, _)| *ancestor == object)
+            .map(|(_, path)| path.as_str())
+    }
+
+    fn push(&mut self, scope: &mut v8::HandleScope, object: v8::Local<v8::Object>, path: String) {
+        self.objects.push((v8::Global::new(scope, object), path));
+    }
+
+    fn pop(&mut self) {
+        self.objects.pop();
+    }
+
+    fn len(&self) -> usize {
+        self.objects.len()
+    }
+}
+
+pub(crate) fn to_json<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<'s, v8::Value>,
+    options: &RunOptions,
+) -> Result<Value, RuntimeError> {
+    let extended = match &options.output_format {
+        OutputFormat::Json => None,
+        OutputFormat::Extended(extended) => Some(extended),
+    };
+    Extractor {
+        extended,
+        hook: options.value_hook.as_ref(),
+        path: String::from("result"),
+        ancestors: Ancestors::default(),
+        max_depth: options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH),
+    }
+    .convert(scope, value)
+}
+
+/// Replaces `value` with the result of its `toJSON()` method when it has
+/// one, like `JSON.stringify` does.
+pub(crate) fn call_to_json<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<'s, v8::Value>,
+    path: impl FnOnce() -> String,
+) -> Result<v8::Local<'s, v8::Value>, RuntimeError> {
+    let Ok(object) = v8::Local::<v8::Object>::try_from(value) else {
+        return Ok(value);
+    };
+    let key = v8::String::new(scope, "toJSON").unwrap();
+    let Some(method) = object
+        .get(scope, key.into())
+        .and_then(|method| v8::Local::<v8::Function>::try_from(method).ok())
+    else {
+        return Ok(value);
+    };
+
+    let scope = &mut v8::TryCatch::new(scope);
+    let key = v8::String::empty(scope);
+    match method.call(scope, value, &[key.into()]) {
+        Some(result) => Ok(result),
+        None => Err(RuntimeError::ToJsonFailed {
+            path: path(),
+            message: scope
+                .exception()
+                .map(|e| e.to_rust_string_lossy(scope))
+                .unwrap_or_default(),
+        }),
+    }
+}
+
+/// Objects built by a class or constructor function, as opposed to plain
+/// object literals.
+pub(crate) fn is_class_instance(
+    scope: &mut v8::HandleScope,
+    object: v8::Local<v8::Object>,
+) -> bool {
+    object.get_constructor_name().to_rust_string_lossy(scope) != "Object"
+}
+
+/// Records `object` as an ancestor, failing if it already is one or if the
+/// nesting limit is reached.
+fn enter(
+    scope: &mut v8::HandleScope,
+    ancestors: &mut Ancestors,
+    object: v8::Local<v8::Object>,
+    path: &str,
+    max_depth: usize,
+) -> Result<(), RuntimeError> {
+    if let Some(target) = ancestors.find(object) {
+        return Err(RuntimeError::CircularReference {
+            path: path.into(),
+            target: target.into(),
+        });
+    }
+    if ancestors.len() >= max_depth {
+        return Err(RuntimeError::TooDeep {
+            path: path.into(),
+            limit: max_depth,
+        });
+    }
+    ancestors.push(scope, object, path.into());
+    Ok(())
+}
+
+struct Extractor<'a> {
+    extended: Option<&'a ExtendedOptions>,
+    hook: Option<&'a ValueHook>,
+    path: String,
+    ancestors: Ancestors,
+    max_depth: usize,
+}
+
+impl Extractor<'_> {
+    fn convert<'s>(
+        &mut self,
+        scope: &mut v8::HandleScope<'s>,
+        value: v8::Local<'s, v8::Value>,
+    ) -> Result<Value, RuntimeError> {
+        // Dates have a `toJSON()` of their own, the extended format has its own policy for them.
+        let value = if self.extended.is_some() && value.is_date() {
+            value
+        } else {
+            call_to_json(scope, value, || self.path.clone())?
+        };
+
+        if value.is_null_or_undefined() {
+            return Ok(Value::Null);
+        }
+        if value.is_boolean() {
+            return Ok(Value::Bool(value.boolean_value(scope)));
+        }
+        if value.is_number() {
+            return Ok(number(value.number_value(scope).unwrap_or(f64::NAN)));
+        }
+        if value.is_string() {
+            return Ok(Value::String(value.to_rust_string_lossy(scope)));
+        }
+        if value.is_big_int() && self.extended.is_some() {
+            let bigint = v8::Local::<v8::BigInt>::try_from(value).unwrap();
+            return Ok(match bigint.i64_value() {
+                (n, true) => Value::from(n),
+                _ => Value::String(value.to_rust_string_lossy(scope)),
+            });
+        }
+        if value.is_big_int() {
+            return self.fallback(scope, value, "bigint");
+        }
+        if value.is_function() {
+            return self.fallback(scope, value, "function");
+        }
+        if value.is_symbol() {
+            return self.fallback(scope, value, "symbol");
+        }
+
+        let scope = &mut v8::HandleScope::new(scope);
+        let object = v8::Local::<v8::Object>::try_from(value).unwrap();
+        if let Some(target) = self.ancestors.find(object) {
+            if self.extended.map(|e| e.cycles) == Some(CyclePolicy::Ref) {
+                return Ok(json!({ "$ref": target }));
+            }
+        }
+        enter(
+            scope,
+            &mut self.ancestors,
+            object,
+            &self.path,
+            self.max_depth,
+        )?;
+        let result = self.container(scope, value, object);
+        self.ancestors.pop();
+        result
+    }
+
+    fn container<'s>(
+        &mut self,
+        scope: &mut v8::HandleScope<'s>,
+        value: v8::Local<'s, v8::Value>,
+        object: v8::Local<'s, v8::Object>,
+    ) -> Result<Value, RuntimeError> {
+        if let Some(extended) = self.extended {
+            if value.is_native_error() {
+                let mut error = Map::new();
+                for key in ["name", "message", "stack"] {
+                    error.insert(key.into(), string_property(scope, object, key));
+                }
+                return Ok(Value::Object(error));
+            }
+            if value.is_reg_exp() {
+                let mut regexp = Map::new();
+                for key in ["source", "flags"] {
+                    regexp.insert(key.into(), string_property(scope, object, key));
+                }
+                return Ok(Value::Object(regexp));
+            }
+            if value.is_date() {
+                return Ok(self.date(scope, object, extended.dates));
+            }
+            if let Some(bytes) = buffer_bytes(value) {
+                return Ok(self.bytes(bytes, extended.bytes));
+            }
+            if value.is_map() {
+                let entries = v8::Local::<v8::Map>::try_from(value)
+                    .unwrap()
+                    .as_array(scope);
+                return self.map(scope, entries, extended.maps);
+            }
+            if value.is_set() {
+                let values = v8::Local::<v8::Set>::try_from(value)
+                    .unwrap()
+                    .as_array(scope);
+                return self.array(scope, values);
+            }
+        }
+        if value.is_array() {
+            let array = v8::Local::<v8::Array>::try_from(value).unwrap();
+            return self.array(scope, array);
+        }
+        if is_class_instance(scope, object) {
+            if let Some(replacement) = self.hook.and_then(|h| h.call(scope, value, &self.path)) {
+                return Ok(replacement);
+            }
+        }
+
+        self.object(scope, object)
+    }
+
+    fn array<'s>(
+        &mut self,
+        scope: &mut v8::HandleScope<'s>,
+        array: v8::Local<'s, v8::Array>,
+    ) -> Result<Value, RuntimeError> {
+        let mut items = Vec::with_capacity(array.length() as usize);
+        for index in 0..array.length() {
+            let item = array
+                .get_index(scope, index)
+                .unwrap_or_else(|| v8::undefined(scope).into());
+            let len = self.path.len();
+            self.path.push_str(&format!("[{}]", index));
+            items.push(self.convert(scope, item)?);
+            self.path.truncate(len);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn map<'s>(
+        &mut self,
+        scope: &mut v8::HandleScope<'s>,
+        entries: v8::Local<'s, v8::Array>,
+        policy: MapPolicy,
+    ) -> Result<Value, RuntimeError> {
+        let mut object = Map::new();
+        let mut pairs = vec![];
+        for index in (0..entries.length()).step_by(2) {
+            let key = entries.get_index(scope, index).unwrap();
+            let value = entries.get_index(scope, index + 1).unwrap();
+            let key_text = key.to_rust_string_lossy(scope);
+
+            let len = self.path.len();
+            push_key(&mut self.path, &key_text);
+            let value = self.convert(scope, value)?;
+            match policy {
+                MapPolicy::Object => {
+                    object.insert(key_text, value);
+                }
+                MapPolicy::Entries => {
+                    let key = self.convert(scope, key)?;
+                    pairs.push(Value::Array(vec![key, value]));
+                }
+            }
+            self.path.truncate(len);
+        }
+        Ok(match policy {
+            MapPolicy::Object => Value::Object(object),
+            MapPolicy::Entries => Value::Array(pairs),
+        })
+    }
+
+    fn object<'s>(
+        &mut self,
+        scope: &mut v8::HandleScope<'s>,
+        object: v8::Local<'s, v8::Object>,
+    ) -> Result<Value, RuntimeError> {
+        let keys = object
+            .get_own_property_names(
+                scope,
+                v8::GetPropertyNamesArgs {
+                    key_conversion: v8::KeyConversionMode::ConvertToString,
+                    ..Default::default()
+                },
+            )
+            .ok_or_else(|| self.unsupported("object with unreadable keys"))?;
+
+        let mut map = Map::new();
+        for index in 0..keys.length() {
+            let key = keys.get_index(scope, index).unwrap();
+            let key_text = key.to_rust_string_lossy(scope);
+            let value = object
+                .get(scope, key)
+                .unwrap_or_else(|| v8::undefined(scope).into());
+            if value.is_undefined() {
+                continue;
+            }
+
+            let len = self.path.len();
+            push_key(&mut self.path, &key_text);
+            map.insert(key_text, self.convert(scope, value)?);
+            self.path.truncate(len);
+        }
+        Ok(Value::Object(map))
+    }
+
+    fn date(
+        &self,
+        scope: &mut v8::HandleScope,
+        object: v8::Local<v8::Object>,
+        policy: DatePolicy,
+    ) -> Value {
+        let millis = v8::Local::<v8::Date>::try_from(object).unwrap().value_of();
+        if millis.is_nan() {
+            return Value::Null;
+        }
+        match policy {
+            DatePolicy::EpochMillis => number(millis),
+            DatePolicy::IsoString => {
+                let key = v8::String::new(scope, "toISOString").unwrap();
+                object
+                    .get(scope, key.into())
+                    .and_then(|f| v8::Local::<v8::Function>::try_from(f).ok())
+                    .and_then(|f| f.call(scope, object.into(), &[]))
+                    .map(|iso| Value::String(iso.to_rust_string_lossy(scope)))
+                    .unwrap_or(Value::Null)
+            }
+        }
+    }
+
+    fn bytes(&self, bytes: Vec<u8>, encoding: BytesEncoding) -> Value {
+        match encoding {
+            BytesEncoding::Array => Value::Array(bytes.into_iter().map(Value::from).collect()),
+            BytesEncoding::Base64 => {
+                Value::String(base64::engine::general_purpose::STANDARD.encode(bytes))
+            }
+        }
+    }
+
+    fn fallback<'s>(
+        &self,
+        scope: &mut v8::HandleScope<'s>,
+        value: v8::Local<'s, v8::Value>,
+        kind: &str,
+    ) -> Result<Value, RuntimeError> {
+        self.hook
+            .and_then(|hook| hook.call(scope, value, &self.path))
+            .ok_or_else(|| self.unsupported(kind))
+    }
+
+    fn unsupported(&self, kind: &str) -> RuntimeError {
+        RuntimeError::UnsupportedValue {
+            path: self.path.clone(),
+            kind: kind.into(),
+        }
+    }
+}
+
+fn string_property(scope: &mut v8::HandleScope, object: v8::Local<v8::Object>, key: &str) -> Value {
+    let key = v8::String::new(scope, key).unwrap();
+    match object.get(scope, key.into()) {
+        Some(value) if !value.is_undefined() => Value::String(value.to_rust_string_lossy(scope)),
+        _ => Value::Null,
+    }
+}
+
+pub(crate) fn push_key(path: &mut String, key: &str) {
+    let is_identifier = key
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_' || c == '$')
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '$');
+    if is_identifier {
+        path.push('.');
+        path.push_str(key);
+    } else {
+        path.push_str(&format!("[{}]", Value::from(key)));
+    }
+}
+
+pub(crate) fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < 9007199254740992.0 {
+        Value::from(n as i64)
+    } else {
+        Number::from_f64(n)
+            .map(Value::Number)
+            .unwrap_or(Value::Null)
+    }
+}
+
+/// Contents of an `ArrayBuffer`, a `SharedArrayBuffer` or a view on one,
+/// such as a `Uint8Array`.
+pub(crate) fn buffer_bytes(value: v8::Local<v8::Value>) -> Option<Vec<u8>> {
+    if let Ok(view) = v8::Local::<v8::ArrayBufferView>::try_from(value) {
+        let mut bytes = vec![0; view.byte_length()];
+        view.copy_contents(&mut bytes);
+        return Some(bytes);
+    }
+    let (store, length) = if let Ok(buffer) = v8::Local::<v8::ArrayBuffer>::try_from(value) {
+        (buffer.get_backing_store(), buffer.byte_length())
+    } else {
+        let buffer = v8::Local::<v8::SharedArrayBuffer>::try_from(value).ok()?;
+        (buffer.get_backing_store(), buffer.byte_length())
+    };
+    Some(store[..length].iter().map(|b| b.get()).collect())
+}
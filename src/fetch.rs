@@ -0,0 +1,381 @@
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::ModuleSpecifier;
+use reqwest::header::{
+    HeaderValue, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, LOCATION,
+};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::RuntimeError;
+use crate::import_policy::ImportPolicy;
+use crate::transpile::SourceKind;
+
+/// Redirect hops followed before a module fetch is abandoned.
+const MAX_REDIRECTS: usize = 10;
+
+pub(crate) struct Fetched {
+    /// Every URL visited, starting with the requested one and ending with
+    /// the one the body was served from.
+    pub(crate) urls: Vec<ModuleSpecifier>,
+    pub(crate) body: Vec<u8>,
+    pub(crate) content_type: Option<String>,
+    pub(crate) validators: Validators,
+}
+
+/// `ETag` and `Last-Modified` of a response, for revalidating it later.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Validators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+}
+
+impl Fetched {
+    pub(crate) fn url(&self) -> &ModuleSpecifier {
+        self.urls.last().unwrap()
+    }
+
+    /// Source kind declared by the `Content-Type` header, `None` when the
+    /// header leaves it to the extension. Types no module is served as, such
+    /// as an HTML error page, are rejected.
+    pub(crate) fn source_kind(&self) -> Result<Option<SourceKind>, Error> {
+        let Some(content_type) = &self.content_type else {
+            return Ok(None);
+        };
+        if let Some(kind) = SourceKind::from_content_type(content_type) {
+            return Ok(Some(kind));
+        }
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match essence.as_str() {
+            "" | "text/plain" | "application/octet-stream" | "binary/octet-stream" => Ok(None),
+            "text/html" | "application/xhtml+xml" => bail!(
+                "{} was served as {}, did you import an HTML error page?",
+                self.url(),
+                essence
+            ),
+            _ => bail!(
+                "{} was served as {}, which is not a JavaScript, TypeScript, JSON or wasm type",
+                self.url(),
+                essence
+            ),
+        }
+    }
+
+    /// Charset declared by the `Content-Type` header.
+    pub(crate) fn charset(&self) -> Option<String> {
+        self.content_type
+            .as_deref()
+            .and_then(crate::charset::from_content_type)
+            .map(String::from)
+    }
+}
+
+/// How remote modules are fetched.
+#[derive(Clone)]
+pub struct HttpOptions {
+    /// Limit for establishing a connection, 10 seconds by default.
+    pub connect_timeout: Duration,
+    /// Limit for a whole request, body included, 60 seconds by default.
+    pub request_timeout: Duration,
+    /// Attempts after the first one for connection failures, timeouts and
+    /// 5xx responses, 2 by default.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each one after it.
+    pub retry_backoff: Duration,
+    /// Upper bound on a response body, 64 MiB by default.
+    pub max_response_bytes: u64,
+    /// Proxy for every request. When unset, `HTTP_PROXY`, `HTTPS_PROXY` and
+    /// `NO_PROXY` are honoured.
+    pub proxy: Option<String>,
+    /// PEM files with certificates trusted on top of the system roots.
+    pub root_certificates: Vec<PathBuf>,
+    /// `Authorization` header sent with every request to an origin, keyed
+    /// by the origin as in `https://git.example.com`. Redirects to another
+    /// origin don't carry it.
+    pub credentials: HashMap<String, String>,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            request_timeout: Duration::from_secs(60),
+            retries: 2,
+            retry_backoff: Duration::from_millis(250),
+            max_response_bytes: 64 << 20,
+            proxy: None,
+            root_certificates: vec![],
+            credentials: HashMap::new(),
+        }
+    }
+}
+
+impl fmt::Debug for HttpOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpOptions")
+            .field("connect_timeout", &self.connect_timeout)
+            .field("request_timeout", &self.request_timeout)
+            .field("retries", &self.retries)
+            .field("retry_backoff", &self.retry_backoff)
+            .field("max_response_bytes", &self.max_response_bytes)
+            .field("proxy", &self.proxy)
+            .field("root_certificates", &self.root_certificates)
+            .field("credentials", &self.credentials.keys())
+            .finish()
+    }
+}
+
+/// Client for module fetches. Redirects are followed by [`fetch`] itself so
+/// each hop can be checked and recorded.
+#[derive(Clone)]
+pub(crate) struct HttpClient {
+    client: reqwest::Client,
+    options: HttpOptions,
+    pub(crate) policy: Option<ImportPolicy>,
+}
+
+impl HttpClient {
+    pub(crate) fn new(options: &HttpOptions) -> Result<Self, Error> {
+        let mut builder = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .connect_timeout(options.connect_timeout)
+            .timeout(options.request_timeout);
+        if let Some(proxy) = &options.proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy).with_context(|| format!("invalid proxy {}", proxy))?,
+            );
+        }
+        for path in &options.root_certificates {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("could not read {}", path.display()))?;
+            for certificate in reqwest::Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("invalid certificate in {}", path.display()))?
+            {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        Ok(Self {
+            client: builder.build()?,
+            options: options.clone(),
+            policy: None,
+        })
+    }
+
+    /// Fails for URLs the import policy doesn't allow.
+    pub(crate) fn check_policy(&self, url: &ModuleSpecifier) -> Result<(), Error> {
+        match &self.policy {
+            Some(policy) => policy.check(url),
+            None => Ok(()),
+        }
+    }
+
+    /// A request to `url`, with the credentials of its origin.
+    pub(crate) fn request(&self, method: Method, url: &ModuleSpecifier) -> RequestBuilder {
+        let req = self.client.request(method, url.clone());
+        let origin = url.origin().ascii_serialization();
+        match self.options.credentials.get(&origin) {
+            Some(credentials) => match HeaderValue::from_str(credentials) {
+                Ok(mut value) => {
+                    value.set_sensitive(true);
+                    req.header(AUTHORIZATION, value)
+                }
+                Err(_) => {
+                    log::warn!("ignoring invalid credentials for {}", origin);
+                    req
+                }
+            },
+            None => req,
+        }
+    }
+
+    /// Sends a GET, retrying connection failures, timeouts and server errors
+    /// with exponential backoff.
+    async fn get(
+        &self,
+        url: &ModuleSpecifier,
+        validators: Option<&Validators>,
+    ) -> Result<reqwest::Response, Error> {
+        self.send(url, || {
+            let mut req = self.request(Method::GET, url);
+            if let Some(validators) = validators {
+                if let Some(etag) = &validators.etag {
+                    req = req.header(IF_NONE_MATCH, etag);
+                }
+                if let Some(last_modified) = &validators.last_modified {
+                    req = req.header(IF_MODIFIED_SINCE, last_modified);
+                }
+            }
+            req
+        })
+        .await
+    }
+
+    /// Sends the request `build` makes, building it again for each retry of
+    /// a connection failure, timeout or server error.
+    pub(crate) async fn send(
+        &self,
+        url: &ModuleSpecifier,
+        build: impl Fn() -> RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        let mut attempt = 0;
+        loop {
+            let retry = match build().send().await {
+                Ok(res) if res.status().is_server_error() && attempt < self.options.retries => {
+                    format!("status {}", res.status())
+                }
+                Err(e) if (e.is_connect() || e.is_timeout()) && attempt < self.options.retries => {
+                    e.to_string()
+                }
+                res => return Ok(res?),
+            };
+            let delay = self.options.retry_backoff * 2u32.saturating_pow(attempt);
+            log::debug!("retrying {} in {:?} after {}", url, delay, retry);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Reads the body, failing once it grows past the size limit.
+    pub(crate) async fn body(
+        &self,
+        url: &ModuleSpecifier,
+        mut res: reqwest::Response,
+    ) -> Result<Vec<u8>, Error> {
+        let limit = self.options.max_response_bytes;
+        let too_large = || anyhow!("{} is larger than the {} byte limit", url, limit);
+        if res.content_length().is_some_and(|length| length > limit) {
+            return Err(too_large());
+        }
+        let mut body = vec![];
+        while let Some(chunk) = res.chunk().await? {
+            if (body.len() + chunk.len()) as u64 > limit {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+}
+
+pub(crate) fn client() -> HttpClient {
+    HttpClient::new(&HttpOptions::default()).expect("module fetch client")
+}
+
+pub(crate) async fn fetch(
+    client: &HttpClient,
+    specifier: &ModuleSpecifier,
+) -> Result<Fetched, Error> {
+    fetch_with(client, specifier, None).await?.ok_or_else(|| {
+        anyhow!(
+            "{} answered not modified to an unconditional request",
+            specifier
+        )
+    })
+}
+
+/// Fetches `specifier` unless it still matches `validators`, in which case
+/// the server answers not modified and `None` is returned.
+pub(crate) async fn fetch_if_modified(
+    client: &HttpClient,
+    specifier: &ModuleSpecifier,
+    validators: &Validators,
+) -> Result<Option<Fetched>, Error> {
+    fetch_with(client, specifier, Some(validators)).await
+}
+
+async fn fetch_with(
+    client: &HttpClient,
+    specifier: &ModuleSpecifier,
+    validators: Option<&Validators>,
+) -> Result<Option<Fetched>, Error> {
+    let mut urls = vec![specifier.clone()];
+    loop {
+        let url = urls.last().unwrap().clone();
+        check_hop(specifier, &url)?;
+        client.check_policy(&url)?;
+
+        let res = client
+            .get(&url, validators)
+            .await
+            .map_err(|e| failed(&url, e))?;
+        if res.status() == StatusCode::NOT_MODIFIED && validators.is_some() {
+            return Ok(None);
+        }
+        if !res.status().is_redirection() {
+            let res = res.error_for_status().map_err(|e| failed(&url, e.into()))?;
+            let header = |name| {
+                res.headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+                    .map(String::from)
+            };
+            let content_type = header(CONTENT_TYPE);
+            let validators = Validators {
+                etag: header(ETAG),
+                last_modified: header(LAST_MODIFIED),
+            };
+            let body = client.body(&url, res).await.map_err(|e| failed(&url, e))?;
+            return Ok(Some(Fetched {
+                urls,
+                body,
+                content_type,
+                validators,
+            }));
+        }
+
+        if urls.len() > MAX_REDIRECTS {
+            return Err(failed(specifier, anyhow!("too many redirects")));
+        }
+        let location = res
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .and_then(|location| url.join(location).ok());
+        let Some(mut next) = location else {
+            return Err(failed(&url, anyhow!("redirect without a valid location")));
+        };
+        // A location without a fragment inherits the one of the request (RFC 9110 10.2.2).
+        if next.fragment().is_none() {
+            next.set_fragment(url.fragment());
+        }
+        if next.origin() != url.origin() {
+            log::debug!("cross-origin redirect from {} to {}", url, next);
+        }
+        urls.push(next);
+    }
+}
+
+/// `cause` as `RuntimeError::Fetch`, with the status of an error response.
+pub(crate) fn failed(url: &ModuleSpecifier, cause: Error) -> Error {
+    let status = cause
+        .downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        .map(|status| status.as_u16());
+    RuntimeError::Fetch {
+        url: url.to_string(),
+        status,
+        chain: vec![],
+        cause,
+    }
+    .into()
+}
+
+fn check_hop(specifier: &ModuleSpecifier, url: &ModuleSpecifier) -> Result<(), Error> {
+    match url.scheme() {
+        "http" | "https" => Ok(()),
+        scheme => bail!(
+            "redirect from {} to unsupported scheme {}",
+            specifier,
+            scheme
+        ),
+    }
+}
@@ -0,0 +1,258 @@
+use anyhow::{anyhow, bail, Error};
+use deno_core::ModuleSpecifier;
+use std::io;
+use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+/// Specifier of an entrypoint, which is either a URL (`https://...`) taken
+/// as is or a local path.
+pub(crate) fn entry_specifier(entry: &Path) -> Result<ModuleSpecifier, Error> {
+    match entry.to_str() {
+        Some(url) if deno_core::specifier_has_uri_scheme(url) => {
+            ModuleSpecifier::parse(url).map_err(|e| anyhow!("invalid module URL {}: {}", url, e))
+        }
+        _ => path_to_specifier(entry),
+    }
+}
+
+/// Resolves a path against the current directory into a `file:` specifier.
+/// Drive letters, UNC shares and characters that need escaping are handled
+/// by `Url::from_file_path`, errors name the path as given.
+pub(crate) fn path_to_specifier(path: &Path) -> Result<ModuleSpecifier, Error> {
+    let current_dir = std::env::current_dir()?;
+    deno_core::resolve_path(path, &current_dir)
+        .map_err(|e| anyhow!("could not resolve module path {}: {}", path.display(), e))
+}
+
+/// Local path behind a `file:` specifier, with percent-escapes decoded.
+/// Encoded separators are rejected rather than turned into extra path
+/// components.
+pub(crate) fn specifier_to_path(specifier: &ModuleSpecifier) -> Result<PathBuf, Error> {
+    let path = specifier.path().to_ascii_lowercase();
+    if path.contains("%2f") || path.contains("%5c") {
+        bail!("file URL {} contains an encoded path separator", specifier);
+    }
+    specifier
+        .to_file_path()
+        .map_err(|_| anyhow!("invalid file URL {}", specifier))
+}
+
+/// Windows absolute paths (`C:\fn.ts`, `C:/fn.ts`, `\\server\share\fn.ts`)
+/// used as import specifiers. `resolve_import` would either reject them or
+/// read the drive letter as a URL scheme.
+pub(crate) fn windows_path_specifier(specifier: &str) -> Option<ModuleSpecifier> {
+    if !cfg!(windows) {
+        return None;
+    }
+    let bytes = specifier.as_bytes();
+    let drive = bytes.len() > 2
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && matches!(bytes[2], b'\\' | b'/');
+    let unc = specifier.starts_with(r"\\");
+    if !drive && !unc {
+        return None;
+    }
+    ModuleSpecifier::from_file_path(deno_core::normalize_path(specifier)).ok()
+}
+
+/// Real location of a `file:` module, so a module reached through several
+/// links is only instantiated once. Missing files are left as they are and
+/// fail when loaded.
+pub(crate) fn canonical_specifier(
+    specifier: ModuleSpecifier,
+    deny_symlinks: bool,
+) -> Result<ModuleSpecifier, Error> {
+    if specifier.scheme() != "file" {
+        return Ok(specifier);
+    }
+    let path = specifier_to_path(&specifier)?;
+    let Ok(canonical) = std::fs::canonicalize(&path) else {
+        return Ok(specifier);
+    };
+    if canonical == path {
+        return Ok(specifier);
+    }
+    if deny_symlinks {
+        bail!("{} is reached through a symbolic link", path.display());
+    }
+    let mut canonical = ModuleSpecifier::from_file_path(&canonical)
+        .map_err(|_| anyhow!("invalid module path {}", canonical.display()))?;
+    canonical.set_query(specifier.query());
+    canonical.set_fragment(specifier.fragment());
+    Ok(canonical)
+}
+
+/// Canonical spelling of a specifier's path: escapes of unreserved
+/// characters are decoded and the remaining ones use upper-case hex, so
+/// equivalent spellings of an import map onto the same module.
+pub(crate) fn normalize_specifier(mut specifier: ModuleSpecifier) -> ModuleSpecifier {
+    let path = normalize_escapes(specifier.path());
+    if path != specifier.path() {
+        specifier.set_path(&path);
+    }
+    specifier
+}
+
+fn normalize_escapes(path: &str) -> String {
+    let mut normalized = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(index) = rest.find('%') {
+        normalized.push_str(&rest[..index]);
+        let escape = rest.get(index + 1..index + 3);
+        match escape.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                normalized.push(byte as char);
+                rest = &rest[index + 3..];
+            }
+            Some(byte) => {
+                normalized.push_str(&format!("%{:02X}", byte));
+                rest = &rest[index + 3..];
+            }
+            None => {
+                normalized.push('%');
+                rest = &rest[index + 1..];
+            }
+        }
+    }
+    normalized.push_str(rest);
+    normalized
+}
+
+/// Reads a module file. When the name isn't found as spelled, the NFC and
+/// NFD forms are tried too, since the import and the file system may
+/// disagree on how accented characters are composed.
+pub(crate) async fn read_module_file(path: &Path) -> io::Result<Vec<u8>> {
+    let error = match tokio::fs::read(path).await {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => error,
+        result => return result,
+    };
+    let Some(text) = path.to_str() else {
+        return Err(error);
+    };
+    for candidate in [text.nfc().collect::<String>(), text.nfd().collect()] {
+        if candidate != text {
+            if let Ok(code) = tokio::fs::read(&candidate).await {
+                return Ok(code);
+            }
+        }
+    }
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_round_trip_through_file_urls() {
+        let dir = std::env::temp_dir();
+        for name in [
+            "fn.ts",
+            "my functions/fn.ts",
+            "100%/fn.ts",
+            "ünïcode/fn #1.ts",
+        ] {
+            let path = dir.join(name);
+            let specifier = path_to_specifier(&path).unwrap();
+            assert_eq!(specifier.scheme(), "file");
+            assert_eq!(specifier, ModuleSpecifier::from_file_path(&path).unwrap());
+            assert_eq!(specifier_to_path(&specifier).unwrap(), path);
+        }
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_the_current_directory() {
+        let specifier = path_to_specifier(Path::new("fn.ts")).unwrap();
+        let expected = std::env::current_dir().unwrap().join("fn.ts");
+        assert_eq!(specifier_to_path(&specifier).unwrap(), expected);
+    }
+
+    #[test]
+    fn urls_are_taken_as_is() {
+        let specifier = entry_specifier(Path::new("https://example.com/fn.ts")).unwrap();
+        assert_eq!(specifier.as_str(), "https://example.com/fn.ts");
+    }
+
+    #[test]
+    fn encoded_separators_are_rejected() {
+        for url in ["file:///a/..%2Fb.ts", "file:///a/..%5cb.ts"] {
+            let specifier = ModuleSpecifier::parse(url).unwrap();
+            assert!(specifier_to_path(&specifier).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn equivalent_escapes_normalize_alike() {
+        let normalized =
+            |url: &str| normalize_specifier(ModuleSpecifier::parse(url).unwrap()).to_string();
+        let expected = "https://example.com/m%C3%B3dulo%20%C3%BAtil.ts";
+        for url in [
+            "https://example.com/módulo útil.ts",
+            "https://example.com/m%C3%B3dulo%20%C3%BAtil.ts",
+            "https://example.com/m%c3%b3dulo%20%c3%batil.ts",
+        ] {
+            assert_eq!(normalized(url), expected, "{}", url);
+        }
+        assert_eq!(
+            normalized("https://example.com/%7Euser/%61.ts"),
+            "https://example.com/~user/a.ts"
+        );
+        // Encoded separators stay encoded, in one spelling.
+        assert_eq!(
+            normalized("https://example.com/a%2fb.ts"),
+            "https://example.com/a%2Fb.ts"
+        );
+        assert_eq!(normalize_escapes("100%"), "100%");
+    }
+
+    #[tokio::test]
+    async fn module_files_are_found_in_either_normal_form() {
+        let dir = tempfile::tempdir().unwrap();
+        let nfd = "mo\u{301}dulo.js";
+        std::fs::write(dir.path().join(nfd), "export {};").unwrap();
+        let nfc = dir.path().join("m\u{f3}dulo.js");
+        assert_eq!(read_module_file(&nfc).await.unwrap(), b"export {};");
+        let missing = dir.path().join("missing.js");
+        let error = read_module_file(&missing).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn windows_paths_are_only_paths_on_windows() {
+        assert_eq!(windows_path_specifier(r"C:\work\fn.ts"), None);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn drive_letters_and_unc_shares() {
+        let specifier = path_to_specifier(Path::new(r"C:\work\my fn.ts")).unwrap();
+        assert_eq!(specifier.as_str(), "file:///C:/work/my%20fn.ts");
+        assert_eq!(
+            specifier_to_path(&specifier).unwrap(),
+            PathBuf::from(r"C:\work\my fn.ts")
+        );
+
+        let specifier = path_to_specifier(Path::new(r"\\server\share\fn.ts")).unwrap();
+        assert_eq!(specifier.as_str(), "file://server/share/fn.ts");
+        assert_eq!(
+            specifier_to_path(&specifier).unwrap(),
+            PathBuf::from(r"\\server\share\fn.ts")
+        );
+
+        for import in [r"C:\work\fn.ts", "C:/work/fn.ts"] {
+            assert_eq!(
+                windows_path_specifier(import).unwrap().as_str(),
+                "file:///C:/work/fn.ts"
+            );
+        }
+        assert_eq!(
+            windows_path_specifier(r"\\server\share\fn.ts")
+                .unwrap()
+                .as_str(),
+            "file://server/share/fn.ts"
+        );
+        assert_eq!(windows_path_specifier("./fn.ts"), None);
+    }
+}
@@ -0,0 +1,89 @@
+use deno_core::v8;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How often the meter interrupts the script to burn a unit of fuel.
+const TICK: Duration = Duration::from_millis(1);
+
+#[derive(Default)]
+struct Tank {
+    remaining: AtomicU64,
+    /// An interrupt is queued and not run yet.
+    pending: AtomicBool,
+    exhausted: AtomicBool,
+    /// The metered work is over, late interrupts burn nothing.
+    stopped: AtomicBool,
+}
+
+/// Burns a unit of fuel each tick the script spends running, and
+/// terminates it once the fuel runs out. A thread requests an interrupt
+/// every tick, which V8 serves at the next function call or loop iteration,
+/// so tight loops are metered too. Only one interrupt is queued at a time,
+/// so a script waiting on I/O burns at most one unit when it resumes.
+pub(crate) struct FuelMeter {
+    tank: Arc<Tank>,
+    stop: mpsc::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl FuelMeter {
+    pub(crate) fn start(isolate: v8::IsolateHandle, fuel: u64) -> Self {
+        let tank = Arc::new(Tank {
+            remaining: AtomicU64::new(fuel),
+            ..Default::default()
+        });
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn({
+            let tank = tank.clone();
+            move || {
+                while stopped.recv_timeout(TICK) == Err(RecvTimeoutError::Timeout) {
+                    if tank.exhausted.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    if tank.pending.swap(true, Ordering::SeqCst) {
+                        continue;
+                    }
+                    let data = Arc::into_raw(tank.clone()) as *mut c_void;
+                    if !isolate.request_interrupt(burn, data) {
+                        // SAFETY: the isolate is gone and never runs `burn`,
+                        // the reference is still ours.
+                        drop(unsafe { Arc::from_raw(data as *const Tank) });
+                        return;
+                    }
+                }
+            }
+        });
+        FuelMeter { tank, stop, thread }
+    }
+
+    /// Stops metering, and tells whether the fuel ran out.
+    pub(crate) fn stop(self) -> bool {
+        self.tank.stopped.store(true, Ordering::SeqCst);
+        drop(self.stop);
+        let _ = self.thread.join();
+        self.tank.exhausted.load(Ordering::SeqCst)
+    }
+}
+
+extern "C" fn burn(isolate: &mut v8::Isolate, data: *mut c_void) {
+    // SAFETY: `data` is the reference the meter handed over when it
+    // requested the interrupt, which runs once.
+    let tank = unsafe { Arc::from_raw(data as *const Tank) };
+    tank.pending.store(false, Ordering::SeqCst);
+    if tank.stopped.load(Ordering::SeqCst) {
+        return;
+    }
+    let burnt = tank
+        .remaining
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |fuel| {
+            fuel.checked_sub(1)
+        });
+    if matches!(burnt, Ok(1) | Err(_)) {
+        tank.exhausted.store(true, Ordering::SeqCst);
+        isolate.terminate_execution();
+    }
+}
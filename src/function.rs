@@ -0,0 +1,76 @@
+use anyhow::Error;
+use jsonschema::JSONSchema;
+use serde_json::Value;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::inputs::Inputs;
+use crate::options::{Entrypoint, RunOptions};
+use crate::worker::{self, LoadedModule};
+use crate::{redact, schema, uncaught};
+
+/// A function module loaded and evaluated once, then called any number of
+/// times from the thread that created it. Module-level state persists
+/// across calls until a failure forces the worker to be reloaded.
+pub struct FunctionRuntime {
+    runtime: tokio::runtime::Runtime,
+    module: LoadedModule,
+    options: RunOptions,
+    output_schema: Option<Arc<JSONSchema>>,
+}
+
+impl FunctionRuntime {
+    pub fn new(function: PathBuf, options: RunOptions) -> Result<Self, Error> {
+        let output_schema = options
+            .output_schema
+            .as_ref()
+            .map(|s| schema::compile(s, options.strict_schema))
+            .transpose()?;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let module = runtime.block_on(worker::load(&function, &options))?;
+        Ok(Self {
+            runtime,
+            module,
+            options,
+            output_schema,
+        })
+    }
+
+    /// Calls the `export` function with the inputs, the way `main` is
+    /// called by a run.
+    pub fn call(&mut self, export: &str, inputs: impl Into<Inputs>) -> Result<Value, Error> {
+        let inputs = inputs.into();
+        inputs.check(&self.options)?;
+        let redactor = redact::Redactor::new(&self.options, &inputs);
+        self.options.entrypoint = Entrypoint::Handler(export.to_string());
+
+        let Self {
+            runtime,
+            module,
+            options,
+            output_schema,
+        } = self;
+        let result = runtime.block_on(async {
+            if let Err(e) = worker::take_uncaught(module).await {
+                let event = uncaught::UncaughtEvent::new(&e, module.id, false, true);
+                uncaught::report(options.on_uncaught.as_ref(), event);
+                worker::reload(module, options).await?;
+            }
+            let result = match worker::call(module, inputs, options).await {
+                Ok(f) => crate::output(&mut module.worker, f, options, output_schema.as_deref()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                if worker::corrupts_worker(e) {
+                    let event = uncaught::UncaughtEvent::new(e, module.id, true, true);
+                    uncaught::report(options.on_uncaught.as_ref(), event);
+                    worker::reload(module, options).await?;
+                }
+            }
+            result
+        });
+        result.map_err(|e| redactor.redact_error(e))
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Error};
+use deno_core::{v8, PollEventLoopOptions};
+use futures::Stream;
+use serde_json::Value;
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+
+use crate::error::{self, RuntimeError};
+use crate::inputs::Inputs;
+use crate::options::RunOptions;
+use crate::worker::{self, LoadedModule};
+use crate::{redact, schema};
+
+/// Values converted ahead of the consumer before the function is paused.
+const BUFFERED: usize = 16;
+
+/// Runs the function on a thread of its own and streams its result. When
+/// the entrypoint returns an async iterable, such as an async generator or
+/// a `ReadableStream`, or a generator, each value it yields is an item;
+/// any other result is streamed as a single item. `max_output_bytes` and
+/// `output_schema` apply to each item, and `timeout` to each step of the
+/// iteration. An error ends the stream.
+///
+/// The function only runs ahead of the consumer by a few items. Dropping
+/// the stream stops the iteration and calls the iterator's `return()`.
+pub fn run_stream(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    options: RunOptions,
+) -> impl Stream<Item = Result<Value, Error>> + Send + 'static {
+    let inputs = inputs.into();
+    let (sender, mut receiver) = mpsc::channel(BUFFERED);
+    let spawned = std::thread::Builder::new()
+        .name("run-stream".to_string())
+        .spawn({
+            let sender = sender.clone();
+            move || {
+                let redactor = redact::Redactor::new(&options, &inputs);
+                let streamed = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .map_err(Error::from)
+                    .and_then(|runtime| {
+                        runtime.block_on(stream(&function, inputs, &options, &sender))
+                    });
+                if let Err(e) = streamed {
+                    let _ = sender.blocking_send(Err(redactor.redact_error(e)));
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        let _ = sender.try_send(Err(e.into()));
+    }
+    drop(sender);
+    futures::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+}
+
+async fn stream(
+    function: &std::path::Path,
+    inputs: Inputs,
+    options: &RunOptions,
+    sender: &mpsc::Sender<Result<Value, Error>>,
+) -> Result<(), Error> {
+    let output_schema = options
+        .output_schema
+        .as_ref()
+        .map(|s| schema::compile(s, options.strict_schema))
+        .transpose()?;
+    inputs.check(options)?;
+    let (mut module, f) = worker::execute(function, inputs, options).await?;
+
+    let Some(iterator) = iterator(&mut module, &f)? else {
+        let value = crate::output(&mut module.worker, f, options, output_schema.as_deref())?;
+        let _ = sender.send(Ok(value)).await;
+        return Ok(());
+    };
+    loop {
+        let isolate = module.worker.js_runtime.v8_isolate().thread_safe_handle();
+        let next = step(&mut module, &iterator, "next");
+        let Some(value) = worker::within(isolate, options.timeout, options, next).await? else {
+            return Ok(());
+        };
+        let value = crate::output(&mut module.worker, value, options, output_schema.as_deref())?;
+        if sender.send(Ok(value)).await.is_err() {
+            log::debug!("stream consumer went away, closing the iterator");
+            let _ = step(&mut module, &iterator, "return").await;
+            return Ok(());
+        }
+    }
+}
+
+/// Iterator over what the function returned, `None` when it returned
+/// something other than an async iterable or a generator.
+fn iterator(
+    module: &mut LoadedModule,
+    value: &v8::Global<v8::Value>,
+) -> Result<Option<v8::Global<v8::Object>>, Error> {
+    let scope = &mut module.worker.js_runtime.handle_scope();
+    let value = v8::Local::new(scope, value);
+    let Ok(object) = v8::Local::<v8::Object>::try_from(value) else {
+        return Ok(None);
+    };
+    let async_iterator = v8::Symbol::get_async_iterator(scope);
+    let method = object
+        .get(scope, async_iterator.into())
+        .and_then(|method| v8::Local::<v8::Function>::try_from(method).ok());
+    let Some(method) = method else {
+        return Ok(value
+            .is_generator_object()
+            .then(|| v8::Global::new(scope, object)));
+    };
+    let scope = &mut v8::TryCatch::new(scope);
+    let Some(iterator) = method.call(scope, object.into(), &[]) else {
+        return Err(thrown(scope));
+    };
+    let iterator = v8::Local::<v8::Object>::try_from(iterator)
+        .map_err(|_| anyhow!("[Symbol.asyncIterator]() did not return an object"))?;
+    Ok(Some(v8::Global::new(scope, iterator)))
+}
+
+/// Calls `method` of the iterator and awaits the result. `None` once the
+/// iterator is done.
+async fn step(
+    module: &mut LoadedModule,
+    iterator: &v8::Global<v8::Object>,
+    method: &str,
+) -> Result<Option<v8::Global<v8::Value>>, Error> {
+    let result = {
+        let scope = &mut module.worker.js_runtime.handle_scope();
+        let iterator = v8::Local::new(scope, iterator);
+        let key = v8::String::new(scope, method).ok_or(anyhow!("could not create key"))?;
+        let Some(function) = iterator
+            .get(scope, key.into())
+            .and_then(|function| v8::Local::<v8::Function>::try_from(function).ok())
+        else {
+            return Ok(None);
+        };
+        let scope = &mut v8::TryCatch::new(scope);
+        let Some(result) = function.call(scope, iterator.into(), &[]) else {
+            return Err(thrown(scope));
+        };
+        v8::Global::new(scope, result)
+    };
+    let result = module.worker.js_runtime.resolve(result);
+    let result = module
+        .worker
+        .js_runtime
+        .with_event_loop_promise(result, PollEventLoopOptions::default())
+        .await
+        .map_err(error::from_js)?;
+
+    let scope = &mut module.worker.js_runtime.handle_scope();
+    let result = v8::Local::new(scope, result);
+    let result = v8::Local::<v8::Object>::try_from(result)
+        .map_err(|_| anyhow!("iterator {}() did not return an object", method))?;
+    let done = v8::String::new(scope, "done").ok_or(anyhow!("could not create key"))?;
+    if result
+        .get(scope, done.into())
+        .is_some_and(|done| done.boolean_value(scope))
+    {
+        return Ok(None);
+    }
+    let key = v8::String::new(scope, "value").ok_or(anyhow!("could not create key"))?;
+    let value = result
+        .get(scope, key.into())
+        .unwrap_or_else(|| v8::undefined(scope).into());
+    Ok(Some(v8::Global::new(scope, value)))
+}
+
+fn thrown(scope: &mut v8::TryCatch<v8::HandleScope>) -> Error {
+    if scope.has_terminated() {
+        return anyhow!("execution was terminated");
+    }
+    match scope.exception() {
+        Some(exception) => {
+            let error = deno_core::error::JsError::from_v8_exception(scope, exception);
+            RuntimeError::js_exception(error).into()
+        }
+        None => anyhow!("iterator threw"),
+    }
+}
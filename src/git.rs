@@ -0,0 +1,823 @@
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::ModuleSpecifier;
+use flate2::{Decompress, FlushDecompress, Status};
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::Method;
+use sha1::{Digest, Sha1};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::cache::{CachePolicy, ModuleCache};
+use crate::fetch::{self, HttpClient};
+
+const GIT_PROTOCOL: &str = "version=2";
+const REQUEST_TYPE: &str = "application/x-git-upload-pack-request";
+const RESULT_TYPE: &str = "application/x-git-upload-pack-result";
+
+/// A file of a repository at a revision, spelled
+/// `git+https://host/org/repo.git#<revision>/path/to/mod.ts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct GitSpecifier {
+    /// The repository's URL, without the `git+`.
+    pub(crate) repo: ModuleSpecifier,
+    pub(crate) revision: String,
+    /// Path of the file in the repository, without a leading slash.
+    pub(crate) path: String,
+}
+
+impl GitSpecifier {
+    pub(crate) fn parse(specifier: &ModuleSpecifier) -> Result<Self, Error> {
+        let invalid = |reason: &str| anyhow!("invalid git specifier {}: {}", specifier, reason);
+        let fragment = specifier
+            .fragment()
+            .ok_or_else(|| invalid("no #<commit>/<path> fragment"))?;
+        let (revision, path) = fragment
+            .split_once('/')
+            .ok_or_else(|| invalid("no path after the commit"))?;
+        let path = percent_encoding::percent_decode_str(path)
+            .decode_utf8()
+            .map_err(|_| invalid("the path is not UTF-8"))?;
+        if revision.is_empty()
+            || path
+                .split('/')
+                .any(|segment| matches!(segment, "" | "." | ".."))
+        {
+            return Err(invalid("empty, . or .. path segment"));
+        }
+        let mut repo = specifier.clone();
+        repo.set_fragment(None);
+        let repo = repo
+            .as_str()
+            .strip_prefix("git+")
+            .and_then(|repo| ModuleSpecifier::parse(repo).ok())
+            .filter(|repo| matches!(repo.scheme(), "http" | "https"))
+            .ok_or_else(|| invalid("not a git+https URL"))?;
+        Ok(Self {
+            repo,
+            revision: revision.to_string(),
+            path: path.into_owned(),
+        })
+    }
+
+    pub(crate) fn to_specifier(&self) -> Result<ModuleSpecifier, Error> {
+        let specifier = format!("git+{}#{}/{}", self.repo, self.revision, self.path);
+        ModuleSpecifier::parse(&specifier)
+            .map_err(|e| anyhow!("invalid git specifier {}: {}", specifier, e))
+    }
+
+    fn is_commit(&self) -> bool {
+        self.revision.len() == 40 && self.revision.bytes().all(|b| b.is_ascii_hexdigit())
+    }
+}
+
+/// Resolves `./`, `../` and `/` imports of a module in a repository to files
+/// of the same revision, `/` standing for the root of the repository.
+/// `None` for other imports.
+pub(crate) fn resolve(specifier: &str, referrer: &str) -> Result<Option<ModuleSpecifier>, Error> {
+    if !referrer.starts_with("git+")
+        || !["./", "../", "/"]
+            .iter()
+            .any(|prefix| specifier.starts_with(prefix))
+    {
+        return Ok(None);
+    }
+    let referrer = ModuleSpecifier::parse(referrer)?;
+    let mut resolved = GitSpecifier::parse(&referrer)?;
+    let mut segments: Vec<&str> = match specifier.starts_with('/') {
+        true => vec![],
+        false => resolved.path.split('/').collect(),
+    };
+    segments.pop();
+    let path = specifier.split(['?', '#']).next().unwrap_or_default();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if segments.pop().is_none() {
+                    bail!("{} leaves the repository of {}", specifier, referrer);
+                }
+            }
+            segment => segments.push(segment),
+        }
+    }
+    resolved.path = segments.join("/");
+    resolved.to_specifier().map(Some)
+}
+
+/// Commits mutable revisions resolved to during a run, so that every module
+/// of a repository comes from the same commit.
+#[derive(Clone, Default)]
+pub(crate) struct GitRevisions {
+    pub(crate) allow_mutable: bool,
+    resolved: Rc<RefCell<HashMap<(String, String), String>>>,
+}
+
+/// Source of a file in a repository and, when its revision is a branch or
+/// tag, the specifier pinning it to the commit that was checked out.
+pub(crate) struct GitModule {
+    pub(crate) code: Vec<u8>,
+    pub(crate) pinned: Option<ModuleSpecifier>,
+}
+
+/// Serves a file from a shallow checkout of its commit, kept in a `git`
+/// directory of the module cache. Checked out commits are served without
+/// touching the network.
+pub(crate) async fn load(
+    client: &HttpClient,
+    cache: Option<&ModuleCache>,
+    revisions: &GitRevisions,
+    specifier: &ModuleSpecifier,
+) -> Result<GitModule, Error> {
+    let mut git = GitSpecifier::parse(specifier)?;
+    client.check_policy(&git.repo)?;
+    let (dir, policy) = match cache {
+        Some(cache) => (cache.dir().to_path_buf(), cache.policy()),
+        None => {
+            let cache = ModuleCache::new(CachePolicy::UseCache)?;
+            (cache.dir().to_path_buf(), cache.policy())
+        }
+    };
+    let offline = policy == CachePolicy::Offline;
+
+    let mut pinned = None;
+    if !git.is_commit() {
+        if !revisions.allow_mutable {
+            bail!(
+                "{} names the revision {}, git imports need a full commit hash",
+                specifier,
+                git.revision
+            );
+        }
+        let key = (git.repo.to_string(), git.revision.clone());
+        let resolved = revisions.resolved.borrow().get(&key).cloned();
+        let commit = match resolved {
+            Some(commit) => commit,
+            None if offline => bail!(
+                "the revision {} of {} can't be resolved, the cache is offline",
+                git.revision,
+                git.repo
+            ),
+            None => {
+                let commit = ls_ref(client, &git.repo, &git.revision).await?;
+                log::debug!("{} of {} is {}", git.revision, git.repo, commit);
+                revisions.resolved.borrow_mut().insert(key, commit.clone());
+                commit
+            }
+        };
+        git.revision = commit;
+        pinned = Some(git.to_specifier()?);
+    }
+
+    let repo_dir = dir.join("git").join(format!(
+        "{:x}",
+        sha2::Sha256::digest(git.repo.as_str().as_bytes())
+    ));
+    let checkout = repo_dir.join(&git.revision);
+    if tokio::fs::metadata(&checkout).await.is_ok() {
+        log::debug!("serving {} from the checkout of {}", git.path, git.revision);
+    } else if offline {
+        bail!(
+            "{} is not checked out and the cache is offline",
+            git.revision
+        );
+    } else {
+        let pack = fetch_pack(client, &git.repo, &git.revision).await?;
+        let objects = unpack(&pack).with_context(|| format!("invalid pack from {}", git.repo))?;
+        check_out(&objects, &git.revision, &repo_dir)
+            .await
+            .with_context(|| format!("could not check out {} of {}", git.revision, git.repo))?;
+    }
+
+    let path = git
+        .path
+        .split('/')
+        .fold(checkout, |path, segment| path.join(segment));
+    let code = tokio::fs::read(&path).await.map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => anyhow!(
+            "{} does not exist at {} in {}",
+            git.path,
+            git.revision,
+            git.repo
+        ),
+        _ => anyhow!(e).context(format!("could not read {}", path.display())),
+    })?;
+    Ok(GitModule { code, pinned })
+}
+
+/// Endpoint of the smart HTTP protocol under the repository's URL.
+fn endpoint(repo: &ModuleSpecifier, path: &str) -> Result<ModuleSpecifier, Error> {
+    let url = format!("{}/{}", repo.as_str().trim_end_matches('/'), path);
+    ModuleSpecifier::parse(&url).map_err(|e| anyhow!("invalid git endpoint {}: {}", url, e))
+}
+
+/// Capabilities the server advertises for protocol version 2.
+async fn capabilities(client: &HttpClient, repo: &ModuleSpecifier) -> Result<Vec<String>, Error> {
+    let url = endpoint(repo, "info/refs?service=git-upload-pack")?;
+    let body = send(client, &url, || {
+        client
+            .request(Method::GET, &url)
+            .header("Git-Protocol", GIT_PROTOCOL)
+    })
+    .await?;
+    let mut lines = PktLines::new(&body);
+    let mut capabilities = vec![];
+    let mut version = None;
+    while let Some(pkt) = lines.next()? {
+        let Pkt::Data(line) = pkt else {
+            continue;
+        };
+        let line = String::from_utf8_lossy(line).trim_end().to_string();
+        if line.starts_with('#') {
+            continue;
+        }
+        match version {
+            None => version = Some(line),
+            Some(_) => capabilities.push(line),
+        }
+    }
+    if version.as_deref() != Some("version 2") {
+        bail!("{} does not speak version 2 of the git protocol", repo);
+    }
+    if capabilities.iter().any(|capability| {
+        capability.starts_with("object-format=") && capability != "object-format=sha1"
+    }) {
+        bail!("{} uses an object format other than SHA-1", repo);
+    }
+    Ok(capabilities)
+}
+
+/// Commit a branch or tag points at, through `ls-refs`.
+async fn ls_ref(client: &HttpClient, repo: &ModuleSpecifier, name: &str) -> Result<String, Error> {
+    capabilities(client, repo).await?;
+    let mut request = vec![];
+    pkt_line(&mut request, "command=ls-refs\n");
+    request.extend_from_slice(DELIM);
+    pkt_line(&mut request, "peel\n");
+    let refs = [
+        format!("refs/heads/{}", name),
+        format!("refs/tags/{}", name),
+        name.to_string(),
+    ];
+    for prefix in &refs {
+        pkt_line(&mut request, &format!("ref-prefix {}\n", prefix));
+    }
+    request.extend_from_slice(FLUSH);
+    let body = upload_pack(client, repo, request).await?;
+
+    let mut found = HashMap::new();
+    let mut lines = PktLines::new(&body);
+    while let Some(Pkt::Data(line)) = lines.next()? {
+        let line = String::from_utf8_lossy(line);
+        let mut fields = line.trim_end().split(' ');
+        let (Some(oid), Some(name)) = (fields.next(), fields.next()) else {
+            continue;
+        };
+        let peeled = fields.find_map(|field| field.strip_prefix("peeled:"));
+        found.insert(name.to_string(), peeled.unwrap_or(oid).to_string());
+    }
+    refs.iter()
+        .find_map(|name| found.remove(name))
+        .ok_or_else(|| anyhow!("{} has no branch or tag {}", repo, name))
+}
+
+/// Pack with the commit and its tree, without history when the server
+/// supports shallow fetches.
+async fn fetch_pack(
+    client: &HttpClient,
+    repo: &ModuleSpecifier,
+    commit: &str,
+) -> Result<Vec<u8>, Error> {
+    let capabilities = capabilities(client, repo).await?;
+    let shallow = capabilities.iter().any(|capability| {
+        capability
+            .strip_prefix("fetch=")
+            .is_some_and(|features| features.split(' ').any(|feature| feature == "shallow"))
+    });
+    log::debug!("fetching {} of {}", commit, repo);
+    let mut request = vec![];
+    pkt_line(&mut request, "command=fetch\n");
+    request.extend_from_slice(DELIM);
+    pkt_line(&mut request, "no-progress\n");
+    pkt_line(&mut request, "ofs-delta\n");
+    pkt_line(&mut request, &format!("want {}\n", commit));
+    if shallow {
+        pkt_line(&mut request, "deepen 1\n");
+    }
+    pkt_line(&mut request, "done\n");
+    request.extend_from_slice(FLUSH);
+    let body = upload_pack(client, repo, request).await?;
+
+    let mut pack = vec![];
+    let mut lines = PktLines::new(&body);
+    let mut in_pack = false;
+    while let Some(pkt) = lines.next()? {
+        let Pkt::Data(line) = pkt else {
+            continue;
+        };
+        if !in_pack {
+            in_pack = line == b"packfile\n";
+            continue;
+        }
+        match line.split_first() {
+            Some((1, data)) => pack.extend_from_slice(data),
+            Some((2, progress)) => {
+                log::debug!("{}: {}", repo, String::from_utf8_lossy(progress).trim_end())
+            }
+            Some((3, error)) => bail!("{}: {}", repo, String::from_utf8_lossy(error).trim_end()),
+            _ => bail!("{} sent an invalid sideband packet", repo),
+        }
+    }
+    if !in_pack {
+        bail!("{} sent no pack for {}", repo, commit);
+    }
+    Ok(pack)
+}
+
+async fn upload_pack(
+    client: &HttpClient,
+    repo: &ModuleSpecifier,
+    request: Vec<u8>,
+) -> Result<Vec<u8>, Error> {
+    let url = endpoint(repo, "git-upload-pack")?;
+    send(client, &url, || {
+        client
+            .request(Method::POST, &url)
+            .header("Git-Protocol", GIT_PROTOCOL)
+            .header(CONTENT_TYPE, REQUEST_TYPE)
+            .header(ACCEPT, RESULT_TYPE)
+            .body(request.clone())
+    })
+    .await
+}
+
+async fn send(
+    client: &HttpClient,
+    url: &ModuleSpecifier,
+    build: impl Fn() -> reqwest::RequestBuilder,
+) -> Result<Vec<u8>, Error> {
+    let res = client
+        .send(url, build)
+        .await
+        .and_then(|res| Ok(res.error_for_status()?))
+        .map_err(|e| fetch::failed(url, e))?;
+    client
+        .body(url, res)
+        .await
+        .map_err(|e| fetch::failed(url, e))
+}
+
+const FLUSH: &[u8] = b"0000";
+const DELIM: &[u8] = b"0001";
+
+fn pkt_line(out: &mut Vec<u8>, line: &str) {
+    out.extend_from_slice(format!("{:04x}", line.len() + 4).as_bytes());
+    out.extend_from_slice(line.as_bytes());
+}
+
+enum Pkt<'a> {
+    Flush,
+    Delim,
+    Data(&'a [u8]),
+}
+
+/// Reads pkt-lines, failing on an `ERR` line.
+struct PktLines<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> PktLines<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn next(&mut self) -> Result<Option<Pkt<'a>>, Error> {
+        if self.bytes.is_empty() {
+            return Ok(None);
+        }
+        let length = self
+            .bytes
+            .get(..4)
+            .and_then(|length| std::str::from_utf8(length).ok())
+            .and_then(|length| usize::from_str_radix(length, 16).ok())
+            .ok_or_else(|| anyhow!("invalid pkt-line length"))?;
+        let pkt = match length {
+            0 => Pkt::Flush,
+            1 => Pkt::Delim,
+            // Response end, for stateless connections.
+            2 => Pkt::Flush,
+            3 => bail!("invalid pkt-line length"),
+            _ => {
+                let data = self
+                    .bytes
+                    .get(4..length)
+                    .ok_or_else(|| anyhow!("truncated pkt-line"))?;
+                if let Some(message) = data.strip_prefix(b"ERR ") {
+                    bail!("{}", String::from_utf8_lossy(message).trim_end());
+                }
+                Pkt::Data(data)
+            }
+        };
+        self.bytes = &self.bytes[length.max(4)..];
+        Ok(Some(pkt))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Commit => "commit",
+            Kind::Tree => "tree",
+            Kind::Blob => "blob",
+            Kind::Tag => "tag",
+        }
+    }
+}
+
+type Oid = [u8; 20];
+
+fn oid(kind: Kind, data: &[u8]) -> Oid {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("{} {}\0", kind.name(), data.len()));
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+fn hex(oid: &Oid) -> String {
+    oid.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+enum Entry {
+    Object(Kind, Vec<u8>),
+    OffsetDelta(usize, Vec<u8>),
+    RefDelta(Oid, Vec<u8>),
+}
+
+type Objects = HashMap<Oid, (Kind, Rc<Vec<u8>>)>;
+
+/// Objects of a pack by id, with deltas applied.
+fn unpack(pack: &[u8]) -> Result<Objects, Error> {
+    let (content, checksum) = pack
+        .split_last_chunk::<20>()
+        .ok_or_else(|| anyhow!("truncated pack"))?;
+    if Sha1::digest(content).as_slice() != checksum {
+        bail!("pack checksum mismatch");
+    }
+    let mut reader = PackReader {
+        bytes: content,
+        offset: 0,
+    };
+    if reader.take(4)? != b"PACK" || !matches!(reader.u32()?, 2 | 3) {
+        bail!("not a version 2 pack");
+    }
+    let count = reader.u32()?;
+
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let offset = reader.offset;
+        let byte = reader.byte()?;
+        let kind = (byte >> 4) & 7;
+        let mut size = (byte & 15) as usize;
+        let mut shift = 4;
+        let mut byte = byte;
+        while byte & 0x80 != 0 {
+            byte = reader.byte()?;
+            size |= ((byte & 0x7f) as usize)
+                .checked_shl(shift)
+                .ok_or_else(|| anyhow!("invalid object size"))?;
+            shift += 7;
+        }
+        let entry = match kind {
+            1..=4 => {
+                let kind = [Kind::Commit, Kind::Tree, Kind::Blob, Kind::Tag][kind as usize - 1];
+                Entry::Object(kind, reader.inflate(size)?)
+            }
+            6 => {
+                let mut byte = reader.byte()?;
+                let mut distance = (byte & 0x7f) as usize;
+                while byte & 0x80 != 0 {
+                    byte = reader.byte()?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as usize;
+                }
+                let base = offset
+                    .checked_sub(distance)
+                    .ok_or_else(|| anyhow!("delta base before the start of the pack"))?;
+                Entry::OffsetDelta(base, reader.inflate(size)?)
+            }
+            7 => {
+                let base = reader.take(20)?.try_into()?;
+                Entry::RefDelta(base, reader.inflate(size)?)
+            }
+            kind => bail!("unknown object type {}", kind),
+        };
+        entries.push((offset, entry));
+    }
+
+    // Deltas may come before their base, resolve them until none is left.
+    let mut by_offset: HashMap<usize, (Kind, Rc<Vec<u8>>)> = HashMap::new();
+    let mut objects: Objects = HashMap::new();
+    let mut pending = entries;
+    while !pending.is_empty() {
+        let before = pending.len();
+        let mut unresolved = vec![];
+        for (offset, entry) in pending {
+            let base = match &entry {
+                Entry::Object(..) => None,
+                Entry::OffsetDelta(base, _) => by_offset.get(base),
+                Entry::RefDelta(base, _) => objects.get(base),
+            };
+            let (kind, data) = match (entry, base) {
+                (Entry::Object(kind, data), _) => (kind, data),
+                (Entry::OffsetDelta(_, delta) | Entry::RefDelta(_, delta), Some((kind, base))) => {
+                    (*kind, apply_delta(base, &delta)?)
+                }
+                (entry, None) => {
+                    unresolved.push((offset, entry));
+                    continue;
+                }
+            };
+            let data = Rc::new(data);
+            by_offset.insert(offset, (kind, data.clone()));
+            objects.insert(oid(kind, &data), (kind, data));
+        }
+        if unresolved.len() == before {
+            bail!("pack has deltas against objects it doesn't contain");
+        }
+        pending = unresolved;
+    }
+    Ok(objects)
+}
+
+struct PackReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl PackReader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], Error> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + n)
+            .ok_or_else(|| anyhow!("truncated pack"))?;
+        self.offset += n;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// Inflates the zlib stream at the offset, which must hold `size`
+    /// bytes.
+    fn inflate(&mut self, size: usize) -> Result<Vec<u8>, Error> {
+        let mut inflater = Decompress::new(true);
+        let mut data = Vec::with_capacity(size);
+        loop {
+            let input = &self.bytes[self.offset + inflater.total_in() as usize..];
+            if data.len() == data.capacity() {
+                data.reserve(1);
+            }
+            let status = inflater.decompress_vec(input, &mut data, FlushDecompress::None)?;
+            match status {
+                Status::StreamEnd => break,
+                Status::Ok => {}
+                Status::BufError if input.is_empty() => bail!("truncated pack"),
+                Status::BufError => {}
+            }
+            if data.len() > size {
+                break;
+            }
+        }
+        if data.len() != size {
+            bail!("object size mismatch");
+        }
+        self.offset += inflater.total_in() as usize;
+        Ok(data)
+    }
+}
+
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut reader = PackReader {
+        bytes: delta,
+        offset: 0,
+    };
+    let varint = |reader: &mut PackReader| -> Result<usize, Error> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = reader.byte()?;
+            value |= ((byte & 0x7f) as usize)
+                .checked_shl(shift)
+                .ok_or_else(|| anyhow!("invalid delta size"))?;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    };
+    if varint(&mut reader)? != base.len() {
+        bail!("delta base size mismatch");
+    }
+    let size = varint(&mut reader)?;
+    let mut data = Vec::with_capacity(size);
+    while reader.offset < delta.len() {
+        let op = reader.byte()?;
+        if op & 0x80 != 0 {
+            let mut field = |bits: u8, count: usize| -> Result<usize, Error> {
+                let mut value = 0;
+                for i in 0..count {
+                    if bits & (1 << i) != 0 {
+                        value |= (reader.byte()? as usize) << (8 * i);
+                    }
+                }
+                Ok(value)
+            };
+            let offset = field(op, 4)?;
+            let length = match field(op >> 4, 3)? {
+                0 => 0x10000,
+                length => length,
+            };
+            let copied = base
+                .get(offset..offset + length)
+                .ok_or_else(|| anyhow!("delta copies past its base"))?;
+            data.extend_from_slice(copied);
+        } else if op != 0 {
+            data.extend_from_slice(reader.take(op as usize)?);
+        } else {
+            bail!("invalid delta instruction");
+        }
+    }
+    if data.len() != size {
+        bail!("delta result size mismatch");
+    }
+    Ok(data)
+}
+
+/// Writes the tree of `commit` to `<repo_dir>/<commit>`, through a
+/// temporary directory renamed into place once complete.
+async fn check_out(objects: &Objects, commit: &str, repo_dir: &Path) -> Result<(), Error> {
+    let commit_oid = parse_oid(commit)?;
+    let (kind, data) = objects
+        .get(&commit_oid)
+        .ok_or_else(|| anyhow!("the pack has no commit {}", commit))?;
+    if *kind != Kind::Commit {
+        bail!("{} is not a commit", commit);
+    }
+    let tree = std::str::from_utf8(data.as_slice())
+        .ok()
+        .and_then(|data| data.lines().next())
+        .and_then(|line| line.strip_prefix("tree "))
+        .ok_or_else(|| anyhow!("commit {} has no tree", commit))?;
+
+    let target = repo_dir.join(commit);
+    let temporary = repo_dir.join(format!("{}.{}.tmp", commit, std::process::id()));
+    let _ = tokio::fs::remove_dir_all(&temporary).await;
+    tokio::fs::create_dir_all(&temporary)
+        .await
+        .with_context(|| format!("could not create {}", temporary.display()))?;
+    let mut trees = vec![(parse_oid(tree)?, temporary.clone())];
+    while let Some((tree, dir)) = trees.pop() {
+        let data = match objects.get(&tree) {
+            Some((Kind::Tree, data)) => data,
+            _ => bail!("the pack has no tree {}", hex(&tree)),
+        };
+        for (mode, name, oid) in tree_entries(data)? {
+            let path = dir.join(name);
+            match mode {
+                "40000" => {
+                    tokio::fs::create_dir(&path).await?;
+                    trees.push((oid, path));
+                }
+                "100644" | "100755" => match objects.get(&oid) {
+                    Some((Kind::Blob, data)) => tokio::fs::write(&path, &**data).await?,
+                    _ => bail!("the pack has no blob {}", hex(&oid)),
+                },
+                _ => log::debug!("skipping {} with mode {}", path.display(), mode),
+            }
+        }
+    }
+    if let Err(e) = tokio::fs::rename(&temporary, &target).await {
+        let _ = tokio::fs::remove_dir_all(&temporary).await;
+        // Checked out concurrently by another run.
+        if tokio::fs::metadata(&target).await.is_err() {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+fn parse_oid(hex: &str) -> Result<Oid, Error> {
+    let mut oid = [0; 20];
+    if hex.len() != 40 {
+        bail!("invalid object id {}", hex);
+    }
+    for (i, byte) in oid.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| anyhow!("invalid object id {}", hex))?;
+    }
+    Ok(oid)
+}
+
+/// Mode, name and object id of the entries of a tree. Names that could
+/// leave the checkout are rejected.
+fn tree_entries(mut data: &[u8]) -> Result<Vec<(&str, &str, Oid)>, Error> {
+    let mut entries = vec![];
+    while !data.is_empty() {
+        let space = data
+            .iter()
+            .position(|&b| b == b' ')
+            .ok_or_else(|| anyhow!("invalid tree entry"))?;
+        let nul = data
+            .iter()
+            .position(|&b| b == 0)
+            .filter(|&nul| nul > space)
+            .ok_or_else(|| anyhow!("invalid tree entry"))?;
+        let mode = std::str::from_utf8(&data[..space])?;
+        let name = std::str::from_utf8(&data[space + 1..nul])
+            .map_err(|_| anyhow!("tree entry name is not UTF-8"))?;
+        if matches!(name, "" | "." | "..") || name.contains(['/', '\\']) {
+            bail!("unsafe tree entry name {:?}", name);
+        }
+        let oid = data
+            .get(nul + 1..nul + 21)
+            .ok_or_else(|| anyhow!("invalid tree entry"))?
+            .try_into()?;
+        entries.push((mode, name, oid));
+        data = &data[nul + 21..];
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn specifier(specifier: &str) -> ModuleSpecifier {
+        ModuleSpecifier::parse(specifier).unwrap()
+    }
+
+    #[test]
+    fn specifiers_split_into_repository_revision_and_path() {
+        let git = GitSpecifier::parse(&specifier(
+            "git+https://example.com/org/repo.git#0123456789abcdef0123456789abcdef01234567/src/mod.ts",
+        ))
+        .unwrap();
+        assert_eq!(git.repo.as_str(), "https://example.com/org/repo.git");
+        assert_eq!(git.revision, "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(git.path, "src/mod.ts");
+        assert!(git.is_commit());
+        assert!(GitSpecifier::parse(&specifier("git+https://example.com/repo.git#main")).is_err());
+        assert!(
+            GitSpecifier::parse(&specifier("git+https://example.com/repo.git#main/../x")).is_err()
+        );
+    }
+
+    #[test]
+    fn relative_imports_stay_in_the_revision() {
+        let referrer = "git+https://example.com/repo.git#main/src/lib/mod.ts";
+        let resolve = |specifier| resolve(specifier, referrer).unwrap().unwrap().to_string();
+        assert_eq!(
+            resolve("./util.ts"),
+            "git+https://example.com/repo.git#main/src/lib/util.ts"
+        );
+        assert_eq!(
+            resolve("../../deps.ts"),
+            "git+https://example.com/repo.git#main/deps.ts"
+        );
+        assert_eq!(
+            resolve("/config.json"),
+            "git+https://example.com/repo.git#main/config.json"
+        );
+        assert!(super::resolve("../../../x.ts", referrer).is_err());
+        assert_eq!(super::resolve("npm:x", referrer).unwrap(), None);
+    }
+
+    #[test]
+    fn deltas_copy_from_their_base_and_insert() {
+        let base = b"hello, world";
+        // Sizes 12 and 13, copy 7 bytes at 0, insert "there!".
+        let delta = [12, 13, 0x90, 7, 6, b't', b'h', b'e', b'r', b'e', b'!'];
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"hello, there!");
+        assert!(apply_delta(b"short", &delta).is_err());
+    }
+
+    #[test]
+    fn tree_entries_cannot_leave_the_checkout() {
+        let mut tree = b"100644 ..\0".to_vec();
+        tree.extend([0; 20]);
+        assert!(tree_entries(&tree).is_err());
+    }
+}
@@ -0,0 +1,544 @@
+use anyhow::Error;
+use deno_core::error::JsError;
+use futures::Stream;
+use serde_json::Value;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::{Code, Request, Response, Status};
+
+use crate::error::RuntimeError;
+use crate::inputs::Inputs;
+use crate::messages::InvocationHandle;
+use crate::options::RunOptions;
+use proto::invocation_server::{Invocation, InvocationServer};
+use proto::{
+    invocation_event, invocation_request, invocation_response, InvocationEvent, InvocationRequest,
+    InvocationResponse,
+};
+
+/// Messages and client of the service, generated from
+/// `proto/invocation.proto`.
+pub mod proto {
+    tonic::include_proto!("experimental_runtime.v1");
+
+    pub(crate) const FILE_DESCRIPTOR_SET: &[u8] =
+        tonic::include_file_descriptor_set!("invocation_descriptor");
+}
+
+/// Serves functions over gRPC, as the `Invocation` service of
+/// `proto/invocation.proto`, next to the standard health and reflection
+/// services. Requests name a function registered with
+/// [`route`](Self::route), or with [`allow_paths`](Self::allow_paths) a
+/// module path on the server.
+///
+/// Each invocation runs on a thread and isolate of its own. Invocations
+/// past the concurrency limit wait for a slot, highest priority first, and
+/// a client going away or its deadline passing cancels its invocation.
+pub struct GrpcServer {
+    routes: HashMap<String, PathBuf>,
+    options: RunOptions,
+    concurrency: usize,
+    allow_paths: bool,
+}
+
+impl GrpcServer {
+    /// Server without routes, running four invocations at a time.
+    pub fn new(options: RunOptions) -> Self {
+        Self {
+            routes: HashMap::new(),
+            options,
+            concurrency: 4,
+            allow_paths: false,
+        }
+    }
+
+    /// Serves `function` as the handle `name`, replacing any earlier
+    /// function of that name.
+    pub fn route(mut self, name: impl Into<String>, function: impl Into<PathBuf>) -> Self {
+        self.routes.insert(name.into(), function.into());
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Lets requests run any module on the server by its path, not only
+    /// the registered ones.
+    pub fn allow_paths(mut self, allow: bool) -> Self {
+        self.allow_paths = allow;
+        self
+    }
+
+    /// Accepts connections until `shutdown` completes, then answers the
+    /// requests already received before returning.
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), Error> {
+        let service = InvocationService(Arc::new(Shared {
+            routes: self.routes,
+            options: self.options,
+            slots: Slots::new(self.concurrency),
+            allow_paths: self.allow_paths,
+        }));
+        let (mut reporter, health) = tonic_health::server::health_reporter();
+        reporter
+            .set_serving::<InvocationServer<InvocationService>>()
+            .await;
+        tonic::transport::Server::builder()
+            .add_service(health)
+            .add_service(reflection().build_v1()?)
+            .add_service(reflection().build_v1alpha()?)
+            .add_service(InvocationServer::new(service))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+            .await?;
+        Ok(())
+    }
+}
+
+/// Reflection over the invocation and health services.
+fn reflection() -> tonic_reflection::server::Builder<'static> {
+    tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(proto::FILE_DESCRIPTOR_SET)
+        .register_encoded_file_descriptor_set(tonic_health::pb::FILE_DESCRIPTOR_SET)
+}
+
+struct Shared {
+    routes: HashMap<String, PathBuf>,
+    options: RunOptions,
+    slots: Arc<Slots>,
+    allow_paths: bool,
+}
+
+/// A request turned into a run.
+struct Invoke {
+    function: PathBuf,
+    inputs: Inputs,
+    options: RunOptions,
+    priority: i32,
+    /// Respond with JSON bytes rather than a `google.protobuf.Value`.
+    json: bool,
+}
+
+impl Shared {
+    fn prepare(&self, request: Request<InvocationRequest>) -> Result<Invoke, Box<Status>> {
+        let deadline = grpc_timeout(request.metadata());
+        let mut options = self.options.clone();
+        #[cfg(feature = "otel")]
+        {
+            options.trace_context = crate::otel::TraceContext::from_headers(|name| {
+                request.metadata().get(name)?.to_str().ok()
+            });
+        }
+        let request = request.into_inner();
+        let function = match request.module {
+            Some(invocation_request::Module::Handle(name)) => match self.routes.get(&name) {
+                Some(function) => function.clone(),
+                None => {
+                    let message = format!("no function named {}", name);
+                    return Err(Box::new(Status::not_found(message)));
+                }
+            },
+            Some(invocation_request::Module::Path(path)) if self.allow_paths => path.into(),
+            Some(invocation_request::Module::Path(_)) => {
+                let message = "the server only runs functions by handle";
+                return Err(Box::new(Status::permission_denied(message)));
+            }
+            None => {
+                return Err(Box::new(Status::invalid_argument(
+                    "the request names no module",
+                )))
+            }
+        };
+        let (inputs, json) = match request.inputs {
+            Some(invocation_request::Inputs::StructInputs(inputs)) => {
+                let inputs: HashMap<_, _> = inputs
+                    .fields
+                    .into_iter()
+                    .map(|(name, value)| (name, to_json(value)))
+                    .collect();
+                (inputs, false)
+            }
+            Some(invocation_request::Inputs::JsonInputs(bytes)) if bytes.is_empty() => {
+                (HashMap::new(), true)
+            }
+            Some(invocation_request::Inputs::JsonInputs(bytes)) => {
+                let inputs = serde_json::from_slice(&bytes).map_err(|e| {
+                    Box::new(Status::invalid_argument(format!(
+                        "inputs must be a json object: {}",
+                        e
+                    )))
+                })?;
+                (inputs, true)
+            }
+            None => (HashMap::new(), false),
+        };
+        let requested =
+            (request.deadline_ms > 0).then(|| Duration::from_millis(request.deadline_ms));
+        options.timeout = [options.timeout, deadline, requested]
+            .into_iter()
+            .flatten()
+            .min();
+        Ok(Invoke {
+            function,
+            inputs: Inputs::from(inputs),
+            options,
+            priority: request.priority,
+            json,
+        })
+    }
+}
+
+struct InvocationService(Arc<Shared>);
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<InvocationEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Invocation for InvocationService {
+    async fn invoke(
+        &self,
+        request: Request<InvocationRequest>,
+    ) -> Result<Response<InvocationResponse>, Status> {
+        let invoke = self.0.prepare(request).map_err(|status| *status)?;
+        let _slot = self.0.slots.acquire(invoke.priority).await;
+        log::debug!("invoking {}", invoke.function.display());
+        let value = crate::run(invoke.function, invoke.inputs, invoke.options)
+            .await
+            .map_err(|e| status(&e))?;
+        Ok(Response::new(response(value, invoke.json)))
+    }
+
+    type InvokeStreamStream = EventStream;
+
+    async fn invoke_stream(
+        &self,
+        request: Request<InvocationRequest>,
+    ) -> Result<Response<EventStream>, Status> {
+        let invoke = self.0.prepare(request).map_err(|status| *status)?;
+        let slot = self.0.slots.acquire(invoke.priority).await;
+        log::debug!("invoking {} with a stream", invoke.function.display());
+        let mut handle = InvocationHandle::start(invoke.function, invoke.inputs, invoke.options)
+            .map_err(|e| status(&e))?;
+        let (sender, events) = mpsc::channel(16);
+        tokio::spawn(async move {
+            let _slot = slot;
+            let gone = loop {
+                let message = tokio::select! {
+                    message = handle.recv() => message,
+                    _ = sender.closed() => break true,
+                };
+                let Some(message) = message else {
+                    break false;
+                };
+                let event = invocation_event::Event::Message(from_json(message));
+                let event = InvocationEvent { event: Some(event) };
+                if sender.send(Ok(event)).await.is_err() {
+                    break true;
+                }
+            };
+            if gone {
+                handle.cancel();
+            }
+            let result = handle.result().await;
+            if !gone {
+                let event = result
+                    .map(|value| InvocationEvent {
+                        event: Some(invocation_event::Event::Result(response(
+                            value,
+                            invoke.json,
+                        ))),
+                    })
+                    .map_err(|e| status(&e));
+                let _ = sender.send(event).await;
+            }
+        });
+        Ok(Response::new(Box::pin(ReceiverStream::new(events))))
+    }
+}
+
+fn response(value: Value, json: bool) -> InvocationResponse {
+    let result = match json {
+        true => invocation_response::Result::Json(value.to_string().into_bytes()),
+        false => invocation_response::Result::Value(from_json(value)),
+    };
+    InvocationResponse {
+        result: Some(result),
+    }
+}
+
+/// The call's deadline, from the `grpc-timeout` header clients send it in.
+fn grpc_timeout(metadata: &MetadataMap) -> Option<Duration> {
+    let value = metadata.get("grpc-timeout")?.to_str().ok()?;
+    let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+    let amount: u64 = amount.parse().ok()?;
+    Some(match unit {
+        "H" => Duration::from_secs(amount.saturating_mul(3600)),
+        "M" => Duration::from_secs(amount.saturating_mul(60)),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    })
+}
+
+/// The status a failed run responds with. The error's kind, as in
+/// `RuntimeError::kind`, is in the `error-kind` metadata.
+fn status(error: &Error) -> Status {
+    let runtime_error = error.downcast_ref::<RuntimeError>();
+    let code = match runtime_error {
+        Some(RuntimeError::InputTooLarge { .. } | RuntimeError::InputValidation { .. }) => {
+            Code::InvalidArgument
+        }
+        Some(RuntimeError::Timeout { .. }) => Code::DeadlineExceeded,
+        Some(RuntimeError::BudgetExceeded { .. } | RuntimeError::HeapLimitExceeded { .. }) => {
+            Code::ResourceExhausted
+        }
+        Some(RuntimeError::PermissionDenied { .. }) => Code::PermissionDenied,
+        Some(RuntimeError::Cancelled) => Code::Cancelled,
+        Some(RuntimeError::ShutDown | RuntimeError::QueueFull { .. }) => Code::Unavailable,
+        Some(
+            RuntimeError::ModuleResolution { .. }
+            | RuntimeError::ModuleLoad { .. }
+            | RuntimeError::Fetch { .. }
+            | RuntimeError::Transpile { .. }
+            | RuntimeError::MissingEntrypoint { .. },
+        ) => Code::FailedPrecondition,
+        _ => Code::Internal,
+    };
+    let kind = match runtime_error {
+        Some(error) => error.kind(),
+        None if error.is::<JsError>() => "js_exception",
+        None => "internal",
+    };
+    let mut status = Status::new(code, format!("{:#}", error));
+    status
+        .metadata_mut()
+        .insert("error-kind", MetadataValue::from_static(kind));
+    status
+}
+
+fn to_json(value: prost_types::Value) -> Value {
+    use prost_types::value::Kind;
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::NumberValue(number)) => {
+            serde_json::Number::from_f64(number).map_or(Value::Null, Value::Number)
+        }
+        Some(Kind::StringValue(string)) => Value::String(string),
+        Some(Kind::BoolValue(bool)) => Value::Bool(bool),
+        Some(Kind::StructValue(object)) => Value::Object(
+            object
+                .fields
+                .into_iter()
+                .map(|(name, value)| (name, to_json(value)))
+                .collect(),
+        ),
+        Some(Kind::ListValue(list)) => Value::Array(list.values.into_iter().map(to_json).collect()),
+    }
+}
+
+fn from_json(value: Value) -> prost_types::Value {
+    use prost_types::value::Kind;
+    let kind = match value {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(bool) => Kind::BoolValue(bool),
+        Value::Number(number) => Kind::NumberValue(number.as_f64().unwrap_or_default()),
+        Value::String(string) => Kind::StringValue(string),
+        Value::Array(values) => Kind::ListValue(prost_types::ListValue {
+            values: values.into_iter().map(from_json).collect(),
+        }),
+        Value::Object(object) => Kind::StructValue(prost_types::Struct {
+            fields: object
+                .into_iter()
+                .map(|(name, value)| (name, from_json(value)))
+                .collect(),
+        }),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+/// Invocation slots, handed to waiting invocations highest priority
+/// first and in the order they arrived within a priority.
+struct Slots {
+    state: Mutex<SlotState>,
+}
+
+struct SlotState {
+    free: usize,
+    waiting: BinaryHeap<Waiter>,
+    arrivals: u64,
+}
+
+struct Waiter {
+    priority: i32,
+    arrival: Reverse<u64>,
+    wake: oneshot::Sender<()>,
+}
+
+impl Waiter {
+    fn key(&self) -> (i32, Reverse<u64>) {
+        (self.priority, self.arrival)
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// A taken slot, given back on drop.
+struct Slot(Arc<Slots>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+/// A wait for a slot. A slot handed to it after it was dropped is given
+/// back.
+struct Wait {
+    slots: Arc<Slots>,
+    woken: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for Wait {
+    fn drop(&mut self) {
+        if let Some(mut woken) = self.woken.take() {
+            woken.close();
+            if woken.try_recv().is_ok() {
+                self.slots.release();
+            }
+        }
+    }
+}
+
+impl Slots {
+    fn new(count: usize) -> Arc<Self> {
+        Arc::new(Self {
+            state: Mutex::new(SlotState {
+                free: count,
+                waiting: BinaryHeap::new(),
+                arrivals: 0,
+            }),
+        })
+    }
+
+    async fn acquire(self: &Arc<Self>, priority: i32) -> Slot {
+        let woken = {
+            let mut state = self.state.lock().unwrap();
+            if state.free > 0 {
+                state.free -= 1;
+                return Slot(self.clone());
+            }
+            let (wake, woken) = oneshot::channel();
+            state.arrivals += 1;
+            let arrival = Reverse(state.arrivals);
+            state.waiting.push(Waiter {
+                priority,
+                arrival,
+                wake,
+            });
+            woken
+        };
+        let mut wait = Wait {
+            slots: self.clone(),
+            woken: Some(woken),
+        };
+        // The sender is only dropped after sending.
+        let _ = wait.woken.as_mut().unwrap().await;
+        wait.woken = None;
+        Slot(self.clone())
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        while let Some(waiter) = state.waiting.pop() {
+            if waiter.wake.send(()).is_ok() {
+                return;
+            }
+        }
+        state.free += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grpc_timeouts() {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(grpc_timeout(&metadata), None);
+        for (header, expected) in [
+            ("2S", Duration::from_secs(2)),
+            ("150m", Duration::from_millis(150)),
+            ("1H", Duration::from_secs(3600)),
+        ] {
+            metadata.insert("grpc-timeout", header.parse().unwrap());
+            assert_eq!(grpc_timeout(&metadata), Some(expected));
+        }
+        metadata.insert("grpc-timeout", "soon".parse().unwrap());
+        assert_eq!(grpc_timeout(&metadata), None);
+    }
+
+    #[tokio::test]
+    async fn slots_go_to_the_highest_priority_first() {
+        let slots = Slots::new(1);
+        let held = slots.acquire(0).await;
+        let (order, mut finished) = mpsc::unbounded_channel();
+        let mut waits = Vec::new();
+        for priority in [1, 5, 3] {
+            let (slots, order) = (slots.clone(), order.clone());
+            waits.push(tokio::spawn(async move {
+                let _slot = slots.acquire(priority).await;
+                order.send(priority).unwrap();
+            }));
+            // Lets the wait queue up before the next one.
+            tokio::task::yield_now().await;
+        }
+        drop(held);
+        for wait in waits {
+            wait.await.unwrap();
+        }
+        let mut priorities = vec![];
+        while let Ok(priority) = finished.try_recv() {
+            priorities.push(priority);
+        }
+        assert_eq!(priorities, [5, 3, 1]);
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let value = serde_json::json!({"a": [1.5, "b", null, true], "c": {"d": -2.0}});
+        assert_eq!(to_json(from_json(value.clone())), value);
+    }
+}
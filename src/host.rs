@@ -0,0 +1,351 @@
+use anyhow::{anyhow, bail, Error};
+use deno_core::{op2, AsyncRefCell, OpState, RcRef, Resource, ResourceId};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::rc::Rc;
+use tokio::io::AsyncReadExt;
+
+use crate::console::{ConsoleCapture, RawEvent};
+use crate::determinism::DeterministicState;
+use crate::host_api::{HostCall, SharedHostApi};
+use crate::messages::HostMessages;
+use crate::trace::{self, RawFetch, ReplayedFetch, TraceMode};
+
+/// Cap on `text()`/`bytes()` when no `file_read_limit` is configured.
+pub(crate) const DEFAULT_FILE_READ_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Host files handed to the script, keyed by the name the script sees.
+/// Paths never cross into JS.
+pub(crate) struct HostFiles {
+    pub(crate) paths: HashMap<String, PathBuf>,
+    pub(crate) read_limit: usize,
+}
+
+impl HostFiles {
+    fn path(&self, name: &str) -> Result<PathBuf, Error> {
+        self.paths
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("no file named {:?} was provided", name))
+    }
+}
+
+/// Variables `Deno.env` serves instead of the host's environment.
+struct HostEnv(HashMap<String, String>);
+
+struct HostFileResource {
+    file: AsyncRefCell<tokio::fs::File>,
+}
+
+impl Resource for HostFileResource {
+    fn name(&self) -> Cow<'_, str> {
+        "hostFile".into()
+    }
+}
+
+deno_core::extension!(
+    host,
+    ops = [
+        op_host_file_list,
+        op_host_file_open,
+        op_host_file_read,
+        op_host_file_read_all,
+        op_host_api_methods,
+        op_host_api_call,
+        op_host_api_call_async,
+        op_host_console_capture,
+        op_host_console_event,
+        op_host_env,
+        op_host_emit,
+        op_host_message_recv,
+        op_host_sandboxed,
+        op_host_deterministic,
+        op_host_random,
+        op_host_now,
+        op_host_elapsed,
+        op_host_trace_mode,
+        op_host_trace_record_fetch,
+        op_host_trace_replay_fetch,
+        op_host_trace_context,
+    ],
+    esm_entry_point = "ext:host/runtime.js",
+    esm = [dir "src", "runtime.js"],
+    options = {
+        files: HostFiles,
+        api: Option<SharedHostApi>,
+        console: ConsoleCapture,
+        env: Option<HashMap<String, String>>,
+        messages: Option<HostMessages>,
+    },
+    state = |state, options| {
+        state.put(options.files);
+        state.put(options.console);
+        if let Some(api) = options.api {
+            state.put(api);
+        }
+        if let Some(env) = options.env {
+            state.put(HostEnv(env));
+        }
+        if let Some(messages) = options.messages {
+            state.put(Rc::new(messages));
+        }
+    },
+);
+
+#[op2]
+#[serde]
+fn op_host_file_list(state: &OpState) -> Result<Vec<(String, u64)>, Error> {
+    let files = state.borrow::<HostFiles>();
+    files
+        .paths
+        .iter()
+        .map(|(name, path)| Ok((name.clone(), std::fs::metadata(path)?.len())))
+        .collect()
+}
+
+#[op2(async)]
+#[smi]
+async fn op_host_file_open(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+) -> Result<ResourceId, Error> {
+    let path = state.borrow().borrow::<HostFiles>().path(&name)?;
+    log::debug!("opening host file {}", name);
+    let file = tokio::fs::File::open(path).await?;
+    let rid = state.borrow_mut().resource_table.add(HostFileResource {
+        file: AsyncRefCell::new(file),
+    });
+    Ok(rid)
+}
+
+#[op2(async)]
+#[buffer]
+async fn op_host_file_read(
+    state: Rc<RefCell<OpState>>,
+    #[smi] rid: ResourceId,
+    #[smi] len: u32,
+) -> Result<Vec<u8>, Error> {
+    let resource = state.borrow().resource_table.get::<HostFileResource>(rid)?;
+    let mut file = RcRef::map(&resource, |r| &r.file).borrow_mut().await;
+    let mut buf = vec![0; len as usize];
+    let n = file.read(&mut buf).await?;
+    buf.truncate(n);
+    Ok(buf)
+}
+
+#[op2(async)]
+#[buffer]
+async fn op_host_file_read_all(
+    state: Rc<RefCell<OpState>>,
+    #[string] name: String,
+) -> Result<Vec<u8>, Error> {
+    let (path, limit) = {
+        let state = state.borrow();
+        let files = state.borrow::<HostFiles>();
+        (files.path(&name)?, files.read_limit)
+    };
+    let file = tokio::fs::File::open(path).await?;
+    let mut buf = vec![];
+    file.take(limit as u64 + 1).read_to_end(&mut buf).await?;
+    if buf.len() > limit {
+        bail!(
+            "file {:?} is larger than {} bytes, use stream() instead",
+            name,
+            limit
+        );
+    }
+    Ok(buf)
+}
+
+fn host_api(state: &OpState) -> Result<SharedHostApi, Error> {
+    state
+        .try_borrow::<SharedHostApi>()
+        .cloned()
+        .ok_or_else(|| anyhow!("no host api was provided"))
+}
+
+#[op2]
+#[serde]
+fn op_host_api_methods(state: &OpState) -> Vec<(String, bool)> {
+    if let Some(TraceMode::Replay(trace)) = state.try_borrow::<TraceMode>() {
+        return trace.host_methods();
+    }
+    state
+        .try_borrow::<SharedHostApi>()
+        .map(|api| {
+            api.0
+                .methods()
+                .into_iter()
+                .map(|method| (method.name, method.is_async))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Calls a host method, through the trace when there is one.
+fn call_host_api(
+    state: &OpState,
+    method: &str,
+    is_async: bool,
+    args: Vec<serde_json::Value>,
+) -> HostCall {
+    let api = host_api(state);
+    trace::host_call(
+        state.try_borrow(),
+        method,
+        is_async,
+        args,
+        |args| match api {
+            Ok(api) => api.0.call(method, args),
+            Err(e) => HostCall::Ready(Err(e)),
+        },
+    )
+}
+
+#[op2]
+#[serde]
+fn op_host_api_call(
+    state: &OpState,
+    #[string] method: String,
+    #[serde] args: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, Error> {
+    match call_host_api(state, &method, false, args) {
+        HostCall::Ready(result) => result,
+        HostCall::Pending(_) => bail!("host api method {:?} is async", method),
+    }
+}
+
+#[op2(async)]
+#[serde]
+async fn op_host_api_call_async(
+    state: Rc<RefCell<OpState>>,
+    #[string] method: String,
+    #[serde] args: Vec<serde_json::Value>,
+) -> Result<serde_json::Value, Error> {
+    let call = call_host_api(&state.borrow(), &method, true, args);
+    match call {
+        HostCall::Ready(result) => result,
+        HostCall::Pending(future) => future.await,
+    }
+}
+
+#[op2(fast)]
+fn op_host_console_capture(state: &OpState) -> bool {
+    state.borrow::<ConsoleCapture>().sink.is_some()
+}
+
+#[op2]
+fn op_host_console_event(state: &OpState, #[serde] event: RawEvent) -> Result<(), Error> {
+    state.borrow::<ConsoleCapture>().emit(event)
+}
+
+#[op2]
+#[serde]
+fn op_host_env(state: &OpState) -> Option<HashMap<String, String>> {
+    state.try_borrow::<HostEnv>().map(|env| env.0.clone())
+}
+
+/// Marks a worker whose file APIs are confined to an `FsSandbox`.
+pub(crate) struct Sandboxed;
+
+#[op2(fast)]
+fn op_host_sandboxed(state: &OpState) -> bool {
+    state.has::<Sandboxed>()
+}
+
+#[op2(fast)]
+fn op_host_deterministic(state: &OpState) -> bool {
+    state.has::<DeterministicState>()
+}
+
+#[op2(fast)]
+fn op_host_random(state: &mut OpState) -> f64 {
+    state.borrow_mut::<DeterministicState>().random()
+}
+
+#[op2(fast)]
+fn op_host_now(state: &OpState) -> f64 {
+    state.borrow::<DeterministicState>().now()
+}
+
+#[op2(fast)]
+fn op_host_elapsed(state: &OpState) -> f64 {
+    state.borrow::<DeterministicState>().elapsed()
+}
+
+#[op2]
+#[string]
+fn op_host_trace_mode(state: &OpState) -> Option<&'static str> {
+    match state.try_borrow::<TraceMode>()? {
+        TraceMode::Record(_) => Some("record"),
+        TraceMode::Replay(_) => Some("replay"),
+    }
+}
+
+#[op2]
+fn op_host_trace_record_fetch(
+    state: &OpState,
+    #[serde] fetch: RawFetch,
+    #[buffer] body: &[u8],
+) -> Result<(), Error> {
+    match state.try_borrow::<TraceMode>() {
+        Some(TraceMode::Record(recorder)) => recorder.record(&fetch.into_entry(body)),
+        _ => bail!("fetches are not being recorded"),
+    }
+}
+
+#[op2]
+#[serde]
+fn op_host_trace_replay_fetch(
+    state: &OpState,
+    #[string] method: String,
+    #[string] url: String,
+) -> Result<ReplayedFetch, Error> {
+    match state.try_borrow::<TraceMode>() {
+        Some(TraceMode::Replay(trace)) => trace.fetch(&method, &url),
+        _ => bail!("no trace is being replayed"),
+    }
+}
+
+/// Headers carrying the trace context of the run to the script's fetches,
+/// see `RunOptions::propagate_trace_context`.
+pub(crate) struct TraceHeaders(pub(crate) Vec<(String, String)>);
+
+#[op2]
+#[serde]
+fn op_host_trace_context(state: &OpState) -> Vec<(String, String)> {
+    state
+        .try_borrow::<TraceHeaders>()
+        .map(|headers| headers.0.clone())
+        .unwrap_or_default()
+}
+
+#[op2]
+fn op_host_emit(state: &OpState, #[serde] message: serde_json::Value) -> Result<(), Error> {
+    let messages = state
+        .try_borrow::<Rc<HostMessages>>()
+        .ok_or_else(|| anyhow!("no host is listening, start the run with InvocationHandle"))?;
+    // The host may have stopped listening, which is not the script's problem.
+    let _ = messages.outbox.send(message);
+    Ok(())
+}
+
+/// Next message from the host, as `[open, message]`. Resolves with `open`
+/// false once the host can't send any more.
+#[op2(async)]
+#[serde]
+async fn op_host_message_recv(state: Rc<RefCell<OpState>>) -> (bool, serde_json::Value) {
+    let Some(messages) = state.borrow().try_borrow::<Rc<HostMessages>>().cloned() else {
+        return (false, serde_json::Value::Null);
+    };
+    let mut inbox = RcRef::map(&messages, |m| &m.inbox).borrow_mut().await;
+    match inbox.as_mut() {
+        Some(inbox) => match inbox.recv().await {
+            Some(message) => (true, message),
+            None => (false, serde_json::Value::Null),
+        },
+        None => (false, serde_json::Value::Null),
+    }
+}
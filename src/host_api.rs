@@ -0,0 +1,260 @@
+use anyhow::{anyhow, Error};
+use futures::future::BoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+
+/// A method the host exposes to scripts as `host.api[name]`.
+#[derive(Debug, Clone)]
+pub struct HostMethod {
+    pub name: String,
+    /// Async methods return a promise in JS, sync ones return the value.
+    pub is_async: bool,
+    /// TypeScript signature for the generated declarations, for example
+    /// `(a: number, b: number): number`. Untyped when unset.
+    pub signature: Option<String>,
+}
+
+/// Outcome of calling a host method.
+pub enum HostCall {
+    Ready(Result<Value, Error>),
+    Pending(BoxFuture<'static, Result<Value, Error>>),
+}
+
+/// Host object whose methods scripts can call through `host.api`. Arguments
+/// arrive as the JSON array of what the script passed, errors are thrown in
+/// JS with their message.
+pub trait HostApi: Send + Sync + 'static {
+    fn methods(&self) -> Vec<HostMethod>;
+    fn call(&self, method: &str, args: Vec<Value>) -> HostCall;
+}
+
+/// A [`HostApi`] shared between runs, set as `RunOptions::host_api`.
+#[derive(Clone)]
+pub struct SharedHostApi(pub(crate) Arc<dyn HostApi>);
+
+impl SharedHostApi {
+    pub fn new(api: impl HostApi) -> Self {
+        Self(Arc::new(api))
+    }
+}
+
+impl From<Box<dyn HostApi>> for SharedHostApi {
+    fn from(api: Box<dyn HostApi>) -> Self {
+        Self(api.into())
+    }
+}
+
+impl fmt::Debug for SharedHostApi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedHostApi")
+    }
+}
+
+type Method = dyn Fn(Vec<Value>) -> HostCall + Send + Sync;
+
+/// Builds a [`HostApi`] out of closures. Arguments are deserialized from
+/// the argument array, so a method taking two arguments takes a tuple.
+#[derive(Default)]
+pub struct HostApiBuilder {
+    methods: Vec<HostMethod>,
+    handlers: HashMap<String, Arc<Method>>,
+}
+
+impl HostApiBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn method<A, R>(
+        self,
+        name: &str,
+        method: impl Fn(A) -> Result<R, Error> + Send + Sync + 'static,
+    ) -> Self
+    where
+        A: DeserializeOwned,
+        R: Serialize,
+    {
+        let name_owned = name.to_string();
+        self.add(name, false, move |args| {
+            HostCall::Ready(decode(&name_owned, args).and_then(|args| encode(method(args)?)))
+        })
+    }
+
+    pub fn async_method<A, R, F>(
+        self,
+        name: &str,
+        method: impl Fn(A) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        A: DeserializeOwned,
+        R: Serialize,
+        F: Future<Output = Result<R, Error>> + Send + 'static,
+    {
+        let name_owned = name.to_string();
+        self.add(name, true, move |args| match decode(&name_owned, args) {
+            Ok(args) => {
+                let future = method(args);
+                HostCall::Pending(Box::pin(async move { encode(future.await?) }))
+            }
+            Err(e) => HostCall::Ready(Err(e)),
+        })
+    }
+
+    /// Sets the TypeScript signature of an already added method.
+    pub fn signature(mut self, name: &str, signature: &str) -> Self {
+        if let Some(method) = self.methods.iter_mut().find(|m| m.name == name) {
+            method.signature = Some(signature.to_string());
+        }
+        self
+    }
+
+    pub fn build(self) -> SharedHostApi {
+        SharedHostApi::new(self)
+    }
+
+    /// These methods over those of `base`, which stay callable unless a
+    /// method here has the same name.
+    pub fn layered(self, base: SharedHostApi) -> SharedHostApi {
+        SharedHostApi::new(Layered { base, top: self })
+    }
+
+    fn add(
+        mut self,
+        name: &str,
+        is_async: bool,
+        handler: impl Fn(Vec<Value>) -> HostCall + Send + Sync + 'static,
+    ) -> Self {
+        self.methods.retain(|m| m.name != name);
+        self.methods.push(HostMethod {
+            name: name.to_string(),
+            is_async,
+            signature: None,
+        });
+        self.handlers.insert(name.to_string(), Arc::new(handler));
+        self
+    }
+}
+
+impl HostApi for HostApiBuilder {
+    fn methods(&self) -> Vec<HostMethod> {
+        self.methods.clone()
+    }
+
+    fn call(&self, method: &str, args: Vec<Value>) -> HostCall {
+        match self.handlers.get(method) {
+            Some(handler) => handler(args),
+            None => HostCall::Ready(Err(anyhow!("host api has no method {:?}", method))),
+        }
+    }
+}
+
+struct Layered {
+    base: SharedHostApi,
+    top: HostApiBuilder,
+}
+
+impl HostApi for Layered {
+    fn methods(&self) -> Vec<HostMethod> {
+        let mut methods = self.top.methods();
+        for method in self.base.0.methods() {
+            if !self.top.handlers.contains_key(&method.name) {
+                methods.push(method);
+            }
+        }
+        methods
+    }
+
+    fn call(&self, method: &str, args: Vec<Value>) -> HostCall {
+        match self.top.handlers.contains_key(method) {
+            true => self.top.call(method, args),
+            false => self.base.0.call(method, args),
+        }
+    }
+}
+
+fn decode<A: DeserializeOwned>(method: &str, args: Vec<Value>) -> Result<A, Error> {
+    // Single-argument methods take the argument itself.
+    let args = match <[Value; 1]>::try_from(args) {
+        Ok([arg]) => serde_json::from_value(arg.clone())
+            .or_else(|_| serde_json::from_value(Value::Array(vec![arg]))),
+        Err(args) => serde_json::from_value(Value::Array(args)),
+    };
+    args.map_err(|e| anyhow!("invalid arguments for {}: {}", method, e))
+}
+
+fn encode<R: Serialize>(value: R) -> Result<Value, Error> {
+    Ok(serde_json::to_value(value)?)
+}
+
+/// TypeScript declarations for `host.api`, to type scripts against the
+/// methods `api` exposes.
+pub fn host_api_declarations(api: &dyn HostApi) -> String {
+    let mut out = String::from("declare namespace host {\n  const api: {\n");
+    for method in api.methods() {
+        out.push_str(&format!("    {};\n", method_declaration(&method)));
+    }
+    out.push_str("  };\n}\n");
+    out
+}
+
+/// `method` as a member of the `host.api` object type.
+pub(crate) fn method_declaration(method: &HostMethod) -> String {
+    let signature = method.signature.clone().unwrap_or_else(|| {
+        let returns = if method.is_async {
+            "Promise<unknown>"
+        } else {
+            "unknown"
+        };
+        format!("(...args: unknown[]): {}", returns)
+    });
+    format!(
+        "{}{}",
+        serde_json::to_string(&method.name).unwrap_or_default(),
+        signature
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn returns(name: &'static str) -> impl Fn(Vec<Value>) -> Result<&'static str, Error> {
+        move |_| Ok(name)
+    }
+
+    fn call(api: &SharedHostApi, method: &str) -> Value {
+        match api.0.call(method, vec![]) {
+            HostCall::Ready(result) => result.unwrap(),
+            HostCall::Pending(_) => panic!("{} is not sync", method),
+        }
+    }
+
+    #[test]
+    fn layered_methods_add_to_the_base() {
+        let base = HostApiBuilder::new()
+            .method("a", returns("base a"))
+            .method("b", returns("base b"))
+            .build();
+        let top = HostApiBuilder::new()
+            .method("b", returns("top b"))
+            .method("c", returns("top c"));
+        let api = top.layered(base);
+
+        let mut names = api
+            .0
+            .methods()
+            .into_iter()
+            .map(|m| m.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(call(&api, "a"), "base a");
+        assert_eq!(call(&api, "b"), "top b");
+        assert_eq!(call(&api, "c"), "top c");
+    }
+}
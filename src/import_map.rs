@@ -0,0 +1,159 @@
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::ModuleSpecifier;
+use serde_json::Value;
+use std::path::Path;
+
+use crate::file_url;
+
+/// Bare or URL specifier prefix mapped to an address. `None` when the map
+/// blocks the specifier by mapping it to `null`.
+type SpecifierMap = Vec<(String, Option<ModuleSpecifier>)>;
+
+/// An [import map](https://html.spec.whatwg.org/multipage/webappapis.html#import-maps),
+/// letting functions import bare specifiers such as `"zod"`.
+#[derive(Debug, Clone, Default)]
+pub struct ImportMap {
+    imports: SpecifierMap,
+    /// Longest scope first.
+    scopes: Vec<(String, SpecifierMap)>,
+}
+
+impl ImportMap {
+    /// Reads the map from a JSON file. Addresses resolve against the file.
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read import map {}", path.display()))?;
+        let json = serde_json::from_str(&text)
+            .with_context(|| format!("could not parse import map {}", path.display()))?;
+        let base = file_url::entry_specifier(path)?;
+        Self::from_json(&json, &base)
+    }
+
+    /// Takes an inline map, with addresses resolving against `base`.
+    pub fn from_json(json: &Value, base: &ModuleSpecifier) -> Result<Self, Error> {
+        let json = json
+            .as_object()
+            .ok_or_else(|| anyhow!("import map must be a JSON object"))?;
+        let imports = match json.get("imports") {
+            Some(imports) => specifier_map(imports, base)?,
+            None => vec![],
+        };
+        let mut scopes = vec![];
+        if let Some(entries) = json.get("scopes") {
+            let entries = entries
+                .as_object()
+                .ok_or_else(|| anyhow!("import map scopes must be an object"))?;
+            for (scope, map) in entries {
+                let scope = base
+                    .join(scope)
+                    .with_context(|| format!("invalid import map scope {:?}", scope))?;
+                scopes.push((scope.to_string(), specifier_map(map, base)?));
+            }
+        }
+        scopes.sort_by(|a, b| b.0.cmp(&a.0));
+        Ok(Self { imports, scopes })
+    }
+
+    /// What `specifier` imported from `referrer` maps to, `None` when the
+    /// map has nothing for it.
+    pub(crate) fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+    ) -> Result<Option<ModuleSpecifier>, Error> {
+        let referrer = ModuleSpecifier::parse(referrer).ok();
+        let as_url = url_like(specifier, referrer.as_ref());
+        let normalized = as_url.as_ref().map_or(specifier, |url| url.as_str());
+
+        if let Some(referrer) = &referrer {
+            for (scope, map) in &self.scopes {
+                let applies = referrer.as_str() == scope
+                    || (scope.ends_with('/') && referrer.as_str().starts_with(scope.as_str()));
+                if applies {
+                    if let Some(resolved) = resolve_in(map, specifier, normalized)? {
+                        return Ok(Some(resolved));
+                    }
+                }
+            }
+        }
+        resolve_in(&self.imports, specifier, normalized)
+    }
+}
+
+fn specifier_map(json: &Value, base: &ModuleSpecifier) -> Result<SpecifierMap, Error> {
+    let entries = json
+        .as_object()
+        .ok_or_else(|| anyhow!("import map entries must be an object"))?;
+    let mut map = vec![];
+    for (key, address) in entries {
+        if key.is_empty() {
+            continue;
+        }
+        let key = url_like(key, Some(base)).map_or_else(|| key.clone(), |url| url.to_string());
+        let address = match address {
+            Value::String(address) => {
+                let resolved = url_like(address, Some(base))
+                    .ok_or_else(|| anyhow!("invalid import map address {:?}", address))?;
+                if key.ends_with('/') && !resolved.as_str().ends_with('/') {
+                    bail!(
+                        "import map address of {:?} must end with a slash like its key",
+                        key
+                    );
+                }
+                Some(resolved)
+            }
+            Value::Null => None,
+            _ => bail!("import map address of {:?} must be a string", key),
+        };
+        map.push((key, address));
+    }
+    // Longest prefix first, so the first match is the most specific.
+    map.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(map)
+}
+
+/// Absolute URLs and `/`, `./` or `../` paths, resolved against `base`.
+/// Anything else is a bare specifier.
+fn url_like(specifier: &str, base: Option<&ModuleSpecifier>) -> Option<ModuleSpecifier> {
+    if ["/", "./", "../"]
+        .iter()
+        .any(|prefix| specifier.starts_with(prefix))
+    {
+        return base?.join(specifier).ok();
+    }
+    ModuleSpecifier::parse(specifier).ok()
+}
+
+fn resolve_in(
+    map: &SpecifierMap,
+    specifier: &str,
+    normalized: &str,
+) -> Result<Option<ModuleSpecifier>, Error> {
+    for (key, address) in map {
+        let rest = if key == normalized {
+            ""
+        } else if key.ends_with('/') && normalized.starts_with(key.as_str()) {
+            &normalized[key.len()..]
+        } else {
+            continue;
+        };
+        let address = address
+            .as_ref()
+            .ok_or_else(|| anyhow!("{} is blocked by the import map", specifier))?;
+        if rest.is_empty() {
+            return Ok(Some(address.clone()));
+        }
+        let resolved = address
+            .join(rest)
+            .with_context(|| format!("could not map {} with the import map", specifier))?;
+        if !resolved.as_str().starts_with(address.as_str()) {
+            bail!(
+                "{} backtracks above its import map prefix {}",
+                specifier,
+                key
+            );
+        }
+        return Ok(Some(resolved));
+    }
+    Ok(None)
+}
@@ -0,0 +1,95 @@
+use anyhow::{bail, Error};
+use deno_core::ModuleSpecifier;
+
+/// Hosts remote modules may be fetched from. Patterns are host names,
+/// `host:port`, or `*.example.com` for any subdomain of `example.com`.
+/// Denied hosts win over allowed ones.
+///
+/// Every fetch is checked before it is sent, redirect hops, `s3:` objects
+/// and npm registry requests included.
+#[derive(Debug, Clone, Default)]
+pub struct ImportPolicy {
+    /// Hosts imports may come from, any host when `None`.
+    pub allow_hosts: Option<Vec<String>>,
+    pub deny_hosts: Vec<String>,
+}
+
+impl ImportPolicy {
+    /// Only allows the given hosts.
+    pub fn allow(hosts: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            allow_hosts: Some(hosts.into_iter().map(Into::into).collect()),
+            deny_hosts: vec![],
+        }
+    }
+
+    pub(crate) fn check(&self, url: &ModuleSpecifier) -> Result<(), Error> {
+        let Some(host) = url.host_str() else {
+            return Ok(());
+        };
+        let host = host.to_ascii_lowercase();
+        let port = url.port_or_known_default();
+        let matches = |pattern: &String| matches(pattern, &host, port);
+        if self.deny_hosts.iter().any(matches) {
+            bail!("{} is denied by the import policy", host);
+        }
+        if let Some(allow) = &self.allow_hosts {
+            if !allow.iter().any(matches) {
+                bail!("{} is not an allowed import host", host);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn matches(pattern: &str, host: &str, port: Option<u16>) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    // IPv6 hosts are bracketed, so a colon after the brackets is a port.
+    let (name, pattern_port) = match pattern.rsplit_once(':') {
+        Some((name, pattern_port)) if !name.contains(':') || name.ends_with(']') => {
+            match pattern_port.parse::<u16>() {
+                Ok(pattern_port) => (name, Some(pattern_port)),
+                Err(_) => (pattern.as_str(), None),
+            }
+        }
+        _ => (pattern.as_str(), None),
+    };
+    if pattern_port.is_some() && pattern_port != port {
+        return false;
+    }
+    match name.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.ends_with('.')),
+        None => name == host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(policy: &ImportPolicy, url: &str) -> Result<(), Error> {
+        policy.check(&ModuleSpecifier::parse(url).unwrap())
+    }
+
+    #[test]
+    fn wildcards_ports_and_denials() {
+        let policy = ImportPolicy {
+            allow_hosts: Some(vec!["*.esm.sh".into(), "deno.land:443".into()]),
+            deny_hosts: vec!["bad.esm.sh".into()],
+        };
+        assert!(check(&policy, "https://cdn.esm.sh/a.js").is_ok());
+        assert!(check(&policy, "https://DENO.land/x/a.ts").is_ok());
+        assert!(check(&policy, "file:///a.js").is_ok());
+        // The wildcard only matches subdomains.
+        assert!(check(&policy, "https://esm.sh/a.js").is_err());
+        assert!(check(&policy, "https://evilesm.sh/a.js").is_err());
+        assert!(check(&policy, "http://deno.land:8080/a.ts").is_err());
+        let error = check(&policy, "https://bad.esm.sh/a.js").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "bad.esm.sh is denied by the import policy"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use deno_core::ModuleSpecifier;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::dependency::RemoteModule;
+
+/// Remembers which module first imported each specifier, so load failures
+/// can report how the loader got there.
+#[derive(Default)]
+pub(crate) struct ImportGraph {
+    referrers: RefCell<HashMap<ModuleSpecifier, ModuleSpecifier>>,
+    remote: RefCell<Vec<RemoteModule>>,
+    found: RefCell<HashMap<ModuleSpecifier, ModuleSpecifier>>,
+    links: RefCell<HashMap<ModuleSpecifier, PathBuf>>,
+}
+
+impl ImportGraph {
+    /// Loads are requested eagerly as imports are discovered, so a module's
+    /// referrer is always recorded before any of its own imports are.
+    pub(crate) fn record(&self, specifier: &ModuleSpecifier, referrer: &ModuleSpecifier) {
+        if specifier != referrer {
+            self.referrers
+                .borrow_mut()
+                .entry(specifier.clone())
+                .or_insert_with(|| referrer.clone());
+        }
+    }
+
+    /// Modules importing from the redirect target are attributed to whoever
+    /// imported the original URL.
+    #[cfg(feature = "net-loader")]
+    pub(crate) fn record_redirect(&self, from: &ModuleSpecifier, to: &ModuleSpecifier) {
+        let referrer = self.referrers.borrow().get(from).cloned();
+        if let Some(referrer) = referrer {
+            self.record(to, &referrer);
+        }
+    }
+
+    /// Notes that `specifier` was served from `found`, which its relative
+    /// imports resolve against.
+    pub(crate) fn record_found(&self, specifier: &ModuleSpecifier, found: &ModuleSpecifier) {
+        self.found
+            .borrow_mut()
+            .insert(specifier.clone(), found.clone());
+    }
+
+    /// Where `specifier` was served from, when that's somewhere else.
+    pub(crate) fn found(&self, specifier: &ModuleSpecifier) -> Option<ModuleSpecifier> {
+        self.found.borrow().get(specifier).cloned()
+    }
+
+    /// Notes that the file of `specifier` was reached through the symbolic
+    /// link at `link`.
+    pub(crate) fn record_link(&self, specifier: &ModuleSpecifier, link: PathBuf) {
+        self.links.borrow_mut().insert(specifier.clone(), link);
+    }
+
+    /// The link `specifier` was reached through, if any.
+    pub(crate) fn link(&self, specifier: &ModuleSpecifier) -> Option<PathBuf> {
+        self.links.borrow().get(specifier).cloned()
+    }
+
+    /// Notes a module fetched from a remote origin, for dependency reports.
+    #[cfg(feature = "net-loader")]
+    pub(crate) fn record_remote(
+        &self,
+        specifier: &ModuleSpecifier,
+        url: &ModuleSpecifier,
+        source: &[u8],
+    ) {
+        let module = RemoteModule::new(specifier, url, source);
+        self.remote.borrow_mut().push(module);
+    }
+
+    pub(crate) fn remote_modules(&self) -> Vec<RemoteModule> {
+        self.remote.borrow().clone()
+    }
+
+    /// Chain from the entry module down to `specifier`, inclusive.
+    pub(crate) fn chain(&self, specifier: &ModuleSpecifier) -> Vec<ModuleSpecifier> {
+        let referrers = self.referrers.borrow();
+        let mut chain = vec![specifier.clone()];
+        while let Some(referrer) = referrers.get(chain.last().unwrap()) {
+            if chain.contains(referrer) {
+                break;
+            }
+            chain.push(referrer.clone());
+        }
+        chain.reverse();
+        chain
+    }
+}
@@ -0,0 +1,71 @@
+use deno_core::v8;
+use serde::Serialize;
+
+use crate::{console, extract, host, inputs};
+
+/// What is built into this binary.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuntimeInfo {
+    pub version: &'static str,
+    pub deno_core: &'static str,
+    pub deno_runtime: &'static str,
+    pub deno_ast: &'static str,
+    pub v8: &'static str,
+    pub target: &'static str,
+    /// Enabled cargo features.
+    pub features: Vec<&'static str>,
+    pub defaults: Defaults,
+}
+
+/// Limits applied when the corresponding option is unset. `None` means
+/// unlimited.
+#[derive(Debug, Clone, Serialize)]
+pub struct Defaults {
+    pub timeout_ms: Option<u64>,
+    pub max_heap_bytes: Option<usize>,
+    pub max_input_bytes: usize,
+    pub max_output_bytes: Option<usize>,
+    pub max_depth: usize,
+    pub file_read_limit: usize,
+    pub console_event_limit: usize,
+}
+
+pub fn runtime_info() -> RuntimeInfo {
+    let features = [
+        ("net-loader", cfg!(feature = "net-loader")),
+        ("typescript", cfg!(feature = "typescript")),
+        ("gzip", cfg!(feature = "gzip")),
+        ("zip", cfg!(feature = "zip")),
+        ("s3", cfg!(feature = "s3")),
+        ("git", cfg!(feature = "git")),
+        ("serve", cfg!(feature = "serve")),
+        ("tracing", cfg!(feature = "tracing")),
+        ("zstd", cfg!(feature = "zstd")),
+        ("grpc", cfg!(feature = "grpc")),
+        ("otel", cfg!(feature = "otel")),
+        ("wasi", cfg!(feature = "wasi")),
+        ("watch", cfg!(feature = "watch")),
+    ];
+    RuntimeInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        deno_core: env!("RUNTIME_DENO_CORE_VERSION"),
+        deno_runtime: env!("RUNTIME_DENO_RUNTIME_VERSION"),
+        deno_ast: env!("RUNTIME_DENO_AST_VERSION"),
+        v8: v8::V8::get_version(),
+        target: env!("RUNTIME_TARGET"),
+        features: features
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name)
+            .collect(),
+        defaults: Defaults {
+            timeout_ms: None,
+            max_heap_bytes: None,
+            max_input_bytes: inputs::DEFAULT_MAX_INPUT_BYTES,
+            max_output_bytes: None,
+            max_depth: extract::DEFAULT_MAX_DEPTH,
+            file_read_limit: host::DEFAULT_FILE_READ_LIMIT,
+            console_event_limit: console::DEFAULT_EVENT_LIMIT,
+        },
+    }
+}
@@ -0,0 +1,192 @@
+use anyhow::{anyhow, Error};
+use bytes::Bytes;
+use deno_core::{serde_v8, v8};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::error::RuntimeError;
+use crate::options::RunOptions;
+use crate::schema;
+use crate::stream::LimitedWriter;
+
+/// Cap on the inputs when `RunOptions::max_input_bytes` is unset.
+pub(crate) const DEFAULT_MAX_INPUT_BYTES: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub enum InputPart {
+    Json(Value),
+    Text(String),
+    /// Delivered as a `Uint8Array` with a `contentType` property.
+    Bytes(Bytes, String),
+}
+
+/// Named inputs passed as the function's first argument. Besides plain JSON
+/// values, parts can carry text or binary payloads, which is what form
+/// uploads usually look like.
+#[derive(Debug, Clone, Default)]
+pub struct Inputs {
+    parts: Vec<(String, InputPart)>,
+}
+
+impl Inputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inputs from the fields of `value`, which must serialize to a map.
+    pub fn from_serializable(value: &impl serde::Serialize) -> Result<Self, Error> {
+        match serde_json::to_value(value)? {
+            Value::Object(fields) => Ok(fields
+                .into_iter()
+                .fold(Inputs::new(), |inputs, (name, value)| {
+                    inputs.json(name, value)
+                })),
+            _ => Err(anyhow!("inputs must serialize to a map")),
+        }
+    }
+
+    pub fn json(self, name: impl Into<String>, value: Value) -> Self {
+        self.part(name, InputPart::Json(value))
+    }
+
+    pub fn text(self, name: impl Into<String>, text: impl Into<String>) -> Self {
+        self.part(name, InputPart::Text(text.into()))
+    }
+
+    pub fn bytes(
+        self,
+        name: impl Into<String>,
+        bytes: impl Into<Bytes>,
+        content_type: impl Into<String>,
+    ) -> Self {
+        self.part(name, InputPart::Bytes(bytes.into(), content_type.into()))
+    }
+
+    /// Adds a part, replacing any earlier part with the same name.
+    pub fn part(mut self, name: impl Into<String>, part: InputPart) -> Self {
+        let name = name.into();
+        self.parts.retain(|(existing, _)| *existing != name);
+        self.parts.push((name, part));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&InputPart> {
+        self.parts
+            .iter()
+            .find(|(existing, _)| existing == name)
+            .map(|(_, part)| part)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &InputPart)> {
+        self.parts.iter().map(|(name, part)| (name.as_str(), part))
+    }
+
+    /// Size of the inputs: serialized length for JSON parts, byte length for
+    /// text and binary ones.
+    pub(crate) fn byte_size(&self) -> u64 {
+        self.parts
+            .iter()
+            .map(|(name, part)| {
+                name.len() as u64
+                    + match part {
+                        InputPart::Json(value) => {
+                            let mut counter = LimitedWriter::new(std::io::sink(), usize::MAX);
+                            let _ = serde_json::to_writer(&mut counter, value);
+                            counter.written()
+                        }
+                        InputPart::Text(text) => text.len() as u64,
+                        InputPart::Bytes(bytes, _) => bytes.len() as u64,
+                    }
+            })
+            .sum()
+    }
+
+    /// Fails with `RuntimeError::InputTooLarge` when the inputs exceed
+    /// `RunOptions::max_input_bytes`, and with `RuntimeError::InputValidation`
+    /// when they don't match `RunOptions::input_schema`.
+    pub(crate) fn check(&self, options: &RunOptions) -> Result<(), Error> {
+        let limit = options.max_input_bytes.unwrap_or(DEFAULT_MAX_INPUT_BYTES);
+        let observed = self.byte_size();
+        if observed > limit as u64 {
+            return Err(RuntimeError::InputTooLarge { limit, observed }.into());
+        }
+        if let Some(input_schema) = &options.input_schema {
+            let input_schema = schema::compile(input_schema, options.strict_schema)?;
+            log::debug!("validating function inputs");
+            let violations = schema::violations(&input_schema, &self.to_json());
+            if !violations.is_empty() {
+                return Err(RuntimeError::InputValidation { violations }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// The inputs as the input schema sees them. Binary parts become
+    /// `{ "contentType": ..., "byteLength": ... }` objects.
+    fn to_json(&self) -> Value {
+        self.parts
+            .iter()
+            .map(|(name, part)| {
+                let value = match part {
+                    InputPart::Json(value) => value.clone(),
+                    InputPart::Text(text) => Value::from(text.as_str()),
+                    InputPart::Bytes(bytes, content_type) => serde_json::json!({
+                        "contentType": content_type,
+                        "byteLength": bytes.len(),
+                    }),
+                };
+                (name.clone(), value)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    pub(crate) fn to_v8<'s>(
+        &self,
+        scope: &mut v8::HandleScope<'s>,
+    ) -> Result<v8::Local<'s, v8::Value>, Error> {
+        let object = v8::Object::new(scope);
+        for (name, part) in &self.parts {
+            let value: v8::Local<v8::Value> = match part {
+                InputPart::Json(value) => {
+                    serde_v8::to_v8(scope, value).map_err(|e| RuntimeError::Serialization {
+                        message: format!("input {:?} is invalid: {}", name, e),
+                    })?
+                }
+                InputPart::Text(text) => v8_string(scope, text)?.into(),
+                InputPart::Bytes(bytes, content_type) => {
+                    let len = bytes.len();
+                    let store =
+                        v8::ArrayBuffer::new_backing_store_from_vec(bytes.to_vec()).make_shared();
+                    let buffer = v8::ArrayBuffer::with_backing_store(scope, &store);
+                    let array = v8::Uint8Array::new(scope, buffer, 0, len)
+                        .ok_or_else(|| anyhow!("input {:?} is too large", name))?;
+                    let key = v8_string(scope, "contentType")?;
+                    let content_type = v8_string(scope, content_type)?;
+                    array.set(scope, key.into(), content_type.into());
+                    array.into()
+                }
+            };
+            let key = v8_string(scope, name)?;
+            object.set(scope, key.into(), value);
+        }
+        Ok(object.into())
+    }
+}
+
+impl From<HashMap<String, Value>> for Inputs {
+    fn from(values: HashMap<String, Value>) -> Self {
+        values
+            .into_iter()
+            .fold(Inputs::new(), |inputs, (name, value)| {
+                inputs.json(name, value)
+            })
+    }
+}
+
+fn v8_string<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    text: &str,
+) -> Result<v8::Local<'s, v8::String>, Error> {
+    v8::String::new(scope, text).ok_or_else(|| anyhow!("input string is too long"))
+}
@@ -0,0 +1,21 @@
+use anyhow::Error;
+use deno_runtime::inspector_server::InspectorServer;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+
+type Servers = Mutex<HashMap<SocketAddr, Arc<InspectorServer>>>;
+
+/// Inspector server listening on `address`. Workers inspected on the same
+/// address share one server, each showing up as a target of its own.
+pub(crate) fn server(address: SocketAddr) -> Result<Arc<InspectorServer>, Error> {
+    static SERVERS: OnceLock<Servers> = OnceLock::new();
+    let mut servers = SERVERS.get_or_init(Default::default).lock().unwrap();
+    if let Some(server) = servers.get(&address) {
+        return Ok(server.clone());
+    }
+    log::debug!("starting inspector on {}", address);
+    let server = Arc::new(InspectorServer::new(address, "experimental_runtime")?);
+    servers.insert(address, server.clone());
+    Ok(server)
+}
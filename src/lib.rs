@@ -0,0 +1,1116 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use deno_core::*;
+
+use deno_runtime::worker::MainWorker;
+
+use deno_core::anyhow::{bail, Context, Error};
+use deno_core::futures::FutureExt;
+use deno_core::ModuleLoader;
+use deno_core::ModuleSource;
+use deno_core::ModuleSpecifier;
+use deno_core::ModuleType;
+use deno_core::{resolve_import, ModuleSourceCode, RequestedModuleType, ResolutionKind};
+
+mod archive;
+mod batch;
+#[cfg(feature = "typescript")]
+mod bundle;
+#[cfg(feature = "net-loader")]
+mod cache;
+mod cancel;
+mod charset;
+mod check;
+mod compress;
+mod console;
+mod cron;
+mod data_url;
+mod dependency;
+mod determinism;
+mod dts;
+mod embedded;
+mod error;
+mod executor;
+mod extract;
+#[cfg(feature = "net-loader")]
+mod fetch;
+mod file_url;
+mod fuel;
+mod function;
+mod generator;
+#[cfg(feature = "git")]
+mod git;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod host;
+mod host_api;
+mod import_map;
+#[cfg(feature = "net-loader")]
+mod import_policy;
+mod imports;
+mod info;
+mod inputs;
+mod inspector;
+mod loader_stack;
+#[cfg(feature = "net-loader")]
+mod lockfile;
+mod manager;
+mod messages;
+#[cfg(feature = "net-loader")]
+mod npm;
+mod options;
+#[cfg(feature = "otel")]
+mod otel;
+mod permissions;
+mod platform;
+mod profile;
+mod queue;
+mod redact;
+mod repl;
+mod rpc;
+mod runtime;
+#[cfg(feature = "s3")]
+mod s3;
+mod sandbox;
+mod scheduler;
+mod schema;
+#[cfg(feature = "serve")]
+mod serve;
+#[cfg(feature = "typescript")]
+mod signature;
+mod snapshot;
+mod stats;
+mod stream;
+mod subprocess;
+mod trace;
+mod transpile;
+mod uncaught;
+mod warning;
+#[cfg(feature = "wasi")]
+mod wasi;
+mod wasm;
+#[cfg(feature = "watch")]
+mod watch;
+mod worker;
+
+pub use archive::open_archive;
+pub use batch::{run_batch, BatchItem, BatchOptions, BatchReport};
+#[cfg(feature = "typescript")]
+pub use bundle::{bundle, Bundle};
+#[cfg(feature = "net-loader")]
+pub use cache::{CachePolicy, ModuleCache};
+pub use cancel::CancellationHandle;
+pub use check::{check, CheckReport, Problem, ProblemKind};
+pub use compress::DiskUsage;
+pub use console::{CallSite, ConsoleEvent, ConsoleSink};
+pub use cron::Cron;
+pub use dependency::{dependency_report, DependencyReport, License, OriginSummary, RemoteModule};
+pub use determinism::{Determinism, VirtualClock};
+pub use dts::generate_dts;
+pub use embedded::{transpile_embedded, EmbeddedModuleLoader, EmbeddedModules};
+pub use error::{RuntimeError, SchemaViolation};
+pub use executor::ExecutorPool;
+pub use extract::{
+    BytesEncoding, CyclePolicy, DatePolicy, ExtendedOptions, MapPolicy, OutputFormat, ValueHook,
+};
+#[cfg(feature = "net-loader")]
+pub use fetch::HttpOptions;
+pub use function::FunctionRuntime;
+pub use generator::run_stream;
+#[cfg(feature = "grpc")]
+pub use grpc::{proto as grpc_proto, GrpcServer};
+pub use host_api::{
+    host_api_declarations, HostApi, HostApiBuilder, HostCall, HostMethod, SharedHostApi,
+};
+pub use import_map::ImportMap;
+#[cfg(feature = "net-loader")]
+pub use import_policy::ImportPolicy;
+pub use info::{runtime_info, Defaults, RuntimeInfo};
+pub use inputs::{InputPart, Inputs};
+pub use loader_stack::{
+    DiskCacheLayer, FilterLayer, LayerModule, LoaderLayer, LoaderStack, MemoryLayer,
+};
+pub use manager::{ManagerStats, RuntimeManager, TenantLimits, TenantStats};
+pub use messages::{InvocationHandle, MessagePort};
+#[cfg(feature = "net-loader")]
+pub use npm::NpmCache;
+pub use options::{DanglingWork, Entrypoint, RunOptions};
+#[cfg(feature = "otel")]
+pub use otel::{Telemetry, TraceContext};
+pub use permissions::RuntimePermissions;
+pub use platform::{init, shutdown, RuntimePlatform};
+pub use profile::{run_profiled, Profile, ProfileOptions};
+pub use queue::{MemoryQueue, Message, QueueRunner, QueueSource};
+pub use redact::RedactOptions;
+pub use repl::run_repl;
+pub use rpc::serve_rpc;
+pub use runtime::{LoaderFactory, Runtime, RuntimeBuilder};
+#[cfg(feature = "s3")]
+pub use s3::{S3Error, S3Object, S3Resolver};
+pub use sandbox::FsSandbox;
+pub use scheduler::{JobHandle, Overlap, ScheduledJob, Scheduler, Trigger};
+#[cfg(feature = "serve")]
+pub use serve::InvokeServer;
+#[cfg(feature = "typescript")]
+pub use signature::{inspect_signature, ParamInfo, SignatureInfo};
+pub use snapshot::Snapshot;
+pub use stats::{
+    CacheStats, ExecutionStats, Invocation, LoaderCacheStats, MetricsSink, StatsCollector,
+};
+pub use subprocess::{is_subprocess, serve_subprocess, Subprocess};
+pub use trace::{RecordedResponse, Trace, TraceEntry, TraceMode, TraceRecorder};
+pub use transpile::{SourceKind, TranspileCache};
+pub use uncaught::{UncaughtEvent, UncaughtHook};
+pub use warning::{Warning, WarningCode, WarningHook};
+#[cfg(feature = "wasi")]
+pub use wasi::{run_wasi, WasiOptions, WasiOutput};
+#[cfg(feature = "watch")]
+pub use watch::{watch, Invalidation, WatchCache, WatchEvent};
+
+pub struct NetworkModuleLoader {
+    #[cfg(feature = "net-loader")]
+    client: fetch::HttpClient,
+    imports: std::rc::Rc<imports::ImportGraph>,
+    source_maps: std::rc::Rc<transpile::SourceMaps>,
+    follow_symlinks: bool,
+    #[cfg(feature = "net-loader")]
+    cache: Option<ModuleCache>,
+    #[cfg(feature = "net-loader")]
+    lockfile: Option<std::rc::Rc<lockfile::Lockfile>>,
+    #[cfg(feature = "net-loader")]
+    npm: Option<NpmCache>,
+    #[cfg(feature = "s3")]
+    s3: Option<S3Resolver>,
+    #[cfg(feature = "git")]
+    git: git::GitRevisions,
+    stats: Option<StatsCollector>,
+    import_map: Option<ImportMap>,
+    transpile_cache: Option<TranspileCache>,
+    virtual_modules: HashMap<ModuleSpecifier, String>,
+    permissions: Option<deno_permissions::PermissionsContainer>,
+    import_root: Option<PathBuf>,
+}
+
+impl Default for NetworkModuleLoader {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "net-loader")]
+            client: fetch::client(),
+            imports: Default::default(),
+            source_maps: Default::default(),
+            follow_symlinks: true,
+            #[cfg(feature = "net-loader")]
+            cache: None,
+            #[cfg(feature = "net-loader")]
+            lockfile: None,
+            #[cfg(feature = "net-loader")]
+            npm: NpmCache::new().ok(),
+            #[cfg(feature = "s3")]
+            s3: None,
+            #[cfg(feature = "git")]
+            git: Default::default(),
+            stats: None,
+            import_map: None,
+            transpile_cache: None,
+            virtual_modules: HashMap::new(),
+            permissions: None,
+            import_root: None,
+        }
+    }
+}
+
+impl NetworkModuleLoader {
+    /// Without following, file modules reached through symbolic links are
+    /// refused instead of loading their target.
+    pub fn with_follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Keeps http(s) modules in `cache` between runs.
+    #[cfg(feature = "net-loader")]
+    pub fn with_cache(mut self, cache: ModuleCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Checks remote modules against the hashes in the lockfile at `path`.
+    /// With `write`, hashes are recorded into it instead, creating it if
+    /// needed.
+    #[cfg(feature = "net-loader")]
+    pub fn with_lockfile(mut self, path: &std::path::Path, write: bool) -> Result<Self, Error> {
+        self.lockfile = Some(std::rc::Rc::new(lockfile::Lockfile::open(path, write)?));
+        Ok(self)
+    }
+
+    /// Fetches remote modules with the timeouts, retries, size limit, proxy
+    /// and certificates of `options`.
+    #[cfg(feature = "net-loader")]
+    pub fn with_http(mut self, options: &HttpOptions) -> Result<Self, Error> {
+        let policy = self.client.policy.take();
+        self.client = fetch::HttpClient::new(options)?;
+        self.client.policy = policy;
+        Ok(self)
+    }
+
+    /// Refuses remote modules from hosts `policy` doesn't allow.
+    #[cfg(feature = "net-loader")]
+    pub fn with_import_policy(mut self, policy: ImportPolicy) -> Self {
+        self.client.policy = Some(policy);
+        self
+    }
+
+    /// Unpacks `npm:` imports into `cache` instead of the default npm cache.
+    #[cfg(feature = "net-loader")]
+    pub fn with_npm_cache(mut self, cache: NpmCache) -> Self {
+        self.npm = Some(cache);
+        self
+    }
+
+    /// Resolves imports through `import_map` first.
+    pub fn with_import_map(mut self, import_map: ImportMap) -> Self {
+        self.import_map = Some(import_map);
+        self
+    }
+
+    /// Serves `source` for `specifier` from memory, whatever its scheme. The
+    /// extension of the specifier says whether it needs compiling.
+    pub fn add_virtual_module(&mut self, specifier: ModuleSpecifier, source: impl Into<String>) {
+        self.virtual_modules.insert(specifier, source.into());
+    }
+
+    /// Reuses compiled TypeScript and JSX from `cache`.
+    pub fn with_transpile_cache(mut self, cache: TranspileCache) -> Self {
+        self.transpile_cache = Some(cache);
+        self
+    }
+
+    /// Counts remote modules, transpilation and cache lookups into `stats`.
+    pub fn with_stats(mut self, stats: StatsCollector) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Stats of the caches the loader was given.
+    pub fn cache_stats(&self) -> LoaderCacheStats {
+        LoaderCacheStats {
+            #[cfg(feature = "net-loader")]
+            module_cache: self.cache.as_ref().map(ModuleCache::stats),
+            #[cfg(not(feature = "net-loader"))]
+            module_cache: None,
+            transpile_cache: self.transpile_cache.as_ref().map(TranspileCache::stats),
+        }
+    }
+
+    /// Serves `s3://bucket/key` specifiers through `resolver`.
+    #[cfg(feature = "s3")]
+    pub fn with_s3(mut self, resolver: S3Resolver) -> Self {
+        self.s3 = Some(resolver);
+        self
+    }
+
+    /// Lets `git+https:` imports name a branch or tag, resolved to a commit
+    /// once per loader, instead of requiring a full commit hash.
+    #[cfg(feature = "git")]
+    pub fn with_mutable_git_refs(mut self) -> Self {
+        self.git.allow_mutable = true;
+        self
+    }
+
+    /// Checks files imported with `import()` against the read permissions,
+    /// as `Deno.readFile` would. Static imports are only checked when they
+    /// reach a file through a symbolic link.
+    pub fn with_permissions(mut self, permissions: deno_permissions::PermissionsContainer) -> Self {
+        self.permissions = Some(permissions);
+        self
+    }
+
+    /// Refuses files outside `root` imported with `import()`, or statically
+    /// through a symbolic link.
+    pub fn with_import_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.import_root = Some(root.into());
+        self
+    }
+
+    /// Dynamic imports have to stay within the import root and the read
+    /// permissions. Static ones only have to when they go through a link
+    /// that does, so a link can't lead out of them.
+    fn check_file_import(
+        &self,
+        specifier: &ModuleSpecifier,
+        path: &std::path::Path,
+        dynamic: bool,
+    ) -> Result<(), Error> {
+        if dynamic {
+            return self.check_allowed(path, "import()");
+        }
+        let Some(link) = self.imports.link(specifier) else {
+            return Ok(());
+        };
+        if self.check_allowed(&link, "import").is_ok() {
+            self.check_allowed(path, "import")
+                .with_context(|| format!("{} links to {}", link.display(), path.display()))?;
+        }
+        Ok(())
+    }
+
+    fn check_allowed(&self, path: &std::path::Path, api_name: &str) -> Result<(), Error> {
+        if let Some(root) = &self.import_root {
+            if !path.starts_with(root) {
+                bail!(
+                    "{} can't be imported, imports are confined to {}",
+                    path.display(),
+                    root.display()
+                );
+            }
+        }
+        if let Some(permissions) = &self.permissions {
+            permissions.check_read_with_api_name(path, Some(api_name))?;
+        }
+        Ok(())
+    }
+
+    /// Real location of a file module, noting the link it was reached
+    /// through.
+    fn canonical(&self, specifier: ModuleSpecifier) -> Result<ModuleSpecifier, Error> {
+        let canonical = file_url::canonical_specifier(specifier.clone(), !self.follow_symlinks)?;
+        if canonical != specifier {
+            if let Ok(link) = file_url::specifier_to_path(&specifier) {
+                self.imports.record_link(&canonical, link);
+            }
+        }
+        Ok(canonical)
+    }
+}
+
+#[cfg(feature = "net-loader")]
+fn record_remote(stats: &Option<StatsCollector>, body: &[u8]) {
+    if let Some(stats) = stats {
+        stats.record(|stats| {
+            stats.remote_modules += 1;
+            stats.remote_bytes += body.len() as u64;
+        });
+    }
+}
+
+impl NetworkModuleLoader {
+    fn resolve_specifier(&self, specifier: &str, referrer: &str) -> Result<ModuleSpecifier, Error> {
+        if referrer.starts_with("data:")
+            && ["./", "../", "/"]
+                .iter()
+                .any(|prefix| specifier.starts_with(prefix))
+        {
+            bail!(
+                "{} can't be imported from a data: URL module, there is no base to resolve it against",
+                specifier
+            );
+        }
+        if referrer.starts_with("file:") {
+            if let Some(specifier) = file_url::windows_path_specifier(specifier) {
+                return self.canonical(specifier);
+            }
+        }
+        if let Some(import_map) = &self.import_map {
+            if let Some(mapped) = import_map.resolve(specifier, referrer)? {
+                return self.canonical(mapped);
+            }
+        }
+        #[cfg(feature = "git")]
+        if let Some(resolved) = git::resolve(specifier, referrer)? {
+            return Ok(resolved);
+        }
+        #[cfg(feature = "net-loader")]
+        if let Some(npm) = &self.npm {
+            if let Some(dependency) = npm.resolve_bare(specifier, referrer)? {
+                return Ok(dependency);
+            }
+        }
+        let specifier = file_url::normalize_specifier(resolve_import(specifier, referrer)?);
+        self.canonical(specifier)
+    }
+}
+
+impl ModuleLoader for NetworkModuleLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        _kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+        self.resolve_specifier(specifier, referrer)
+            .map_err(|e| error::resolution(specifier, referrer, e))
+    }
+
+    fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        maybe_referrer: Option<&ModuleSpecifier>,
+        is_dyn_import: bool,
+        requested_module_type: RequestedModuleType,
+    ) -> ModuleLoadResponse {
+        let module_specifier = module_specifier.clone();
+        if let Some(referrer) = maybe_referrer {
+            self.imports.record(&module_specifier, referrer);
+        }
+        let checked = match module_specifier.scheme() {
+            "file" if !self.virtual_modules.contains_key(&module_specifier) => {
+                file_url::specifier_to_path(&module_specifier).and_then(|path| {
+                    self.check_file_import(&module_specifier, &path, is_dyn_import)
+                })
+            }
+            _ => Ok(()),
+        };
+        if let Err(cause) = checked {
+            let chain = self.imports.chain(&module_specifier);
+            return ModuleLoadResponse::Sync(Err(error::load(&module_specifier, chain, cause)));
+        }
+        let imports = self.imports.clone();
+        let source_maps = self.source_maps.clone();
+        let stats = self.stats.clone();
+        let virtual_source = self.virtual_modules.get(&module_specifier).cloned();
+        let transpile_cache = self
+            .transpile_cache
+            .as_ref()
+            .map(|cache| cache.for_run(stats.as_ref()));
+        #[cfg(feature = "net-loader")]
+        let client = self.client.clone();
+        #[cfg(feature = "net-loader")]
+        let cache = self
+            .cache
+            .as_ref()
+            .map(|cache| cache.for_run(stats.as_ref()));
+        #[cfg(feature = "net-loader")]
+        let lockfile = self.lockfile.clone();
+        #[cfg(feature = "net-loader")]
+        let npm = self.npm.clone();
+        #[cfg(feature = "s3")]
+        let s3_resolver = self.s3.clone();
+        #[cfg(feature = "git")]
+        let git_revisions = self.git.clone();
+
+        let load = {
+            let module_specifier = module_specifier.clone();
+            let imports = imports.clone();
+            async move {
+                if transpile::is_declaration(&module_specifier) {
+                    log::debug!("skipping declaration file {}", module_specifier);
+                    return Ok(ModuleSource::new(
+                        ModuleType::JavaScript,
+                        ModuleSourceCode::Bytes(Vec::new().into_boxed_slice().into()),
+                        &module_specifier,
+                        None,
+                    ));
+                }
+
+                let (charset, kind, redirect_module_url, code): (
+                    Option<String>,
+                    Option<transpile::SourceKind>,
+                    Option<ModuleSpecifier>,
+                    _,
+                ) = if let Some(code) = virtual_source {
+                    (None, None, None, code.into_bytes())
+                } else {
+                    match module_specifier.scheme() {
+                        #[cfg(feature = "net-loader")]
+                        "http" | "https" => {
+                            log::debug!("loading url import: {}", module_specifier);
+                            client.check_policy(&module_specifier)?;
+                            let fetched = match &cache {
+                                Some(cache) => cache.fetch(&client, &module_specifier).await?,
+                                None => fetch::fetch(&client, &module_specifier).await?,
+                            };
+                            for hop in fetched.urls.windows(2) {
+                                imports.record_redirect(&hop[0], &hop[1]);
+                            }
+                            if let Some(lockfile) = &lockfile {
+                                lockfile.check(fetched.url(), &fetched.body)?;
+                            }
+                            imports.record_remote(&module_specifier, fetched.url(), &fetched.body);
+                            record_remote(&stats, &fetched.body);
+                            let redirect =
+                                (fetched.url() != &module_specifier).then(|| fetched.url().clone());
+                            (
+                                fetched.charset(),
+                                fetched.source_kind()?,
+                                redirect,
+                                fetched.body,
+                            )
+                        }
+                        #[cfg(feature = "s3")]
+                        "s3" => {
+                            let resolver = s3_resolver
+                                .as_ref()
+                                .ok_or_else(|| anyhow::anyhow!("no s3 resolver was configured"))?;
+                            let fetched = s3::fetch(&client, resolver, &module_specifier).await?;
+                            if let Some(lockfile) = &lockfile {
+                                lockfile.check(&module_specifier, &fetched.body)?;
+                            }
+                            imports.record_remote(
+                                &module_specifier,
+                                &module_specifier,
+                                &fetched.body,
+                            );
+                            record_remote(&stats, &fetched.body);
+                            (
+                                fetched.charset(),
+                                fetched.source_kind()?,
+                                None,
+                                fetched.body,
+                            )
+                        }
+                        #[cfg(feature = "git")]
+                        "git+https" | "git+http" => {
+                            let module = git::load(
+                                &client,
+                                cache.as_ref(),
+                                &git_revisions,
+                                &module_specifier,
+                            )
+                            .await?;
+                            let found = module.pinned.as_ref().unwrap_or(&module_specifier);
+                            imports.record_remote(&module_specifier, found, &module.code);
+                            record_remote(&stats, &module.code);
+                            let path = git::GitSpecifier::parse(&module_specifier)?.path;
+                            let kind = transpile::SourceKind::from_path(&path);
+                            (None, Some(kind), module.pinned, module.code)
+                        }
+                        #[cfg(feature = "net-loader")]
+                        "npm" => {
+                            let npm = npm.as_ref().ok_or_else(|| {
+                                anyhow::anyhow!(
+                                    "no npm cache, neither XDG_CACHE_HOME nor HOME is set"
+                                )
+                            })?;
+                            let entry = npm.resolve(&client, &module_specifier).await?;
+                            log::debug!("loading npm import {} from {}", module_specifier, entry);
+                            let path = file_url::specifier_to_path(&entry)?;
+                            let code = file_url::read_module_file(&path)
+                                .await
+                                .with_context(|| format!("could not read {}", path.display()))?;
+                            let code = npm.to_esm(&path, code)?;
+                            imports.record_remote(&module_specifier, &entry, &code);
+                            let kind = transpile::SourceKind::from_specifier(&entry);
+                            (None, Some(kind), Some(entry), code)
+                        }
+                        #[cfg(not(feature = "net-loader"))]
+                        "http" | "https" | "npm" => {
+                            bail!(
+                                "{} can't be loaded, built without network loader support",
+                                module_specifier
+                            )
+                        }
+                        "data" => {
+                            let data = data_url::parse(&module_specifier)?;
+                            (data.charset, Some(data.kind), None, data.body)
+                        }
+                        "file" => {
+                            log::debug!("resolving file module");
+                            let path = file_url::specifier_to_path(&module_specifier)?;
+                            let code = file_url::read_module_file(&path)
+                                .await
+                                .with_context(|| format!("could not read {}", path.display()))?;
+                            #[cfg(feature = "net-loader")]
+                            let code = match &npm {
+                                Some(npm) => npm.to_esm(&path, code)?,
+                                None => code,
+                            };
+                            (None, None, None, code)
+                        }
+                        schema => bail!("Invalid schema {}", schema),
+                    }
+                };
+
+                // A served `Content-Type` wins over the extension.
+                let kind = kind
+                    .unwrap_or_else(|| transpile::SourceKind::from_specifier(&module_specifier));
+                let code = if kind == transpile::SourceKind::Wasm {
+                    wasm::wrapper(&code)
+                        .with_context(|| format!("could not load {}", module_specifier))?
+                        .into_bytes()
+                } else {
+                    let code = charset::decode(&code, charset.as_deref(), &module_specifier)?;
+                    let started = std::time::Instant::now();
+                    let transpiled = transpile::transpile_module(
+                        &module_specifier,
+                        kind,
+                        code,
+                        Some(&source_maps),
+                        transpile_cache.as_ref(),
+                    )?;
+                    if let Some(stats) = stats.as_ref().filter(|_| kind.needs_transpile()) {
+                        stats.record(|stats| {
+                            stats.transpiled = true;
+                            stats.transpile += started.elapsed();
+                        });
+                    }
+                    transpiled
+                };
+
+                let module_type =
+                    transpile::module_type(&module_specifier, kind, &requested_module_type)?;
+
+                if let Some(redirect_module_url) = redirect_module_url {
+                    imports.record_found(&module_specifier, &redirect_module_url);
+                    Ok(ModuleSource::new_with_redirect(
+                        module_type,
+                        ModuleSourceCode::Bytes(code.into_boxed_slice().into()),
+                        &module_specifier,
+                        &redirect_module_url,
+                        None,
+                    ))
+                } else {
+                    Ok(ModuleSource::new(
+                        module_type,
+                        ModuleSourceCode::Bytes(code.into_boxed_slice().into()),
+                        &module_specifier,
+                        None,
+                    ))
+                }
+            }
+        };
+
+        ModuleLoadResponse::Async(
+            async move {
+                load.await.map_err(|cause| {
+                    error::load(&module_specifier, imports.chain(&module_specifier), cause)
+                })
+            }
+            .boxed_local(),
+        )
+    }
+
+    fn get_source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+        self.source_maps.source_map(file_name)
+    }
+
+    fn get_source_mapped_source_line(&self, file_name: &str, line_number: usize) -> Option<String> {
+        self.source_maps.source_line(file_name, line_number)
+    }
+}
+
+pub fn run_insecure(function: PathBuf, inputs: impl Into<Inputs>) -> Result<Value, anyhow::Error> {
+    run_with_options(function, inputs, RunOptions::default())
+}
+
+/// Runs the function with access limited to `permissions`. Access outside
+/// of them fails with `RuntimeError::PermissionDenied` unless the script
+/// catches it.
+pub fn run_with_permissions(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    permissions: RuntimePermissions,
+) -> Result<Value, anyhow::Error> {
+    let options = RunOptions {
+        permissions: Some(permissions),
+        ..Default::default()
+    };
+    run_with_options(function, inputs, options)
+}
+
+/// Calls the `export` function of the module, `default` included, with
+/// positional `args`.
+pub fn run_function(
+    function: PathBuf,
+    export: &str,
+    args: Vec<Value>,
+) -> Result<Value, anyhow::Error> {
+    let options = RunOptions {
+        entrypoint: Entrypoint::Function {
+            name: export.to_string(),
+            args,
+        },
+        ..Default::default()
+    };
+    run_with_options(function, Inputs::new(), options)
+}
+
+/// Runs `code` as the function module, without writing it to a file.
+/// Relative imports in it resolve among `options.embedded`, when set.
+pub fn run_source(
+    code: &str,
+    kind: SourceKind,
+    inputs: impl Into<Inputs>,
+    mut options: RunOptions,
+) -> Result<Value, anyhow::Error> {
+    let path = format!("source.{}", kind.extension());
+    let mut modules = options
+        .embedded
+        .take()
+        .unwrap_or_else(|| EmbeddedModules::new().with_network_fallback());
+    modules.insert(&path, code.to_string());
+    options.embedded = Some(modules);
+    run_with_options(PathBuf::from(path), inputs, options)
+}
+
+/// Result of [`run_with_values`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputValue {
+    Json(Value),
+    /// A returned `Uint8Array`, other typed array, `DataView`,
+    /// `ArrayBuffer` or `SharedArrayBuffer`.
+    Bytes(bytes::Bytes),
+}
+
+/// Runs the function like [`run_with_options`], except that a buffer
+/// returned at the top level comes back as bytes instead of going through
+/// the output format. Buffers nested in the result are converted as
+/// usual. Binary inputs go in as [`InputPart::Bytes`].
+pub fn run_with_values(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    options: RunOptions,
+) -> Result<OutputValue, anyhow::Error> {
+    let output_schema = options
+        .output_schema
+        .as_ref()
+        .map(|s| schema::compile(s, options.strict_schema))
+        .transpose()?;
+    let inputs = inputs.into();
+    let redactor = redact::Redactor::new(&options, &inputs);
+    if let Some(subprocess) = &options.subprocess {
+        return subprocess
+            .run_with_values(&function, inputs, &options)
+            .map_err(|e| redactor.redact_error(e));
+    }
+    inputs.check(&options)?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let result = runtime.block_on(async {
+        let (mut module, f) = worker::execute(&function, inputs, &options).await?;
+        let bytes = {
+            let scope = &mut module.worker.js_runtime.handle_scope();
+            let value = v8::Local::new(scope, &f);
+            extract::buffer_bytes(value)
+        };
+        match bytes {
+            Some(bytes) => {
+                if let Some(limit) = options.max_output_bytes.filter(|&l| bytes.len() > l) {
+                    return Err(RuntimeError::OutputTooLarge { limit }.into());
+                }
+                Ok(OutputValue::Bytes(bytes.into()))
+            }
+            None => output(&mut module.worker, f, &options, output_schema.as_deref())
+                .map(OutputValue::Json),
+        }
+    });
+    result.map_err(|e| redactor.redact_error(e))
+}
+
+/// Runs the function like [`run_with_options`] and deserializes its
+/// result into `T` straight from V8, without building a JSON value first.
+/// Inputs come from the fields of any `Serialize` value that serializes to a
+/// map. With `output_schema` or `max_output_bytes` set, the result goes
+/// through JSON to apply them, as it does to come back from a
+/// `subprocess`.
+pub fn run_typed<T: serde::de::DeserializeOwned>(
+    function: PathBuf,
+    inputs: &impl serde::Serialize,
+    options: RunOptions,
+) -> Result<T, anyhow::Error> {
+    let inputs = Inputs::from_serializable(inputs)?;
+    if options.output_schema.is_some()
+        || options.max_output_bytes.is_some()
+        || options.subprocess.is_some()
+    {
+        let value = run_with_options(function, inputs, options)?;
+        return serde_json::from_value(value).map_err(|e| {
+            RuntimeError::Deserialization {
+                message: e.to_string(),
+            }
+            .into()
+        });
+    }
+    inputs.check(&options)?;
+    let redactor = redact::Redactor::new(&options, &inputs);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let function = function.as_path();
+    let result = runtime.block_on(stats::metered(function, &options, |options| async move {
+        let (mut module, f) = worker::execute(function, inputs, &options).await?;
+        let scope = &mut module.worker.js_runtime.handle_scope();
+        let value = v8::Local::new(scope, f);
+        serde_v8::from_v8(scope, value).map_err(|e| {
+            RuntimeError::Deserialization {
+                message: e.to_string(),
+            }
+            .into()
+        })
+    }));
+    result.map_err(|e| redactor.redact_error(e))
+}
+
+/// Result of [`run_captured`].
+#[derive(Debug, Clone)]
+pub struct RunOutput {
+    pub value: Value,
+    /// Console calls made while the module was evaluated and the function
+    /// ran, in order.
+    pub console: Vec<ConsoleEvent>,
+}
+
+/// Runs the function and returns its console output with the result
+/// instead of printing it. Replaces `options.console`.
+pub fn run_captured(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    mut options: RunOptions,
+) -> Result<RunOutput, anyhow::Error> {
+    let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let sink = events.clone();
+    options.console = Some(ConsoleSink::callback(move |event| {
+        sink.lock().unwrap().push(event)
+    }));
+    let value = run_with_options(function, inputs, options)?;
+    let console = std::mem::take(&mut *events.lock().unwrap());
+    Ok(RunOutput { value, console })
+}
+
+/// Runs like [`run_with_options`] and also returns what the run took and
+/// consumed. The stats are filled in as far as the run got when it fails.
+pub fn run_with_stats(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    mut options: RunOptions,
+) -> (Result<Value, anyhow::Error>, ExecutionStats) {
+    let stats = StatsCollector::new();
+    options.stats = Some(stats.clone());
+    let result = run_with_options(function, inputs, options);
+    (result, stats.stats())
+}
+
+pub fn run_with_options(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    options: RunOptions,
+) -> Result<Value, anyhow::Error> {
+    if let Some(subprocess) = &options.subprocess {
+        let inputs = inputs.into();
+        let redactor = redact::Redactor::new(&options, &inputs);
+        return subprocess
+            .run(&function, inputs, &options)
+            .map_err(|e| redactor.redact_error(e));
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(run_async(&function, inputs.into(), &options))
+}
+
+/// Runs the function on a thread of its own, so it can be awaited from
+/// inside an existing tokio runtime. The future is `Send`; dropping it
+/// before it settles cancels the run through `options.cancellation`.
+pub async fn run(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    mut options: RunOptions,
+) -> Result<Value, anyhow::Error> {
+    struct CancelOnDrop(Option<CancellationHandle>);
+
+    impl Drop for CancelOnDrop {
+        fn drop(&mut self) {
+            if let Some(cancellation) = self.0.take() {
+                cancellation.cancel();
+            }
+        }
+    }
+
+    let inputs = inputs.into();
+    let mut guard = CancelOnDrop(Some(
+        options
+            .cancellation
+            .get_or_insert_with(CancellationHandle::new)
+            .clone(),
+    ));
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+    std::thread::Builder::new()
+        .name("run".to_string())
+        .spawn(move || {
+            let _ = sender.send(run_with_options(function, inputs, options));
+        })?;
+    let result = receiver
+        .await
+        .map_err(|_| anyhow::anyhow!("function thread exited without a result"))?;
+    guard.0 = None;
+    result
+}
+
+async fn run_async(
+    function: &std::path::Path,
+    inputs: Inputs,
+    options: &RunOptions,
+) -> Result<Value, anyhow::Error> {
+    let output_schema = options
+        .output_schema
+        .as_ref()
+        .map(|s| schema::compile(s, options.strict_schema))
+        .transpose()?;
+    inputs.check(options)?;
+    let redactor = redact::Redactor::new(options, &inputs);
+
+    let result = stats::metered(function, options, |options| async move {
+        let (mut module, f) = worker::execute(function, inputs, &options).await?;
+        output(&mut module.worker, f, &options, output_schema.as_deref())
+    })
+    .await;
+    result.map_err(|e| redactor.redact_error(e))
+}
+
+/// Runs the function once per item of `inputs`, in order, on a single
+/// worker: bootstrapping and module evaluation happen once instead of per
+/// item. A failing item only fails its own result. When the failure may have
+/// left the worker unusable, a fresh one is loaded for the remaining items.
+///
+/// The outer error is returned when the module itself can't be loaded.
+/// With `RunOptions::subprocess` set, each item is an invocation of its own
+/// in a child, and fails on its own when the module can't be loaded.
+pub fn run_many<I: Into<Inputs>>(
+    function: PathBuf,
+    inputs: impl IntoIterator<Item = I>,
+    options: RunOptions,
+) -> Result<Vec<Result<Value, anyhow::Error>>, anyhow::Error> {
+    let output_schema = options
+        .output_schema
+        .as_ref()
+        .map(|s| schema::compile(s, options.strict_schema))
+        .transpose()?;
+    if options.subprocess.is_some() {
+        return Ok(inputs
+            .into_iter()
+            .map(|inputs| run_with_options(function.clone(), inputs, options.clone()))
+            .collect());
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let mut module = worker::load(&function, &options).await?;
+        let mut results = vec![];
+        for inputs in inputs {
+            let inputs: Inputs = inputs.into();
+            if let Err(e) = inputs.check(&options) {
+                results.push(Err(e));
+                continue;
+            }
+            let redactor = redact::Redactor::new(&options, &inputs);
+            if let Err(e) = worker::take_uncaught(&mut module).await {
+                let event = uncaught::UncaughtEvent::new(&e, module.id, false, true);
+                uncaught::report(options.on_uncaught.as_ref(), event);
+                worker::reload(&mut module, &options).await?;
+            }
+            let result = match worker::call(&mut module, inputs, &options).await {
+                Ok(f) => output(&mut module.worker, f, &options, output_schema.as_deref()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                if worker::corrupts_worker(e) {
+                    let event = uncaught::UncaughtEvent::new(e, module.id, true, true);
+                    uncaught::report(options.on_uncaught.as_ref(), event);
+                    worker::reload(&mut module, &options).await?;
+                }
+            }
+            results.push(result.map_err(|e| redactor.redact_error(e)));
+        }
+        Ok(results)
+    })
+}
+
+/// Converts the resolved result and applies the output limits and schema.
+fn output(
+    main_worker: &mut MainWorker,
+    f: v8::Global<v8::Value>,
+    options: &RunOptions,
+    output_schema: Option<&jsonschema::JSONSchema>,
+) -> Result<Value, anyhow::Error> {
+    let scope = &mut main_worker.js_runtime.handle_scope();
+    let local_f = v8::Local::<v8::Value>::new(scope, f);
+
+    let deserialized_value = extract::to_json(scope, local_f, options)?;
+
+    if options.max_output_bytes.is_some() || options.stats.is_some() {
+        let limit = options.max_output_bytes.unwrap_or(usize::MAX);
+        let mut counter = stream::LimitedWriter::new(std::io::sink(), limit);
+        serde_json::to_writer(&mut counter, &deserialized_value)
+            .map_err(|_| RuntimeError::OutputTooLarge { limit })?;
+        if let Some(stats) = &options.stats {
+            stats.record(|stats| stats.result_bytes = counter.written() as usize);
+        }
+    }
+
+    if let Some(output_schema) = output_schema {
+        log::debug!("validating function output");
+        let violations = schema::violations(output_schema, &deserialized_value);
+        if !violations.is_empty() {
+            return Err(RuntimeError::OutputValidation {
+                violations,
+                value: options.keep_invalid_output.then_some(deserialized_value),
+            }
+            .into());
+        }
+    }
+
+    Ok(deserialized_value)
+}
+
+/// Runs the function and writes its result as JSON into `writer` without
+/// building an intermediate `serde_json::Value`.
+///
+/// Only strict JSON output is supported and `output_schema` is rejected,
+/// since validating would require the whole value in memory.
+pub fn run_to_writer(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    options: RunOptions,
+    writer: impl std::io::Write,
+) -> Result<u64, anyhow::Error> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(run_to_writer_async(function, inputs, options, writer))
+}
+
+/// Async flavour of [`run_to_writer`]. The future is `!Send`, it must be
+/// driven by a current-thread runtime or a `LocalSet`. Dropping it stops the
+/// serialization at the next chunk boundary.
+pub async fn run_to_writer_async(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    options: RunOptions,
+    writer: impl std::io::Write,
+) -> Result<u64, anyhow::Error> {
+    if options.output_schema.is_some() {
+        bail!("output schema validation is not supported when streaming results");
+    }
+    if let OutputFormat::Extended(_) = options.output_format {
+        bail!("the extended output format is not supported when streaming results");
+    }
+
+    let inputs = inputs.into();
+    let limit = options.max_output_bytes.unwrap_or(usize::MAX);
+    if let Some(subprocess) = &options.subprocess {
+        // The result arrives whole from the child, it is only written here.
+        let redactor = redact::Redactor::new(&options, &inputs);
+        let value = subprocess
+            .run(&function, inputs, &options)
+            .map_err(|e| redactor.redact_error(e))?;
+        let mut writer = stream::LimitedWriter::new(writer, limit);
+        stream::write_converted(&mut writer, &value)?;
+        return Ok(writer.written());
+    }
+    inputs.check(&options)?;
+    let redactor = redact::Redactor::new(&options, &inputs);
+    let (mut module, f) = worker::execute(&function, inputs, &options)
+        .await
+        .map_err(|e| redactor.redact_error(e))?;
+    let mut writer = stream::LimitedWriter::new(writer, limit);
+    stream::write_json(&mut module.worker.js_runtime, f, &mut writer, &options)
+        .await
+        .map_err(|e| redactor.redact_error(e))?;
+    Ok(writer.written())
+}
@@ -0,0 +1,501 @@
+use anyhow::{anyhow, bail, Context, Error};
+use async_trait::async_trait;
+use deno_core::{
+    ModuleLoadResponse, ModuleLoader, ModuleSource, ModuleSourceCode, ModuleSpecifier, ModuleType,
+    RequestedModuleType, ResolutionKind,
+};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::compress::{self, Encoding};
+use crate::error;
+use crate::imports::ImportGraph;
+use crate::stats::{CacheCounters, CacheEvent, CacheStats};
+use crate::transpile::{self, SourceKind, SourceMaps, TranspileCache};
+use crate::wasm;
+
+/// Source of a module as a [`LoaderLayer`] supplies it.
+#[derive(Debug, Clone)]
+pub struct LayerModule {
+    pub code: Vec<u8>,
+    /// What the code is written in, guessed from the extension when unset.
+    pub kind: Option<SourceKind>,
+    /// URL the module was served from, when it was redirected. Its imports
+    /// resolve against it.
+    pub found: Option<ModuleSpecifier>,
+}
+
+impl LayerModule {
+    pub fn new(code: impl Into<Vec<u8>>) -> Self {
+        Self {
+            code: code.into(),
+            kind: None,
+            found: None,
+        }
+    }
+
+    pub fn with_kind(mut self, kind: SourceKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+}
+
+/// One layer of a [`LoaderStack`], such as a store modules are kept in, a
+/// cache, or a filter.
+#[async_trait(?Send)]
+pub trait LoaderLayer: Send + Sync + 'static {
+    /// The module at `specifier`, or `None` to leave it to the layers
+    /// below. An error fails the import, which is how filters refuse
+    /// modules.
+    async fn load(&self, specifier: &ModuleSpecifier) -> Result<Option<LayerModule>, Error>;
+
+    /// Sees a module that a layer below supplied, for layers that cache.
+    /// Modules from the module loader at the bottom arrive compiled.
+    async fn loaded(
+        &self,
+        _specifier: &ModuleSpecifier,
+        _module: &LayerModule,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Sees every import as it is resolved, `referrer` importing
+    /// `specifier`, for layers that track the module graph.
+    fn resolved(&self, _specifier: &ModuleSpecifier, _referrer: &ModuleSpecifier) {}
+}
+
+/// Layers asked for each module in the order they were added, before the
+/// module loader the run would otherwise use: the network loader, or
+/// `RunOptions::module_loader`. The first layer to supply a module wins,
+/// and the layers above it see it through [`LoaderLayer::loaded`].
+/// Specifiers are still resolved by the module loader, import maps
+/// included.
+///
+/// In-memory overrides over a disk cache over a filter, with the network
+/// at the bottom, are a [`MemoryLayer`], a [`DiskCacheLayer`] and a
+/// [`FilterLayer`] in that order. Modules from elsewhere, such as S3 or a
+/// database, come from a [`LoaderLayer`] of one's own.
+#[derive(Clone, Default)]
+pub struct LoaderStack {
+    layers: Vec<Arc<dyn LoaderLayer>>,
+    without_network: bool,
+}
+
+impl LoaderStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a layer below the ones added so far.
+    pub fn layer(mut self, layer: impl LoaderLayer) -> Self {
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// Adds a layer above the ones added so far.
+    #[cfg(feature = "watch")]
+    pub(crate) fn above(mut self, layer: impl LoaderLayer) -> Self {
+        self.layers.insert(0, Arc::new(layer));
+        self
+    }
+
+    /// Fails imports that no layer supplies instead of passing them on to
+    /// the module loader.
+    pub fn without_network(mut self) -> Self {
+        self.without_network = true;
+        self
+    }
+
+    pub(crate) fn loader(
+        &self,
+        below: Rc<dyn ModuleLoader>,
+        imports: Rc<ImportGraph>,
+        transpile_cache: Option<TranspileCache>,
+    ) -> StackLoader {
+        StackLoader {
+            layers: self.layers.clone(),
+            below,
+            without_network: self.without_network,
+            imports,
+            source_maps: Default::default(),
+            transpile_cache,
+        }
+    }
+}
+
+impl fmt::Debug for LoaderStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoaderStack")
+            .field("layers", &self.layers.len())
+            .field("without_network", &self.without_network)
+            .finish()
+    }
+}
+
+/// The module loader of a worker with a [`LoaderStack`].
+pub(crate) struct StackLoader {
+    layers: Vec<Arc<dyn LoaderLayer>>,
+    below: Rc<dyn ModuleLoader>,
+    without_network: bool,
+    imports: Rc<ImportGraph>,
+    source_maps: Rc<SourceMaps>,
+    transpile_cache: Option<TranspileCache>,
+}
+
+impl ModuleLoader for StackLoader {
+    fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        kind: ResolutionKind,
+    ) -> Result<ModuleSpecifier, Error> {
+        let specifier = self.below.resolve(specifier, referrer, kind)?;
+        if let Ok(referrer) = ModuleSpecifier::parse(referrer) {
+            for layer in &self.layers {
+                layer.resolved(&specifier, &referrer);
+            }
+        }
+        Ok(specifier)
+    }
+
+    fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        maybe_referrer: Option<&ModuleSpecifier>,
+        is_dyn_import: bool,
+        requested_module_type: RequestedModuleType,
+    ) -> ModuleLoadResponse {
+        let specifier = module_specifier.clone();
+        let referrer = maybe_referrer.cloned();
+        let layers = self.layers.clone();
+        let below = self.below.clone();
+        let without_network = self.without_network;
+        let imports = self.imports.clone();
+        let source_maps = self.source_maps.clone();
+        let transpile_cache = self.transpile_cache.clone();
+        ModuleLoadResponse::Async(
+            async move {
+                for (n, layer) in layers.iter().enumerate() {
+                    let module = match layer.load(&specifier).await {
+                        Ok(Some(module)) => module,
+                        Ok(None) => continue,
+                        Err(cause) => return Err(load_error(&imports, &specifier, cause)),
+                    };
+                    log::debug!("loading {} from loader layer {}", specifier, n);
+                    if let Some(referrer) = &referrer {
+                        imports.record(&specifier, referrer);
+                    }
+                    let loaded = async {
+                        for layer in &layers[..n] {
+                            layer.loaded(&specifier, &module).await?;
+                        }
+                        module_source(
+                            &specifier,
+                            module,
+                            &requested_module_type,
+                            &source_maps,
+                            transpile_cache.as_ref(),
+                        )
+                    };
+                    return loaded
+                        .await
+                        .map_err(|cause| load_error(&imports, &specifier, cause));
+                }
+                if without_network {
+                    let cause = anyhow!("no loader layer supplies it");
+                    return Err(load_error(&imports, &specifier, cause));
+                }
+
+                let source = match below.load(
+                    &specifier,
+                    referrer.as_ref(),
+                    is_dyn_import,
+                    requested_module_type,
+                ) {
+                    ModuleLoadResponse::Sync(source) => source,
+                    ModuleLoadResponse::Async(source) => source.await,
+                }?;
+                if !layers.is_empty() {
+                    let module = LayerModule {
+                        code: source.code.as_bytes().to_vec(),
+                        kind: Some(match source.module_type {
+                            ModuleType::Json => SourceKind::Json,
+                            _ => SourceKind::JavaScript,
+                        }),
+                        found: imports.found(&specifier),
+                    };
+                    for layer in &layers {
+                        layer
+                            .loaded(&specifier, &module)
+                            .await
+                            .map_err(|cause| load_error(&imports, &specifier, cause))?;
+                    }
+                }
+                Ok(source)
+            }
+            .boxed_local(),
+        )
+    }
+
+    fn get_source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+        self.source_maps
+            .source_map(file_name)
+            .or_else(|| self.below.get_source_map(file_name))
+    }
+
+    fn get_source_mapped_source_line(&self, file_name: &str, line_number: usize) -> Option<String> {
+        self.source_maps
+            .source_line(file_name, line_number)
+            .or_else(|| {
+                self.below
+                    .get_source_mapped_source_line(file_name, line_number)
+            })
+    }
+}
+
+fn load_error(imports: &ImportGraph, specifier: &ModuleSpecifier, cause: Error) -> Error {
+    error::load(specifier, imports.chain(specifier), cause)
+}
+
+fn module_source(
+    specifier: &ModuleSpecifier,
+    module: LayerModule,
+    requested: &RequestedModuleType,
+    source_maps: &SourceMaps,
+    transpile_cache: Option<&TranspileCache>,
+) -> Result<ModuleSource, Error> {
+    let url = module.found.as_ref().unwrap_or(specifier);
+    let kind = module
+        .kind
+        .unwrap_or_else(|| SourceKind::from_specifier(url));
+    let code = match kind {
+        SourceKind::Wasm => wasm::wrapper(&module.code)?.into_bytes(),
+        kind => {
+            let code =
+                String::from_utf8(module.code).with_context(|| format!("{} is not utf-8", url))?;
+            transpile::transpile_module(url, kind, code, Some(source_maps), transpile_cache)?
+        }
+    };
+    let module_type = transpile::module_type(specifier, kind, requested)?;
+    let code = ModuleSourceCode::Bytes(code.into_boxed_slice().into());
+    Ok(match &module.found {
+        Some(found) => ModuleSource::new_with_redirect(module_type, code, specifier, found, None),
+        None => ModuleSource::new(module_type, code, specifier, None),
+    })
+}
+
+/// Modules kept in memory, which take precedence over the layers below.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLayer {
+    modules: HashMap<ModuleSpecifier, LayerModule>,
+    counters: CacheCounters,
+}
+
+impl MemoryLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_module(mut self, specifier: ModuleSpecifier, module: LayerModule) -> Self {
+        self.modules.insert(specifier, module);
+        self
+    }
+
+    /// Lookups since the layer was created or last reset, and the modules
+    /// it holds. Clones share the counts.
+    pub fn stats(&self) -> CacheStats {
+        let bytes = self
+            .modules
+            .values()
+            .map(|module| module.code.len())
+            .sum::<usize>();
+        self.counters.stats(self.modules.len() as u64, bytes as u64)
+    }
+
+    pub fn reset_stats(&self) {
+        self.counters.reset()
+    }
+}
+
+#[async_trait(?Send)]
+impl LoaderLayer for MemoryLayer {
+    async fn load(&self, specifier: &ModuleSpecifier) -> Result<Option<LayerModule>, Error> {
+        let module = self.modules.get(specifier).cloned();
+        self.counters.record(match module {
+            Some(_) => CacheEvent::Hit,
+            None => CacheEvent::Miss,
+        });
+        Ok(module)
+    }
+}
+
+/// Keeps modules the layers below supply in a directory, and serves them
+/// from there afterwards without asking again. Local files are left out, so
+/// edits to them show. Entries never expire, clear the directory to fetch
+/// modules again.
+#[derive(Debug, Clone)]
+pub struct DiskCacheLayer {
+    dir: PathBuf,
+    counters: CacheCounters,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DiskEntry {
+    specifier: String,
+    found: Option<String>,
+    /// Extension of the source kind.
+    kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Encoding::is_plain")]
+    encoding: Encoding,
+}
+
+impl DiskCacheLayer {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            counters: CacheCounters::default(),
+        }
+    }
+
+    /// Lookups since the layer was created or last reset, and the entries
+    /// in its directory. Clones share the counts.
+    pub fn stats(&self) -> CacheStats {
+        let (mut entries, mut bytes) = (0, 0);
+        for entry in std::fs::read_dir(&self.dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "json")
+            {
+                entries += 1;
+            } else if let Ok(metadata) = entry.metadata() {
+                bytes += metadata.len();
+            }
+        }
+        self.counters.stats(entries, bytes)
+    }
+
+    pub fn reset_stats(&self) {
+        self.counters.reset()
+    }
+
+    /// Body and metadata paths, named after an FNV-1a hash of the
+    /// specifier. The metadata holds the specifier to tell collisions.
+    fn paths(&self, specifier: &ModuleSpecifier) -> (PathBuf, PathBuf) {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in specifier.as_str().bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        let name = format!("{:016x}", hash);
+        (self.dir.join(&name), self.dir.join(name + ".json"))
+    }
+
+    async fn read(&self, specifier: &ModuleSpecifier) -> Result<Option<LayerModule>, Error> {
+        let (body, metadata) = self.paths(specifier);
+        let Ok(metadata) = tokio::fs::read(&metadata).await else {
+            return Ok(None);
+        };
+        let entry: DiskEntry = serde_json::from_slice(&metadata)?;
+        if entry.specifier != specifier.as_str() {
+            return Ok(None);
+        }
+        let Some(code) = compress::decode(entry.encoding, tokio::fs::read(&body).await?) else {
+            return Ok(None);
+        };
+        Ok(Some(LayerModule {
+            code,
+            kind: entry
+                .kind
+                .map(|extension| SourceKind::from_path(&format!(".{}", extension))),
+            found: entry.found.map(|found| found.parse()).transpose()?,
+        }))
+    }
+
+    async fn write(&self, specifier: &ModuleSpecifier, module: &LayerModule) -> Result<(), Error> {
+        let (body, metadata) = self.paths(specifier);
+        let (encoding, code) = compress::encode(&module.code);
+        let entry = DiskEntry {
+            specifier: specifier.to_string(),
+            found: module.found.as_ref().map(ToString::to_string),
+            kind: module.kind.map(|kind| kind.extension().to_string()),
+            encoding,
+        };
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(&body, code).await?;
+        tokio::fs::write(&metadata, serde_json::to_vec(&entry)?).await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl LoaderLayer for DiskCacheLayer {
+    async fn load(&self, specifier: &ModuleSpecifier) -> Result<Option<LayerModule>, Error> {
+        if specifier.scheme() == "file" {
+            return Ok(None);
+        }
+        let module = self.read(specifier).await.unwrap_or_else(|e| {
+            log::debug!("ignoring cached {}: {:#}", specifier, e);
+            None
+        });
+        self.counters.record(match module {
+            Some(_) => CacheEvent::Hit,
+            None => CacheEvent::Miss,
+        });
+        Ok(module)
+    }
+
+    async fn loaded(&self, specifier: &ModuleSpecifier, module: &LayerModule) -> Result<(), Error> {
+        if specifier.scheme() != "file" {
+            match self.write(specifier, module).await {
+                Ok(()) => self.counters.record(CacheEvent::Insertion),
+                Err(e) => log::warn!("could not cache {}: {:#}", specifier, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+type Check = dyn Fn(&ModuleSpecifier) -> Result<(), Error> + Send + Sync;
+
+/// Refuses the modules it doesn't allow, so no layer below sees them.
+pub struct FilterLayer(Box<Check>);
+
+impl FilterLayer {
+    pub fn new(allow: impl Fn(&ModuleSpecifier) -> bool + Send + Sync + 'static) -> Self {
+        Self(Box::new(move |specifier| {
+            if !allow(specifier) {
+                bail!("{} is not allowed by the loader stack", specifier);
+            }
+            Ok(())
+        }))
+    }
+
+    /// Applies an import policy to remote modules.
+    #[cfg(feature = "net-loader")]
+    pub fn policy(policy: crate::import_policy::ImportPolicy) -> Self {
+        Self(Box::new(move |specifier| match specifier.scheme() {
+            "http" | "https" => policy.check(specifier),
+            _ => Ok(()),
+        }))
+    }
+}
+
+impl fmt::Debug for FilterLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FilterLayer")
+    }
+}
+
+#[async_trait(?Send)]
+impl LoaderLayer for FilterLayer {
+    async fn load(&self, specifier: &ModuleSpecifier) -> Result<Option<LayerModule>, Error> {
+        (self.0)(specifier)?;
+        Ok(None)
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::{bail, Context, Error};
+use deno_core::ModuleSpecifier;
+use serde_json::{json, Map, Value};
+use sha2::Digest;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+
+/// SHA-256 hashes expected for remote modules, in the `remote` map of a
+/// Deno lockfile. Keys are the URLs bodies were served from, after
+/// redirects.
+pub(crate) struct Lockfile {
+    path: PathBuf,
+    /// Record hashes instead of checking them.
+    write: bool,
+    content: RefCell<Map<String, Value>>,
+}
+
+impl Lockfile {
+    /// A lockfile that doesn't exist yet is only accepted in write mode.
+    pub(crate) fn open(path: &Path, write: bool) -> Result<Self, Error> {
+        let content = match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("could not parse lockfile {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && write => {
+                let mut content = Map::new();
+                content.insert("version".to_string(), json!("3"));
+                content
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("could not read lockfile {}", path.display()))
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            write,
+            content: RefCell::new(content),
+        })
+    }
+
+    pub(crate) fn check(&self, url: &ModuleSpecifier, body: &[u8]) -> Result<(), Error> {
+        let actual = format!("{:x}", sha2::Sha256::digest(body));
+        let mut content = self.content.borrow_mut();
+        let expected = content
+            .get("remote")
+            .and_then(|remote| remote.get(url.as_str()))
+            .and_then(Value::as_str);
+        match expected {
+            Some(expected) if expected == actual => return Ok(()),
+            _ if self.write => {}
+            Some(expected) => bail!(
+                "integrity check failed for {}: the lockfile expects {}, the fetched content hashes to {}",
+                url,
+                expected,
+                actual
+            ),
+            None => bail!(
+                "{} is not in the lockfile {}, its content hashes to {}",
+                url,
+                self.path.display(),
+                actual
+            ),
+        }
+
+        let remote = content
+            .entry("remote")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Value::Object(remote) = remote {
+            remote.insert(url.to_string(), Value::String(actual));
+        }
+        let bytes = serde_json::to_vec_pretty(&*content)?;
+        std::fs::write(&self.path, bytes)
+            .with_context(|| format!("could not write lockfile {}", self.path.display()))
+    }
+}
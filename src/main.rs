@@ -1,254 +1,726 @@
-use anyhow::anyhow;
-use serde_json::Value;
+use anyhow::{anyhow, bail, Context, Error};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use deno_core::error::JsError;
+use serde_json::{json, Map, Value};
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::Arc;
-
-use deno_core::JsRuntime;
-
-use deno_ast::{MediaType, ParseParams};
-use deno_core::*;
-
-use deno_permissions::PermissionsContainer;
-use deno_permissions::{Permissions, PermissionsOptions};
-use deno_runtime::worker::MainWorker;
-use deno_runtime::worker::WorkerOptions;
-
-use deno_core::anyhow::{bail, Error};
-use deno_core::futures::FutureExt;
-use deno_core::ModuleLoader;
-use deno_core::ModuleSource;
-use deno_core::ModuleSpecifier;
-use deno_core::ModuleType;
-use deno_core::{resolve_import, ModuleSourceCode, RequestedModuleType, ResolutionKind};
-
-pub struct NetworkModuleLoader;
-
-impl ModuleLoader for NetworkModuleLoader {
-    fn resolve(
-        &self,
-        specifier: &str,
-        referrer: &str,
-        _kind: ResolutionKind,
-    ) -> Result<ModuleSpecifier, Error> {
-        Ok(resolve_import(specifier, referrer)?)
-    }
-
-    fn load(
-        &self,
-        module_specifier: &ModuleSpecifier,
-        _maybe_referrer: Option<&ModuleSpecifier>,
-        _is_dyn_import: bool,
-        requested_module_type: RequestedModuleType,
-    ) -> ModuleLoadResponse {
-        let module_specifier = module_specifier.clone();
-
-        ModuleLoadResponse::Async(
-            async move {
-                let mut redirect_module_url = None;
-                let code = match module_specifier.scheme() {
-                    "http" | "https" => {
-                        log::debug!("loading url import: {}", module_specifier);
-                        let res = reqwest::get(module_specifier.clone()).await?;
-                        let res = res.error_for_status()?;
-                        if res.url() != &module_specifier {
-                            redirect_module_url = Some(res.url().clone());
-                        }
-                        res.bytes().await?.to_vec()
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, UNIX_EPOCH};
+
+use experimental_runtime::{
+    check, dependency_report, is_subprocess, run_repl, run_to_writer, runtime_info, serve_rpc,
+    serve_subprocess, ConsoleSink, Determinism, ImportMap, RunOptions, Runtime, RuntimeBuilder,
+    RuntimeError, RuntimePermissions, Subprocess, Trace, TraceMode, TraceRecorder, TranspileCache,
+};
+#[cfg(feature = "watch")]
+use experimental_runtime::{watch, WatchEvent};
+#[cfg(feature = "net-loader")]
+use experimental_runtime::{CachePolicy, ModuleCache};
+
+/// Exit code when the script itself failed, by throwing or otherwise.
+const SCRIPT_FAILED: u8 = 1;
+/// Exit code for usage errors and failures outside the script, matching
+/// clap's own usage errors.
+const HOST_FAILED: u8 = 2;
+/// Exit code when the module or one of its imports could not be resolved
+/// or loaded.
+const MODULE_FAILED: u8 = 3;
+/// Exit code when the run hit its timeout or ran out of fuel.
+const TIMED_OUT: u8 = 4;
+/// Address of `--inspect` without a value, the usual DevTools port.
+const DEFAULT_INSPECT: &str = "127.0.0.1:9229";
+
+#[derive(Parser)]
+#[command(version, disable_version_flag = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Print version
+    #[arg(short = 'V', long)]
+    version: bool,
+    /// With --version, print the embedded engine versions and enabled
+    /// features as JSON.
+    #[arg(long, requires = "version")]
+    verbose: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a function module and print its result as JSON.
+    ///
+    /// The result goes to stdout, console output and errors to stderr.
+    #[command(
+        after_help = "Exit codes: 0 on success, 1 when the script threw or failed, \
+        2 for usage and host errors, 3 when a module could not be resolved or loaded, \
+        4 on timeout or running out of fuel."
+    )]
+    Run(Box<RunArgs>),
+    /// Serve JSON-RPC 2.0 over stdin/stdout, one message per line.
+    Rpc,
+    /// Serve functions at `POST /invoke/<name>` until interrupted.
+    #[cfg(feature = "serve")]
+    Serve {
+        /// A function to serve as `name=path`. Repeatable.
+        #[arg(long = "route", value_name = "NAME=PATH", required = true)]
+        routes: Vec<String>,
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: std::net::SocketAddr,
+        /// Invocations running at once.
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// Stop each invocation after this long.
+        #[arg(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        /// Serve the gRPC `Invocation` service instead, with the routes as
+        /// handles.
+        #[cfg(feature = "grpc")]
+        #[arg(long)]
+        grpc: bool,
+    },
+    /// Evaluate expressions in a worker set up like a real run.
+    Repl {
+        /// Function module whose namespace is bound to `mod`.
+        #[arg(long)]
+        module: Option<PathBuf>,
+        /// Sample inputs as JSON, bound to `inputs`.
+        #[arg(long, default_value = "{}")]
+        inputs: String,
+        #[command(flatten)]
+        runtime: RuntimeArgs,
+    },
+    /// Load and compile a function's import graph and look up its export,
+    /// without running anything. Prints a JSON report and fails when it
+    /// lists problems.
+    Check {
+        module: PathBuf,
+        /// Export that would be called with the inputs.
+        #[arg(long, default_value = "main")]
+        export: String,
+        /// Import map JSON file for bare specifiers.
+        #[arg(long)]
+        import_map: Option<PathBuf>,
+        /// How to print the report.
+        #[arg(long, value_enum, default_value_t = Output::Json)]
+        output: Output,
+    },
+    /// Pack a function and everything it imports into a tar archive that
+    /// runs without the network.
+    #[cfg(feature = "typescript")]
+    Bundle {
+        module: PathBuf,
+        /// Archive to write, gzipped when it ends in `.tar.gz` or `.tgz`.
+        #[arg(long, short)]
+        out: PathBuf,
+        /// Import map JSON file for bare specifiers.
+        #[arg(long)]
+        import_map: Option<PathBuf>,
+    },
+    /// Fetch a function's import graph without running it.
+    Cache {
+        entry: PathBuf,
+        /// Write a JSON report of the remote dependencies to this file.
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// Directory to keep the modules in, for `run --cache-dir`.
+        #[cfg(feature = "net-loader")]
+        #[arg(long, value_name = "DIR")]
+        cache_dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct RunArgs {
+    module: PathBuf,
+    /// An input as `key=json`, e.g. `--input count=3`. Repeatable, and
+    /// wins over --input-file.
+    #[arg(long = "input", value_name = "KEY=JSON")]
+    inputs: Vec<String>,
+    /// JSON object of inputs, `-` to read it from stdin.
+    #[arg(long)]
+    input_file: Option<PathBuf>,
+    /// Export to call with the inputs.
+    #[arg(long, default_value = "main")]
+    export: String,
+    #[command(flatten)]
+    runtime: RuntimeArgs,
+    /// Run the function in a child process, so an engine crash is
+    /// reported as an error.
+    #[arg(long)]
+    isolate: bool,
+    /// Run again whenever the module or a local file it imports changes.
+    #[cfg(feature = "watch")]
+    #[arg(long, conflicts_with_all = ["isolate", "batch"])]
+    watch: bool,
+    /// Run once per line of this file of JSON objects, `-` for stdin,
+    /// printing one line of JSON per run. --input values go to every run.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["input_file", "output"]
+    )]
+    batch: Option<PathBuf>,
+    /// How to print the result.
+    #[arg(long, value_enum, default_value_t = Output::Json)]
+    output: Output,
+}
+
+/// Flags describing the runtime, shared by `run` and `repl`.
+#[derive(Args)]
+struct RuntimeArgs {
+    /// Stop the run after this long, e.g. `5s`, `500ms` or `2m`.
+    #[arg(long, value_parser = parse_duration)]
+    timeout: Option<Duration>,
+    /// Stop the run once the script was busy for this many milliseconds
+    /// of CPU work.
+    #[arg(long, value_name = "UNITS")]
+    fuel: Option<u64>,
+    /// Allow network access, to all hosts or only the listed ones.
+    #[arg(long, value_name = "HOST", num_args = 0.., value_delimiter = ',')]
+    allow_net: Option<Vec<String>>,
+    /// Import map JSON file for bare specifiers.
+    #[arg(long)]
+    import_map: Option<PathBuf>,
+    /// Compile TypeScript and JSX on every run instead of reusing
+    /// cached output.
+    #[arg(long)]
+    no_transpile_cache: bool,
+    /// Let Chrome DevTools attach through the inspector on this address.
+    #[arg(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = DEFAULT_INSPECT
+    )]
+    inspect: Option<std::net::SocketAddr>,
+    /// Like --inspect, and wait for DevTools to attach, pausing before
+    /// the export is called.
+    #[arg(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = DEFAULT_INSPECT,
+        conflicts_with = "inspect"
+    )]
+    inspect_brk: Option<std::net::SocketAddr>,
+    /// Run deterministically, with `Math.random` drawn from this seed
+    /// and the clock frozen.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Milliseconds since the Unix epoch the frozen clock shows, 0 by
+    /// default.
+    #[arg(long, value_name = "MILLIS", requires = "seed")]
+    start_time: Option<u64>,
+    /// Record what fetches and host calls return to this trace file.
+    #[arg(long, value_name = "FILE")]
+    trace: Option<PathBuf>,
+    /// Serve fetches and host calls from the --trace file instead.
+    #[arg(long, requires = "trace")]
+    replay: bool,
+    /// Keep remote modules in this directory between runs, as `cache`
+    /// fills it.
+    #[cfg(feature = "net-loader")]
+    #[arg(long, value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+    /// Load remote modules from --cache-dir only, without the network.
+    #[cfg(feature = "net-loader")]
+    #[arg(long, requires = "cache_dir")]
+    offline: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Output {
+    /// The result on a single line.
+    Json,
+    /// Indented JSON.
+    Pretty,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if cli.version {
+        if cli.verbose {
+            let info = serde_json::to_string_pretty(&runtime_info()).unwrap();
+            println!("{}", info);
+        } else {
+            println!("experimental_runtime {}", env!("CARGO_PKG_VERSION"));
+        }
+        return ExitCode::SUCCESS;
+    }
+
+    let mut code = ExitCode::SUCCESS;
+    match cli.command {
+        Some(Command::Run(args)) => {
+            if is_subprocess() {
+                let served =
+                    runtime(&args).and_then(|runtime| serve_subprocess(runtime.options().clone()));
+                if let Err(e) = served {
+                    eprintln!("subprocess error: {:#}", e);
+                    code = ExitCode::from(HOST_FAILED);
+                }
+                return code;
+            }
+            #[cfg(feature = "watch")]
+            if args.watch {
+                if let Err(e) = watch_module(&args) {
+                    eprintln!("error: {:#}", e);
+                    code = ExitCode::from(HOST_FAILED);
+                }
+                return code;
+            }
+            if let Some(batch) = &args.batch {
+                match run_batch(&args, batch) {
+                    Ok(None) => {}
+                    Ok(Some(e)) => code = ExitCode::from(exit_code(&e)),
+                    Err(e) => {
+                        eprintln!("error: {:#}", e);
+                        code = ExitCode::from(HOST_FAILED);
+                    }
+                }
+                return code;
+            }
+            let result = read_inputs(&args.inputs, args.input_file.as_ref())
+                .and_then(|inputs| runtime(&args)?.run(args.module.clone(), inputs));
+            if let Some(e) = print_result(result, args.output) {
+                code = ExitCode::from(exit_code(&e));
+            }
+        }
+        Some(Command::Rpc) => {
+            if let Err(e) = serve_rpc(std::io::stdin().lock(), std::io::stdout().lock()) {
+                eprintln!("rpc error: {:#}", e);
+                code = ExitCode::from(HOST_FAILED);
+            }
+        }
+        #[cfg(feature = "grpc")]
+        Some(Command::Serve {
+            routes,
+            listen,
+            concurrency,
+            timeout,
+            grpc: true,
+        }) => {
+            if let Err(e) = serve_grpc(&routes, listen, concurrency, timeout) {
+                eprintln!("serve error: {:#}", e);
+                code = ExitCode::from(HOST_FAILED);
+            }
+        }
+        #[cfg(feature = "serve")]
+        Some(Command::Serve {
+            routes,
+            listen,
+            concurrency,
+            timeout,
+            ..
+        }) => {
+            if let Err(e) = serve(&routes, listen, concurrency, timeout) {
+                eprintln!("serve error: {:#}", e);
+                code = ExitCode::from(HOST_FAILED);
+            }
+        }
+        Some(Command::Repl {
+            module,
+            inputs,
+            runtime,
+        }) => {
+            let repl = serde_json::from_str(&inputs)
+                .map_err(anyhow::Error::from)
+                .and_then(|inputs| {
+                    run_repl(
+                        module,
+                        inputs,
+                        builder(&runtime)?.build()?.options().clone(),
+                        std::io::stdin().lock(),
+                        std::io::stdout(),
+                    )
+                });
+            if let Err(e) = repl {
+                eprintln!("repl error: {:#}", e);
+                code = ExitCode::from(exit_code(&e));
+            }
+        }
+        Some(Command::Check {
+            module,
+            export,
+            import_map,
+            output,
+        }) => {
+            let mut runtime = Runtime::builder().entrypoint(&export);
+            let report = import_map
+                .map(|path| ImportMap::from_file(&path))
+                .transpose()
+                .and_then(|import_map| {
+                    if let Some(import_map) = import_map {
+                        runtime = runtime.import_map(import_map);
                     }
-                    "file" => {
-                        log::debug!("resolving file module");
-                        let path = match module_specifier.to_file_path() {
-                            Ok(path) => path,
-                            Err(_) => bail!("Invalid file URL."),
-                        };
-                        tokio::fs::read(path).await?
+                    check(&module, runtime.build()?.options())
+                });
+            match report {
+                Ok(report) => {
+                    match output {
+                        Output::Json => println!("{}", serde_json::to_string(&report).unwrap()),
+                        Output::Pretty => {
+                            println!("{}", serde_json::to_string_pretty(&report).unwrap())
+                        }
                     }
-                    schema => bail!("Invalid schema {}", schema),
-                };
-
-                let media_type = MediaType::from_specifier(&module_specifier);
-                let (_, should_transpile) = match MediaType::from_specifier(&module_specifier) {
-                    MediaType::JavaScript | MediaType::Mjs | MediaType::Cjs => {
-                        (deno_core::ModuleType::JavaScript, false)
+                    if !report.is_ok() {
+                        code = ExitCode::from(SCRIPT_FAILED);
                     }
-                    MediaType::Jsx => (deno_core::ModuleType::JavaScript, true),
-                    MediaType::TypeScript
-                    | MediaType::Mts
-                    | MediaType::Dts
-                    | MediaType::Dmts
-                    | MediaType::Dcts
-                    | MediaType::Tsx => (deno_core::ModuleType::JavaScript, true),
-                    MediaType::Json => (deno_core::ModuleType::Json, false),
-                    _ => (deno_core::ModuleType::JavaScript, false),
-                };
-
-                let code = if should_transpile {
-                    log::debug!("compiling ts module");
-                    let parsed = deno_ast::parse_module(ParseParams {
-                        specifier: module_specifier.clone(),
-                        text: Arc::from(String::from_utf8_lossy(code.as_ref())),
-                        media_type,
-                        capture_tokens: false,
-                        scope_analysis: false,
-                        maybe_syntax: None,
-                    })?;
-
-                    parsed
-                        .transpile(
-                            &deno_ast::TranspileOptions {
-                                ..Default::default()
-                            },
-                            &deno_ast::EmitOptions {
-                                ..Default::default()
-                            },
-                        )?
-                        .into_source()
-                        .source
-                } else {
-                    code
-                };
-
-                // TODO: The MIME types should probably be checked.
-                let module_type = match requested_module_type {
-                    RequestedModuleType::None => ModuleType::JavaScript,
-                    RequestedModuleType::Json => ModuleType::Json,
-                    RequestedModuleType::Other(_) => {
-                        log::error!("unreachable module type requested");
-                        bail!("Import types other than JSON are not supported");
+                }
+                Err(e) => {
+                    eprintln!("check error: {:#}", e);
+                    code = ExitCode::from(HOST_FAILED);
+                }
+            }
+        }
+        #[cfg(feature = "typescript")]
+        Some(Command::Bundle {
+            module,
+            out,
+            import_map,
+        }) => {
+            let bundled = import_map
+                .map(|path| ImportMap::from_file(&path))
+                .transpose()
+                .and_then(|import_map| {
+                    let options = RunOptions {
+                        import_map,
+                        ..RunOptions::default()
+                    };
+                    let bundle = experimental_runtime::bundle(&module, &options)?;
+                    bundle.write(&out)?;
+                    Ok(bundle)
+                });
+            match bundled {
+                // The path to run the archive's function with.
+                Ok(bundle) => println!("{}", bundle.entry),
+                Err(e) => {
+                    eprintln!("bundle error: {:#}", e);
+                    code = ExitCode::from(HOST_FAILED);
+                }
+            }
+        }
+        Some(Command::Cache {
+            entry,
+            report,
+            #[cfg(feature = "net-loader")]
+            cache_dir,
+        }) => {
+            let options = RunOptions {
+                #[cfg(feature = "net-loader")]
+                module_cache: cache_dir.map(|dir| ModuleCache::in_dir(dir, CachePolicy::UseCache)),
+                ..RunOptions::default()
+            };
+            match dependency_report(&entry, &options) {
+                Ok(deps) => {
+                    if let Some(report) = report {
+                        let written = serde_json::to_vec_pretty(&deps)
+                            .map_err(std::io::Error::from)
+                            .and_then(|json| std::fs::write(&report, json));
+                        if let Err(e) = written {
+                            eprintln!("could not write {}: {}", report.display(), e);
+                            code = ExitCode::from(HOST_FAILED);
+                        }
                     }
-                };
-
-                if let Some(redirect_module_url) = redirect_module_url {
-                    Ok(ModuleSource::new_with_redirect(
-                        module_type,
-                        ModuleSourceCode::Bytes(code.into_boxed_slice().into()),
-                        &module_specifier,
-                        &redirect_module_url,
-                        None,
-                    ))
-                } else {
-                    Ok(ModuleSource::new(
-                        module_type,
-                        ModuleSourceCode::Bytes(code.into_boxed_slice().into()),
-                        &module_specifier,
-                        None,
-                    ))
+                }
+                Err(e) => {
+                    eprintln!("cache error: {:#}", e);
+                    code = ExitCode::from(exit_code(&e));
                 }
             }
-            .boxed_local(),
-        )
+        }
+        None => {
+            let _ = Cli::command().print_help();
+            code = ExitCode::from(HOST_FAILED);
+        }
     }
+
+    code
 }
 
-pub fn init() {
-    let platform = v8::new_default_platform(0, false).make_shared();
-    JsRuntime::init_platform(Some(platform), false);
+/// Prints the result, or the error, which is returned.
+fn print_result(result: Result<Value, Error>, output: Output) -> Option<Error> {
+    match result {
+        Ok(value) => {
+            match output {
+                Output::Json => println!("{}", value),
+                Output::Pretty => println!("{:#}", value),
+            }
+            None
+        }
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            Some(e)
+        }
+    }
 }
 
-pub fn run_insecure(
-    function: PathBuf,
-    inputs: std::collections::HashMap<String, serde_json::Value>,
-) -> Result<Value, anyhow::Error> {
-    let runtime = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()?;
-    runtime.block_on(async {
-        //TODO: remove this runtime mechanism and use threadpool with channels
-        let main_module = deno_core::resolve_path(function.clone(), &std::env::current_dir()?)
-            .map_err(|e| anyhow!("could not load module function code: {}", e))?;
-
-        log::debug!("setting up runtime worker");
-        let worker_options = WorkerOptions {
-            module_loader: std::rc::Rc::new(NetworkModuleLoader),
-            ..Default::default()
+/// Streams the result of each line's run to stdout as a line of its own,
+/// or `{"error": message}` when it failed. Returns the last failure.
+fn run_batch(args: &RunArgs, batch: &Path) -> Result<Option<Error>, Error> {
+    let text = if batch.as_os_str() == "-" {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        std::fs::read_to_string(batch)
+            .with_context(|| format!("could not read {}", batch.display()))?
+    };
+    let shared = read_inputs(&args.inputs, None)?;
+    let runtime = runtime(args)?;
+    let mut stdout = std::io::stdout().lock();
+    let mut failed = None;
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = match serde_json::from_str(line) {
+            Ok(Value::Object(inputs)) => {
+                let mut inputs: HashMap<_, _> = inputs.into_iter().collect();
+                inputs.extend(shared.clone());
+                let options = runtime.options().clone();
+                run_to_writer(args.module.clone(), inputs, options, &mut stdout).map(|_| ())
+            }
+            Ok(_) => Err(anyhow!("line {} is not a json object", index + 1)),
+            Err(e) => Err(Error::from(e).context(format!("line {} is not valid json", index + 1))),
         };
+        if let Err(e) = result {
+            eprintln!("error: line {}: {:#}", index + 1, e);
+            serde_json::to_writer(&mut stdout, &json!({ "error": format!("{:#}", e) }))?;
+            failed = Some(e);
+        }
+        writeln!(stdout)?;
+    }
+    Ok(failed)
+}
 
-        //TODO: trickle down perms
-        let permissions =
-            PermissionsContainer::new(Permissions::from_options(&PermissionsOptions {
-                allow_all: true,
-                allow_env: None,
-                deny_env: None,
-                allow_hrtime: false,
-                deny_hrtime: true,
-                allow_net: Some(vec![]),
-                deny_net: None,
-                allow_ffi: None,
-                deny_ffi: None,
-                allow_read: None,
-                deny_read: None,
-                allow_run: None,
-                deny_run: None,
-                allow_sys: None,
-                deny_sys: None,
-                allow_write: None,
-                deny_write: None,
-                prompt: false,
-            })?);
-        let mut main_worker =
-            MainWorker::bootstrap_from_options(main_module.clone(), permissions, worker_options);
-
-        // main_worker.execute_main_module(&main_module).await?;
-        let mod_id = main_worker.preload_main_module(&main_module).await?;
-
-        log::debug!("evaluating function");
-        //TODO: handle error
-        let _ = main_worker.evaluate_module(mod_id);
-
-        log::debug!("running event loop");
-        main_worker.run_event_loop(false).await?;
-        log::debug!("done event loop");
-        let fres = {
-            let global = main_worker.js_runtime.get_module_namespace(mod_id)?;
-            let scope = &mut main_worker.js_runtime.handle_scope();
-            let namespace = v8::Local::<v8::Object>::new(scope, global);
-
-            let func_key = v8::String::new(scope, "main")
-                .ok_or(anyhow!("could not setup main function key"))?;
-
-            let func = namespace
-                .get(scope, func_key.into())
-                .ok_or(anyhow!("entrypoint not found"))?;
-            let func = v8::Local::<v8::Function>::try_from(func)
-                .map_err(|_| anyhow!("main function not found"))?;
-
-            let i = serde_v8::to_v8(scope, inputs)
-                .map_err(|_| anyhow!("inputs provided are invalid"))?;
-
-            let recv = v8::Integer::new(scope, 1).into();
-            let func_res = func
-                .call(scope, recv, &[i])
-                .ok_or(anyhow!("unknown error"))?;
-
-            v8::Global::new(scope, func_res)
+/// Prints the result of every run until the watcher fails.
+#[cfg(feature = "watch")]
+fn watch_module(args: &RunArgs) -> Result<(), Error> {
+    let inputs = read_inputs(&args.inputs, args.input_file.as_ref())?;
+    let options = runtime(args)?.options().clone();
+    watch(args.module.clone(), inputs, options, |event| {
+        match event {
+            WatchEvent::Ran(result) => {
+                print_result(result, args.output);
+            }
+            WatchEvent::Changed(invalidation) => eprintln!("{}", invalidation),
+        }
+        std::ops::ControlFlow::Continue(())
+    })
+}
+
+fn runtime(args: &RunArgs) -> Result<Runtime, Error> {
+    let mut runtime = builder(&args.runtime)?.entrypoint(&args.export);
+    if args.isolate {
+        runtime = runtime.subprocess(Subprocess::current_exe()?);
+    }
+    runtime.build()
+}
+
+fn builder(args: &RuntimeArgs) -> Result<RuntimeBuilder, Error> {
+    let mut runtime =
+        Runtime::builder()
+            .console(ConsoleSink::Stderr)
+            .permissions(RuntimePermissions {
+                allow_net: args.allow_net.clone(),
+                ..RuntimePermissions::none()
+            });
+    if let Some(timeout) = args.timeout {
+        runtime = runtime.timeout(timeout);
+    }
+    if let Some(fuel) = args.fuel {
+        runtime = runtime.fuel(fuel);
+    }
+    if !args.no_transpile_cache {
+        runtime = runtime.transpile_cache(TranspileCache::new()?);
+    }
+    if let Some(path) = &args.import_map {
+        runtime = runtime.import_map(ImportMap::from_file(path)?);
+    }
+    if let Some(address) = args.inspect_brk.or(args.inspect) {
+        runtime = runtime.inspect(address, args.inspect_brk.is_some());
+    }
+    if let Some(seed) = args.seed {
+        let start_time = UNIX_EPOCH + Duration::from_millis(args.start_time.unwrap_or_default());
+        runtime = runtime.determinism(Determinism::new(seed).with_start_time(start_time));
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(dir) = &args.cache_dir {
+        let policy = match args.offline {
+            true => CachePolicy::Offline,
+            false => CachePolicy::UseCache,
         };
-        let f = main_worker.js_runtime.resolve_value(fres).await?;
-        let scope = &mut main_worker.js_runtime.handle_scope();
-        let local_f = v8::Local::<v8::Value>::new(scope, f);
+        runtime = runtime.module_cache(ModuleCache::in_dir(dir, policy));
+    }
+    if let Some(path) = &args.trace {
+        runtime = runtime.trace(match args.replay {
+            true => TraceMode::Replay(Trace::open(path)?),
+            false => TraceMode::Record(TraceRecorder::create(path)?),
+        });
+    }
+    Ok(runtime)
+}
 
-        let deserialized_value = serde_v8::from_v8::<serde_json::Value>(scope, local_f)
-            .map_err(|_| anyhow!("failed to deserialise returned value"))?;
+#[cfg(feature = "serve")]
+fn serve(
+    routes: &[String],
+    listen: std::net::SocketAddr,
+    concurrency: usize,
+    timeout: Option<Duration>,
+) -> Result<(), Error> {
+    let options = RunOptions {
+        timeout,
+        ..RunOptions::default()
+    };
+    let mut server = experimental_runtime::InvokeServer::new(options).concurrency(concurrency);
+    for route in routes {
+        let (name, path) = route
+            .split_once('=')
+            .ok_or_else(|| anyhow!("route {:?} is not name=path", route))?;
+        server = server.route(name, path);
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        eprintln!("listening on http://{}", listener.local_addr()?);
+        server
+            .serve(listener, async {
+                let _ = tokio::signal::ctrl_c().await;
+            })
+            .await
+    })
+}
 
-        Ok(deserialized_value)
+#[cfg(feature = "grpc")]
+fn serve_grpc(
+    routes: &[String],
+    listen: std::net::SocketAddr,
+    concurrency: usize,
+    timeout: Option<Duration>,
+) -> Result<(), Error> {
+    let options = RunOptions {
+        timeout,
+        ..RunOptions::default()
+    };
+    let mut server = experimental_runtime::GrpcServer::new(options).concurrency(concurrency);
+    for route in routes {
+        let (name, path) = route
+            .split_once('=')
+            .ok_or_else(|| anyhow!("route {:?} is not name=path", route))?;
+        server = server.route(name, path);
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let listener = tokio::net::TcpListener::bind(listen).await?;
+        eprintln!("listening for grpc on {}", listener.local_addr()?);
+        server
+            .serve(listener, async {
+                let _ = tokio::signal::ctrl_c().await;
+            })
+            .await
     })
 }
 
-pub fn deinit() {
-    unsafe {
-        v8::V8::dispose();
+fn read_inputs(
+    inputs: &[String],
+    input_file: Option<&PathBuf>,
+) -> Result<HashMap<String, Value>, Error> {
+    let mut values = Map::new();
+    if let Some(path) = input_file {
+        let text = if path.as_os_str() == "-" {
+            let mut text = String::new();
+            std::io::stdin().read_to_string(&mut text)?;
+            text
+        } else {
+            std::fs::read_to_string(path)
+                .with_context(|| format!("could not read {}", path.display()))?
+        };
+        match serde_json::from_str(&text).context("input file is not valid json")? {
+            Value::Object(map) => values = map,
+            _ => bail!("input file must hold a json object"),
+        }
     }
-    v8::V8::dispose_platform();
+    for input in inputs {
+        let (key, json) = input
+            .split_once('=')
+            .ok_or_else(|| anyhow!("input {:?} is not key=json", input))?;
+        let value = serde_json::from_str(json)
+            .with_context(|| format!("input {} is not valid json", key))?;
+        values.insert(key.to_string(), value);
+    }
+    Ok(values.into_iter().collect())
+}
+
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "invalid duration {:?}, expected a number with an optional ms, s or m unit",
+            text
+        )
+    };
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (amount, unit) = text.split_at(split);
+    if !amount.contains(|c: char| c.is_ascii_digit()) || amount.matches('.').count() > 1 {
+        return Err(invalid());
+    }
+    let amount: f64 = amount.parse().map_err(|_| invalid())?;
+    let seconds = match unit {
+        "ms" => amount / 1000.0,
+        "s" | "" => amount,
+        "m" => amount * 60.0,
+        _ => return Err(format!("unknown duration unit {:?}", unit)),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("duration {:?} is too long", text))
 }
-fn main() {
-    init();
 
-    let mut inputs: HashMap<String, Value> = HashMap::new();
-    inputs.insert("secret_key".into(), Value::String("key123".into()));
-    inputs.insert("payload".into(), Value::String("{}".into()));
+/// Tells apart the script failing, its modules failing to load, limits it
+/// ran into and failures on the host side.
+fn exit_code(error: &Error) -> u8 {
+    let kind = match error.downcast_ref::<RuntimeError>() {
+        // Failures in a child keep the kind they had there.
+        Some(RuntimeError::Subprocess { kind, .. }) => kind.as_str(),
+        Some(error) => error.kind(),
+        None if error.is::<JsError>() => "js_exception",
+        None => return HOST_FAILED,
+    };
+    match kind {
+        "module_resolution" | "module_load" | "fetch" | "transpile" => MODULE_FAILED,
+        "timeout" | "budget_exceeded" => TIMED_OUT,
+        "internal" => HOST_FAILED,
+        _ => SCRIPT_FAILED,
+    }
+}
 
-    let result = run_insecure("./create_jwt.js".into(), inputs);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    print!("result = {:?}", result);
-    deinit();
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("3m"), Ok(Duration::from_secs(180)));
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        for text in ["", ".", ".s", "1.2.3s", "s"] {
+            let error = parse_duration(text).unwrap_err();
+            assert!(error.starts_with("invalid duration"), "{}", error);
+        }
+        assert!(parse_duration("5h")
+            .unwrap_err()
+            .contains("unknown duration unit"));
+        let long = format!("{}s", "9".repeat(30));
+        assert!(parse_duration(&long).unwrap_err().contains("too long"));
+    }
 }
@@ -0,0 +1,217 @@
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::cancel::CancellationHandle;
+use crate::error::RuntimeError;
+use crate::executor::ExecutorPool;
+use crate::inputs::Inputs;
+use crate::options::RunOptions;
+
+/// How much of the manager one tenant may use.
+#[derive(Debug, Clone, Copy)]
+pub struct TenantLimits {
+    /// Invocations running at once, each on its own thread and isolate.
+    /// 1 by default.
+    pub max_concurrency: usize,
+    /// Invocations waiting for a thread before further ones are refused
+    /// with `RuntimeError::QueueFull`. 64 by default.
+    pub max_queued: usize,
+}
+
+impl Default for TenantLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 1,
+            max_queued: 64,
+        }
+    }
+}
+
+/// Runs the functions of several tenants side by side. Every tenant gets
+/// an executor pool of its own, so their invocations never share a thread
+/// or an isolate, and one tenant filling its queue doesn't hold up others.
+/// Tenants not added explicitly are started on first use with the
+/// manager's options and limits.
+pub struct RuntimeManager {
+    options: RunOptions,
+    limits: TenantLimits,
+    tenants: Mutex<HashMap<String, Arc<Tenant>>>,
+}
+
+struct Tenant {
+    pool: ExecutorPool,
+    limits: TenantLimits,
+    /// Queued and running invocations.
+    in_flight: AtomicUsize,
+}
+
+/// Load of a [`RuntimeManager`] at one point in time.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ManagerStats {
+    /// Isolates running an invocation, across tenants.
+    pub active_isolates: usize,
+    /// Invocations waiting for a thread, across tenants.
+    pub queued: usize,
+    pub tenants: BTreeMap<String, TenantStats>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TenantStats {
+    pub running: usize,
+    pub queued: usize,
+    pub max_concurrency: usize,
+    pub max_queued: usize,
+}
+
+impl RuntimeManager {
+    /// Tenants started on first use run with `options` and `limits`.
+    pub fn new(options: RunOptions, limits: TenantLimits) -> Self {
+        Self {
+            options,
+            limits,
+            tenants: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Starts `tenant` with options and limits of its own. Fails if the
+    /// tenant is already running.
+    pub fn add_tenant(
+        &self,
+        tenant: &str,
+        options: RunOptions,
+        limits: TenantLimits,
+    ) -> Result<(), Error> {
+        let mut tenants = self.tenants.lock().unwrap();
+        if tenants.contains_key(tenant) {
+            return Err(anyhow!("tenant {} is already running", tenant));
+        }
+        tenants.insert(
+            tenant.to_string(),
+            Arc::new(Tenant::start(options, limits)?),
+        );
+        Ok(())
+    }
+
+    /// Stops taking invocations for `tenant`, lets its queued ones finish
+    /// and joins its threads. Returns whether the tenant was running.
+    pub fn remove_tenant(&self, tenant: &str) -> bool {
+        let removed = self.tenants.lock().unwrap().remove(tenant);
+        // The pool shuts down once the last pending invocation lets go of it.
+        removed.is_some()
+    }
+
+    /// Queues a run of `function` for `tenant`. Fails straight away with
+    /// `RuntimeError::QueueFull` when the tenant's queue is full. Dropping
+    /// the future cancels the invocation.
+    pub fn submit(
+        &self,
+        tenant: &str,
+        function: PathBuf,
+        inputs: impl Into<Inputs>,
+    ) -> impl Future<Output = Result<Value, Error>> + Send + 'static {
+        let inputs = inputs.into();
+        let admitted = self.tenant(tenant).and_then(|started| {
+            let limits = started.limits;
+            started
+                .in_flight
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                    (n < limits.max_concurrency + limits.max_queued).then_some(n + 1)
+                })
+                .map_err(|_| RuntimeError::QueueFull {
+                    tenant: tenant.to_string(),
+                    limit: limits.max_queued,
+                })?;
+            Ok(Pending {
+                tenant: started,
+                cancellation: CancellationHandle::new(),
+                done: false,
+            })
+        });
+        async move {
+            let pending = admitted?;
+            let result = pending
+                .tenant
+                .pool
+                .submit_cancellable(function, inputs, pending.cancellation.clone())
+                .await;
+            pending.finish();
+            result
+        }
+    }
+
+    pub fn stats(&self) -> ManagerStats {
+        let tenants = self.tenants.lock().unwrap();
+        let mut stats = ManagerStats::default();
+        for (name, tenant) in tenants.iter() {
+            let in_flight = tenant.in_flight.load(Ordering::SeqCst);
+            let running = in_flight.min(tenant.limits.max_concurrency);
+            stats.active_isolates += running;
+            stats.queued += in_flight - running;
+            stats.tenants.insert(
+                name.clone(),
+                TenantStats {
+                    running,
+                    queued: in_flight - running,
+                    max_concurrency: tenant.limits.max_concurrency,
+                    max_queued: tenant.limits.max_queued,
+                },
+            );
+        }
+        stats
+    }
+
+    /// Stops every tenant, letting queued invocations finish.
+    pub fn shutdown(self) {
+        self.tenants.lock().unwrap().clear();
+    }
+
+    fn tenant(&self, tenant: &str) -> Result<Arc<Tenant>, Error> {
+        let mut tenants = self.tenants.lock().unwrap();
+        if let Some(tenant) = tenants.get(tenant) {
+            return Ok(tenant.clone());
+        }
+        log::debug!("starting tenant {}", tenant);
+        let started = Arc::new(Tenant::start(self.options.clone(), self.limits)?);
+        tenants.insert(tenant.to_string(), started.clone());
+        Ok(started)
+    }
+}
+
+impl Tenant {
+    fn start(options: RunOptions, limits: TenantLimits) -> Result<Self, Error> {
+        Ok(Self {
+            pool: ExecutorPool::with_options(limits.max_concurrency, options)?,
+            limits,
+            in_flight: AtomicUsize::new(0),
+        })
+    }
+}
+
+/// Counts an admitted invocation until it settles, cancelling it if the
+/// caller stops waiting first.
+struct Pending {
+    tenant: Arc<Tenant>,
+    cancellation: CancellationHandle,
+    done: bool,
+}
+
+impl Pending {
+    fn finish(mut self) {
+        self.done = true;
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if !self.done {
+            self.cancellation.cancel();
+        }
+        self.tenant.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Error};
+use serde_json::Value;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::cancel::CancellationHandle;
+use crate::inputs::Inputs;
+use crate::options::RunOptions;
+
+/// Script end of an invocation's message channel, set as
+/// `RunOptions::messages` by [`InvocationHandle::start`].
+#[derive(Clone)]
+pub struct MessagePort(Arc<Port>);
+
+struct Port {
+    inbox: Mutex<Option<mpsc::UnboundedReceiver<Value>>>,
+    outbox: mpsc::UnboundedSender<Value>,
+}
+
+impl fmt::Debug for MessagePort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MessagePort")
+    }
+}
+
+/// Channel ends handed to a worker. Messages from the host reach the first
+/// worker started with the port.
+pub(crate) struct HostMessages {
+    pub(crate) inbox: deno_core::AsyncRefCell<Option<mpsc::UnboundedReceiver<Value>>>,
+    pub(crate) outbox: mpsc::UnboundedSender<Value>,
+}
+
+impl MessagePort {
+    pub(crate) fn host_messages(&self) -> HostMessages {
+        HostMessages {
+            inbox: deno_core::AsyncRefCell::new(self.0.inbox.lock().unwrap().take()),
+            outbox: self.0.outbox.clone(),
+        }
+    }
+}
+
+/// A run in progress that the host exchanges messages with. Messages sent
+/// here reach the `host.onMessage` listeners of the script, and what the
+/// script passes to `host.emit` comes out of [`recv`](Self::recv).
+pub struct InvocationHandle {
+    to_script: mpsc::UnboundedSender<Value>,
+    from_script: mpsc::UnboundedReceiver<Value>,
+    result: oneshot::Receiver<Result<Value, Error>>,
+    cancellation: CancellationHandle,
+}
+
+impl InvocationHandle {
+    /// Starts running the function on a thread of its own.
+    pub fn start(
+        function: PathBuf,
+        inputs: impl Into<Inputs>,
+        mut options: RunOptions,
+    ) -> Result<Self, Error> {
+        let inputs = inputs.into();
+        let (to_script, inbox) = mpsc::unbounded_channel();
+        let (outbox, from_script) = mpsc::unbounded_channel();
+        options.messages = Some(MessagePort(Arc::new(Port {
+            inbox: Mutex::new(Some(inbox)),
+            outbox,
+        })));
+        let cancellation = options
+            .cancellation
+            .get_or_insert_with(CancellationHandle::new)
+            .clone();
+        let (sender, result) = oneshot::channel();
+        std::thread::Builder::new()
+            .name("invocation".to_string())
+            .spawn(move || {
+                let _ = sender.send(crate::run_with_options(function, inputs, options));
+            })?;
+        Ok(Self {
+            to_script,
+            from_script,
+            result,
+            cancellation,
+        })
+    }
+
+    /// Delivers `message` to the script's listeners. Fails once the run
+    /// is over.
+    pub fn send(&self, message: Value) -> Result<(), Error> {
+        self.to_script
+            .send(message)
+            .map_err(|_| anyhow!("the invocation has finished"))
+    }
+
+    /// Next message the script emitted, `None` once the run is over and
+    /// every message was received.
+    pub async fn recv(&mut self) -> Option<Value> {
+        self.from_script.recv().await
+    }
+
+    pub fn cancel(&self) {
+        self.cancellation.cancel();
+    }
+
+    /// Waits for the function's result. Messages not received yet are
+    /// dropped.
+    pub async fn result(self) -> Result<Value, Error> {
+        self.result
+            .await
+            .map_err(|_| anyhow!("function thread exited without a result"))?
+    }
+}
+
+impl fmt::Debug for InvocationHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InvocationHandle")
+            .field("cancelled", &self.cancellation.is_cancelled())
+            .finish()
+    }
+}
@@ -0,0 +1,739 @@
+use anyhow::{anyhow, bail, Context, Error};
+use base64::Engine;
+use deno_core::ModuleSpecifier;
+use deno_semver::npm::NpmPackageReqReference;
+use deno_semver::package::PackageReq;
+use deno_semver::Version;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Digest;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::{archive, fetch};
+
+/// Node builtins a package may import or require without the `node:`
+/// prefix, served by the runtime's node compat layer.
+const BUILTINS: &[&str] = &[
+    "assert",
+    "buffer",
+    "child_process",
+    "crypto",
+    "events",
+    "fs",
+    "fs/promises",
+    "http",
+    "https",
+    "net",
+    "os",
+    "path",
+    "process",
+    "querystring",
+    "stream",
+    "string_decoder",
+    "timers",
+    "tty",
+    "url",
+    "util",
+    "zlib",
+];
+
+/// Conditions of an `exports` entry, in the order they are preferred.
+const CONDITIONS: &[&str] = &["deno", "import", "module", "node", "require", "default"];
+
+/// npm packages unpacked on disk, one `name@version` directory each, for
+/// `npm:` imports. A version already unpacked is used when it satisfies
+/// the import, the registry is only asked for packages that are missing.
+///
+/// CommonJS files in the packages are served as ES modules: literal
+/// `require` calls become imports, and `exports.name` assignments become
+/// named exports next to the default export of `module.exports`.
+#[derive(Debug, Clone)]
+pub struct NpmCache {
+    dir: PathBuf,
+    registry: ModuleSpecifier,
+}
+
+#[derive(Deserialize)]
+struct Packument {
+    #[serde(rename = "dist-tags", default)]
+    dist_tags: HashMap<String, String>,
+    #[serde(default)]
+    versions: HashMap<String, PackageVersion>,
+}
+
+#[derive(Deserialize)]
+struct PackageVersion {
+    dist: Dist,
+}
+
+#[derive(Deserialize)]
+struct Dist {
+    tarball: String,
+    integrity: Option<String>,
+}
+
+impl NpmCache {
+    /// Cache in `$XDG_CACHE_HOME/experimental_runtime/npm`, falling back to
+    /// `~/.cache` when the variable is unset, fetching from the public
+    /// registry.
+    pub fn new() -> Result<Self, Error> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .ok_or_else(|| anyhow!("no cache directory, neither XDG_CACHE_HOME nor HOME is set"))?;
+        Ok(Self::in_dir(base.join("experimental_runtime/npm")))
+    }
+
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            registry: ModuleSpecifier::parse("https://registry.npmjs.org/").unwrap(),
+        }
+    }
+
+    /// Fetches packages from `registry` instead of the public registry.
+    pub fn with_registry(mut self, mut registry: ModuleSpecifier) -> Self {
+        if !registry.path().ends_with('/') {
+            registry.set_path(&format!("{}/", registry.path()));
+        }
+        self.registry = registry;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// File the `npm:` specifier points at, unpacking its package first
+    /// when needed.
+    pub(crate) async fn resolve(
+        &self,
+        client: &fetch::HttpClient,
+        specifier: &ModuleSpecifier,
+    ) -> Result<ModuleSpecifier, Error> {
+        let reference = NpmPackageReqReference::from_specifier(specifier)?;
+        let req = reference.req();
+        let root = match self.unpacked(req)? {
+            Some(root) => root,
+            None => self.install(client, req).await?,
+        };
+        let path = entry(&root, reference.sub_path())?;
+        let path = std::fs::canonicalize(&path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        ModuleSpecifier::from_file_path(&path)
+            .map_err(|_| anyhow!("invalid module path {}", path.display()))
+    }
+
+    /// Maps a bare specifier imported from inside a package to the
+    /// dependency it names, at the version range the package asks for.
+    /// `None` when the import is not a bare one from an npm package.
+    pub(crate) fn resolve_bare(
+        &self,
+        specifier: &str,
+        referrer: &str,
+    ) -> Result<Option<ModuleSpecifier>, Error> {
+        if !is_bare(specifier) {
+            return Ok(None);
+        }
+        let Some(root) = ModuleSpecifier::parse(referrer)
+            .ok()
+            .filter(|referrer| referrer.scheme() == "file")
+            .and_then(|referrer| referrer.to_file_path().ok())
+            .and_then(|path| self.package_root(&path))
+        else {
+            return Ok(None);
+        };
+        let specifier = match builtin(specifier) {
+            Some(builtin) => builtin,
+            None => {
+                let (name, sub_path) = split_package(specifier);
+                match dependency_range(&read_manifest(&root)?, name) {
+                    Some(range) => format!("npm:{}@{}{}", name, range, sub_path),
+                    None => format!("npm:{}{}", name, sub_path),
+                }
+            }
+        };
+        Ok(Some(ModuleSpecifier::parse(&specifier)?))
+    }
+
+    /// Turns a CommonJS file of a cached package into an ES module. Other
+    /// files are returned as they are.
+    pub(crate) fn to_esm(&self, path: &Path, code: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let Some(root) = self.package_root(path) else {
+            return Ok(code);
+        };
+        let source = String::from_utf8_lossy(&code);
+        if !is_commonjs(path, &source) {
+            return Ok(code);
+        }
+        log::debug!("wrapping commonjs module {}", path.display());
+        let manifest = read_manifest(&root)?;
+        Ok(commonjs_wrapper(path, &source, &manifest)?.into_bytes())
+    }
+
+    /// Highest version already unpacked that satisfies `req`. Tags always
+    /// go to the registry.
+    fn unpacked(&self, req: &PackageReq) -> Result<Option<PathBuf>, Error> {
+        if req.version_req.tag().is_some() {
+            return Ok(None);
+        }
+        let (parent, base) = self.package_dir(&req.name);
+        let Ok(entries) = std::fs::read_dir(&parent) else {
+            return Ok(None);
+        };
+        let prefix = format!("{}@", base);
+        let mut best: Option<(Version, PathBuf)> = None;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let Some(version) = name
+                .strip_prefix(&prefix)
+                .and_then(|version| Version::parse_from_npm(version).ok())
+            else {
+                continue;
+            };
+            if req.version_req.matches(&version)
+                && best.as_ref().is_none_or(|(best, _)| version > *best)
+            {
+                best = Some((version, entry.path()));
+            }
+        }
+        Ok(best.map(|(_, path)| path))
+    }
+
+    async fn install(
+        &self,
+        client: &fetch::HttpClient,
+        req: &PackageReq,
+    ) -> Result<PathBuf, Error> {
+        let url = self.packument_url(&req.name)?;
+        log::debug!("fetching npm package metadata {}", url);
+        let packument: Packument = serde_json::from_slice(&fetch::fetch(client, &url).await?.body)
+            .with_context(|| format!("invalid npm metadata for {}", req.name))?;
+        let version = select_version(&packument, req)?;
+        let dist = &packument
+            .versions
+            .get(&version)
+            .ok_or_else(|| anyhow!("{}@{} is missing from the registry", req.name, version))?
+            .dist;
+
+        let (parent, base) = self.package_dir(&req.name);
+        let root = parent.join(format!("{}@{}", base, version));
+        if root.is_dir() {
+            return Ok(root);
+        }
+        let tarball = ModuleSpecifier::parse(&dist.tarball)?;
+        log::debug!(
+            "fetching npm package {}@{} from {}",
+            req.name,
+            version,
+            tarball
+        );
+        let body = fetch::fetch(client, &tarball).await?.body;
+        match &dist.integrity {
+            Some(integrity) => check_integrity(&body, integrity)
+                .with_context(|| format!("could not verify {}@{}", req.name, version))?,
+            None => log::warn!("{}@{} has no integrity to check", req.name, version),
+        }
+        let entries = archive::gunzip(&body)
+            .and_then(|data| archive::read_tar(&data))
+            .with_context(|| format!("invalid npm package {}@{}", req.name, version))?;
+
+        // Unpacked next to the final directory and renamed into place, so
+        // a half-written package is never picked up.
+        let staging = parent.join(format!(".tmp-{}@{}-{}", base, version, std::process::id()));
+        let unpacked = entries.into_iter().try_for_each(|(name, data)| {
+            let Some(name) = archive::entry_path(&name)? else {
+                return Ok(());
+            };
+            // Packages are published under a `package/` directory.
+            let Some((_, name)) = name.split_once('/') else {
+                return Ok(());
+            };
+            let path = staging.join(name);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(&path, data)
+                .with_context(|| format!("could not write {}", path.display()))
+        });
+        if let Err(e) = unpacked {
+            let _ = std::fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+        if std::fs::rename(&staging, &root).is_err() {
+            // Another process unpacked it first.
+            std::fs::remove_dir_all(&staging)?;
+            if !root.is_dir() {
+                bail!(
+                    "could not unpack {}@{} into {}",
+                    req.name,
+                    version,
+                    root.display()
+                );
+            }
+        }
+        Ok(root)
+    }
+
+    /// Where the registry serves the metadata of package `name`.
+    pub(crate) fn packument_url(&self, name: &str) -> Result<ModuleSpecifier, Error> {
+        Ok(self.registry.join(&name.replace('/', "%2f"))?)
+    }
+
+    /// Directory holding the versions of `name`, and the file name prefix
+    /// they share. Scoped packages get a directory per scope.
+    fn package_dir(&self, name: &str) -> (PathBuf, String) {
+        match name.split_once('/') {
+            Some((scope, base)) => (self.dir.join(scope), base.to_string()),
+            None => (self.dir.clone(), name.to_string()),
+        }
+    }
+
+    /// Unpacked package `path` belongs to, if it is in the cache.
+    fn package_root(&self, path: &Path) -> Option<PathBuf> {
+        let dir = std::fs::canonicalize(&self.dir).unwrap_or_else(|_| self.dir.clone());
+        let relative = path.strip_prefix(&dir).ok()?;
+        let mut components = relative.components();
+        let first = components.next()?.as_os_str().to_string_lossy();
+        if first.starts_with('@') {
+            let second = components.next()?;
+            Some(dir.join(&*first).join(second))
+        } else {
+            Some(dir.join(&*first))
+        }
+    }
+}
+
+fn select_version(packument: &Packument, req: &PackageReq) -> Result<String, Error> {
+    if let Some(tag) = req.version_req.tag() {
+        let version = packument
+            .dist_tags
+            .get(tag)
+            .ok_or_else(|| anyhow!("{} has no {} tag", req.name, tag))?;
+        // The version names a directory, so it has to be one.
+        if Version::parse_from_npm(version).is_err() || !packument.versions.contains_key(version) {
+            bail!(
+                "the {} tag of {} points at {:?}, which is not one of its versions",
+                tag,
+                req.name,
+                version
+            );
+        }
+        return Ok(version.clone());
+    }
+    // Like npm, the latest tag wins when it satisfies the range.
+    if let Some(latest) = packument.dist_tags.get("latest") {
+        if Version::parse_from_npm(latest).is_ok_and(|version| req.version_req.matches(&version)) {
+            return Ok(latest.clone());
+        }
+    }
+    packument
+        .versions
+        .keys()
+        .filter_map(|text| Some((Version::parse_from_npm(text).ok()?, text)))
+        .filter(|(version, _)| req.version_req.matches(version))
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, text)| text.clone())
+        .ok_or_else(|| anyhow!("no version of {} matches {}", req.name, req.version_req))
+}
+
+/// Checks an SRI `integrity` value, such as `sha512-...`.
+fn check_integrity(body: &[u8], integrity: &str) -> Result<(), Error> {
+    let (algorithm, expected) = integrity
+        .split_once('-')
+        .ok_or_else(|| anyhow!("invalid integrity {}", integrity))?;
+    let actual = match algorithm {
+        "sha512" => sha2::Sha512::digest(body).to_vec(),
+        "sha256" => sha2::Sha256::digest(body).to_vec(),
+        algorithm => bail!("unsupported integrity algorithm {}", algorithm),
+    };
+    if base64::engine::general_purpose::STANDARD.encode(actual) != expected {
+        bail!("integrity mismatch, expected {}", integrity);
+    }
+    Ok(())
+}
+
+fn read_manifest(root: &Path) -> Result<Value, Error> {
+    let path = root.join("package.json");
+    let text =
+        std::fs::read(&path).with_context(|| format!("could not read {}", path.display()))?;
+    serde_json::from_slice(&text).with_context(|| format!("invalid {}", path.display()))
+}
+
+/// Module a package or one of its subpaths points at, through `exports`,
+/// then `module` and `main`.
+fn entry(root: &Path, sub_path: Option<&str>) -> Result<PathBuf, Error> {
+    let manifest = read_manifest(root)?;
+    let key = match sub_path {
+        Some(sub_path) => format!("./{}", sub_path),
+        None => ".".to_string(),
+    };
+    if let Some(exports) = manifest.get("exports") {
+        let target = match exports {
+            Value::Object(map) if map.keys().any(|key| key.starts_with('.')) => {
+                export_target(map, &key)
+            }
+            exports if key == "." => conditional(exports),
+            _ => None,
+        };
+        return target
+            .map(|target| root.join(target))
+            .ok_or_else(|| anyhow!("{} is not exported by {}", key, root.display()));
+    }
+    let target = match sub_path {
+        Some(sub_path) => sub_path.to_string(),
+        None => ["module", "main"]
+            .iter()
+            .find_map(|field| manifest.get(*field)?.as_str())
+            .unwrap_or("index.js")
+            .to_string(),
+    };
+    complete(&root.join(target)).ok_or_else(|| anyhow!("{} has no module {}", root.display(), key))
+}
+
+/// Target of `key` in an `exports` map, including `./dir/*` patterns.
+fn export_target(map: &serde_json::Map<String, Value>, key: &str) -> Option<String> {
+    if let Some(target) = map.get(key) {
+        return conditional(target);
+    }
+    map.iter().find_map(|(pattern, target)| {
+        let (prefix, suffix) = pattern.split_once('*')?;
+        let matched = key.strip_prefix(prefix)?.strip_suffix(suffix)?;
+        Some(conditional(target)?.replace('*', matched))
+    })
+}
+
+fn conditional(target: &Value) -> Option<String> {
+    match target {
+        Value::String(target) => Some(target.clone()),
+        Value::Array(targets) => targets.iter().find_map(conditional),
+        Value::Object(map) => CONDITIONS
+            .iter()
+            .find_map(|condition| conditional(map.get(*condition)?)),
+        _ => None,
+    }
+}
+
+/// Node's lookup of a file required without its extension.
+fn complete(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_string_lossy();
+    let candidates = [
+        path.to_path_buf(),
+        path.with_file_name(format!("{}.js", name)),
+        path.with_file_name(format!("{}.json", name)),
+        path.with_file_name(format!("{}.cjs", name)),
+        path.join("index.js"),
+        path.join("index.json"),
+    ];
+    candidates.into_iter().find(|path| path.is_file())
+}
+
+fn is_bare(specifier: &str) -> bool {
+    !["./", "../", "/"]
+        .iter()
+        .any(|prefix| specifier.starts_with(prefix))
+        && ModuleSpecifier::parse(specifier).is_err()
+}
+
+fn builtin(specifier: &str) -> Option<String> {
+    let name = specifier.strip_prefix("node:").unwrap_or(specifier);
+    BUILTINS.contains(&name).then(|| format!("node:{}", name))
+}
+
+/// Package name and `/subpath` of a bare specifier.
+fn split_package(specifier: &str) -> (&str, &str) {
+    let skip = if specifier.starts_with('@') { 2 } else { 1 };
+    match specifier.match_indices('/').nth(skip - 1) {
+        Some((index, _)) => specifier.split_at(index),
+        None => (specifier, ""),
+    }
+}
+
+fn dependency_range<'a>(manifest: &'a Value, name: &str) -> Option<&'a str> {
+    ["dependencies", "peerDependencies"]
+        .iter()
+        .find_map(|field| manifest.get(*field)?.get(name)?.as_str())
+}
+
+/// `.cjs` files, and `.js` files outside `"type": "module"` packages that
+/// don't use import or export statements.
+fn is_commonjs(path: &Path, source: &str) -> bool {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("cjs") => true,
+        Some("js") => {
+            let module_type = path.ancestors().skip(1).find_map(|dir| {
+                let manifest = read_manifest(dir).ok()?;
+                Some(manifest.get("type")?.as_str() == Some("module"))
+            });
+            !module_type.unwrap_or(false) && !has_module_syntax(source)
+        }
+        _ => false,
+    }
+}
+
+fn has_module_syntax(source: &str) -> bool {
+    source.lines().any(|line| {
+        let line = line.trim_start();
+        [
+            "import ", "import{", "import\"", "import'", "export ", "export{",
+        ]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+    })
+}
+
+fn commonjs_wrapper(path: &Path, source: &str, manifest: &Value) -> Result<String, Error> {
+    let dir = path.parent().unwrap();
+    let source = match source.strip_prefix("#!") {
+        Some(rest) => rest.split_once('\n').map_or("", |(_, rest)| rest),
+        None => source,
+    };
+    let mut imports = String::new();
+    let mut required = vec![];
+    for (index, specifier) in literal_requires(source).into_iter().enumerate() {
+        let target = if specifier.starts_with("./") || specifier.starts_with("../") {
+            let Some(target) = complete(&dir.join(&specifier)) else {
+                continue;
+            };
+            ModuleSpecifier::from_file_path(&target)
+                .map_err(|_| anyhow!("invalid module path {}", target.display()))?
+                .to_string()
+        } else if let Some(builtin) = builtin(&specifier) {
+            builtin
+        } else if dependency_range(manifest, split_package(&specifier).0).is_some() {
+            specifier.clone()
+        } else {
+            // Optional requires are left to fail when they run.
+            continue;
+        };
+        let attributes = if target.ends_with(".json") {
+            " with { type: \"json\" }"
+        } else {
+            ""
+        };
+        imports.push_str(&format!(
+            "import * as __require{} from {}{};\n",
+            index,
+            serde_json::to_string(&target)?,
+            attributes
+        ));
+        required.push(format!(
+            "{}: __interop(__require{})",
+            serde_json::to_string(&specifier)?,
+            index
+        ));
+    }
+
+    let filename = path.to_string_lossy();
+    let dirname = dir.to_string_lossy();
+    let names = exported_names(source);
+    let mut exports = String::new();
+    for (index, name) in names.iter().enumerate() {
+        exports.push_str(&format!(
+            "const __export{} = __module.exports[{}];\n",
+            index,
+            serde_json::to_string(name)?
+        ));
+    }
+    if !names.is_empty() {
+        let list = names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                Ok(format!(
+                    "__export{} as {}",
+                    index,
+                    serde_json::to_string(name)?
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        exports.push_str(&format!("export {{ {} }};\n", list.join(", ")));
+    }
+
+    Ok(format!(
+        r#"{imports}const __interop = (ns) =>
+  "default" in ns && Object.keys(ns).every((key) => key === "default" || ns.default?.[key] === ns[key]) ? ns.default : ns;
+const __required = {{ {required} }};
+const __filename = {filename};
+const __dirname = {dirname};
+const __module = {{ exports: {{}} }};
+function __require(specifier) {{
+  if (Object.hasOwn(__required, specifier)) return __required[specifier];
+  throw new Error(`cannot require ${{specifier}} from ${{__filename}}, only literal requires of dependencies are supported`);
+}}
+(function (exports, require, module, __filename, __dirname, global) {{
+{source}
+}}).call(__module.exports, __module.exports, __require, __module, __filename, __dirname, globalThis);
+export default __module.exports;
+{exports}"#,
+        required = required.join(", "),
+        filename = serde_json::to_string(&filename)?,
+        dirname = serde_json::to_string(&dirname)?,
+    ))
+}
+
+/// Specifiers of `require("...")` calls with a plain string literal.
+fn literal_requires(source: &str) -> Vec<String> {
+    let mut specifiers = vec![];
+    for (index, _) in source.match_indices("require(") {
+        let before = source[..index].chars().next_back();
+        if before.is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '$' || c == '.') {
+            continue;
+        }
+        let rest = source[index + "require(".len()..].trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        let Some((specifier, rest)) = rest[1..].split_once(quote) else {
+            continue;
+        };
+        if specifier.contains(['\\', '\n']) || !rest.trim_start().starts_with(')') {
+            continue;
+        }
+        if !specifiers.iter().any(|known| known == specifier) {
+            specifiers.push(specifier.to_string());
+        }
+    }
+    specifiers
+}
+
+/// Names assigned as `exports.name = ...`, `module.exports.name = ...` or
+/// defined with `Object.defineProperty(exports, "name", ...)`.
+fn exported_names(source: &str) -> Vec<String> {
+    let mut names = vec![];
+    let identifier = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    for (index, _) in source.match_indices("exports.") {
+        let before = &source[..index];
+        if before.ends_with(|c: char| identifier(c) || c == '.') && !before.ends_with("module.") {
+            continue;
+        }
+        let rest = &source[index + "exports.".len()..];
+        let end = rest.find(|c: char| !identifier(c)).unwrap_or(rest.len());
+        let after = rest[end..].trim_start();
+        if end > 0 && after.starts_with('=') && !after.starts_with("==") {
+            names.push(rest[..end].to_string());
+        }
+    }
+    for (index, _) in source.match_indices("Object.defineProperty(exports,") {
+        let rest = source[index + "Object.defineProperty(exports,".len()..].trim_start();
+        let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+            continue;
+        };
+        if let Some((name, _)) = rest[1..].split_once(quote) {
+            names.push(name.to_string());
+        }
+    }
+    let mut unique = vec![];
+    for name in names {
+        if name != "default" && name != "__esModule" && !unique.contains(&name) {
+            unique.push(name);
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn packument() -> Packument {
+        let version = json!({ "dist": { "tarball": "https://registry.test/a.tgz" } });
+        serde_json::from_value(json!({
+            "dist-tags": {
+                "latest": "1.2.0",
+                "next": "2.0.0-beta.1",
+                "escape": "../../../outside",
+                "gone": "3.0.0",
+            },
+            "versions": {
+                "1.1.0": version,
+                "1.2.0": version,
+                "1.3.0": version,
+                "2.0.0-beta.1": version,
+            },
+        }))
+        .unwrap()
+    }
+
+    fn select(req: &str) -> Result<String, Error> {
+        select_version(&packument(), &PackageReq::from_str(req).unwrap())
+    }
+
+    #[test]
+    fn selects_tags_and_ranges() {
+        assert_eq!(select("a@next").unwrap(), "2.0.0-beta.1");
+        // The latest tag wins when it satisfies the range.
+        assert_eq!(select("a@^1.1").unwrap(), "1.2.0");
+        assert_eq!(select("a@~1.3").unwrap(), "1.3.0");
+        assert!(select("a@^4").is_err());
+    }
+
+    #[test]
+    fn rejects_tags_that_are_not_versions() {
+        assert!(select("a@escape").is_err());
+        assert!(select("a@gone").is_err());
+        assert!(select("a@missing").is_err());
+    }
+
+    #[test]
+    fn checks_tarball_integrity() {
+        let sha256 = "sha256-LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=";
+        assert!(check_integrity(b"hello", sha256).is_ok());
+        assert!(check_integrity(b"hello!", sha256).is_err());
+        assert!(check_integrity(b"hello", "md5-XUFAKrxLKna5cZ2REBfFkg==").is_err());
+        assert!(check_integrity(b"hello", "sha512").is_err());
+    }
+
+    #[test]
+    fn finds_commonjs_requires_and_exports() {
+        let source = r#"
+const a = require("./a");
+const b = require('dep/sub');
+const c = obj.require("skipped");
+const d = require(name);
+exports.one = 1;
+module.exports.two = 2;
+Object.defineProperty(exports, "three", { value: 3 });
+exports.__esModule = true;
+if (exports.one == 1) {}
+"#;
+        assert_eq!(literal_requires(source), ["./a", "dep/sub"]);
+        assert_eq!(exported_names(source), ["one", "two", "three"]);
+        assert!(!has_module_syntax(source));
+        assert!(has_module_syntax("const a = 1;\nexport { a };"));
+    }
+
+    #[test]
+    fn resolves_entries_through_exports_maps() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("dist/utils")).unwrap();
+        for file in ["dist/index.mjs", "dist/utils/pad.js", "legacy.js"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        std::fs::write(
+            root.join("package.json"),
+            json!({
+                "main": "legacy.js",
+                "exports": {
+                    ".": { "import": "./dist/index.mjs", "require": "./legacy.js" },
+                    "./utils/*": "./dist/utils/*.js",
+                },
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert_eq!(entry(root, None).unwrap(), root.join("./dist/index.mjs"));
+        assert_eq!(
+            entry(root, Some("utils/pad")).unwrap(),
+            root.join("./dist/utils/pad.js")
+        );
+        let error = entry(root, Some("legacy.js")).unwrap_err();
+        assert!(
+            error.to_string().contains("./legacy.js is not exported"),
+            "{}",
+            error
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use deno_core::ModuleSpecifier;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cancel::CancellationHandle;
+use crate::console::ConsoleSink;
+use crate::determinism::Determinism;
+use crate::embedded::EmbeddedModules;
+use crate::extract::{OutputFormat, ValueHook};
+use crate::host_api::SharedHostApi;
+use crate::import_map::ImportMap;
+use crate::permissions::RuntimePermissions;
+use crate::redact::RedactOptions;
+use crate::runtime::LoaderFactory;
+use crate::sandbox::FsSandbox;
+use crate::snapshot::Snapshot;
+use crate::stats::{MetricsSink, StatsCollector};
+use crate::transpile::TranspileCache;
+use crate::uncaught::UncaughtHook;
+use crate::warning::{WarningCode, WarningHook};
+
+#[derive(Debug, Clone, Default)]
+pub enum Entrypoint {
+    /// Call the `main` export with the inputs and use what it returns. A
+    /// module without `main` has its default export called instead. Objects
+    /// and classes, instantiated without arguments, are called through
+    /// their `handler` method.
+    #[default]
+    MainFunction,
+    /// Use the default export as the result, once top-level await settled.
+    DefaultExportValue,
+    /// Use the named export as the result, once top-level await settled.
+    ExportValue(String),
+    /// Call the named export with the inputs, as `main` would be.
+    Handler(String),
+    /// Call the named export, `default` included, with positional
+    /// arguments instead of the inputs.
+    Function { name: String, args: Vec<Value> },
+}
+
+impl Entrypoint {
+    pub(crate) fn export_name(&self) -> &str {
+        match self {
+            Entrypoint::MainFunction => "main",
+            Entrypoint::DefaultExportValue => "default",
+            Entrypoint::ExportValue(name)
+            | Entrypoint::Handler(name)
+            | Entrypoint::Function { name, .. } => name,
+        }
+    }
+
+    /// Arguments of the call, `None` standing for the inputs, or `None`
+    /// altogether when the export is used as a value.
+    pub(crate) fn arguments(&self) -> Option<Option<&[Value]>> {
+        match self {
+            Entrypoint::MainFunction | Entrypoint::Handler(_) => Some(None),
+            Entrypoint::Function { args, .. } => Some(Some(args)),
+            Entrypoint::DefaultExportValue | Entrypoint::ExportValue(_) => None,
+        }
+    }
+}
+
+/// What to do about ops and timers the function left running after its
+/// result settled. Those are cut short when the worker is torn down.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DanglingWork {
+    #[default]
+    Ignore,
+    Warn,
+    /// Fail with `RuntimeError::DanglingWork`.
+    Fail,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    pub entrypoint: Entrypoint,
+    /// Load the function from modules compiled into the binary, the
+    /// function path names the entry among them.
+    pub embedded: Option<EmbeddedModules>,
+    /// Modules served from memory, keyed by specifier, such as
+    /// `virtual:config.js` or an `https:` URL to stand in for.
+    pub virtual_modules: HashMap<ModuleSpecifier, String>,
+    /// Maps bare specifiers, and rewrites others, before imports resolve.
+    pub import_map: Option<ImportMap>,
+    /// Keeps compiled TypeScript and JSX on disk between runs.
+    pub transpile_cache: Option<TranspileCache>,
+    /// Keeps http(s) imports on disk between runs.
+    #[cfg(feature = "net-loader")]
+    pub module_cache: Option<crate::cache::ModuleCache>,
+    /// Hosts remote modules may be fetched from.
+    #[cfg(feature = "net-loader")]
+    pub import_policy: Option<crate::import_policy::ImportPolicy>,
+    /// Timeouts, retries, size limit, proxy and certificates for fetching
+    /// remote modules.
+    #[cfg(feature = "net-loader")]
+    pub http: Option<crate::fetch::HttpOptions>,
+    /// Where `npm:` imports are unpacked, the default npm cache when unset.
+    #[cfg(feature = "net-loader")]
+    pub npm_cache: Option<crate::npm::NpmCache>,
+    /// Deno lockfile with the SHA-256 expected for each remote module,
+    /// keyed by the URL it was served from. A module that is missing or
+    /// doesn't match fails to load.
+    #[cfg(feature = "net-loader")]
+    pub lockfile: Option<PathBuf>,
+    /// Record the hashes of fetched modules into `lockfile` instead of
+    /// checking them.
+    #[cfg(feature = "net-loader")]
+    pub lockfile_write: bool,
+    /// Resolves `s3://bucket/key` imports to fetchable URLs.
+    #[cfg(feature = "s3")]
+    pub s3: Option<crate::s3::S3Resolver>,
+    /// Let `git+https:` imports name a branch or tag instead of a full
+    /// commit hash. It is resolved once per run.
+    #[cfg(feature = "git")]
+    pub allow_mutable_git_refs: bool,
+    /// Refuse file modules reached through symbolic links instead of loading
+    /// their target.
+    pub deny_symlinks: bool,
+    /// JSON Schema the inputs must satisfy before the function is called,
+    /// as an object keyed by input name.
+    pub input_schema: Option<Value>,
+    /// JSON Schema the function's return value must satisfy.
+    pub output_schema: Option<Value>,
+    /// Reject properties that the schemas do not declare.
+    pub strict_schema: bool,
+    /// Keep the rejected value on `RuntimeError::OutputValidation` for debugging.
+    pub keep_invalid_output: bool,
+    pub output_format: OutputFormat,
+    /// Consulted for values the output format can't convert on its own.
+    pub value_hook: Option<ValueHook>,
+    /// Nesting limit for the returned value, 512 by default.
+    pub max_depth: Option<usize>,
+    /// Upper bound on the size of the inputs, checked before a worker is
+    /// created. 32 MiB by default, `usize::MAX` for no limit.
+    pub max_input_bytes: Option<usize>,
+    /// Upper bound on the size of the serialized result.
+    pub max_output_bytes: Option<usize>,
+    /// Host files exposed to the script as `host.files[name]`, without
+    /// granting read access to their paths.
+    pub files: HashMap<String, PathBuf>,
+    /// Environment `Deno.env` shows instead of the host's. `Deno.env` is
+    /// read-only then, and variables missing from the map read as
+    /// `undefined`. With `permissions`, env access is limited to these keys.
+    pub env: Option<HashMap<String, String>>,
+    /// Confines the script's file APIs to a directory of their own, with
+    /// read and write access to all of it.
+    pub fs_sandbox: Option<FsSandbox>,
+    /// Channel behind `host.onMessage` and `host.emit`, set by
+    /// [`InvocationHandle::start`](crate::InvocationHandle::start).
+    pub messages: Option<crate::messages::MessagePort>,
+    /// Size cap for `host.files[name].text()` and `bytes()`, 16 MiB by default.
+    pub file_read_limit: Option<usize>,
+    /// Host object whose methods scripts call as `host.api.name(...)`.
+    pub host_api: Option<SharedHostApi>,
+    pub dangling_work: DanglingWork,
+    /// Captures console calls as structured events instead of printing them.
+    pub console: Option<ConsoleSink>,
+    /// Size cap for the arguments of one console event, 64 KiB by default.
+    pub console_event_limit: Option<usize>,
+    /// Receives errors that escape a warm worker outside of a call's result.
+    /// They are logged at error level when unset. Either way the worker is
+    /// not reused as it is.
+    pub on_uncaught: Option<UncaughtHook>,
+    /// Receives non-fatal issues noticed during the run. They are logged at
+    /// warning level when unset.
+    pub on_warning: Option<WarningHook>,
+    /// Warnings that fail the run with `RuntimeError::DeniedWarning`.
+    pub deny_warnings: Vec<WarningCode>,
+    /// Wall-clock limit for evaluating the module and, separately, for each
+    /// call. Exceeding it fails with `RuntimeError::Timeout`.
+    pub timeout: Option<Duration>,
+    /// Bounds the CPU work of evaluating the module and, separately, of
+    /// each call: a unit of fuel burns for every millisecond the script
+    /// spends running, waiting on I/O aside. Running out fails with
+    /// `RuntimeError::BudgetExceeded`, however generous `timeout` is.
+    pub fuel: Option<u64>,
+    /// Starts workers from a startup snapshot instead of evaluating the
+    /// runtime's JS.
+    pub snapshot: Option<Snapshot>,
+    /// Receives timings and resource usage of the run.
+    pub stats: Option<StatsCollector>,
+    /// Starts the V8 inspector on this address, for Chrome DevTools to
+    /// attach through `chrome://inspect`. Set no `timeout` while stepping
+    /// through code, the limit keeps running while paused.
+    pub inspect: Option<std::net::SocketAddr>,
+    /// With `inspect`, waits for a debugger to attach and pauses right
+    /// before the entrypoint is called.
+    pub inspect_brk: bool,
+    /// Receives the stats of every invocation as it settles.
+    pub metrics: Option<Arc<dyn MetricsSink>>,
+    /// Creates the module loader of each worker, in place of the network
+    /// loader. Embedded modules still fall back to it.
+    pub module_loader: Option<LoaderFactory>,
+    /// Layers asked for modules before the module loader.
+    pub loader_stack: Option<crate::loader_stack::LoaderStack>,
+    /// Stops the run when cancelled from another thread.
+    pub cancellation: Option<CancellationHandle>,
+    /// Seeds `Math.random`, runs `Date` on a virtual clock and blocks other
+    /// sources of nondeterminism, for reproducible runs.
+    pub determinism: Option<Determinism>,
+    /// Records what `fetch` and `host.api` return to a trace, or serves
+    /// them from one without reaching the network or the host.
+    pub trace: Option<crate::trace::TraceMode>,
+    /// W3C trace context of the request the run serves, continued by the
+    /// run's `invocation` span. Set per request by `InvokeServer` and
+    /// `GrpcServer` from its headers.
+    #[cfg(feature = "otel")]
+    pub trace_context: Option<crate::otel::TraceContext>,
+    /// Adds the `traceparent` and `tracestate` headers of the run's span to
+    /// the script's fetches that don't set them.
+    #[cfg(feature = "otel")]
+    pub propagate_trace_context: bool,
+    /// Runs the function in a child process instead, so a crash of the
+    /// engine fails the run with `RuntimeError::WorkerCrashed`. Used by
+    /// `run_with_options`, `run` and the `run_*` functions going through
+    /// it, and by `run_with_values`, `run_typed`, `run_many` and
+    /// `run_to_writer`.
+    pub subprocess: Option<crate::subprocess::Subprocess>,
+    /// Upper bound on the isolate's heap in bytes. Reaching it stops the
+    /// script with `RuntimeError::HeapLimitExceeded`.
+    pub max_heap_size: Option<usize>,
+    /// What the script may access. Unset keeps the permissive defaults of
+    /// `run_insecure`.
+    pub permissions: Option<RuntimePermissions>,
+    /// Secret values scrubbed from errors before they are returned.
+    pub redact: RedactOptions,
+}
@@ -0,0 +1,306 @@
+use anyhow::{anyhow, Context, Error};
+use opentelemetry::metrics::{Counter, Histogram, Meter, MeterProvider, ObservableGauge};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig, WithTonicConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::runtime;
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::manager::{ManagerStats, TenantStats};
+use crate::stats::{CacheStats, Invocation, MetricsSink};
+
+const SCOPE: &str = "experimental_runtime";
+
+/// W3C trace context of an incoming request, which the `invocation` span
+/// of the run it starts continues. See `RunOptions::trace_context`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceContext {
+    pub traceparent: String,
+    pub tracestate: Option<String>,
+}
+
+impl TraceContext {
+    /// From the `traceparent` and `tracestate` headers of a request,
+    /// looked up by `header`. `None` without a `traceparent`.
+    pub fn from_headers<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Option<Self> {
+        Some(Self {
+            traceparent: header("traceparent")?.to_string(),
+            tracestate: header("tracestate").map(String::from),
+        })
+    }
+
+    /// Context of the current span, `None` when it isn't exported.
+    pub(crate) fn current() -> Option<Self> {
+        let mut headers = HashMap::new();
+        TraceContextPropagator::new()
+            .inject_context(&tracing::Span::current().context(), &mut headers);
+        Self::from_headers(|name| headers.get(name).map(String::as_str))
+    }
+
+    /// Headers carrying this context to another service.
+    pub(crate) fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![("traceparent".to_string(), self.traceparent.clone())];
+        if let Some(tracestate) = &self.tracestate {
+            headers.push(("tracestate".to_string(), tracestate.clone()));
+        }
+        headers
+    }
+
+    /// Makes `span` a child of the remote span. A malformed `traceparent`
+    /// leaves it a root.
+    pub(crate) fn set_parent(&self, span: &tracing::Span) {
+        let headers: HashMap<_, _> = self.headers().into_iter().collect();
+        span.set_parent(TraceContextPropagator::new().extract(&headers));
+    }
+}
+
+/// Exports the spans of runs and the metrics of invocations through
+/// OpenTelemetry. Spans reach it through [`layer`](Self::layer), metrics
+/// through [`metrics`](Self::metrics) set as `RunOptions::metrics`.
+pub struct Telemetry {
+    tracer_provider: TracerProvider,
+    meter_provider: SdkMeterProvider,
+    /// Gauges stop being observed once dropped.
+    gauges: Mutex<Vec<ObservableGauge<u64>>>,
+}
+
+impl Telemetry {
+    /// Exports to the OTLP gRPC collector at `endpoint`, sending `headers`
+    /// with every export. Must be called within a tokio runtime, which
+    /// runs the exports in the background.
+    pub fn otlp(endpoint: &str, headers: &HashMap<String, String>) -> Result<Self, Error> {
+        let mut metadata = tonic::metadata::MetadataMap::new();
+        for (name, value) in headers {
+            let name = tonic::metadata::MetadataKey::from_bytes(name.to_lowercase().as_bytes())
+                .with_context(|| format!("invalid otlp header name {:?}", name))?;
+            let value = value
+                .parse()
+                .with_context(|| format!("invalid value for otlp header {}", name))?;
+            metadata.insert(name, value);
+        }
+        let spans = SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .with_metadata(metadata.clone())
+            .build()
+            .context("could not create the otlp span exporter")?;
+        let metrics = MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .with_metadata(metadata)
+            .build()
+            .context("could not create the otlp metric exporter")?;
+        let tracer_provider = TracerProvider::builder()
+            .with_batch_exporter(spans, runtime::Tokio)
+            .build();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(metrics, runtime::Tokio).build())
+            .build();
+        Ok(Self::from_providers(tracer_provider, meter_provider))
+    }
+
+    /// Exports through providers set up by the caller.
+    pub fn from_providers(
+        tracer_provider: TracerProvider,
+        meter_provider: SdkMeterProvider,
+    ) -> Self {
+        Self {
+            tracer_provider,
+            meter_provider,
+            gauges: Mutex::new(vec![]),
+        }
+    }
+
+    /// Layer exporting the spans of a `tracing` subscriber, the `invocation`
+    /// span of each run and the `bootstrap`, `load`, `evaluate`,
+    /// `event_loop` and `call` spans of its phases among them.
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.tracer_provider.tracer(SCOPE))
+    }
+
+    /// Records every invocation it receives as OpenTelemetry metrics.
+    pub fn metrics(&self) -> Arc<dyn MetricsSink> {
+        Arc::new(OtelMetrics::new(&self.meter()))
+    }
+
+    /// Reports the load `stats` returns as gauges, per tenant, whenever
+    /// metrics are collected. Typically `move || manager.stats()` for a
+    /// shared [`RuntimeManager`](crate::RuntimeManager).
+    pub fn observe_pools(&self, stats: impl Fn() -> ManagerStats + Send + Sync + 'static) {
+        let stats = Arc::new(stats);
+        let gauge =
+            |name: &'static str, description: &'static str, value: fn(&TenantStats) -> usize| {
+                let stats = stats.clone();
+                self.meter()
+                    .u64_observable_gauge(name)
+                    .with_description(description)
+                    .with_callback(move |observer| {
+                        for (tenant, tenant_stats) in stats().tenants {
+                            let attributes = [KeyValue::new("tenant", tenant)];
+                            observer.observe(value(&tenant_stats) as u64, &attributes);
+                        }
+                    })
+                    .build()
+            };
+        let running = gauge(
+            "runtime.pool.running",
+            "Invocations running on an isolate",
+            |tenant| tenant.running,
+        );
+        let queued = gauge(
+            "runtime.pool.queued",
+            "Invocations waiting for an isolate",
+            |tenant| tenant.queued,
+        );
+        self.gauges.lock().unwrap().extend([running, queued]);
+    }
+
+    /// Reports the entries and bytes of the caches `stats` returns as
+    /// gauges, per cache name, whenever metrics are collected. Typically
+    /// `move || vec![("modules".into(), cache.stats())]`. Lookups of runs
+    /// are counted by [`metrics`](Self::metrics) as `runtime.cache.lookups`.
+    pub fn observe_caches(
+        &self,
+        stats: impl Fn() -> Vec<(String, CacheStats)> + Send + Sync + 'static,
+    ) {
+        let stats = Arc::new(stats);
+        let gauge = |name: &'static str,
+                     description: &'static str,
+                     unit: &'static str,
+                     value: fn(&CacheStats) -> u64| {
+            let stats = stats.clone();
+            self.meter()
+                .u64_observable_gauge(name)
+                .with_description(description)
+                .with_unit(unit)
+                .with_callback(move |observer| {
+                    for (cache, cache_stats) in stats() {
+                        observer.observe(value(&cache_stats), &[KeyValue::new("cache", cache)]);
+                    }
+                })
+                .build()
+        };
+        let entries = gauge(
+            "runtime.cache.entries",
+            "Entries held by a cache",
+            "{entry}",
+            |cache| cache.entries,
+        );
+        let bytes = gauge(
+            "runtime.cache.bytes",
+            "Bytes held by a cache",
+            "By",
+            |cache| cache.bytes,
+        );
+        self.gauges.lock().unwrap().extend([entries, bytes]);
+    }
+
+    /// Exports what is buffered and stops the providers.
+    pub fn shutdown(&self) -> Result<(), Error> {
+        let spans = self.tracer_provider.shutdown();
+        let metrics = self.meter_provider.shutdown();
+        spans.map_err(|e| anyhow!("could not shut down the tracer provider: {}", e))?;
+        metrics.map_err(|e| anyhow!("could not shut down the meter provider: {}", e))
+    }
+
+    fn meter(&self) -> Meter {
+        self.meter_provider.meter(SCOPE)
+    }
+}
+
+impl fmt::Debug for Telemetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Telemetry").finish_non_exhaustive()
+    }
+}
+
+/// Instruments the invocations reported to it are recorded on.
+struct OtelMetrics {
+    invocations: Counter<u64>,
+    duration: Histogram<f64>,
+    phases: Histogram<f64>,
+    peak_heap: Histogram<u64>,
+    cache_lookups: Counter<u64>,
+}
+
+impl OtelMetrics {
+    fn new(meter: &Meter) -> Self {
+        Self {
+            invocations: meter
+                .u64_counter("runtime.invocations")
+                .with_description("Settled invocations")
+                .build(),
+            duration: meter
+                .f64_histogram("runtime.invocation.duration")
+                .with_description("Time from start to the result of an invocation")
+                .with_unit("s")
+                .build(),
+            phases: meter
+                .f64_histogram("runtime.invocation.phase.duration")
+                .with_description("Time an invocation spent in each phase")
+                .with_unit("s")
+                .build(),
+            peak_heap: meter
+                .u64_histogram("runtime.invocation.peak_heap")
+                .with_description("Largest heap usage sampled during an invocation")
+                .with_unit("By")
+                .build(),
+            cache_lookups: meter
+                .u64_counter("runtime.cache.lookups")
+                .with_description("Lookups of invocations in the module and transpile caches")
+                .build(),
+        }
+    }
+}
+
+impl MetricsSink for OtelMetrics {
+    fn invocation(&self, invocation: &Invocation) {
+        let attributes = [
+            KeyValue::new("function", invocation.function.display().to_string()),
+            KeyValue::new("outcome", invocation.error.unwrap_or("ok")),
+        ];
+        self.invocations.add(1, &attributes);
+        self.duration
+            .record(invocation.elapsed.as_secs_f64(), &attributes);
+        let stats = &invocation.stats;
+        for (phase, duration) in [
+            ("load", stats.load),
+            ("evaluate", stats.evaluate),
+            ("event_loop", stats.event_loop),
+            ("call", stats.call),
+        ] {
+            let mut attributes = attributes.to_vec();
+            attributes.push(KeyValue::new("phase", phase));
+            self.phases.record(duration.as_secs_f64(), &attributes);
+        }
+        self.peak_heap
+            .record(stats.peak_heap_size as u64, &attributes);
+        for (cache, lookups) in [
+            ("module", stats.module_cache),
+            ("transpile", stats.transpile_cache),
+        ] {
+            let Some(lookups) = lookups else {
+                continue;
+            };
+            for (result, count) in [("hit", lookups.hits), ("miss", lookups.misses)] {
+                let attributes = [
+                    attributes[0].clone(),
+                    KeyValue::new("cache", cache),
+                    KeyValue::new("result", result),
+                ];
+                self.cache_lookups.add(count, &attributes);
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use deno_permissions::PermissionsOptions;
+use std::path::PathBuf;
+
+/// What a script may access. Each `allow_*` list is `None` to deny that
+/// kind of access, an empty list to allow all of it, or an allowlist of
+/// hosts (`api.example.com`, `127.0.0.1:8080`), paths, variable names or
+/// commands. The default denies everything.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimePermissions {
+    pub allow_net: Option<Vec<String>>,
+    pub allow_read: Option<Vec<PathBuf>>,
+    pub allow_write: Option<Vec<PathBuf>>,
+    pub allow_env: Option<Vec<String>>,
+    pub allow_run: Option<Vec<String>>,
+    pub allow_sys: Option<Vec<String>>,
+    pub allow_ffi: Option<Vec<PathBuf>>,
+    /// High resolution timers.
+    pub allow_hrtime: bool,
+}
+
+impl RuntimePermissions {
+    /// Denies everything.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Allows everything, which is what `run_insecure` uses.
+    pub fn allow_all() -> Self {
+        Self {
+            allow_net: Some(vec![]),
+            allow_read: Some(vec![]),
+            allow_write: Some(vec![]),
+            allow_env: Some(vec![]),
+            allow_run: Some(vec![]),
+            allow_sys: Some(vec![]),
+            allow_ffi: Some(vec![]),
+            allow_hrtime: true,
+        }
+    }
+
+    pub(crate) fn to_options(&self) -> PermissionsOptions {
+        PermissionsOptions {
+            allow_all: false,
+            allow_env: self.allow_env.clone(),
+            deny_env: None,
+            allow_hrtime: self.allow_hrtime,
+            deny_hrtime: !self.allow_hrtime,
+            allow_net: self.allow_net.clone(),
+            deny_net: None,
+            allow_ffi: self.allow_ffi.clone(),
+            deny_ffi: None,
+            allow_read: self.allow_read.clone(),
+            deny_read: None,
+            allow_run: self.allow_run.clone(),
+            deny_run: None,
+            allow_sys: self.allow_sys.clone(),
+            deny_sys: None,
+            allow_write: self.allow_write.clone(),
+            deny_write: None,
+            prompt: false,
+        }
+    }
+}
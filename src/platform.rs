@@ -0,0 +1,117 @@
+use anyhow::{bail, Error};
+use deno_core::{v8, JsRuntime};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::error::RuntimeError;
+
+struct Platform {
+    initialized: bool,
+    shut_down: bool,
+    /// Workers and executor threads currently using V8.
+    live: usize,
+}
+
+static PLATFORM: Mutex<Platform> = Mutex::new(Platform {
+    initialized: false,
+    shut_down: false,
+    live: 0,
+});
+
+/// Initializes the V8 platform. Runs do this on first use, so calling it is
+/// only needed to pay the cost up front. Calling it again does nothing;
+/// after [`shutdown`] it fails with `RuntimeError::ShutDown`.
+pub fn init() -> Result<(), Error> {
+    let mut platform = PLATFORM.lock().unwrap();
+    ensure(&mut platform)
+}
+
+/// Disposes of the V8 platform, for embedders that need it gone before the
+/// process exits. Fails while workers, executor pool threads or
+/// [`RuntimePlatform`] handles are still alive. V8 can't be initialized
+/// again, every run afterwards fails with `RuntimeError::ShutDown`.
+pub fn shutdown() -> Result<(), Error> {
+    let mut platform = PLATFORM.lock().unwrap();
+    if platform.shut_down {
+        return Ok(());
+    }
+    if platform.live > 0 {
+        bail!(
+            "can't shut down while {} workers or pool threads are alive",
+            platform.live
+        );
+    }
+    platform.shut_down = true;
+    if platform.initialized {
+        unsafe {
+            v8::V8::dispose();
+        }
+        v8::V8::dispose_platform();
+    }
+    Ok(())
+}
+
+fn ensure(platform: &mut Platform) -> Result<(), Error> {
+    if platform.shut_down {
+        return Err(RuntimeError::ShutDown.into());
+    }
+    if !platform.initialized {
+        let v8_platform = v8::new_default_platform(0, false).make_shared();
+        JsRuntime::init_platform(Some(v8_platform), false);
+        platform.initialized = true;
+    }
+    Ok(())
+}
+
+/// Shared handle on the V8 platform, for embedders that create and drop
+/// runtimes over the life of the process. Acquiring one initializes V8 if
+/// nothing did yet, and while any handle or run is alive [`shutdown`]
+/// refuses to dispose of it. Clones share one reference.
+#[derive(Clone)]
+pub struct RuntimePlatform {
+    _guard: Arc<PlatformGuard>,
+}
+
+impl RuntimePlatform {
+    /// Fails with `RuntimeError::ShutDown` once V8 was disposed of.
+    pub fn acquire() -> Result<Self, Error> {
+        Ok(Self {
+            _guard: Arc::new(PlatformGuard::acquire()?),
+        })
+    }
+
+    /// Drops this handle and disposes of V8 if it was the last thing using
+    /// it. Other handles and runs still alive make this fail, with the
+    /// handle released either way.
+    pub fn shutdown(self) -> Result<(), Error> {
+        drop(self);
+        shutdown()
+    }
+}
+
+impl fmt::Debug for RuntimePlatform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuntimePlatform")
+            .field("live", &PLATFORM.lock().unwrap().live)
+            .finish()
+    }
+}
+
+/// Keeps [`shutdown`] from disposing of V8 while held.
+pub(crate) struct PlatformGuard(());
+
+impl PlatformGuard {
+    /// Initializes the platform if needed.
+    pub(crate) fn acquire() -> Result<Self, Error> {
+        let mut platform = PLATFORM.lock().unwrap();
+        ensure(&mut platform)?;
+        platform.live += 1;
+        Ok(Self(()))
+    }
+}
+
+impl Drop for PlatformGuard {
+    fn drop(&mut self) {
+        PLATFORM.lock().unwrap().live -= 1;
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Error};
+use deno_core::{LocalInspectorSession, PollEventLoopOptions};
+use futures::FutureExt;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::inputs::Inputs;
+use crate::options::RunOptions;
+use crate::worker::{self, LoadedModule};
+use crate::{redact, schema};
+
+/// What [`run_profiled`] collects.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileOptions {
+    /// Sample a CPU profile while the entrypoint runs.
+    pub cpu: bool,
+    /// Take a heap snapshot once the result settled.
+    pub heap_snapshot: bool,
+    /// Microseconds between CPU samples, 1000 by default.
+    pub sampling_interval: Option<u32>,
+    /// Also write the profiles into this directory, as
+    /// `<module>-<millis>.cpuprofile` and `.heapsnapshot` files.
+    pub dir: Option<PathBuf>,
+}
+
+/// Profiles of one invocation in the formats DevTools loads.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// The `Profiler.Profile` of the call.
+    pub cpu: Option<Value>,
+    /// Heap snapshot JSON.
+    pub heap_snapshot: Option<String>,
+    /// Files written to `ProfileOptions::dir`.
+    pub files: Vec<PathBuf>,
+}
+
+/// Runs like [`run_with_options`](crate::run_with_options) and profiles the
+/// call of the entrypoint through the inspector. Evaluating the module is
+/// not part of the CPU profile. Profiles are returned as far as they were
+/// collected when the run fails.
+pub fn run_profiled(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    options: RunOptions,
+    profile: &ProfileOptions,
+) -> (Result<Value, Error>, Profile) {
+    let inputs = inputs.into();
+    let redactor = redact::Redactor::new(&options, &inputs);
+    let mut collected = Profile::default();
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => return (Err(e.into()), collected),
+    };
+    let result = runtime.block_on(run(&function, inputs, &options, profile, &mut collected));
+    let written = write(&function, profile, &mut collected);
+    let result = result.and_then(|value| written.map(|_| value));
+    (result.map_err(|e| redactor.redact_error(e)), collected)
+}
+
+async fn run(
+    function: &Path,
+    inputs: Inputs,
+    options: &RunOptions,
+    profile: &ProfileOptions,
+    collected: &mut Profile,
+) -> Result<Value, Error> {
+    let output_schema = options
+        .output_schema
+        .as_ref()
+        .map(|s| schema::compile(s, options.strict_schema))
+        .transpose()?;
+    inputs.check(options)?;
+    let mut module = worker::load(function, options).await?;
+    let mut session = module.worker.create_inspector_session();
+    let mut notifications = session.take_notification_rx();
+
+    if profile.cpu {
+        let interval = profile.sampling_interval.unwrap_or(1000);
+        post(&mut module, &mut session, "Profiler.enable", None).await?;
+        let params = json!({ "interval": interval });
+        post(
+            &mut module,
+            &mut session,
+            "Profiler.setSamplingInterval",
+            Some(params),
+        )
+        .await?;
+        post(&mut module, &mut session, "Profiler.start", None).await?;
+    }
+    let result = match worker::call(&mut module, inputs, options).await {
+        Ok(f) => crate::output(&mut module.worker, f, options, output_schema.as_deref()),
+        Err(e) => Err(e),
+    };
+    if result.as_ref().is_err_and(worker::corrupts_worker) {
+        // A terminated isolate can't answer the inspector any more.
+        return result;
+    }
+    if profile.cpu {
+        let stopped = post(&mut module, &mut session, "Profiler.stop", None).await?;
+        collected.cpu = stopped.get("profile").cloned();
+    }
+    if profile.heap_snapshot {
+        log::debug!("taking heap snapshot");
+        post(&mut module, &mut session, "HeapProfiler.enable", None).await?;
+        post(
+            &mut module,
+            &mut session,
+            "HeapProfiler.takeHeapSnapshot",
+            None,
+        )
+        .await?;
+        // The chunks all arrive before the response to the request.
+        let mut snapshot = String::new();
+        while let Ok(notification) = notifications.try_recv() {
+            if notification["method"] == "HeapProfiler.addHeapSnapshotChunk" {
+                if let Some(chunk) = notification["params"]["chunk"].as_str() {
+                    snapshot.push_str(chunk);
+                }
+            }
+        }
+        collected.heap_snapshot = Some(snapshot);
+    }
+    result
+}
+
+async fn post(
+    module: &mut LoadedModule,
+    session: &mut LocalInspectorSession,
+    method: &str,
+    params: Option<Value>,
+) -> Result<Value, Error> {
+    module
+        .worker
+        .js_runtime
+        .with_event_loop_future(
+            session.post_message(method, params).boxed_local(),
+            PollEventLoopOptions::default(),
+        )
+        .await
+        .map_err(|e| anyhow!("inspector {} failed: {:#}", method, e))
+}
+
+fn write(function: &Path, profile: &ProfileOptions, collected: &mut Profile) -> Result<(), Error> {
+    let Some(dir) = &profile.dir else {
+        return Ok(());
+    };
+    let stem = function
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "function".to_string());
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
+    std::fs::create_dir_all(dir)?;
+    if let Some(cpu) = &collected.cpu {
+        let path = dir.join(format!("{}-{}.cpuprofile", stem, millis));
+        std::fs::write(&path, serde_json::to_vec(cpu)?)?;
+        collected.files.push(path);
+    }
+    if let Some(snapshot) = &collected.heap_snapshot {
+        let path = dir.join(format!("{}-{}.heapsnapshot", stem, millis));
+        std::fs::write(&path, snapshot)?;
+        collected.files.push(path);
+    }
+    Ok(())
+}
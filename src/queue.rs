@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+
+use crate::inputs::Inputs;
+use crate::options::RunOptions;
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub id: String,
+    pub payload: Vec<u8>,
+    /// Deliveries so far including this one, as counted by the source.
+    pub attempts: u32,
+}
+
+/// A queue the runner consumes from.
+#[async_trait]
+pub trait QueueSource: Send + Sync + 'static {
+    /// Next message, waiting for one if needed. `None` ends the run.
+    async fn next(&self) -> Option<Message>;
+    async fn ack(&self, message: &Message);
+    async fn nack(&self, message: &Message, requeue: bool);
+}
+
+type Decoder = dyn Fn(&Message) -> Result<Inputs, Error> + Send + Sync;
+type DeadLetter = dyn Fn(&Message, &Error) + Send + Sync;
+
+/// Consumes messages from a [`QueueSource`] and runs the function once per
+/// message.
+///
+/// Delivery is at-least-once: a message is acked only after its run
+/// succeeded, a failed run is nacked for redelivery until it reached
+/// `max_attempts`, then handed to the dead-letter callback and nacked
+/// without requeueing. With a concurrency above one, runs may finish and be
+/// acked out of order.
+pub struct QueueRunner {
+    function: PathBuf,
+    options: RunOptions,
+    concurrency: usize,
+    max_attempts: u32,
+    decoder: Arc<Decoder>,
+    dead_letter: Option<Arc<DeadLetter>>,
+}
+
+impl QueueRunner {
+    /// Runner decoding payloads as a JSON object of inputs, one run at a
+    /// time and three attempts per message.
+    pub fn new(function: PathBuf, options: RunOptions) -> Self {
+        Self {
+            function,
+            options,
+            concurrency: 1,
+            max_attempts: 3,
+            decoder: Arc::new(|message: &Message| {
+                let inputs: HashMap<String, Value> = serde_json::from_slice(&message.payload)?;
+                Ok(inputs.into())
+            }),
+            dead_letter: None,
+        }
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn decoder(
+        mut self,
+        decoder: impl Fn(&Message) -> Result<Inputs, Error> + Send + Sync + 'static,
+    ) -> Self {
+        self.decoder = Arc::new(decoder);
+        self
+    }
+
+    /// Called with messages that are given up on, either because they can't
+    /// be decoded or because their last attempt failed.
+    pub fn dead_letter(
+        mut self,
+        dead_letter: impl Fn(&Message, &Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.dead_letter = Some(Arc::new(dead_letter));
+        self
+    }
+
+    /// Pulls messages until the source runs dry or `drain` completes. On
+    /// drain no further messages are pulled, but runs already started are
+    /// finished and acked before returning.
+    pub async fn run<S: QueueSource>(
+        &self,
+        source: Arc<S>,
+        drain: impl Future<Output = ()>,
+    ) -> Result<(), Error> {
+        let slots = Arc::new(Semaphore::new(self.concurrency));
+        tokio::pin!(drain);
+
+        loop {
+            let slot = tokio::select! {
+                _ = &mut drain => break,
+                slot = slots.clone().acquire_owned() => slot?,
+            };
+            let message = tokio::select! {
+                _ = &mut drain => break,
+                message = source.next() => match message {
+                    Some(message) => message,
+                    None => break,
+                },
+            };
+
+            let source = source.clone();
+            let function = self.function.clone();
+            let options = self.options.clone();
+            let decoder = self.decoder.clone();
+            let dead_letter = self.dead_letter.clone();
+            let max_attempts = self.max_attempts;
+            tokio::spawn(async move {
+                let _slot = slot;
+                // Redelivering won't make a payload decodable.
+                let (result, retryable) = match decoder(&message) {
+                    Ok(inputs) => {
+                        let result = tokio::task::spawn_blocking(move || {
+                            crate::run_with_options(function, inputs, options)
+                        })
+                        .await
+                        .unwrap_or_else(|e| Err(anyhow!("run panicked: {}", e)));
+                        (result, true)
+                    }
+                    Err(e) => (Err(e.context("could not decode message")), false),
+                };
+
+                match result {
+                    Ok(_) => source.ack(&message).await,
+                    Err(e) if retryable && message.attempts < max_attempts => {
+                        log::debug!("run for message {} failed, requeueing: {:#}", message.id, e);
+                        source.nack(&message, true).await;
+                    }
+                    Err(e) => {
+                        log::debug!("giving up on message {}: {:#}", message.id, e);
+                        if let Some(dead_letter) = &dead_letter {
+                            dead_letter(&message, &e);
+                        }
+                        source.nack(&message, false).await;
+                    }
+                }
+            });
+        }
+
+        // Every slot is free again once in-flight runs are done.
+        let _ = slots.acquire_many(self.concurrency as u32).await?;
+        Ok(())
+    }
+}
+
+/// In-memory [`QueueSource`], mainly for tests. Requeued messages go to the
+/// back of the queue with their attempt count increased.
+#[derive(Default)]
+pub struct MemoryQueue {
+    state: Mutex<MemoryQueueState>,
+}
+
+#[derive(Default)]
+struct MemoryQueueState {
+    pending: VecDeque<Message>,
+    acked: Vec<Message>,
+    rejected: Vec<Message>,
+    next_id: u64,
+}
+
+impl MemoryQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, payload: impl Into<Vec<u8>>) {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id.to_string();
+        state.next_id += 1;
+        state.pending.push_back(Message {
+            id,
+            payload: payload.into(),
+            attempts: 0,
+        });
+    }
+
+    pub fn acked(&self) -> Vec<Message> {
+        self.state.lock().unwrap().acked.clone()
+    }
+
+    /// Messages nacked without requeueing.
+    pub fn rejected(&self) -> Vec<Message> {
+        self.state.lock().unwrap().rejected.clone()
+    }
+}
+
+#[async_trait]
+impl QueueSource for MemoryQueue {
+    /// Returns `None` as soon as the queue is empty instead of waiting, so
+    /// messages requeued after that point are left for the next run.
+    async fn next(&self) -> Option<Message> {
+        let mut message = self.state.lock().unwrap().pending.pop_front()?;
+        message.attempts += 1;
+        Some(message)
+    }
+
+    async fn ack(&self, message: &Message) {
+        self.state.lock().unwrap().acked.push(message.clone());
+    }
+
+    async fn nack(&self, message: &Message, requeue: bool) {
+        let mut state = self.state.lock().unwrap();
+        if requeue {
+            state.pending.push_back(message.clone());
+        } else {
+            state.rejected.push(message.clone());
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use anyhow::anyhow;
+use deno_core::error::JsError;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::error::RuntimeError;
+use crate::inputs::{InputPart, Inputs};
+use crate::options::RunOptions;
+
+/// Secret values to scrub from errors before they leave the runtime.
+///
+/// Redaction is best-effort: only exact occurrences of the configured values
+/// are replaced, anything derived from them (encoded, sliced, hashed) is not.
+#[derive(Debug, Clone, Default)]
+pub struct RedactOptions {
+    /// Values to replace, keyed by the name shown as `[REDACTED:<name>]`.
+    pub secrets: HashMap<String, String>,
+    /// Case-insensitive glob patterns (`*_key`, `*token*`) for input names
+    /// whose string values are treated as secrets.
+    pub input_patterns: Vec<String>,
+    /// The same for variables of `RunOptions::env`. Their values are also
+    /// scrubbed from console output.
+    pub env_patterns: Vec<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct Redactor {
+    /// Longest values first so a secret containing another is replaced whole.
+    secrets: Vec<(String, String)>,
+}
+
+impl Redactor {
+    pub(crate) fn new(options: &RunOptions, inputs: &Inputs) -> Self {
+        let mut secrets = Self::configured(options);
+        let options = &options.redact;
+        for (name, part) in inputs.iter() {
+            if !options
+                .input_patterns
+                .iter()
+                .any(|pattern| glob_match(pattern, name))
+            {
+                continue;
+            }
+            match part {
+                InputPart::Json(Value::String(value)) | InputPart::Text(value) => {
+                    secrets.push((name.to_string(), value.clone()));
+                }
+                _ => {}
+            }
+        }
+        Self::sorted(secrets)
+    }
+
+    /// Redacts the secrets known before any inputs, for console output.
+    pub(crate) fn for_console(options: &RunOptions) -> Self {
+        Self::sorted(Self::configured(options))
+    }
+
+    fn configured(options: &RunOptions) -> Vec<(String, String)> {
+        let mut secrets: Vec<(String, String)> = options
+            .redact
+            .secrets
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        for (name, value) in options.env.iter().flatten() {
+            if options
+                .redact
+                .env_patterns
+                .iter()
+                .any(|pattern| glob_match(pattern, name))
+            {
+                secrets.push((name.clone(), value.clone()));
+            }
+        }
+        secrets
+    }
+
+    fn sorted(mut secrets: Vec<(String, String)>) -> Self {
+        secrets.retain(|(_, value)| !value.is_empty());
+        secrets.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+        Self { secrets }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.secrets.is_empty()
+    }
+
+    pub(crate) fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (key, value) in &self.secrets {
+            if text.contains(value.as_str()) {
+                text = text.replace(value.as_str(), &format!("[REDACTED:{}]", key));
+            }
+        }
+        text
+    }
+
+    pub(crate) fn redact_json(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => *text = self.redact(text),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_json(item)),
+            Value::Object(map) => map.values_mut().for_each(|item| self.redact_json(item)),
+            _ => {}
+        }
+    }
+
+    /// Scrubs JS exceptions and runtime errors in place. Other errors are
+    /// only rebuilt from their redacted message when they contain a secret.
+    pub(crate) fn redact_error(&self, error: anyhow::Error) -> anyhow::Error {
+        if self.is_empty() {
+            return error;
+        }
+        let mut error = match error.downcast::<JsError>() {
+            Ok(mut js_error) => {
+                self.redact_js_error(&mut js_error);
+                return js_error.into();
+            }
+            Err(error) => error,
+        };
+        if let Some(runtime_error) = error.downcast_mut::<RuntimeError>() {
+            match runtime_error {
+                RuntimeError::ToJsonFailed { message, .. }
+                | RuntimeError::ModuleEvaluation { message, .. }
+                | RuntimeError::PermissionDenied { message }
+                | RuntimeError::Subprocess { message, .. }
+                | RuntimeError::Serialization { message }
+                | RuntimeError::Deserialization { message } => *message = self.redact(message),
+                RuntimeError::JsException {
+                    message,
+                    stack,
+                    source_line,
+                    ..
+                } => {
+                    *message = self.redact(message);
+                    for text in [stack, source_line].into_iter().flatten() {
+                        *text = self.redact(text);
+                    }
+                }
+                RuntimeError::InputValidation { violations } => {
+                    for violation in violations {
+                        violation.message = self.redact(&violation.message);
+                    }
+                }
+                RuntimeError::OutputValidation { violations, value } => {
+                    for violation in violations {
+                        violation.message = self.redact(&violation.message);
+                    }
+                    if let Some(value) = value {
+                        self.redact_json(value);
+                    }
+                }
+                _ => {}
+            }
+            return error;
+        }
+
+        let message = format!("{:#}", error);
+        let redacted = self.redact(&message);
+        if redacted == message {
+            error
+        } else {
+            anyhow!(redacted)
+        }
+    }
+
+    fn redact_js_error(&self, error: &mut JsError) {
+        for text in [
+            &mut error.name,
+            &mut error.message,
+            &mut error.stack,
+            &mut error.source_line,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *text = self.redact(text);
+        }
+        error.exception_message = self.redact(&error.exception_message);
+        if let Some(cause) = &mut error.cause {
+            self.redact_js_error(cause);
+        }
+        for aggregated in error.aggregated.iter_mut().flatten() {
+            self.redact_js_error(aggregated);
+        }
+    }
+}
+
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+    let parts: Vec<&str> = pattern.split('*').collect();
+    let (first, rest) = parts.split_first().unwrap();
+    let Some(mut remaining) = name.strip_prefix(first) else {
+        return false;
+    };
+    let Some((last, middle)) = rest.split_last() else {
+        return remaining.is_empty();
+    };
+    for part in middle {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+    remaining.ends_with(last)
+}
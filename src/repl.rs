@@ -0,0 +1,262 @@
+use anyhow::{anyhow, Error};
+use deno_core::error::JsError;
+use deno_core::{serde_v8, v8, PollEventLoopOptions};
+use serde_json::Value;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::embedded::EmbeddedModules;
+use crate::error::RuntimeError;
+use crate::extract::{self, ExtendedOptions, OutputFormat};
+use crate::options::RunOptions;
+use crate::worker::{self, LoadedModule};
+
+const HELP: &str = "\
+.help      show this help
+.history   list the entries evaluated so far
+.exit      leave, as does end of input
+Globals: `mod` is the module's namespace, `inputs` the sample inputs.
+Ctrl-C cancels the running evaluation.";
+
+/// Line-oriented REPL evaluating in a worker set up like a real run of
+/// `module`. Entries continue over several lines while brackets or a
+/// template literal are left open. Results print in the extended output
+/// format.
+///
+/// Entries using `await` are evaluated in an async function, so
+/// declarations in them don't outlive the entry.
+pub fn run_repl(
+    module: Option<PathBuf>,
+    inputs: Value,
+    mut options: RunOptions,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<(), Error> {
+    let function = match module {
+        Some(module) => module,
+        None => {
+            let mut modules = EmbeddedModules::new();
+            modules.insert("repl.js", "");
+            options.embedded = Some(modules.with_network_fallback());
+            PathBuf::from("repl.js")
+        }
+    };
+    let mut print_options = options.clone();
+    print_options.output_format = OutputFormat::Extended(ExtendedOptions::default());
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let mut module = worker::load(&function, &options).await?;
+        bind_globals(&mut module, &inputs)?;
+        let interrupt = watch_interrupts(&mut module);
+
+        let mut history: Vec<String> = vec![];
+        let mut entry = String::new();
+        loop {
+            write!(output, "{}", if entry.is_empty() { "> " } else { "... " })?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                break;
+            }
+            entry.push_str(&line);
+            if unbalanced(&entry) {
+                continue;
+            }
+            let code = std::mem::take(&mut entry);
+            match code.trim() {
+                "" => continue,
+                ".exit" => break,
+                ".help" => writeln!(output, "{}", HELP)?,
+                ".history" => {
+                    for (index, entry) in history.iter().enumerate() {
+                        writeln!(output, "{:>4}  {}", index + 1, entry)?;
+                    }
+                }
+                code => {
+                    history.push(code.to_string());
+                    interrupt.evaluating.store(true, Ordering::SeqCst);
+                    let result = tokio::select! {
+                        result = evaluate(&mut module, code) => result,
+                        _ = interrupt.notify.notified() => Err(anyhow!("interrupted")),
+                    };
+                    interrupt.evaluating.store(false, Ordering::SeqCst);
+                    module
+                        .worker
+                        .js_runtime
+                        .v8_isolate()
+                        .cancel_terminate_execution();
+
+                    let printed = result.and_then(|value| {
+                        let scope = &mut module.worker.js_runtime.handle_scope();
+                        let value = v8::Local::new(scope, value);
+                        let value = extract::to_json(scope, value, &print_options)?;
+                        Ok(serde_json::to_string_pretty(&value)?)
+                    });
+                    match printed {
+                        Ok(text) => writeln!(output, "{}", text)?,
+                        Err(e) => writeln!(output, "error: {:#}", e)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+fn bind_globals(module: &mut LoadedModule, inputs: &Value) -> Result<(), Error> {
+    let namespace = module.namespace()?;
+    let scope = &mut module.worker.js_runtime.handle_scope();
+    let global = scope.get_current_context().global(scope);
+    let namespace = v8::Local::new(scope, namespace);
+    let inputs = serde_v8::to_v8(scope, inputs).map_err(|e| RuntimeError::Serialization {
+        message: e.to_string(),
+    })?;
+    for (name, value) in [("mod", namespace.into()), ("inputs", inputs)] {
+        let key = v8::String::new(scope, name).ok_or(anyhow!("could not bind {}", name))?;
+        global.set(scope, key.into(), value);
+    }
+    Ok(())
+}
+
+struct Interrupt {
+    evaluating: Arc<AtomicBool>,
+    notify: Arc<Notify>,
+}
+
+/// Turns Ctrl-C into cancelling the running evaluation. Synchronous JS is
+/// terminated from the watcher thread, waiting on async work is abandoned
+/// through `notify`. Ctrl-C at the prompt is ignored.
+fn watch_interrupts(module: &mut LoadedModule) -> Interrupt {
+    let isolate = module.worker.js_runtime.v8_isolate().thread_safe_handle();
+    let interrupt = Interrupt {
+        evaluating: Arc::new(AtomicBool::new(false)),
+        notify: Arc::new(Notify::new()),
+    };
+    let evaluating = interrupt.evaluating.clone();
+    let notify = interrupt.notify.clone();
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("interrupt watcher runtime");
+        runtime.block_on(async {
+            while tokio::signal::ctrl_c().await.is_ok() {
+                if evaluating.load(Ordering::SeqCst) {
+                    isolate.terminate_execution();
+                    notify.notify_one();
+                }
+            }
+        });
+    });
+    interrupt
+}
+
+async fn evaluate(module: &mut LoadedModule, code: &str) -> Result<v8::Global<v8::Value>, Error> {
+    let value = if code.contains("await") {
+        // An expression first, so its value is the result.
+        match execute(module, format!("(async () => (\n{}\n))()", code)) {
+            Err(e) if is_syntax_error(&e) => {
+                execute(module, format!("(async () => {{\n{}\n}})()", code))?
+            }
+            result => result?,
+        }
+    } else {
+        execute(module, code.to_string())?
+    };
+    let value = module.worker.js_runtime.resolve(value);
+    module
+        .worker
+        .js_runtime
+        .with_event_loop_promise(value, PollEventLoopOptions::default())
+        .await
+}
+
+fn execute(module: &mut LoadedModule, code: String) -> Result<v8::Global<v8::Value>, Error> {
+    module.worker.execute_script("[repl]", code.into())
+}
+
+fn is_syntax_error(error: &Error) -> bool {
+    error
+        .downcast_ref::<JsError>()
+        .is_some_and(|e| e.name.as_deref() == Some("SyntaxError"))
+}
+
+/// Whether the entry so far leaves brackets or a template literal open.
+fn unbalanced(code: &str) -> bool {
+    let mut depth = 0i32;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in code.chars() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q || (c == '\n' && q != '`') {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' | '`' => quote = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0 || quote == Some('`')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_brackets_and_template_literals_continue_the_entry() {
+        assert!(unbalanced("function f() {\n"));
+        assert!(unbalanced("[1,\n(2"));
+        assert!(!unbalanced("function f() {\n  return 1;\n}\n"));
+        assert!(unbalanced("`first line\n"));
+        assert!(!unbalanced("`first line\nsecond`\n"));
+        // Brackets in strings don't count, and strings end with the line.
+        assert!(!unbalanced("\"{\" + '[\\'('\n"));
+        assert!(!unbalanced("'unterminated {\n"));
+    }
+
+    fn repl(input: &str) -> String {
+        let mut output = vec![];
+        run_repl(
+            None,
+            serde_json::json!({ "name": "repl" }),
+            RunOptions::default(),
+            input.as_bytes(),
+            &mut output,
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn top_level_await_evaluates_to_the_awaited_value() {
+        let output = repl(
+            "await Promise.resolve(40 + 2)\n\
+             const x = await Promise.resolve(inputs.name);\n\
+             x.length\n",
+        );
+        assert!(output.starts_with("> 42\n"), "{}", output);
+        // A block: declarations stay inside it, so `x` is not defined after.
+        assert!(output.contains("x is not defined"), "{}", output);
+    }
+
+    #[test]
+    fn entries_continue_over_lines_until_balanced() {
+        let output = repl("[1,\n2].length\n");
+        assert!(output.contains("... 2"), "{}", output);
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::Error;
+use deno_core::error::JsError;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use crate::error::RuntimeError;
+use crate::inputs::Inputs;
+use crate::options::RunOptions;
+use crate::uncaught::{self, UncaughtEvent};
+use crate::worker::{self, LoadedModule};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const RUNTIME_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct LoadParams {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct CallParams {
+    handle: u64,
+    #[serde(default)]
+    inputs: HashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct UnloadParams {
+    handle: u64,
+}
+
+struct Server {
+    options: RunOptions,
+    modules: HashMap<u64, LoadedModule>,
+    next_handle: u64,
+}
+
+enum Failure {
+    Protocol(i64, String),
+    Runtime(Error),
+}
+
+/// Serves JSON-RPC 2.0 with one message per line (NDJSON) until `shutdown`
+/// or end of input.
+///
+/// `load` evaluates a module once and returns a handle, `call` runs its
+/// `main` on that warm worker, `unload` drops it. Requests are handled in
+/// the order they arrive.
+pub fn serve_rpc(input: impl BufRead, mut output: impl Write) -> Result<(), Error> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let mut server = Server {
+        options: RunOptions::default(),
+        modules: HashMap::new(),
+        next_handle: 1,
+    };
+
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match serde_json::from_str::<Value>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                respond(
+                    &mut output,
+                    Value::Null,
+                    Err(Failure::Protocol(PARSE_ERROR, e.to_string())),
+                )?;
+                continue;
+            }
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let request = match serde_json::from_value::<Request>(request) {
+            Ok(request) if request.jsonrpc == "2.0" => request,
+            Ok(_) => {
+                let failure = Failure::Protocol(INVALID_REQUEST, "jsonrpc must be \"2.0\"".into());
+                respond(&mut output, id, Err(failure))?;
+                continue;
+            }
+            Err(e) => {
+                respond(
+                    &mut output,
+                    id,
+                    Err(Failure::Protocol(INVALID_REQUEST, e.to_string())),
+                )?;
+                continue;
+            }
+        };
+
+        log::debug!("rpc request {}", request.method);
+        let shutdown = request.method == "shutdown";
+        let result = runtime.block_on(server.handle(&request.method, request.params));
+        // Requests without an id are notifications and get no response.
+        if let Some(id) = request.id {
+            respond(&mut output, id, result)?;
+        }
+        if shutdown {
+            break;
+        }
+    }
+    Ok(())
+}
+
+impl Server {
+    async fn handle(&mut self, method: &str, params: Value) -> Result<Value, Failure> {
+        match method {
+            "load" => {
+                let params: LoadParams = parse_params(params)?;
+                let module = worker::load(&params.path, &self.options)
+                    .await
+                    .map_err(Failure::Runtime)?;
+                let handle = self.next_handle;
+                self.next_handle += 1;
+                self.modules.insert(handle, module);
+                Ok(json!({ "handle": handle }))
+            }
+            "call" => {
+                let params: CallParams = parse_params(params)?;
+                let module = self.modules.get_mut(&params.handle).ok_or_else(|| {
+                    Failure::Protocol(INVALID_PARAMS, format!("unknown handle {}", params.handle))
+                })?;
+                let inputs = Inputs::from(params.inputs);
+                inputs.check(&self.options).map_err(Failure::Runtime)?;
+                if let Err(e) = worker::take_uncaught(module).await {
+                    let event = UncaughtEvent::new(&e, module.id, false, true);
+                    uncaught::report(self.options.on_uncaught.as_ref(), event);
+                    if let Err(e) = worker::reload(module, &self.options).await {
+                        self.modules.remove(&params.handle);
+                        return Err(Failure::Runtime(e));
+                    }
+                }
+                let result = match worker::call(module, inputs, &self.options).await {
+                    Ok(f) => crate::output(&mut module.worker, f, &self.options, None),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = &result {
+                    if worker::corrupts_worker(e) {
+                        let event = UncaughtEvent::new(e, module.id, true, false);
+                        uncaught::report(self.options.on_uncaught.as_ref(), event);
+                        log::debug!("dropping module {} after failed call", params.handle);
+                        self.modules.remove(&params.handle);
+                    }
+                }
+                result.map_err(Failure::Runtime)
+            }
+            "unload" => {
+                let params: UnloadParams = parse_params(params)?;
+                Ok(Value::Bool(self.modules.remove(&params.handle).is_some()))
+            }
+            "shutdown" => {
+                self.modules.clear();
+                Ok(Value::Null)
+            }
+            method => Err(Failure::Protocol(
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", method),
+            )),
+        }
+    }
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, Failure> {
+    serde_json::from_value(params).map_err(|e| Failure::Protocol(INVALID_PARAMS, e.to_string()))
+}
+
+fn respond(
+    output: &mut impl Write,
+    id: Value,
+    result: Result<Value, Failure>,
+) -> Result<(), Error> {
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(Failure::Protocol(code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+        Err(Failure::Runtime(error)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {
+                "code": RUNTIME_ERROR,
+                "message": format!("{:#}", error),
+                "data": { "kind": error_kind(&error) },
+            },
+        }),
+    };
+    serde_json::to_writer(&mut *output, &response)?;
+    output.write_all(b"\n")?;
+    output.flush()?;
+    Ok(())
+}
+
+pub(crate) fn error_kind(error: &Error) -> &'static str {
+    if let Some(error) = error.downcast_ref::<RuntimeError>() {
+        error.kind()
+    } else if error.downcast_ref::<JsError>().is_some() {
+        "js_exception"
+    } else {
+        "internal"
+    }
+}
@@ -1,5 +1,518 @@
 // runtime.js
 
-; ((globalThis) => {
-    const core = Deno.core ?? Deno[Deno.internal].core
-})(globalThis)
+import { core, primordials } from "ext:core/mod.js";
+import {
+  op_host_api_call,
+  op_host_api_call_async,
+  op_host_api_methods,
+  op_host_console_capture,
+  op_host_console_event,
+  op_host_deterministic,
+  op_host_elapsed,
+  op_host_emit,
+  op_host_env,
+  op_host_file_list,
+  op_host_file_open,
+  op_host_file_read,
+  op_host_file_read_all,
+  op_host_message_recv,
+  op_host_now,
+  op_host_random,
+  op_host_sandboxed,
+  op_host_trace_context,
+  op_host_trace_mode,
+  op_host_trace_record_fetch,
+  op_host_trace_replay_fetch,
+} from "ext:core/ops";
+import { inspectArgs } from "ext:deno_console/01_console.js";
+const {
+  ArrayIsArray,
+  ArrayPrototypeIncludes,
+  ArrayPrototypeIndexOf,
+  ArrayPrototypeMap,
+  ArrayPrototypePush,
+  ArrayPrototypeSlice,
+  ArrayPrototypeSplice,
+  DatePrototypeToString,
+  ErrorPrototype,
+  MapPrototypeEntries,
+  NumberIsFinite,
+  ObjectDefineProperty,
+  ObjectFreeze,
+  ObjectHasOwn,
+  ObjectKeys,
+  ObjectPrototypeIsPrototypeOf,
+  ObjectSetPrototypeOf,
+  PromiseReject,
+  ReflectConstruct,
+  RegExpPrototypeExec,
+  SafeArrayIterator,
+  SafeSet,
+  SetPrototypeValues,
+  String: StringConstructor,
+  StringPrototypeSplit,
+  SymbolFor,
+  TypeError,
+  TypedArrayPrototypeGetByteLength,
+  Uint8Array,
+} = primordials;
+
+const CHUNK_SIZE = 64 * 1024;
+
+class HostFile {
+  #name;
+  #size;
+
+  constructor(name, size) {
+    this.#name = name;
+    this.#size = size;
+  }
+
+  get name() {
+    return this.#name;
+  }
+
+  get size() {
+    return this.#size;
+  }
+
+  stream() {
+    const name = this.#name;
+    let rid;
+    return new globalThis.ReadableStream({
+      async start() {
+        rid = await op_host_file_open(name);
+      },
+      async pull(controller) {
+        const chunk = await op_host_file_read(rid, CHUNK_SIZE);
+        if (TypedArrayPrototypeGetByteLength(chunk) === 0) {
+          core.tryClose(rid);
+          controller.close();
+        } else {
+          controller.enqueue(chunk);
+        }
+      },
+      cancel() {
+        core.tryClose(rid);
+      },
+    });
+  }
+
+  bytes() {
+    return op_host_file_read_all(this.#name);
+  }
+
+  async text() {
+    return new globalThis.TextDecoder().decode(await this.bytes());
+  }
+}
+
+let files;
+
+const host = {};
+ObjectDefineProperty(host, "files", {
+  enumerable: true,
+  get() {
+    if (files === undefined) {
+      files = {};
+      for (const { 0: name, 1: size } of op_host_file_list()) {
+        files[name] = ObjectFreeze(new HostFile(name, size));
+      }
+      ObjectFreeze(files);
+    }
+    return files;
+  },
+});
+
+let api;
+
+ObjectDefineProperty(host, "api", {
+  enumerable: true,
+  get() {
+    if (api === undefined) {
+      api = {};
+      for (const { 0: name, 1: isAsync } of op_host_api_methods()) {
+        api[name] = isAsync
+          ? (...args) => op_host_api_call_async(name, args)
+          : (...args) => op_host_api_call(name, args);
+      }
+      ObjectFreeze(api);
+    }
+    return api;
+  },
+});
+
+// Messages between the host and a running function, see
+// `InvocationHandle`. The receive loop is unref'ed so a listener doesn't
+// keep the function from settling.
+
+const messageListeners = [];
+let receiving = false;
+// Pending receives only keep the event loop alive while the worker awaits
+// the result of a call, so functions can wait for messages without holding
+// up module evaluation.
+let awaitingMessages = false;
+let nextMessage;
+
+async function receiveMessages() {
+  while (true) {
+    const promise = op_host_message_recv();
+    if (!awaitingMessages) {
+      core.unrefOpPromise(promise);
+    }
+    nextMessage = promise;
+    const { 0: open, 1: message } = await promise;
+    nextMessage = undefined;
+    if (!open) {
+      return;
+    }
+    const listeners = ArrayPrototypeSlice(messageListeners);
+    for (const listener of new SafeArrayIterator(listeners)) {
+      listener(message);
+    }
+  }
+}
+
+ObjectDefineProperty(host, "onMessage", {
+  enumerable: true,
+  value: (listener) => {
+    ArrayPrototypePush(messageListeners, listener);
+    if (!receiving) {
+      receiving = true;
+      receiveMessages();
+    }
+    return () => {
+      const index = ArrayPrototypeIndexOf(messageListeners, listener);
+      if (index !== -1) {
+        ArrayPrototypeSplice(messageListeners, index, 1);
+      }
+    };
+  },
+});
+
+const AWAIT_MESSAGES = SymbolFor("experimental_runtime.awaitMessages");
+ObjectDefineProperty(globalThis, AWAIT_MESSAGES, {
+  value(awaiting) {
+    awaitingMessages = awaiting;
+    if (nextMessage !== undefined) {
+      if (awaiting) {
+        core.refOpPromise(nextMessage);
+      } else {
+        core.unrefOpPromise(nextMessage);
+      }
+    }
+  },
+});
+
+ObjectDefineProperty(host, "emit", {
+  enumerable: true,
+  value: (message) => op_host_emit(message),
+});
+
+// Console capture. deno_runtime sets up `globalThis.console` during
+// bootstrap, after extensions are evaluated, so the worker calls this
+// installer once bootstrap is done.
+
+const CONSOLE_METHODS = [
+  "log",
+  "info",
+  "warn",
+  "error",
+  "debug",
+  "trace",
+  "dir",
+  "table",
+  "group",
+  "groupCollapsed",
+  "groupEnd",
+];
+
+function render(value, seen) {
+  switch (typeof value) {
+    case "string":
+    case "boolean":
+      return value;
+    case "number":
+      return NumberIsFinite(value) ? value : { $type: "Number", value: StringConstructor(value) };
+    case "undefined":
+      return { $type: "undefined" };
+    case "bigint":
+      return { $type: "BigInt", value: StringConstructor(value) };
+    case "symbol":
+      return { $type: "Symbol", description: value.description ?? null };
+    case "function":
+      return { $type: "Function", name: value.name };
+  }
+  if (value === null) {
+    return null;
+  }
+  if (seen.has(value)) {
+    return { $type: "Circular" };
+  }
+  seen.add(value);
+  try {
+    if (ObjectPrototypeIsPrototypeOf(ErrorPrototype, value)) {
+      return { $type: "Error", name: value.name, message: value.message, stack: value.stack };
+    }
+    if (value instanceof globalThis.Map) {
+      return {
+        $type: "Map",
+        entries: ArrayPrototypeMap([...MapPrototypeEntries(value)], ({ 0: k, 1: v }) => [
+          render(k, seen),
+          render(v, seen),
+        ]),
+      };
+    }
+    if (value instanceof globalThis.Set) {
+      return {
+        $type: "Set",
+        values: ArrayPrototypeMap([...SetPrototypeValues(value)], (v) => render(v, seen)),
+      };
+    }
+    if (ArrayIsArray(value)) {
+      return ArrayPrototypeMap(value, (v) => render(v, seen));
+    }
+    if (typeof value.toJSON === "function") {
+      return render(value.toJSON(), seen);
+    }
+    const out = {};
+    for (const key of new SafeArrayIterator(ObjectKeys(value))) {
+      out[key] = render(value[key], seen);
+    }
+    return out;
+  } catch (e) {
+    return { $type: "Unserializable", message: StringConstructor(e) };
+  } finally {
+    seen.delete(value);
+  }
+}
+
+// Frames: "Error", this helper, the console wrapper, then the caller.
+function callSite() {
+  const stack = new Error().stack ?? "";
+  const frame = StringPrototypeSplit(stack, "\n")[3];
+  if (frame === undefined) {
+    return null;
+  }
+  const match = RegExpPrototypeExec(/\(?([^()\s]+):(\d+):(\d+)\)?$/, frame);
+  if (match === null) {
+    return null;
+  }
+  return { file: match[1], line: Number(match[2]), column: Number(match[3]) };
+}
+
+function installConsole() {
+  if (!op_host_console_capture()) {
+    return;
+  }
+  let groupDepth = 0;
+  for (const level of new SafeArrayIterator(CONSOLE_METHODS)) {
+    globalThis.console[level] = (...args) => {
+      const location = callSite();
+      let table = null;
+      if (level === "table") {
+        table = render(args[0], new SafeSet());
+        args = ArrayPrototypeSlice(args, 1);
+      }
+      if (level === "groupEnd") {
+        groupDepth = groupDepth > 0 ? groupDepth - 1 : 0;
+      }
+      op_host_console_event({
+        level,
+        text: inspectArgs(args),
+        args: ArrayPrototypeMap(args, (arg) => render(arg, new SafeSet())),
+        location,
+        groupDepth,
+        table,
+      });
+      if (level === "group" || level === "groupCollapsed") {
+        groupDepth++;
+      }
+    };
+  }
+}
+
+// Serves `Deno.env` from the injected environment, see `RunOptions::env`.
+function installEnv() {
+  const vars = op_host_env();
+  if (vars === null) {
+    return;
+  }
+  const readOnly = () =>
+    new globalThis.Deno.errors.PermissionDenied("the environment is read-only");
+  globalThis.Deno.env = ObjectFreeze({
+    get(key) {
+      return ObjectHasOwn(vars, key) ? vars[key] : undefined;
+    },
+    has(key) {
+      return ObjectHasOwn(vars, key);
+    },
+    toObject() {
+      return { ...vars };
+    },
+    set() {
+      throw readOnly();
+    },
+    delete() {
+      throw readOnly();
+    },
+  });
+}
+
+// Trace context of the run for the services the script calls, see
+// `RunOptions::propagate_trace_context`. Headers the script sets win.
+function installTraceContext() {
+  const headers = op_host_trace_context();
+  if (headers.length === 0) {
+    return;
+  }
+  const fetch = globalThis.fetch;
+  globalThis.fetch = (input, init) => {
+    const request = new globalThis.Request(input, init);
+    for (const { 0: name, 1: value } of new SafeArrayIterator(headers)) {
+      if (!request.headers.has(name)) {
+        request.headers.set(name, value);
+      }
+    }
+    return fetch(request);
+  };
+}
+
+// Files of a filesystem sandbox are only reachable through the file APIs,
+// a `file:` fetch would read the host's path. See `RunOptions::fs_sandbox`.
+function installSandbox() {
+  if (!op_host_sandboxed()) {
+    return;
+  }
+  const fetch = globalThis.fetch;
+  globalThis.fetch = (input, init) => {
+    const url = ObjectPrototypeIsPrototypeOf(globalThis.Request.prototype, input)
+      ? input.url
+      : StringConstructor(input);
+    // Parsed the way fetch() parses it, which drops leading spaces and any
+    // tabs or newlines. The host refuses these fetches as well.
+    if (
+      globalThis.URL.canParse(url) &&
+      new globalThis.URL(url).protocol === "file:"
+    ) {
+      return PromiseReject(
+        new globalThis.Deno.errors.PermissionDenied(
+          "file: URLs can't be fetched in a filesystem sandbox",
+        ),
+      );
+    }
+    return fetch(input, init);
+  };
+}
+
+// Record and replay of fetches, see `RunOptions::trace`. Bodies are read
+// in full, so a streamed response arrives all at once.
+const NULL_BODY_STATUS = [204, 205, 304];
+
+function tracedResponse(url, status, statusText, headers, body) {
+  const response = new globalThis.Response(
+    ArrayPrototypeIncludes(NULL_BODY_STATUS, status) ? null : body,
+    { status, statusText, headers },
+  );
+  ObjectDefineProperty(response, "url", { value: url });
+  return response;
+}
+
+function installTrace() {
+  const mode = op_host_trace_mode();
+  if (mode === null) {
+    return;
+  }
+  const fetch = globalThis.fetch;
+  globalThis.fetch = async (input, init) => {
+    const request = new globalThis.Request(input, init);
+    const { method, url } = request;
+    if (mode === "replay") {
+      const replayed = op_host_trace_replay_fetch(method, url);
+      if (replayed.error !== null) {
+        throw new TypeError(replayed.error);
+      }
+      const { status, statusText, headers, body } = replayed;
+      return tracedResponse(url, status, statusText, headers, body);
+    }
+    let response;
+    try {
+      response = await fetch(request);
+    } catch (e) {
+      const error = StringConstructor(e?.message ?? e);
+      op_host_trace_record_fetch({ method, url, error }, new Uint8Array());
+      throw e;
+    }
+    const { status, statusText } = response;
+    const headers = [...response.headers];
+    const body = new Uint8Array(await response.arrayBuffer());
+    op_host_trace_record_fetch({ method, url, status, statusText, headers, error: null }, body);
+    return tracedResponse(response.url, status, statusText, headers, body);
+  };
+}
+
+// Reproducible runs, see `RunOptions::determinism`. The replacement `Date`
+// shares the original's prototype, so `instanceof Date` holds either way.
+function installDeterminism() {
+  if (!op_host_deterministic()) {
+    return;
+  }
+  const replace = (object, name, value) =>
+    ObjectDefineProperty(object, name, { value, writable: true, configurable: true });
+
+  replace(globalThis.Math, "random", () => op_host_random());
+
+  const OriginalDate = globalThis.Date;
+  function Date(...args) {
+    if (new.target === undefined) {
+      return DatePrototypeToString(new OriginalDate(op_host_now()));
+    }
+    return ReflectConstruct(OriginalDate, args.length === 0 ? [op_host_now()] : args, new.target);
+  }
+  ObjectSetPrototypeOf(Date, OriginalDate);
+  ObjectDefineProperty(Date, "prototype", { value: OriginalDate.prototype });
+  replace(OriginalDate.prototype, "constructor", Date);
+  replace(Date, "now", () => op_host_now());
+  replace(globalThis, "Date", Date);
+  replace(globalThis.performance, "now", () => op_host_elapsed());
+
+  const blocked = [
+    [globalThis.crypto, "crypto", "getRandomValues"],
+    [globalThis.crypto, "crypto", "randomUUID"],
+    [globalThis.crypto.subtle, "crypto.subtle", "generateKey"],
+    [globalThis.Deno, "Deno", "hostname"],
+    [globalThis.Deno, "Deno", "osUptime"],
+    [globalThis.Deno, "Deno", "loadavg"],
+    [globalThis.Deno, "Deno", "systemMemoryInfo"],
+    [globalThis.Deno, "Deno", "networkInterfaces"],
+    [globalThis.Deno, "Deno", "memoryUsage"],
+  ];
+  for (const { 0: object, 1: owner, 2: name } of new SafeArrayIterator(blocked)) {
+    replace(object, name, () => {
+      throw new globalThis.Deno.errors.NotSupported(
+        `${owner}.${name}() is not available in deterministic mode`,
+      );
+    });
+  }
+}
+
+const INSTALL = SymbolFor("experimental_runtime.install");
+ObjectDefineProperty(globalThis, INSTALL, {
+  configurable: true,
+  value() {
+    delete globalThis[INSTALL];
+    installConsole();
+    installEnv();
+    installTraceContext();
+    installSandbox();
+    installDeterminism();
+    installTrace();
+  },
+});
+
+ObjectDefineProperty(globalThis, "host", {
+  value: ObjectFreeze(host),
+  enumerable: false,
+  configurable: true,
+  writable: false,
+});
@@ -0,0 +1,263 @@
+use anyhow::{bail, Error};
+use deno_core::ModuleLoader;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::console::ConsoleSink;
+use crate::determinism::Determinism;
+use crate::embedded::EmbeddedModules;
+use crate::host_api::{HostApi, HostApiBuilder};
+use crate::import_map::ImportMap;
+use crate::inputs::Inputs;
+use crate::loader_stack::LoaderStack;
+use crate::options::{Entrypoint, RunOptions};
+use crate::permissions::RuntimePermissions;
+use crate::subprocess::Subprocess;
+use crate::trace::TraceMode;
+use crate::transpile::TranspileCache;
+
+/// Creates the module loader of each worker, in place of the built-in
+/// network loader. Workers are single threaded, so each gets its own.
+#[derive(Clone)]
+pub struct LoaderFactory(Arc<dyn Fn() -> Rc<dyn ModuleLoader> + Send + Sync>);
+
+impl LoaderFactory {
+    pub fn new(factory: impl Fn() -> Rc<dyn ModuleLoader> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(factory))
+    }
+
+    pub(crate) fn create(&self) -> Rc<dyn ModuleLoader> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for LoaderFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("LoaderFactory")
+    }
+}
+
+/// Runs functions with a fixed configuration, for embedding. Cheap to
+/// clone and usable from any thread, each run gets a fresh worker.
+#[derive(Debug, Clone)]
+pub struct Runtime {
+    options: RunOptions,
+}
+
+impl Runtime {
+    pub fn builder() -> RuntimeBuilder {
+        RuntimeBuilder::default()
+    }
+
+    pub fn run(&self, function: PathBuf, inputs: impl Into<Inputs>) -> Result<Value, Error> {
+        crate::run_with_options(function, inputs, self.options.clone())
+    }
+
+    /// Like [`crate::run_typed`].
+    pub fn run_typed<T: serde::de::DeserializeOwned>(
+        &self,
+        function: PathBuf,
+        inputs: &impl serde::Serialize,
+    ) -> Result<T, Error> {
+        crate::run_typed(function, inputs, self.options.clone())
+    }
+
+    /// Like [`crate::run`], on a thread of its own.
+    pub async fn run_async(
+        &self,
+        function: PathBuf,
+        inputs: impl Into<Inputs>,
+    ) -> Result<Value, Error> {
+        crate::run(function, inputs, self.options.clone()).await
+    }
+
+    /// Resolves, fetches and compiles the import graphs of `modules`, paths
+    /// or URLs, without evaluating them. Remote modules land in the module
+    /// cache, npm packages in the npm cache and compiled TypeScript in the
+    /// transpile cache, as far as those are configured, so the first run
+    /// of a function doesn't wait for them. Each run still bootstraps a
+    /// fresh worker.
+    pub fn preload(&self, modules: &[impl AsRef<Path>]) -> Result<(), Error> {
+        let modules = modules.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+        crate::dependency::preload(&modules, &self.options)
+    }
+
+    pub fn options(&self) -> &RunOptions {
+        &self.options
+    }
+}
+
+/// Builds a [`Runtime`]. Starts from [`RunOptions::default`], which allows
+/// everything unless permissions are set.
+#[derive(Default)]
+pub struct RuntimeBuilder {
+    options: RunOptions,
+    ops: Option<HostApiBuilder>,
+}
+
+impl RuntimeBuilder {
+    /// Starts from `options` instead of the defaults.
+    pub fn options(mut self, options: RunOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn permissions(mut self, permissions: RuntimePermissions) -> Self {
+        self.options.permissions = Some(permissions);
+        self
+    }
+
+    /// Export called with the inputs, `main` by default.
+    pub fn entrypoint(mut self, export: &str) -> Self {
+        self.options.entrypoint = match export {
+            "main" => Entrypoint::MainFunction,
+            _ => Entrypoint::Handler(export.to_string()),
+        };
+        self
+    }
+
+    pub fn module_loader(
+        mut self,
+        factory: impl Fn() -> Rc<dyn ModuleLoader> + Send + Sync + 'static,
+    ) -> Self {
+        self.options.module_loader = Some(LoaderFactory::new(factory));
+        self
+    }
+
+    pub fn loader_stack(mut self, stack: LoaderStack) -> Self {
+        self.options.loader_stack = Some(stack);
+        self
+    }
+
+    /// Serves modules from `modules`, functions are then paths within it.
+    pub fn embedded(mut self, modules: EmbeddedModules) -> Self {
+        self.options.embedded = Some(modules);
+        self
+    }
+
+    pub fn import_map(mut self, import_map: ImportMap) -> Self {
+        self.options.import_map = Some(import_map);
+        self
+    }
+
+    pub fn transpile_cache(mut self, cache: TranspileCache) -> Self {
+        self.options.transpile_cache = Some(cache);
+        self
+    }
+
+    #[cfg(feature = "net-loader")]
+    pub fn module_cache(mut self, cache: crate::cache::ModuleCache) -> Self {
+        self.options.module_cache = Some(cache);
+        self
+    }
+
+    pub fn determinism(mut self, determinism: Determinism) -> Self {
+        self.options.determinism = Some(determinism);
+        self
+    }
+
+    pub fn trace(mut self, trace: TraceMode) -> Self {
+        self.options.trace = Some(trace);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn fuel(mut self, fuel: u64) -> Self {
+        self.options.fuel = Some(fuel);
+        self
+    }
+
+    pub fn console(mut self, sink: ConsoleSink) -> Self {
+        self.options.console = Some(sink);
+        self
+    }
+
+    pub fn subprocess(mut self, subprocess: Subprocess) -> Self {
+        self.options.subprocess = Some(subprocess);
+        self
+    }
+
+    /// Adds a variable to the environment `Deno.env` serves in place of
+    /// the host's.
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options
+            .env
+            .get_or_insert_with(Default::default)
+            .insert(name.into(), value.into());
+        self
+    }
+
+    /// Like [`env`](Self::env), and redacts the value from errors and
+    /// console output.
+    pub fn secret_env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let name = name.into();
+        let value = value.into();
+        self.options
+            .redact
+            .secrets
+            .insert(name.clone(), value.clone());
+        self.env(name, value)
+    }
+
+    /// Starts the inspector on `address`, pausing before the entrypoint is
+    /// called when `brk` is set.
+    pub fn inspect(mut self, address: std::net::SocketAddr, brk: bool) -> Self {
+        self.options.inspect = Some(address);
+        self.options.inspect_brk = brk;
+        self
+    }
+
+    /// Exposes an async Rust function to scripts as `host.api[name]`,
+    /// returning a promise. Arguments are deserialized from the array of
+    /// what the script passed, so a function taking two takes a tuple.
+    /// Adds to a `RunOptions::host_api` set through
+    /// [`options`](Self::options); building fails when it already has a
+    /// method of the same name.
+    pub fn register_op<A, R, F>(
+        mut self,
+        name: &str,
+        op: impl Fn(A) -> F + Send + Sync + 'static,
+    ) -> Self
+    where
+        A: DeserializeOwned,
+        R: Serialize,
+        F: Future<Output = Result<R, Error>> + Send + 'static,
+    {
+        self.ops = Some(self.ops.unwrap_or_default().async_method(name, op));
+        self
+    }
+
+    pub fn build(mut self) -> Result<Runtime, Error> {
+        if let Some(ops) = self.ops {
+            self.options.host_api = Some(match self.options.host_api.take() {
+                Some(base) => {
+                    let methods = base.0.methods();
+                    for op in ops.methods() {
+                        if methods.iter().any(|method| method.name == op.name) {
+                            bail!(
+                                "op {:?} clashes with a host api method of that name",
+                                op.name
+                            );
+                        }
+                    }
+                    ops.layered(base)
+                }
+                None => ops.build(),
+            });
+        }
+        Ok(Runtime {
+            options: self.options,
+        })
+    }
+}
@@ -0,0 +1,113 @@
+use anyhow::{anyhow, Error};
+use deno_core::ModuleSpecifier;
+use reqwest::StatusCode;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::RuntimeError;
+use crate::fetch::{self, Fetched};
+
+/// An object behind an `s3://bucket/key` specifier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Object {
+    pub bucket: String,
+    pub key: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum S3Error {
+    #[error("no object {key} in bucket {bucket}")]
+    NotFound { bucket: String, key: String },
+    #[error("access denied to object {key} in bucket {bucket}")]
+    AccessDenied { bucket: String, key: String },
+    #[error("could not reach the endpoint for object {key} in bucket {bucket}")]
+    Unreachable { bucket: String, key: String },
+}
+
+type ResolveFn = dyn Fn(&S3Object) -> Result<ModuleSpecifier, Error> + Send + Sync;
+
+/// Turns `s3:` specifiers into HTTP URLs the object can be fetched from,
+/// such as pre-signed URLs, so no SDK or credentials live in the loader.
+#[derive(Clone)]
+pub struct S3Resolver(Arc<ResolveFn>);
+
+impl S3Resolver {
+    pub fn new(
+        resolve: impl Fn(&S3Object) -> Result<ModuleSpecifier, Error> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(resolve))
+    }
+
+    /// Unsigned path-style requests against `endpoint`, for public buckets
+    /// and S3-compatible stores behind a proxy that signs requests.
+    pub fn public(endpoint: ModuleSpecifier) -> Self {
+        Self::new(move |object| {
+            let mut url = endpoint.clone();
+            url.path_segments_mut()
+                .map_err(|_| anyhow!("invalid s3 endpoint {}", endpoint))?
+                .pop_if_empty()
+                .push(&object.bucket)
+                .extend(object.key.split('/'));
+            Ok(url)
+        })
+    }
+}
+
+impl fmt::Debug for S3Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("S3Resolver")
+    }
+}
+
+/// Fetches an `s3:` module through the resolver. The module keeps its `s3:`
+/// specifier, so relative imports resolve to keys under the same prefix
+/// rather than against the resolved URL.
+pub(crate) async fn fetch(
+    client: &fetch::HttpClient,
+    resolver: &S3Resolver,
+    specifier: &ModuleSpecifier,
+) -> Result<Fetched, Error> {
+    let object = S3Object {
+        bucket: specifier
+            .host_str()
+            .filter(|bucket| !bucket.is_empty())
+            .ok_or_else(|| anyhow!("s3 specifier {} has no bucket", specifier))?
+            .to_string(),
+        key: specifier.path().trim_start_matches('/').to_string(),
+    };
+    let url = (resolver.0)(&object)?;
+    log::debug!(
+        "loading s3 object {} from {}",
+        object.key,
+        url.origin().ascii_serialization()
+    );
+
+    // Errors name the object rather than the resolved URL, which may carry
+    // a signature.
+    fetch::fetch(client, &url).await.map_err(|e| {
+        let S3Object { bucket, key } = object;
+        let cause = match e.downcast_ref::<RuntimeError>() {
+            Some(RuntimeError::Fetch { cause, .. }) => cause,
+            _ => &e,
+        };
+        let Some(error) = cause.downcast_ref::<reqwest::Error>() else {
+            return anyhow!("fetching object {} in bucket {} failed", key, bucket);
+        };
+        match error.status() {
+            Some(StatusCode::NOT_FOUND) => S3Error::NotFound { bucket, key }.into(),
+            Some(StatusCode::FORBIDDEN | StatusCode::UNAUTHORIZED) => {
+                S3Error::AccessDenied { bucket, key }.into()
+            }
+            Some(status) => anyhow!(
+                "fetching object {} in bucket {} failed with status {}",
+                key,
+                bucket,
+                status
+            ),
+            None if error.is_connect() || error.is_timeout() => {
+                S3Error::Unreachable { bucket, key }.into()
+            }
+            None => anyhow!("fetching object {} in bucket {} failed", key, bucket),
+        }
+    })
+}
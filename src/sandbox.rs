@@ -0,0 +1,464 @@
+use anyhow::{Context, Error};
+use bytes::Bytes;
+use deno_core::error::custom_error;
+use deno_core::url::Url;
+use deno_core::{CancelHandle, OpState};
+use deno_fetch::{CancelableResponseFuture, FetchHandler};
+use deno_fs::{AccessCheckCb, FileSystem, FsDirEntry, FsFileType, OpenOptions, RealFs};
+use deno_io::fs::{File, FsError, FsResult, FsStat};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A directory the script sees as the whole filesystem. `Deno.readTextFile`,
+/// `Deno.writeTextFile` and the rest of the file APIs, `node:fs` included,
+/// resolve paths inside it, with `/` being its root and the starting
+/// working directory. The script may read and write anywhere in it, but
+/// cannot reach outside, and `file:` fetches are refused.
+///
+/// By default every worker gets a fresh temporary directory, removed with
+/// the worker, so nothing outlives a one-shot run. A warm `ExecutorPool`
+/// empties it between jobs; a `FunctionRuntime` keeps it across calls,
+/// like the rest of its state. Symbolic links can't be
+/// created in the sandbox; a root given with [`in_dir`](Self::in_dir)
+/// should not contain links pointing out of it either.
+#[derive(Debug, Clone, Default)]
+pub struct FsSandbox {
+    root: Option<PathBuf>,
+    files: HashMap<PathBuf, Bytes>,
+}
+
+impl FsSandbox {
+    /// Sandbox in a temporary directory of its own.
+    pub fn temporary() -> Self {
+        Self::default()
+    }
+
+    /// Sandbox rooted at `root`, which is kept when the worker goes away.
+    pub fn in_dir(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: Some(root.into()),
+            files: HashMap::new(),
+        }
+    }
+
+    /// Places a file at `path` inside the sandbox before the module is
+    /// loaded.
+    pub fn file(mut self, path: impl Into<PathBuf>, contents: impl Into<Bytes>) -> Self {
+        self.files.insert(path.into(), contents.into());
+        self
+    }
+
+    /// Creates the root and the files, and the filesystem serving them.
+    pub(crate) fn mount(&self) -> Result<(Arc<RootedFs>, Workspace), Error> {
+        let (root, temporary) = match &self.root {
+            Some(root) => {
+                std::fs::create_dir_all(root)
+                    .with_context(|| format!("could not create {}", root.display()))?;
+                (root.canonicalize()?, false)
+            }
+            None => {
+                static NEXT: AtomicU64 = AtomicU64::new(0);
+                let root = std::env::temp_dir().join(format!(
+                    "experimental_runtime-sandbox-{}-{}",
+                    std::process::id(),
+                    NEXT.fetch_add(1, Ordering::Relaxed)
+                ));
+                std::fs::create_dir(&root)
+                    .with_context(|| format!("could not create {}", root.display()))?;
+                (root.canonicalize()?, true)
+            }
+        };
+        let fs = Arc::new(RootedFs {
+            root: root.clone(),
+            cwd: Mutex::new(PathBuf::from("/")),
+        });
+        let workspace = Workspace {
+            root,
+            temporary,
+            fs: fs.clone(),
+        };
+        self.populate(&fs)?;
+        log::debug!("mounted sandbox at {}", workspace.root.display());
+        Ok((fs, workspace))
+    }
+
+    fn populate(&self, fs: &RootedFs) -> Result<(), Error> {
+        std::fs::create_dir_all(fs.real(Path::new("/tmp")))?;
+        for (path, contents) in &self.files {
+            let real = fs.real(path);
+            if let Some(parent) = real.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&real, contents)
+                .with_context(|| format!("could not write {} to the sandbox", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Root of a mounted sandbox, removed on drop when it is temporary.
+#[derive(Debug)]
+pub(crate) struct Workspace {
+    root: PathBuf,
+    temporary: bool,
+    fs: Arc<RootedFs>,
+}
+
+impl Workspace {
+    /// Empties a temporary sandbox and puts the files of `sandbox` back, for
+    /// a worker that runs another invocation. A sandbox in a directory of
+    /// the caller's is left as it is.
+    pub(crate) fn reset(&self, sandbox: &FsSandbox) -> Result<(), Error> {
+        if !self.temporary {
+            return Ok(());
+        }
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            match entry.file_type()?.is_dir() {
+                true => std::fs::remove_dir_all(entry.path())?,
+                false => std::fs::remove_file(entry.path())?,
+            }
+        }
+        *self.fs.cwd.lock().unwrap() = PathBuf::from("/");
+        sandbox.populate(&self.fs)
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        if self.temporary {
+            if let Err(e) = std::fs::remove_dir_all(&self.root) {
+                log::debug!("could not remove sandbox {}: {}", self.root.display(), e);
+            }
+        }
+    }
+}
+
+/// The real filesystem with every path taken relative to `root`.
+#[derive(Debug)]
+pub(crate) struct RootedFs {
+    root: PathBuf,
+    /// Working directory inside the sandbox, never the process's.
+    cwd: Mutex<PathBuf>,
+}
+
+impl RootedFs {
+    /// Host path for a path the script used. `..` stops at the root.
+    fn real(&self, path: &Path) -> PathBuf {
+        let path = self.cwd.lock().unwrap().join(path);
+        let mut real = self.root.clone();
+        let mut depth = 0;
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => {
+                    real.push(part);
+                    depth += 1;
+                }
+                Component::ParentDir if depth > 0 => {
+                    real.pop();
+                    depth -= 1;
+                }
+                _ => {}
+            }
+        }
+        real
+    }
+
+    /// Path the script sees for a host path inside the root.
+    fn sandboxed(&self, real: &Path) -> PathBuf {
+        let relative = real.strip_prefix(&self.root).unwrap_or(Path::new(""));
+        Path::new("/").join(relative)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl FileSystem for RootedFs {
+    fn cwd(&self) -> FsResult<PathBuf> {
+        Ok(self.cwd.lock().unwrap().clone())
+    }
+
+    fn tmp_dir(&self) -> FsResult<PathBuf> {
+        Ok(PathBuf::from("/tmp"))
+    }
+
+    fn chdir(&self, path: &Path) -> FsResult<()> {
+        let real = self.real(path);
+        if !real.is_dir() {
+            return Err(FsError::Io(std::io::ErrorKind::NotFound.into()));
+        }
+        *self.cwd.lock().unwrap() = self.sandboxed(&real);
+        Ok(())
+    }
+
+    fn umask(&self, mask: Option<u32>) -> FsResult<u32> {
+        RealFs.umask(mask)
+    }
+
+    fn open_sync(
+        &self,
+        path: &Path,
+        options: OpenOptions,
+        access_check: Option<AccessCheckCb>,
+    ) -> FsResult<Rc<dyn File>> {
+        RealFs.open_sync(&self.real(path), options, access_check)
+    }
+
+    async fn open_async<'a>(
+        &'a self,
+        path: PathBuf,
+        options: OpenOptions,
+        access_check: Option<AccessCheckCb<'a>>,
+    ) -> FsResult<Rc<dyn File>> {
+        RealFs
+            .open_async(self.real(&path), options, access_check)
+            .await
+    }
+
+    fn mkdir_sync(&self, path: &Path, recursive: bool, mode: u32) -> FsResult<()> {
+        RealFs.mkdir_sync(&self.real(path), recursive, mode)
+    }
+
+    async fn mkdir_async(&self, path: PathBuf, recursive: bool, mode: u32) -> FsResult<()> {
+        RealFs.mkdir_async(self.real(&path), recursive, mode).await
+    }
+
+    fn chmod_sync(&self, path: &Path, mode: u32) -> FsResult<()> {
+        RealFs.chmod_sync(&self.real(path), mode)
+    }
+
+    async fn chmod_async(&self, path: PathBuf, mode: u32) -> FsResult<()> {
+        RealFs.chmod_async(self.real(&path), mode).await
+    }
+
+    fn chown_sync(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> FsResult<()> {
+        RealFs.chown_sync(&self.real(path), uid, gid)
+    }
+
+    async fn chown_async(&self, path: PathBuf, uid: Option<u32>, gid: Option<u32>) -> FsResult<()> {
+        RealFs.chown_async(self.real(&path), uid, gid).await
+    }
+
+    fn lchown_sync(&self, path: &Path, uid: Option<u32>, gid: Option<u32>) -> FsResult<()> {
+        RealFs.lchown_sync(&self.real(path), uid, gid)
+    }
+
+    async fn lchown_async(
+        &self,
+        path: PathBuf,
+        uid: Option<u32>,
+        gid: Option<u32>,
+    ) -> FsResult<()> {
+        RealFs.lchown_async(self.real(&path), uid, gid).await
+    }
+
+    fn remove_sync(&self, path: &Path, recursive: bool) -> FsResult<()> {
+        RealFs.remove_sync(&self.real(path), recursive)
+    }
+
+    async fn remove_async(&self, path: PathBuf, recursive: bool) -> FsResult<()> {
+        RealFs.remove_async(self.real(&path), recursive).await
+    }
+
+    fn copy_file_sync(&self, oldpath: &Path, newpath: &Path) -> FsResult<()> {
+        RealFs.copy_file_sync(&self.real(oldpath), &self.real(newpath))
+    }
+
+    async fn copy_file_async(&self, oldpath: PathBuf, newpath: PathBuf) -> FsResult<()> {
+        RealFs
+            .copy_file_async(self.real(&oldpath), self.real(&newpath))
+            .await
+    }
+
+    fn cp_sync(&self, path: &Path, new_path: &Path) -> FsResult<()> {
+        RealFs.cp_sync(&self.real(path), &self.real(new_path))
+    }
+
+    async fn cp_async(&self, path: PathBuf, new_path: PathBuf) -> FsResult<()> {
+        RealFs
+            .cp_async(self.real(&path), self.real(&new_path))
+            .await
+    }
+
+    fn stat_sync(&self, path: &Path) -> FsResult<FsStat> {
+        RealFs.stat_sync(&self.real(path))
+    }
+
+    async fn stat_async(&self, path: PathBuf) -> FsResult<FsStat> {
+        RealFs.stat_async(self.real(&path)).await
+    }
+
+    fn lstat_sync(&self, path: &Path) -> FsResult<FsStat> {
+        RealFs.lstat_sync(&self.real(path))
+    }
+
+    async fn lstat_async(&self, path: PathBuf) -> FsResult<FsStat> {
+        RealFs.lstat_async(self.real(&path)).await
+    }
+
+    fn realpath_sync(&self, path: &Path) -> FsResult<PathBuf> {
+        let real = RealFs.realpath_sync(&self.real(path))?;
+        Ok(self.sandboxed(&real))
+    }
+
+    async fn realpath_async(&self, path: PathBuf) -> FsResult<PathBuf> {
+        let real = RealFs.realpath_async(self.real(&path)).await?;
+        Ok(self.sandboxed(&real))
+    }
+
+    fn read_dir_sync(&self, path: &Path) -> FsResult<Vec<FsDirEntry>> {
+        RealFs.read_dir_sync(&self.real(path))
+    }
+
+    async fn read_dir_async(&self, path: PathBuf) -> FsResult<Vec<FsDirEntry>> {
+        RealFs.read_dir_async(self.real(&path)).await
+    }
+
+    fn rename_sync(&self, oldpath: &Path, newpath: &Path) -> FsResult<()> {
+        RealFs.rename_sync(&self.real(oldpath), &self.real(newpath))
+    }
+
+    async fn rename_async(&self, oldpath: PathBuf, newpath: PathBuf) -> FsResult<()> {
+        RealFs
+            .rename_async(self.real(&oldpath), self.real(&newpath))
+            .await
+    }
+
+    fn link_sync(&self, oldpath: &Path, newpath: &Path) -> FsResult<()> {
+        RealFs.link_sync(&self.real(oldpath), &self.real(newpath))
+    }
+
+    async fn link_async(&self, oldpath: PathBuf, newpath: PathBuf) -> FsResult<()> {
+        RealFs
+            .link_async(self.real(&oldpath), self.real(&newpath))
+            .await
+    }
+
+    // A link's target is resolved by the host, outside of the sandbox.
+    fn symlink_sync(
+        &self,
+        _oldpath: &Path,
+        _newpath: &Path,
+        _file_type: Option<FsFileType>,
+    ) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    async fn symlink_async(
+        &self,
+        _oldpath: PathBuf,
+        _newpath: PathBuf,
+        _file_type: Option<FsFileType>,
+    ) -> FsResult<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn read_link_sync(&self, path: &Path) -> FsResult<PathBuf> {
+        RealFs.read_link_sync(&self.real(path))
+    }
+
+    async fn read_link_async(&self, path: PathBuf) -> FsResult<PathBuf> {
+        RealFs.read_link_async(self.real(&path)).await
+    }
+
+    fn truncate_sync(&self, path: &Path, len: u64) -> FsResult<()> {
+        RealFs.truncate_sync(&self.real(path), len)
+    }
+
+    async fn truncate_async(&self, path: PathBuf, len: u64) -> FsResult<()> {
+        RealFs.truncate_async(self.real(&path), len).await
+    }
+
+    fn utime_sync(
+        &self,
+        path: &Path,
+        atime_secs: i64,
+        atime_nanos: u32,
+        mtime_secs: i64,
+        mtime_nanos: u32,
+    ) -> FsResult<()> {
+        RealFs.utime_sync(
+            &self.real(path),
+            atime_secs,
+            atime_nanos,
+            mtime_secs,
+            mtime_nanos,
+        )
+    }
+
+    async fn utime_async(
+        &self,
+        path: PathBuf,
+        atime_secs: i64,
+        atime_nanos: u32,
+        mtime_secs: i64,
+        mtime_nanos: u32,
+    ) -> FsResult<()> {
+        RealFs
+            .utime_async(
+                self.real(&path),
+                atime_secs,
+                atime_nanos,
+                mtime_secs,
+                mtime_nanos,
+            )
+            .await
+    }
+
+    fn lutime_sync(
+        &self,
+        path: &Path,
+        atime_secs: i64,
+        atime_nanos: u32,
+        mtime_secs: i64,
+        mtime_nanos: u32,
+    ) -> FsResult<()> {
+        RealFs.lutime_sync(
+            &self.real(path),
+            atime_secs,
+            atime_nanos,
+            mtime_secs,
+            mtime_nanos,
+        )
+    }
+
+    async fn lutime_async(
+        &self,
+        path: PathBuf,
+        atime_secs: i64,
+        atime_nanos: u32,
+        mtime_secs: i64,
+        mtime_nanos: u32,
+    ) -> FsResult<()> {
+        RealFs
+            .lutime_async(
+                self.real(&path),
+                atime_secs,
+                atime_nanos,
+                mtime_secs,
+                mtime_nanos,
+            )
+            .await
+    }
+}
+
+/// Refuses `file:` fetches, which would read the host filesystem rather
+/// than the sandbox.
+#[derive(Clone)]
+pub(crate) struct RefuseFileFetch;
+
+impl FetchHandler for RefuseFileFetch {
+    fn fetch_file(
+        &self,
+        _state: &mut OpState,
+        _url: &Url,
+    ) -> (CancelableResponseFuture, Option<Rc<CancelHandle>>) {
+        let error = custom_error(
+            "PermissionDenied",
+            "file: URLs can't be fetched in a filesystem sandbox",
+        );
+        (Box::pin(async move { Ok(Err(error)) }), None)
+    }
+}
@@ -0,0 +1,399 @@
+use anyhow::{anyhow, Error};
+use serde_json::Value;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, watch, Notify};
+
+use crate::cancel::CancellationHandle;
+use crate::cron::Cron;
+use crate::error::RuntimeError;
+use crate::executor::ExecutorPool;
+use crate::inputs::Inputs;
+
+/// When a job runs.
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    /// Every so often, counted from when the job was scheduled.
+    Interval(Duration),
+    Cron(Cron),
+}
+
+impl Trigger {
+    pub fn cron(expression: &str) -> Result<Self, Error> {
+        Ok(Trigger::Cron(Cron::parse(expression)?))
+    }
+
+    fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        match self {
+            Trigger::Interval(interval) => Some(after + (*interval).max(Duration::from_millis(1))),
+            Trigger::Cron(cron) => cron.next_after(after),
+        }
+    }
+
+    /// The last time the job came due after `due` and up to `now`, if it
+    /// did.
+    fn last_due(&self, due: SystemTime, now: SystemTime) -> Option<SystemTime> {
+        match self {
+            Trigger::Interval(interval) => {
+                let interval = (*interval).max(Duration::from_millis(1));
+                let elapsed = now.duration_since(due).ok()?;
+                let periods = elapsed.as_nanos() / interval.as_nanos();
+                let periods = u32::try_from(periods).ok().filter(|&n| n > 0)?;
+                Some(due + interval * periods)
+            }
+            Trigger::Cron(cron) => {
+                let mut last = None;
+                while let Some(next) = cron.next_after(last.unwrap_or(due)) {
+                    if next > now {
+                        break;
+                    }
+                    last = Some(next);
+                }
+                last
+            }
+        }
+    }
+
+    /// The first time the job comes due after `now`, following on from
+    /// `due` so intervals keep their phase however long runs take.
+    fn next_due(&self, due: SystemTime, now: SystemTime) -> Option<SystemTime> {
+        self.next_after(self.last_due(due, now).unwrap_or(due))
+    }
+}
+
+/// What happens when a job is due while its previous run is still going.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overlap {
+    /// The run is skipped.
+    #[default]
+    Skip,
+    /// A run starts as soon as the previous one finished. Runs due meanwhile
+    /// are merged into that one, so a slow job doesn't build up a backlog.
+    Queue,
+}
+
+type ResultCallback = dyn Fn(&Result<Value, Error>) + Send + Sync;
+
+/// A function to run on a [`Trigger`], with the same inputs every time.
+#[derive(Clone)]
+pub struct ScheduledJob {
+    function: PathBuf,
+    trigger: Trigger,
+    inputs: Inputs,
+    overlap: Overlap,
+    timeout: Option<Duration>,
+    on_result: Option<Arc<ResultCallback>>,
+}
+
+impl ScheduledJob {
+    pub fn new(function: impl Into<PathBuf>, trigger: Trigger) -> Self {
+        Self {
+            function: function.into(),
+            trigger,
+            inputs: Inputs::new(),
+            overlap: Overlap::default(),
+            timeout: None,
+            on_result: None,
+        }
+    }
+
+    pub fn inputs(mut self, inputs: impl Into<Inputs>) -> Self {
+        self.inputs = inputs.into();
+        self
+    }
+
+    pub fn overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Cancels a run that takes longer, which then fails with
+    /// `RuntimeError::Timeout`. Applies on top of the pool's own timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Called with the result of every run. Without it, failures are
+    /// logged.
+    pub fn on_result(
+        mut self,
+        on_result: impl Fn(&Result<Value, Error>) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_result = Some(Arc::new(on_result));
+        self
+    }
+}
+
+impl fmt::Debug for ScheduledJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScheduledJob")
+            .field("function", &self.function)
+            .field("trigger", &self.trigger)
+            .field("overlap", &self.overlap)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// Controls a scheduled job. Cheap to clone.
+#[derive(Clone)]
+pub struct JobHandle(Arc<Control>);
+
+#[derive(Default)]
+struct Control {
+    paused: AtomicBool,
+    cancelled: AtomicBool,
+    wake: Notify,
+    /// Cancels the run in progress, if any.
+    running: Mutex<Option<CancellationHandle>>,
+}
+
+impl JobHandle {
+    /// Skips runs that come due until [`resume`](Self::resume) is called.
+    /// A run in progress finishes.
+    pub fn pause(&self) {
+        self.0.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.0.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::SeqCst)
+    }
+
+    /// Unschedules the job for good, cancelling a run in progress.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        if let Some(run) = &*self.0.running.lock().unwrap() {
+            run.cancel();
+        }
+        self.0.wake.notify_one();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for JobHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JobHandle")
+            .field("paused", &self.is_paused())
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Runs jobs on an [`ExecutorPool`] as their triggers come due, for
+/// periodic work without an external scheduler. Triggers are kept on a
+/// thread of the scheduler's own, the runs happen on the pool, with the
+/// pool's options.
+pub struct Scheduler {
+    jobs: Option<mpsc::UnboundedSender<(ScheduledJob, JobHandle)>>,
+    stop: watch::Sender<bool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Scheduler {
+    pub fn new(pool: Arc<ExecutorPool>) -> Result<Self, Error> {
+        let (jobs, mut scheduled) = mpsc::unbounded_channel::<(ScheduledJob, JobHandle)>();
+        let (stop, stopping) = watch::channel(false);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        let thread = std::thread::Builder::new()
+            .name("scheduler".to_string())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let mut drivers = vec![];
+                    while let Some((job, handle)) = scheduled.recv().await {
+                        let driver = drive(pool.clone(), job, handle, stopping.clone());
+                        drivers.push(tokio::spawn(driver));
+                    }
+                    for driver in drivers {
+                        let _ = driver.await;
+                    }
+                })
+            })?;
+        Ok(Self {
+            jobs: Some(jobs),
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn schedule(&self, job: ScheduledJob) -> Result<JobHandle, Error> {
+        let handle = JobHandle(Arc::default());
+        self.jobs
+            .as_ref()
+            .ok_or_else(|| anyhow!("scheduler is shut down"))?
+            .send((job, handle.clone()))
+            .map_err(|_| anyhow!("scheduler thread stopped"))?;
+        Ok(handle)
+    }
+
+    /// Stops triggering jobs and waits for the runs in progress to finish.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let _ = self.stop.send(true);
+        self.jobs.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("scheduler thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+async fn drive(
+    pool: Arc<ExecutorPool>,
+    job: ScheduledJob,
+    handle: JobHandle,
+    mut stopping: watch::Receiver<bool>,
+) {
+    let control = &handle.0;
+    let mut due = job.trigger.next_after(SystemTime::now());
+    while let Some(at) = due {
+        let wait = at.duration_since(SystemTime::now()).unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = control.wake.notified() => {}
+            _ = stopping.changed() => {}
+        }
+        if control.cancelled.load(Ordering::SeqCst) || *stopping.borrow() {
+            break;
+        }
+        if SystemTime::now() < at {
+            continue;
+        }
+        if control.paused.load(Ordering::SeqCst) {
+            log::debug!("skipping paused job {}", job.function.display());
+            due = job.trigger.next_due(at, SystemTime::now());
+            continue;
+        }
+
+        // The due time of the run, runs missed while it went on are due
+        // after it.
+        let mut run_for = at;
+        loop {
+            let result = run(&pool, &job, control).await;
+            match &job.on_result {
+                Some(on_result) => on_result(&result),
+                None => {
+                    if let Err(e) = &result {
+                        log::warn!(
+                            "scheduled run of {} failed: {:#}",
+                            job.function.display(),
+                            e
+                        );
+                    }
+                }
+            }
+            let Some(missed) = job.trigger.last_due(run_for, SystemTime::now()) else {
+                break;
+            };
+            if control.cancelled.load(Ordering::SeqCst) || *stopping.borrow() {
+                break;
+            }
+            match job.overlap {
+                Overlap::Queue if !control.paused.load(Ordering::SeqCst) => {
+                    log::debug!("running queued job {}", job.function.display());
+                    run_for = missed;
+                }
+                _ => {
+                    log::debug!("skipped overlapping run of {}", job.function.display());
+                    break;
+                }
+            }
+        }
+        due = job.trigger.next_due(run_for, SystemTime::now());
+    }
+    log::debug!("unscheduled {}", job.function.display());
+}
+
+async fn run(pool: &ExecutorPool, job: &ScheduledJob, control: &Control) -> Result<Value, Error> {
+    let cancellation = CancellationHandle::new();
+    *control.running.lock().unwrap() = Some(cancellation.clone());
+    let result = pool.submit_cancellable(
+        job.function.clone(),
+        job.inputs.clone(),
+        cancellation.clone(),
+    );
+    tokio::pin!(result);
+    let result = match job.timeout {
+        Some(limit) => tokio::select! {
+            result = &mut result => result,
+            _ = tokio::time::sleep(limit) => {
+                cancellation.cancel();
+                // Waits for the pool to stop it, so runs don't pile up.
+                let _ = result.await;
+                Err(RuntimeError::Timeout { limit }.into())
+            }
+        },
+        None => result.await,
+    };
+    control.running.lock().unwrap().take();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn intervals_keep_their_phase() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_000);
+        let second = Duration::from_secs(1);
+        let trigger = Trigger::Interval(10 * second);
+        // A run taking 3s doesn't push the next one back.
+        assert_eq!(trigger.last_due(start, start + 3 * second), None);
+        assert_eq!(
+            trigger.next_due(start, start + 3 * second),
+            Some(start + 10 * second)
+        );
+        // Runs due during a long one come down to the last of them.
+        assert_eq!(
+            trigger.last_due(start, start + 25 * second),
+            Some(start + 20 * second)
+        );
+        assert_eq!(
+            trigger.next_due(start, start + 25 * second),
+            Some(start + 30 * second)
+        );
+    }
+
+    #[test]
+    fn cron_runs_missed_during_a_run() {
+        // 2024-01-01T00:00:00Z.
+        let start = UNIX_EPOCH + Duration::from_secs(1_704_067_200);
+        let trigger = Trigger::cron("*/5 * * * *").unwrap();
+        assert_eq!(trigger.last_due(start, start + 4 * MINUTE), None);
+        assert_eq!(
+            trigger.last_due(start, start + 12 * MINUTE),
+            Some(start + 10 * MINUTE)
+        );
+        assert_eq!(
+            trigger.next_due(start, start + 12 * MINUTE),
+            Some(start + 15 * MINUTE)
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use jsonschema::JSONSchema;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::error::{RuntimeError, SchemaViolation};
+
+/// How many compiled schemas are kept, the oldest being dropped first.
+const CACHE_SIZE: usize = 64;
+
+#[derive(Default)]
+struct SchemaCache {
+    compiled: HashMap<(String, bool), Arc<JSONSchema>>,
+    /// Keys of `compiled`, oldest first.
+    order: VecDeque<(String, bool)>,
+}
+
+fn cache() -> &'static Mutex<SchemaCache> {
+    static CACHE: OnceLock<Mutex<SchemaCache>> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Compiles `schema`, reusing a recent compilation of the same schema when there is one.
+///
+/// In strict mode every object schema that does not say otherwise gets
+/// `"additionalProperties": false`, so unknown keys are rejected. The
+/// properties its `allOf`, `anyOf`, `oneOf` and `if`/`then`/`else`
+/// subschemas declare count as known, and those subschemas are not closed
+/// themselves, as they describe the same object. Schemas behind a `$ref`
+/// are left as they are, and so are the objects using them.
+pub(crate) fn compile(schema: &Value, strict: bool) -> Result<Arc<JSONSchema>, RuntimeError> {
+    let key = (schema.to_string(), strict);
+    if let Some(compiled) = cache().lock().unwrap().compiled.get(&key) {
+        return Ok(compiled.clone());
+    }
+
+    log::debug!("compiling schema");
+    let closed;
+    let schema = if strict {
+        closed = close_objects(schema, true);
+        &closed
+    } else {
+        schema
+    };
+    let compiled = Arc::new(
+        JSONSchema::compile(schema).map_err(|e| RuntimeError::InvalidSchema(e.to_string()))?,
+    );
+    let mut cache = cache().lock().unwrap();
+    if cache
+        .compiled
+        .insert(key.clone(), compiled.clone())
+        .is_none()
+    {
+        cache.order.push_back(key);
+        if cache.order.len() > CACHE_SIZE {
+            let oldest = cache.order.pop_front().unwrap();
+            cache.compiled.remove(&oldest);
+        }
+    }
+    Ok(compiled)
+}
+
+pub(crate) fn violations(schema: &JSONSchema, value: &Value) -> Vec<SchemaViolation> {
+    match schema.validate(value) {
+        Ok(()) => vec![],
+        Err(errors) => errors
+            .map(|e| SchemaViolation {
+                path: e.instance_path.to_string(),
+                message: e.to_string(),
+            })
+            .collect(),
+    }
+}
+
+/// `schema` with its object schemas closed, itself included when `close`
+/// is set.
+fn close_objects(schema: &Value, close: bool) -> Value {
+    let Value::Object(map) = schema else {
+        return schema.clone();
+    };
+
+    let mut closed = serde_json::Map::new();
+    for (keyword, value) in map {
+        let value = match keyword.as_str() {
+            "properties" | "patternProperties" => match value {
+                Value::Object(schemas) => Value::Object(
+                    schemas
+                        .iter()
+                        .map(|(name, schema)| (name.clone(), close_objects(schema, true)))
+                        .collect(),
+                ),
+                other => other.clone(),
+            },
+            "allOf" | "anyOf" | "oneOf" => match value {
+                Value::Array(schemas) => Value::Array(
+                    schemas
+                        .iter()
+                        .map(|schema| close_objects(schema, false))
+                        .collect(),
+                ),
+                other => other.clone(),
+            },
+            "prefixItems" => match value {
+                Value::Array(schemas) => Value::Array(
+                    schemas
+                        .iter()
+                        .map(|schema| close_objects(schema, true))
+                        .collect(),
+                ),
+                other => other.clone(),
+            },
+            "items" | "additionalItems" => close_objects(value, true),
+            "not" | "if" | "then" | "else" => close_objects(value, false),
+            _ => value.clone(),
+        };
+        closed.insert(keyword.clone(), value);
+    }
+
+    if !close || map.contains_key("additionalProperties") {
+        return Value::Object(closed);
+    }
+    let mut known = Known::default();
+    // Properties behind a `$ref` aren't known, closing would reject them.
+    if !known.collect(schema) || !known.is_object {
+        return Value::Object(closed);
+    }
+    for (keyword, names) in [
+        ("properties", known.properties),
+        ("patternProperties", known.patterns),
+    ] {
+        if names.is_empty() {
+            continue;
+        }
+        let Value::Object(schemas) = closed
+            .entry(keyword)
+            .or_insert_with(|| Value::Object(Default::default()))
+        else {
+            continue;
+        };
+        for name in names {
+            schemas.entry(name).or_insert(Value::Bool(true));
+        }
+    }
+    closed.insert("additionalProperties".into(), Value::Bool(false));
+    Value::Object(closed)
+}
+
+/// What an object schema and the subschemas applying to the same object
+/// declare.
+#[derive(Default)]
+struct Known {
+    is_object: bool,
+    properties: Vec<String>,
+    patterns: Vec<String>,
+}
+
+impl Known {
+    /// Returns false when part of the schema is a `$ref`.
+    fn collect(&mut self, schema: &Value) -> bool {
+        let Value::Object(map) = schema else {
+            return true;
+        };
+        if map.contains_key("$ref") {
+            return false;
+        }
+        self.is_object |=
+            map.contains_key("properties") || map.get("type") == Some(&Value::from("object"));
+        for (keyword, names) in [
+            ("properties", &mut self.properties),
+            ("patternProperties", &mut self.patterns),
+        ] {
+            if let Some(Value::Object(schemas)) = map.get(keyword) {
+                names.extend(schemas.keys().cloned());
+            }
+        }
+        for keyword in ["allOf", "anyOf", "oneOf"] {
+            if let Some(Value::Array(schemas)) = map.get(keyword) {
+                if !schemas.iter().all(|schema| self.collect(schema)) {
+                    return false;
+                }
+            }
+        }
+        ["if", "then", "else"]
+            .into_iter()
+            .filter_map(|keyword| map.get(keyword))
+            .all(|schema| self.collect(schema))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn accepts(schema: &Value, value: Value) -> bool {
+        violations(&compile(schema, true).unwrap(), &value).is_empty()
+    }
+
+    #[test]
+    fn strict_mode_rejects_unknown_properties() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "address": { "properties": { "city": { "type": "string" } } }
+            }
+        });
+        assert!(accepts(
+            &schema,
+            json!({ "name": "a", "address": { "city": "b" } })
+        ));
+        assert!(!accepts(&schema, json!({ "name": "a", "extra": 1 })));
+        assert!(!accepts(
+            &schema,
+            json!({ "address": { "city": "b", "zip": "c" } })
+        ));
+    }
+
+    #[test]
+    fn strict_mode_keeps_composed_properties() {
+        let schema = json!({
+            "type": "object",
+            "allOf": [
+                { "properties": { "a": { "type": "number" } } },
+                { "properties": { "b": { "type": "number" } } }
+            ]
+        });
+        assert!(accepts(&schema, json!({ "a": 1, "b": 2 })));
+        assert!(!accepts(&schema, json!({ "a": 1, "c": 3 })));
+
+        let schema = json!({
+            "oneOf": [
+                { "properties": { "kind": { "const": "a" }, "a": {} }, "required": ["kind"] },
+                { "properties": { "kind": { "const": "b" }, "b": {} }, "required": ["kind"] }
+            ]
+        });
+        assert!(accepts(&schema, json!({ "kind": "a", "a": 1 })));
+        assert!(!accepts(&schema, json!({ "kind": "a", "z": 1 })));
+    }
+
+    #[test]
+    fn strict_mode_leaves_referenced_properties_open() {
+        let schema = json!({
+            "$defs": { "base": { "properties": { "id": {} } } },
+            "allOf": [{ "$ref": "#/$defs/base" }],
+            "properties": { "name": {} }
+        });
+        assert!(accepts(&schema, json!({ "id": 1, "name": "a" })));
+    }
+
+    #[test]
+    fn cache_is_bounded() {
+        for n in 0..CACHE_SIZE + 10 {
+            compile(&json!({ "maxLength": n }), false).unwrap();
+        }
+        let cache = cache().lock().unwrap();
+        assert!(cache.compiled.len() <= CACHE_SIZE);
+        assert_eq!(cache.compiled.len(), cache.order.len());
+    }
+}
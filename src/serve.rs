@@ -0,0 +1,222 @@
+use anyhow::Error;
+use bytes::Bytes;
+use deno_core::error::JsError;
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Incoming;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::{watch, Semaphore};
+use tokio::task::JoinSet;
+
+use crate::error::RuntimeError;
+use crate::inputs::{Inputs, DEFAULT_MAX_INPUT_BYTES};
+use crate::options::RunOptions;
+
+/// Serves functions over HTTP. `POST /invoke/<name>` runs the function
+/// registered as `name` with the request's JSON object as inputs and
+/// responds with its result as JSON. Failed runs respond with
+/// `{"error": {"kind", "message"}}` and a status matching the error.
+///
+/// Each invocation runs on a thread and isolate of its own. Invocations
+/// past the concurrency limit wait for a slot, and a client going away
+/// cancels its invocation.
+pub struct InvokeServer {
+    routes: HashMap<String, PathBuf>,
+    options: RunOptions,
+    concurrency: usize,
+}
+
+struct Shared {
+    routes: HashMap<String, PathBuf>,
+    options: RunOptions,
+    slots: Semaphore,
+}
+
+impl InvokeServer {
+    /// Server without routes, running four invocations at a time.
+    pub fn new(options: RunOptions) -> Self {
+        Self {
+            routes: HashMap::new(),
+            options,
+            concurrency: 4,
+        }
+    }
+
+    /// Serves `function` at `/invoke/<name>`, replacing any earlier
+    /// function of that name.
+    pub fn route(mut self, name: impl Into<String>, function: impl Into<PathBuf>) -> Self {
+        self.routes.insert(name.into(), function.into());
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Accepts connections until `shutdown` completes. On shutdown no
+    /// further connections are accepted and idle ones are closed, but
+    /// requests already received are answered before returning.
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), Error> {
+        let shared = Arc::new(Shared {
+            routes: self.routes,
+            options: self.options,
+            slots: Semaphore::new(self.concurrency),
+        });
+        let (closing, closed) = watch::channel(());
+        let mut connections = JoinSet::new();
+        tokio::pin!(shutdown);
+
+        loop {
+            let (stream, peer) = tokio::select! {
+                _ = &mut shutdown => break,
+                accepted = listener.accept() => accepted?,
+            };
+            log::debug!("connection from {}", peer);
+            let shared = shared.clone();
+            let mut closed = closed.clone();
+            connections.spawn(async move {
+                let service = hyper::service::service_fn(move |request| {
+                    let shared = shared.clone();
+                    async move { Ok::<_, std::convert::Infallible>(shared.handle(request).await) }
+                });
+                let connection = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service);
+                tokio::pin!(connection);
+                let served = tokio::select! {
+                    served = connection.as_mut() => served,
+                    _ = closed.changed() => {
+                        connection.as_mut().graceful_shutdown();
+                        connection.await
+                    }
+                };
+                if let Err(e) = served {
+                    log::debug!("connection from {} failed: {}", peer, e);
+                }
+            });
+            // Reap finished connections so the set doesn't grow unbounded.
+            while connections.try_join_next().is_some() {}
+        }
+
+        log::debug!(
+            "shutting down, waiting for {} connections",
+            connections.len()
+        );
+        let _ = closing.send(());
+        while connections.join_next().await.is_some() {}
+        Ok(())
+    }
+}
+
+impl Shared {
+    async fn handle(&self, request: Request<Incoming>) -> Response<Full<Bytes>> {
+        let Some(name) = request.uri().path().strip_prefix("/invoke/") else {
+            return failure(StatusCode::NOT_FOUND, "not_found", "no such route");
+        };
+        let name = name.to_string();
+        let Some(function) = self.routes.get(&name).cloned() else {
+            let message = format!("no function named {}", name);
+            return failure(StatusCode::NOT_FOUND, "not_found", &message);
+        };
+        if request.method() != Method::POST {
+            return failure(
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method_not_allowed",
+                "functions are invoked with POST",
+            );
+        }
+
+        #[cfg(feature = "otel")]
+        let options = RunOptions {
+            trace_context: crate::otel::TraceContext::from_headers(|name| {
+                request.headers().get(name)?.to_str().ok()
+            }),
+            ..self.options.clone()
+        };
+        #[cfg(not(feature = "otel"))]
+        let options = self.options.clone();
+        let limit = self
+            .options
+            .max_input_bytes
+            .unwrap_or(DEFAULT_MAX_INPUT_BYTES);
+        let body = match Limited::new(request.into_body(), limit).collect().await {
+            Ok(body) => body.to_bytes(),
+            Err(e) => {
+                return failure(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    "input_too_large",
+                    &e.to_string(),
+                )
+            }
+        };
+        let inputs = if body.is_empty() {
+            HashMap::new()
+        } else {
+            match serde_json::from_slice::<HashMap<String, Value>>(&body) {
+                Ok(inputs) => inputs,
+                Err(e) => {
+                    let message = format!("body must be a json object: {}", e);
+                    return failure(StatusCode::BAD_REQUEST, "invalid_body", &message);
+                }
+            }
+        };
+
+        let Ok(_slot) = self.slots.acquire().await else {
+            return failure(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "shut_down",
+                "server is closing",
+            );
+        };
+        log::debug!("invoking {}", name);
+        match crate::run(function, Inputs::from(inputs), options).await {
+            Ok(value) => respond(StatusCode::OK, &value),
+            Err(e) => {
+                let (status, kind) = status(&e);
+                failure(status, kind, &format!("{:#}", e))
+            }
+        }
+    }
+}
+
+fn status(error: &Error) -> (StatusCode, &'static str) {
+    match error.downcast_ref::<RuntimeError>() {
+        Some(error @ RuntimeError::InputTooLarge { .. }) => {
+            (StatusCode::PAYLOAD_TOO_LARGE, error.kind())
+        }
+        Some(error @ RuntimeError::InputValidation { .. }) => {
+            (StatusCode::UNPROCESSABLE_ENTITY, error.kind())
+        }
+        Some(error @ RuntimeError::Timeout { .. }) => (StatusCode::GATEWAY_TIMEOUT, error.kind()),
+        Some(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.kind()),
+        None if error.is::<JsError>() => (StatusCode::INTERNAL_SERVER_ERROR, "js_exception"),
+        None => (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+    }
+}
+
+fn failure(status: StatusCode, kind: &str, message: &str) -> Response<Full<Bytes>> {
+    respond(
+        status,
+        &json!({ "error": { "kind": kind, "message": message } }),
+    )
+}
+
+fn respond(status: StatusCode, body: &Value) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(Bytes::from(body.to_string())));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
@@ -0,0 +1,316 @@
+use anyhow::{Context, Error};
+use deno_ast::swc::ast::{
+    Decl, DefaultDecl, Expr, Function, ModuleDecl, ModuleExportName, ModuleItem, Pat, Stmt,
+    TsKeywordTypeKind, TsLit, TsType, TsTypeAnn, TsTypeElement, TsUnionOrIntersectionType,
+};
+use deno_ast::{MediaType, ParseParams, ParsedSource, SourceRanged, SourceRangedForSpanned};
+use deno_core::{resolve_import, ModuleSpecifier};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::file_url;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SignatureInfo {
+    /// Module the entrypoint is declared in, which differs from the inspected
+    /// module when the entrypoint is re-exported.
+    pub specifier: ModuleSpecifier,
+    pub is_async: bool,
+    pub params: Vec<ParamInfo>,
+    pub return_type: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParamInfo {
+    /// Identifier of the parameter, or the pattern source for destructured ones.
+    pub name: String,
+    pub type_annotation: Option<String>,
+    /// JSON Schema shaped description of the annotated type, where it could be derived.
+    pub structure: Option<Value>,
+    pub optional: bool,
+}
+
+/// Statically extracts the signature of the `main` export of the module at `path`.
+///
+/// Nothing is executed. Re-exports (`export { main } from "./impl.ts"`) are
+/// followed one hop for local files. Returns `None` when no function export
+/// named `main` can be found.
+pub fn inspect_signature(path: &Path) -> Result<Option<SignatureInfo>, Error> {
+    let specifier = file_url::path_to_specifier(path)?;
+    inspect_export(&specifier, "main", 1)
+}
+
+fn inspect_export(
+    specifier: &ModuleSpecifier,
+    export: &str,
+    hops_left: usize,
+) -> Result<Option<SignatureInfo>, Error> {
+    let parsed = parse(specifier)?;
+
+    let mut local_name = None;
+    for item in &parsed.module().body {
+        let ModuleItem::ModuleDecl(decl) = item else {
+            continue;
+        };
+        match decl {
+            ModuleDecl::ExportDecl(export_decl) => {
+                if let Some(info) = declared_function(&parsed, &export_decl.decl, export) {
+                    return Ok(Some(info));
+                }
+            }
+            ModuleDecl::ExportDefaultDecl(default_decl) if export == "default" => {
+                if let DefaultDecl::Fn(fn_expr) = &default_decl.decl {
+                    return Ok(Some(function_info(&parsed, &fn_expr.function)));
+                }
+            }
+            ModuleDecl::ExportDefaultExpr(default_expr) if export == "default" => {
+                if let Some(info) = expr_function(&parsed, &default_expr.expr) {
+                    return Ok(Some(info));
+                }
+            }
+            ModuleDecl::ExportNamed(named) => {
+                for specifier_decl in named.specifiers.iter().filter_map(|s| s.as_named()) {
+                    let exported = specifier_decl
+                        .exported
+                        .as_ref()
+                        .unwrap_or(&specifier_decl.orig);
+                    if export_name(exported) != export {
+                        continue;
+                    }
+                    let orig = export_name(&specifier_decl.orig);
+                    match &named.src {
+                        Some(src) if hops_left > 0 => {
+                            let target = resolve(&src.value, specifier)?;
+                            return follow(&target, &orig, hops_left - 1);
+                        }
+                        Some(_) => return Ok(None),
+                        None => local_name = Some(orig),
+                    }
+                }
+            }
+            ModuleDecl::ExportAll(all) if hops_left > 0 => {
+                let target = resolve(&all.src.value, specifier)?;
+                if let Some(info) = follow(&target, export, hops_left - 1)? {
+                    return Ok(Some(info));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // `function main() {}` declared locally and exported through `export { main }`
+    let Some(local_name) = local_name else {
+        return Ok(None);
+    };
+    Ok(parsed.module().body.iter().find_map(|item| match item {
+        ModuleItem::Stmt(Stmt::Decl(decl)) => declared_function(&parsed, decl, &local_name),
+        _ => None,
+    }))
+}
+
+fn follow(
+    target: &ModuleSpecifier,
+    export: &str,
+    hops_left: usize,
+) -> Result<Option<SignatureInfo>, Error> {
+    if target.scheme() != "file" {
+        log::debug!("not following re-export from remote module {}", target);
+        return Ok(None);
+    }
+    inspect_export(target, export, hops_left)
+}
+
+fn resolve(src: &str, referrer: &ModuleSpecifier) -> Result<ModuleSpecifier, Error> {
+    match file_url::windows_path_specifier(src) {
+        Some(specifier) => Ok(specifier),
+        None => Ok(resolve_import(src, referrer.as_str())?),
+    }
+}
+
+fn parse(specifier: &ModuleSpecifier) -> Result<ParsedSource, Error> {
+    let path = file_url::specifier_to_path(specifier)?;
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("could not read {}", path.display()))?;
+    Ok(deno_ast::parse_module(ParseParams {
+        specifier: specifier.clone(),
+        text: Arc::from(text),
+        media_type: MediaType::from_specifier(specifier),
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })?)
+}
+
+fn export_name(name: &ModuleExportName) -> String {
+    match name {
+        ModuleExportName::Ident(ident) => ident.sym.to_string(),
+        ModuleExportName::Str(s) => s.value.to_string(),
+    }
+}
+
+fn declared_function(parsed: &ParsedSource, decl: &Decl, name: &str) -> Option<SignatureInfo> {
+    match decl {
+        Decl::Fn(fn_decl) if fn_decl.ident.sym == *name => {
+            Some(function_info(parsed, &fn_decl.function))
+        }
+        Decl::Var(var_decl) => var_decl.decls.iter().find_map(|declarator| {
+            let Pat::Ident(ident) = &declarator.name else {
+                return None;
+            };
+            if ident.id.sym != *name {
+                return None;
+            }
+            expr_function(parsed, declarator.init.as_ref()?)
+        }),
+        _ => None,
+    }
+}
+
+fn expr_function(parsed: &ParsedSource, expr: &Expr) -> Option<SignatureInfo> {
+    match expr {
+        Expr::Fn(fn_expr) => Some(function_info(parsed, &fn_expr.function)),
+        Expr::Arrow(arrow) => Some(SignatureInfo {
+            specifier: parsed.specifier().clone(),
+            is_async: arrow.is_async,
+            params: arrow
+                .params
+                .iter()
+                .map(|pat| param_info(parsed, pat))
+                .collect(),
+            return_type: arrow.return_type.as_ref().map(|t| type_text(parsed, t)),
+        }),
+        Expr::Paren(paren) => expr_function(parsed, &paren.expr),
+        _ => None,
+    }
+}
+
+fn function_info(parsed: &ParsedSource, function: &Function) -> SignatureInfo {
+    SignatureInfo {
+        specifier: parsed.specifier().clone(),
+        is_async: function.is_async,
+        params: function
+            .params
+            .iter()
+            .map(|param| param_info(parsed, &param.pat))
+            .collect(),
+        return_type: function.return_type.as_ref().map(|t| type_text(parsed, t)),
+    }
+}
+
+fn param_info(parsed: &ParsedSource, pat: &Pat) -> ParamInfo {
+    let text_info = parsed.text_info_lazy();
+    let (name, type_ann, optional) = match pat {
+        Pat::Ident(ident) => (ident.id.sym.to_string(), &ident.type_ann, ident.id.optional),
+        Pat::Object(object) => (
+            object.range().text_fast(text_info).to_string(),
+            &object.type_ann,
+            object.optional,
+        ),
+        Pat::Array(array) => (
+            array.range().text_fast(text_info).to_string(),
+            &array.type_ann,
+            array.optional,
+        ),
+        Pat::Rest(rest) => (
+            rest.arg.range().text_fast(text_info).to_string(),
+            &rest.type_ann,
+            true,
+        ),
+        Pat::Assign(assign) => {
+            let inner = param_info(parsed, &assign.left);
+            return ParamInfo {
+                optional: true,
+                ..inner
+            };
+        }
+        other => (other.range().text_fast(text_info).to_string(), &None, false),
+    };
+
+    ParamInfo {
+        name,
+        type_annotation: type_ann.as_ref().map(|t| type_text(parsed, t)),
+        structure: type_ann.as_ref().and_then(|t| structure(&t.type_ann)),
+        optional,
+    }
+}
+
+fn type_text(parsed: &ParsedSource, type_ann: &TsTypeAnn) -> String {
+    type_ann
+        .type_ann
+        .range()
+        .text_fast(parsed.text_info_lazy())
+        .to_string()
+}
+
+fn structure(ty: &TsType) -> Option<Value> {
+    match ty {
+        TsType::TsKeywordType(keyword) => match keyword.kind {
+            TsKeywordTypeKind::TsStringKeyword => Some(json!({ "type": "string" })),
+            TsKeywordTypeKind::TsNumberKeyword => Some(json!({ "type": "number" })),
+            TsKeywordTypeKind::TsBooleanKeyword => Some(json!({ "type": "boolean" })),
+            TsKeywordTypeKind::TsNullKeyword => Some(json!({ "type": "null" })),
+            TsKeywordTypeKind::TsObjectKeyword => Some(json!({ "type": "object" })),
+            TsKeywordTypeKind::TsAnyKeyword | TsKeywordTypeKind::TsUnknownKeyword => {
+                Some(json!({}))
+            }
+            _ => None,
+        },
+        TsType::TsLitType(lit) => match &lit.lit {
+            TsLit::Str(s) => Some(json!({ "const": s.value.as_str() })),
+            TsLit::Number(n) => Some(json!({ "const": n.value })),
+            TsLit::Bool(b) => Some(json!({ "const": b.value })),
+            _ => None,
+        },
+        TsType::TsArrayType(array) => Some(json!({
+            "type": "array",
+            "items": structure(&array.elem_type).unwrap_or_else(|| json!({})),
+        })),
+        TsType::TsParenthesizedType(paren) => structure(&paren.type_ann),
+        TsType::TsUnionOrIntersectionType(TsUnionOrIntersectionType::TsUnionType(union)) => {
+            let variants = union
+                .types
+                .iter()
+                .map(|t| structure(t))
+                .collect::<Option<Vec<_>>>()?;
+            let consts = variants
+                .iter()
+                .map(|v| v.get("const").cloned())
+                .collect::<Option<Vec<_>>>();
+            match consts {
+                Some(consts) => Some(json!({ "enum": consts })),
+                None => Some(json!({ "anyOf": variants })),
+            }
+        }
+        TsType::TsTypeLit(lit) => {
+            let mut properties = serde_json::Map::new();
+            let mut required = vec![];
+            for member in &lit.members {
+                let TsTypeElement::TsPropertySignature(prop) = member else {
+                    continue;
+                };
+                let key = match &*prop.key {
+                    Expr::Ident(ident) if !prop.computed => ident.sym.to_string(),
+                    Expr::Lit(deno_ast::swc::ast::Lit::Str(s)) => s.value.to_string(),
+                    _ => continue,
+                };
+                let value = prop
+                    .type_ann
+                    .as_ref()
+                    .and_then(|t| structure(&t.type_ann))
+                    .unwrap_or_else(|| json!({}));
+                if !prop.optional {
+                    required.push(Value::String(key.clone()));
+                }
+                properties.insert(key, value);
+            }
+            Some(json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            }))
+        }
+        _ => None,
+    }
+}
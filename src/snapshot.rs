@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Context, Error};
+use deno_core::{Extension, ExtensionFileSource};
+use deno_runtime::ops::bootstrap::SnapshotOptions;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::compress;
+use crate::console::{self, ConsoleCapture};
+use crate::host;
+use crate::platform::PlatformGuard;
+use crate::redact::Redactor;
+use crate::warning::Warnings;
+
+/// V8 startup snapshot of a bootstrapped runtime, host extension
+/// included, set as `RunOptions::snapshot` so workers skip evaluating the
+/// runtime's JS. Snapshots only work with the binary that built them.
+/// Their bytes live for the rest of the process.
+#[derive(Clone, Copy)]
+pub struct Snapshot(pub(crate) &'static [u8]);
+
+impl Snapshot {
+    /// Bootstraps a runtime and snapshots it. Takes a few hundred
+    /// milliseconds, so build once and share the result.
+    pub fn build() -> Result<Self, Error> {
+        Self::build_inner(None)
+    }
+
+    /// Like [`build`](Self::build), with `script` evaluated as a classic
+    /// script before the snapshot is taken, so the globals it sets up are
+    /// there from the start in every worker. It runs ahead of the runtime's
+    /// bootstrap, without I/O and without the `Deno` namespace.
+    pub fn build_with_warmup(script: &str) -> Result<Self, Error> {
+        Self::build_inner(Some(script))
+    }
+
+    fn build_inner(warmup: Option<&str>) -> Result<Self, Error> {
+        let _platform = PlatformGuard::acquire()?;
+        let dir = std::env::temp_dir().join(format!("experimental_runtime-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join("runtime.snap");
+        let extension = host::host::init_ops_and_esm(
+            host::HostFiles {
+                paths: Default::default(),
+                read_limit: host::DEFAULT_FILE_READ_LIMIT,
+            },
+            None,
+            ConsoleCapture {
+                sink: None,
+                event_limit: console::DEFAULT_EVENT_LIMIT,
+                invocation: 0,
+                warnings: Warnings::default(),
+                redactor: Redactor::default(),
+            },
+            None,
+            None,
+        );
+        let mut extensions = vec![extension];
+        if let Some(script) = warmup {
+            // Holds no ops, so workers booting from the snapshot don't need it.
+            extensions.push(Extension {
+                name: "warmup",
+                js_files: vec![ExtensionFileSource::new_computed(
+                    "ext:warmup/warmup.js",
+                    Arc::from(script),
+                )]
+                .into(),
+                ..Default::default()
+            });
+        }
+        let built = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            deno_runtime::snapshot::create_runtime_snapshot(
+                path.clone(),
+                SnapshotOptions::default(),
+                extensions,
+            )
+        }));
+        let snapshot = built
+            .map_err(|_| anyhow!("could not build the runtime snapshot"))
+            .and_then(|_| Self::load(&path));
+        let _ = std::fs::remove_dir_all(&dir);
+        snapshot
+    }
+
+    /// Reads a snapshot written by [`save`](Self::save).
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("could not read snapshot {}", path.display()))?;
+        let bytes = compress::decode(compress::sniff(&bytes), bytes)
+            .ok_or_else(|| anyhow!("could not decompress snapshot {}", path.display()))?;
+        Ok(Self(Box::leak(bytes.into_boxed_slice())))
+    }
+
+    /// Writes the snapshot to `path`, compressed with the `zstd` feature.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        std::fs::write(path, compress::encode(self.0).1)
+            .with_context(|| format!("could not write snapshot {}", path.display()))
+    }
+
+    /// A snapshot embedded in the binary, e.g. with `include_bytes!`.
+    pub fn from_static(bytes: &'static [u8]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Snapshot({} bytes)", self.0.len())
+    }
+}
@@ -0,0 +1,339 @@
+use serde::Serialize;
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::RuntimeError;
+use crate::options::RunOptions;
+
+/// What a run took and consumed, as far as it got.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExecutionStats {
+    /// Bootstrapping the worker and loading the module graph.
+    pub load: Duration,
+    /// Evaluating the module, up to its top-level await settling.
+    pub evaluate: Duration,
+    /// Calling the entrypoint, until it returned.
+    pub call: Duration,
+    /// Running the event loop after evaluation and until the result settled.
+    pub event_loop: Duration,
+    /// Transpiling TypeScript and JSX modules, part of `load`.
+    pub transpile: Duration,
+    /// Heap usage sampled after the call.
+    pub used_heap_size: usize,
+    pub total_heap_size: usize,
+    /// Largest heap usage sampled, after evaluation and after each call.
+    pub peak_heap_size: usize,
+    /// Length of the result serialized as JSON.
+    pub result_bytes: usize,
+    /// http(s) and s3 modules loaded, from the module cache included.
+    pub remote_modules: usize,
+    pub remote_bytes: u64,
+    /// Whether any module was transpiled.
+    pub transpiled: bool,
+    /// Lookups of the run in the module cache, `None` when it had none.
+    /// Entries and bytes are left at zero, see `ModuleCache::stats`.
+    pub module_cache: Option<CacheStats>,
+    /// Lookups of the run in the transpile cache, like `module_cache`.
+    pub transpile_cache: Option<CacheStats>,
+}
+
+/// What a cache served and holds. Hits, misses, insertions and evictions
+/// count since the cache was created or its stats last reset, entries and
+/// bytes are what it holds when the stats are taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub insertions: u64,
+    /// Entries dropped or replaced by newer ones.
+    pub evictions: u64,
+    pub entries: u64,
+    pub bytes: u64,
+}
+
+impl CacheStats {
+    fn count(&mut self, event: CacheEvent) {
+        match event {
+            CacheEvent::Hit => self.hits += 1,
+            CacheEvent::Miss => self.misses += 1,
+            CacheEvent::Insertion => self.insertions += 1,
+            CacheEvent::Eviction => self.evictions += 1,
+        }
+    }
+
+    fn add(&mut self, other: &CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.insertions += other.insertions;
+        self.evictions += other.evictions;
+    }
+}
+
+/// Stats of the caches of a `NetworkModuleLoader`, `None` for those it
+/// wasn't given.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LoaderCacheStats {
+    pub module_cache: Option<CacheStats>,
+    pub transpile_cache: Option<CacheStats>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum CacheEvent {
+    Hit,
+    Miss,
+    Insertion,
+    /// Only the module cache replaces entries.
+    #[cfg_attr(not(feature = "net-loader"), allow(dead_code))]
+    Eviction,
+}
+
+/// Counters of a cache, shared by its clones. A clone made with
+/// [`CacheCounters::for_run`] also counts into the stats of that run.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CacheCounters {
+    counts: Arc<[AtomicU64; 4]>,
+    run: Option<(StatsCollector, RunCounts)>,
+}
+
+/// Where a cache's lookups go in the stats of a run.
+type RunCounts = fn(&mut ExecutionStats) -> &mut Option<CacheStats>;
+
+impl CacheCounters {
+    pub(crate) fn for_run(&self, stats: Option<&StatsCollector>, counts: RunCounts) -> Self {
+        Self {
+            counts: self.counts.clone(),
+            run: stats.map(|stats| (stats.clone(), counts)),
+        }
+    }
+
+    pub(crate) fn record(&self, event: CacheEvent) {
+        self.counts[event as usize].fetch_add(1, Ordering::Relaxed);
+        if let Some((stats, counts)) = &self.run {
+            stats.record(|stats| {
+                counts(stats)
+                    .get_or_insert_with(Default::default)
+                    .count(event)
+            });
+        }
+    }
+
+    pub(crate) fn stats(&self, entries: u64, bytes: u64) -> CacheStats {
+        let [hits, misses, insertions, evictions] = self
+            .counts
+            .each_ref()
+            .map(|count| count.load(Ordering::Relaxed));
+        CacheStats {
+            hits,
+            misses,
+            insertions,
+            evictions,
+            entries,
+            bytes,
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        for count in self.counts.iter() {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Collects [`ExecutionStats`] of the runs it is set on as
+/// `RunOptions::stats`. Durations add up over runs sharing a collector.
+#[derive(Clone, Default)]
+pub struct StatsCollector(Arc<Mutex<ExecutionStats>>);
+
+impl StatsCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn stats(&self) -> ExecutionStats {
+        self.0.lock().unwrap().clone()
+    }
+
+    pub(crate) fn record(&self, record: impl FnOnce(&mut ExecutionStats)) {
+        record(&mut self.0.lock().unwrap())
+    }
+}
+
+impl fmt::Debug for StatsCollector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("StatsCollector")
+            .field(&self.stats())
+            .finish()
+    }
+}
+
+/// Receives what each invocation took once it settled, set as
+/// `RunOptions::metrics`. Called on the thread that ran the invocation,
+/// so implementations should hand the numbers off rather than block.
+pub trait MetricsSink: Send + Sync {
+    fn invocation(&self, invocation: &Invocation);
+}
+
+impl fmt::Debug for dyn MetricsSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MetricsSink")
+    }
+}
+
+/// One settled invocation, as passed to a [`MetricsSink`].
+#[derive(Debug, Clone, Serialize)]
+pub struct Invocation {
+    pub function: PathBuf,
+    /// From start to the result, including what `stats` doesn't break down.
+    pub elapsed: Duration,
+    pub stats: ExecutionStats,
+    /// `RuntimeError::kind` of the failure, `"internal"` for other errors.
+    pub error: Option<&'static str>,
+}
+
+/// Runs `work` with a collector of its own and reports the invocation to
+/// `RunOptions::metrics`. Without a sink `work` runs with `options` as is.
+/// Either way it runs inside the run's `invocation` span.
+pub(crate) async fn metered<T, F: Future<Output = Result<T, anyhow::Error>>>(
+    function: &Path,
+    options: &RunOptions,
+    work: impl FnOnce(RunOptions) -> F,
+) -> Result<T, anyhow::Error> {
+    let Some(sink) = &options.metrics else {
+        return invocation(function, options, work(options.clone())).await;
+    };
+    let collector = StatsCollector::new();
+    let metered = RunOptions {
+        stats: Some(collector.clone()),
+        ..options.clone()
+    };
+    let started = Instant::now();
+    let result = invocation(function, options, work(metered)).await;
+    let stats = collector.stats();
+    if let Some(outer) = &options.stats {
+        outer.record(|outer| outer.add(&stats));
+    }
+    let error = result.as_ref().err().map(error_kind);
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        function = %function.display(),
+        elapsed_ms = started.elapsed().as_secs_f64() * 1000.0,
+        peak_heap_size = stats.peak_heap_size,
+        result_bytes = stats.result_bytes,
+        error,
+        "invocation settled"
+    );
+    sink.invocation(&Invocation {
+        function: function.to_path_buf(),
+        elapsed: started.elapsed(),
+        stats,
+        error,
+    });
+    result
+}
+
+fn error_kind(error: &anyhow::Error) -> &'static str {
+    error
+        .downcast_ref::<RuntimeError>()
+        .map_or("internal", RuntimeError::kind)
+}
+
+impl ExecutionStats {
+    /// Folds the stats of another run into these, the way a shared
+    /// collector would have.
+    fn add(&mut self, other: &ExecutionStats) {
+        self.load += other.load;
+        self.evaluate += other.evaluate;
+        self.call += other.call;
+        self.event_loop += other.event_loop;
+        self.transpile += other.transpile;
+        if other.used_heap_size > 0 || other.total_heap_size > 0 {
+            self.used_heap_size = other.used_heap_size;
+            self.total_heap_size = other.total_heap_size;
+        }
+        self.peak_heap_size = self.peak_heap_size.max(other.peak_heap_size);
+        self.result_bytes = other.result_bytes;
+        self.remote_modules += other.remote_modules;
+        self.remote_bytes += other.remote_bytes;
+        self.transpiled |= other.transpiled;
+        for (counts, other) in [
+            (&mut self.module_cache, &other.module_cache),
+            (&mut self.transpile_cache, &other.transpile_cache),
+        ] {
+            if let Some(other) = other {
+                counts.get_or_insert_with(Default::default).add(other);
+            }
+        }
+    }
+}
+
+/// `work` inside the `invocation` span of a run, which continues
+/// `RunOptions::trace_context` and records the kind of error the run
+/// failed with.
+#[cfg(feature = "tracing")]
+async fn invocation<T>(
+    function: &Path,
+    options: &RunOptions,
+    work: impl Future<Output = Result<T, anyhow::Error>>,
+) -> Result<T, anyhow::Error> {
+    let span = tracing::info_span!(
+        "invocation",
+        function = %function.display(),
+        error = tracing::field::Empty,
+        otel.status_code = tracing::field::Empty,
+    );
+    continue_trace(&span, options);
+    let result = tracing::Instrument::instrument(work, span.clone()).await;
+    if let Err(e) = &result {
+        span.record("error", error_kind(e));
+        span.record("otel.status_code", "ERROR");
+    }
+    result
+}
+
+#[cfg(feature = "otel")]
+fn continue_trace(span: &tracing::Span, options: &RunOptions) {
+    if let Some(context) = &options.trace_context {
+        context.set_parent(span);
+    }
+}
+
+#[cfg(all(feature = "tracing", not(feature = "otel")))]
+fn continue_trace(_span: &tracing::Span, _options: &RunOptions) {}
+
+#[cfg(not(feature = "tracing"))]
+async fn invocation<T>(
+    _function: &Path,
+    _options: &RunOptions,
+    work: impl Future<Output = Result<T, anyhow::Error>>,
+) -> Result<T, anyhow::Error> {
+    work.await
+}
+
+/// `work` inside a tracing span named after the phase of the run.
+#[cfg(feature = "tracing")]
+pub(crate) fn phase<F: Future>(phase: &'static str, work: F) -> impl Future<Output = F::Output> {
+    tracing::Instrument::instrument(
+        work,
+        tracing::debug_span!("phase", phase, otel.name = phase),
+    )
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn phase<F: Future>(_phase: &'static str, work: F) -> F {
+    work
+}
+
+/// [`phase`] for a phase that doesn't await.
+#[cfg(feature = "tracing")]
+pub(crate) fn phase_sync<T>(phase: &'static str, work: impl FnOnce() -> T) -> T {
+    tracing::debug_span!("phase", phase, otel.name = phase).in_scope(work)
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn phase_sync<T>(_phase: &'static str, work: impl FnOnce() -> T) -> T {
+    work()
+}
@@ -0,0 +1,311 @@
+use deno_core::{v8, JsRuntime};
+use std::io::{self, Write};
+
+use crate::error::RuntimeError;
+use crate::extract::{call_to_json, is_class_instance, number, push_key, DEFAULT_MAX_DEPTH};
+use crate::options::RunOptions;
+
+/// Values serialized between two yields back to the executor.
+const VALUES_PER_CHUNK: usize = 4096;
+
+pub(crate) struct LimitedWriter<W> {
+    inner: W,
+    limit: usize,
+    written: u64,
+    exceeded: bool,
+}
+
+impl<W: Write> LimitedWriter<W> {
+    pub(crate) fn new(inner: W, limit: usize) -> Self {
+        Self {
+            inner,
+            limit,
+            written: 0,
+            exceeded: false,
+        }
+    }
+
+    pub(crate) fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written + buf.len() as u64 > self.limit as u64 {
+            self.exceeded = true;
+            return Err(io::Error::other("output limit exceeded"));
+        }
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+enum Frame {
+    Array {
+        array: v8::Global<v8::Array>,
+        index: u32,
+    },
+    Object {
+        object: v8::Global<v8::Object>,
+        keys: v8::Global<v8::Array>,
+        index: u32,
+        key: Option<String>,
+    },
+}
+
+/// Serializes `value` as JSON into `writer`, yielding to the executor every
+/// `VALUES_PER_CHUNK` values. Containers still being written are kept as
+/// globals on an explicit stack so no handle scope is held across yields.
+pub(crate) async fn write_json<W: Write>(
+    runtime: &mut JsRuntime,
+    value: v8::Global<v8::Value>,
+    writer: &mut LimitedWriter<W>,
+    options: &RunOptions,
+) -> Result<(), anyhow::Error> {
+    let mut stack = vec![];
+    {
+        let scope = &mut runtime.handle_scope();
+        let value = v8::Local::new(scope, value);
+        write_value(scope, value, &mut stack, writer, options)?;
+    }
+
+    while !stack.is_empty() {
+        {
+            let scope = &mut runtime.handle_scope();
+            for _ in 0..VALUES_PER_CHUNK {
+                if stack.is_empty() {
+                    break;
+                }
+                let scope = &mut v8::HandleScope::new(scope);
+                step(scope, &mut stack, writer, options)?;
+            }
+        }
+        tokio::task::yield_now().await;
+    }
+
+    writer.flush().map_err(|e| io_error(e, writer))?;
+    Ok(())
+}
+
+fn step<W: Write>(
+    scope: &mut v8::HandleScope,
+    stack: &mut Vec<Frame>,
+    writer: &mut LimitedWriter<W>,
+    options: &RunOptions,
+) -> Result<(), anyhow::Error> {
+    let frame = stack.last_mut().unwrap();
+    let child = match frame {
+        Frame::Array { array, index } => {
+            let array = v8::Local::new(scope, &*array);
+            if *index == array.length() {
+                None
+            } else {
+                if *index > 0 {
+                    write(writer, b",")?;
+                }
+                let item = array
+                    .get_index(scope, *index)
+                    .unwrap_or_else(|| v8::undefined(scope).into());
+                *index += 1;
+                Some(item)
+            }
+        }
+        Frame::Object {
+            object,
+            keys,
+            index,
+            key,
+        } => {
+            let object = v8::Local::new(scope, &*object);
+            let keys = v8::Local::new(scope, &*keys);
+            let mut child = None;
+            while *index < keys.length() {
+                let name = keys.get_index(scope, *index).unwrap();
+                *index += 1;
+                let value = object
+                    .get(scope, name)
+                    .unwrap_or_else(|| v8::undefined(scope).into());
+                if value.is_undefined() {
+                    continue;
+                }
+
+                let name = name.to_rust_string_lossy(scope);
+                if key.is_some() {
+                    write(writer, b",")?;
+                }
+                serde_json::to_writer(&mut *writer, &name)
+                    .map_err(|e| io_error(e.into(), writer))?;
+                write(writer, b":")?;
+                *key = Some(name);
+                child = Some(value);
+                break;
+            }
+            child
+        }
+    };
+
+    match child {
+        Some(child) => write_value(scope, child, stack, writer, options),
+        None => {
+            let closing: &[u8] = match stack.pop().unwrap() {
+                Frame::Array { .. } => b"]",
+                Frame::Object { .. } => b"}",
+            };
+            write(writer, closing)
+        }
+    }
+}
+
+fn write_value<'s, W: Write>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<'s, v8::Value>,
+    stack: &mut Vec<Frame>,
+    writer: &mut LimitedWriter<W>,
+    options: &RunOptions,
+) -> Result<(), anyhow::Error> {
+    let value = call_to_json(scope, value, || path(stack))?;
+    if value.is_null_or_undefined() {
+        return write(writer, b"null");
+    }
+    if value.is_boolean() {
+        let text: &[u8] = if value.boolean_value(scope) {
+            b"true"
+        } else {
+            b"false"
+        };
+        return write(writer, text);
+    }
+    if value.is_number() {
+        let n = number(value.number_value(scope).unwrap_or(f64::NAN));
+        return serde_json::to_writer(&mut *writer, &n).map_err(|e| io_error(e.into(), writer));
+    }
+    if value.is_string() {
+        let text = value.to_rust_string_lossy(scope);
+        return serde_json::to_writer(&mut *writer, &text).map_err(|e| io_error(e.into(), writer));
+    }
+    let kind = if value.is_function() {
+        Some("function")
+    } else if value.is_symbol() {
+        Some("symbol")
+    } else if value.is_big_int() {
+        Some("bigint")
+    } else {
+        None
+    };
+    if let Some(kind) = kind {
+        if let Some(replacement) = replace(scope, value, stack, options) {
+            return write_converted(writer, &replacement);
+        }
+        return Err(RuntimeError::UnsupportedValue {
+            path: path(stack),
+            kind: kind.into(),
+        }
+        .into());
+    }
+
+    let object = v8::Local::<v8::Object>::try_from(value).unwrap();
+    if let Some(depth) = stack.iter().position(|frame| match frame {
+        Frame::Array { array, .. } => *array == object,
+        Frame::Object {
+            object: ancestor, ..
+        } => *ancestor == object,
+    }) {
+        return Err(RuntimeError::CircularReference {
+            path: path(stack),
+            target: path(&stack[..depth]),
+        }
+        .into());
+    }
+    let max_depth = options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+    if stack.len() >= max_depth {
+        return Err(RuntimeError::TooDeep {
+            path: path(stack),
+            limit: max_depth,
+        }
+        .into());
+    }
+
+    if let Ok(array) = v8::Local::<v8::Array>::try_from(value) {
+        write(writer, b"[")?;
+        stack.push(Frame::Array {
+            array: v8::Global::new(scope, array),
+            index: 0,
+        });
+        return Ok(());
+    }
+
+    if is_class_instance(scope, object) {
+        if let Some(replacement) = replace(scope, value, stack, options) {
+            return write_converted(writer, &replacement);
+        }
+    }
+
+    let keys = object
+        .get_own_property_names(
+            scope,
+            v8::GetPropertyNamesArgs {
+                key_conversion: v8::KeyConversionMode::ConvertToString,
+                ..Default::default()
+            },
+        )
+        .unwrap_or_else(|| v8::Array::new(scope, 0));
+    write(writer, b"{")?;
+    stack.push(Frame::Object {
+        object: v8::Global::new(scope, object),
+        keys: v8::Global::new(scope, keys),
+        index: 0,
+        key: None,
+    });
+    Ok(())
+}
+
+fn replace<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<'s, v8::Value>,
+    stack: &[Frame],
+    options: &RunOptions,
+) -> Option<serde_json::Value> {
+    let hook = options.value_hook.as_ref()?;
+    hook.call(scope, value, &path(stack))
+}
+
+/// Writes an already converted value, such as a hook's replacement.
+pub(crate) fn write_converted<W: Write>(
+    writer: &mut LimitedWriter<W>,
+    value: &serde_json::Value,
+) -> Result<(), anyhow::Error> {
+    serde_json::to_writer(&mut *writer, value).map_err(|e| io_error(e.into(), writer))
+}
+
+fn path(stack: &[Frame]) -> String {
+    let mut path = String::from("result");
+    for frame in stack {
+        match frame {
+            Frame::Array { index, .. } => path.push_str(&format!("[{}]", index.saturating_sub(1))),
+            Frame::Object { key: Some(key), .. } => push_key(&mut path, key),
+            Frame::Object { key: None, .. } => {}
+        }
+    }
+    path
+}
+
+fn write<W: Write>(writer: &mut LimitedWriter<W>, bytes: &[u8]) -> Result<(), anyhow::Error> {
+    writer.write_all(bytes).map_err(|e| io_error(e, writer))
+}
+
+fn io_error<W>(error: io::Error, writer: &LimitedWriter<W>) -> anyhow::Error {
+    if writer.exceeded {
+        RuntimeError::OutputTooLarge {
+            limit: writer.limit,
+        }
+        .into()
+    } else {
+        error.into()
+    }
+}
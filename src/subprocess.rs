@@ -0,0 +1,915 @@
+use anyhow::{anyhow, bail, Context, Error};
+use base64::Engine;
+use deno_core::ModuleSpecifier;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::RandomState;
+use std::ffi::OsString;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus, Stdio};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::{RuntimeError, SchemaViolation};
+use crate::inputs::{InputPart, Inputs};
+use crate::options::RunOptions;
+use crate::OutputValue;
+
+/// Set on children, holding the parent's address and the token to connect
+/// with.
+const CHILD_ENV: &str = "EXPERIMENTAL_RUNTIME_SUBPROCESS";
+/// How long a child may take to start and connect.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long past `RunOptions::timeout` the parent waits before killing a
+/// child that didn't answer, when its own limit didn't stop the script.
+const GRACE: Duration = Duration::from_secs(5);
+/// How often the parent checks for cancellation while a child runs.
+const POLL: Duration = Duration::from_millis(10);
+const MAX_FRAME: usize = 1 << 30;
+
+/// A message between parent and child: a big-endian `u32` length followed
+/// by that many bytes of JSON.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Frame {
+    Hello {
+        token: String,
+    },
+    Invoke {
+        function: PathBuf,
+        inputs: Vec<(String, WirePart)>,
+        /// Whether a buffer returned at the top level comes back as
+        /// `Bytes`, as in `run_with_values`.
+        #[serde(default)]
+        values: bool,
+    },
+    Result {
+        value: Value,
+    },
+    Bytes {
+        /// Base64 encoded.
+        data: String,
+    },
+    Error {
+        kind: String,
+        message: String,
+        /// The failure as the `RuntimeError` it was, when it was one.
+        #[serde(default)]
+        error: Option<WireError>,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum WirePart {
+    Json {
+        value: Value,
+    },
+    Text {
+        text: String,
+    },
+    Bytes {
+        /// Base64 encoded.
+        data: String,
+        content_type: String,
+    },
+}
+
+/// Runs each invocation in a supervised child process, so a V8 out of
+/// memory abort or a crash in native code fails the call with
+/// `RuntimeError::WorkerCrashed` instead of taking the embedding process
+/// down. Set it as `RunOptions::subprocess`.
+///
+/// Children run `program`, which must call [`serve_subprocess`] when
+/// [`is_subprocess`] says it was started as one, with the options to run
+/// functions with: hooks, sinks and collectors of the parent's options
+/// aren't called. Idle children are reused, a crashed one is replaced on
+/// the next invocation. Cheap to clone, clones share the children.
+#[derive(Clone)]
+pub struct Subprocess(Arc<Supervisor>);
+
+struct Supervisor {
+    program: PathBuf,
+    args: Vec<OsString>,
+    idle: Mutex<Vec<Worker>>,
+}
+
+/// A connected child.
+struct Worker {
+    process: Child,
+    stream: TcpStream,
+}
+
+/// Why the parent cut a child off.
+enum Stopped {
+    Timeout(Duration),
+    Cancelled,
+}
+
+impl Subprocess {
+    pub fn new(
+        program: impl Into<PathBuf>,
+        args: impl IntoIterator<Item = impl Into<OsString>>,
+    ) -> Self {
+        Self(Arc::new(Supervisor {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            idle: Mutex::new(vec![]),
+        }))
+    }
+
+    /// Children run the current executable with the arguments it was
+    /// started with.
+    pub fn current_exe() -> Result<Self, Error> {
+        let program = std::env::current_exe().context("could not locate the current executable")?;
+        Ok(Self::new(program, std::env::args_os().skip(1)))
+    }
+
+    pub(crate) fn run(
+        &self,
+        function: &Path,
+        inputs: Inputs,
+        options: &RunOptions,
+    ) -> Result<Value, Error> {
+        match self.invoke(function, inputs, options, false)? {
+            OutputValue::Json(value) => Ok(value),
+            OutputValue::Bytes(_) => bail!("subprocess sent bytes for a JSON result"),
+        }
+    }
+
+    /// Runs like `run_with_values`.
+    pub(crate) fn run_with_values(
+        &self,
+        function: &Path,
+        inputs: Inputs,
+        options: &RunOptions,
+    ) -> Result<OutputValue, Error> {
+        self.invoke(function, inputs, options, true)
+    }
+
+    fn invoke(
+        &self,
+        function: &Path,
+        inputs: Inputs,
+        options: &RunOptions,
+        values: bool,
+    ) -> Result<OutputValue, Error> {
+        if let Some(cancellation) = &options.cancellation {
+            if cancellation.is_cancelled() {
+                return Err(RuntimeError::Cancelled.into());
+            }
+        }
+        let idle = self.0.idle.lock().unwrap().pop();
+        let mut worker = match idle {
+            Some(worker) => worker,
+            None => self.spawn()?,
+        };
+        let request = Frame::Invoke {
+            function: function.to_path_buf(),
+            inputs: inputs
+                .iter()
+                .map(|(name, part)| (name.to_string(), part.into()))
+                .collect(),
+            values,
+        };
+
+        let (done, finished) = mpsc::channel::<()>();
+        let watchdog = {
+            let stream = worker.stream.try_clone()?;
+            let limit = options.timeout;
+            let cancellation = options.cancellation.clone();
+            std::thread::spawn(move || {
+                let started = Instant::now();
+                loop {
+                    if finished.recv_timeout(POLL) != Err(RecvTimeoutError::Timeout) {
+                        return None;
+                    }
+                    let stopped = match (limit, &cancellation) {
+                        (_, Some(cancellation)) if cancellation.is_cancelled() => {
+                            Some(Stopped::Cancelled)
+                        }
+                        (Some(limit), _) if started.elapsed() > limit + GRACE => {
+                            Some(Stopped::Timeout(limit))
+                        }
+                        _ => None,
+                    };
+                    if stopped.is_some() {
+                        let _ = stream.shutdown(Shutdown::Both);
+                        return stopped;
+                    }
+                }
+            })
+        };
+        let reply =
+            write_frame(&mut worker.stream, &request).and_then(|()| read_frame(&mut worker.stream));
+        drop(done);
+        let stopped = watchdog.join().unwrap_or(None);
+
+        match (reply, stopped) {
+            (Ok(Frame::Result { value }), None) => {
+                self.0.idle.lock().unwrap().push(worker);
+                Ok(OutputValue::Json(value))
+            }
+            (Ok(Frame::Bytes { data }), None) => {
+                self.0.idle.lock().unwrap().push(worker);
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(data)
+                    .context("result bytes are not base64")?;
+                Ok(OutputValue::Bytes(bytes.into()))
+            }
+            (
+                Ok(Frame::Error {
+                    kind,
+                    message,
+                    error,
+                }),
+                None,
+            ) => {
+                self.0.idle.lock().unwrap().push(worker);
+                Err(match error {
+                    Some(error) => error.into(),
+                    None => RuntimeError::Subprocess { kind, message },
+                }
+                .into())
+            }
+            (_, Some(Stopped::Timeout(limit))) => Err(RuntimeError::Timeout { limit }.into()),
+            (_, Some(Stopped::Cancelled)) => Err(RuntimeError::Cancelled.into()),
+            (reply, None) => {
+                let status = worker
+                    .reap()
+                    .map_or_else(|| "unknown status".to_string(), |s| s.to_string());
+                match reply {
+                    Ok(_) => log::warn!(
+                        "subprocess {} sent an unexpected reply",
+                        worker.process.id()
+                    ),
+                    Err(e) => log::warn!("subprocess {} crashed: {:#}", worker.process.id(), e),
+                }
+                Err(RuntimeError::WorkerCrashed { status }.into())
+            }
+        }
+    }
+
+    fn spawn(&self) -> Result<Worker, Error> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))?;
+        listener.set_nonblocking(true)?;
+        let token = token();
+        let mut process = std::process::Command::new(&self.0.program)
+            .args(&self.0.args)
+            .env(CHILD_ENV, format!("{} {}", listener.local_addr()?, token))
+            .stdin(Stdio::null())
+            .spawn()
+            .with_context(|| format!("could not start {}", self.0.program.display()))?;
+        log::debug!("started subprocess {}", process.id());
+
+        let started = Instant::now();
+        let connected = loop {
+            match listener.accept() {
+                Ok((stream, _)) => break Ok(stream),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => break Err(Error::from(e)),
+            }
+            if let Some(status) = process.try_wait()? {
+                break Err(anyhow!(
+                    "subprocess exited before connecting ({}), does it call serve_subprocess?",
+                    status
+                ));
+            }
+            if started.elapsed() > CONNECT_TIMEOUT {
+                break Err(anyhow!(
+                    "subprocess did not connect within {:?}",
+                    CONNECT_TIMEOUT
+                ));
+            }
+            std::thread::sleep(POLL);
+        };
+        let mut worker = match connected {
+            Ok(stream) => Worker { process, stream },
+            Err(e) => {
+                let _ = process.kill();
+                let _ = process.wait();
+                return Err(e);
+            }
+        };
+        worker.stream.set_nonblocking(false)?;
+        worker.stream.set_nodelay(true)?;
+        worker
+            .stream
+            .set_read_timeout(Some(CONNECT_TIMEOUT.saturating_sub(started.elapsed())))?;
+        match read_frame(&mut worker.stream) {
+            Ok(Frame::Hello { token: sent }) if sent == token => {}
+            _ => bail!("subprocess did not identify itself"),
+        }
+        worker.stream.set_read_timeout(None)?;
+        Ok(worker)
+    }
+}
+
+impl fmt::Debug for Subprocess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subprocess")
+            .field("program", &self.0.program)
+            .field("args", &self.0.args)
+            .finish()
+    }
+}
+
+impl Worker {
+    /// Waits a moment for the exited or exiting child, killing it if it
+    /// lingers.
+    fn reap(&mut self) -> Option<ExitStatus> {
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(1) {
+            match self.process.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) => std::thread::sleep(POLL),
+                Err(_) => return None,
+            }
+        }
+        let _ = self.process.kill();
+        self.process.wait().ok()
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+/// Whether this process was started by a [`Subprocess`], and should call
+/// [`serve_subprocess`] instead of going on as usual.
+pub fn is_subprocess() -> bool {
+    std::env::var_os(CHILD_ENV).is_some()
+}
+
+/// Connects to the parent that started this process and runs the
+/// invocations it sends with `options`, one at a time, until the parent
+/// goes away.
+pub fn serve_subprocess(options: RunOptions) -> Result<(), Error> {
+    let parent = std::env::var(CHILD_ENV).map_err(|_| anyhow!("not started as a subprocess"))?;
+    let (address, token) = parent
+        .split_once(' ')
+        .ok_or_else(|| anyhow!("invalid {} value", CHILD_ENV))?;
+    let mut stream = TcpStream::connect(address).context("could not connect to the parent")?;
+    stream.set_nodelay(true)?;
+    write_frame(
+        &mut stream,
+        &Frame::Hello {
+            token: token.to_string(),
+        },
+    )?;
+    let options = RunOptions {
+        subprocess: None,
+        ..options
+    };
+
+    loop {
+        let frame = match read_frame(&mut stream) {
+            Ok(frame) => frame,
+            Err(e) if is_closed(&e) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let Frame::Invoke {
+            function,
+            inputs,
+            values,
+        } = frame
+        else {
+            bail!("expected an invocation from the parent");
+        };
+        log::debug!("subprocess invoking {}", function.display());
+        let inputs = inputs
+            .into_iter()
+            .map(|(name, part)| Ok((name, part.try_into()?)))
+            .collect::<Result<Vec<_>, Error>>()?
+            .into_iter()
+            .fold(Inputs::new(), |inputs, (name, part)| {
+                inputs.part(name, part)
+            });
+        let result = if values {
+            crate::run_with_values(function, inputs, options.clone())
+        } else {
+            crate::run_with_options(function, inputs, options.clone()).map(OutputValue::Json)
+        };
+        let reply = match result.map_err(crate::error::from_js) {
+            Ok(OutputValue::Json(value)) => Frame::Result { value },
+            Ok(OutputValue::Bytes(bytes)) => Frame::Bytes {
+                data: base64::engine::general_purpose::STANDARD.encode(bytes),
+            },
+            Err(e) => Frame::Error {
+                kind: crate::rpc::error_kind(&e).to_string(),
+                message: format!("{:#}", e),
+                error: e.downcast_ref::<RuntimeError>().and_then(WireError::new),
+            },
+        };
+        match write_frame(&mut stream, &reply) {
+            Err(e) if is_closed(&e) => return Ok(()),
+            written => written?,
+        }
+    }
+}
+
+/// A `RuntimeError` sent by a child, for the parent to fail with the same
+/// variant. Causes are sent as their messages.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "variant", rename_all = "snake_case")]
+enum WireError {
+    InvalidSchema {
+        message: String,
+    },
+    InputValidation {
+        violations: Vec<(String, String)>,
+    },
+    OutputValidation {
+        violations: Vec<(String, String)>,
+        value: Option<Value>,
+    },
+    Serialization {
+        message: String,
+    },
+    Deserialization {
+        message: String,
+    },
+    UnsupportedValue {
+        path: String,
+        kind: String,
+    },
+    ToJsonFailed {
+        path: String,
+        message: String,
+    },
+    CircularReference {
+        path: String,
+        target: String,
+    },
+    TooDeep {
+        path: String,
+        limit: usize,
+    },
+    InputTooLarge {
+        limit: usize,
+        observed: u64,
+    },
+    OutputTooLarge {
+        limit: usize,
+    },
+    DanglingWork {
+        pending: Vec<String>,
+    },
+    Timeout {
+        limit: Duration,
+    },
+    BudgetExceeded {
+        fuel: u64,
+    },
+    ModuleEvaluation {
+        specifier: String,
+        message: String,
+        location: Option<String>,
+    },
+    JsException {
+        name: Option<String>,
+        message: String,
+        stack: Option<String>,
+        source_line: Option<String>,
+    },
+    MissingEntrypoint {
+        export: String,
+        available: Vec<String>,
+    },
+    PermissionDenied {
+        message: String,
+    },
+    WorkerCrashed {
+        status: String,
+    },
+    Subprocess {
+        kind: String,
+        message: String,
+    },
+    Cancelled,
+    ShutDown,
+    QueueFull {
+        tenant: String,
+        limit: usize,
+    },
+    HeapLimitExceeded {
+        limit: usize,
+        observed: usize,
+    },
+    ModuleResolution {
+        specifier: String,
+        referrer: String,
+        cause: String,
+    },
+    Fetch {
+        url: String,
+        status: Option<u16>,
+        chain: Vec<String>,
+        cause: String,
+    },
+    Transpile {
+        specifier: String,
+        message: String,
+        chain: Vec<String>,
+    },
+    ModuleLoad {
+        specifier: String,
+        chain: Vec<String>,
+        cause: String,
+    },
+}
+
+impl WireError {
+    /// `None` for errors that can't be sent whole, which the parent gets
+    /// as `RuntimeError::Subprocess`.
+    fn new(error: &RuntimeError) -> Option<Self> {
+        let violations = |violations: &[SchemaViolation]| {
+            violations
+                .iter()
+                .map(|v| (v.path.clone(), v.message.clone()))
+                .collect()
+        };
+        let chain = |chain: &[ModuleSpecifier]| chain.iter().map(|s| s.to_string()).collect();
+        Some(match error {
+            RuntimeError::InvalidSchema(message) => WireError::InvalidSchema {
+                message: message.clone(),
+            },
+            RuntimeError::InputValidation { violations: v } => WireError::InputValidation {
+                violations: violations(v),
+            },
+            RuntimeError::OutputValidation {
+                violations: v,
+                value,
+            } => WireError::OutputValidation {
+                violations: violations(v),
+                value: value.clone(),
+            },
+            RuntimeError::Serialization { message } => WireError::Serialization {
+                message: message.clone(),
+            },
+            RuntimeError::Deserialization { message } => WireError::Deserialization {
+                message: message.clone(),
+            },
+            RuntimeError::UnsupportedValue { path, kind } => WireError::UnsupportedValue {
+                path: path.clone(),
+                kind: kind.clone(),
+            },
+            RuntimeError::ToJsonFailed { path, message } => WireError::ToJsonFailed {
+                path: path.clone(),
+                message: message.clone(),
+            },
+            RuntimeError::CircularReference { path, target } => WireError::CircularReference {
+                path: path.clone(),
+                target: target.clone(),
+            },
+            RuntimeError::TooDeep { path, limit } => WireError::TooDeep {
+                path: path.clone(),
+                limit: *limit,
+            },
+            RuntimeError::InputTooLarge { limit, observed } => WireError::InputTooLarge {
+                limit: *limit,
+                observed: *observed,
+            },
+            RuntimeError::OutputTooLarge { limit } => WireError::OutputTooLarge { limit: *limit },
+            RuntimeError::DanglingWork { pending } => WireError::DanglingWork {
+                pending: pending.clone(),
+            },
+            RuntimeError::DeniedWarning(_) => return None,
+            RuntimeError::Timeout { limit } => WireError::Timeout { limit: *limit },
+            RuntimeError::BudgetExceeded { fuel } => WireError::BudgetExceeded { fuel: *fuel },
+            RuntimeError::ModuleEvaluation {
+                specifier,
+                message,
+                location,
+            } => WireError::ModuleEvaluation {
+                specifier: specifier.clone(),
+                message: message.clone(),
+                location: location.clone(),
+            },
+            RuntimeError::JsException {
+                name,
+                message,
+                stack,
+                source_line,
+            } => WireError::JsException {
+                name: name.clone(),
+                message: message.clone(),
+                stack: stack.clone(),
+                source_line: source_line.clone(),
+            },
+            RuntimeError::MissingEntrypoint { export, available } => WireError::MissingEntrypoint {
+                export: export.clone(),
+                available: available.clone(),
+            },
+            RuntimeError::PermissionDenied { message } => WireError::PermissionDenied {
+                message: message.clone(),
+            },
+            RuntimeError::WorkerCrashed { status } => WireError::WorkerCrashed {
+                status: status.clone(),
+            },
+            RuntimeError::Subprocess { kind, message } => WireError::Subprocess {
+                kind: kind.clone(),
+                message: message.clone(),
+            },
+            RuntimeError::Cancelled => WireError::Cancelled,
+            RuntimeError::ShutDown => WireError::ShutDown,
+            RuntimeError::QueueFull { tenant, limit } => WireError::QueueFull {
+                tenant: tenant.clone(),
+                limit: *limit,
+            },
+            RuntimeError::HeapLimitExceeded { limit, observed } => WireError::HeapLimitExceeded {
+                limit: *limit,
+                observed: *observed,
+            },
+            RuntimeError::ModuleResolution {
+                specifier,
+                referrer,
+                cause,
+            } => WireError::ModuleResolution {
+                specifier: specifier.clone(),
+                referrer: referrer.clone(),
+                cause: format!("{:#}", cause),
+            },
+            RuntimeError::Fetch {
+                url,
+                status,
+                chain: imports,
+                cause,
+            } => WireError::Fetch {
+                url: url.clone(),
+                status: *status,
+                chain: chain(imports),
+                cause: format!("{:#}", cause),
+            },
+            RuntimeError::Transpile {
+                specifier,
+                message,
+                chain: imports,
+            } => WireError::Transpile {
+                specifier: specifier.clone(),
+                message: message.clone(),
+                chain: chain(imports),
+            },
+            RuntimeError::ModuleLoad {
+                specifier,
+                chain: imports,
+                cause,
+            } => WireError::ModuleLoad {
+                specifier: specifier.to_string(),
+                chain: chain(imports),
+                cause: format!("{:#}", cause),
+            },
+        })
+    }
+}
+
+impl From<WireError> for RuntimeError {
+    fn from(error: WireError) -> Self {
+        let violations = |violations: Vec<(String, String)>| {
+            violations
+                .into_iter()
+                .map(|(path, message)| SchemaViolation { path, message })
+                .collect()
+        };
+        let chain = |chain: Vec<String>| {
+            chain
+                .iter()
+                .filter_map(|s| ModuleSpecifier::parse(s).ok())
+                .collect()
+        };
+        match error {
+            WireError::InvalidSchema { message } => RuntimeError::InvalidSchema(message),
+            WireError::InputValidation { violations: v } => RuntimeError::InputValidation {
+                violations: violations(v),
+            },
+            WireError::OutputValidation {
+                violations: v,
+                value,
+            } => RuntimeError::OutputValidation {
+                violations: violations(v),
+                value,
+            },
+            WireError::Serialization { message } => RuntimeError::Serialization { message },
+            WireError::Deserialization { message } => RuntimeError::Deserialization { message },
+            WireError::UnsupportedValue { path, kind } => {
+                RuntimeError::UnsupportedValue { path, kind }
+            }
+            WireError::ToJsonFailed { path, message } => {
+                RuntimeError::ToJsonFailed { path, message }
+            }
+            WireError::CircularReference { path, target } => {
+                RuntimeError::CircularReference { path, target }
+            }
+            WireError::TooDeep { path, limit } => RuntimeError::TooDeep { path, limit },
+            WireError::InputTooLarge { limit, observed } => {
+                RuntimeError::InputTooLarge { limit, observed }
+            }
+            WireError::OutputTooLarge { limit } => RuntimeError::OutputTooLarge { limit },
+            WireError::DanglingWork { pending } => RuntimeError::DanglingWork { pending },
+            WireError::Timeout { limit } => RuntimeError::Timeout { limit },
+            WireError::BudgetExceeded { fuel } => RuntimeError::BudgetExceeded { fuel },
+            WireError::ModuleEvaluation {
+                specifier,
+                message,
+                location,
+            } => RuntimeError::ModuleEvaluation {
+                specifier,
+                message,
+                location,
+            },
+            WireError::JsException {
+                name,
+                message,
+                stack,
+                source_line,
+            } => RuntimeError::JsException {
+                name,
+                message,
+                stack,
+                source_line,
+            },
+            WireError::MissingEntrypoint { export, available } => {
+                RuntimeError::MissingEntrypoint { export, available }
+            }
+            WireError::PermissionDenied { message } => RuntimeError::PermissionDenied { message },
+            WireError::WorkerCrashed { status } => RuntimeError::WorkerCrashed { status },
+            WireError::Subprocess { kind, message } => RuntimeError::Subprocess { kind, message },
+            WireError::Cancelled => RuntimeError::Cancelled,
+            WireError::ShutDown => RuntimeError::ShutDown,
+            WireError::QueueFull { tenant, limit } => RuntimeError::QueueFull { tenant, limit },
+            WireError::HeapLimitExceeded { limit, observed } => {
+                RuntimeError::HeapLimitExceeded { limit, observed }
+            }
+            WireError::ModuleResolution {
+                specifier,
+                referrer,
+                cause,
+            } => RuntimeError::ModuleResolution {
+                specifier,
+                referrer,
+                cause: anyhow!(cause),
+            },
+            WireError::Fetch {
+                url,
+                status,
+                chain: imports,
+                cause,
+            } => RuntimeError::Fetch {
+                url,
+                status,
+                chain: chain(imports),
+                cause: anyhow!(cause),
+            },
+            WireError::Transpile {
+                specifier,
+                message,
+                chain: imports,
+            } => RuntimeError::Transpile {
+                specifier,
+                message,
+                chain: chain(imports),
+            },
+            WireError::ModuleLoad {
+                specifier,
+                chain: imports,
+                cause,
+            } => match ModuleSpecifier::parse(&specifier) {
+                Ok(specifier) => RuntimeError::ModuleLoad {
+                    specifier,
+                    chain: chain(imports),
+                    cause: anyhow!(cause),
+                },
+                Err(_) => RuntimeError::Subprocess {
+                    kind: "module_load".to_string(),
+                    message: format!("could not load {}: {}", specifier, cause),
+                },
+            },
+        }
+    }
+}
+
+impl From<&InputPart> for WirePart {
+    fn from(part: &InputPart) -> Self {
+        match part {
+            InputPart::Json(value) => WirePart::Json {
+                value: value.clone(),
+            },
+            InputPart::Text(text) => WirePart::Text { text: text.clone() },
+            InputPart::Bytes(bytes, content_type) => WirePart::Bytes {
+                data: base64::engine::general_purpose::STANDARD.encode(bytes),
+                content_type: content_type.clone(),
+            },
+        }
+    }
+}
+
+impl TryFrom<WirePart> for InputPart {
+    type Error = Error;
+
+    fn try_from(part: WirePart) -> Result<Self, Error> {
+        Ok(match part {
+            WirePart::Json { value } => InputPart::Json(value),
+            WirePart::Text { text } => InputPart::Text(text),
+            WirePart::Bytes { data, content_type } => InputPart::Bytes(
+                base64::engine::general_purpose::STANDARD
+                    .decode(data)
+                    .context("input bytes are not base64")?
+                    .into(),
+                content_type,
+            ),
+        })
+    }
+}
+
+fn write_frame(stream: &mut TcpStream, frame: &Frame) -> Result<(), Error> {
+    let body = serde_json::to_vec(frame)?;
+    if body.len() > MAX_FRAME {
+        bail!("message of {} bytes is too large to send", body.len());
+    }
+    stream.write_all(&(body.len() as u32).to_be_bytes())?;
+    stream.write_all(&body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+fn read_frame(stream: &mut TcpStream) -> Result<Frame, Error> {
+    let mut length = [0; 4];
+    stream.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME {
+        bail!("message of {} bytes is too large to receive", length);
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body)?;
+    serde_json::from_slice(&body).context("invalid message")
+}
+
+/// Whether `error` is the other side hanging up.
+fn is_closed(error: &Error) -> bool {
+    error.downcast_ref::<std::io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            ErrorKind::UnexpectedEof | ErrorKind::BrokenPipe | ErrorKind::ConnectionReset
+        )
+    })
+}
+
+/// A secret for the child to prove it is the one that was started, as
+/// anyone on the host can connect to the loopback address.
+fn token() -> String {
+    (0..2)
+        .map(|_| {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u32(std::process::id());
+            format!("{:016x}", hasher.finish())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(error: RuntimeError) -> RuntimeError {
+        let wire = WireError::new(&error).expect("sendable error");
+        let json = serde_json::to_string(&wire).unwrap();
+        serde_json::from_str::<WireError>(&json).unwrap().into()
+    }
+
+    #[test]
+    fn child_errors_keep_their_variant() {
+        let limit = Duration::from_millis(1500);
+        assert!(matches!(
+            round_trip(RuntimeError::Timeout { limit }),
+            RuntimeError::Timeout { limit: l } if l == limit
+        ));
+        let error = round_trip(RuntimeError::JsException {
+            name: Some("TypeError".to_string()),
+            message: "x is not a function".to_string(),
+            stack: Some("TypeError: x is not a function\n    at f".to_string()),
+            source_line: None,
+        });
+        assert_eq!(error.kind(), "js_exception");
+        assert_eq!(
+            error.to_string(),
+            "TypeError: x is not a function\n    at f"
+        );
+        let specifier = ModuleSpecifier::parse("file:///main.ts").unwrap();
+        let error = round_trip(RuntimeError::ModuleLoad {
+            specifier: specifier.clone(),
+            chain: vec![specifier],
+            cause: anyhow!("not found"),
+        });
+        assert_eq!(error.kind(), "module_load");
+        assert!(error.to_string().contains("not found"));
+    }
+}
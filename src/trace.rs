@@ -0,0 +1,303 @@
+use anyhow::{anyhow, Context, Error};
+use base64::Engine;
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::host_api::HostCall;
+
+/// Whether a run writes what it fetches and gets back from host methods to
+/// a trace, or is served from one instead of the real services.
+#[derive(Debug, Clone)]
+pub enum TraceMode {
+    Record(TraceRecorder),
+    Replay(Trace),
+}
+
+/// Something the function received from outside, one line of a trace file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TraceEntry {
+    /// A `fetch`. Request headers and bodies are left out, they may hold
+    /// credentials.
+    Fetch {
+        method: String,
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        response: Option<RecordedResponse>,
+        /// Why the fetch failed, without a response.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// A call of a `host.api` method.
+    HostCall {
+        method: String,
+        #[serde(default)]
+        is_async: bool,
+        args: Vec<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub headers: Vec<(String, String)>,
+    /// The body, base64 encoded.
+    pub body: String,
+}
+
+impl TraceEntry {
+    fn key(&self) -> String {
+        match self {
+            TraceEntry::Fetch { method, url, .. } => fetch_key(method, url),
+            TraceEntry::HostCall { method, args, .. } => host_call_key(method, args),
+        }
+    }
+}
+
+fn fetch_key(method: &str, url: &str) -> String {
+    format!("fetch {} {}", method, url)
+}
+
+fn host_call_key(method: &str, args: &[Value]) -> String {
+    format!("host {} {}", method, Value::from(args))
+}
+
+/// Appends entries to a trace file as JSON lines, as they happen, so a run
+/// that is cut short still leaves what it received.
+#[derive(Clone)]
+pub struct TraceRecorder(Arc<Mutex<File>>);
+
+impl TraceRecorder {
+    /// Starts a trace at `path`, replacing any file there.
+    pub fn create(path: &Path) -> Result<Self, Error> {
+        let file = File::create(path)
+            .with_context(|| format!("could not create trace {}", path.display()))?;
+        Ok(Self(Arc::new(Mutex::new(file))))
+    }
+
+    pub(crate) fn record(&self, entry: &TraceEntry) -> Result<(), Error> {
+        let line = serde_json::to_string(entry)?;
+        let mut file = self.0.lock().unwrap();
+        writeln!(file, "{}", line).context("could not write to the trace")
+    }
+
+    fn record_host_call(
+        &self,
+        method: String,
+        is_async: bool,
+        args: Vec<Value>,
+        result: Result<Value, Error>,
+    ) -> Result<Value, Error> {
+        let (recorded, error) = match &result {
+            Ok(value) => (Some(value.clone()), None),
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+        self.record(&TraceEntry::HostCall {
+            method,
+            is_async,
+            args,
+            result: recorded,
+            error,
+        })?;
+        result
+    }
+}
+
+impl fmt::Debug for TraceRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TraceRecorder")
+    }
+}
+
+/// Recorded entries, served in the order they were recorded for each
+/// request. Once the entries for a request run out, the last one is served
+/// again. Replaying is shared between the runs the trace is set for.
+#[derive(Clone)]
+pub struct Trace(Arc<Mutex<HashMap<String, VecDeque<TraceEntry>>>>);
+
+impl Trace {
+    /// Reads a trace written by [`TraceRecorder`].
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let file =
+            File::open(path).with_context(|| format!("could not open trace {}", path.display()))?;
+        let mut entries = vec![];
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = serde_json::from_str(&line)
+                .with_context(|| format!("invalid trace entry on line {}", n + 1))?;
+            entries.push(entry);
+        }
+        Ok(Self::from_entries(entries))
+    }
+
+    pub fn from_entries(entries: impl IntoIterator<Item = TraceEntry>) -> Self {
+        let mut by_key = HashMap::<_, VecDeque<_>>::new();
+        for entry in entries {
+            by_key.entry(entry.key()).or_default().push_back(entry);
+        }
+        Self(Arc::new(Mutex::new(by_key)))
+    }
+
+    fn take(&self, key: &str) -> Option<TraceEntry> {
+        let mut entries = self.0.lock().unwrap();
+        let queue = entries.get_mut(key)?;
+        match queue.len() {
+            0 | 1 => queue.front().cloned(),
+            _ => queue.pop_front(),
+        }
+    }
+
+    /// Host methods the trace has calls of, whether they are async, so
+    /// `host.api` offers them without the host.
+    pub(crate) fn host_methods(&self) -> Vec<(String, bool)> {
+        let entries = self.0.lock().unwrap();
+        let mut methods = entries
+            .values()
+            .filter_map(|queue| match queue.front() {
+                Some(TraceEntry::HostCall {
+                    method, is_async, ..
+                }) => Some((method.clone(), *is_async)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        methods.sort();
+        methods.dedup();
+        methods
+    }
+
+    pub(crate) fn fetch(&self, method: &str, url: &str) -> Result<ReplayedFetch, Error> {
+        let Some(TraceEntry::Fetch {
+            response, error, ..
+        }) = self.take(&fetch_key(method, url))
+        else {
+            return Err(anyhow!("no recorded response for {} {}", method, url));
+        };
+        let Some(response) = response else {
+            return Ok(ReplayedFetch {
+                status: 0,
+                status_text: String::new(),
+                headers: vec![],
+                body: vec![].into(),
+                error: Some(error.unwrap_or_else(|| "fetch failed".to_string())),
+            });
+        };
+        let body = base64::engine::general_purpose::STANDARD
+            .decode(&response.body)
+            .with_context(|| format!("recorded body of {} {} is not base64", method, url))?;
+        Ok(ReplayedFetch {
+            status: response.status,
+            status_text: response.status_text,
+            headers: response.headers,
+            body: body.into(),
+            error: None,
+        })
+    }
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let requests = self.0.lock().map(|entries| entries.len()).unwrap_or(0);
+        f.debug_struct("Trace")
+            .field("requests", &requests)
+            .finish()
+    }
+}
+
+/// A fetch as the script reports it for recording.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RawFetch {
+    method: String,
+    url: String,
+    status: Option<u16>,
+    #[serde(default)]
+    status_text: String,
+    #[serde(default)]
+    headers: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+impl RawFetch {
+    pub(crate) fn into_entry(self, body: &[u8]) -> TraceEntry {
+        let response = match (self.status, &self.error) {
+            (Some(status), None) => Some(RecordedResponse {
+                status,
+                status_text: self.status_text,
+                headers: self.headers,
+                body: base64::engine::general_purpose::STANDARD.encode(body),
+            }),
+            _ => None,
+        };
+        TraceEntry::Fetch {
+            method: self.method,
+            url: self.url,
+            response,
+            error: self.error,
+        }
+    }
+}
+
+/// A recorded fetch handed back to the script.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReplayedFetch {
+    status: u16,
+    status_text: String,
+    headers: Vec<(String, String)>,
+    body: deno_core::ToJsBuffer,
+    error: Option<String>,
+}
+
+/// Calls a host method through the trace: recorded when recording,
+/// answered from the trace without calling it when replaying.
+pub(crate) fn host_call(
+    mode: Option<&TraceMode>,
+    method: &str,
+    is_async: bool,
+    args: Vec<Value>,
+    call: impl FnOnce(Vec<Value>) -> HostCall,
+) -> HostCall {
+    match mode {
+        None => call(args),
+        Some(TraceMode::Replay(trace)) => {
+            HostCall::Ready(match trace.take(&host_call_key(method, &args)) {
+                Some(TraceEntry::HostCall {
+                    error: Some(error), ..
+                }) => Err(anyhow!(error)),
+                Some(TraceEntry::HostCall { result, .. }) => Ok(result.unwrap_or(Value::Null)),
+                _ => Err(anyhow!(
+                    "no recorded result for host method {:?} with these arguments",
+                    method
+                )),
+            })
+        }
+        Some(TraceMode::Record(recorder)) => {
+            let recorder = recorder.clone();
+            let method = method.to_string();
+            match call(args.clone()) {
+                HostCall::Ready(result) => {
+                    HostCall::Ready(recorder.record_host_call(method, is_async, args, result))
+                }
+                HostCall::Pending(future) => HostCall::Pending(
+                    async move { recorder.record_host_call(method, is_async, args, future.await) }
+                        .boxed(),
+                ),
+            }
+        }
+    }
+}
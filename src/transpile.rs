@@ -0,0 +1,392 @@
+use anyhow::{anyhow, bail, Context, Error};
+use deno_core::{ModuleSpecifier, ModuleType, RequestedModuleType};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::compress::{self, DiskUsage};
+use crate::stats::{CacheCounters, CacheStats, StatsCollector};
+
+/// Whether the specifier names a type declaration file, which has no
+/// runtime code.
+pub(crate) fn is_declaration(specifier: &ModuleSpecifier) -> bool {
+    let path = specifier.path().to_ascii_lowercase();
+    [".d.ts", ".d.mts", ".d.cts"]
+        .iter()
+        .any(|suffix| path.ends_with(suffix))
+}
+
+/// What a module's source is written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    JavaScript,
+    Jsx,
+    TypeScript,
+    Tsx,
+    Json,
+    Wasm,
+}
+
+impl SourceKind {
+    /// Guessed from the extension, JavaScript when there is none.
+    pub(crate) fn from_specifier(specifier: &ModuleSpecifier) -> Self {
+        Self::from_path(specifier.path())
+    }
+
+    pub(crate) fn from_path(path: &str) -> Self {
+        let path = path.to_ascii_lowercase();
+        let extension = path.rsplit_once('.').map_or("", |(_, extension)| extension);
+        match extension {
+            "ts" | "mts" | "cts" => SourceKind::TypeScript,
+            "tsx" => SourceKind::Tsx,
+            "jsx" => SourceKind::Jsx,
+            "json" => SourceKind::Json,
+            "wasm" => SourceKind::Wasm,
+            _ => SourceKind::JavaScript,
+        }
+    }
+
+    /// Taken from a `Content-Type` header or data: URL media type. `None`
+    /// for types that say nothing about the source, such as
+    /// `application/octet-stream`.
+    pub(crate) fn from_content_type(content_type: &str) -> Option<Self> {
+        let essence = content_type.split(';').next()?.trim().to_ascii_lowercase();
+        match essence.as_str() {
+            "application/typescript"
+            | "text/typescript"
+            | "application/x-typescript"
+            | "video/mp2t"
+            | "video/vnd.dlna.mpeg-tts" => Some(SourceKind::TypeScript),
+            "text/tsx" => Some(SourceKind::Tsx),
+            "text/jsx" => Some(SourceKind::Jsx),
+            "application/javascript"
+            | "text/javascript"
+            | "application/ecmascript"
+            | "text/ecmascript"
+            | "application/x-javascript"
+            | "application/node" => Some(SourceKind::JavaScript),
+            "application/json" | "text/json" => Some(SourceKind::Json),
+            "application/wasm" => Some(SourceKind::Wasm),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            SourceKind::JavaScript => "js",
+            SourceKind::Jsx => "jsx",
+            SourceKind::TypeScript => "ts",
+            SourceKind::Tsx => "tsx",
+            SourceKind::Json => "json",
+            SourceKind::Wasm => "wasm",
+        }
+    }
+
+    pub(crate) fn needs_transpile(self) -> bool {
+        matches!(
+            self,
+            SourceKind::Jsx | SourceKind::TypeScript | SourceKind::Tsx
+        )
+    }
+}
+
+/// Module type to load `specifier` as, compiled from `kind`, for an import
+/// that asked for `requested`.
+pub(crate) fn module_type(
+    specifier: &ModuleSpecifier,
+    kind: SourceKind,
+    requested: &RequestedModuleType,
+) -> Result<ModuleType, Error> {
+    match requested {
+        RequestedModuleType::None if kind == SourceKind::Json => {
+            bail!("{} is JSON, import it with {{ type: \"json\" }}", specifier)
+        }
+        RequestedModuleType::None => Ok(ModuleType::JavaScript),
+        RequestedModuleType::Json => Ok(ModuleType::Json),
+        RequestedModuleType::Other(_) => {
+            log::error!("unreachable module type requested");
+            bail!("Import types other than JSON are not supported");
+        }
+    }
+}
+
+/// Source maps of the modules a loader transpiled, with their original
+/// text, so stack traces point at the lines that were written.
+#[derive(Default)]
+pub(crate) struct SourceMaps(RefCell<HashMap<String, (Vec<u8>, String)>>);
+
+impl SourceMaps {
+    pub(crate) fn source_map(&self, file_name: &str) -> Option<Vec<u8>> {
+        let maps = self.0.borrow();
+        maps.get(file_name).map(|(map, _)| map.clone())
+    }
+
+    /// Line `line_number`, counted from 0, of the original text.
+    pub(crate) fn source_line(&self, file_name: &str, line_number: usize) -> Option<String> {
+        let maps = self.0.borrow();
+        let (_, original) = maps.get(file_name)?;
+        original.lines().nth(line_number).map(String::from)
+    }
+}
+
+/// Compiled TypeScript and JSX kept on disk between runs, keyed by the
+/// SHA-256 of the specifier, the source and the compile settings. Large
+/// entries are compressed with the `zstd` feature, told apart by their
+/// first bytes as entries have no metadata.
+#[derive(Debug, Clone)]
+pub struct TranspileCache {
+    dir: PathBuf,
+    counters: CacheCounters,
+}
+
+impl TranspileCache {
+    /// Cache in `$XDG_CACHE_HOME/experimental_runtime/gen`, falling back to
+    /// `~/.cache` when the variable is unset.
+    pub fn new() -> Result<Self, Error> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .ok_or_else(|| anyhow!("no cache directory, neither XDG_CACHE_HOME nor HOME is set"))?;
+        Ok(Self::in_dir(base.join("experimental_runtime/gen")))
+    }
+
+    pub fn in_dir(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            counters: CacheCounters::default(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Lookups since the cache was created or last reset, and what it
+    /// holds on disk. Clones share the counts.
+    pub fn stats(&self) -> CacheStats {
+        let usage = self.disk_usage().unwrap_or_else(|e| {
+            log::debug!("could not measure the transpile cache: {:#}", e);
+            DiskUsage::default()
+        });
+        self.counters.stats(usage.entries as u64, usage.disk_bytes)
+    }
+
+    pub fn reset_stats(&self) {
+        self.counters.reset()
+    }
+
+    /// The cache, also counting lookups into the stats of a run.
+    pub(crate) fn for_run(&self, stats: Option<&StatsCollector>) -> Self {
+        Self {
+            counters: self
+                .counters
+                .for_run(stats, |stats| &mut stats.transpile_cache),
+            ..self.clone()
+        }
+    }
+
+    /// Entry count and sizes of the compiled code and source maps in the
+    /// cache, as stored and as they were written.
+    pub fn disk_usage(&self) -> Result<DiskUsage, Error> {
+        let mut usage = DiskUsage::default();
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(usage),
+            Err(e) => return Err(e).context(format!("could not read {}", self.dir.display())),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|e| e.to_str());
+            if !matches!(extension, Some("js" | "map")) {
+                continue;
+            }
+            let Ok(bytes) = std::fs::read(&path) else {
+                continue;
+            };
+            if extension == Some("js") {
+                usage.entries += 1;
+            }
+            usage.disk_bytes += bytes.len() as u64;
+            usage.logical_bytes += compress::logical_len(&bytes).unwrap_or(bytes.len() as u64);
+        }
+        Ok(usage)
+    }
+
+    #[cfg(feature = "typescript")]
+    fn key(
+        &self,
+        specifier: &ModuleSpecifier,
+        kind: SourceKind,
+        code: &str,
+        maps: bool,
+    ) -> PathBuf {
+        use sha2::Digest;
+        let mut hasher = sha2::Sha256::new();
+        for part in [
+            env!("CARGO_PKG_VERSION"),
+            specifier.as_str(),
+            kind.extension(),
+            if maps { "maps" } else { "" },
+            code,
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        self.dir.join(format!("{:x}", hasher.finalize()))
+    }
+
+    #[cfg(feature = "typescript")]
+    fn read(&self, key: &Path, maps: bool) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+        let read = |extension| {
+            let bytes = std::fs::read(key.with_extension(extension)).ok()?;
+            compress::decode(compress::sniff(&bytes), bytes)
+        };
+        let code = read("js")?;
+        let map = match maps {
+            true => Some(read("map")?),
+            false => None,
+        };
+        Some((code, map))
+    }
+
+    #[cfg(feature = "typescript")]
+    fn write(&self, key: &Path, code: &[u8], map: Option<&[u8]>) -> Result<(), Error> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("could not create {}", self.dir.display()))?;
+        if let Some(map) = map {
+            std::fs::write(key.with_extension("map"), compress::encode(map).1)?;
+        }
+        // Written last, so a complete entry is never missing its map.
+        std::fs::write(key.with_extension("js"), compress::encode(code).1)?;
+        Ok(())
+    }
+}
+
+/// Compiles TypeScript and JSX to JavaScript, other modules are passed
+/// through as they are. Source maps are recorded into `source_maps` when
+/// given, and not generated otherwise. With a `cache`, compiled output is
+/// reused across runs.
+#[cfg(feature = "typescript")]
+pub(crate) fn transpile_module(
+    specifier: &ModuleSpecifier,
+    kind: SourceKind,
+    code: String,
+    source_maps: Option<&SourceMaps>,
+    cache: Option<&TranspileCache>,
+) -> Result<Vec<u8>, Error> {
+    use deno_ast::{MediaType, ParseParams};
+
+    if !kind.needs_transpile() {
+        return Ok(code.into_bytes());
+    }
+    let key = cache.map(|cache| {
+        (
+            cache,
+            cache.key(specifier, kind, &code, source_maps.is_some()),
+        )
+    });
+    if let Some((cache, key)) = &key {
+        if let Some((emitted, map)) = cache.read(key, source_maps.is_some()) {
+            log::debug!("using cached compile of {}", specifier);
+            cache.counters.record(crate::stats::CacheEvent::Hit);
+            if let (Some(source_maps), Some(map)) = (source_maps, map) {
+                source_maps
+                    .0
+                    .borrow_mut()
+                    .insert(specifier.to_string(), (map, code));
+            }
+            return Ok(emitted);
+        }
+        cache.counters.record(crate::stats::CacheEvent::Miss);
+    }
+    let media_type = match kind {
+        SourceKind::Jsx => MediaType::Jsx,
+        SourceKind::Tsx => MediaType::Tsx,
+        _ => MediaType::TypeScript,
+    };
+
+    log::debug!("compiling ts module");
+    let failed = |e: &dyn std::fmt::Display| crate::error::RuntimeError::Transpile {
+        specifier: specifier.to_string(),
+        message: e.to_string(),
+        chain: vec![],
+    };
+    let parsed = deno_ast::parse_module(ParseParams {
+        specifier: specifier.clone(),
+        text: std::sync::Arc::from(code),
+        media_type,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })
+    .map_err(|e| failed(&e))?;
+
+    let original = parsed.text().clone();
+
+    let emitted = parsed
+        .transpile(
+            &deno_ast::TranspileOptions {
+                ..Default::default()
+            },
+            &deno_ast::EmitOptions {
+                source_map: match source_maps {
+                    Some(_) => deno_ast::SourceMapOption::Separate,
+                    None => deno_ast::SourceMapOption::None,
+                },
+                ..Default::default()
+            },
+        )
+        .map_err(|e| failed(&e))?
+        .into_source();
+    if let Some((cache, key)) = &key {
+        match cache.write(key, &emitted.source, emitted.source_map.as_deref()) {
+            Ok(()) => cache.counters.record(crate::stats::CacheEvent::Insertion),
+            Err(e) => log::warn!("could not cache compile of {}: {:#}", specifier, e),
+        }
+    }
+    if let (Some(source_maps), Some(map)) = (source_maps, emitted.source_map) {
+        source_maps
+            .0
+            .borrow_mut()
+            .insert(specifier.to_string(), (map, original.to_string()));
+    }
+    Ok(emitted.source)
+}
+
+/// Without the `typescript` feature modules that need compiling are
+/// rejected, everything else is passed through.
+#[cfg(not(feature = "typescript"))]
+pub(crate) fn transpile_module(
+    specifier: &ModuleSpecifier,
+    kind: SourceKind,
+    code: String,
+    _source_maps: Option<&SourceMaps>,
+    _cache: Option<&TranspileCache>,
+) -> Result<Vec<u8>, Error> {
+    if kind.needs_transpile() {
+        anyhow::bail!(
+            "{} needs compiling, built without typescript support",
+            specifier
+        );
+    }
+    Ok(code.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn declaration_files() {
+        for (url, expected) in [
+            ("file:///types.d.ts", true),
+            ("file:///types.D.MTS", true),
+            ("https://example.com/lib.d.cts?v=1", true),
+            ("file:///types.ts", false),
+            ("file:///d.ts.js", false),
+        ] {
+            let specifier = ModuleSpecifier::parse(url).unwrap();
+            assert_eq!(is_declaration(&specifier), expected, "{}", url);
+        }
+    }
+}
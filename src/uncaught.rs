@@ -0,0 +1,64 @@
+use anyhow::Error;
+use deno_core::error::JsError;
+use std::fmt;
+use std::sync::Arc;
+
+/// An error that escaped the function outside of a normal result, such as
+/// an exception thrown from a timer after a call returned.
+#[derive(Debug, Clone)]
+pub struct UncaughtEvent {
+    pub message: String,
+    /// Details when the error is a JS exception.
+    pub js_error: Option<JsError>,
+    /// Identifies the worker, unique within the process.
+    pub worker_id: u64,
+    /// Whether it surfaced while a call was running, as opposed to between
+    /// calls on a warm worker.
+    pub during_call: bool,
+    /// Whether the worker is replaced with a freshly loaded one.
+    pub recycled: bool,
+}
+
+impl UncaughtEvent {
+    pub(crate) fn new(error: &Error, worker_id: u64, during_call: bool, recycled: bool) -> Self {
+        Self {
+            message: format!("{:#}", error),
+            js_error: error.downcast_ref::<JsError>().cloned(),
+            worker_id,
+            during_call,
+            recycled,
+        }
+    }
+}
+
+/// Receives uncaught errors instead of the default error log.
+#[derive(Clone)]
+pub struct UncaughtHook(Arc<dyn Fn(UncaughtEvent) + Send + Sync>);
+
+impl UncaughtHook {
+    pub fn new(hook: impl Fn(UncaughtEvent) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+}
+
+impl fmt::Debug for UncaughtHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("UncaughtHook")
+    }
+}
+
+pub(crate) fn report(hook: Option<&UncaughtHook>, event: UncaughtEvent) {
+    match hook {
+        Some(hook) => (hook.0)(event),
+        None => log::error!(
+            "uncaught error in worker {}{}: {}",
+            event.worker_id,
+            if event.during_call {
+                ""
+            } else {
+                " between calls"
+            },
+            event.message
+        ),
+    }
+}
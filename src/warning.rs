@@ -0,0 +1,83 @@
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::{join, RuntimeError};
+use crate::options::RunOptions;
+
+/// Stable identifier of a kind of [`Warning`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningCode {
+    DanglingWork,
+    ConsoleTruncated,
+    ConsoleWriteFailed,
+}
+
+/// A non-fatal issue noticed during a run.
+#[derive(thiserror::Error, Debug, Clone, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum Warning {
+    #[error("function settled with work still pending: {}", join(pending))]
+    DanglingWork { pending: Vec<String> },
+    #[error("console.{level} arguments were truncated to {limit} bytes")]
+    ConsoleTruncated { level: String, limit: usize },
+    #[error("could not write console event: {message}")]
+    ConsoleWriteFailed { message: String },
+}
+
+impl Warning {
+    pub fn code(&self) -> WarningCode {
+        match self {
+            Warning::DanglingWork { .. } => WarningCode::DanglingWork,
+            Warning::ConsoleTruncated { .. } => WarningCode::ConsoleTruncated,
+            Warning::ConsoleWriteFailed { .. } => WarningCode::ConsoleWriteFailed,
+        }
+    }
+}
+
+/// Receives warnings instead of the default warning log.
+#[derive(Clone)]
+pub struct WarningHook(Arc<dyn Fn(Warning) + Send + Sync>);
+
+impl WarningHook {
+    pub fn new(hook: impl Fn(Warning) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+}
+
+impl fmt::Debug for WarningHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WarningHook")
+    }
+}
+
+/// Where warnings of a run go.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Warnings {
+    hook: Option<WarningHook>,
+    deny: Vec<WarningCode>,
+}
+
+impl Warnings {
+    pub(crate) fn new(options: &RunOptions) -> Self {
+        Self {
+            hook: options.on_warning.clone(),
+            deny: options.deny_warnings.clone(),
+        }
+    }
+
+    /// Reports `warning`, or fails with `RuntimeError::DeniedWarning` if its
+    /// code was promoted to an error.
+    pub(crate) fn emit(&self, warning: Warning) -> Result<(), Error> {
+        if self.deny.contains(&warning.code()) {
+            return Err(RuntimeError::DeniedWarning(warning).into());
+        }
+        match &self.hook {
+            Some(hook) => (hook.0)(warning),
+            None => log::warn!("{}", warning),
+        }
+        Ok(())
+    }
+}
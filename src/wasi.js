@@ -0,0 +1,535 @@
+// WASI preview1 for a command module, see `run_wasi`. The host puts the
+// module's bytes, base64-encoded, in `MODULE` ahead of this code.
+
+const SUCCESS = 0;
+const EACCES = 2;
+const EBADF = 8;
+const EEXIST = 20;
+const EINVAL = 28;
+const EIO = 29;
+const EISDIR = 31;
+const ENOENT = 44;
+const ENOSYS = 52;
+const ENOTDIR = 54;
+const ENOTEMPTY = 55;
+const ESPIPE = 70;
+const ENOTCAPABLE = 76;
+
+const ERRNO = {
+  NotFound: ENOENT,
+  AlreadyExists: EEXIST,
+  PermissionDenied: EACCES,
+  NotCapable: EACCES,
+  IsADirectory: EISDIR,
+  NotADirectory: ENOTDIR,
+  DirectoryNotEmpty: ENOTEMPTY,
+  InvalidData: EINVAL,
+  BadResource: EBADF,
+  UnexpectedEof: EIO,
+  WriteZero: EIO,
+  Interrupted: EIO,
+};
+
+const FILETYPE_CHARACTER_DEVICE = 2;
+const FILETYPE_DIRECTORY = 3;
+const FILETYPE_REGULAR_FILE = 4;
+const FILETYPE_SYMBOLIC_LINK = 7;
+
+const OFLAGS_CREAT = 1;
+const OFLAGS_DIRECTORY = 2;
+const OFLAGS_EXCL = 4;
+const OFLAGS_TRUNC = 8;
+const FDFLAGS_APPEND = 1;
+const LOOKUP_SYMLINK_FOLLOW = 1;
+const RIGHTS_FD_READ = 1n << 1n;
+const RIGHTS_FD_WRITE = 1n << 6n;
+const RIGHTS_ALL = (1n << 30n) - 1n;
+
+const decodeBase64 = (text) => Uint8Array.from(atob(text), (c) => c.charCodeAt(0));
+
+// Compiled once per worker, instantiated per call.
+const module = await WebAssembly.compile(decodeBase64(MODULE));
+
+// Thrown by `proc_exit` to unwind the module.
+class Exit {
+  constructor(code) {
+    this.code = code;
+  }
+}
+
+export function main({ args, env, stdin, preopens, maxStdout }) {
+  let memory;
+  const view = () => new DataView(memory.buffer);
+  const bytes = (ptr, len) => new Uint8Array(memory.buffer, ptr, len);
+  const string = (ptr, len) => new TextDecoder().decode(bytes(ptr, len));
+  const encoder = new TextEncoder();
+
+  const stdout = [];
+  let stdoutLength = 0;
+  const stderr = new TextDecoder();
+  let stderrLine = "";
+  const writeStderr = (chunk, done) => {
+    const lines = (stderrLine + stderr.decode(chunk, { stream: !done })).split("\n");
+    stderrLine = lines.pop();
+    for (const line of lines) {
+      console.error(line);
+    }
+    if (done && stderrLine !== "") {
+      console.error(stderrLine);
+    }
+  };
+
+  const fds = new Map([
+    [0, { kind: "stdin", data: stdin, offset: 0 }],
+    [1, { kind: "stdout" }],
+    [2, { kind: "stderr" }],
+  ]);
+  preopens.forEach(([guest, host], index) => {
+    fds.set(3 + index, { kind: "dir", guest, root: host, path: [] });
+  });
+  let nextFd = 3 + preopens.length;
+
+  // Host path of `path` relative to the directory `fd`, never leaving the
+  // preopened directory it was opened from.
+  const resolve = (fd, ptr, len) => {
+    const dir = fds.get(fd);
+    if (dir === undefined) {
+      return { errno: EBADF };
+    }
+    if (dir.kind !== "dir") {
+      return { errno: ENOTDIR };
+    }
+    const path = [...dir.path];
+    for (const segment of string(ptr, len).split("/")) {
+      if (segment === "" || segment === ".") {
+        continue;
+      }
+      if (segment === "..") {
+        if (path.length === 0) {
+          return { errno: ENOTCAPABLE };
+        }
+        path.pop();
+      } else {
+        path.push(segment);
+      }
+    }
+    return { root: dir.root, path, host: [dir.root, ...path].join("/") };
+  };
+
+  const filetype = (info) => {
+    if (info.isDirectory) {
+      return FILETYPE_DIRECTORY;
+    }
+    if (info.isSymlink) {
+      return FILETYPE_SYMBOLIC_LINK;
+    }
+    return FILETYPE_REGULAR_FILE;
+  };
+
+  const nanoseconds = (date) => (date === null ? 0n : BigInt(date.getTime()) * 1000000n);
+
+  const writeFilestat = (ptr, info, type) => {
+    const v = view();
+    v.setBigUint64(ptr, 0n, true);
+    v.setBigUint64(ptr + 8, BigInt(info.ino ?? 0), true);
+    v.setUint8(ptr + 16, type ?? filetype(info));
+    v.setBigUint64(ptr + 24, BigInt(info.nlink ?? 1), true);
+    v.setBigUint64(ptr + 32, BigInt(info.size ?? 0), true);
+    v.setBigUint64(ptr + 40, nanoseconds(info.atime ?? null), true);
+    v.setBigUint64(ptr + 48, nanoseconds(info.mtime ?? null), true);
+    v.setBigUint64(ptr + 56, nanoseconds(info.birthtime ?? info.mtime ?? null), true);
+  };
+
+  // Buffers of an iovec array.
+  const iovecs = (ptr, len) => {
+    const v = view();
+    const buffers = [];
+    for (let i = 0; i < len; i++) {
+      buffers.push(bytes(v.getUint32(ptr + i * 8, true), v.getUint32(ptr + i * 8 + 4, true)));
+    }
+    return buffers;
+  };
+
+  const strings = (list) => list.map((item) => encoder.encode(item + "\0"));
+
+  const sizes = (list) => (countPtr, sizePtr) => {
+    const v = view();
+    v.setUint32(countPtr, list.length, true);
+    v.setUint32(sizePtr, list.reduce((size, item) => size + item.length, 0), true);
+    return SUCCESS;
+  };
+
+  const copy = (list) => (pointersPtr, bufferPtr) => {
+    list.forEach((item, index) => {
+      view().setUint32(pointersPtr + index * 4, bufferPtr, true);
+      bytes(bufferPtr, item.length).set(item);
+      bufferPtr += item.length;
+    });
+    return SUCCESS;
+  };
+
+  const argv = strings(args);
+  const environ = strings(Object.entries(env).map(([name, value]) => `${name}=${value}`));
+
+  const wasi = {
+    args_sizes_get: sizes(argv),
+    args_get: copy(argv),
+    environ_sizes_get: sizes(environ),
+    environ_get: copy(environ),
+
+    clock_res_get(_id, ptr) {
+      view().setBigUint64(ptr, 1000n, true);
+      return SUCCESS;
+    },
+
+    clock_time_get(id, _precision, ptr) {
+      const time = id === 0
+        ? BigInt(Date.now()) * 1000000n
+        : BigInt(Math.round(performance.now() * 1e6));
+      view().setBigUint64(ptr, time, true);
+      return SUCCESS;
+    },
+
+    fd_write(fd, ptr, len, writtenPtr) {
+      const entry = fds.get(fd);
+      let written = 0;
+      for (const buffer of iovecs(ptr, len)) {
+        switch (entry?.kind) {
+          case "stdout":
+            stdoutLength += buffer.length;
+            if (maxStdout !== null && stdoutLength > maxStdout) {
+              throw new RangeError(`standard output is larger than ${maxStdout} bytes`);
+            }
+            stdout.push(buffer.slice());
+            break;
+          case "stderr":
+            writeStderr(buffer, false);
+            break;
+          case "file":
+            for (let offset = 0; offset < buffer.length;) {
+              offset += entry.file.writeSync(buffer.subarray(offset));
+            }
+            break;
+          default:
+            return EBADF;
+        }
+        written += buffer.length;
+      }
+      view().setUint32(writtenPtr, written, true);
+      return SUCCESS;
+    },
+
+    fd_read(fd, ptr, len, readPtr) {
+      const entry = fds.get(fd);
+      let read = 0;
+      for (const buffer of iovecs(ptr, len)) {
+        let n;
+        switch (entry?.kind) {
+          case "stdin":
+            n = Math.min(buffer.length, entry.data.length - entry.offset);
+            buffer.set(entry.data.subarray(entry.offset, entry.offset + n));
+            entry.offset += n;
+            break;
+          case "file":
+            n = entry.file.readSync(buffer) ?? 0;
+            break;
+          default:
+            return EBADF;
+        }
+        read += n;
+        if (n < buffer.length) {
+          break;
+        }
+      }
+      view().setUint32(readPtr, read, true);
+      return SUCCESS;
+    },
+
+    fd_seek(fd, offset, whence, resultPtr) {
+      const entry = fds.get(fd);
+      if (entry === undefined) {
+        return EBADF;
+      }
+      if (entry.kind !== "file") {
+        return ESPIPE;
+      }
+      const position = entry.file.seekSync(Number(offset), whence);
+      view().setBigUint64(resultPtr, BigInt(position), true);
+      return SUCCESS;
+    },
+
+    fd_tell(fd, resultPtr) {
+      return wasi.fd_seek(fd, 0n, 1, resultPtr);
+    },
+
+    fd_close(fd) {
+      const entry = fds.get(fd);
+      if (entry === undefined) {
+        return EBADF;
+      }
+      entry.file?.close();
+      fds.delete(fd);
+      return SUCCESS;
+    },
+
+    fd_sync(fd) {
+      return fds.has(fd) ? SUCCESS : EBADF;
+    },
+
+    fd_datasync(fd) {
+      return fds.has(fd) ? SUCCESS : EBADF;
+    },
+
+    fd_fdstat_get(fd, ptr) {
+      const entry = fds.get(fd);
+      if (entry === undefined) {
+        return EBADF;
+      }
+      const type = {
+        dir: FILETYPE_DIRECTORY,
+        file: FILETYPE_REGULAR_FILE,
+      }[entry.kind] ?? FILETYPE_CHARACTER_DEVICE;
+      const v = view();
+      v.setUint8(ptr, type);
+      v.setUint16(ptr + 2, entry.append ? FDFLAGS_APPEND : 0, true);
+      v.setBigUint64(ptr + 8, RIGHTS_ALL, true);
+      v.setBigUint64(ptr + 16, RIGHTS_ALL, true);
+      return SUCCESS;
+    },
+
+    fd_fdstat_set_flags(fd) {
+      return fds.has(fd) ? SUCCESS : EBADF;
+    },
+
+    fd_filestat_get(fd, ptr) {
+      const entry = fds.get(fd);
+      switch (entry?.kind) {
+        case undefined:
+          return EBADF;
+        case "file":
+          writeFilestat(ptr, entry.file.statSync());
+          return SUCCESS;
+        case "dir":
+          writeFilestat(ptr, Deno.statSync([entry.root, ...entry.path].join("/")));
+          return SUCCESS;
+        default:
+          writeFilestat(ptr, {}, FILETYPE_CHARACTER_DEVICE);
+          return SUCCESS;
+      }
+    },
+
+    fd_prestat_get(fd, ptr) {
+      const entry = fds.get(fd);
+      if (entry?.guest === undefined) {
+        return EBADF;
+      }
+      const v = view();
+      v.setUint8(ptr, 0);
+      v.setUint32(ptr + 4, encoder.encode(entry.guest).length, true);
+      return SUCCESS;
+    },
+
+    fd_prestat_dir_name(fd, ptr, len) {
+      const entry = fds.get(fd);
+      if (entry?.guest === undefined) {
+        return EBADF;
+      }
+      bytes(ptr, len).set(encoder.encode(entry.guest).subarray(0, len));
+      return SUCCESS;
+    },
+
+    fd_readdir(fd, ptr, len, cookie, usedPtr) {
+      const entry = fds.get(fd);
+      if (entry === undefined) {
+        return EBADF;
+      }
+      if (entry.kind !== "dir") {
+        return ENOTDIR;
+      }
+      const names = [...Deno.readDirSync([entry.root, ...entry.path].join("/"))];
+      const out = bytes(ptr, len);
+      let used = 0;
+      for (let index = Number(cookie); index < names.length && used < len; index++) {
+        const name = encoder.encode(names[index].name);
+        const dirent = new Uint8Array(24 + name.length);
+        const v = new DataView(dirent.buffer);
+        v.setBigUint64(0, BigInt(index + 1), true);
+        v.setUint32(16, name.length, true);
+        v.setUint8(20, filetype(names[index]));
+        dirent.set(name, 24);
+        // A truncated last entry tells the module to retry with a
+        // larger buffer.
+        const n = Math.min(dirent.length, len - used);
+        out.set(dirent.subarray(0, n), used);
+        used += n;
+      }
+      view().setUint32(usedPtr, used, true);
+      return SUCCESS;
+    },
+
+    path_open(fd, _dirflags, ptr, len, oflags, rights, _inheriting, fdflags, resultPtr) {
+      const resolved = resolve(fd, ptr, len);
+      if (resolved.errno !== undefined) {
+        return resolved.errno;
+      }
+      let info = null;
+      try {
+        info = Deno.statSync(resolved.host);
+      } catch (e) {
+        if (!(e instanceof Deno.errors.NotFound)) {
+          throw e;
+        }
+      }
+      let entry;
+      if (oflags & OFLAGS_DIRECTORY && info === null) {
+        return ENOENT;
+      }
+      if (oflags & OFLAGS_DIRECTORY && !info.isDirectory) {
+        return ENOTDIR;
+      }
+      if (info?.isDirectory) {
+        if (oflags & (OFLAGS_CREAT | OFLAGS_TRUNC)) {
+          return EISDIR;
+        }
+        entry = { kind: "dir", root: resolved.root, path: resolved.path };
+      } else {
+        const append = (fdflags & FDFLAGS_APPEND) !== 0;
+        const write = (rights & RIGHTS_FD_WRITE) !== 0n || append;
+        const read = (rights & RIGHTS_FD_READ) !== 0n || !write;
+        const file = Deno.openSync(resolved.host, {
+          read,
+          write,
+          append,
+          create: (oflags & OFLAGS_CREAT) !== 0,
+          createNew: (oflags & OFLAGS_CREAT) !== 0 && (oflags & OFLAGS_EXCL) !== 0,
+          truncate: (oflags & OFLAGS_TRUNC) !== 0,
+        });
+        entry = { kind: "file", file, append };
+      }
+      const opened = nextFd++;
+      fds.set(opened, entry);
+      view().setUint32(resultPtr, opened, true);
+      return SUCCESS;
+    },
+
+    path_filestat_get(fd, flags, ptr, len, resultPtr) {
+      const resolved = resolve(fd, ptr, len);
+      if (resolved.errno !== undefined) {
+        return resolved.errno;
+      }
+      const info = flags & LOOKUP_SYMLINK_FOLLOW
+        ? Deno.statSync(resolved.host)
+        : Deno.lstatSync(resolved.host);
+      writeFilestat(resultPtr, info);
+      return SUCCESS;
+    },
+
+    path_create_directory(fd, ptr, len) {
+      const resolved = resolve(fd, ptr, len);
+      if (resolved.errno !== undefined) {
+        return resolved.errno;
+      }
+      Deno.mkdirSync(resolved.host);
+      return SUCCESS;
+    },
+
+    path_remove_directory(fd, ptr, len) {
+      const resolved = resolve(fd, ptr, len);
+      if (resolved.errno !== undefined) {
+        return resolved.errno;
+      }
+      if (!Deno.lstatSync(resolved.host).isDirectory) {
+        return ENOTDIR;
+      }
+      Deno.removeSync(resolved.host);
+      return SUCCESS;
+    },
+
+    path_unlink_file(fd, ptr, len) {
+      const resolved = resolve(fd, ptr, len);
+      if (resolved.errno !== undefined) {
+        return resolved.errno;
+      }
+      if (Deno.lstatSync(resolved.host).isDirectory) {
+        return EISDIR;
+      }
+      Deno.removeSync(resolved.host);
+      return SUCCESS;
+    },
+
+    path_rename(fd, ptr, len, newFd, newPtr, newLen) {
+      const from = resolve(fd, ptr, len);
+      const to = resolve(newFd, newPtr, newLen);
+      const errno = from.errno ?? to.errno;
+      if (errno !== undefined) {
+        return errno;
+      }
+      Deno.renameSync(from.host, to.host);
+      return SUCCESS;
+    },
+
+    random_get(ptr, len) {
+      // getRandomValues fills at most 64 KiB at a time.
+      for (let offset = 0; offset < len; offset += 65536) {
+        crypto.getRandomValues(bytes(ptr + offset, Math.min(65536, len - offset)));
+      }
+      return SUCCESS;
+    },
+
+    sched_yield() {
+      return SUCCESS;
+    },
+
+    proc_exit(code) {
+      throw new Exit(code);
+    },
+  };
+
+  // Host failures the module can handle become its errno, anything else
+  // fails the run.
+  const imports = {};
+  for (const [name, f] of Object.entries(wasi)) {
+    imports[name] = (...args) => {
+      try {
+        return f(...args);
+      } catch (e) {
+        const errno = ERRNO[e?.name];
+        if (errno === undefined) {
+          throw e;
+        }
+        return errno;
+      }
+    };
+  }
+  const instance = new WebAssembly.Instance(module, {
+    wasi_snapshot_preview1: new Proxy(imports, {
+      get: (target, name) => target[name] ?? (() => ENOSYS),
+    }),
+  });
+  memory = instance.exports.memory;
+
+  let status = 0;
+  try {
+    instance.exports._start();
+  } catch (e) {
+    if (!(e instanceof Exit)) {
+      throw e;
+    }
+    status = e.code;
+  } finally {
+    writeStderr(new Uint8Array(), true);
+    for (const entry of fds.values()) {
+      entry.file?.close();
+    }
+  }
+  if (status !== 0) {
+    throw new Error(`the WebAssembly module exited with status ${status}`);
+  }
+  const output = new Uint8Array(stdoutLength);
+  let offset = 0;
+  for (const chunk of stdout) {
+    output.set(chunk, offset);
+    offset += chunk.length;
+  }
+  return output;
+}
@@ -0,0 +1,108 @@
+use anyhow::{bail, Context, Error};
+use base64::Engine;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+
+use crate::inputs::Inputs;
+use crate::options::{Entrypoint, RunOptions};
+use crate::{file_url, wasm, OutputValue};
+
+const PREVIEW1: &str = "wasi_snapshot_preview1";
+
+/// How [`run_wasi`] runs a WASI command module.
+#[derive(Debug, Clone, Default)]
+pub struct WasiOptions {
+    /// Options of the run. Timeout, fuel, metrics, cancellation and the
+    /// other limits apply as they do to scripts, and `env` is the module's
+    /// environment, empty when unset.
+    pub run: RunOptions,
+    /// Host directories the module sees under the guest paths, e.g.
+    /// `("/data", "/srv/data")`. The run's permissions must allow access
+    /// to them.
+    pub preopens: Vec<(String, PathBuf)>,
+    pub stdin: Vec<u8>,
+    pub output: WasiOutput,
+}
+
+/// What [`run_wasi`] makes of the module's standard output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WasiOutput {
+    /// Parsed as JSON, empty output being `null`.
+    #[default]
+    Json,
+    Bytes,
+}
+
+/// Runs the `_start` export of a WASI preview1 command module on V8's
+/// WebAssembly, with `args` following the module's file name as its
+/// arguments. Standard error goes to the console, one line per entry. A
+/// nonzero exit status fails the run.
+pub fn run_wasi(
+    module: PathBuf,
+    args: &[String],
+    options: WasiOptions,
+) -> Result<OutputValue, Error> {
+    let bytes =
+        std::fs::read(&module).with_context(|| format!("could not read {}", module.display()))?;
+    let interface = wasm::parse(&bytes)?;
+    if let Some(import) = interface.imports.iter().find(|import| *import != PREVIEW1) {
+        bail!(
+            "{} imports from {}, only {} is available to WASI modules",
+            module.display(),
+            import,
+            PREVIEW1
+        );
+    }
+    if !interface.exports.iter().any(|name| name == "_start") {
+        bail!(
+            "{} is not a WASI command, it has no _start export",
+            module.display()
+        );
+    }
+
+    let encoded = base64::engine::general_purpose::STANDARD.encode(&bytes);
+    let entry = format!(
+        "const MODULE = \"{}\";\n{}",
+        encoded,
+        include_str!("wasi.js")
+    );
+    let mut entry_path = module.clone().into_os_string();
+    entry_path.push(".js");
+    let entry_path = PathBuf::from(entry_path);
+
+    let mut run = options.run;
+    let env = run.env.clone().unwrap_or_default();
+    let max_stdout = run.max_output_bytes;
+    run.entrypoint = Entrypoint::MainFunction;
+    run.virtual_modules
+        .insert(file_url::path_to_specifier(&entry_path)?, entry);
+
+    let name = module
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let args: Vec<_> = std::iter::once(name).chain(args.iter().cloned()).collect();
+    let preopens = options
+        .preopens
+        .iter()
+        .map(|(guest, host)| Ok(json!([guest, std::path::absolute(host)?.to_string_lossy()])))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let inputs = Inputs::new()
+        .json("args", json!(args))
+        .json("env", json!(env))
+        .bytes("stdin", options.stdin, "application/octet-stream")
+        .json("preopens", Value::Array(preopens))
+        .json("maxStdout", json!(max_stdout));
+
+    match crate::run_with_values(entry_path, inputs, run)? {
+        OutputValue::Bytes(stdout) if options.output == WasiOutput::Json => {
+            if stdout.is_empty() {
+                return Ok(OutputValue::Json(Value::Null));
+            }
+            let value = serde_json::from_slice(&stdout)
+                .with_context(|| format!("standard output of {} is not json", module.display()))?;
+            Ok(OutputValue::Json(value))
+        }
+        output => Ok(output),
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::{anyhow, bail, Error};
+use base64::Engine;
+use std::fmt::Write;
+
+/// JS module standing in for a WebAssembly module, which deno_core can't
+/// load itself. It instantiates the bytes with the wasm module's imports
+/// taken from the JS modules they name, and re-exports the instance's
+/// exports under their own names. Unless the wasm module exports a
+/// `default` itself, the default export is the instance's exports object.
+pub(crate) fn wrapper(bytes: &[u8]) -> Result<String, Error> {
+    let interface = parse(bytes)?;
+    let mut code = String::new();
+    for (index, module) in interface.imports.iter().enumerate() {
+        writeln!(code, "import * as import{} from {};", index, quote(module))?;
+    }
+    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+    writeln!(
+        code,
+        "const bytes = Uint8Array.from(atob(\"{}\"), (c) => c.charCodeAt(0));",
+        encoded
+    )?;
+    let imports = interface
+        .imports
+        .iter()
+        .enumerate()
+        .map(|(index, module)| format!("{}: import{}", quote(module), index))
+        .collect::<Vec<_>>()
+        .join(", ");
+    writeln!(
+        code,
+        "const {{ instance }} = await WebAssembly.instantiate(bytes, {{ {} }});",
+        imports
+    )?;
+    for (index, name) in interface.exports.iter().enumerate() {
+        writeln!(
+            code,
+            "const export{0} = instance.exports[{1}];\nexport {{ export{0} as {1} }};",
+            index,
+            quote(name)
+        )?;
+    }
+    if !interface.exports.iter().any(|name| name == "default") {
+        writeln!(code, "export default instance.exports;")?;
+    }
+    Ok(code)
+}
+
+fn quote(text: &str) -> String {
+    serde_json::Value::from(text).to_string()
+}
+
+/// Names of the modules a wasm module imports from, and of its exports.
+pub(crate) struct Interface {
+    pub(crate) imports: Vec<String>,
+    pub(crate) exports: Vec<String>,
+}
+
+pub(crate) fn parse(bytes: &[u8]) -> Result<Interface, Error> {
+    let mut reader = Reader { bytes, offset: 0 };
+    if reader.take(4)? != b"\0asm" {
+        bail!("not a WebAssembly module");
+    }
+    reader.take(4)?;
+
+    let mut interface = Interface {
+        imports: vec![],
+        exports: vec![],
+    };
+    while reader.offset < bytes.len() {
+        let id = reader.byte()?;
+        let size = reader.leb()? as usize;
+        let mut section = Reader {
+            bytes: reader.take(size)?,
+            offset: 0,
+        };
+        match id {
+            2 => {
+                for _ in 0..section.leb()? {
+                    let module = section.name()?;
+                    section.name()?;
+                    section.skip_import_desc()?;
+                    if !interface.imports.contains(&module) {
+                        interface.imports.push(module);
+                    }
+                }
+            }
+            7 => {
+                for _ in 0..section.leb()? {
+                    interface.exports.push(section.name()?);
+                    section.byte()?;
+                    section.leb()?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(interface)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| anyhow!("truncated WebAssembly module"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn leb(&mut self) -> Result<u32, Error> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= u32::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("invalid integer in WebAssembly module")
+    }
+
+    fn name(&mut self) -> Result<String, Error> {
+        let len = self.leb()? as usize;
+        Ok(String::from_utf8(self.take(len)?.to_vec())?)
+    }
+
+    fn limits(&mut self) -> Result<(), Error> {
+        let flags = self.byte()?;
+        self.leb()?;
+        if flags & 1 != 0 {
+            self.leb()?;
+        }
+        Ok(())
+    }
+
+    fn skip_import_desc(&mut self) -> Result<(), Error> {
+        match self.byte()? {
+            // Function type index.
+            0 => {
+                self.leb()?;
+            }
+            // Table: element type, limits.
+            1 => {
+                self.byte()?;
+                self.limits()?;
+            }
+            2 => self.limits()?,
+            // Global: value type, mutability.
+            3 => {
+                self.take(2)?;
+            }
+            // Tag: attribute, type index.
+            4 => {
+                self.byte()?;
+                self.leb()?;
+            }
+            kind => bail!("unknown import kind {} in WebAssembly module", kind),
+        }
+        Ok(())
+    }
+}
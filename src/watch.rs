@@ -0,0 +1,240 @@
+use anyhow::{anyhow, Context, Error};
+use async_trait::async_trait;
+use deno_core::ModuleSpecifier;
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::inputs::Inputs;
+use crate::loader_stack::{LayerModule, LoaderLayer};
+use crate::options::RunOptions;
+
+/// How long to wait for more events after a change, so the writes of one
+/// save are handled together.
+const SETTLE: Duration = Duration::from_millis(50);
+
+/// Compiled modules of earlier runs and the imports between them, for
+/// reruns that only load again what a change affects. Put it on top of a
+/// [`LoaderStack`](crate::LoaderStack), then
+/// [`invalidate`](Self::invalidate) what changed between runs.
+#[derive(Clone, Default)]
+pub struct WatchCache(Arc<Mutex<Graph>>);
+
+#[derive(Default)]
+struct Graph {
+    modules: HashMap<ModuleSpecifier, LayerModule>,
+    /// Every specifier asked for, loaded or not.
+    requested: HashSet<ModuleSpecifier>,
+    /// Modules importing each module.
+    dependents: HashMap<ModuleSpecifier, HashSet<ModuleSpecifier>>,
+}
+
+impl WatchCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops the local modules at `changed` and, transitively, those
+    /// importing them, so the next run loads them again. Remote modules
+    /// are kept.
+    pub fn invalidate(&self, changed: &[PathBuf]) -> Invalidation {
+        let mut graph = self.0.lock().unwrap();
+        let mut invalidation = Invalidation::default();
+        let mut pending = Vec::new();
+        for path in changed {
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+            let Ok(specifier) = ModuleSpecifier::from_file_path(canonical) else {
+                continue;
+            };
+            if graph.requested.contains(&specifier) {
+                invalidation.changed.push(path.clone());
+                pending.push(specifier);
+            }
+        }
+        while let Some(specifier) = pending.pop() {
+            if specifier.scheme() != "file" || invalidation.reloading.contains(&specifier) {
+                continue;
+            }
+            if let Some(dependents) = graph.dependents.get(&specifier) {
+                pending.extend(dependents.iter().cloned());
+            }
+            invalidation.reloading.push(specifier);
+        }
+        for specifier in &invalidation.reloading {
+            graph.modules.remove(specifier);
+            // Their imports are recorded again as they load.
+            for dependents in graph.dependents.values_mut() {
+                dependents.remove(specifier);
+            }
+        }
+        invalidation
+    }
+
+    /// Directories holding the local modules asked for so far.
+    pub fn directories(&self) -> Vec<PathBuf> {
+        let graph = self.0.lock().unwrap();
+        let mut directories: Vec<_> = graph
+            .requested
+            .iter()
+            .filter_map(|specifier| specifier.to_file_path().ok())
+            .filter_map(|path| path.parent().map(PathBuf::from))
+            .collect();
+        directories.sort();
+        directories.dedup();
+        directories
+    }
+}
+
+impl fmt::Debug for WatchCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let graph = self.0.lock().unwrap();
+        f.debug_struct("WatchCache")
+            .field("modules", &graph.modules.len())
+            .finish()
+    }
+}
+
+#[async_trait(?Send)]
+impl LoaderLayer for WatchCache {
+    async fn load(&self, specifier: &ModuleSpecifier) -> Result<Option<LayerModule>, Error> {
+        let mut graph = self.0.lock().unwrap();
+        graph.requested.insert(specifier.clone());
+        Ok(graph.modules.get(specifier).cloned())
+    }
+
+    async fn loaded(&self, specifier: &ModuleSpecifier, module: &LayerModule) -> Result<(), Error> {
+        let mut graph = self.0.lock().unwrap();
+        graph.modules.insert(specifier.clone(), module.clone());
+        Ok(())
+    }
+
+    fn resolved(&self, specifier: &ModuleSpecifier, referrer: &ModuleSpecifier) {
+        let mut graph = self.0.lock().unwrap();
+        graph
+            .dependents
+            .entry(specifier.clone())
+            .or_default()
+            .insert(referrer.clone());
+    }
+}
+
+/// Files that changed and the modules loaded again because of them.
+#[derive(Debug, Clone, Default)]
+pub struct Invalidation {
+    pub changed: Vec<PathBuf>,
+    /// The changed modules and those importing them, directly or not.
+    pub reloading: Vec<ModuleSpecifier>,
+}
+
+impl fmt::Display for Invalidation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let changed: Vec<_> = self
+            .changed
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&cwd)
+                    .unwrap_or(path)
+                    .display()
+                    .to_string()
+            })
+            .collect();
+        let count = self.reloading.len();
+        write!(
+            f,
+            "changed {} → reloading {} module{}",
+            changed.join(", "),
+            count,
+            if count == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// What [`watch`] reports as it goes.
+#[derive(Debug)]
+pub enum WatchEvent {
+    /// A run finished.
+    Ran(Result<Value, Error>),
+    /// Files the function imports changed, the function runs again next.
+    Changed(Invalidation),
+}
+
+/// Runs the function, then again whenever it or a local module it imports
+/// changes, until `on_event` breaks. Modules the change doesn't affect are
+/// reused as compiled on the first run that loaded them. Not for
+/// `RunOptions::subprocess`, whose child loads every module itself.
+pub fn watch(
+    function: PathBuf,
+    inputs: impl Into<Inputs>,
+    mut options: RunOptions,
+    mut on_event: impl FnMut(WatchEvent) -> ControlFlow<()>,
+) -> Result<(), Error> {
+    let inputs = inputs.into();
+    let cache = WatchCache::new();
+    // Watched from the start, in case the first run fails before loading it.
+    let entry = function
+        .canonicalize()
+        .or_else(|_| std::path::absolute(&function))?;
+    if let Ok(entry) = ModuleSpecifier::from_file_path(entry) {
+        cache.0.lock().unwrap().requested.insert(entry);
+    }
+    let stack = options.loader_stack.take().unwrap_or_default();
+    options.loader_stack = Some(stack.above(cache.clone()));
+    let (sender, events) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let mut watched = HashSet::new();
+    loop {
+        let result = crate::run_with_options(function.clone(), inputs.clone(), options.clone());
+        for directory in cache.directories() {
+            if watched.insert(directory.clone()) {
+                watcher
+                    .watch(&directory, RecursiveMode::NonRecursive)
+                    .with_context(|| format!("could not watch {}", directory.display()))?;
+            }
+        }
+        if on_event(WatchEvent::Ran(result)).is_break() {
+            return Ok(());
+        }
+        let invalidation = loop {
+            let invalidation = cache.invalidate(&next_change(&events)?);
+            if !invalidation.reloading.is_empty() {
+                break invalidation;
+            }
+        };
+        log::debug!("{}", invalidation);
+        if on_event(WatchEvent::Changed(invalidation)).is_break() {
+            return Ok(());
+        }
+    }
+}
+
+type Events = Receiver<notify::Result<notify::Event>>;
+
+/// Paths touched by the next batch of file events.
+fn next_change(events: &Events) -> Result<Vec<PathBuf>, Error> {
+    let stopped = || anyhow!("the file watcher stopped");
+    let mut paths = Vec::new();
+    loop {
+        let event = match paths.is_empty() {
+            true => events.recv().map_err(|_| stopped())?,
+            false => match events.recv_timeout(SETTLE) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Err(stopped()),
+            },
+        };
+        let event = event.context("file watcher failed")?;
+        if !matches!(event.kind, EventKind::Access(_)) {
+            paths.extend(event.paths);
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
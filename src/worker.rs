@@ -0,0 +1,734 @@
+use anyhow::{anyhow, bail, Error};
+use deno_core::error::JsError;
+use deno_core::stats::{
+    RuntimeActivity, RuntimeActivityStats, RuntimeActivityStatsFactory, RuntimeActivityStatsFilter,
+};
+use deno_core::{
+    serde_v8, v8, FastString, ModuleId, ModuleLoader, ModuleSpecifier, PollEventLoopOptions,
+};
+use deno_permissions::PermissionsContainer;
+use deno_permissions::{Permissions, PermissionsOptions};
+use deno_runtime::worker::MainWorker;
+use deno_runtime::worker::WorkerOptions;
+use std::cell::Cell;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::console::{self, ConsoleCapture, ConsoleSink};
+use crate::determinism::DeterministicState;
+use crate::embedded::EmbeddedModuleLoader;
+use crate::error::{self, RuntimeError};
+use crate::fuel::FuelMeter;
+use crate::imports::ImportGraph;
+use crate::inputs::Inputs;
+use crate::options::{DanglingWork, Entrypoint, RunOptions};
+use crate::platform::PlatformGuard;
+use crate::redact::Redactor;
+use crate::sandbox::{RefuseFileFetch, Workspace};
+use crate::stats::{self, ExecutionStats};
+use crate::warning::{Warning, Warnings};
+use crate::{file_url, host, inspector, NetworkModuleLoader};
+
+/// A bootstrapped worker with the function module evaluated, ready to have
+/// its entrypoint called any number of times.
+pub(crate) struct LoadedModule {
+    pub(crate) worker: MainWorker,
+    pub(crate) id: u64,
+    function: PathBuf,
+    mod_id: ModuleId,
+    activity: RuntimeActivityStatsFactory,
+    activity_filter: RuntimeActivityStatsFilter,
+    /// Activity that belongs to the host rather than the function.
+    baseline: RuntimeActivityStats,
+    heap: HeapLimit,
+    /// Removed after the worker, when the sandbox is temporary.
+    workspace: Option<Workspace>,
+    _platform: PlatformGuard,
+}
+
+/// Heap usage at which the near-heap-limit callback terminated the
+/// isolate, if it did.
+#[derive(Clone, Default)]
+struct HeapLimit(Rc<Cell<Option<usize>>>);
+
+impl HeapLimit {
+    fn install(worker: &mut MainWorker, options: &RunOptions) -> Self {
+        let heap = HeapLimit::default();
+        if options.max_heap_size.is_some() {
+            let isolate = worker.js_runtime.v8_isolate().thread_safe_handle();
+            let observed = heap.0.clone();
+            worker
+                .js_runtime
+                .add_near_heap_limit_callback(move |current, _initial| {
+                    observed.set(Some(current));
+                    isolate.terminate_execution();
+                    // Headroom for the termination to unwind.
+                    current * 2
+                });
+        }
+        heap
+    }
+
+    /// Replaces the error of a run that hit the limit.
+    fn check<T>(&self, result: Result<T, Error>, options: &RunOptions) -> Result<T, Error> {
+        match (self.0.get(), options.max_heap_size) {
+            (Some(observed), Some(limit)) if result.is_err() => {
+                Err(RuntimeError::HeapLimitExceeded { limit, observed }.into())
+            }
+            _ => result,
+        }
+    }
+}
+
+impl LoadedModule {
+    pub(crate) fn namespace(&mut self) -> Result<v8::Global<v8::Object>, Error> {
+        self.worker.js_runtime.get_module_namespace(self.mod_id)
+    }
+
+    /// Gives the next call a fresh sandbox, see `Workspace::reset`.
+    pub(crate) fn reset_sandbox(&self, options: &RunOptions) -> Result<(), Error> {
+        match (&self.workspace, &options.fs_sandbox) {
+            (Some(workspace), Some(sandbox)) => workspace.reset(sandbox),
+            _ => Ok(()),
+        }
+    }
+}
+
+static NEXT_WORKER_ID: AtomicU64 = AtomicU64::new(1);
+
+type FunctionLoader = (ModuleSpecifier, Rc<dyn ModuleLoader>, Rc<ImportGraph>);
+
+/// Loader for the function's import graph as configured by `options`, the
+/// specifier of the function module, and the graph the network loader
+/// records.
+pub(crate) fn module_loader(
+    function: &Path,
+    options: &RunOptions,
+    permissions: Option<&PermissionsContainer>,
+) -> Result<FunctionLoader, Error> {
+    let mut network_loader =
+        NetworkModuleLoader::default().with_follow_symlinks(!options.deny_symlinks);
+    if let Some(permissions) = permissions {
+        network_loader = network_loader.with_permissions(permissions.clone());
+    }
+    if options.fs_sandbox.is_some() {
+        // Reads are all granted in a sandbox, so `import()` can't rely on
+        // the permissions to keep out of the host filesystem.
+        let entry = function
+            .canonicalize()
+            .or_else(|_| std::path::absolute(function))?;
+        if let Some(root) = entry.parent() {
+            network_loader = network_loader.with_import_root(root);
+        }
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(cache) = &options.module_cache {
+        network_loader = network_loader.with_cache(cache.clone());
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(http) = &options.http {
+        network_loader = network_loader.with_http(http)?;
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(policy) = &options.import_policy {
+        network_loader = network_loader.with_import_policy(policy.clone());
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(cache) = &options.npm_cache {
+        network_loader = network_loader.with_npm_cache(cache.clone());
+    }
+    #[cfg(feature = "net-loader")]
+    if let Some(path) = &options.lockfile {
+        network_loader = network_loader.with_lockfile(path, options.lockfile_write)?;
+    }
+    #[cfg(feature = "git")]
+    if options.allow_mutable_git_refs {
+        network_loader = network_loader.with_mutable_git_refs();
+    }
+    #[cfg(feature = "s3")]
+    if let Some(resolver) = &options.s3 {
+        network_loader = network_loader.with_s3(resolver.clone());
+    }
+    if let Some(stats) = &options.stats {
+        network_loader = network_loader.with_stats(stats.clone());
+    }
+    if let Some(import_map) = &options.import_map {
+        network_loader = network_loader.with_import_map(import_map.clone());
+    }
+    for (specifier, source) in &options.virtual_modules {
+        network_loader.add_virtual_module(specifier.clone(), source.clone());
+    }
+    if let Some(cache) = &options.transpile_cache {
+        network_loader = network_loader.with_transpile_cache(cache.clone());
+    }
+    let imports = network_loader.imports.clone();
+    let network_loader: Rc<dyn ModuleLoader> = match &options.module_loader {
+        Some(factory) => factory.create(),
+        None => Rc::new(network_loader),
+    };
+    let network_loader: Rc<dyn ModuleLoader> = match &options.loader_stack {
+        Some(stack) => Rc::new(stack.loader(
+            network_loader,
+            imports.clone(),
+            options.transpile_cache.clone(),
+        )),
+        None => network_loader,
+    };
+    let (main_module, module_loader): (_, Rc<dyn ModuleLoader>) = match &options.embedded {
+        Some(modules) => {
+            let mut loader = EmbeddedModuleLoader::new(modules.clone());
+            if modules.network_fallback() {
+                loader = loader.with_fallback(network_loader);
+            }
+            let path = function.to_string_lossy();
+            (modules.specifier(&path), Rc::new(loader))
+        }
+        None => (
+            file_url::canonical_specifier(
+                file_url::entry_specifier(function)?,
+                options.deny_symlinks,
+            )?,
+            network_loader,
+        ),
+    };
+    Ok((main_module, module_loader, imports))
+}
+
+pub(crate) async fn load(function: &Path, options: &RunOptions) -> Result<LoadedModule, Error> {
+    if options
+        .cancellation
+        .as_ref()
+        .is_some_and(|c| c.is_cancelled())
+    {
+        return Err(RuntimeError::Cancelled.into());
+    }
+    let platform = PlatformGuard::acquire()?;
+    let started = Instant::now();
+    let permissions = match &options.permissions {
+        Some(permissions) => {
+            let mut permissions = permissions.to_options();
+            if let Some(env) = &options.env {
+                // An empty allowlist would allow every variable.
+                permissions.allow_env = (!env.is_empty()).then(|| env.keys().cloned().collect());
+            }
+            if options.fs_sandbox.is_some() {
+                // Checks see the paths inside the sandbox, which confines them.
+                // Whatever reads the host filesystem without going through it,
+                // `file:` fetches and imports, is refused separately.
+                permissions.allow_read = Some(vec![]);
+                permissions.allow_write = Some(vec![]);
+            }
+            permissions
+        }
+        None => PermissionsOptions {
+            allow_all: true,
+            allow_env: None,
+            deny_env: None,
+            allow_hrtime: false,
+            deny_hrtime: true,
+            allow_net: Some(vec![]),
+            deny_net: None,
+            allow_ffi: None,
+            deny_ffi: None,
+            allow_read: None,
+            deny_read: None,
+            allow_run: None,
+            deny_run: None,
+            allow_sys: None,
+            deny_sys: None,
+            allow_write: None,
+            deny_write: None,
+            prompt: false,
+        },
+    };
+    let permissions = PermissionsContainer::new(Permissions::from_options(&permissions)?);
+    let (main_module, module_loader, _) = module_loader(function, options, Some(&permissions))?;
+
+    log::debug!("setting up runtime worker");
+    // A snapshot already holds the extension's JS.
+    let host_init = match options.snapshot {
+        Some(_) => host::host::init_ops,
+        None => host::host::init_ops_and_esm,
+    };
+    let redactor = Redactor::for_console(options);
+    let redacts = !redactor.is_empty() || !options.redact.input_patterns.is_empty();
+    let host_extension = host_init(
+        host::HostFiles {
+            paths: options.files.clone(),
+            read_limit: options
+                .file_read_limit
+                .unwrap_or(host::DEFAULT_FILE_READ_LIMIT),
+        },
+        options.host_api.clone(),
+        ConsoleCapture {
+            // Console output goes through the runtime to redact secrets.
+            sink: options
+                .console
+                .clone()
+                .or_else(|| redacts.then_some(ConsoleSink::Stdio)),
+            event_limit: options
+                .console_event_limit
+                .unwrap_or(console::DEFAULT_EVENT_LIMIT),
+            invocation: 0,
+            warnings: Warnings::new(options),
+            redactor,
+        },
+        options.env.clone(),
+        options.messages.as_ref().map(|port| port.host_messages()),
+    );
+    let sandbox = options.fs_sandbox.as_ref().map(|s| s.mount()).transpose()?;
+    let worker_options = WorkerOptions {
+        module_loader,
+        extensions: vec![host_extension],
+        startup_snapshot: options.snapshot.map(|snapshot| snapshot.0),
+        create_params: options
+            .max_heap_size
+            .map(|max| v8::CreateParams::default().heap_limits(0, max)),
+        get_error_class_fn: Some(&error_class),
+        maybe_inspector_server: options.inspect.map(inspector::server).transpose()?,
+        fs: match &sandbox {
+            Some((fs, _)) => fs.clone(),
+            None => Arc::new(deno_fs::RealFs),
+        },
+        ..Default::default()
+    };
+
+    let mut worker = stats::phase_sync("bootstrap", || {
+        MainWorker::bootstrap_from_options(main_module.clone(), permissions, worker_options)
+    });
+    let workspace = sandbox.map(|(_, workspace)| {
+        let state = worker.js_runtime.op_state();
+        let mut state = state.borrow_mut();
+        state.put(host::Sandboxed);
+        // `file:` fetches bypass the sandbox's filesystem and its grants.
+        state.borrow_mut::<deno_fetch::Options>().file_fetch_handler = Rc::new(RefuseFileFetch);
+        workspace
+    });
+    {
+        let state = worker.js_runtime.op_state();
+        let mut state = state.borrow_mut();
+        if let Some(determinism) = &options.determinism {
+            state.put(DeterministicState::new(determinism));
+        }
+        if let Some(trace) = &options.trace {
+            state.put(trace.clone());
+        }
+        #[cfg(feature = "otel")]
+        if options.propagate_trace_context {
+            if let Some(context) = crate::otel::TraceContext::current() {
+                state.put(host::TraceHeaders(context.headers()));
+            }
+        }
+    }
+    // Hooks that need the bootstrapped globals, see runtime.js.
+    worker.execute_script(
+        "[host:install]",
+        FastString::from_static("globalThis[Symbol.for(\"experimental_runtime.install\")]();"),
+    )?;
+    let activity = worker.js_runtime.runtime_activity_stats_factory();
+    let activity_filter = RuntimeActivityStatsFilter::default()
+        .with_ops()
+        .with_timers();
+    let baseline = activity.clone().capture(&activity_filter);
+
+    // Loading through `preload_main_module` marks only the entry with
+    // `import.meta.main`, its `import.meta.url` is the post-redirect URL.
+    let mod_id = stats::phase("load", worker.preload_main_module(&main_module)).await;
+    record(options, |stats| stats.load += started.elapsed());
+    let mod_id = mod_id?;
+
+    log::debug!("evaluating function");
+    let heap = HeapLimit::install(&mut worker, options);
+    let isolate = worker.js_runtime.v8_isolate().thread_safe_handle();
+    let evaluated = within(isolate, options.timeout, options, async {
+        // Settles top-level await, a rejection is the run's error.
+        let started = Instant::now();
+        let evaluated = stats::phase("evaluate", worker.evaluate_module(mod_id)).await;
+        record(options, |stats| stats.evaluate += started.elapsed());
+        evaluated.map_err(|e| error::evaluation(&main_module, e))?;
+
+        log::debug!("running event loop");
+        let started = Instant::now();
+        let settled = stats::phase("event_loop", worker.run_event_loop(false)).await;
+        record(options, |stats| stats.event_loop += started.elapsed());
+        settled.map_err(error::from_js)?;
+        log::debug!("done event loop");
+        Ok(())
+    })
+    .await;
+    heap.check(evaluated, options)?;
+    sample_heap(&mut worker, options);
+
+    Ok(LoadedModule {
+        worker,
+        id: NEXT_WORKER_ID.fetch_add(1, Ordering::Relaxed),
+        function: function.to_path_buf(),
+        mod_id,
+        activity,
+        activity_filter,
+        baseline,
+        heap,
+        workspace,
+        _platform: platform,
+    })
+}
+
+/// Calls the entrypoint, or reads the export, and resolves the result,
+/// within `RunOptions::timeout`.
+pub(crate) async fn call(
+    module: &mut LoadedModule,
+    inputs: Inputs,
+    options: &RunOptions,
+) -> Result<v8::Global<v8::Value>, Error> {
+    call_with_timeout(module, inputs, options, options.timeout).await
+}
+
+async fn invoke(
+    module: &mut LoadedModule,
+    inputs: Inputs,
+    options: &RunOptions,
+) -> Result<v8::Global<v8::Value>, Error> {
+    let worker = &mut module.worker;
+    {
+        let state = worker.js_runtime.op_state();
+        let mut state = state.borrow_mut();
+        let capture = state.borrow_mut::<ConsoleCapture>();
+        capture.invocation += 1;
+        // Secret inputs are known from here on.
+        capture.redactor = Redactor::new(options, &inputs);
+    }
+    if let Some(determinism) = &options.determinism {
+        // Every invocation starts from the seed and the start time again.
+        worker
+            .js_runtime
+            .op_state()
+            .borrow_mut()
+            .put(DeterministicState::new(determinism));
+    }
+    if options.inspect.is_some() && options.inspect_brk {
+        log::debug!("waiting for a debugger before calling the entrypoint");
+        worker
+            .js_runtime
+            .inspector()
+            .borrow_mut()
+            .wait_for_session_and_break_on_next_statement();
+    }
+    let fres = {
+        let global = worker.js_runtime.get_module_namespace(module.mod_id)?;
+        let scope = &mut worker.js_runtime.handle_scope();
+        let namespace = v8::Local::<v8::Object>::new(scope, global);
+
+        let mut export = options.entrypoint.export_name();
+        let mut func = export_value(scope, namespace, export)?;
+        // `main` falls back to the default export.
+        if func.is_none() && matches!(options.entrypoint, Entrypoint::MainFunction) {
+            export = "default";
+            func = export_value(scope, namespace, export)?;
+        }
+        let Some(func) = func else {
+            let export = options.entrypoint.export_name();
+            return Err(missing_entrypoint(scope, namespace, export).into());
+        };
+
+        let func_res = if let Some(args) = options.entrypoint.arguments() {
+            let args = match args {
+                Some(args) => args
+                    .iter()
+                    .map(|arg| serde_v8::to_v8(scope, arg))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| RuntimeError::Serialization {
+                        message: e.to_string(),
+                    })?,
+                None => vec![inputs.to_v8(scope)?],
+            };
+
+            let scope = &mut v8::TryCatch::new(scope);
+            let started = Instant::now();
+            let returned =
+                callable(scope, func).and_then(|(func, recv)| func.call(scope, recv, &args));
+            record(options, |stats| stats.call += started.elapsed());
+            match returned {
+                Some(value) => value,
+                None if scope.has_terminated() => bail!("execution was terminated"),
+                None => {
+                    let Some(exception) = scope.exception() else {
+                        return Err(missing_entrypoint(scope, namespace, export).into());
+                    };
+                    let error = JsError::from_v8_exception(scope, exception);
+                    return Err(RuntimeError::js_exception(error).into());
+                }
+            }
+        } else {
+            func
+        };
+
+        v8::Global::new(scope, func_res)
+    };
+    let messages = options.messages.is_some();
+    if messages {
+        await_messages(worker, true)?;
+    }
+    let f = worker.js_runtime.resolve(fres);
+    let started = Instant::now();
+    let f = worker
+        .js_runtime
+        .with_event_loop_promise(f, PollEventLoopOptions::default())
+        .await;
+    record(options, |stats| stats.event_loop += started.elapsed());
+    if messages {
+        await_messages(worker, false)?;
+    }
+    let f = f.map_err(error::from_js)?;
+
+    if options.dangling_work != DanglingWork::Ignore {
+        let current = module.activity.clone().capture(&module.activity_filter);
+        let pending = RuntimeActivityStats::diff(&module.baseline, &current)
+            .appeared
+            .iter()
+            .map(|activity| match activity {
+                RuntimeActivity::AsyncOp(_, _, name) => format!("async op {}", name),
+                RuntimeActivity::Resource(_, _, name) => format!("resource {}", name),
+                RuntimeActivity::Timer(..) => String::from("timer"),
+                RuntimeActivity::Interval(..) => String::from("interval"),
+            })
+            .collect::<Vec<_>>();
+        if !pending.is_empty() {
+            if options.dangling_work == DanglingWork::Fail {
+                return Err(RuntimeError::DanglingWork { pending }.into());
+            }
+            Warnings::new(options).emit(Warning::DanglingWork { pending })?;
+        }
+    }
+    Ok(f)
+}
+
+/// Whether messages from the host keep the event loop alive, see
+/// runtime.js.
+fn await_messages(worker: &mut MainWorker, awaiting: bool) -> Result<(), Error> {
+    let script = if awaiting {
+        "globalThis[Symbol.for(\"experimental_runtime.awaitMessages\")](true);"
+    } else {
+        "globalThis[Symbol.for(\"experimental_runtime.awaitMessages\")](false);"
+    };
+    worker.execute_script("[host:messages]", FastString::from_static(script))?;
+    Ok(())
+}
+
+fn export_value<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    namespace: v8::Local<v8::Object>,
+    export: &str,
+) -> Result<Option<v8::Local<'s, v8::Value>>, Error> {
+    let key = v8::String::new(scope, export).ok_or(anyhow!("could not setup main function key"))?;
+    Ok(namespace
+        .get(scope, key.into())
+        .filter(|value| !value.is_undefined()))
+}
+
+/// The function to call for an exported value and its receiver: the value
+/// itself, or the `handler` method of an object or of a new instance of a
+/// class. `None` when there is nothing to call, or constructing the class
+/// threw.
+fn callable<'s>(
+    scope: &mut v8::HandleScope<'s>,
+    value: v8::Local<'s, v8::Value>,
+) -> Option<(v8::Local<'s, v8::Function>, v8::Local<'s, v8::Value>)> {
+    let target: v8::Local<v8::Value> = match v8::Local::<v8::Function>::try_from(value) {
+        Ok(class) if is_class(scope, class) => class.new_instance(scope, &[])?.into(),
+        Ok(func) => return Some((func, v8::Integer::new(scope, 1).into())),
+        Err(_) if value.is_object() => value,
+        Err(_) => return None,
+    };
+    let object = target.to_object(scope)?;
+    let key = v8::String::new(scope, "handler")?;
+    let handler = object.get(scope, key.into())?;
+    let handler = v8::Local::<v8::Function>::try_from(handler).ok()?;
+    Some((handler, object.into()))
+}
+
+fn is_class(scope: &mut v8::HandleScope, func: v8::Local<v8::Function>) -> bool {
+    func.to_string(scope)
+        .is_some_and(|source| source.to_rust_string_lossy(scope).starts_with("class"))
+}
+
+fn missing_entrypoint(
+    scope: &mut v8::HandleScope,
+    namespace: v8::Local<v8::Object>,
+    export: &str,
+) -> RuntimeError {
+    RuntimeError::MissingEntrypoint {
+        export: export.to_string(),
+        available: export_names(scope, namespace),
+    }
+}
+
+/// Names the module namespace exports. Reading them doesn't touch the
+/// bindings, so it works before the module is evaluated.
+pub(crate) fn export_names(
+    scope: &mut v8::HandleScope,
+    namespace: v8::Local<v8::Object>,
+) -> Vec<String> {
+    let mut names = vec![];
+    if let Some(keys) = namespace.get_own_property_names(scope, Default::default()) {
+        for index in 0..keys.length() {
+            if let Some(name) = keys.get_index(scope, index) {
+                names.push(name.to_rust_string_lossy(scope));
+            }
+        }
+    }
+    names
+}
+
+/// [`call`] limited to `limit` instead of `RunOptions::timeout`.
+pub(crate) async fn call_with_timeout(
+    module: &mut LoadedModule,
+    inputs: Inputs,
+    options: &RunOptions,
+    limit: Option<Duration>,
+) -> Result<v8::Global<v8::Value>, Error> {
+    let isolate = module.worker.js_runtime.v8_isolate().thread_safe_handle();
+    let heap = module.heap.clone();
+    let call = stats::phase("call", invoke(module, inputs, options));
+    let result = within(isolate, limit, options, call).await;
+    sample_heap(&mut module.worker, options);
+    heap.check(result, options)
+}
+
+fn sample_heap(worker: &mut MainWorker, options: &RunOptions) {
+    if let Some(stats) = &options.stats {
+        let mut heap = v8::HeapStatistics::default();
+        worker
+            .js_runtime
+            .v8_isolate()
+            .get_heap_statistics(&mut heap);
+        stats.record(|stats| {
+            stats.used_heap_size = heap.used_heap_size();
+            stats.total_heap_size = heap.total_heap_size();
+            stats.peak_heap_size = stats.peak_heap_size.max(heap.used_heap_size());
+        });
+    }
+}
+
+/// Fails `work` with `RuntimeError::Timeout` once `limit` passes, with
+/// `RuntimeError::BudgetExceeded` once it burnt `RunOptions::fuel`, or
+/// with `RuntimeError::Cancelled` once `RunOptions::cancellation` is
+/// cancelled. Synchronous JS is stopped by terminating execution, which
+/// leaves the worker unusable.
+pub(crate) async fn within<T>(
+    isolate: v8::IsolateHandle,
+    limit: Option<Duration>,
+    options: &RunOptions,
+    work: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let cancellation = options.cancellation.as_ref();
+    // Detaches once `work` settled, whichever way.
+    let _attached = cancellation.map(|cancellation| cancellation.attach(isolate.clone()));
+    if cancellation.is_some_and(|cancellation| cancellation.is_cancelled()) {
+        return Err(RuntimeError::Cancelled.into());
+    }
+    let work = async {
+        let result = match cancellation {
+            Some(cancellation) => tokio::select! {
+                result = work => result,
+                _ = cancellation.cancelled() => Err(RuntimeError::Cancelled.into()),
+            },
+            None => work.await,
+        };
+        match result {
+            Err(_) if cancellation.is_some_and(|c| c.is_cancelled()) => {
+                Err(RuntimeError::Cancelled.into())
+            }
+            result => result,
+        }
+    };
+
+    let meter = options
+        .fuel
+        .map(|fuel| (fuel, FuelMeter::start(isolate.clone(), fuel)));
+    let result = match limit {
+        Some(limit) => {
+            let (done, finished) = mpsc::channel::<()>();
+            let watchdog = std::thread::spawn(move || {
+                let timed_out = finished.recv_timeout(limit) == Err(RecvTimeoutError::Timeout);
+                if timed_out {
+                    isolate.terminate_execution();
+                }
+                timed_out
+            });
+            let result = tokio::time::timeout(limit, work).await;
+            drop(done);
+            let terminated = watchdog.join().unwrap_or(false);
+            match result {
+                Ok(result) if !terminated => result,
+                _ => Err(RuntimeError::Timeout { limit }.into()),
+            }
+        }
+        None => work.await,
+    };
+    match meter {
+        Some((fuel, meter)) => match meter.stop() && result.is_err() {
+            true => Err(RuntimeError::BudgetExceeded { fuel }.into()),
+            false => result,
+        },
+        None => result,
+    }
+}
+
+/// Surfaces errors from work the function left running since its last
+/// call, such as a throwing timer, without waiting for pending work.
+pub(crate) async fn take_uncaught(module: &mut LoadedModule) -> Result<(), Error> {
+    tokio::select! {
+        biased;
+        result = module.worker.run_event_loop(false) => result,
+        _ = std::future::ready(()) => Ok(()),
+    }
+}
+
+/// Replaces the worker with a freshly loaded one.
+pub(crate) async fn reload(module: &mut LoadedModule, options: &RunOptions) -> Result<(), Error> {
+    let function = module.function.clone();
+    *module = load(&function, options).await?;
+    Ok(())
+}
+
+/// Whether a failed call may have left the worker unusable for further
+/// calls. Exceptions thrown by the function and result conversion errors
+/// don't, timeouts and anything coming from the event loop or V8 itself
+/// might.
+pub(crate) fn corrupts_worker(error: &Error) -> bool {
+    match error.downcast_ref::<RuntimeError>() {
+        Some(error) => matches!(
+            error,
+            RuntimeError::Timeout { .. }
+                | RuntimeError::BudgetExceeded { .. }
+                | RuntimeError::HeapLimitExceeded { .. }
+                | RuntimeError::Cancelled
+        ),
+        None => error.downcast_ref::<JsError>().is_none(),
+    }
+}
+
+fn record(options: &RunOptions, record: impl FnOnce(&mut ExecutionStats)) {
+    if let Some(stats) = &options.stats {
+        stats.record(record);
+    }
+}
+
+fn error_class(error: &Error) -> &'static str {
+    deno_runtime::errors::get_error_class_name(error).unwrap_or("Error")
+}
+
+pub(crate) async fn execute(
+    function: &Path,
+    inputs: Inputs,
+    options: &RunOptions,
+) -> Result<(LoadedModule, v8::Global<v8::Value>), Error> {
+    let mut module = load(function, options).await?;
+    let f = call(&mut module, inputs, options).await?;
+    Ok((module, f))
+}
@@ -0,0 +1,111 @@
+#![cfg(feature = "typescript")]
+
+mod common;
+
+use experimental_runtime::{
+    open_archive, run_with_options, Inputs, RunOptions, RuntimePermissions,
+};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+
+const MAIN: &str = r#"
+import { greet } from "./lib/greet.ts";
+import config from "../config.json" with { type: "json" };
+
+export function main({ name }: { name: string }): string {
+  return greet(name, config.greeting);
+}
+"#;
+
+const GREET: &str =
+    "export const greet = (name: string, greeting: string): string => `${greeting}, ${name}`;";
+
+/// A ustar archive of `entries`.
+fn tar(entries: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = vec![];
+    for (name, body) in entries {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[108..116].copy_from_slice(b"0000000\0");
+        header[116..124].copy_from_slice(b"0000000\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", body.len()).as_bytes());
+        header[136..148].copy_from_slice(b"00000000000\0");
+        header[156] = b'0';
+        header[257..265].copy_from_slice(b"ustar\x0000");
+        header[148..156].fill(b' ');
+        let checksum = header.iter().map(|b| *b as u64).sum::<u64>();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(body.as_bytes());
+        out.resize(out.len().div_ceil(512) * 512, 0);
+    }
+    out.resize(out.len() + 1024, 0);
+    out
+}
+
+fn function() -> Vec<u8> {
+    tar(&[
+        ("src/main.ts", MAIN),
+        ("src/lib/greet.ts", GREET),
+        ("config.json", r#"{ "greeting": "hello" }"#),
+    ])
+}
+
+fn run(archive: &Path, entry: &str) -> Result<serde_json::Value, anyhow::Error> {
+    let options = RunOptions {
+        embedded: Some(open_archive(archive)?),
+        permissions: Some(RuntimePermissions::none()),
+        ..Default::default()
+    };
+    let inputs = Inputs::new().text("name", "archive");
+    run_with_options(PathBuf::from(entry), inputs, options)
+}
+
+#[test]
+fn module_graphs_run_from_tarballs() {
+    let fixture = common::Fixture::new();
+    let archive = fixture.file("function.tar", function());
+    assert_eq!(
+        run(&archive, "src/main.ts").unwrap(),
+        json!("hello, archive")
+    );
+
+    let error = run(&archive, "src/missing.ts").unwrap_err();
+    assert!(format!("{:#}", error).contains("missing.ts"), "{:#}", error);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn module_graphs_run_from_gzipped_tarballs() {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(&function()).unwrap();
+    let fixture = common::Fixture::new();
+    let archive = fixture.file("function.tar.gz", encoder.finish().unwrap());
+    assert_eq!(
+        run(&archive, "src/main.ts").unwrap(),
+        json!("hello, archive")
+    );
+}
+
+#[test]
+fn escaping_and_corrupted_archives_are_rejected_when_opened() {
+    let fixture = common::Fixture::new();
+    let archive = fixture.file("escape.tar", tar(&[("src/../../etc/passwd", "root")]));
+    let error = open_archive(&archive).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("escapes the archive root"),
+        "{:#}",
+        error
+    );
+
+    let mut corrupted = function();
+    corrupted[0] ^= 0xff;
+    let archive = fixture.file("corrupted.tar", corrupted);
+    let error = open_archive(&archive).unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("invalid module archive"), "{}", message);
+    assert!(message.contains("bad checksum"), "{}", message);
+}
@@ -0,0 +1,91 @@
+#![cfg(feature = "typescript")]
+
+mod common;
+
+use experimental_runtime::{bundle, run_with_options, Inputs, RunOptions, RuntimePermissions};
+use std::path::PathBuf;
+
+/// Options running `bundle` with nothing but its own modules.
+fn offline(modules: experimental_runtime::EmbeddedModules) -> RunOptions {
+    RunOptions {
+        embedded: Some(modules),
+        permissions: Some(RuntimePermissions::none()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn bundles_run_from_their_own_modules() {
+    let fixture = common::Fixture::new();
+    fixture.file(
+        "lib/greet.ts",
+        "export const greet = (name: string): string => `hello, ${name}`;",
+    );
+    fixture.file("lazy.js", "export const lazy = 'lazy';");
+    let main = fixture.file(
+        "main.ts",
+        r#"
+import { greet } from "./lib/greet.ts";
+export async function main({ name }: { name: string }) {
+  const { lazy } = await import("./lazy.js");
+  return `${greet(name)} ${lazy}`;
+}
+"#,
+    );
+
+    let bundle = bundle(&main, &RunOptions::default()).unwrap();
+    assert_eq!(bundle.entry, "main.ts.js");
+    let paths: Vec<_> = bundle.modules.keys().map(String::as_str).collect();
+    assert_eq!(paths, ["lazy.js", "lib/greet.ts.js", "main.ts.js"]);
+    assert!(!bundle.modules["lib/greet.ts.js"].contains(": string"));
+
+    // The sources are gone, the bundle still runs.
+    drop(fixture);
+    let inputs = Inputs::new().text("name", "bundle");
+    let value = run_with_options(
+        PathBuf::from(&bundle.entry),
+        inputs,
+        offline(bundle.to_embedded()),
+    )
+    .unwrap();
+    assert_eq!(value, "hello, bundle lazy");
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn remote_modules_are_vendored() {
+    let server = common::Server::start();
+    server.route(
+        "/dep.js",
+        common::Response::ok("application/javascript", "export const dep = 'remote';"),
+    );
+    let fixture = common::Fixture::new();
+    let main = fixture.file(
+        "main.js",
+        format!(
+            "import {{ dep }} from \"{}\";\nexport const main = () => dep;",
+            server.url("/dep.js")
+        ),
+    );
+
+    let bundle = bundle(&main, &RunOptions::default()).unwrap();
+    let vendored = bundle
+        .modules
+        .keys()
+        .find(|path| path.starts_with("vendor/http/127.0.0.1_"))
+        .unwrap_or_else(|| panic!("{:?}", bundle.modules.keys()));
+    assert!(vendored.ends_with("/dep.js"), "{}", vendored);
+    assert!(bundle.modules["main.js"].contains(&format!("\"/{}\"", vendored)));
+
+    let archive = fixture.path().join("bundle.tar");
+    bundle.write(&archive).unwrap();
+    let modules = experimental_runtime::open_archive(&archive).unwrap();
+    let value = run_with_options(
+        PathBuf::from(&bundle.entry),
+        Inputs::new(),
+        offline(modules),
+    )
+    .unwrap();
+    assert_eq!(value, "remote");
+    assert_eq!(server.hits("/dep.js"), 1);
+}
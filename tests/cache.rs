@@ -0,0 +1,232 @@
+#![cfg(feature = "net-loader")]
+
+mod common;
+
+use experimental_runtime::{
+    run_with_options, CachePolicy, Inputs, ModuleCache, RunOptions, StatsCollector, TranspileCache,
+};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const LIB: &str = "/lib.js";
+
+fn serve(version: u32) -> common::Response {
+    common::Response::ok(
+        "application/javascript",
+        format!("export const version = {};", version),
+    )
+    .header("ETag", &format!("\"v{}\"", version))
+}
+
+/// An entry module importing `lib.js` from `server`.
+fn entry(fixture: &common::Fixture, server: &common::Server) -> PathBuf {
+    fixture.file(
+        "main.js",
+        format!(
+            "import {{ version }} from {:?};\nexport const main = () => version;",
+            server.url(LIB)
+        ),
+    )
+}
+
+fn run(
+    function: &Path,
+    cache: &common::Fixture,
+    policy: CachePolicy,
+) -> anyhow::Result<serde_json::Value> {
+    let options = RunOptions {
+        module_cache: Some(ModuleCache::in_dir(cache.path(), policy)),
+        ..Default::default()
+    };
+    run_with_options(function.to_path_buf(), Inputs::new(), options)
+}
+
+#[test]
+fn cached_modules_are_not_fetched_again() {
+    let server = common::Server::start();
+    server.route(LIB, serve(1));
+    let (fixture, cache) = (common::Fixture::new(), common::Fixture::new());
+    let function = entry(&fixture, &server);
+
+    assert_eq!(run(&function, &cache, CachePolicy::UseCache).unwrap(), 1);
+    assert_eq!(run(&function, &cache, CachePolicy::UseCache).unwrap(), 1);
+    assert_eq!(server.hits(LIB), 1);
+
+    // Offline serves what is cached without the network.
+    server.route(LIB, serve(2));
+    assert_eq!(run(&function, &cache, CachePolicy::Offline).unwrap(), 1);
+    assert_eq!(server.hits(LIB), 1);
+}
+
+#[test]
+fn offline_names_the_missing_module() {
+    let server = common::Server::start();
+    server.route(LIB, serve(1));
+    let (fixture, cache) = (common::Fixture::new(), common::Fixture::new());
+    let function = entry(&fixture, &server);
+
+    let error = run(&function, &cache, CachePolicy::Offline).unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains(&server.url(LIB)), "{}", message);
+    assert!(message.contains("is not cached"), "{}", message);
+    assert_eq!(server.hits(LIB), 0);
+}
+
+#[test]
+fn bypass_always_fetches_and_writes_nothing() {
+    let server = common::Server::start();
+    server.route(LIB, serve(1));
+    let (fixture, cache) = (common::Fixture::new(), common::Fixture::new());
+    let function = entry(&fixture, &server);
+
+    run(&function, &cache, CachePolicy::Bypass).unwrap();
+    run(&function, &cache, CachePolicy::Bypass).unwrap();
+    assert_eq!(server.hits(LIB), 2);
+    assert_eq!(std::fs::read_dir(cache.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn stale_modules_are_revalidated() {
+    let server = common::Server::start();
+    server.route(LIB, serve(1));
+    let (fixture, cache) = (common::Fixture::new(), common::Fixture::new());
+    let function = entry(&fixture, &server);
+    let stale = CachePolicy::RevalidateIfStale(Duration::ZERO);
+
+    assert_eq!(run(&function, &cache, stale).unwrap(), 1);
+
+    // Not modified keeps the cached copy.
+    server.route(LIB, common::Response::status(304));
+    assert_eq!(run(&function, &cache, stale).unwrap(), 1);
+    assert_eq!(server.hits(LIB), 2);
+
+    // A failing server falls back to the stale copy.
+    server.route(LIB, common::Response::status(500));
+    assert_eq!(run(&function, &cache, stale).unwrap(), 1);
+
+    server.route(LIB, serve(2));
+    assert_eq!(run(&function, &cache, stale).unwrap(), 2);
+    assert_eq!(run(&function, &cache, CachePolicy::Offline).unwrap(), 2);
+}
+
+#[test]
+fn fresh_modules_are_served_without_revalidating() {
+    let server = common::Server::start();
+    server.route(LIB, serve(1));
+    let (fixture, cache) = (common::Fixture::new(), common::Fixture::new());
+    let function = entry(&fixture, &server);
+    let policy = CachePolicy::RevalidateIfStale(Duration::from_secs(3600));
+
+    run(&function, &cache, policy).unwrap();
+    server.route(LIB, serve(2));
+    assert_eq!(run(&function, &cache, policy).unwrap(), 1);
+    assert_eq!(server.hits(LIB), 1);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn large_modules_round_trip_compressed() {
+    // A module well over the compression threshold.
+    let body: String = (0..2000)
+        .map(|n| format!("export const value{} = {};\n", n, n))
+        .collect();
+    let body = body + "export const version = 1;\n";
+    let server = common::Server::start();
+    server.route(
+        LIB,
+        common::Response::ok("application/javascript", body.clone()),
+    );
+    let (fixture, cache) = (common::Fixture::new(), common::Fixture::new());
+    let function = entry(&fixture, &server);
+
+    assert_eq!(run(&function, &cache, CachePolicy::UseCache).unwrap(), 1);
+    let usage = ModuleCache::in_dir(cache.path(), CachePolicy::UseCache)
+        .disk_usage()
+        .unwrap();
+    assert_eq!(usage.entries, 1);
+    assert_eq!(usage.logical_bytes, body.len() as u64);
+    assert!(usage.disk_bytes < usage.logical_bytes / 4, "{:?}", usage);
+
+    assert_eq!(run(&function, &cache, CachePolicy::Offline).unwrap(), 1);
+    assert_eq!(server.hits(LIB), 1);
+}
+
+#[test]
+fn corrupt_entries_are_fetched_again() {
+    let server = common::Server::start();
+    server.route(LIB, serve(1));
+    let (fixture, cache) = (common::Fixture::new(), common::Fixture::new());
+    let function = entry(&fixture, &server);
+    run(&function, &cache, CachePolicy::UseCache).unwrap();
+
+    // Marked compressed, but not a zstd frame.
+    for entry in std::fs::read_dir(cache.path()).unwrap() {
+        let path = entry.unwrap().path();
+        if let Some(name) = path.to_str().and_then(|p| p.strip_suffix(".metadata.json")) {
+            let metadata = std::fs::read_to_string(&path).unwrap();
+            let metadata = metadata.replacen('{', "{\"encoding\":\"zstd\",", 1);
+            std::fs::write(&path, metadata).unwrap();
+            std::fs::write(name, "not a frame").unwrap();
+        }
+    }
+    assert_eq!(run(&function, &cache, CachePolicy::UseCache).unwrap(), 1);
+    assert_eq!(server.hits(LIB), 2);
+}
+
+#[cfg(feature = "typescript")]
+#[test]
+fn repeated_runs_load_every_module_from_the_caches() {
+    let server = common::Server::start();
+    server.route(
+        LIB,
+        common::Response::ok(
+            "application/javascript",
+            "export { version } from \"./version.ts\";",
+        ),
+    );
+    server.route(
+        "/version.ts",
+        common::Response::ok(
+            "application/typescript",
+            "export const version: number = 3;",
+        ),
+    );
+    let (fixture, cache) = (common::Fixture::new(), common::Fixture::new());
+    let function = entry(&fixture, &server);
+    let modules = ModuleCache::in_dir(cache.path().join("deps"), CachePolicy::UseCache);
+    let compiled = TranspileCache::in_dir(cache.path().join("gen"));
+    let run = || {
+        let stats = StatsCollector::new();
+        let options = RunOptions {
+            module_cache: Some(modules.clone()),
+            transpile_cache: Some(compiled.clone()),
+            stats: Some(stats.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            run_with_options(function.clone(), Inputs::new(), options).unwrap(),
+            3
+        );
+        stats.stats()
+    };
+
+    let first = run();
+    assert_eq!(first.module_cache.unwrap().misses, 2);
+    assert_eq!(first.transpile_cache.unwrap().misses, 1);
+    let stats = modules.stats();
+    assert_eq!((stats.misses, stats.insertions, stats.entries), (2, 2, 2));
+    assert!(stats.bytes > 0);
+    assert_eq!(compiled.stats().insertions, 1);
+
+    modules.reset_stats();
+    compiled.reset_stats();
+    let second = run();
+    let lookups = second.module_cache.unwrap();
+    assert_eq!((lookups.hits, lookups.misses), (2, 0));
+    let lookups = second.transpile_cache.unwrap();
+    assert_eq!((lookups.hits, lookups.misses), (1, 0));
+    let stats = modules.stats();
+    assert_eq!((stats.hits, stats.misses, stats.insertions), (2, 0, 0));
+    assert_eq!((compiled.stats().hits, compiled.stats().misses), (1, 0));
+    assert_eq!(server.hits(LIB), 1);
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use experimental_runtime::{
+    run_with_options, CancellationHandle, ExecutorPool, Inputs, RunOptions, RuntimeError,
+};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+const SPIN: &str = "export function main() { while (true) {} }";
+const PENDING: &str = "export function main() { return new Promise(() => {}); }";
+
+fn assert_cancelled(result: Result<serde_json::Value, anyhow::Error>) {
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::Cancelled)
+        ),
+        "{:#}",
+        error
+    );
+}
+
+/// Cancels `handle` after `delay` from another thread, returning when it
+/// did.
+fn cancel_later(handle: &CancellationHandle, delay: Duration) -> std::thread::JoinHandle<Instant> {
+    let handle = handle.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(delay);
+        handle.cancel();
+        Instant::now()
+    })
+}
+
+fn run(
+    source: &str,
+    cancellation: &CancellationHandle,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let (_fixture, function) = common::module("main.js", source);
+    let options = RunOptions {
+        cancellation: Some(cancellation.clone()),
+        ..Default::default()
+    };
+    run_with_options(function, Inputs::new(), options)
+}
+
+#[test]
+fn busy_scripts_stop_soon_after_cancel() {
+    for source in [SPIN, PENDING] {
+        let handle = CancellationHandle::new();
+        let canceller = cancel_later(&handle, Duration::from_millis(300));
+        let result = run(source, &handle);
+        let stopped = Instant::now();
+        assert_cancelled(result);
+        let cancelled_at = canceller.join().unwrap();
+        assert!(
+            stopped.duration_since(cancelled_at) < Duration::from_millis(100),
+            "took {:?} to stop",
+            stopped.duration_since(cancelled_at)
+        );
+    }
+}
+
+#[test]
+fn cancelling_before_the_run_short_circuits() {
+    let handle = CancellationHandle::new();
+    handle.cancel();
+    handle.cancel();
+    assert!(handle.is_cancelled());
+    assert_cancelled(run("export function main() { return 1; }", &handle));
+}
+
+#[test]
+fn cancelling_after_the_run_does_nothing() {
+    let handle = CancellationHandle::new();
+    let value = run("export function main() { return 1; }", &handle).unwrap();
+    assert_eq!(value, 1);
+    handle.cancel();
+    handle.cancel();
+}
+
+#[test]
+fn cancelled_pool_jobs_do_not_poison_the_worker() {
+    let fixture = common::Fixture::new();
+    let spin = fixture.file("spin.js", SPIN);
+    let ok = fixture.file(
+        "ok.js",
+        "let calls = 0;\nexport const main = () => ++calls;",
+    );
+    for pool in [
+        ExecutorPool::new(1).unwrap(),
+        ExecutorPool::warm(1, RunOptions::default(), 100).unwrap(),
+    ] {
+        let handle = CancellationHandle::new();
+        let job = pool.submit_cancellable(spin.clone(), Inputs::new(), handle.clone());
+        cancel_later(&handle, Duration::from_millis(200));
+        assert_cancelled(futures::executor::block_on(job));
+
+        // The same single thread takes the next jobs.
+        assert_eq!(pool.execute(ok.clone(), Inputs::new()).unwrap(), 1);
+        assert!(
+            pool.execute(ok.clone(), Inputs::new())
+                .unwrap()
+                .as_u64()
+                .unwrap()
+                >= 1
+        );
+    }
+}
+
+#[test]
+fn warm_workers_stop_on_cancel_and_ignore_late_ones() {
+    let (_fixture, function) = common::module(
+        "main.js",
+        "let calls = 0;\nexport function main({ spin }) { calls++; while (spin) {} return calls; }",
+    );
+    let pool = ExecutorPool::warm(1, RunOptions::default(), 100).unwrap();
+    let job = |spin: bool, handle: &CancellationHandle| {
+        let inputs = Inputs::new().json("spin", json!(spin));
+        futures::executor::block_on(pool.submit_cancellable(
+            function.clone(),
+            inputs,
+            handle.clone(),
+        ))
+    };
+    assert_eq!(job(false, &CancellationHandle::new()).unwrap(), 1);
+
+    let handle = CancellationHandle::new();
+    let canceller = cancel_later(&handle, Duration::from_millis(200));
+    let result = job(true, &handle);
+    let stopped = Instant::now();
+    assert_cancelled(result);
+    assert!(stopped.duration_since(canceller.join().unwrap()) < Duration::from_millis(100));
+
+    // The cancelled worker was replaced, and a handle cancelled after its
+    // job settled leaves the warm worker alone.
+    let late = CancellationHandle::new();
+    assert_eq!(job(false, &late).unwrap(), 1);
+    late.cancel();
+    assert_eq!(job(false, &CancellationHandle::new()).unwrap(), 2);
+}
+
+#[test]
+fn queued_pool_jobs_can_be_cancelled() {
+    let fixture = common::Fixture::new();
+    let slow = fixture.file(
+        "slow.js",
+        "export async function main() { await new Promise((r) => setTimeout(r, 300)); return 1; }",
+    );
+    let pool = ExecutorPool::new(1).unwrap();
+    let running = pool.submit(slow.clone(), Inputs::new());
+    let handle = CancellationHandle::new();
+    let queued = pool.submit_cancellable(slow, Inputs::new(), handle.clone());
+    handle.cancel();
+    assert_cancelled(futures::executor::block_on(queued));
+    assert_eq!(futures::executor::block_on(running).unwrap(), 1);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use experimental_runtime::{check, Entrypoint, ProblemKind, RunOptions};
+
+#[test]
+fn checks_link_the_graph_without_evaluating_it() {
+    let fixture = common::Fixture::new();
+    fixture.file(
+        "lib.js",
+        "throw new Error(\"evaluated\");\nexport const x = 1;",
+    );
+    let main = fixture.file(
+        "main.js",
+        "import { x } from \"./lib.js\";\nexport function main() { return x; }",
+    );
+
+    let report = check(&main, &RunOptions::default()).unwrap();
+    assert!(report.is_ok(), "{:?}", report.problems);
+    let names: Vec<_> = report
+        .modules
+        .iter()
+        .map(|specifier| specifier.path().rsplit('/').next().unwrap())
+        .collect();
+    assert_eq!(names, ["lib.js", "main.js"]);
+    assert_eq!(report.export.as_deref(), Some("main"));
+    assert_eq!(report.callable, Some(true));
+}
+
+#[test]
+fn every_problem_in_the_graph_is_reported() {
+    let fixture = common::Fixture::new();
+    let main = fixture.file(
+        "main.js",
+        "import \"./missing.js\";\nimport \"not-mapped\";\nexport function main() {}",
+    );
+
+    let report = check(&main, &RunOptions::default()).unwrap();
+    assert!(!report.is_ok());
+    let kinds: Vec<_> = report.problems.iter().map(|p| p.kind).collect();
+    assert_eq!(kinds.len(), 2, "{:?}", report.problems);
+    assert!(kinds.contains(&ProblemKind::Resolution));
+    assert!(kinds.contains(&ProblemKind::Load));
+    let load = report
+        .problems
+        .iter()
+        .find(|p| p.kind == ProblemKind::Load)
+        .unwrap();
+    assert!(load.message.contains("missing.js"), "{}", load.message);
+    // Nothing is linked against the stand-ins.
+    assert_eq!(report.export, None);
+}
+
+#[test]
+fn missing_entrypoints_are_problems() {
+    let (_fixture, main) = common::module(
+        "main.js",
+        "export function other() {}\nexport const limit = 3;",
+    );
+
+    let report = check(&main, &RunOptions::default()).unwrap();
+    let [problem] = &report.problems[..] else {
+        panic!("{:?}", report.problems);
+    };
+    assert_eq!(problem.kind, ProblemKind::Entrypoint);
+    assert!(problem.specifier.is_some());
+
+    let options = RunOptions {
+        entrypoint: Entrypoint::Handler("limit".into()),
+        ..Default::default()
+    };
+    let report = check(&main, &options).unwrap();
+    assert_eq!(report.export.as_deref(), Some("limit"));
+    // A const isn't initialized before the module is evaluated.
+    assert_eq!(report.callable, None);
+    assert!(report.is_ok(), "{:?}", report.problems);
+}
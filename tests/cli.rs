@@ -0,0 +1,292 @@
+mod common;
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+use predicates::str::contains;
+
+const ECHO: &str = r#"
+export function main(inputs) {
+  console.log("logged, not printed to stdout");
+  return inputs;
+}
+export function keys(inputs) {
+  return Object.keys(inputs).sort();
+}
+"#;
+
+fn cli() -> Command {
+    Command::cargo_bin("experimental_runtime").unwrap()
+}
+
+#[test]
+fn prints_the_result_as_json() {
+    let (_fixture, function) = common::module("echo.js", ECHO);
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--input", "count=3", "--input", r#"name="ada""#])
+        .assert()
+        .success()
+        .stdout(r#"{"count":3,"name":"ada"}"#.to_string() + "\n")
+        .stderr(contains("logged, not printed to stdout"));
+}
+
+#[test]
+fn reads_inputs_from_a_file_or_stdin() {
+    let fixture = common::Fixture::new();
+    let function = fixture.file("echo.js", ECHO);
+    let inputs = fixture.file("inputs.json", r#"{ "a": 1, "b": 2 }"#);
+
+    // --input wins over the file.
+    cli()
+        .arg("run")
+        .arg(&function)
+        .arg("--input-file")
+        .arg(&inputs)
+        .args(["--input", "b=20"])
+        .assert()
+        .success()
+        .stdout("{\"a\":1,\"b\":20}\n");
+
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--input-file", "-", "--export", "keys"])
+        .write_stdin(r#"{ "z": null, "y": [] }"#)
+        .assert()
+        .success()
+        .stdout("[\"y\",\"z\"]\n");
+}
+
+#[test]
+fn pretty_output() {
+    let (_fixture, function) = common::module("echo.js", ECHO);
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--input", "a=1", "--output", "pretty"])
+        .assert()
+        .success()
+        .stdout("{\n  \"a\": 1\n}\n");
+}
+
+#[test]
+fn batch_prints_a_json_line_per_input() {
+    let (_fixture, function) = common::module("echo.js", ECHO);
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--batch", "-", "--input", "shared=true"])
+        .write_stdin("{ \"n\": 1 }\n\n{ \"n\": [2, 3] }\n{ \"n\": \"three\" }\n")
+        .assert()
+        .success()
+        .stdout(
+            "{\"n\":1,\"shared\":true}\n\
+             {\"n\":[2,3],\"shared\":true}\n\
+             {\"n\":\"three\",\"shared\":true}\n",
+        );
+}
+
+#[test]
+fn batch_reports_failing_lines_and_goes_on() {
+    let (_fixture, function) = common::module(
+        "checked.js",
+        "export function main({ n }) { if (n < 0) throw new Error(\"negative\"); return n; }",
+    );
+    let output = cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--batch", "-"])
+        .write_stdin("{ \"n\": 1 }\nnot json\n{ \"n\": -1 }\n{ \"n\": 2 }\n")
+        .assert()
+        // The exit code of the last failure.
+        .code(1)
+        .stderr(contains("line 2"))
+        .get_output()
+        .stdout
+        .clone();
+    let lines: Vec<serde_json::Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], 1);
+    assert!(lines[1]["error"]
+        .as_str()
+        .unwrap()
+        .contains("not valid json"));
+    assert!(lines[2]["error"].as_str().unwrap().contains("negative"));
+    assert_eq!(lines[3], 2);
+}
+
+#[test]
+fn script_errors_exit_with_1() {
+    let (_fixture, function) = common::module(
+        "throws.js",
+        "export function main() { throw new Error(\"it broke\"); }",
+    );
+    cli()
+        .arg("run")
+        .arg(&function)
+        .assert()
+        .code(1)
+        .stdout(predicate::str::is_empty())
+        .stderr(contains("error:").and(contains("it broke")));
+}
+
+#[test]
+fn network_access_needs_allow_net() {
+    let server = common::Server::start();
+    server.route("/", common::Response::ok("text/plain", "reached"));
+    let (_fixture, function) = common::module(
+        "fetch.js",
+        "export async function main({ url }) { return await (await fetch(url)).text(); }",
+    );
+    let input = format!("url={:?}", server.url("/"));
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--input", &input])
+        .assert()
+        .code(1)
+        .stderr(contains("net access"));
+
+    let host = server.url("").trim_start_matches("http://").to_string();
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--input", &input, "--allow-net", &host])
+        .assert()
+        .success()
+        .stdout("\"reached\"\n");
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn cache_fills_the_cache_dir_for_offline_runs() {
+    let server = common::Server::start();
+    server.route(
+        "/lib.js",
+        common::Response::ok("application/javascript", "export const answer = 42;"),
+    );
+    let (fixture, cache) = (common::Fixture::new(), common::Fixture::new());
+    let function = fixture.file(
+        "main.js",
+        format!(
+            "import {{ answer }} from {:?};\nexport const main = () => answer;",
+            server.url("/lib.js")
+        ),
+    );
+
+    cli()
+        .arg("cache")
+        .arg(&function)
+        .arg("--cache-dir")
+        .arg(cache.path())
+        .assert()
+        .success();
+    assert_eq!(server.hits("/lib.js"), 1);
+
+    cli()
+        .arg("run")
+        .arg(&function)
+        .arg("--cache-dir")
+        .arg(cache.path())
+        .arg("--offline")
+        .assert()
+        .success()
+        .stdout("42\n");
+    assert_eq!(server.hits("/lib.js"), 1);
+
+    // Offline, modules missing from the cache fail to load.
+    let empty = common::Fixture::new();
+    cli()
+        .arg("run")
+        .arg(&function)
+        .arg("--cache-dir")
+        .arg(empty.path())
+        .arg("--offline")
+        .assert()
+        .code(3)
+        .stderr(contains("is not cached"));
+}
+
+#[test]
+fn missing_modules_exit_with_3() {
+    let fixture = common::Fixture::new();
+    cli()
+        .arg("run")
+        .arg(fixture.path().join("missing.js"))
+        .assert()
+        .code(3)
+        .stderr(contains("missing.js"));
+}
+
+#[test]
+fn timeouts_exit_with_4() {
+    let (_fixture, function) = common::module("spin.js", "export function main() { for (;;) {} }");
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--timeout", "200ms"])
+        .assert()
+        .code(4);
+}
+
+#[test]
+fn usage_errors_exit_with_2() {
+    let (_fixture, function) = common::module("echo.js", ECHO);
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--input", "no-equals-sign"])
+        .assert()
+        .code(2)
+        .stderr(contains("is not key=json"));
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--input", "a={not json"])
+        .assert()
+        .code(2)
+        .stderr(contains("input a is not valid json"));
+    cli()
+        .arg("run")
+        .arg(&function)
+        .args(["--timeout", "soon"])
+        .assert()
+        .code(2)
+        .stderr(contains("invalid duration"));
+    cli().assert().code(2);
+}
+
+#[cfg(feature = "typescript")]
+#[test]
+fn no_transpile_cache_leaves_the_cache_empty() {
+    let fixture = common::Fixture::new();
+    let function = fixture.file("typed.ts", "export const main = (): number => 1;");
+    let cache = fixture.path().join("xdg");
+    let entries = || match std::fs::read_dir(cache.join("experimental_runtime/gen")) {
+        Ok(entries) => entries.count(),
+        Err(_) => 0,
+    };
+
+    cli()
+        .env("XDG_CACHE_HOME", &cache)
+        .args(["run", "--no-transpile-cache"])
+        .arg(&function)
+        .assert()
+        .success()
+        .stdout("1\n");
+    assert_eq!(entries(), 0);
+
+    cli()
+        .env("XDG_CACHE_HOME", &cache)
+        .arg("run")
+        .arg(&function)
+        .assert()
+        .success()
+        .stdout("1\n");
+    assert!(entries() > 0);
+}
@@ -0,0 +1,213 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tempfile::TempDir;
+
+/// Function modules written to a temporary directory, removed on drop.
+pub struct Fixture {
+    dir: TempDir,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        Self {
+            dir: tempfile::tempdir().unwrap(),
+        }
+    }
+
+    /// Writes `source` to `name`, creating parent directories, and returns
+    /// its path.
+    pub fn file(&self, name: &str, source: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.dir.path().join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, source).unwrap();
+        path
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.path().to_path_buf()
+    }
+}
+
+/// A single function module in a fixture of its own.
+pub fn module(name: &str, source: &str) -> (Fixture, PathBuf) {
+    let fixture = Fixture::new();
+    let path = fixture.file(name, source);
+    (fixture, path)
+}
+
+/// A response of [`Server`].
+#[derive(Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: vec![],
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+type Handler = Arc<dyn Fn(&Headers, &[u8]) -> Response + Send + Sync>;
+type Routes = Arc<Mutex<HashMap<String, Handler>>>;
+
+/// A minimal HTTP/1.1 server on a loopback port, answering requests from a
+/// fixed set of routes and 404 otherwise. Requests are counted by path.
+pub struct Server {
+    address: SocketAddr,
+    routes: Routes,
+    hits: Arc<Mutex<HashMap<String, usize>>>,
+    headers: Arc<Mutex<HashMap<String, Headers>>>,
+}
+
+type Headers = Vec<(String, String)>;
+
+impl Server {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let routes = Routes::default();
+        let hits = Arc::new(Mutex::new(HashMap::new()));
+        let headers = Arc::new(Mutex::new(HashMap::new()));
+        let server = Self {
+            address,
+            routes: routes.clone(),
+            hits: hits.clone(),
+            headers: headers.clone(),
+        };
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let routes = routes.clone();
+                let hits = hits.clone();
+                let headers = headers.clone();
+                std::thread::spawn(move || serve(stream, &routes, &hits, &headers));
+            }
+        });
+        server
+    }
+
+    pub fn route(&self, path: &str, response: Response) -> &Self {
+        self.handle(path, move |_, _| response.clone())
+    }
+
+    /// Answers requests for `path` with what `handler` makes of their
+    /// headers, names lowercased, and body.
+    pub fn handle(
+        &self,
+        path: &str,
+        handler: impl Fn(&Headers, &[u8]) -> Response + Send + Sync + 'static,
+    ) -> &Self {
+        self.routes
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), Arc::new(handler));
+        self
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.address, path)
+    }
+
+    pub fn hits(&self, path: &str) -> usize {
+        self.hits.lock().unwrap().get(path).copied().unwrap_or(0)
+    }
+
+    /// Headers of the latest request for `path`, names lowercased.
+    pub fn headers(&self, path: &str) -> Headers {
+        self.headers
+            .lock()
+            .unwrap()
+            .get(path)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+fn serve(
+    stream: TcpStream,
+    routes: &Routes,
+    hits: &Mutex<HashMap<String, usize>>,
+    headers: &Mutex<HashMap<String, Headers>>,
+) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+        let mut content_length = 0;
+        let mut request_headers = vec![];
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap_or(0);
+                }
+                request_headers.push((name.to_lowercase(), value.trim().to_string()));
+            }
+        }
+        let mut body = vec![0; content_length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+
+        let path = request_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or("/")
+            .to_string();
+        *hits.lock().unwrap().entry(path.clone()).or_default() += 1;
+        headers
+            .lock()
+            .unwrap()
+            .insert(path.clone(), request_headers.clone());
+        let handler = routes.lock().unwrap().get(&path).cloned();
+        let response = match handler {
+            Some(handler) => handler(&request_headers, &body),
+            None => Response::status(404),
+        };
+        let mut head = format!(
+            "HTTP/1.1 {} X\r\nContent-Length: {}\r\n",
+            response.status,
+            response.body.len()
+        );
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        let stream = reader.get_mut();
+        if stream.write_all(head.as_bytes()).is_err() || stream.write_all(&response.body).is_err() {
+            return;
+        }
+    }
+}
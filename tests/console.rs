@@ -0,0 +1,160 @@
+mod common;
+
+use experimental_runtime::{
+    run_captured, run_with_options, ConsoleEvent, ConsoleSink, Inputs, RunOptions, WarningCode,
+    WarningHook,
+};
+use serde_json::json;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+const LOGS: &str = r#"
+export function main() {
+  console.log("a", { b: 1 }, [2, 3]);
+  console.error("failed with %s", "code 7");
+  return "done";
+}
+"#;
+
+/// Marker logged by `child_run`, looked for in the child's stdout.
+const MARKER: &str = "console-marker-8d1f";
+
+#[test]
+fn info_and_error_records_are_captured() {
+    let (_fixture, function) = common::module("logs.js", LOGS);
+    let output = run_captured(function, Inputs::new(), RunOptions::default()).unwrap();
+    assert_eq!(output.value, "done");
+    assert_eq!(output.console.len(), 2);
+
+    let log = &output.console[0];
+    assert_eq!(log.level, "log");
+    assert!(!log.is_stderr());
+    assert_eq!(log.text, "a { b: 1 } [ 2, 3 ]");
+    assert_eq!(log.args, [json!("a"), json!({ "b": 1 }), json!([2, 3])]);
+    assert!(log.location.as_ref().unwrap().file.ends_with("logs.js"));
+
+    let error = &output.console[1];
+    assert_eq!(error.level, "error");
+    assert!(error.is_stderr());
+    assert_eq!(error.text, "failed with code 7");
+}
+
+#[test]
+fn callbacks_stream_events_as_they_happen() {
+    let (_fixture, function) = common::module(
+        "stream.js",
+        r#"
+console.log("evaluating");
+export function main() { console.warn("calling"); }
+"#,
+    );
+    let events = Arc::new(Mutex::new(Vec::<ConsoleEvent>::new()));
+    let sink = events.clone();
+    let options = RunOptions {
+        console: Some(ConsoleSink::callback(move |event| {
+            sink.lock().unwrap().push(event)
+        })),
+        ..Default::default()
+    };
+    run_with_options(function, Inputs::new(), options).unwrap();
+    let events = events.lock().unwrap();
+    let seen: Vec<_> = events
+        .iter()
+        .map(|event| (event.level.as_str(), event.text.as_str(), event.invocation))
+        .collect();
+    assert_eq!(seen, [("log", "evaluating", 0), ("warn", "calling", 1)]);
+}
+
+/// Runs in a child process started by `capture_keeps_stdout_clean`.
+#[test]
+fn child_run() {
+    let Ok(mode) = std::env::var("CONSOLE_TEST_CHILD") else {
+        return;
+    };
+    let (_fixture, function) = common::module(
+        "marker.js",
+        &format!("export function main() {{ console.log({:?}); }}", MARKER),
+    );
+    match mode.as_str() {
+        "captured" => {
+            run_captured(function, Inputs::new(), RunOptions::default()).unwrap();
+        }
+        _ => {
+            run_with_options(function, Inputs::new(), RunOptions::default()).unwrap();
+        }
+    }
+}
+
+fn child_stdout(mode: &str) -> String {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "child_run", "--nocapture", "--test-threads=1"])
+        .env("CONSOLE_TEST_CHILD", mode)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn capture_keeps_stdout_clean() {
+    assert!(child_stdout("printed").contains(MARKER));
+    assert!(!child_stdout("captured").contains(MARKER));
+}
+
+/// Logs an argument too large for an 8 byte event limit.
+const LARGE: &str = "export function main() { console.log(\"x\".repeat(64)); return \"done\"; }";
+
+fn limited(options: RunOptions) -> Result<serde_json::Value, anyhow::Error> {
+    let (_fixture, function) = common::module("large.js", LARGE);
+    let options = RunOptions {
+        console: Some(ConsoleSink::callback(|_| {})),
+        console_event_limit: Some(8),
+        ..options
+    };
+    run_with_options(function, Inputs::new(), options)
+}
+
+#[test]
+fn truncated_events_are_reported_as_warnings() {
+    let warnings = Arc::new(Mutex::new(vec![]));
+    let seen = warnings.clone();
+    let options = RunOptions {
+        on_warning: Some(WarningHook::new(move |warning| {
+            seen.lock().unwrap().push(warning)
+        })),
+        ..Default::default()
+    };
+    assert_eq!(limited(options).unwrap(), "done");
+
+    let warnings = warnings.lock().unwrap();
+    let [warning] = &warnings[..] else {
+        panic!("{:?}", warnings);
+    };
+    assert_eq!(warning.code(), WarningCode::ConsoleTruncated);
+    assert_eq!(
+        serde_json::to_value(warning).unwrap(),
+        json!({ "code": "console_truncated", "level": "log", "limit": 8 })
+    );
+}
+
+#[test]
+fn denied_warnings_fail_the_run() {
+    let options = RunOptions {
+        deny_warnings: vec![WarningCode::ConsoleTruncated],
+        ..Default::default()
+    };
+    let error = limited(options).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("console.log arguments were truncated to 8 bytes"),
+        "{:#}",
+        error
+    );
+
+    // Other codes stay warnings.
+    let options = RunOptions {
+        deny_warnings: vec![WarningCode::DanglingWork],
+        on_warning: Some(WarningHook::new(|_| {})),
+        ..Default::default()
+    };
+    assert_eq!(limited(options).unwrap(), "done");
+}
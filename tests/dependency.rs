@@ -0,0 +1,72 @@
+#![cfg(feature = "net-loader")]
+
+mod common;
+
+use deno_core::ModuleSpecifier;
+use experimental_runtime::{dependency_report, DependencyReport, NpmCache, RunOptions};
+
+/// Report for a function importing `npm:left-pad@1.3.0`, which is already
+/// unpacked in the npm cache, so only its packument is asked for.
+fn npm_report(server: &common::Server, header: &str) -> DependencyReport {
+    let fixture = common::Fixture::new();
+    fixture.file(
+        "npm/left-pad@1.3.0/package.json",
+        r#"{ "name": "left-pad", "version": "1.3.0", "main": "index.js" }"#,
+    );
+    fixture.file(
+        "npm/left-pad@1.3.0/index.js",
+        format!("{}\nexport default (s) => s;", header),
+    );
+    let entry = fixture.file(
+        "main.js",
+        "import pad from \"npm:left-pad@1.3.0\";\nexport const main = () => pad(\"x\");",
+    );
+    let registry = ModuleSpecifier::parse(&server.url("/")).unwrap();
+    let options = RunOptions {
+        npm_cache: Some(NpmCache::in_dir(fixture.path().join("npm")).with_registry(registry)),
+        ..Default::default()
+    };
+    dependency_report(&entry, &options).unwrap()
+}
+
+#[test]
+fn npm_licenses_come_from_the_packument() {
+    let server = common::Server::start();
+    server.route(
+        "/left-pad",
+        common::Response::ok(
+            "application/json",
+            r#"{ "license": "MIT", "versions": { "1.3.0": { "license": "WTFPL" } } }"#,
+        ),
+    );
+    let report = npm_report(&server, "// SPDX-License-Identifier: Apache-2.0");
+
+    let [module] = &report.modules[..] else {
+        panic!("{:?}", report.modules);
+    };
+    assert_eq!(module.specifier.as_str(), "npm:left-pad@1.3.0");
+    assert_eq!(module.version.as_deref(), Some("1.3.0"));
+    let license = module.license.as_ref().unwrap();
+    assert_eq!(license.id, "WTFPL");
+    assert!(!license.heuristic);
+    assert_eq!(server.hits("/left-pad"), 1);
+}
+
+#[test]
+fn licenses_fall_back_to_the_header_without_registry_metadata() {
+    // The registry has no packument for the package.
+    let server = common::Server::start();
+    let report = npm_report(&server, "/*! @license ISC */");
+
+    let license = report.modules[0].license.as_ref().unwrap();
+    assert_eq!(license.id, "ISC");
+    assert!(license.heuristic);
+
+    // Nor does a packument without a license replace it.
+    server.route(
+        "/left-pad",
+        common::Response::ok("application/json", r#"{ "versions": {} }"#),
+    );
+    let report = npm_report(&server, "/*! @license ISC */");
+    assert!(report.modules[0].license.as_ref().unwrap().heuristic);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use experimental_runtime::{
+    run_with_options, Determinism, FunctionRuntime, Inputs, RunOptions, VirtualClock,
+};
+use serde_json::{json, Value};
+use std::time::{Duration, UNIX_EPOCH};
+
+const SAMPLE: &str = r#"
+export function main() {
+  const random = [Math.random(), Math.random(), Math.random()];
+  const start = Date.now();
+  const spin = performance.now();
+  while (performance.now() - spin < 20 && Date.now() === start);
+  return { random, now: Date.now(), date: new Date().getTime(), elapsed: performance.now() };
+}
+"#;
+
+fn sample(determinism: Determinism) -> Value {
+    let (_fixture, function) = common::module("sample.js", SAMPLE);
+    let options = RunOptions {
+        determinism: Some(determinism),
+        ..Default::default()
+    };
+    run_with_options(function, Inputs::new(), options).unwrap()
+}
+
+#[test]
+fn seeded_runs_are_reproducible() {
+    let start = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+    let first = sample(Determinism::new(7).with_start_time(start));
+    assert_eq!(first, sample(Determinism::new(7).with_start_time(start)));
+    assert_ne!(first["random"], sample(Determinism::new(8))["random"]);
+
+    // The frozen clock doesn't move, however long the call spins.
+    assert_eq!(first["now"], 1_700_000_000_000u64);
+    assert_eq!(first["date"], first["now"]);
+    assert_eq!(first["elapsed"], 0);
+}
+
+#[test]
+fn warm_workers_restart_the_generator_and_clock() {
+    let (_fixture, function) = common::module("sample.js", SAMPLE);
+    let options = RunOptions {
+        determinism: Some(
+            Determinism::new(7).with_clock(VirtualClock::Quantized(Duration::from_secs(60))),
+        ),
+        ..Default::default()
+    };
+    let mut runtime = FunctionRuntime::new(function, options).unwrap();
+    let first = runtime.call("main", Inputs::new()).unwrap();
+    let second = runtime.call("main", Inputs::new()).unwrap();
+    assert_eq!(first, second);
+    assert_eq!(first["now"], 0);
+}
+
+#[test]
+fn unreproducible_apis_are_not_supported() {
+    let (_fixture, function) = common::module(
+        "entropy.js",
+        r#"
+export function main() {
+  try {
+    crypto.getRandomValues(new Uint8Array(4));
+    return "random bytes";
+  } catch (e) {
+    return e instanceof Deno.errors.NotSupported;
+  }
+}
+"#,
+    );
+    let options = RunOptions {
+        determinism: Some(Determinism::new(1)),
+        ..Default::default()
+    };
+    let value = run_with_options(function, Inputs::new(), options).unwrap();
+    assert_eq!(value, json!(true));
+}
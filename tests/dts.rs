@@ -0,0 +1,56 @@
+use experimental_runtime::{generate_dts, HostApiBuilder, RunOptions, Runtime};
+use serde_json::json;
+
+/// Declarations of a run with files, host methods and both schemas. Update
+/// `tests/dts/host.d.ts` along with changes to the host surface.
+#[test]
+fn declarations_match_the_snapshot() {
+    let api = HostApiBuilder::new()
+        .method("add", |(a, b): (f64, f64)| Ok(a + b))
+        .signature("add", "(a: number, b: number): number")
+        .build();
+    let options = RunOptions {
+        files: [
+            ("report.csv".to_string(), "/tmp/report.csv".into()),
+            ("config.json".to_string(), "/tmp/config.json".into()),
+        ]
+        .into(),
+        host_api: Some(api),
+        input_schema: Some(json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}},
+                "limit": {"type": ["integer", "null"]},
+                "options": {"type": "object", "properties": {"verbose": {"type": "boolean"}}},
+            },
+            "required": ["name"],
+            "additionalProperties": false,
+        })),
+        output_schema: Some(json!({
+            "anyOf": [
+                {"type": "string"},
+                {"type": "object", "additionalProperties": {"type": "number"}},
+            ],
+        })),
+        ..Default::default()
+    };
+    let runtime = Runtime::builder()
+        .options(options)
+        .register_op("lookup", |key: String| async move { Ok(key) })
+        .build()
+        .unwrap();
+    assert_eq!(
+        generate_dts(runtime.options()),
+        include_str!("dts/host.d.ts")
+    );
+}
+
+#[test]
+fn bare_options_declare_an_empty_host() {
+    let dts = generate_dts(&RunOptions::default());
+    assert!(dts.contains("  const files: {\n  };\n"));
+    assert!(dts.contains("  const api: {\n  };\n"));
+    assert!(!dts.contains("onMessage"));
+    assert!(!dts.contains("type Inputs"));
+}
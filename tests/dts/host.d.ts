@@ -0,0 +1,32 @@
+// Generated by experimental_runtime, do not edit.
+
+interface HostFile {
+  readonly name: string;
+  readonly size: number;
+  stream(): ReadableStream<Uint8Array>;
+  bytes(): Promise<Uint8Array>;
+  text(): Promise<string>;
+}
+
+declare namespace host {
+  const files: {
+    readonly "config.json": HostFile;
+    readonly "report.csv": HostFile;
+  };
+  const api: {
+    "lookup"(...args: unknown[]): Promise<unknown>;
+    "add"(a: number, b: number): number;
+  };
+}
+
+type Inputs = {
+  "limit"?: number | null;
+  "name": string;
+  "options"?: {
+    "verbose"?: boolean;
+    [key: string]: unknown;
+  };
+  "tags"?: Array<"a" | "b">;
+};
+
+type Output = (string) | (Record<string, number>);
@@ -0,0 +1,122 @@
+#![cfg(feature = "typescript")]
+
+mod common;
+
+use deno_core::ModuleSpecifier;
+use experimental_runtime::{
+    run_with_options, transpile_embedded, EmbeddedModules, Inputs, RunOptions, RuntimeError,
+    RuntimePermissions,
+};
+use serde_json::json;
+use std::path::PathBuf;
+
+const MAIN: &str = r#"
+import { greet } from "./lib/greet.ts";
+import { shout } from "./lib/shout.js";
+import config from "./config.json" with { type: "json" };
+
+export function main({ name }: { name: string }): string {
+  return shout(greet(name, config.greeting));
+}
+"#;
+
+const GREET: &str = r#"
+type Greeting = string;
+export const greet = (name: string, greeting: Greeting): string => `${greeting}, ${name}`;
+"#;
+
+const SHOUT: &str = r#"
+export const shout = (text: string): string => text.toUpperCase() + "!";
+"#;
+
+fn modules() -> EmbeddedModules {
+    let mut modules = EmbeddedModules::new();
+    modules
+        .insert("main.ts", MAIN)
+        .insert("lib/greet.ts", GREET)
+        .insert("config.json", r#"{ "greeting": "hello" }"#);
+    modules.insert_transpiled(
+        "lib/shout.js",
+        transpile_embedded("lib/shout.ts", SHOUT).unwrap(),
+    );
+    modules
+}
+
+fn run(modules: EmbeddedModules, entry: &str) -> Result<serde_json::Value, anyhow::Error> {
+    let options = RunOptions {
+        embedded: Some(modules),
+        permissions: Some(RuntimePermissions::none()),
+        ..Default::default()
+    };
+    let inputs = Inputs::new().text("name", "embedded");
+    run_with_options(PathBuf::from(entry), inputs, options)
+}
+
+#[test]
+fn fully_embedded_graph_runs_without_any_permissions() {
+    assert_eq!(
+        run(modules(), "main.ts").unwrap(),
+        json!("HELLO, EMBEDDED!")
+    );
+    assert_eq!(
+        run(modules(), "./main.ts").unwrap(),
+        json!("HELLO, EMBEDDED!")
+    );
+}
+
+#[test]
+fn pre_transpiled_modules_are_plain_javascript() {
+    let code = transpile_embedded("lib/greet.ts", GREET).unwrap();
+    assert!(!code.contains("Greeting"), "{}", code);
+    assert!(transpile_embedded("broken.ts", "export const = ;").is_err());
+}
+
+#[test]
+fn missing_embedded_modules_are_rejected() {
+    let mut modules = modules();
+    modules.insert(
+        "escape.js",
+        "import './../../etc/passwd.js';\nexport function main() {}",
+    );
+    let error = run(modules, "escape.js").unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("no module is embedded at /etc/passwd.js"),
+        "{:#}",
+        error
+    );
+
+    let error = run(self::modules(), "nope.ts").unwrap_err();
+    assert!(format!("{:#}", error).contains("nope.ts"), "{:#}", error);
+}
+
+#[test]
+fn outside_specifiers_are_rejected_without_a_fallback() {
+    let fixture = common::Fixture::new();
+    let outside = fixture.file("outside.js", "export const value = 1;");
+    let outside = ModuleSpecifier::from_file_path(outside.canonicalize().unwrap()).unwrap();
+    let source = format!(
+        "import {{ value }} from {:?};\nexport function main() {{ return value; }}",
+        outside.as_str()
+    );
+
+    let mut modules = EmbeddedModules::new();
+    modules.insert("main.js", source.clone());
+    let error = run(modules, "main.js").unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::ModuleResolution { .. })
+        ),
+        "{:#}",
+        error
+    );
+    assert!(
+        format!("{:#}", error).contains("outside the embedded modules"),
+        "{:#}",
+        error
+    );
+
+    let mut modules = EmbeddedModules::new().with_network_fallback();
+    modules.insert("main.js", source);
+    assert_eq!(run(modules, "main.js").unwrap(), json!(1));
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use experimental_runtime::{
+    run_with_options, FunctionRuntime, Inputs, RunOptions, RuntimePermissions,
+};
+use serde_json::json;
+use std::collections::HashMap;
+
+const ENV: &str = r#"
+export function main() {
+  let setFailed = false;
+  try {
+    Deno.env.set("DATABASE_URL", "changed");
+  } catch (e) {
+    setFailed = e instanceof Deno.errors.PermissionDenied;
+  }
+  const copy = Deno.env.toObject();
+  copy.INJECTED = "mutated copy";
+  return {
+    injected: Deno.env.get("DATABASE_URL") ?? null,
+    path: Deno.env.get("PATH") ?? null,
+    hasPath: Deno.env.has("PATH"),
+    all: Deno.env.toObject(),
+    setFailed,
+  };
+}
+"#;
+
+fn env(vars: &[(&str, &str)]) -> Option<HashMap<String, String>> {
+    Some(
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    )
+}
+
+fn expected(database_url: &str) -> serde_json::Value {
+    json!({
+        "injected": database_url,
+        "path": null,
+        "hasPath": false,
+        "all": { "DATABASE_URL": database_url },
+        "setFailed": true,
+    })
+}
+
+#[test]
+fn only_injected_variables_are_visible() {
+    assert!(std::env::var_os("PATH").is_some());
+    let (_fixture, function) = common::module("env.js", ENV);
+    for permissions in [None, Some(RuntimePermissions::none())] {
+        let options = RunOptions {
+            env: env(&[("DATABASE_URL", "postgres://db")]),
+            permissions,
+            ..Default::default()
+        };
+        let value = run_with_options(function.clone(), Inputs::new(), options).unwrap();
+        assert_eq!(value, expected("postgres://db"));
+    }
+}
+
+#[test]
+fn injected_variables_stay_with_their_runtime() {
+    let (_fixture, function) = common::module("env.js", ENV);
+    let runtime = |url: &str| {
+        let options = RunOptions {
+            env: env(&[("DATABASE_URL", url)]),
+            ..Default::default()
+        };
+        FunctionRuntime::new(function.clone(), options).unwrap()
+    };
+    let mut first = runtime("postgres://first");
+    let mut second = runtime("postgres://second");
+    for _ in 0..2 {
+        assert_eq!(
+            first.call("main", Inputs::new()).unwrap(),
+            expected("postgres://first")
+        );
+        assert_eq!(
+            second.call("main", Inputs::new()).unwrap(),
+            expected("postgres://second")
+        );
+    }
+}
+
+#[test]
+fn without_injection_env_access_follows_the_permissions() {
+    let (_fixture, function) = common::module(
+        "path.js",
+        "export const main = () => Deno.env.get(\"PATH\") !== undefined;",
+    );
+    let allowed = RunOptions {
+        permissions: Some(RuntimePermissions {
+            allow_env: Some(vec!["PATH".to_string()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    assert_eq!(
+        run_with_options(function.clone(), Inputs::new(), allowed).unwrap(),
+        true
+    );
+
+    let denied = RunOptions {
+        permissions: Some(RuntimePermissions::none()),
+        ..Default::default()
+    };
+    let error = run_with_options(function, Inputs::new(), denied).unwrap_err();
+    assert!(format!("{:#}", error).contains("env access"), "{:#}", error);
+}
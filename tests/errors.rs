@@ -0,0 +1,295 @@
+mod common;
+
+use experimental_runtime::{run_with_options, Inputs, RunOptions, RuntimeError};
+
+fn run(name: &str, source: &str) -> anyhow::Error {
+    let (_fixture, function) = common::module(name, source);
+    run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err()
+}
+
+#[test]
+fn top_level_throws_are_reported_with_their_location() {
+    let error = run(
+        "boom.js",
+        "\nthrow new Error(\"boom\");\nexport function main() {}\n",
+    );
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::ModuleEvaluation {
+            specifier,
+            message,
+            location,
+        }) => {
+            assert!(specifier.ends_with("boom.js"), "{}", specifier);
+            assert!(message.contains("boom"), "{}", message);
+            let location = location.as_deref().unwrap();
+            assert!(location.contains("boom.js:2:"), "{}", location);
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+#[test]
+fn rejected_top_level_awaits_are_reported() {
+    let error = run(
+        "rejected.js",
+        "await Promise.reject(new Error(\"config unavailable\"));\nexport function main() {}\n",
+    );
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::ModuleEvaluation { message, .. }) => {
+            assert!(message.contains("config unavailable"), "{}", message)
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+#[test]
+fn throws_in_imported_modules_fail_the_evaluation() {
+    let fixture = common::Fixture::new();
+    fixture.file("dependency.js", "throw new TypeError(\"bad dependency\");");
+    let function = fixture.file(
+        "main.js",
+        "import \"./dependency.js\";\nexport function main() {}\n",
+    );
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::ModuleEvaluation { message, .. }) if message.contains("bad dependency")
+        ),
+        "{:#}",
+        error
+    );
+}
+
+/// File names of the modules in an import chain.
+fn names(chain: &[deno_core::ModuleSpecifier]) -> Vec<&str> {
+    chain
+        .iter()
+        .map(|specifier| specifier.path().rsplit('/').next().unwrap())
+        .collect()
+}
+
+#[test]
+fn missing_modules_report_the_import_chain() {
+    let fixture = common::Fixture::new();
+    fixture.file("a.js", "import \"./lib/b.js\";");
+    fixture.file("lib/b.js", "import \"./missing.js\";");
+    let function = fixture.file("main.js", "import \"./a.js\";\nexport function main() {}\n");
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::ModuleLoad {
+            specifier, chain, ..
+        }) => {
+            assert!(
+                specifier.path().ends_with("/lib/missing.js"),
+                "{}",
+                specifier
+            );
+            assert_eq!(names(chain), ["main.js", "a.js", "b.js", "missing.js"]);
+        }
+        _ => panic!("{:#}", error),
+    }
+    let message = format!("{:#}", error);
+    assert!(message.contains("import chain: file://"), "{}", message);
+
+    // Dynamic imports report the chain up to the importing module.
+    let error = run(
+        "dynamic.js",
+        "export async function main() { await import(\"./missing.js\"); }",
+    );
+    let message = format!("{:#}", error);
+    assert!(message.contains("dynamic.js -> file://"), "{}", message);
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn failed_fetches_report_the_status_and_import_chain() {
+    let server = common::Server::start();
+    server.route(
+        "/lib.js",
+        common::Response::ok("application/javascript", "import \"./gone.js\";"),
+    );
+    let (_fixture, function) = common::module(
+        "main.js",
+        &format!(
+            "import {:?};\nexport function main() {{}}\n",
+            server.url("/lib.js")
+        ),
+    );
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::Fetch {
+            url, status, chain, ..
+        }) => {
+            assert_eq!(url, &server.url("/gone.js"));
+            assert_eq!(*status, Some(404));
+            assert_eq!(names(chain), ["main.js", "lib.js", "gone.js"]);
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+#[test]
+fn exceptions_in_main_carry_the_js_stack() {
+    let error = run(
+        "stack.js",
+        r#"
+function parseToken(token) {
+  throw new TypeError(`malformed token ${token}`);
+}
+export function main() {
+  return parseToken("abc");
+}
+"#,
+    );
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::JsException {
+            name,
+            message,
+            stack,
+            source_line,
+        }) => {
+            assert_eq!(name.as_deref(), Some("TypeError"));
+            assert!(message.contains("malformed token abc"), "{}", message);
+            let stack = stack.as_deref().unwrap();
+            assert!(stack.contains("parseToken"), "{}", stack);
+            assert!(stack.contains("main"), "{}", stack);
+            let source_line = source_line.as_deref().unwrap();
+            assert!(
+                source_line.contains("throw new TypeError"),
+                "{}",
+                source_line
+            );
+        }
+        _ => panic!("{:#}", error),
+    }
+    assert_eq!(
+        error.downcast_ref::<RuntimeError>().unwrap().kind(),
+        "js_exception"
+    );
+}
+
+#[test]
+fn rejected_promises_from_main_are_exceptions() {
+    let error = run(
+        "reject.js",
+        "export async function main() { throw new RangeError(\"out of range\"); }",
+    );
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::JsException { name: Some(name), message, .. })
+                if name == "RangeError" && message.contains("out of range")
+        ),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn thrown_non_errors_are_exceptions_too() {
+    let error = run(
+        "string.js",
+        "export function main() { throw \"plain string\"; }",
+    );
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::JsException { message, .. }) => {
+            assert!(message.contains("plain string"), "{}", message)
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+#[test]
+fn missing_entrypoints_list_the_exports() {
+    let error = run(
+        "exports.js",
+        "export const helper = 1;\nexport function other() {}",
+    );
+    match error.downcast_ref::<RuntimeError>() {
+        Some(error @ RuntimeError::MissingEntrypoint { .. }) => {
+            let message = error.to_string();
+            assert!(message.contains("helper"), "{}", message);
+            assert!(message.contains("other"), "{}", message);
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+#[cfg(feature = "typescript")]
+#[test]
+fn syntax_errors_are_transpile_errors() {
+    let error = run("broken.ts", "export const main = (: number => 1;");
+    match error.downcast_ref::<RuntimeError>() {
+        Some(error @ RuntimeError::Transpile { specifier, .. }) => {
+            assert!(specifier.ends_with("broken.ts"), "{}", specifier);
+            assert_eq!(error.kind(), "transpile");
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+#[test]
+fn unsupported_results_name_the_value() {
+    let error = run(
+        "symbol.js",
+        "export function main() { return { id: Symbol(\"opaque\") }; }",
+    );
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::UnsupportedValue { path, kind }) => {
+            assert_eq!((path.as_str(), kind.as_str()), ("result.id", "symbol"))
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+/// Types and blank lines the transpiler strips, so the throw on line 12
+/// moves up in the emitted JavaScript.
+#[cfg(feature = "typescript")]
+const TYPED_THROW: &str = r#"interface Order {
+  id: string;
+  total: number;
+}
+
+type Totals = Record<string, number>;
+
+export function main(): Totals {
+  const order: Order = { id: "a", total: -1 };
+  const totals: Totals = {};
+  if (order.total < 0) {
+    throw new Error(`negative total for ${order.id}`);
+  }
+  return totals;
+}
+"#;
+
+#[cfg(feature = "typescript")]
+fn assert_original_line(error: &anyhow::Error, file: &str) {
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::JsException { stack, .. }) => {
+            let stack = stack.as_deref().unwrap();
+            assert!(stack.contains(&format!("{}:12:", file)), "{}", stack);
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+#[cfg(feature = "typescript")]
+#[test]
+fn typescript_stacks_point_at_the_original_lines() {
+    let error = run("order.ts", TYPED_THROW);
+    assert_original_line(&error, "order.ts");
+}
+
+#[cfg(all(feature = "typescript", feature = "net-loader"))]
+#[test]
+fn remote_typescript_stacks_point_at_the_original_lines() {
+    let server = common::Server::start();
+    server.route(
+        "/order.ts",
+        common::Response::ok("application/typescript", TYPED_THROW),
+    );
+    let function = std::path::PathBuf::from(server.url("/order.ts"));
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    assert_original_line(&error, &server.url("/order.ts"));
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use experimental_runtime::{
+    ExecutorPool, Inputs, RunOptions, RuntimeError, RuntimeManager, TenantLimits,
+};
+use std::sync::Arc;
+
+const SQUARE: &str = r#"
+export async function main({ n }) {
+  await new Promise((resolve) => setTimeout(resolve, 10));
+  return n * n;
+}
+"#;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_jobs_from_many_tasks() {
+    let (_fixture, function) = common::module("square.js", SQUARE);
+    let pool = Arc::new(ExecutorPool::new(4).unwrap());
+
+    let tasks: Vec<_> = (0..20)
+        .map(|n| {
+            let pool = pool.clone();
+            let function = function.clone();
+            tokio::spawn(async move {
+                let value = pool
+                    .submit(function, Inputs::new().json("n", n.into()))
+                    .await;
+                (n, value)
+            })
+        })
+        .collect();
+    for task in tasks {
+        let (n, value) = task.await.unwrap();
+        assert_eq!(value.unwrap(), n * n);
+    }
+}
+
+#[test]
+fn blocking_execution_from_threads() {
+    let (_fixture, function) = common::module("square.js", SQUARE);
+    let pool = ExecutorPool::new(2).unwrap();
+    std::thread::scope(|scope| {
+        for n in 0..6 {
+            let pool = &pool;
+            let function = function.clone();
+            scope.spawn(move || {
+                let value = pool.execute(function, Inputs::new().json("n", n.into()));
+                assert_eq!(value.unwrap(), n * n);
+            });
+        }
+    });
+}
+
+#[test]
+fn shutdown_finishes_queued_jobs() {
+    let (_fixture, function) = common::module("square.js", SQUARE);
+    let pool = ExecutorPool::new(1).unwrap();
+    let pending: Vec<_> = (0..5)
+        .map(|n| pool.submit(function.clone(), Inputs::new().json("n", n.into())))
+        .collect();
+    pool.shutdown();
+    for (n, result) in pending.into_iter().enumerate() {
+        let value = futures::executor::block_on(result).unwrap();
+        assert_eq!(value, n * n);
+    }
+}
+
+#[test]
+fn dropping_the_pool_joins_its_threads() {
+    let (_fixture, function) = common::module("square.js", SQUARE);
+    let pool = ExecutorPool::new(2).unwrap();
+    let pending = pool.submit(function, Inputs::new().json("n", 3.into()));
+    drop(pool);
+    assert_eq!(futures::executor::block_on(pending).unwrap(), 9);
+}
+
+#[test]
+fn warm_pools_keep_module_state() {
+    let (_fixture, function) = common::module(
+        "counter.js",
+        "let calls = 0;\nexport function main() { return ++calls; }",
+    );
+    let pool = ExecutorPool::warm(1, RunOptions::default(), 100).unwrap();
+    let values: Vec<_> = (0..3)
+        .map(|_| pool.execute(function.clone(), Inputs::new()).unwrap())
+        .collect();
+    assert_eq!(values, [1, 2, 3]);
+}
+
+/// [`SQUARE`], slow enough for the queue to be looked at while it runs.
+const SLOW_SQUARE: &str = r#"
+export async function main({ n }) {
+  await new Promise((resolve) => setTimeout(resolve, 200));
+  return n * n;
+}
+"#;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn tenants_queue_separately_and_report_their_load() {
+    let (_fixture, function) = common::module("square.js", SLOW_SQUARE);
+    let limits = TenantLimits {
+        max_concurrency: 1,
+        max_queued: 1,
+    };
+    let manager = RuntimeManager::new(RunOptions::default(), limits);
+    let square = |tenant: &str, n: i32| {
+        manager.submit(tenant, function.clone(), Inputs::new().json("n", n.into()))
+    };
+
+    let running = tokio::spawn(square("a", 2));
+    let queued = tokio::spawn(square("a", 3));
+    let error = square("a", 4).await.unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::QueueFull { tenant, limit }) => {
+            assert_eq!((tenant.as_str(), *limit), ("a", 1));
+        }
+        _ => panic!("{:#}", error),
+    }
+    let stats = manager.stats();
+    assert_eq!((stats.active_isolates, stats.queued), (1, 1));
+    assert_eq!(
+        (stats.tenants["a"].running, stats.tenants["a"].queued),
+        (1, 1)
+    );
+
+    // A full queue doesn't hold up other tenants.
+    assert_eq!(square("b", 5).await.unwrap(), 25);
+    assert_eq!(running.await.unwrap().unwrap(), 4);
+    assert_eq!(queued.await.unwrap().unwrap(), 9);
+    let stats = manager.stats();
+    assert_eq!((stats.active_isolates, stats.queued), (0, 0));
+    assert_eq!(
+        stats.tenants.keys().collect::<Vec<_>>(),
+        [&"a".to_string(), &"b".to_string()]
+    );
+
+    assert!(manager
+        .add_tenant("a", RunOptions::default(), limits)
+        .is_err());
+    assert!(manager.remove_tenant("a"));
+    assert!(!manager.remove_tenant("a"));
+    manager.shutdown();
+}
@@ -0,0 +1,51 @@
+//! Runs under every feature combination, including
+//! `--no-default-features`, with the file-only loader.
+
+mod common;
+
+use experimental_runtime::{run_with_options, Inputs, RunOptions};
+
+#[test]
+fn plain_javascript_runs_with_any_features() {
+    let fixture = common::Fixture::new();
+    fixture.file("lib.js", "export const double = (n) => n * 2;");
+    let function = fixture.file(
+        "main.js",
+        r#"
+import { double } from "./lib.js";
+export function main({ n }) { return double(n); }
+"#,
+    );
+    let inputs = Inputs::new().json("n", 21.into());
+    let value = run_with_options(function, inputs, RunOptions::default()).unwrap();
+    assert_eq!(value, 42);
+}
+
+#[cfg(not(feature = "typescript"))]
+#[test]
+fn typescript_needs_the_typescript_feature() {
+    let (_fixture, function) = common::module("main.ts", "export const main = (): number => 1;");
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(
+        error.contains("built without typescript support"),
+        "{}",
+        error
+    );
+}
+
+#[cfg(not(feature = "net-loader"))]
+#[test]
+fn remote_imports_need_the_network_loader() {
+    let (_fixture, function) = common::module(
+        "main.js",
+        "import 'https://example.com/lib.js';\nexport const main = () => 1;",
+    );
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(
+        error.contains("built without network loader support"),
+        "{}",
+        error
+    );
+}
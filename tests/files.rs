@@ -0,0 +1,85 @@
+mod common;
+
+use experimental_runtime::{run_with_options, Inputs, RunOptions, RuntimePermissions};
+use serde_json::json;
+use std::collections::HashMap;
+
+const FILES: &str = r#"
+export async function main({ path }) {
+  const file = host.files.report;
+  let streamed = 0;
+  let chunks = 0;
+  for await (const chunk of file.stream()) {
+    streamed += chunk.byteLength;
+    chunks++;
+  }
+  let text;
+  try {
+    text = (await file.text()).slice(0, 5);
+  } catch (e) {
+    text = e.message;
+  }
+  let direct;
+  try {
+    await Deno.readFile(path);
+    direct = "read";
+  } catch (e) {
+    direct = e.name;
+  }
+  return {
+    names: Object.keys(host.files),
+    name: file.name,
+    size: file.size,
+    streamed,
+    chunks,
+    text,
+    direct,
+  };
+}
+"#;
+
+/// Runs `FILES` without any permissions, with a 100 KiB file as `report`.
+fn run(limit: Option<usize>) -> serde_json::Value {
+    let fixture = common::Fixture::new();
+    let report = fixture.file("private/report.txt", "lines\n".repeat(100 * 1024 / 6));
+    let function = fixture.file("files.js", FILES);
+    let options = RunOptions {
+        permissions: Some(RuntimePermissions::none()),
+        files: HashMap::from([("report".to_string(), report.clone())]),
+        file_read_limit: limit,
+        ..Default::default()
+    };
+    let inputs = Inputs::new().text("path", report.to_str().unwrap());
+    run_with_options(function, inputs, options).unwrap()
+}
+
+#[test]
+fn provided_files_are_readable_without_read_permission() {
+    let value = run(None);
+    let size = "lines\n".repeat(100 * 1024 / 6).len();
+    assert_eq!(
+        value,
+        json!({
+            "names": ["report"],
+            "name": "report",
+            "size": size,
+            "streamed": size,
+            "chunks": 2,
+            "text": "lines",
+            "direct": "PermissionDenied",
+        })
+    );
+}
+
+#[test]
+fn whole_file_reads_stop_at_the_limit() {
+    let value = run(Some(1024));
+    let message = value["text"].as_str().unwrap();
+    assert!(
+        message.contains("\"report\" is larger than 1024 bytes"),
+        "{}",
+        message
+    );
+    // Streaming isn't capped.
+    assert_eq!(value["streamed"], value["size"]);
+}
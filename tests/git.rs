@@ -0,0 +1,187 @@
+#![cfg(feature = "git")]
+
+mod common;
+
+use experimental_runtime::{
+    run_with_options, CachePolicy, HttpOptions, Inputs, ModuleCache, RunOptions,
+};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const ADVERTISEMENT: &str = "/repo.git/info/refs?service=git-upload-pack";
+const UPLOAD_PACK: &str = "/repo.git/git-upload-pack";
+
+fn git(repo: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(repo)
+        .env("GIT_AUTHOR_NAME", "test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {:?} failed", args);
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// A repository with a TypeScript module importing a sibling, on `main`.
+/// Returns the commit.
+fn repository(fixture: &common::Fixture, answer: &str) -> String {
+    let repo = fixture.path().join("repo");
+    fixture.file(
+        "repo/src/mod.ts",
+        "import { answer } from \"./answer.ts\";\nexport const main = (): number => answer;\n",
+    );
+    fixture.file(
+        "repo/src/answer.ts",
+        format!("export const answer: number = {};\n", answer),
+    );
+    git(&repo, &["init", "-q", "-b", "main"]);
+    git(&repo, &["add", "-A"]);
+    git(&repo, &["commit", "-q", "-m", "answer"]);
+    git(&repo, &["rev-parse", "HEAD"])
+}
+
+/// `git upload-pack` behind the smart HTTP endpoints, checking the
+/// `Authorization` header against `credentials` when given.
+fn serve(repo: PathBuf, credentials: Option<&'static str>) -> common::Server {
+    let server = common::Server::start();
+    let upload_pack = move |advertise: bool| {
+        let repo = repo.clone();
+        move |headers: &Vec<(String, String)>, body: &[u8]| {
+            let header = |name: &str| {
+                headers
+                    .iter()
+                    .find(|(header, _)| header == name)
+                    .map(|(_, value)| value.as_str())
+            };
+            if credentials.is_some() && header("authorization") != credentials {
+                return common::Response::status(401);
+            }
+            let mut command = Command::new("git");
+            command.arg("upload-pack").arg("--stateless-rpc");
+            if advertise {
+                command.arg("--advertise-refs");
+            }
+            let mut child = command
+                .arg(&repo)
+                .env("GIT_PROTOCOL", header("git-protocol").unwrap_or_default())
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child.stdin.take().unwrap().write_all(body).unwrap();
+            let output = child.wait_with_output().unwrap();
+            let mut response = vec![];
+            if advertise {
+                response.extend_from_slice(b"001e# service=git-upload-pack\n0000");
+            }
+            response.extend(output.stdout);
+            common::Response::ok("application/x-git-upload-pack-result", response)
+        }
+    };
+    server.handle(ADVERTISEMENT, upload_pack(true));
+    server.handle(UPLOAD_PACK, upload_pack(false));
+    server
+}
+
+fn run(
+    server: &common::Server,
+    revision: &str,
+    options: RunOptions,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let specifier = format!("git+{}#{}/src/mod.ts", server.url("/repo.git"), revision);
+    run_with_options(PathBuf::from(specifier), Inputs::new(), options)
+}
+
+fn cached(fixture: &common::Fixture, policy: CachePolicy) -> RunOptions {
+    RunOptions {
+        module_cache: Some(ModuleCache::in_dir(fixture.path().join("cache"), policy)),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn pinned_commits_are_fetched_once_and_served_from_the_cache() {
+    let fixture = common::Fixture::new();
+    let commit = repository(&fixture, "42");
+    let server = serve(fixture.path().join("repo"), None);
+
+    let value = run(&server, &commit, cached(&fixture, CachePolicy::UseCache)).unwrap();
+    assert_eq!(value, 42);
+    let requests = server.hits(ADVERTISEMENT) + server.hits(UPLOAD_PACK);
+    assert!(requests > 0);
+
+    let value = run(&server, &commit, cached(&fixture, CachePolicy::Offline)).unwrap();
+    assert_eq!(value, 42);
+    let value = run(&server, &commit, cached(&fixture, CachePolicy::UseCache)).unwrap();
+    assert_eq!(value, 42);
+    assert_eq!(
+        server.hits(ADVERTISEMENT) + server.hits(UPLOAD_PACK),
+        requests
+    );
+}
+
+#[test]
+fn branches_need_mutable_refs_to_be_allowed() {
+    let fixture = common::Fixture::new();
+    repository(&fixture, "7");
+    let server = serve(fixture.path().join("repo"), None);
+
+    let error = run(&server, "main", cached(&fixture, CachePolicy::UseCache)).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("need a full commit hash"),
+        "{:#}",
+        error
+    );
+    assert_eq!(server.hits(ADVERTISEMENT), 0);
+
+    let options = RunOptions {
+        allow_mutable_git_refs: true,
+        ..cached(&fixture, CachePolicy::UseCache)
+    };
+    assert_eq!(run(&server, "main", options).unwrap(), 7);
+}
+
+#[test]
+fn credentials_are_sent_to_their_origin() {
+    let fixture = common::Fixture::new();
+    let commit = repository(&fixture, "1");
+    let server = serve(fixture.path().join("repo"), Some("Bearer token"));
+
+    let error = run(&server, &commit, cached(&fixture, CachePolicy::UseCache)).unwrap_err();
+    assert!(format!("{:#}", error).contains("401"), "{:#}", error);
+
+    let origin = server.url("").trim_end_matches('/').to_string();
+    let options = RunOptions {
+        http: Some(HttpOptions {
+            credentials: HashMap::from([(origin, "Bearer token".to_string())]),
+            ..Default::default()
+        }),
+        ..cached(&fixture, CachePolicy::UseCache)
+    };
+    assert_eq!(run(&server, &commit, options).unwrap(), 1);
+}
+
+#[test]
+fn missing_files_name_the_commit() {
+    let fixture = common::Fixture::new();
+    let commit = repository(&fixture, "1");
+    let server = serve(fixture.path().join("repo"), None);
+
+    let specifier = format!("git+{}#{}/src/missing.ts", server.url("/repo.git"), commit);
+    let error = run_with_options(
+        PathBuf::from(specifier),
+        Inputs::new(),
+        cached(&fixture, CachePolicy::UseCache),
+    )
+    .unwrap_err();
+    assert!(
+        format!("{:#}", error).contains(&format!("src/missing.ts does not exist at {}", commit)),
+        "{:#}",
+        error
+    );
+}
@@ -0,0 +1,213 @@
+#![cfg(feature = "grpc")]
+
+mod common;
+
+use experimental_runtime::grpc_proto::invocation_client::InvocationClient;
+use experimental_runtime::grpc_proto::{
+    invocation_event, invocation_request, invocation_response, InvocationRequest,
+};
+use experimental_runtime::{GrpcServer, RunOptions};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tonic::transport::Channel;
+use tonic::Code;
+
+const ADD: &str = "export const main = ({ a, b }) => a + b;";
+
+const EMIT: &str = r#"
+export function main({ count }) {
+  for (let i = 0; i < count; i++) host.emit({ step: i });
+  return "done";
+}
+"#;
+
+const SPIN: &str = "export function main() { for (;;) {} }";
+
+/// A server on a free port with the handles `add`, `emit` and `spin`,
+/// stopped when the returned sender is dropped.
+async fn start(fixture: &common::Fixture) -> (Channel, oneshot::Sender<()>) {
+    let server = GrpcServer::new(RunOptions::default())
+        .route("add", fixture.file("add.js", ADD))
+        .route("emit", fixture.file("emit.js", EMIT))
+        .route("spin", fixture.file("spin.js", SPIN));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(server.serve(listener, async {
+        let _ = stopped.await;
+    }));
+    let channel = Channel::from_shared(format!("http://{}", address))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    (channel, stop)
+}
+
+fn request(handle: &str, inputs: serde_json::Value) -> InvocationRequest {
+    InvocationRequest {
+        module: Some(invocation_request::Module::Handle(handle.to_string())),
+        inputs: Some(invocation_request::Inputs::JsonInputs(
+            inputs.to_string().into_bytes(),
+        )),
+        ..Default::default()
+    }
+}
+
+fn json_result(result: Option<invocation_response::Result>) -> serde_json::Value {
+    match result {
+        Some(invocation_response::Result::Json(bytes)) => serde_json::from_slice(&bytes).unwrap(),
+        other => panic!("expected a json result, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn invoke_runs_a_handle() {
+    let fixture = common::Fixture::new();
+    let (channel, _stop) = start(&fixture).await;
+    let mut client = InvocationClient::new(channel);
+
+    let response = client
+        .invoke(request("add", serde_json::json!({"a": 2, "b": 3})))
+        .await
+        .unwrap();
+    assert_eq!(json_result(response.into_inner().result), 5);
+
+    // Struct inputs get a google.protobuf.Value back.
+    let number = |n: f64| prost_types::Value {
+        kind: Some(prost_types::value::Kind::NumberValue(n)),
+    };
+    let inputs = prost_types::Struct {
+        fields: [
+            ("a".to_string(), number(1.0)),
+            ("b".to_string(), number(4.0)),
+        ]
+        .into(),
+    };
+    let response = client
+        .invoke(InvocationRequest {
+            module: Some(invocation_request::Module::Handle("add".into())),
+            inputs: Some(invocation_request::Inputs::StructInputs(inputs)),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        response.into_inner().result,
+        Some(invocation_response::Result::Value(number(5.0)))
+    );
+}
+
+#[tokio::test]
+async fn unknown_handles_and_paths_are_refused() {
+    let fixture = common::Fixture::new();
+    let (channel, _stop) = start(&fixture).await;
+    let mut client = InvocationClient::new(channel);
+
+    let status = client
+        .invoke(request("missing", serde_json::json!({})))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+
+    let status = client
+        .invoke(InvocationRequest {
+            module: Some(invocation_request::Module::Path("/etc/passwd".into())),
+            ..Default::default()
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::PermissionDenied);
+}
+
+#[tokio::test]
+async fn the_call_deadline_stops_the_run() {
+    let fixture = common::Fixture::new();
+    let (channel, _stop) = start(&fixture).await;
+    let mut client = InvocationClient::new(channel);
+
+    let mut call = tonic::Request::new(request("spin", serde_json::json!({})));
+    call.set_timeout(Duration::from_millis(300));
+    let status = client.invoke(call).await.unwrap_err();
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+
+    // So does the request's own deadline.
+    let status = client
+        .invoke(InvocationRequest {
+            deadline_ms: 200,
+            ..request("spin", serde_json::json!({}))
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+    assert_eq!(status.metadata().get("error-kind").unwrap(), "timeout");
+}
+
+#[tokio::test]
+async fn invoke_stream_forwards_emitted_messages() {
+    let fixture = common::Fixture::new();
+    let (channel, _stop) = start(&fixture).await;
+    let mut client = InvocationClient::new(channel);
+
+    let mut events = client
+        .invoke_stream(request("emit", serde_json::json!({"count": 3})))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut steps = vec![];
+    let result = loop {
+        let event = events.message().await.unwrap().unwrap();
+        match event.event.unwrap() {
+            invocation_event::Event::Message(message) => steps.push(message),
+            invocation_event::Event::Result(result) => break json_result(result.result),
+        }
+    };
+    assert_eq!(steps.len(), 3);
+    assert_eq!(result, "done");
+    assert!(events.message().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn health_and_reflection_are_served() {
+    use tonic_health::pb::health_check_response::ServingStatus;
+    use tonic_health::pb::health_client::HealthClient;
+    use tonic_health::pb::HealthCheckRequest;
+
+    let fixture = common::Fixture::new();
+    let (channel, _stop) = start(&fixture).await;
+
+    let status = HealthClient::new(channel.clone())
+        .check(HealthCheckRequest {
+            service: "experimental_runtime.v1.Invocation".into(),
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .status;
+    assert_eq!(status, ServingStatus::Serving as i32);
+
+    use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+    use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+    use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+    use tonic_reflection::pb::v1::ServerReflectionRequest;
+
+    let request = ServerReflectionRequest {
+        host: String::new(),
+        message_request: Some(MessageRequest::ListServices(String::new())),
+    };
+    let mut responses = ServerReflectionClient::new(channel)
+        .server_reflection_info(tokio_stream::iter([request]))
+        .await
+        .unwrap()
+        .into_inner();
+    let response = responses.message().await.unwrap().unwrap();
+    let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+        panic!(
+            "expected a service list, got {:?}",
+            response.message_response
+        );
+    };
+    let services: Vec<_> = list.service.into_iter().map(|s| s.name).collect();
+    assert!(services.contains(&"experimental_runtime.v1.Invocation".to_string()));
+    assert!(services.contains(&"grpc.health.v1.Health".to_string()));
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use anyhow::anyhow;
+use experimental_runtime::{
+    run_with_options, HostApiBuilder, Inputs, RunOptions, Runtime, SharedHostApi,
+};
+use serde_json::json;
+
+/// A sync method adding its arguments, an async one echoing its argument
+/// and one that always fails.
+fn api() -> HostApiBuilder {
+    HostApiBuilder::new()
+        .method("add", |(a, b): (f64, f64)| Ok(a + b))
+        .async_method("echo", |value: serde_json::Value| async move { Ok(value) })
+        .method(
+            "fail",
+            |_: Vec<serde_json::Value>| -> Result<(), anyhow::Error> {
+                Err(anyhow!("no secret for you"))
+            },
+        )
+}
+
+fn run(source: &str, api: SharedHostApi) -> Result<serde_json::Value, anyhow::Error> {
+    let (_fixture, function) = common::module("main.js", source);
+    let options = RunOptions {
+        host_api: Some(api),
+        ..Default::default()
+    };
+    run_with_options(function, Inputs::new(), options)
+}
+
+#[test]
+fn sync_methods_return_their_value() {
+    let value = run(
+        "export const main = () => host.api.add(2, 3);",
+        api().build(),
+    )
+    .unwrap();
+    assert_eq!(value, 5.0);
+}
+
+#[test]
+fn async_methods_return_a_promise() {
+    let value = run(
+        r#"export async function main() {
+  const pending = host.api.echo({ ok: [1, "two"] });
+  return { isPromise: pending instanceof Promise, value: await pending };
+}"#,
+        api().build(),
+    )
+    .unwrap();
+    assert_eq!(
+        value,
+        json!({ "isPromise": true, "value": { "ok": [1, "two"] } })
+    );
+}
+
+#[test]
+fn method_errors_are_catchable_with_their_message() {
+    let value = run(
+        r#"export function main() {
+  try {
+    host.api.fail();
+    return "no error";
+  } catch (e) {
+    return { error: e instanceof Error, message: e.message };
+  }
+}"#,
+        api().build(),
+    )
+    .unwrap();
+    assert_eq!(value["error"], true);
+    assert!(
+        value["message"]
+            .as_str()
+            .unwrap()
+            .contains("no secret for you"),
+        "{}",
+        value
+    );
+
+    // Uncaught, it fails the run.
+    let error = run("export const main = () => host.api.fail();", api().build()).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("no secret for you"),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn layers_add_and_override_methods_of_the_base() {
+    let layered = HostApiBuilder::new()
+        .method("add", |(a, b): (f64, f64)| Ok(a + b + 100.0))
+        .method("double", |x: f64| Ok(x * 2.0))
+        .layered(api().build());
+    let value = run(
+        r#"export async function main() {
+  return {
+    names: Object.keys(host.api).sort(),
+    add: host.api.add(2, 3),
+    double: host.api.double(4),
+    echo: await host.api.echo("base"),
+  };
+}"#,
+        layered,
+    )
+    .unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "names": ["add", "double", "echo", "fail"],
+            "add": 105,
+            "double": 8,
+            "echo": "base",
+        })
+    );
+}
+
+#[test]
+fn registered_ops_are_callable_next_to_the_host_api() {
+    let runtime = Runtime::builder()
+        .options(RunOptions {
+            host_api: Some(api().build()),
+            ..Default::default()
+        })
+        .register_op("secret", |name: String| async move {
+            Ok(format!("secret of {}", name))
+        })
+        .build()
+        .unwrap();
+    let (_fixture, function) = common::module(
+        "main.js",
+        "export const main = async () => [await host.api.secret(\"db\"), host.api.add(1, 2)];",
+    );
+    let value = runtime.run(function, Inputs::new()).unwrap();
+    assert_eq!(value, json!(["secret of db", 3]));
+}
+
+#[test]
+fn ops_clashing_with_host_api_methods_are_refused() {
+    let error = Runtime::builder()
+        .options(RunOptions {
+            host_api: Some(api().build()),
+            ..Default::default()
+        })
+        .register_op("add", |(a, b): (f64, f64)| async move { Ok(a * b) })
+        .build()
+        .unwrap_err();
+    assert!(format!("{:#}", error).contains("\"add\""), "{:#}", error);
+}
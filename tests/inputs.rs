@@ -0,0 +1,165 @@
+mod common;
+
+use experimental_runtime::{
+    run_with_options, run_with_values, InputPart, Inputs, OutputValue, RunOptions, RuntimeError,
+};
+use serde_json::json;
+
+const PARTS: &str = r#"
+export function main({ metadata, note, upload }) {
+  let sum = 0;
+  for (const byte of upload) sum = (sum + byte) % 65521;
+  return {
+    metadata,
+    note,
+    isBytes: upload instanceof Uint8Array,
+    contentType: upload.contentType,
+    length: upload.length,
+    sum,
+  };
+}
+"#;
+
+#[test]
+fn json_text_and_binary_parts() {
+    let (_fixture, function) = common::module("parts.js", PARTS);
+    let upload = (0..5 * 1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+    let sum = upload.iter().fold(0u64, |sum, &b| (sum + b as u64) % 65521);
+    let inputs = Inputs::new()
+        .json(
+            "metadata",
+            json!({ "name": "photo.png", "tags": ["a", "b"] }),
+        )
+        .text("note", "hello")
+        .bytes("upload", upload, "image/png");
+
+    let value = run_with_options(function, inputs, RunOptions::default()).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "metadata": { "name": "photo.png", "tags": ["a", "b"] },
+            "note": "hello",
+            "isBytes": true,
+            "contentType": "image/png",
+            "length": 5 * 1024 * 1024,
+            "sum": sum,
+        })
+    );
+}
+
+#[test]
+fn schema_sees_binary_parts_as_descriptions() {
+    let (_fixture, function) = common::module("parts.js", PARTS);
+    let options = RunOptions {
+        input_schema: Some(json!({
+            "type": "object",
+            "properties": {
+                "upload": {
+                    "type": "object",
+                    "properties": {
+                        "contentType": { "const": "image/png" },
+                        "byteLength": { "maximum": 4 }
+                    }
+                }
+            }
+        })),
+        ..Default::default()
+    };
+    let inputs = Inputs::new().part(
+        "upload",
+        InputPart::Bytes(vec![1, 2, 3, 4, 5].into(), "image/png".into()),
+    );
+
+    let error = run_with_options(function, inputs, options).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::InputValidation { violations }) => {
+            assert_eq!(violations.len(), 1);
+            assert_eq!(violations[0].path, "/upload/byteLength");
+        }
+        other => panic!("expected input validation to fail, got {:?}", other),
+    }
+}
+
+#[test]
+fn input_size_limit_counts_binary_parts() {
+    let (_fixture, function) = common::module("parts.js", PARTS);
+    let options = RunOptions {
+        max_input_bytes: Some(1024),
+        ..Default::default()
+    };
+    let inputs = Inputs::new().bytes("upload", vec![0; 4096], "application/octet-stream");
+
+    let error = run_with_options(function, inputs, options).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::InputTooLarge { limit: 1024, .. })
+    ));
+}
+
+const TRANSFORM: &str = r#"
+export function main({ data, as }) {
+  const out = new Uint8Array(data.length);
+  for (let i = 0; i < data.length; i++) out[i] = data[i] ^ 0xff;
+  switch (as) {
+    case "buffer": return out.buffer;
+    case "view": return new DataView(out.buffer, 1, 3);
+    case "slice": return out.subarray(2, 6);
+    case "json": return { length: out.length };
+    default: return out;
+  }
+}
+"#;
+
+fn transform(data: Vec<u8>, kind: &str) -> OutputValue {
+    let (_fixture, function) = common::module("transform.js", TRANSFORM);
+    let inputs = Inputs::new()
+        .bytes("data", data, "application/octet-stream")
+        .text("as", kind);
+    run_with_values(function, inputs, RunOptions::default()).unwrap()
+}
+
+#[test]
+fn megabyte_buffers_round_trip_as_bytes() {
+    let data = (0..1024 * 1024)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    let expected = data.iter().map(|b| b ^ 0xff).collect::<Vec<_>>();
+    for kind in ["array", "buffer"] {
+        match transform(data.clone(), kind) {
+            OutputValue::Bytes(bytes) => assert!(bytes == expected, "{} differs", kind),
+            other => panic!("{:?}", other),
+        }
+    }
+}
+
+#[test]
+fn views_return_only_their_bytes() {
+    let data = vec![0, 1, 2, 3, 4, 5, 6, 7];
+    assert_eq!(
+        transform(data.clone(), "slice"),
+        OutputValue::Bytes(vec![0xfd, 0xfc, 0xfb, 0xfa].into())
+    );
+    assert_eq!(
+        transform(data.clone(), "view"),
+        OutputValue::Bytes(vec![0xfe, 0xfd, 0xfc].into())
+    );
+    assert_eq!(
+        transform(data, "json"),
+        OutputValue::Json(json!({ "length": 8 }))
+    );
+}
+
+#[test]
+fn byte_output_limits_apply() {
+    let (_fixture, function) = common::module("transform.js", TRANSFORM);
+    let inputs = Inputs::new().bytes("data", vec![0; 4096], "application/octet-stream");
+    let options = RunOptions {
+        max_output_bytes: Some(1024),
+        ..Default::default()
+    };
+    let error = run_with_values(function, inputs, options).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::OutputTooLarge { limit: 1024 })
+    ));
+}
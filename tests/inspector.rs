@@ -0,0 +1,92 @@
+mod common;
+
+use experimental_runtime::{run_with_options, Inputs, RunOptions};
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+const SLOW: &str = r#"
+export async function main() {
+  await new Promise((resolve) => setTimeout(resolve, 500));
+  return "done";
+}
+"#;
+
+/// An address nothing listens on yet.
+fn free_address() -> SocketAddr {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+}
+
+/// Targets the inspector at `address` lists, once it answers.
+fn targets(address: SocketAddr) -> Vec<Value> {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(
+        stream,
+        "GET /json/list HTTP/1.1\r\nhost: {}\r\nconnection: close\r\n\r\n",
+        address
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+#[test]
+fn inspected_runs_show_up_as_targets() {
+    let (_fixture, function) = common::module("slow.js", SLOW);
+    let address = free_address();
+    let options = RunOptions {
+        inspect: Some(address),
+        ..Default::default()
+    };
+    let running = std::thread::spawn(move || run_with_options(function, Inputs::new(), options));
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let listed = loop {
+        // The server only listens once the worker is being set up.
+        let listed = match TcpStream::connect(address) {
+            Ok(_) => targets(address),
+            Err(_) => vec![],
+        };
+        if !listed.is_empty() || Instant::now() > deadline {
+            break listed;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    };
+    // Without --inspect-brk the run doesn't wait for a debugger.
+    assert_eq!(running.join().unwrap().unwrap(), "done");
+
+    let [target] = &listed[..] else {
+        panic!("{:?}", listed);
+    };
+    assert!(
+        target["url"].as_str().unwrap().ends_with("/slow.js"),
+        "{}",
+        target
+    );
+    assert!(target["webSocketDebuggerUrl"]
+        .as_str()
+        .unwrap()
+        .starts_with(&format!("ws://{}/", address)));
+}
+
+#[test]
+fn taken_inspector_addresses_fail_the_run() {
+    let (_fixture, function) = common::module("slow.js", SLOW);
+    let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+    let options = RunOptions {
+        inspect: Some(taken.local_addr().unwrap()),
+        ..Default::default()
+    };
+    let error = run_with_options(function, Inputs::new(), options).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("Failed to start inspector server"),
+        "{:#}",
+        error
+    );
+}
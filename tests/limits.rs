@@ -0,0 +1,161 @@
+mod common;
+
+use experimental_runtime::{run_with_options, Inputs, RunOptions, RuntimeError};
+use std::time::{Duration, Instant};
+
+const LIMIT: Duration = Duration::from_millis(300);
+const GRACE: Duration = Duration::from_secs(2);
+
+fn run_timed(name: &str, source: &str) -> (Result<serde_json::Value, anyhow::Error>, Duration) {
+    let (_fixture, function) = common::module(name, source);
+    let options = RunOptions {
+        timeout: Some(LIMIT),
+        ..Default::default()
+    };
+    let started = Instant::now();
+    let result = run_with_options(function, Inputs::new(), options);
+    (result, started.elapsed())
+}
+
+fn assert_timed_out(name: &str, source: &str) {
+    let (result, elapsed) = run_timed(name, source);
+    let error = result.unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::Timeout { limit }) => assert_eq!(*limit, LIMIT),
+        _ => panic!("{:#}", error),
+    }
+    assert!(elapsed < LIMIT + GRACE, "took {:?}", elapsed);
+}
+
+#[test]
+fn busy_loops_are_terminated() {
+    assert_timed_out("busy.js", "export function main() { while (true) {} }");
+}
+
+#[test]
+fn promises_that_never_settle_time_out() {
+    assert_timed_out(
+        "pending.js",
+        "export function main() { return new Promise(() => {}); }",
+    );
+}
+
+#[test]
+fn slow_async_work_times_out() {
+    assert_timed_out(
+        "sleep.js",
+        "export async function main() { await new Promise((r) => setTimeout(r, 60000)); }",
+    );
+}
+
+#[test]
+fn hanging_top_level_code_times_out() {
+    assert_timed_out("top.js", "while (true) {}\nexport function main() {}");
+}
+
+#[test]
+fn runs_within_the_limit_are_unaffected() {
+    let (result, _) = run_timed(
+        "quick.js",
+        "export async function main() { await new Promise((r) => setTimeout(r, 10)); return 1; }",
+    );
+    assert_eq!(result.unwrap(), 1);
+}
+
+#[test]
+fn the_runtime_is_usable_after_a_timeout() {
+    assert_timed_out("busy.js", "export function main() { for (;;) {} }");
+    let (result, _) = run_timed(
+        "after.js",
+        "export function main() { return \"still here\"; }",
+    );
+    assert_eq!(result.unwrap(), "still here");
+}
+
+#[test]
+fn heap_limits_stop_runaway_allocations() {
+    const HEAP: usize = 32 * 1024 * 1024;
+    let (_fixture, function) = common::module(
+        "hog.js",
+        r#"
+export function main() {
+  const chunks = [];
+  for (;;) chunks.push(new Array(1024 * 1024).fill("x"));
+}
+"#,
+    );
+    let options = RunOptions {
+        max_heap_size: Some(HEAP),
+        // Whichever trips first wins, the heap limit well before this.
+        timeout: Some(Duration::from_secs(30)),
+        ..Default::default()
+    };
+    let error = run_with_options(function, Inputs::new(), options).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::HeapLimitExceeded { limit, observed }) => {
+            assert_eq!(*limit, HEAP);
+            assert!(*observed > 0);
+        }
+        _ => panic!("{:#}", error),
+    }
+
+    // The host survives and runs the next function.
+    let (result, _) = run_timed(
+        "after.js",
+        "export function main() { return [1, 2].length; }",
+    );
+    assert_eq!(result.unwrap(), 2);
+}
+
+#[test]
+fn timeouts_win_over_a_heap_limit_that_is_never_reached() {
+    let (_fixture, function) = common::module("busy.js", "export function main() { for (;;) {} }");
+    let options = RunOptions {
+        max_heap_size: Some(64 * 1024 * 1024),
+        timeout: Some(LIMIT),
+        ..Default::default()
+    };
+    let error = run_with_options(function, Inputs::new(), options).unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::Timeout { .. })
+        ),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn fuel_bounds_cpu_work_but_not_waiting() {
+    let run = |name: &str, source: &str| {
+        let (_fixture, function) = common::module(name, source);
+        let options = RunOptions {
+            fuel: Some(100),
+            timeout: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let result = run_with_options(function, Inputs::new(), options);
+        (result, started.elapsed())
+    };
+
+    let (result, elapsed) = run("busy.js", "export function main() { for (;;) {} }");
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::BudgetExceeded { fuel: 100 })
+        ),
+        "{:#}",
+        error
+    );
+    assert!(elapsed < GRACE, "took {:?}", elapsed);
+
+    // Waiting on a timer burns next to nothing.
+    let (result, _) = run(
+        "idle.js",
+        "export async function main() { await new Promise((r) => setTimeout(r, 500)); return 'rested'; }",
+    );
+    assert_eq!(result.unwrap(), "rested");
+}
@@ -0,0 +1,858 @@
+mod common;
+
+use deno_core::ModuleSpecifier;
+use experimental_runtime::{
+    run_with_options, FilterLayer, ImportMap, Inputs, LayerModule, LoaderStack, MemoryLayer,
+    RunOptions,
+};
+#[cfg(feature = "net-loader")]
+use experimental_runtime::{DiskCacheLayer, HttpOptions, ImportPolicy, RuntimeError};
+
+fn run(function: std::path::PathBuf) -> serde_json::Value {
+    run_with_options(function, Inputs::new(), RunOptions::default()).unwrap()
+}
+
+#[test]
+fn paths_with_spaces_and_escapes() {
+    let fixture = common::Fixture::new();
+    fixture.file(
+        "my functions/100% done/helper.js",
+        "export const answer = 42;",
+    );
+    let function = fixture.file(
+        "my functions/100% done/entry.js",
+        r#"
+import { answer } from "./helper.js";
+export function main() {
+  return { answer, url: import.meta.url };
+}
+"#,
+    );
+    let value = run(function.clone());
+    assert_eq!(value["answer"], 42);
+    let url = value["url"].as_str().unwrap();
+    assert!(
+        url.ends_with("/my%20functions/100%25%20done/entry.js"),
+        "{}",
+        url
+    );
+    let path = ModuleSpecifier::parse(url).unwrap().to_file_path().unwrap();
+    assert_eq!(
+        path.canonicalize().unwrap(),
+        function.canonicalize().unwrap()
+    );
+}
+
+#[test]
+fn missing_modules_are_reported_by_path() {
+    let fixture = common::Fixture::new();
+    let function = fixture.file("my functions/entry.js", "export function main() {}");
+    let missing = function.with_file_name("missing.js");
+    let error = run_with_options(missing, Inputs::new(), RunOptions::default()).unwrap_err();
+    let error = format!("{:#}", error);
+    let shown = std::path::Path::new("my functions").join("missing.js");
+    assert!(error.contains(&shown.display().to_string()), "{}", error);
+}
+
+fn utf16le(text: &str, bom: bool) -> Vec<u8> {
+    bom.then_some(0xfeff)
+        .into_iter()
+        .chain(text.encode_utf16())
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+const GREETING: &str = r#"export function main() { return "héllo wörld ✓"; }"#;
+
+#[test]
+fn utf16_file_modules_with_a_byte_order_mark() {
+    let fixture = common::Fixture::new();
+    let function = fixture.file("utf16.js", utf16le(GREETING, true));
+    assert_eq!(run(function), "héllo wörld ✓");
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn remote_modules_in_their_declared_charset() {
+    let server = common::Server::start();
+    server.route(
+        "/utf16.js",
+        common::Response::ok(
+            "application/javascript; charset=utf-16le",
+            utf16le(GREETING, false),
+        ),
+    );
+    server.route(
+        "/latin1.js",
+        common::Response::ok(
+            "application/javascript; charset=iso-8859-1",
+            b"export function main() { return \"caf\xe9\"; }".to_vec(),
+        ),
+    );
+    server.route(
+        "/klingon.js",
+        common::Response::ok("application/javascript; charset=klingon", GREETING),
+    );
+
+    assert_eq!(run(server.url("/utf16.js").into()), "héllo wörld ✓");
+    assert_eq!(run(server.url("/latin1.js").into()), "café");
+    let error = run_with_options(
+        server.url("/klingon.js").into(),
+        Inputs::new(),
+        RunOptions::default(),
+    )
+    .unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.contains("klingon"), "{}", error);
+    assert!(error.contains(&server.url("/klingon.js")), "{}", error);
+}
+
+const DECLARATIONS: &str = r#"
+export declare function greet(name: string): string;
+declare global { interface Window { answer: number } }
+"#;
+
+#[test]
+fn declaration_imports_are_skipped() {
+    let fixture = common::Fixture::new();
+    fixture.file("types.d.ts", DECLARATIONS);
+    fixture.file("legacy.D.MTS", DECLARATIONS);
+    let function = fixture.file(
+        "entry.js",
+        r#"
+import "./types.d.ts";
+import "./legacy.D.MTS";
+export function main() { return "ran"; }
+"#,
+    );
+    assert_eq!(run(function), "ran");
+}
+
+#[cfg(feature = "typescript")]
+#[test]
+fn typescript_declaration_imports_are_skipped() {
+    let fixture = common::Fixture::new();
+    fixture.file("types.d.ts", DECLARATIONS);
+    let function = fixture.file(
+        "entry.ts",
+        r#"
+import "./types.d.ts";
+import type { greet } from "./types.d.ts";
+export function main(): string {
+  const f: typeof greet = (name) => `hi ${name}`;
+  return f("ada");
+}
+"#,
+    );
+    assert_eq!(run(function), "hi ada");
+}
+
+const HELPER: &str = r#"
+export const url = import.meta.url;
+export const main = import.meta.main;
+"#;
+
+const META: &str = r#"
+import * as helper from "./helper.js";
+export function main() {
+  return {
+    main: import.meta.main,
+    url: import.meta.url,
+    helperMain: helper.main,
+    derived: new URL("./helper.js", import.meta.url).href === helper.url,
+  };
+}
+"#;
+
+fn assert_meta(value: &serde_json::Value, url: &str) {
+    assert_eq!(value["main"], true);
+    assert_eq!(value["url"], url);
+    assert_eq!(value["helperMain"], false);
+    assert_eq!(value["derived"], true);
+}
+
+#[test]
+fn import_meta_of_file_modules() {
+    let fixture = common::Fixture::new();
+    fixture.file("helper.js", HELPER);
+    let function = fixture.file("entry.js", META);
+    let url = ModuleSpecifier::from_file_path(function.canonicalize().unwrap()).unwrap();
+    assert_meta(&run(function), url.as_str());
+}
+
+#[test]
+fn import_meta_of_in_memory_modules() {
+    let mut modules = experimental_runtime::EmbeddedModules::new();
+    modules.insert("helper.js", HELPER);
+    let options = RunOptions {
+        embedded: Some(modules),
+        ..Default::default()
+    };
+    let value = experimental_runtime::run_source(
+        META,
+        experimental_runtime::SourceKind::JavaScript,
+        Inputs::new(),
+        options,
+    )
+    .unwrap();
+    let url = value["url"].as_str().unwrap();
+    assert!(ModuleSpecifier::parse(url).is_ok(), "{}", url);
+    assert_meta(&value, url);
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn import_meta_of_redirected_remote_modules() {
+    let server = common::Server::start();
+    server.route(
+        "/latest/entry.js",
+        common::Response::status(302).header("Location", "/v2/entry.js"),
+    );
+    server.route(
+        "/v2/entry.js",
+        common::Response::ok("application/javascript", META),
+    );
+    server.route(
+        "/v2/helper.js",
+        common::Response::ok("application/javascript", HELPER),
+    );
+    let value = run(server.url("/latest/entry.js").into());
+    assert_meta(&value, &server.url("/v2/entry.js"));
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn relative_redirects_are_followed_hop_by_hop() {
+    let server = common::Server::start();
+    server.route(
+        "/a/lib.js",
+        common::Response::status(301).header("Location", "../b/lib.js"),
+    );
+    server.route(
+        "/b/lib.js",
+        common::Response::status(302).header("Location", "/c/lib.js?v=1"),
+    );
+    server.route(
+        "/c/lib.js?v=1",
+        common::Response::ok(
+            "application/javascript",
+            "export const url = import.meta.url;",
+        ),
+    );
+    let (_fixture, function) = common::module(
+        "entry.js",
+        &format!(
+            "import {{ url }} from {:?};\nimport {{ url as again }} from {:?};\n\
+             export const main = () => ({{ url, same: url === again }});",
+            server.url("/a/lib.js"),
+            server.url("/b/lib.js"),
+        ),
+    );
+    assert_eq!(
+        run(function),
+        serde_json::json!({ "url": server.url("/c/lib.js?v=1"), "same": true })
+    );
+    assert_eq!(server.hits("/a/lib.js"), 1);
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn broken_redirects_fail_the_import() {
+    let server = common::Server::start();
+    server.route(
+        "/loop.js",
+        common::Response::status(302).header("Location", "/loop.js"),
+    );
+    server.route(
+        "/file.js",
+        common::Response::status(302).header("Location", "file:///etc/passwd"),
+    );
+    server.route("/nowhere.js", common::Response::status(302));
+    for (path, expected) in [
+        ("/loop.js", "too many redirects"),
+        ("/file.js", "unsupported scheme file"),
+        ("/nowhere.js", "redirect without a valid location"),
+    ] {
+        let function = server.url(path).into();
+        let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+        let error = format!("{:#}", error);
+        assert!(error.contains(expected), "{}: {}", path, error);
+    }
+}
+
+#[test]
+fn derived_urls_load_through_the_loader() {
+    let fixture = common::Fixture::new();
+    fixture.file("data/config.json", r#"{ "answer": 42 }"#);
+    let function = fixture.file(
+        "entry.js",
+        r#"
+export async function main() {
+  const url = new URL("./data/config.json", import.meta.url);
+  const { default: config } = await import(url.href, { with: { type: "json" } });
+  return config.answer;
+}
+"#,
+    );
+    let options = RunOptions {
+        permissions: Some(experimental_runtime::RuntimePermissions {
+            allow_read: Some(vec![fixture.path().canonicalize().unwrap()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let value = run_with_options(function, Inputs::new(), options).unwrap();
+    assert_eq!(value, 42);
+}
+
+const COUNTER: &str =
+    "export const state = { loads: (globalThis.loads = (globalThis.loads ?? 0) + 1) };";
+
+#[test]
+fn equivalent_spellings_load_one_module() {
+    let fixture = common::Fixture::new();
+    fixture.file("módulo útil.js", COUNTER);
+    let function = fixture.file(
+        "entry.js",
+        r#"
+import { state as a } from "./módulo útil.js";
+import { state as b } from "./m%C3%B3dulo%20%C3%BAtil.js";
+import { state as c } from "./m%c3%b3dulo%20%c3%batil.js";
+export function main() {
+  return { same: a === b && b === c, loads: globalThis.loads };
+}
+"#,
+    );
+    assert_eq!(
+        run(function),
+        serde_json::json!({ "same": true, "loads": 1 })
+    );
+}
+
+#[test]
+fn encoded_slashes_are_not_separators() {
+    let fixture = common::Fixture::new();
+    fixture.file("sub/helper.js", "export const x = 1;");
+    let function = fixture.file(
+        "entry.js",
+        r#"import { x } from "./sub%2Fhelper.js"; export function main() { return x; }"#,
+    );
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.contains("encoded path separator"), "{}", error);
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn equivalent_spellings_share_a_cache_entry() {
+    let server = common::Server::start();
+    server.route(
+        "/m%C3%B3dulo%20%C3%BAtil.js",
+        common::Response::ok("application/javascript", COUNTER),
+    );
+    server.route(
+        "/entry.js",
+        common::Response::ok(
+            "application/javascript",
+            r#"
+import { state as a } from "./módulo útil.js";
+import { state as b } from "./m%c3%b3dulo%20%c3%batil.js";
+export function main() { return a === b; }
+"#,
+        ),
+    );
+    let cache = tempfile::tempdir().unwrap();
+    let options = RunOptions {
+        module_cache: Some(experimental_runtime::ModuleCache::in_dir(
+            cache.path(),
+            experimental_runtime::CachePolicy::UseCache,
+        )),
+        ..Default::default()
+    };
+    for _ in 0..2 {
+        let value = run_with_options(
+            server.url("/entry.js").into(),
+            Inputs::new(),
+            options.clone(),
+        );
+        assert_eq!(value.unwrap(), true);
+    }
+    assert_eq!(server.hits("/m%C3%B3dulo%20%C3%BAtil.js"), 1);
+    // A body and its metadata for each of the two modules.
+    assert_eq!(std::fs::read_dir(cache.path()).unwrap().count(), 4);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinked_modules_load_once() {
+    let fixture = common::Fixture::new();
+    let real = fixture.file("lib/counter.js", COUNTER);
+    std::os::unix::fs::symlink(&real, fixture.path().join("alias.js")).unwrap();
+    let function = fixture.file(
+        "entry.js",
+        r#"
+import { state as a } from "./lib/counter.js";
+import { state as b } from "./alias.js";
+export function main() { return { same: a === b, loads: globalThis.loads }; }
+"#,
+    );
+    assert_eq!(
+        run(function.clone()),
+        serde_json::json!({ "same": true, "loads": 1 })
+    );
+
+    let options = RunOptions {
+        deny_symlinks: true,
+        ..Default::default()
+    };
+    let error = run_with_options(function, Inputs::new(), options).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.contains("symbolic link"), "{}", error);
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_do_not_escape_the_allowed_root() {
+    let outside = common::Fixture::new();
+    let secret = outside.file("secret.js", "export default 'secret';");
+    let fixture = common::Fixture::new();
+    fixture.file("inside.js", "export default 'inside';");
+    std::os::unix::fs::symlink(&secret, fixture.path().join("escape.js")).unwrap();
+    let function = fixture.file(
+        "entry.js",
+        r#"
+export async function main({ module }) {
+  return (await import(`./${module}`)).default;
+}
+"#,
+    );
+    let options = RunOptions {
+        permissions: Some(experimental_runtime::RuntimePermissions {
+            allow_read: Some(vec![fixture.path().canonicalize().unwrap()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let inputs = |module: &str| Inputs::new().text("module", module);
+
+    let value = run_with_options(function.clone(), inputs("inside.js"), options.clone());
+    assert_eq!(value.unwrap(), "inside");
+    let error = run_with_options(function, inputs("escape.js"), options).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(!error.contains("'secret'"), "{}", error);
+    assert!(error.contains("Requires read access"), "{}", error);
+}
+
+#[cfg(unix)]
+#[test]
+fn static_imports_through_symlinks_do_not_escape_the_allowed_root() {
+    let outside = common::Fixture::new();
+    let secret = outside.file("secret.js", "export default 'secret';");
+    let fixture = common::Fixture::new();
+    fixture.file("inside.js", "export default 'inside';");
+    std::os::unix::fs::symlink(&secret, fixture.path().join("escape.js")).unwrap();
+    let options = RunOptions {
+        permissions: Some(experimental_runtime::RuntimePermissions {
+            allow_read: Some(vec![fixture.path().canonicalize().unwrap()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let entry = |module: &str| {
+        fixture.file(
+            "entry.js",
+            format!(
+                "import value from \"./{}\";\nexport const main = () => value;",
+                module
+            ),
+        )
+    };
+
+    let value = run_with_options(entry("inside.js"), Inputs::new(), options.clone());
+    assert_eq!(value.unwrap(), "inside");
+    let error = run_with_options(entry("escape.js"), Inputs::new(), options).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.contains("escape.js links to"), "{}", error);
+    assert!(error.contains("Requires read access"), "{}", error);
+}
+
+#[cfg(all(feature = "net-loader", feature = "typescript"))]
+#[test]
+fn content_type_decides_how_remote_modules_load() {
+    let server = common::Server::start();
+    server.route(
+        "/pkg@1.2.3",
+        common::Response::ok(
+            "application/typescript; charset=utf-8",
+            "export const twice = (n: number): number => n * 2;",
+        ),
+    );
+    server.route(
+        "/util?target=es2022",
+        common::Response::ok("text/javascript", "export const label = \"untyped\";"),
+    );
+    server.route(
+        "/config",
+        common::Response::ok("application/json", r#"{ "factor": 21 }"#),
+    );
+    // Unhelpful types fall back to the extension.
+    server.route(
+        "/typed.ts",
+        common::Response::ok(
+            "application/octet-stream",
+            "export const typed: boolean = true;",
+        ),
+    );
+    let entry = format!(
+        r#"
+import {{ twice }} from "{}";
+import {{ label }} from "{}";
+import config from "{}" with {{ type: "json" }};
+import {{ typed }} from "{}";
+export function main() {{
+  return {{ value: twice(config.factor), label, typed }};
+}}
+"#,
+        server.url("/pkg@1.2.3"),
+        server.url("/util?target=es2022"),
+        server.url("/config"),
+        server.url("/typed.ts"),
+    );
+    let (_fixture, function) = common::module("entry.js", &entry);
+    let value = run(function);
+    assert_eq!(
+        value,
+        serde_json::json!({ "value": 42, "label": "untyped", "typed": true })
+    );
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn html_error_pages_are_not_modules() {
+    let server = common::Server::start();
+    server.route(
+        "/lib",
+        common::Response::ok("text/html", "<!doctype html><p>not found</p>"),
+    );
+    let (_fixture, function) = common::module(
+        "entry.js",
+        &format!(
+            "import {:?};\nexport const main = () => 1;",
+            server.url("/lib")
+        ),
+    );
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.contains("HTML error page"), "{}", error);
+}
+
+#[test]
+fn bare_specifiers_resolve_through_the_import_map() {
+    let fixture = common::Fixture::new();
+    fixture.file("vendor/zod/index.js", "export default \"zod 3\";");
+    fixture.file("vendor/zod-v2/index.js", "export default \"zod 2\";");
+    fixture.file("lib/util.js", "export default \"util\";");
+    fixture.file("legacy/mod.js", "export { default } from \"zod\";");
+    let function = fixture.file(
+        "main.js",
+        r#"
+import zod from "zod";
+import util from "lib/util.js";
+import legacy from "./legacy/mod.js";
+export const main = () => [zod, util, legacy];
+"#,
+    );
+    let blocked = fixture.file(
+        "blocked.js",
+        "import \"internal\";\nexport const main = () => 1;",
+    );
+    let base = ModuleSpecifier::from_directory_path(fixture.path()).unwrap();
+    let map = ImportMap::from_json(
+        &serde_json::json!({
+            "imports": {
+                "zod": "./vendor/zod/index.js",
+                "lib/": "./lib/",
+                "internal": null,
+            },
+            "scopes": {
+                "./legacy/": { "zod": "./vendor/zod-v2/index.js" },
+            },
+        }),
+        &base,
+    )
+    .unwrap();
+    let options = RunOptions {
+        import_map: Some(map),
+        ..Default::default()
+    };
+
+    let value = run_with_options(function, Inputs::new(), options.clone()).unwrap();
+    assert_eq!(value, serde_json::json!(["zod 3", "util", "zod 2"]));
+
+    let error = run_with_options(blocked, Inputs::new(), options).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("internal is blocked by the import map"),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn virtual_modules_are_served_from_memory() {
+    let fixture = common::Fixture::new();
+    let function = fixture.file(
+        "main.js",
+        r#"
+import config from "virtual:config.js";
+import { version } from "https://deno.land/x/stand-in/mod.js";
+import { generated } from "./generated.js";
+export const main = () => [config.name, version, generated];
+"#,
+    );
+    let specifier = |url: &str| ModuleSpecifier::parse(url).unwrap();
+    let generated = ModuleSpecifier::from_file_path(fixture.path().join("generated.js")).unwrap();
+    let options = RunOptions {
+        virtual_modules: [
+            (
+                specifier("virtual:config.js"),
+                "export default { name: \"config\" };",
+            ),
+            (
+                specifier("https://deno.land/x/stand-in/mod.js"),
+                "export const version = 1;",
+            ),
+            (generated, "export const generated = true;"),
+        ]
+        .into_iter()
+        .map(|(specifier, source)| (specifier, source.to_string()))
+        .collect(),
+        ..Default::default()
+    };
+    let value = run_with_options(function, Inputs::new(), options.clone()).unwrap();
+    assert_eq!(value, serde_json::json!(["config", 1, true]));
+
+    let missing = fixture.file(
+        "missing.js",
+        "import \"virtual:missing.js\";\nexport const main = () => 1;",
+    );
+    let error = run_with_options(missing, Inputs::new(), options).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("virtual:missing.js"),
+        "{:#}",
+        error
+    );
+}
+
+/// Serves `/flaky.js` with a 503 for the first `failures` requests.
+#[cfg(feature = "net-loader")]
+fn flaky_server(failures: usize) -> common::Server {
+    let server = common::Server::start();
+    let served = std::sync::atomic::AtomicUsize::new(0);
+    server.handle("/flaky.js", move |_, _| {
+        if served.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < failures {
+            common::Response::status(503)
+        } else {
+            common::Response::ok("application/javascript", "export default 1;")
+        }
+    });
+    server
+}
+
+#[cfg(feature = "net-loader")]
+fn fetch_remote(
+    server: &common::Server,
+    path: &str,
+    http: HttpOptions,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let (_fixture, function) = common::module(
+        "main.js",
+        &format!(
+            "import value from {:?};\nexport const main = () => value;",
+            server.url(path)
+        ),
+    );
+    let options = RunOptions {
+        http: Some(HttpOptions {
+            retry_backoff: std::time::Duration::from_millis(1),
+            ..http
+        }),
+        ..Default::default()
+    };
+    run_with_options(function, Inputs::new(), options)
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn server_errors_are_retried_with_backoff() {
+    let server = flaky_server(2);
+    let value = fetch_remote(&server, "/flaky.js", HttpOptions::default()).unwrap();
+    assert_eq!(value, 1);
+    assert_eq!(server.hits("/flaky.js"), 3);
+
+    // Not found isn't retried.
+    let error = fetch_remote(&server, "/missing.js", HttpOptions::default()).unwrap_err();
+    assert!(format!("{:#}", error).contains("404"), "{:#}", error);
+    assert_eq!(server.hits("/missing.js"), 1);
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn fetches_fail_after_the_last_retry_or_past_the_size_limit() {
+    let server = flaky_server(2);
+    let http = HttpOptions {
+        retries: 1,
+        ..Default::default()
+    };
+    let error = fetch_remote(&server, "/flaky.js", http).unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::Fetch {
+                status: Some(503),
+                ..
+            })
+        ),
+        "{:#}",
+        error
+    );
+    assert_eq!(server.hits("/flaky.js"), 2);
+
+    server.route(
+        "/large.js",
+        common::Response::ok("application/javascript", "x".repeat(100)),
+    );
+    let http = HttpOptions {
+        max_response_bytes: 16,
+        ..Default::default()
+    };
+    let error = fetch_remote(&server, "/large.js", http).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("is larger than the 16 byte limit"),
+        "{:#}",
+        error
+    );
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn disallowed_import_hosts_are_refused_before_fetching() {
+    let server = common::Server::start();
+    server.route(
+        "/lib.js",
+        common::Response::ok("application/javascript", "export default 1;"),
+    );
+    let (_fixture, function) = common::module(
+        "main.js",
+        &format!(
+            "import value from {:?};\nexport const main = () => value;",
+            server.url("/lib.js")
+        ),
+    );
+    let run = |policy: ImportPolicy| {
+        let options = RunOptions {
+            import_policy: Some(policy),
+            ..Default::default()
+        };
+        run_with_options(function.clone(), Inputs::new(), options)
+    };
+
+    assert_eq!(run(ImportPolicy::allow(["127.0.0.1"])).unwrap(), 1);
+    assert_eq!(server.hits("/lib.js"), 1);
+
+    let error = run(ImportPolicy::allow(["deno.land"])).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("127.0.0.1 is not an allowed import host"),
+        "{:#}",
+        error
+    );
+    let denied = ImportPolicy {
+        deny_hosts: vec!["127.0.0.1".into()],
+        ..ImportPolicy::allow(["127.0.0.1"])
+    };
+    let error = run(denied).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("127.0.0.1 is denied by the import policy"),
+        "{:#}",
+        error
+    );
+    assert_eq!(server.hits("/lib.js"), 1);
+}
+
+#[test]
+fn loader_stacks_serve_modules_from_their_layers() {
+    let greet = ModuleSpecifier::parse("https://example.com/greet.js").unwrap();
+    let memory = MemoryLayer::new().with_module(
+        greet,
+        LayerModule::new("export const greet = (name) => `hello, ${name}`;"),
+    );
+    let fixture = common::Fixture::new();
+    let function = fixture.file(
+        "main.js",
+        r#"
+import { greet } from "https://example.com/greet.js";
+export const main = async ({ other }) => other ? (await import(other)).x : greet("stack");
+"#,
+    );
+    let stack = LoaderStack::new()
+        .layer(memory.clone())
+        .layer(FilterLayer::new(|specifier| {
+            specifier.host_str() != Some("blocked.example.com")
+        }))
+        .without_network();
+    let run = |other: &str| {
+        let options = RunOptions {
+            loader_stack: Some(stack.clone()),
+            ..Default::default()
+        };
+        let inputs = Inputs::new().json("other", serde_json::json!(other));
+        run_with_options(function.clone(), inputs, options)
+    };
+
+    assert_eq!(run("").unwrap(), "hello, stack");
+    assert_eq!(memory.stats().hits, 1);
+
+    let error = run("https://blocked.example.com/x.js").unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("is not allowed by the loader stack"),
+        "{:#}",
+        error
+    );
+    let error = run("https://example.com/other.js").unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("no loader layer supplies it"),
+        "{:#}",
+        error
+    );
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn disk_cache_layers_keep_what_the_network_served() {
+    let server = common::Server::start();
+    server.route(
+        "/lib.js",
+        common::Response::ok("application/javascript", "export const x = 1;"),
+    );
+    let fixture = common::Fixture::new();
+    let function = fixture.file(
+        "main.js",
+        format!(
+            "import {{ x }} from \"{}\";\nexport const main = () => x;",
+            server.url("/lib.js")
+        ),
+    );
+    let cache = DiskCacheLayer::new(fixture.path().join("modules"));
+    for _ in 0..2 {
+        let options = RunOptions {
+            loader_stack: Some(LoaderStack::new().layer(cache.clone())),
+            ..Default::default()
+        };
+        assert_eq!(
+            run_with_options(function.clone(), Inputs::new(), options).unwrap(),
+            1
+        );
+    }
+    assert_eq!(server.hits("/lib.js"), 1);
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.insertions, stats.entries), (1, 1, 1));
+}
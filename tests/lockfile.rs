@@ -0,0 +1,107 @@
+#![cfg(feature = "net-loader")]
+
+mod common;
+
+use experimental_runtime::{run_with_options, Inputs, RunOptions};
+use std::path::{Path, PathBuf};
+
+const ORIGINAL: &str = "export const value = \"original\";";
+const TAMPERED: &str = "export const value = \"tampered\";";
+
+fn js(body: &str) -> common::Response {
+    common::Response::ok("application/javascript", body)
+}
+
+/// A server with `/latest/lib.js` redirecting to `/v1/lib.js`, and an entry
+/// module importing it.
+fn setup() -> (common::Server, common::Fixture, PathBuf) {
+    let server = common::Server::start();
+    server.route(
+        "/latest/lib.js",
+        common::Response::status(302).header("Location", "/v1/lib.js"),
+    );
+    server.route("/v1/lib.js", js(ORIGINAL));
+    let fixture = common::Fixture::new();
+    let function = fixture.file(
+        "main.js",
+        format!(
+            "import {{ value }} from {:?};\nexport const main = () => value;",
+            server.url("/latest/lib.js")
+        ),
+    );
+    (server, fixture, function)
+}
+
+fn run(function: &Path, lockfile: &Path, write: bool) -> anyhow::Result<serde_json::Value> {
+    let options = RunOptions {
+        lockfile: Some(lockfile.to_path_buf()),
+        lockfile_write: write,
+        ..Default::default()
+    };
+    run_with_options(function.to_path_buf(), Inputs::new(), options)
+}
+
+fn remote(lockfile: &Path) -> serde_json::Map<String, serde_json::Value> {
+    let content: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(lockfile).unwrap()).unwrap();
+    content["remote"].as_object().unwrap().clone()
+}
+
+#[test]
+fn written_lockfiles_are_keyed_by_the_final_url() {
+    let (server, fixture, function) = setup();
+    let lockfile = fixture.path().join("deno.lock");
+
+    assert_eq!(run(&function, &lockfile, true).unwrap(), "original");
+    let remote = remote(&lockfile);
+    assert_eq!(
+        remote.keys().collect::<Vec<_>>(),
+        [&server.url("/v1/lib.js")]
+    );
+    let hash = remote[&server.url("/v1/lib.js")].as_str().unwrap();
+    assert_eq!(hash.len(), 64);
+
+    assert_eq!(run(&function, &lockfile, false).unwrap(), "original");
+}
+
+#[test]
+fn tampered_content_fails_the_load() {
+    let (server, fixture, function) = setup();
+    let lockfile = fixture.path().join("deno.lock");
+    run(&function, &lockfile, true).unwrap();
+    let expected = remote(&lockfile)[&server.url("/v1/lib.js")]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    server.route("/v1/lib.js", js(TAMPERED));
+    let error = format!("{:#}", run(&function, &lockfile, false).unwrap_err());
+    assert!(error.contains("integrity check failed"), "{}", error);
+    assert!(error.contains(&server.url("/v1/lib.js")), "{}", error);
+    assert!(error.contains(&expected), "{}", error);
+
+    // Write mode accepts the new content and records its hash.
+    assert_eq!(run(&function, &lockfile, true).unwrap(), "tampered");
+    assert_ne!(
+        remote(&lockfile)[&server.url("/v1/lib.js")],
+        expected.as_str()
+    );
+}
+
+#[test]
+fn modules_missing_from_the_lockfile_fail_the_load() {
+    let (server, fixture, function) = setup();
+    let lockfile = fixture.file("deno.lock", r#"{ "version": "3", "remote": {} }"#);
+    let error = format!("{:#}", run(&function, &lockfile, false).unwrap_err());
+    assert!(error.contains("is not in the lockfile"), "{}", error);
+    assert!(error.contains(&server.url("/v1/lib.js")), "{}", error);
+}
+
+#[test]
+fn a_missing_lockfile_is_only_created_in_write_mode() {
+    let (_server, fixture, function) = setup();
+    let lockfile = fixture.path().join("missing.lock");
+    let error = format!("{:#}", run(&function, &lockfile, false).unwrap_err());
+    assert!(error.contains("could not read lockfile"), "{}", error);
+    assert!(!lockfile.exists());
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use experimental_runtime::{run_with_options, Inputs, InvocationHandle, RunOptions};
+use serde_json::json;
+
+/// Echoes messages back until told to stop, then returns how many it got.
+const ECHO: &str = r#"
+export function main() {
+  let received = 0;
+  return new Promise((resolve) => {
+    host.onMessage((message) => {
+      if (message.stop) return resolve(received);
+      received++;
+      host.emit({ echo: message });
+    });
+  });
+}
+"#;
+
+#[tokio::test]
+async fn functions_wait_for_and_answer_host_messages() {
+    let (_fixture, function) = common::module("echo.js", ECHO);
+    let mut handle =
+        InvocationHandle::start(function, Inputs::new(), RunOptions::default()).unwrap();
+
+    for n in 0..3 {
+        handle.send(json!({ "n": n })).unwrap();
+        assert_eq!(handle.recv().await.unwrap(), json!({ "echo": { "n": n } }));
+    }
+    handle.send(json!({ "stop": true })).unwrap();
+    assert_eq!(handle.result().await.unwrap(), 3);
+}
+
+#[tokio::test]
+async fn channels_fail_outside_an_invocation() {
+    let (_fixture, function) = common::module(
+        "emit.js",
+        "export const main = () => host.emit(\"nobody listens\");",
+    );
+    let error =
+        run_with_options(function.clone(), Inputs::new(), RunOptions::default()).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("no host is listening"),
+        "{:#}",
+        error
+    );
+
+    // Sending fails once the run is over.
+    let mut handle =
+        InvocationHandle::start(function, Inputs::new(), RunOptions::default()).unwrap();
+    assert_eq!(handle.recv().await.unwrap(), "nobody listens");
+    assert!(handle.recv().await.is_none());
+    assert!(handle.send(json!(1)).is_err());
+}
@@ -0,0 +1,219 @@
+#![cfg(feature = "otel")]
+
+mod common;
+
+use experimental_runtime::{run_with_options, RunOptions, Telemetry, TraceContext};
+use opentelemetry::trace::{SpanId, Status, TraceId};
+use opentelemetry::{KeyValue, Value};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::metrics::data::Sum;
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::runtime;
+use opentelemetry_sdk::testing::metrics::InMemoryMetricExporter;
+use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+use opentelemetry_sdk::trace::TracerProvider;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing_subscriber::layer::SubscriberExt;
+
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const PARENT_ID: &str = "00f067aa0ba902b7";
+
+fn incoming() -> TraceContext {
+    TraceContext {
+        traceparent: format!("00-{}-{}-01", TRACE_ID, PARENT_ID),
+        tracestate: Some("vendor=value".into()),
+    }
+}
+
+/// Telemetry exporting spans to memory as they end.
+fn telemetry() -> (Telemetry, InMemorySpanExporter) {
+    let spans = InMemorySpanExporter::default();
+    let tracer_provider = TracerProvider::builder()
+        .with_simple_exporter(spans.clone())
+        .build();
+    let telemetry = Telemetry::from_providers(tracer_provider, SdkMeterProvider::default());
+    (telemetry, spans)
+}
+
+/// Runs on this thread with the spans going to `telemetry`.
+fn traced_run(
+    telemetry: &Telemetry,
+    function: std::path::PathBuf,
+    options: RunOptions,
+) -> Result<serde_json::Value, anyhow::Error> {
+    let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+    tracing::subscriber::with_default(subscriber, || {
+        run_with_options(function, HashMap::new(), options)
+    })
+}
+
+fn span<'a>(spans: &'a [SpanData], name: &str) -> &'a SpanData {
+    spans
+        .iter()
+        .find(|span| span.name == name)
+        .unwrap_or_else(|| panic!("no {} span", name))
+}
+
+fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+    span.attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == key)
+        .map(|attribute| &attribute.value)
+}
+
+#[test]
+fn runs_export_a_span_per_phase_under_the_incoming_trace() {
+    let (_fixture, function) = common::module("main.js", "export const main = () => 42;");
+    let (telemetry, exporter) = telemetry();
+    let options = RunOptions {
+        trace_context: Some(incoming()),
+        ..Default::default()
+    };
+    let value = traced_run(&telemetry, function.clone(), options).unwrap();
+    assert_eq!(value, 42);
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let invocation = span(&spans, "invocation");
+    let trace_id = TraceId::from_hex(TRACE_ID).unwrap();
+    assert_eq!(invocation.span_context.trace_id(), trace_id);
+    assert_eq!(
+        invocation.parent_span_id,
+        SpanId::from_hex(PARENT_ID).unwrap()
+    );
+    assert_eq!(
+        attribute(invocation, "function"),
+        Some(&Value::from(function.display().to_string()))
+    );
+    assert_eq!(attribute(invocation, "error"), None);
+    for phase in ["bootstrap", "load", "evaluate", "call"] {
+        let span = span(&spans, phase);
+        assert_eq!(span.span_context.trace_id(), trace_id);
+        assert_eq!(
+            span.parent_span_id,
+            invocation.span_context.span_id(),
+            "{} is not a child of the invocation span",
+            phase
+        );
+        assert_eq!(attribute(span, "phase"), Some(&Value::from(phase)));
+    }
+}
+
+#[test]
+fn failed_runs_mark_their_span() {
+    let (_fixture, function) = common::module("spin.js", "export function main() { for (;;) {} }");
+    let (telemetry, exporter) = telemetry();
+    let options = RunOptions {
+        timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    };
+    traced_run(&telemetry, function, options).unwrap_err();
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let invocation = span(&spans, "invocation");
+    assert_eq!(
+        attribute(invocation, "error"),
+        Some(&Value::from("timeout"))
+    );
+    assert!(matches!(invocation.status, Status::Error { .. }));
+    // Without an incoming context the run starts a trace of its own.
+    assert_eq!(invocation.parent_span_id, SpanId::INVALID);
+}
+
+#[test]
+fn fetches_carry_the_trace_context_when_asked_to() {
+    let server = common::Server::start();
+    server.route("/", common::Response::ok("text/plain", "ok"));
+    let (_fixture, function) = common::module(
+        "fetch.js",
+        "export async function main({ url }) { return await (await fetch(url)).text(); }",
+    );
+    let (telemetry, exporter) = telemetry();
+    let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+    let run = |propagate_trace_context| {
+        let options = RunOptions {
+            trace_context: Some(incoming()),
+            propagate_trace_context,
+            ..Default::default()
+        };
+        let inputs = HashMap::from([("url".to_string(), json!(server.url("/")))]);
+        run_with_options(function.clone(), inputs, options).unwrap();
+        server.headers("/")
+    };
+
+    let headers = tracing::subscriber::with_default(subscriber, || run(false));
+    assert!(!headers.iter().any(|(name, _)| name == "traceparent"));
+
+    let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+    let headers = tracing::subscriber::with_default(subscriber, || run(true));
+    let spans = exporter.get_finished_spans().unwrap();
+    let invocation = spans
+        .iter()
+        .rev()
+        .find(|span| span.name == "invocation")
+        .unwrap();
+    let expected = format!("00-{}-{}-01", TRACE_ID, invocation.span_context.span_id());
+    assert!(headers.contains(&("traceparent".to_string(), expected)));
+    assert!(headers.contains(&("tracestate".to_string(), "vendor=value".to_string())));
+}
+
+#[test]
+fn invocations_are_recorded_as_metrics() {
+    // The periodic reader exports from a runtime of its own, runs make
+    // theirs.
+    let exports = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap();
+    let exporter = InMemoryMetricExporter::default();
+    let meter_provider = {
+        let _runtime = exports.enter();
+        SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter.clone(), runtime::Tokio).build())
+            .build()
+    };
+    let telemetry = Telemetry::from_providers(TracerProvider::default(), meter_provider.clone());
+    let (_fixture, function) = common::module("main.js", "export const main = () => 42;");
+    let options = RunOptions {
+        metrics: Some(telemetry.metrics()),
+        ..Default::default()
+    };
+    run_with_options(function.clone(), HashMap::new(), options.clone()).unwrap();
+    run_with_options(function.clone(), HashMap::new(), options).unwrap();
+    meter_provider.force_flush().unwrap();
+
+    let exported = exporter.get_finished_metrics().unwrap();
+    let metrics: Vec<_> = exported
+        .iter()
+        .flat_map(|resource| &resource.scope_metrics)
+        .flat_map(|scope| &scope.metrics)
+        .collect();
+    for name in [
+        "runtime.invocation.duration",
+        "runtime.invocation.phase.duration",
+        "runtime.invocation.peak_heap",
+    ] {
+        assert!(
+            metrics.iter().any(|metric| metric.name == name),
+            "no {}",
+            name
+        );
+    }
+    let invocations = metrics
+        .iter()
+        .find(|metric| metric.name == "runtime.invocations")
+        .unwrap();
+    let sum = invocations
+        .data
+        .as_any()
+        .downcast_ref::<Sum<u64>>()
+        .unwrap();
+    let point = &sum.data_points[0];
+    assert_eq!(point.value, 2);
+    assert!(point.attributes.contains(&KeyValue::new("outcome", "ok")));
+    assert!(point
+        .attributes
+        .contains(&KeyValue::new("function", function.display().to_string())));
+}
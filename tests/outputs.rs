@@ -0,0 +1,354 @@
+mod common;
+
+use deno_core::{serde_v8, v8};
+use experimental_runtime::{
+    run_with_options, BytesEncoding, CyclePolicy, DanglingWork, DatePolicy, ExtendedOptions,
+    Inputs, MapPolicy, OutputFormat, RunOptions, RuntimeError, RuntimePermissions, ValueHook,
+    Warning, WarningHook,
+};
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn to_json_is_respected() {
+    let (_fixture, function) = common::module(
+        "money.js",
+        r#"
+class Money {
+  constructor(cents, currency) { this.cents = cents; this.currency = currency; }
+  toJSON() { return `${(this.cents / 100).toFixed(2)} ${this.currency}`; }
+}
+export function main() {
+  return { total: new Money(1250, "EUR"), items: [new Money(5, "USD")] };
+}
+"#,
+    );
+    let value = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap();
+    assert_eq!(
+        value,
+        json!({ "total": "12.50 EUR", "items": ["0.05 USD"] })
+    );
+}
+
+const UNSERIALIZABLE: &str = r#"
+class Point {
+  #x; #y;
+  constructor(x, y) { this.#x = x; this.#y = y; }
+  get coordinates() { return [this.#x, this.#y]; }
+}
+export function main() {
+  return { origin: new Point(1, 2), big: 2n ** 70n };
+}
+"#;
+
+/// Maps points through their `coordinates` getter and bigints to strings.
+fn hook() -> ValueHook {
+    ValueHook::new(|scope, value, path| {
+        if value.is_big_int() {
+            return Some(Value::String(value.to_rust_string_lossy(scope)));
+        }
+        assert_eq!(path, "result.origin");
+        let object = v8::Local::<v8::Object>::try_from(value).ok()?;
+        let key = v8::String::new(scope, "coordinates")?;
+        let coordinates = object.get(scope, key.into())?;
+        serde_v8::from_v8(scope, coordinates).ok()
+    })
+}
+
+#[test]
+fn hook_maps_class_instances_and_bigints() {
+    let (_fixture, function) = common::module("point.js", UNSERIALIZABLE);
+    let options = RunOptions {
+        value_hook: Some(hook()),
+        ..Default::default()
+    };
+    let value = run_with_options(function, Inputs::new(), options).unwrap();
+    assert_eq!(
+        value,
+        json!({ "origin": [1, 2], "big": "1180591620717411303424" })
+    );
+}
+
+#[test]
+fn unmapped_values_fail_with_their_path() {
+    let (_fixture, function) = common::module("point.js", UNSERIALIZABLE);
+    let options = RunOptions {
+        value_hook: Some(ValueHook::new(|_, _, _| None)),
+        ..Default::default()
+    };
+    let error = run_with_options(function, Inputs::new(), options).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::UnsupportedValue { path, kind }) => {
+            assert_eq!(path, "result.big");
+            assert_eq!(kind, "bigint");
+        }
+        other => panic!("expected an unsupported value, got {:?}", other),
+    }
+}
+
+#[test]
+fn throwing_to_json_fails_the_run() {
+    let (_fixture, function) = common::module(
+        "throws.js",
+        r#"export function main() { return { a: { toJSON() { throw new Error("nope"); } } }; }"#,
+    );
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::ToJsonFailed { path, message }) => {
+            assert_eq!(path, "result.a");
+            assert!(message.contains("nope"), "{}", message);
+        }
+        other => panic!("expected toJSON to fail, got {:?}", other),
+    }
+}
+
+fn dangling(source: &str, mode: DanglingWork, options: RunOptions) -> Result<Value, anyhow::Error> {
+    let (_fixture, function) = common::module("dangling.js", source);
+    let options = RunOptions {
+        dangling_work: mode,
+        ..options
+    };
+    run_with_options(function, Inputs::new(), options)
+}
+
+const INTERVAL: &str = r#"
+export function main() {
+  setInterval(() => {}, 60_000);
+  return "done";
+}
+"#;
+
+#[test]
+fn uncleared_intervals_are_dangling_work() {
+    let error = dangling(INTERVAL, DanglingWork::Fail, RunOptions::default()).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::DanglingWork { pending }) => {
+            assert_eq!(pending, &["interval".to_string()]);
+        }
+        other => panic!("expected dangling work, got {:?}", other),
+    }
+
+    let warnings = Arc::new(Mutex::new(vec![]));
+    let seen = warnings.clone();
+    let options = RunOptions {
+        on_warning: Some(WarningHook::new(move |warning| {
+            seen.lock().unwrap().push(warning)
+        })),
+        ..Default::default()
+    };
+    assert_eq!(
+        dangling(INTERVAL, DanglingWork::Warn, options).unwrap(),
+        "done"
+    );
+    let warnings = warnings.lock().unwrap();
+    assert!(matches!(
+        &warnings[..],
+        [Warning::DanglingWork { pending }] if pending == &["interval".to_string()]
+    ));
+
+    let value = dangling(INTERVAL, DanglingWork::Ignore, RunOptions::default()).unwrap();
+    assert_eq!(value, "done");
+}
+
+#[test]
+fn unawaited_fetches_are_dangling_work() {
+    // Accepts connections and never answers, so the fetch stays pending.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let source = format!(
+        r#"
+export function main() {{
+  void fetch("http://{}/cleanup", {{ method: "POST" }});
+  return "done";
+}}
+"#,
+        address
+    );
+    let options = RunOptions {
+        permissions: Some(RuntimePermissions {
+            allow_net: Some(vec![address.to_string()]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let error = dangling(&source, DanglingWork::Fail, options).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::DanglingWork { pending }) => {
+            assert!(pending.iter().any(|p| p.contains("fetch")), "{:?}", pending);
+        }
+        other => panic!("expected dangling work, got {:?}", other),
+    }
+    drop(listener);
+}
+
+#[test]
+fn settled_work_is_not_dangling() {
+    let source = r#"
+export async function main() {
+  const id = setInterval(() => {}, 1);
+  await new Promise((resolve) => setTimeout(resolve, 5));
+  clearInterval(id);
+  return "done";
+}
+"#;
+    let value = dangling(source, DanglingWork::Fail, RunOptions::default()).unwrap();
+    assert_eq!(value, "done");
+}
+
+const GRAPHS: &str = r#"
+export function main({ depth }) {
+  if (depth) {
+    let value = [];
+    for (let i = 0; i < depth; i++) value = [value];
+    return value;
+  }
+  const shared = { shared: true };
+  const root = { nodes: [], shared: [shared, shared] };
+  for (let id = 0; id < 3; id++) root.nodes.push({ id, parent: root });
+  return root;
+}
+"#;
+
+#[test]
+fn circular_results_fail_with_both_paths() {
+    let (_fixture, function) = common::module("graph.js", GRAPHS);
+    let error = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::CircularReference { path, target }) => {
+            assert_eq!(path, "result.nodes[0].parent");
+            assert_eq!(target, "result");
+        }
+        _ => panic!("{:#}", error),
+    }
+    assert_eq!(
+        error.to_string(),
+        "result.nodes[0].parent points back to result"
+    );
+}
+
+#[test]
+fn back_references_can_become_refs() {
+    let (_fixture, function) = common::module("graph.js", GRAPHS);
+    let options = RunOptions {
+        output_format: OutputFormat::Extended(ExtendedOptions {
+            cycles: CyclePolicy::Ref,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let value = run_with_options(function, Inputs::new(), options).unwrap();
+    assert_eq!(
+        value["nodes"][2],
+        json!({ "id": 2, "parent": { "$ref": "result" } })
+    );
+    // Values seen twice without a cycle are repeated, not referenced.
+    assert_eq!(
+        value["shared"],
+        json!([{ "shared": true }, { "shared": true }])
+    );
+}
+
+#[test]
+fn deeply_nested_results_fail_at_the_limit() {
+    let (_fixture, function) = common::module("graph.js", GRAPHS);
+    let options = RunOptions {
+        max_depth: Some(10),
+        ..Default::default()
+    };
+    let run = |depth: u32| {
+        let inputs = Inputs::new().json("depth", json!(depth));
+        run_with_options(function.clone(), inputs, options.clone())
+    };
+
+    let nested = format!("{}{}", "[".repeat(10), "]".repeat(10));
+    assert_eq!(run(9).unwrap().to_string(), nested);
+    let error = run(20).unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::TooDeep { limit: 10, .. })
+        ),
+        "{:#}",
+        error
+    );
+}
+
+const RICH: &str = r#"
+export function main({ callback }) {
+  const value = {
+    error: new TypeError("bad input"),
+    pattern: /a+b/gi,
+    nested: { bytes: new Uint8Array([1, 2, 3]) },
+    date: new Date(0),
+    invalid: new Date(NaN),
+    map: new Map([[1, "one"]]),
+    set: new Set(["x", "y"]),
+  };
+  if (callback) value.nested.callback = () => {};
+  return value;
+}
+"#;
+
+fn extended(extended: ExtendedOptions) -> RunOptions {
+    RunOptions {
+        output_format: OutputFormat::Extended(extended),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn extended_results_keep_non_json_values() {
+    let (_fixture, function) = common::module("rich.js", RICH);
+    let value = run_with_options(
+        function.clone(),
+        Inputs::new(),
+        extended(Default::default()),
+    )
+    .unwrap();
+    assert_eq!(value["error"]["name"], "TypeError");
+    assert_eq!(value["error"]["message"], "bad input");
+    assert!(
+        value["error"]["stack"]
+            .as_str()
+            .unwrap()
+            .contains("rich.js"),
+        "{}",
+        value
+    );
+    assert_eq!(value["pattern"], json!({ "source": "a+b", "flags": "gi" }));
+    assert_eq!(value["nested"]["bytes"], json!([1, 2, 3]));
+    assert_eq!(value["date"], "1970-01-01T00:00:00.000Z");
+    assert_eq!(value["invalid"], Value::Null);
+    assert_eq!(value["map"], json!({ "1": "one" }));
+    assert_eq!(value["set"], json!(["x", "y"]));
+
+    let options = extended(ExtendedOptions {
+        bytes: BytesEncoding::Base64,
+        dates: DatePolicy::EpochMillis,
+        maps: MapPolicy::Entries,
+        ..Default::default()
+    });
+    let value = run_with_options(function.clone(), Inputs::new(), options).unwrap();
+    assert_eq!(value["nested"]["bytes"], "AQID");
+    assert_eq!(value["date"], 0);
+    assert_eq!(value["map"], json!([[1, "one"]]));
+
+    // Plain JSON stays the default.
+    let value = run_with_options(function, Inputs::new(), RunOptions::default()).unwrap();
+    assert_eq!(value["pattern"], json!({}));
+    assert_eq!(value["date"], "1970-01-01T00:00:00.000Z");
+}
+
+#[test]
+fn extended_results_still_refuse_functions() {
+    let (_fixture, function) = common::module("rich.js", RICH);
+    let inputs = Inputs::new().json("callback", json!(true));
+    let error = run_with_options(function, inputs, extended(Default::default())).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::UnsupportedValue { path, kind }) => {
+            assert_eq!(path, "result.nested.callback");
+            assert_eq!(kind, "function");
+        }
+        _ => panic!("{:#}", error),
+    }
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use experimental_runtime::{run_with_permissions, Inputs, RuntimeError, RuntimePermissions};
+
+const READ: &str = r#"
+export async function main({ path }) {
+  return await Deno.readTextFile(path);
+}
+"#;
+
+const FETCH: &str = r#"
+export async function main({ url }) {
+  const response = await fetch(url);
+  return await response.text();
+}
+"#;
+
+fn assert_denied(error: &anyhow::Error, access: &str) {
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::PermissionDenied { message }) => {
+            assert!(message.contains(access), "{}", message)
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+#[test]
+fn everything_is_denied_by_default() {
+    let fixture = common::Fixture::new();
+    let secret = fixture.file("secret.txt", "secret");
+    let function = fixture.file("read.js", READ);
+    let inputs = Inputs::new().text("path", secret.to_str().unwrap());
+    let error = run_with_permissions(function, inputs, RuntimePermissions::none()).unwrap_err();
+    assert_denied(&error, "read access");
+
+    let function = fixture.file("env.js", "export const main = () => Deno.env.get('HOME');");
+    let error =
+        run_with_permissions(function, Inputs::new(), RuntimePermissions::none()).unwrap_err();
+    assert_denied(&error, "env access");
+}
+
+#[test]
+fn reads_are_limited_to_the_allowlist() {
+    let fixture = common::Fixture::new();
+    let public = fixture.file("public/data.txt", "public");
+    let private = fixture.file("private/data.txt", "private");
+    let function = fixture.file("read.js", READ);
+    let permissions = RuntimePermissions {
+        allow_read: Some(vec![fixture.path().join("public")]),
+        ..Default::default()
+    };
+
+    let inputs = Inputs::new().text("path", public.to_str().unwrap());
+    let value = run_with_permissions(function.clone(), inputs, permissions.clone()).unwrap();
+    assert_eq!(value, "public");
+
+    let inputs = Inputs::new().text("path", private.to_str().unwrap());
+    let error = run_with_permissions(function, inputs, permissions).unwrap_err();
+    assert_denied(&error, "read access");
+}
+
+#[test]
+fn network_access_is_limited_to_the_allowlist() {
+    let allowed = common::Server::start();
+    allowed.route("/data", common::Response::ok("text/plain", "allowed"));
+    let other = common::Server::start();
+    other.route("/data", common::Response::ok("text/plain", "other"));
+
+    let (_fixture, function) = common::module("fetch.js", FETCH);
+    let host = allowed.url("").trim_start_matches("http://").to_string();
+    let permissions = RuntimePermissions {
+        allow_net: Some(vec![host]),
+        ..Default::default()
+    };
+
+    let inputs = Inputs::new().text("url", allowed.url("/data"));
+    let value = run_with_permissions(function.clone(), inputs, permissions.clone()).unwrap();
+    assert_eq!(value, "allowed");
+
+    let inputs = Inputs::new().text("url", other.url("/data"));
+    let error = run_with_permissions(function, inputs, permissions).unwrap_err();
+    assert_denied(&error, "net access");
+    assert_eq!(other.hits("/data"), 0);
+}
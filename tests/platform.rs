@@ -0,0 +1,66 @@
+//! The platform is process-wide and can't come back after `shutdown`, so
+//! this binary holds a single test walking through its whole life.
+
+mod common;
+
+use experimental_runtime::{
+    init, run_with_options, shutdown, ExecutorPool, Inputs, RunOptions, RuntimeError,
+    RuntimePlatform,
+};
+
+fn is_shut_down(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::ShutDown)
+    )
+}
+
+#[test]
+fn platform_lifecycle() {
+    let (_fixture, function) = common::module("double.js", "export const main = ({ n }) => n * 2;");
+    let run = |n: u64| {
+        run_with_options(
+            function.clone(),
+            Inputs::new().json("n", n.into()),
+            RunOptions::default(),
+        )
+    };
+
+    // Concurrent first use, without calling `init`.
+    std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..8).map(|n| scope.spawn(move || run(n))).collect();
+        for (n, thread) in threads.into_iter().enumerate() {
+            assert_eq!(thread.join().unwrap().unwrap(), n as u64 * 2);
+        }
+    });
+    init().unwrap();
+    init().unwrap();
+
+    // Anything holding the platform keeps it alive.
+    let pool = ExecutorPool::new(2).unwrap();
+    let error = shutdown().unwrap_err();
+    assert!(error.to_string().contains("are alive"), "{}", error);
+    assert_eq!(
+        pool.execute(function.clone(), Inputs::new().json("n", 4.into()))
+            .unwrap(),
+        8
+    );
+    pool.shutdown();
+
+    let platform = RuntimePlatform::acquire().unwrap();
+    let other = platform.clone();
+    assert!(platform.shutdown().is_err());
+    assert_eq!(run(5).unwrap(), 10);
+    other.shutdown().unwrap();
+
+    // Shut down for good.
+    shutdown().unwrap();
+    assert!(is_shut_down(&run(1).unwrap_err()));
+    assert!(is_shut_down(&init().unwrap_err()));
+    assert!(is_shut_down(&RuntimePlatform::acquire().unwrap_err()));
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| assert!(is_shut_down(&run(1).unwrap_err())));
+        }
+    });
+}
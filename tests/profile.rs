@@ -0,0 +1,80 @@
+mod common;
+
+use experimental_runtime::{run_profiled, Inputs, ProfileOptions, RunOptions};
+use serde_json::{json, Value};
+
+const BUSY: &str = r#"
+function busyWork(n) {
+  let sum = 0;
+  for (let i = 0; i < n; i++) sum += Math.sqrt(i);
+  return sum;
+}
+
+export function main({ fail }) {
+  const start = Date.now();
+  while (Date.now() - start < 50) busyWork(10000);
+  if (fail) throw new Error("asked to fail");
+  return "done";
+}
+"#;
+
+/// Names of the functions sampled in a CPU profile.
+fn sampled(cpu: &Value) -> Vec<&str> {
+    cpu["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|node| node["callFrame"]["functionName"].as_str())
+        .collect()
+}
+
+#[test]
+fn profiles_are_collected_and_written() {
+    let (fixture, function) = common::module("busy.js", BUSY);
+    let profile = ProfileOptions {
+        cpu: true,
+        heap_snapshot: true,
+        sampling_interval: Some(100),
+        dir: Some(fixture.path().join("profiles")),
+    };
+    let (result, collected) =
+        run_profiled(function, Inputs::new(), RunOptions::default(), &profile);
+    assert_eq!(result.unwrap(), "done");
+
+    let cpu = collected.cpu.unwrap();
+    assert!(sampled(&cpu).contains(&"busyWork"), "{:?}", sampled(&cpu));
+    let snapshot: Value = serde_json::from_str(&collected.heap_snapshot.unwrap()).unwrap();
+    assert!(snapshot["snapshot"]["meta"].is_object());
+    assert!(snapshot["nodes"].is_array());
+
+    let mut written: Vec<_> = collected
+        .files
+        .iter()
+        .map(|path| {
+            assert!(path.starts_with(fixture.path().join("profiles")));
+            let name = path.file_name().unwrap().to_str().unwrap();
+            assert!(name.starts_with("busy-"), "{}", name);
+            path.extension().unwrap().to_str().unwrap().to_string()
+        })
+        .collect();
+    written.sort();
+    assert_eq!(written, ["cpuprofile", "heapsnapshot"]);
+}
+
+#[test]
+fn failed_runs_keep_their_profile() {
+    let (_fixture, function) = common::module("busy.js", BUSY);
+    let profile = ProfileOptions {
+        cpu: true,
+        ..Default::default()
+    };
+    let inputs = Inputs::new().json("fail", json!(true));
+    let (result, collected) = run_profiled(function, inputs, RunOptions::default(), &profile);
+    assert!(
+        format!("{:#}", result.unwrap_err()).contains("asked to fail"),
+        "the run should fail"
+    );
+    assert!(sampled(collected.cpu.as_ref().unwrap()).contains(&"busyWork"));
+    assert!(collected.heap_snapshot.is_none());
+    assert!(collected.files.is_empty());
+}
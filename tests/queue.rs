@@ -0,0 +1,107 @@
+mod common;
+
+use experimental_runtime::{MemoryQueue, QueueRunner, RunOptions};
+use std::sync::{Arc, Mutex};
+
+const CHECKED: &str = r#"
+export function main({ n }) {
+  if (n < 0) throw new Error("negative");
+  return n;
+}
+"#;
+
+/// Ids, attempts and errors of the dead-lettered messages.
+type DeadLetters = Arc<Mutex<Vec<(String, u32, String)>>>;
+
+fn runner(function: std::path::PathBuf) -> (QueueRunner, DeadLetters) {
+    let dead = DeadLetters::default();
+    let letters = dead.clone();
+    let runner = QueueRunner::new(function, RunOptions::default())
+        .max_attempts(3)
+        .dead_letter(move |message, error| {
+            letters.lock().unwrap().push((
+                message.id.clone(),
+                message.attempts,
+                format!("{:#}", error),
+            ))
+        });
+    (runner, dead)
+}
+
+fn ids(messages: Vec<experimental_runtime::Message>) -> Vec<String> {
+    messages.into_iter().map(|message| message.id).collect()
+}
+
+#[tokio::test]
+async fn successful_runs_are_acked() {
+    let (_fixture, function) = common::module("checked.js", CHECKED);
+    let (runner, dead) = runner(function);
+    let queue = Arc::new(MemoryQueue::new());
+    queue.push(r#"{ "n": 1 }"#);
+    queue.push(r#"{ "n": 2 }"#);
+
+    runner
+        .run(queue.clone(), std::future::pending())
+        .await
+        .unwrap();
+    assert_eq!(ids(queue.acked()), ["0", "1"]);
+    assert!(queue.rejected().is_empty());
+    assert!(dead.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn failing_runs_are_retried_then_dead_lettered() {
+    let (_fixture, function) = common::module("checked.js", CHECKED);
+    let (runner, dead) = runner(function);
+    let queue = Arc::new(MemoryQueue::new());
+    queue.push(r#"{ "n": -1 }"#);
+    queue.push("not json");
+
+    runner
+        .run(queue.clone(), std::future::pending())
+        .await
+        .unwrap();
+    assert!(queue.acked().is_empty());
+    // Undecodable payloads aren't retried.
+    let rejected = queue.rejected();
+    let attempts: Vec<_> = rejected
+        .iter()
+        .map(|m| (m.id.as_str(), m.attempts))
+        .collect();
+    assert_eq!(attempts, [("1", 1), ("0", 3)]);
+    let dead = dead.lock().unwrap();
+    assert_eq!(dead.len(), 2);
+    assert!(dead[0].2.contains("could not decode message"), "{:?}", dead);
+    assert_eq!((dead[1].0.as_str(), dead[1].1), ("0", 3));
+    assert!(dead[1].2.contains("negative"), "{:?}", dead);
+}
+
+#[tokio::test]
+async fn requeued_messages_are_acked_once_a_retry_succeeds() {
+    let fixture = common::Fixture::new();
+    let counter = fixture.file("attempts", "0");
+    let function = fixture.file(
+        "flaky.js",
+        r#"
+export function main({ counter }) {
+  const attempt = Number(Deno.readTextFileSync(counter)) + 1;
+  Deno.writeTextFileSync(counter, String(attempt));
+  if (attempt < 2) throw new Error("flaky");
+  return attempt;
+}
+"#,
+    );
+    let (runner, dead) = runner(function);
+    let queue = Arc::new(MemoryQueue::new());
+    queue.push(serde_json::json!({ "counter": counter }).to_string());
+
+    runner
+        .run(queue.clone(), std::future::pending())
+        .await
+        .unwrap();
+    let acked = queue.acked();
+    assert_eq!(acked.len(), 1);
+    assert_eq!(acked[0].attempts, 2);
+    assert!(queue.rejected().is_empty());
+    assert!(dead.lock().unwrap().is_empty());
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use experimental_runtime::{
+    run_captured, run_with_options, Inputs, RedactOptions, RunOptions, RuntimeError,
+};
+use std::collections::HashMap;
+
+const SECRET: &str = "s3cr3t-t0ken-value";
+
+fn assert_redacted(text: &str, name: &str) {
+    assert!(!text.contains(SECRET), "secret leaked in {:?}", text);
+    assert!(
+        text.contains(&format!("[REDACTED:{}]", name)),
+        "no redaction marker in {:?}",
+        text
+    );
+}
+
+#[test]
+fn secret_inputs_are_redacted_from_console_output() {
+    let (_fixture, function) = common::module(
+        "logs.js",
+        r#"
+export function main(inputs) {
+  console.log("inputs", inputs);
+  console.error(`calling with ${inputs.api_token}`);
+  console.table([{ token: inputs.api_token }]);
+  return inputs.user;
+}
+"#,
+    );
+    let options = RunOptions {
+        redact: RedactOptions {
+            input_patterns: vec!["*TOKEN*".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let inputs = Inputs::new().text("api_token", SECRET).text("user", "ada");
+
+    let output = run_captured(function, inputs, options).unwrap();
+    assert_eq!(output.value, "ada");
+    assert_eq!(output.console.len(), 3);
+    for event in &output.console {
+        assert_redacted(&event.text, "api_token");
+        let structured = serde_json::to_string(&(&event.args, &event.table)).unwrap();
+        assert_redacted(&structured, "api_token");
+    }
+}
+
+#[test]
+fn configured_secrets_are_redacted_from_errors() {
+    let (_fixture, function) = common::module(
+        "throws.js",
+        &format!(
+            r#"
+export function main() {{
+  const password = "{}";
+  throw new Error(`could not connect with ${{password}}`);
+}}
+"#,
+            SECRET
+        ),
+    );
+    let options = RunOptions {
+        redact: RedactOptions {
+            secrets: HashMap::from([("db".to_string(), SECRET.to_string())]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let error = run_with_options(function, Inputs::new(), options).unwrap_err();
+    assert_redacted(&format!("{:#}", error), "db");
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::JsException {
+            message,
+            stack,
+            source_line,
+            ..
+        }) => {
+            assert_redacted(message, "db");
+            assert_redacted(stack.as_deref().unwrap(), "db");
+            if let Some(line) = source_line {
+                assert!(!line.contains(SECRET));
+            }
+        }
+        other => panic!("expected a JS exception, got {:?}", other),
+    }
+}
+
+#[test]
+fn secret_env_variables_are_redacted() {
+    let (_fixture, function) = common::module(
+        "env.js",
+        r#"
+export function main() {
+  console.log("key is", Deno.env.get("SERVICE_KEY"));
+  throw new Error(Deno.env.get("SERVICE_KEY"));
+}
+"#,
+    );
+    let options = RunOptions {
+        env: Some(HashMap::from([(
+            "SERVICE_KEY".to_string(),
+            SECRET.to_string(),
+        )])),
+        redact: RedactOptions {
+            env_patterns: vec!["*_key".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let events = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let sink = events.clone();
+    let options = RunOptions {
+        console: Some(experimental_runtime::ConsoleSink::callback(move |event| {
+            sink.lock().unwrap().push(event)
+        })),
+        ..options
+    };
+    let error = run_with_options(function, Inputs::new(), options).unwrap_err();
+    assert_redacted(&format!("{:#}", error), "SERVICE_KEY");
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_redacted(&events[0].text, "SERVICE_KEY");
+}
+
+#[test]
+fn derived_values_are_not_caught() {
+    // Redaction only replaces exact occurrences, as documented.
+    let (_fixture, function) = common::module(
+        "derived.js",
+        r#"export function main({ api_token }) { throw new Error(api_token.toUpperCase()); }"#,
+    );
+    let options = RunOptions {
+        redact: RedactOptions {
+            input_patterns: vec!["*token*".to_string()],
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let error =
+        run_with_options(function, Inputs::new().text("api_token", SECRET), options).unwrap_err();
+    assert!(format!("{:#}", error).contains(&SECRET.to_uppercase()));
+}
@@ -0,0 +1,143 @@
+//! Drives `experimental_runtime rpc` as a child process over its stdin and
+//! stdout.
+
+mod common;
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+struct Rpc {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Rpc {
+    fn start() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_experimental_runtime"))
+            .arg("rpc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        Self {
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        writeln!(self.stdin, "{}", line).unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut line = String::new();
+        self.stdout.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    fn request(&mut self, id: u64, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        self.send(&request.to_string());
+        let response = self.receive();
+        assert_eq!(response["jsonrpc"], "2.0");
+        assert_eq!(response["id"], id);
+        response
+    }
+}
+
+const COUNTER: &str = r#"
+let calls = 0;
+export function main({ by = 1 }) {
+  if (by < 0) throw new RangeError("negative step");
+  calls += by;
+  return calls;
+}
+"#;
+
+#[test]
+fn load_call_unload_and_shutdown() {
+    let (_fixture, function) = common::module("counter.js", COUNTER);
+    let mut rpc = Rpc::start();
+
+    let loaded = rpc.request(1, "load", json!({ "path": function }));
+    let handle = loaded["result"]["handle"].clone();
+    assert!(handle.is_u64(), "{}", loaded);
+
+    // Calls share the warm module.
+    let first = rpc.request(2, "call", json!({ "handle": handle }));
+    assert_eq!(first["result"], 1);
+    let second = rpc.request(
+        3,
+        "call",
+        json!({ "handle": handle, "inputs": { "by": 2 } }),
+    );
+    assert_eq!(second["result"], 3);
+
+    let failed = rpc.request(
+        4,
+        "call",
+        json!({ "handle": handle, "inputs": { "by": -1 } }),
+    );
+    assert_eq!(failed["error"]["code"], -32000);
+    assert_eq!(failed["error"]["data"]["kind"], "js_exception");
+    let message = failed["error"]["message"].as_str().unwrap();
+    assert!(message.contains("negative step"), "{}", message);
+    // A thrown error leaves the module usable.
+    let third = rpc.request(5, "call", json!({ "handle": handle }));
+    assert_eq!(third["result"], 4);
+
+    assert_eq!(
+        rpc.request(6, "unload", json!({ "handle": handle }))["result"],
+        true
+    );
+    let gone = rpc.request(7, "call", json!({ "handle": handle }));
+    assert_eq!(gone["error"]["code"], -32602);
+
+    assert_eq!(
+        rpc.request(8, "shutdown", Value::Null)["result"],
+        Value::Null
+    );
+    assert!(rpc.child.wait().unwrap().success());
+}
+
+#[test]
+fn protocol_errors() {
+    let mut rpc = Rpc::start();
+
+    rpc.send("{ not json");
+    let response = rpc.receive();
+    assert_eq!(response["id"], Value::Null);
+    assert_eq!(response["error"]["code"], -32700);
+
+    rpc.send(r#"{ "jsonrpc": "1.0", "id": 1, "method": "shutdown" }"#);
+    assert_eq!(rpc.receive()["error"]["code"], -32600);
+
+    let unknown = rpc.request(2, "explode", Value::Null);
+    assert_eq!(unknown["error"]["code"], -32601);
+
+    let invalid = rpc.request(3, "call", json!({ "handle": "one" }));
+    assert_eq!(invalid["error"]["code"], -32602);
+
+    let missing = rpc.request(4, "load", json!({ "path": "/does/not/exist.js" }));
+    assert_eq!(missing["error"]["code"], -32000);
+    let kind = missing["error"]["data"]["kind"].as_str().unwrap();
+    assert!(kind.starts_with("module_"), "{}", missing);
+
+    // Notifications get no response, the next response is for id 5.
+    rpc.send(r#"{ "jsonrpc": "2.0", "method": "unload", "params": { "handle": 1 } }"#);
+    assert_eq!(
+        rpc.request(5, "unload", json!({ "handle": 1 }))["result"],
+        false
+    );
+}
+
+#[test]
+fn end_of_input_stops_the_server() {
+    let mut rpc = Rpc::start();
+    drop(rpc.stdin);
+    assert!(rpc.child.wait().unwrap().success());
+}
@@ -0,0 +1,267 @@
+mod common;
+
+use experimental_runtime::{
+    run_batch, run_function, run_many, run_with_options, BatchOptions, Entrypoint, FunctionRuntime,
+    Inputs, RunOptions, RuntimeError,
+};
+use serde_json::json;
+use std::time::Duration;
+
+/// Counts evaluations of the module and calls since, throws on `throw` and
+/// spins on `spin`.
+const STATEFUL: &str = r#"
+globalThis.evaluations = (globalThis.evaluations ?? 0) + 1;
+let calls = 0;
+export function main({ action }) {
+  calls += 1;
+  if (action === "throw") throw new Error("item failed");
+  if (action === "spin") for (;;) {}
+  return { evaluations: globalThis.evaluations, calls };
+}
+"#;
+
+fn action(action: &str) -> Inputs {
+    Inputs::new().text("action", action)
+}
+
+#[test]
+fn many_inputs_share_one_evaluation() {
+    let (_fixture, function) = common::module("stateful.js", STATEFUL);
+    let results = run_many(
+        function,
+        ["ok", "throw", "ok"].map(action),
+        RunOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(
+        results[0].as_ref().unwrap(),
+        &json!({ "evaluations": 1, "calls": 1 })
+    );
+    let error = results[1].as_ref().unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<RuntimeError>().map(RuntimeError::kind),
+        Some("js_exception")
+    );
+    // A thrown error only fails its item, the module keeps its state.
+    assert_eq!(
+        results[2].as_ref().unwrap(),
+        &json!({ "evaluations": 1, "calls": 3 })
+    );
+}
+
+#[test]
+fn corrupting_failures_reload_the_module() {
+    let (_fixture, function) = common::module("stateful.js", STATEFUL);
+    let options = RunOptions {
+        timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let results = run_many(function, ["ok", "spin", "ok"].map(action), options).unwrap();
+    assert_eq!(
+        results[0].as_ref().unwrap(),
+        &json!({ "evaluations": 1, "calls": 1 })
+    );
+    assert!(matches!(
+        results[1]
+            .as_ref()
+            .unwrap_err()
+            .downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::Timeout { .. })
+    ));
+    // The timed out worker was replaced, the module evaluated anew.
+    assert_eq!(results[2].as_ref().unwrap()["calls"], 1);
+}
+
+#[test]
+fn batch_reports_each_item() {
+    let (_fixture, function) = common::module("stateful.js", STATEFUL);
+    let inputs = ["ok", "throw", "ok"].map(|action| json!({ "action": action }));
+    let report = run_batch(function, inputs, BatchOptions::default()).unwrap();
+    let outcomes = report
+        .items
+        .iter()
+        .map(|item| (item.index, item.result.as_ref().ok().cloned()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        outcomes,
+        [
+            (0, Some(json!({ "evaluations": 1, "calls": 1 }))),
+            (1, None),
+            (2, Some(json!({ "evaluations": 1, "calls": 3 }))),
+        ]
+    );
+    // The exception may have interrupted an update of module state.
+    assert_eq!(report.state_corrupted_at, Some(1));
+    assert!(!report.stopped_early);
+}
+
+#[test]
+fn function_runtime_evaluates_once_for_many_calls() {
+    let (_fixture, function) = common::module("stateful.js", STATEFUL);
+    let mut runtime = FunctionRuntime::new(function, RunOptions::default()).unwrap();
+    for calls in 1..=100 {
+        let value = runtime.call("main", action("ok")).unwrap();
+        assert_eq!(value, json!({ "evaluations": 1, "calls": calls }));
+    }
+
+    // A thrown error keeps the state, a timeout starts over.
+    assert!(runtime.call("main", action("throw")).is_err());
+    let value = runtime.call("main", action("ok")).unwrap();
+    assert_eq!(value, json!({ "evaluations": 1, "calls": 102 }));
+}
+
+#[test]
+fn function_runtime_reloads_after_a_timeout() {
+    let (_fixture, function) = common::module("stateful.js", STATEFUL);
+    let options = RunOptions {
+        timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let mut runtime = FunctionRuntime::new(function, options).unwrap();
+    runtime.call("main", action("ok")).unwrap();
+    let error = runtime.call("main", action("spin")).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<RuntimeError>().map(RuntimeError::kind),
+        Some("timeout")
+    );
+    let value = runtime.call("main", action("ok")).unwrap();
+    assert_eq!(value, json!({ "evaluations": 1, "calls": 1 }));
+}
+
+#[test]
+fn function_runtime_calls_named_exports() {
+    let (_fixture, function) = common::module(
+        "exports.js",
+        r#"
+let total = 0;
+export function add({ n }) { total += n; return total; }
+export function get() { return total; }
+"#,
+    );
+    let mut runtime = FunctionRuntime::new(function, RunOptions::default()).unwrap();
+    runtime
+        .call("add", Inputs::new().json("n", 2.into()))
+        .unwrap();
+    runtime
+        .call("add", Inputs::new().json("n", 3.into()))
+        .unwrap();
+    assert_eq!(runtime.call("get", Inputs::new()).unwrap(), 5);
+
+    let error = runtime.call("missing", Inputs::new()).unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<RuntimeError>().map(RuntimeError::kind),
+        Some("missing_entrypoint")
+    );
+}
+
+/// Computes its exports with top-level await, rejecting when `FAIL` is set.
+const TOP_LEVEL: &str = r#"
+const data = await new Promise((resolve) => setTimeout(() => resolve({ n: 1 }), 10));
+if (globalThis.FAIL) await Promise.reject(new Error("upstream down"));
+export default data;
+export const count = data.n + 1;
+"#;
+
+fn entrypoint(source: &str, entrypoint: Entrypoint) -> Result<serde_json::Value, anyhow::Error> {
+    let (_fixture, function) = common::module("values.js", source);
+    let options = RunOptions {
+        entrypoint,
+        ..Default::default()
+    };
+    run_with_options(function, Inputs::new(), options)
+}
+
+#[test]
+fn export_values_are_taken_once_top_level_await_settled() {
+    let value = entrypoint(TOP_LEVEL, Entrypoint::DefaultExportValue).unwrap();
+    assert_eq!(value, json!({ "n": 1 }));
+    let value = entrypoint(TOP_LEVEL, Entrypoint::ExportValue("count".into())).unwrap();
+    assert_eq!(value, 2);
+}
+
+#[test]
+fn rejected_top_level_awaits_fail_export_value_runs() {
+    let source = format!("globalThis.FAIL = true;\n{}", TOP_LEVEL);
+    let error = entrypoint(&source, Entrypoint::DefaultExportValue).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::ModuleEvaluation { message, .. }) => {
+            assert!(message.contains("upstream down"), "{}", message)
+        }
+        _ => panic!("{:#}", error),
+    }
+
+    let error = entrypoint(TOP_LEVEL, Entrypoint::ExportValue("missing".into())).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::MissingEntrypoint { export, available }) => {
+            assert_eq!(export, "missing");
+            assert_eq!(available, &["count", "default"]);
+        }
+        _ => panic!("{:#}", error),
+    }
+}
+
+const HANDLERS: &str = r#"
+export function onCreate(id, record) { return { created: id, ...record }; }
+export const limit = 10;
+export default (a, b) => a + b;
+"#;
+
+#[test]
+fn named_exports_are_called_with_positional_arguments() {
+    let (_fixture, function) = common::module("handlers.js", HANDLERS);
+    let value = run_function(
+        function.clone(),
+        "onCreate",
+        vec![json!(7), json!({ "name": "x" })],
+    )
+    .unwrap();
+    assert_eq!(value, json!({ "created": 7, "name": "x" }));
+    let value = run_function(function, "default", vec![json!(2), json!(3)]).unwrap();
+    assert_eq!(value, 5);
+}
+
+#[test]
+fn missing_or_uncallable_exports_list_the_exports() {
+    let (_fixture, function) = common::module("handlers.js", HANDLERS);
+    for export in ["onDelete", "limit"] {
+        let error = run_function(function.clone(), export, vec![]).unwrap_err();
+        match error.downcast_ref::<RuntimeError>() {
+            Some(RuntimeError::MissingEntrypoint {
+                export: missing,
+                available,
+            }) => {
+                assert_eq!(missing, export);
+                assert_eq!(available, &["default", "limit", "onCreate"]);
+            }
+            _ => panic!("{:#}", error),
+        }
+    }
+}
+
+#[test]
+fn default_exports_are_called_without_main() {
+    let cases = [
+        "export default async ({ n }) => n + 1;",
+        "export default class { step = 1; handler({ n }) { return n + this.step; } }",
+        "export default { step: 1, handler({ n }) { return n + this.step; } };",
+        "export const main = ({ n }) => n + 1;\nexport default () => \"not called\";",
+    ];
+    for source in cases {
+        let (_fixture, function) = common::module("default.js", source);
+        let inputs = Inputs::new().json("n", json!(1));
+        let value = run_with_options(function, inputs, RunOptions::default()).unwrap();
+        assert_eq!(value, 2, "{}", source);
+    }
+}
+
+#[test]
+fn default_exports_without_a_handler_are_refused() {
+    let error = entrypoint("export default { step: 1 };", Entrypoint::MainFunction).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::MissingEntrypoint { export, available }) => {
+            assert_eq!(export, "default");
+            assert_eq!(available, &["default"]);
+        }
+        _ => panic!("{:#}", error),
+    }
+}
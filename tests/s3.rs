@@ -0,0 +1,120 @@
+#![cfg(feature = "s3")]
+
+mod common;
+
+use deno_core::ModuleSpecifier;
+use experimental_runtime::{
+    run_with_options, Inputs, RunOptions, RuntimeError, S3Error, S3Object, S3Resolver,
+};
+use std::path::PathBuf;
+
+const MAIN: &str = r#"
+import { version } from "./lib/version.js";
+export function main() { return version; }
+"#;
+
+/// Resolves objects to path-style URLs on `base` with a fake signature, as
+/// a pre-signing resolver would.
+fn signing(base: String) -> S3Resolver {
+    S3Resolver::new(move |object: &S3Object| {
+        let url = format!("{}/{}/{}?signature=SECRET", base, object.bucket, object.key);
+        Ok(ModuleSpecifier::parse(&url)?)
+    })
+}
+
+fn run(resolver: S3Resolver, entry: &str) -> Result<serde_json::Value, anyhow::Error> {
+    let options = RunOptions {
+        s3: Some(resolver),
+        ..Default::default()
+    };
+    run_with_options(PathBuf::from(entry), Inputs::new(), options)
+}
+
+fn s3_error(error: &anyhow::Error) -> &S3Error {
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::ModuleLoad { cause, .. }) => cause.downcast_ref().unwrap(),
+        _ => panic!("{:#}", error),
+    }
+}
+
+#[test]
+fn relative_imports_stay_under_the_prefix() {
+    let server = common::Server::start();
+    for version in ["v1", "v2"] {
+        server.route(
+            &format!("/functions/{}/main.js?signature=SECRET", version),
+            common::Response::ok("application/javascript", MAIN),
+        );
+        server.route(
+            &format!("/functions/{}/lib/version.js?signature=SECRET", version),
+            common::Response::ok(
+                "application/javascript",
+                format!("export const version = {:?};", version),
+            ),
+        );
+    }
+    let resolver = signing(server.url(""));
+    assert_eq!(
+        run(resolver.clone(), "s3://functions/v1/main.js").unwrap(),
+        "v1"
+    );
+    assert_eq!(run(resolver, "s3://functions/v2/main.js").unwrap(), "v2");
+}
+
+#[test]
+fn public_buckets_use_path_style_urls() {
+    let server = common::Server::start();
+    server.route(
+        "/store/functions/main.js",
+        common::Response::ok("application/javascript", "export const main = () => 1;"),
+    );
+    let endpoint = ModuleSpecifier::parse(&server.url("/store/")).unwrap();
+    let value = run(S3Resolver::public(endpoint), "s3://functions/main.js").unwrap();
+    assert_eq!(value, 1);
+}
+
+#[test]
+fn failures_name_the_object_but_not_the_signed_url() {
+    let server = common::Server::start();
+    server.route(
+        "/functions/private.js?signature=SECRET",
+        common::Response::status(403),
+    );
+    let resolver = signing(server.url(""));
+
+    let error = run(resolver.clone(), "s3://functions/missing.js").unwrap_err();
+    match s3_error(&error) {
+        S3Error::NotFound { bucket, key } => {
+            assert_eq!((bucket.as_str(), key.as_str()), ("functions", "missing.js"))
+        }
+        other => panic!("{}", other),
+    }
+    assert!(!format!("{:#}", error).contains("SECRET"), "{:#}", error);
+
+    let error = run(resolver, "s3://functions/private.js").unwrap_err();
+    assert!(matches!(s3_error(&error), S3Error::AccessDenied { key, .. } if key == "private.js"));
+    assert!(!format!("{:#}", error).contains("SECRET"), "{:#}", error);
+
+    // Nothing listens on a port that was just released.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let error = run(signing(closed), "s3://functions/main.js").unwrap_err();
+    assert!(matches!(s3_error(&error), S3Error::Unreachable { key, .. } if key == "main.js"));
+    assert!(!format!("{:#}", error).contains("SECRET"), "{:#}", error);
+}
+
+#[test]
+fn s3_imports_need_a_resolver() {
+    let error = run_with_options(
+        PathBuf::from("s3://functions/main.js"),
+        Inputs::new(),
+        RunOptions::default(),
+    )
+    .unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("no s3 resolver was configured"),
+        "{:#}",
+        error
+    );
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use experimental_runtime::{run_with_options, FsSandbox, Inputs, RunOptions};
+use serde_json::json;
+
+const FILES: &str = r#"
+export async function main({ outside }) {
+  const config = JSON.parse(await Deno.readTextFile("/config.json"));
+  await Deno.mkdir("/out");
+  Deno.chdir("/out");
+  await Deno.writeTextFile("result.txt", `hello ${config.name}`);
+  const escaped = await Deno.readTextFile("../../../config.json");
+  let reachable = true;
+  try {
+    await Deno.readTextFile(outside);
+  } catch (e) {
+    reachable = !(e instanceof Deno.errors.NotFound);
+  }
+  const names = [];
+  for await (const entry of Deno.readDir("/")) names.push(entry.name);
+  return { cwd: Deno.cwd(), escaped: escaped === JSON.stringify(config), reachable, names: names.sort() };
+}
+"#;
+
+#[test]
+fn file_apis_are_confined_to_the_sandbox() {
+    let (fixture, function) = common::module("files.js", FILES);
+    let outside = fixture.file("secret.txt", "not for the script");
+    let root = fixture.path().join("root");
+    let options = RunOptions {
+        fs_sandbox: Some(FsSandbox::in_dir(&root).file("config.json", r#"{"name":"sandbox"}"#)),
+        ..Default::default()
+    };
+    let inputs = Inputs::new().json("outside", json!(outside));
+    let value = run_with_options(function, inputs, options).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "cwd": "/out",
+            "escaped": true,
+            "reachable": false,
+            "names": ["config.json", "out", "tmp"],
+        })
+    );
+    // The root given is kept, with what the script wrote.
+    assert_eq!(
+        std::fs::read_to_string(root.join("out/result.txt")).unwrap(),
+        "hello sandbox"
+    );
+}
+
+#[test]
+fn file_fetches_are_refused_in_a_sandbox() {
+    let (fixture, function) = common::module(
+        "fetch.js",
+        r#"
+export async function main({ path }) {
+  try {
+    await fetch(`file://${path}`);
+    return "fetched";
+  } catch (e) {
+    return { denied: e instanceof Deno.errors.PermissionDenied, message: e.message };
+  }
+}
+"#,
+    );
+    let path = fixture.file("secret.txt", "not for the script");
+    let options = RunOptions {
+        fs_sandbox: Some(FsSandbox::temporary()),
+        ..Default::default()
+    };
+    let inputs = Inputs::new().json("path", json!(path));
+    let value = run_with_options(function, inputs, options).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "denied": true,
+            "message": "file: URLs can't be fetched in a filesystem sandbox",
+        })
+    );
+}
@@ -0,0 +1,110 @@
+#![cfg(feature = "serve")]
+
+mod common;
+
+use experimental_runtime::{InvokeServer, RunOptions};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+const FUNCTION: &str = r#"
+export async function main({ wait, fail, ...rest }) {
+  if (wait) await new Promise((resolve) => setTimeout(resolve, wait));
+  if (fail) throw new Error("asked to fail");
+  return rest;
+}
+"#;
+
+/// Status and JSON body of a request to the server at `address`.
+async fn request(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nhost: {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        method,
+        path,
+        address,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+/// Serves `FUNCTION` as `echo` until the returned sender is used or dropped.
+async fn start() -> (
+    SocketAddr,
+    oneshot::Sender<()>,
+    tokio::task::JoinHandle<()>,
+    common::Fixture,
+) {
+    let (fixture, function) = common::module("echo.js", FUNCTION);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = InvokeServer::new(RunOptions::default())
+        .route("echo", function)
+        .concurrency(2);
+    let serving = tokio::spawn(async move {
+        server
+            .serve(listener, async {
+                let _ = stopped.await;
+            })
+            .await
+            .unwrap()
+    });
+    (address, stop, serving, fixture)
+}
+
+#[tokio::test]
+async fn routes_run_with_the_body_as_inputs() {
+    let (address, _stop, _serving, _fixture) = start().await;
+    let (status, body) = request(address, "POST", "/invoke/echo", r#"{ "a": 1 }"#).await;
+    assert_eq!((status, body), (200, json!({ "a": 1 })));
+    let (status, body) = request(address, "POST", "/invoke/echo", "").await;
+    assert_eq!((status, body), (200, json!({})));
+}
+
+#[tokio::test]
+async fn failures_respond_with_a_kind_and_status() {
+    let (address, _stop, _serving, _fixture) = start().await;
+    let cases = [
+        ("POST", "/invoke/missing", "{}", 404, "not_found"),
+        ("GET", "/invoke/echo", "", 405, "method_not_allowed"),
+        ("POST", "/invoke/echo", "[1]", 400, "invalid_body"),
+        (
+            "POST",
+            "/invoke/echo",
+            r#"{ "fail": true }"#,
+            500,
+            "js_exception",
+        ),
+    ];
+    for (method, path, body, expected, kind) in cases {
+        let (status, body) = request(address, method, path, body).await;
+        assert_eq!(status, expected, "{} {}: {}", method, path, body);
+        assert_eq!(body["error"]["kind"], kind, "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn shutdown_answers_requests_in_flight() {
+    let (address, stop, serving, _fixture) = start().await;
+    let slow = tokio::spawn(request(
+        address,
+        "POST",
+        "/invoke/echo",
+        r#"{ "wait": 300, "b": 2 }"#,
+    ));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    stop.send(()).unwrap();
+
+    assert_eq!(slow.await.unwrap(), (200, json!({ "b": 2 })));
+    serving.await.unwrap();
+    assert!(TcpStream::connect(address).await.is_err());
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "typescript")]
+
+mod common;
+
+use experimental_runtime::inspect_signature;
+use serde_json::json;
+
+#[test]
+fn annotated_parameters_and_return_types_are_extracted() {
+    let (_fixture, function) = common::module(
+        "main.ts",
+        r#"
+export async function main(
+  { name, count }: { name: string; count?: number; mode: "a" | "b" },
+  tags: string[] = [],
+): Promise<string> {
+  return name.repeat(count ?? 1) + tags.join();
+}
+"#,
+    );
+    let info = inspect_signature(&function).unwrap().unwrap();
+    assert!(info.is_async);
+    assert_eq!(info.return_type.as_deref(), Some("Promise<string>"));
+
+    let [options, tags] = &info.params[..] else {
+        panic!("{:?}", info.params);
+    };
+    assert_eq!(options.name, "{ name, count }");
+    assert!(!options.optional);
+    assert_eq!(
+        options.structure,
+        Some(json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string" },
+                "count": { "type": "number" },
+                "mode": { "enum": ["a", "b"] },
+            },
+            "required": ["name", "mode"],
+        }))
+    );
+    assert_eq!(tags.name, "tags");
+    assert_eq!(tags.type_annotation.as_deref(), Some("string[]"));
+    assert!(tags.optional);
+}
+
+#[test]
+fn re_exported_entrypoints_are_followed_one_hop() {
+    let fixture = common::Fixture::new();
+    let implementation = fixture.file(
+        "impl.ts",
+        "export const run = (id: number): boolean => id > 0;",
+    );
+    let function = fixture.file("main.ts", "export { run as main } from \"./impl.ts\";");
+    let info = inspect_signature(&function).unwrap().unwrap();
+    assert_eq!(info.specifier.to_file_path().unwrap(), implementation);
+    assert_eq!(info.return_type.as_deref(), Some("boolean"));
+    assert_eq!(info.params[0].type_annotation.as_deref(), Some("number"));
+
+    // Not two.
+    let function = fixture.file("outer.ts", "export { main } from \"./main.ts\";");
+    assert!(inspect_signature(&function).unwrap().is_none());
+}
+
+#[test]
+fn unannotated_functions_give_partial_info() {
+    let (_fixture, function) = common::module("main.js", "export function main(a, b = 1) {}");
+    let info = inspect_signature(&function).unwrap().unwrap();
+    assert!(!info.is_async);
+    assert_eq!(info.return_type, None);
+    let params: Vec<_> = info
+        .params
+        .iter()
+        .map(|p| (p.name.as_str(), p.type_annotation.is_none(), p.optional))
+        .collect();
+    assert_eq!(params, [("a", true, false), ("b", true, true)]);
+}
+
+#[test]
+fn missing_entrypoints_and_unparsable_modules() {
+    let fixture = common::Fixture::new();
+    let function = fixture.file("main.ts", "export const value = 1;");
+    assert!(inspect_signature(&function).unwrap().is_none());
+
+    let function = fixture.file("broken.ts", "export function main( {");
+    assert!(inspect_signature(&function).is_err());
+    assert!(inspect_signature(&fixture.path().join("absent.ts")).is_err());
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use experimental_runtime::{
+    run_with_options, ExecutorPool, Inputs, RunOptions, RuntimeError, RuntimePermissions, Snapshot,
+};
+use std::sync::OnceLock;
+
+/// Built once, snapshots take a few hundred milliseconds.
+fn snapshot() -> Snapshot {
+    static SNAPSHOT: OnceLock<Snapshot> = OnceLock::new();
+    *SNAPSHOT.get_or_init(|| Snapshot::build().unwrap())
+}
+
+/// The JWT example's signing, without its remote import.
+const JWT: &str = r#"
+const base64url = (bytes) =>
+  btoa(String.fromCharCode(...new Uint8Array(bytes)))
+    .replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+
+export async function main({ secret_key, payload }) {
+  const encoder = new TextEncoder();
+  const key = await crypto.subtle.importKey(
+    "raw",
+    encoder.encode(secret_key),
+    { name: "HMAC", hash: "SHA-256" },
+    false,
+    ["sign"],
+  );
+  const header = base64url(encoder.encode(JSON.stringify({ alg: "HS256", typ: "JWT" })));
+  const body = base64url(encoder.encode(payload));
+  const signature = await crypto.subtle.sign("HMAC", key, encoder.encode(`${header}.${body}`));
+  return `${header}.${body}.${base64url(signature)}`;
+}
+"#;
+
+const GLOBALS: &str = r#"
+export function main() {
+  return {
+    globals: Object.getOwnPropertyNames(globalThis).sort(),
+    deno: Object.keys(Deno).sort(),
+  };
+}
+"#;
+
+fn run(source: &str, inputs: Inputs, options: RunOptions) -> anyhow::Result<serde_json::Value> {
+    let (_fixture, function) = common::module("main.js", source);
+    run_with_options(function, inputs, options)
+}
+
+fn with_snapshot(options: RunOptions) -> RunOptions {
+    RunOptions {
+        snapshot: Some(snapshot()),
+        ..options
+    }
+}
+
+#[test]
+fn snapshotted_workers_sign_like_fresh_ones() {
+    let inputs = || {
+        Inputs::new()
+            .text("secret_key", "my secret")
+            .text("payload", r#"{"sub":"1234567890"}"#)
+    };
+    let fresh = run(JWT, inputs(), RunOptions::default()).unwrap();
+    let snapshotted = run(JWT, inputs(), with_snapshot(RunOptions::default())).unwrap();
+    assert_eq!(fresh, snapshotted);
+    assert_eq!(fresh.as_str().unwrap().split('.').count(), 3);
+}
+
+#[test]
+fn snapshotted_workers_have_the_same_globals() {
+    let fresh = run(GLOBALS, Inputs::new(), RunOptions::default()).unwrap();
+    let snapshotted = run(GLOBALS, Inputs::new(), with_snapshot(RunOptions::default())).unwrap();
+    assert_eq!(fresh, snapshotted);
+}
+
+#[test]
+fn permissions_apply_to_snapshotted_workers() {
+    let options = with_snapshot(RunOptions {
+        permissions: Some(RuntimePermissions::none()),
+        ..Default::default()
+    });
+    let error = run(
+        "export const main = () => Deno.readTextFileSync('/etc/hostname');",
+        Inputs::new(),
+        options,
+    )
+    .unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::PermissionDenied { .. })
+        ),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn warmup_globals_are_in_every_worker() {
+    let snapshot = Snapshot::build_with_warmup("globalThis.preloaded = { answer: 42 };").unwrap();
+    let options = RunOptions {
+        snapshot: Some(snapshot),
+        ..Default::default()
+    };
+    let value = run(
+        "export const main = () => globalThis.preloaded.answer;",
+        Inputs::new(),
+        options,
+    )
+    .unwrap();
+    assert_eq!(value, 42);
+}
+
+#[test]
+fn saved_snapshots_load_again() {
+    let fixture = common::Fixture::new();
+    let path = fixture.path().join("runtime.snap");
+    snapshot().save(&path).unwrap();
+    let options = RunOptions {
+        snapshot: Some(Snapshot::load(&path).unwrap()),
+        ..Default::default()
+    };
+    assert_eq!(
+        run("export const main = () => 1;", Inputs::new(), options).unwrap(),
+        1
+    );
+    assert!(Snapshot::load(&fixture.path().join("missing.snap")).is_err());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn saved_snapshots_are_compressed() {
+    let fixture = common::Fixture::new();
+    let path = fixture.path().join("runtime.snap");
+    snapshot().save(&path).unwrap();
+    let saved = std::fs::read(&path).unwrap();
+    assert!(
+        saved.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]),
+        "not a zstd frame"
+    );
+    let options = RunOptions {
+        snapshot: Some(Snapshot::load(&path).unwrap()),
+        ..Default::default()
+    };
+    assert_eq!(
+        run("export const main = () => 1;", Inputs::new(), options).unwrap(),
+        1
+    );
+}
+
+#[test]
+fn pools_share_a_snapshot() {
+    let (_fixture, function) = common::module("main.js", JWT);
+    let pool = ExecutorPool::with_options(2, with_snapshot(RunOptions::default())).unwrap();
+    let tokens: Vec<_> = (0..4)
+        .map(|i| {
+            let inputs = Inputs::new()
+                .text("secret_key", "key")
+                .text("payload", format!(r#"{{"n":{}}}"#, i));
+            pool.submit(function.clone(), inputs)
+        })
+        .collect();
+    for token in tokens {
+        let token = futures::executor::block_on(token).unwrap();
+        assert_eq!(token.as_str().unwrap().split('.').count(), 3);
+    }
+}
@@ -0,0 +1,78 @@
+use base64::Engine;
+use experimental_runtime::{run_source, Inputs, RunOptions, SourceKind};
+use serde_json::json;
+
+fn base64(code: &str) -> String {
+    base64::engine::general_purpose::STANDARD.encode(code)
+}
+
+#[test]
+fn inline_sources_run_without_files() {
+    let value = run_source(
+        "export const main = ({ n }) => n * 2;",
+        SourceKind::JavaScript,
+        Inputs::new().json("n", json!(21)),
+        RunOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(value, 42);
+
+    // Percent-encoded data: URL modules are importable too.
+    let value = run_source(
+        "import { x } from \"data:text/javascript,export%20const%20x%20%3D%201%3B\";\nexport const main = () => x;",
+        SourceKind::JavaScript,
+        Inputs::new(),
+        RunOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(value, 1);
+}
+
+#[cfg(feature = "typescript")]
+#[test]
+fn typescript_data_urls_are_transpiled() {
+    let library = base64("export const double = (n: number): number => n * 2;");
+    let code = format!(
+        "import {{ double }} from \"data:application/typescript;base64,{}\";\nexport const main = (): number => double(4);",
+        library
+    );
+    let value = run_source(
+        &code,
+        SourceKind::TypeScript,
+        Inputs::new(),
+        RunOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(value, 8);
+}
+
+#[test]
+fn relative_imports_and_bad_payloads_in_data_urls_fail() {
+    let run = |library: String| {
+        let code = format!(
+            "import {:?};\nexport const main = () => 1;",
+            format!("data:text/javascript;base64,{}", library)
+        );
+        let error = run_source(
+            &code,
+            SourceKind::JavaScript,
+            Inputs::new(),
+            RunOptions::default(),
+        )
+        .unwrap_err();
+        format!("{:#}", error)
+    };
+
+    let message = run(base64("import \"./sibling.js\";"));
+    assert!(
+        message.contains("./sibling.js can't be imported from a data: URL module"),
+        "{}",
+        message
+    );
+    let message = run("not*base64".to_string());
+    assert!(
+        message.contains("invalid base64 in data: URL"),
+        "{}",
+        message
+    );
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use experimental_runtime::{
+    run_with_stats, Inputs, Invocation, MetricsSink, RunOptions, RuntimeError,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const WORK: &str = r#"
+const table = Array.from({ length: 10000 }, (_, i) => ({ i, label: `row ${i}` }));
+await new Promise((resolve) => setTimeout(resolve, 5));
+export async function main() {
+  await new Promise((resolve) => setTimeout(resolve, 5));
+  return table.length;
+}
+"#;
+
+#[test]
+fn phases_and_heap_are_measured() {
+    let (_fixture, function) = common::module("work.js", WORK);
+    let (result, stats) = run_with_stats(function, Inputs::new(), RunOptions::default());
+    assert_eq!(result.unwrap(), 10000);
+    assert!(stats.load > Duration::ZERO, "{:?}", stats);
+    assert!(stats.evaluate > Duration::ZERO, "{:?}", stats);
+    assert!(
+        stats.call > Duration::ZERO || stats.event_loop > Duration::ZERO,
+        "{:?}",
+        stats
+    );
+    assert!(stats.used_heap_size > 0, "{:?}", stats);
+    assert!(stats.total_heap_size >= stats.used_heap_size, "{:?}", stats);
+    assert!(stats.peak_heap_size >= stats.used_heap_size, "{:?}", stats);
+    assert_eq!(stats.result_bytes, "10000".len());
+    assert_eq!(stats.remote_modules, 0);
+    assert!(!stats.transpiled);
+}
+
+#[test]
+fn stats_cover_failed_runs_as_far_as_they_got() {
+    let (_fixture, function) = common::module(
+        "throws.js",
+        "export function main() { throw new Error(\"late\"); }",
+    );
+    let (result, stats) = run_with_stats(function, Inputs::new(), RunOptions::default());
+    assert!(result.is_err());
+    assert!(stats.load > Duration::ZERO, "{:?}", stats);
+    assert_eq!(stats.result_bytes, 0);
+}
+
+#[cfg(feature = "typescript")]
+#[test]
+fn transpiling_is_reported() {
+    let (_fixture, function) = common::module("typed.ts", "export const main = (): number => 1;");
+    let (result, stats) = run_with_stats(function, Inputs::new(), RunOptions::default());
+    assert_eq!(result.unwrap(), 1);
+    assert!(stats.transpiled);
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn remote_fetches_are_counted() {
+    let server = common::Server::start();
+    let one = "export const one = 1;";
+    let two = "export const two = 2;";
+    server.route(
+        "/one.js",
+        common::Response::ok("application/javascript", one),
+    );
+    server.route(
+        "/two.js",
+        common::Response::ok("application/javascript", two),
+    );
+    let (_fixture, function) = common::module(
+        "main.js",
+        &format!(
+            "import {{ one }} from {:?};\nimport {{ two }} from {:?};\nexport const main = () => one + two;",
+            server.url("/one.js"),
+            server.url("/two.js")
+        ),
+    );
+    let (result, stats) = run_with_stats(function, Inputs::new(), RunOptions::default());
+    assert_eq!(result.unwrap(), 3);
+    assert_eq!(stats.remote_modules, 2);
+    assert_eq!(stats.remote_bytes, (one.len() + two.len()) as u64);
+}
+
+struct Recorder(Mutex<Vec<Invocation>>);
+
+impl MetricsSink for Recorder {
+    fn invocation(&self, invocation: &Invocation) {
+        self.0.lock().unwrap().push(invocation.clone());
+    }
+}
+
+#[test]
+fn metrics_sinks_see_every_invocation() {
+    let fixture = common::Fixture::new();
+    let ok = fixture.file("ok.js", "export const main = () => 1;");
+    let spin = fixture.file("spin.js", "export function main() { for (;;) {} }");
+    let recorder = Arc::new(Recorder(Mutex::new(vec![])));
+    let options = RunOptions {
+        metrics: Some(recorder.clone()),
+        timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    run_with_stats(ok.clone(), Inputs::new(), options.clone())
+        .0
+        .unwrap();
+    let error = run_with_stats(spin.clone(), Inputs::new(), options)
+        .0
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::Timeout { .. })
+    ));
+
+    let invocations = recorder.0.lock().unwrap();
+    assert_eq!(invocations.len(), 2);
+    assert_eq!(invocations[0].function, ok);
+    assert_eq!(invocations[0].error, None);
+    assert_eq!(invocations[1].function, spin);
+    assert_eq!(invocations[1].error, Some("timeout"));
+    assert!(invocations[1].elapsed >= Duration::from_millis(200));
+}
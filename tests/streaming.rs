@@ -0,0 +1,100 @@
+mod common;
+
+use experimental_runtime::{run_stream, Inputs, RunOptions, RuntimeError};
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+const STREAMS: &str = r#"
+export function main({ kind, marker }) {
+  switch (kind) {
+    case "generator":
+      return (async function* () {
+        for (let n = 1; n <= 3; n++) {
+          await new Promise((resolve) => setTimeout(resolve, 1));
+          yield { n };
+        }
+      })();
+    case "readable":
+      return new ReadableStream({
+        start(controller) {
+          controller.enqueue("a");
+          controller.enqueue("b");
+          controller.close();
+        },
+      });
+    case "throws":
+      return (async function* () {
+        yield 1;
+        throw new Error("stream broke");
+      })();
+    case "endless":
+      return (async function* () {
+        try {
+          for (let n = 0; ; n++) yield n;
+        } finally {
+          Deno.writeTextFileSync(marker, "closed");
+        }
+      })();
+    default:
+      return "single";
+  }
+}
+"#;
+
+async fn collect(kind: &str) -> Vec<Result<Value, anyhow::Error>> {
+    let (_fixture, function) = common::module("streams.js", STREAMS);
+    let inputs = Inputs::new().text("kind", kind);
+    run_stream(function, inputs, RunOptions::default())
+        .collect()
+        .await
+}
+
+fn values(items: Vec<Result<Value, anyhow::Error>>) -> Vec<Value> {
+    items.into_iter().map(Result::unwrap).collect()
+}
+
+#[tokio::test]
+async fn yielded_values_are_streamed_in_order() {
+    assert_eq!(
+        values(collect("generator").await),
+        [json!({ "n": 1 }), json!({ "n": 2 }), json!({ "n": 3 })]
+    );
+    assert_eq!(values(collect("readable").await), [json!("a"), json!("b")]);
+    // Anything else is a single item.
+    assert_eq!(values(collect("value").await), [json!("single")]);
+}
+
+#[tokio::test]
+async fn errors_end_the_stream() {
+    let mut items = collect("throws").await.into_iter();
+    assert_eq!(items.next().unwrap().unwrap(), 1);
+    let error = items.next().unwrap().unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(RuntimeError::JsException { message, .. }) => {
+            assert!(message.contains("stream broke"), "{}", message)
+        }
+        _ => panic!("{:#}", error),
+    }
+    assert!(items.next().is_none());
+}
+
+#[tokio::test]
+async fn dropping_the_stream_returns_the_iterator() {
+    let fixture = common::Fixture::new();
+    let function = fixture.file("streams.js", STREAMS);
+    let marker = fixture.path().join("closed");
+    let inputs = Inputs::new()
+        .text("kind", "endless")
+        .text("marker", marker.to_str().unwrap());
+    let mut stream = Box::pin(run_stream(function, inputs, RunOptions::default()));
+    assert_eq!(stream.next().await.unwrap().unwrap(), 0);
+    drop(stream);
+
+    for _ in 0..100 {
+        if marker.exists() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    panic!("the iterator was not returned");
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use experimental_runtime::{
+    run, run_to_writer, run_with_options, Inputs, RunOptions, RuntimeError, Subprocess,
+};
+use serde_json::{json, Value};
+
+/// Returns the pid of the process it runs in, or takes it down.
+const PID: &str = "export function main({ exit }) { if (exit) Deno.exit(70); return Deno.pid; }";
+
+/// Children running the cli, which serves invocations when started as one.
+fn options() -> RunOptions {
+    let cli = env!("CARGO_BIN_EXE_experimental_runtime");
+    RunOptions {
+        subprocess: Some(Subprocess::new(cli, ["run", "unused.js"])),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn crashed_children_fail_the_run_and_are_replaced() {
+    let (_fixture, function) = common::module("pid.js", PID);
+    let options = options();
+
+    let child = run_with_options(function.clone(), Inputs::new(), options.clone()).unwrap();
+    assert_ne!(child, json!(std::process::id()));
+    // Idle children are reused.
+    let again = run_with_options(function.clone(), Inputs::new(), options.clone()).unwrap();
+    assert_eq!(again, child);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let inputs = Inputs::new().json("exit", json!(true));
+    let error = runtime
+        .block_on(run(function.clone(), inputs, options.clone()))
+        .unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::WorkerCrashed { .. })
+        ),
+        "{:#}",
+        error
+    );
+
+    // The next run goes to a fresh child, through run_to_writer too.
+    let mut output = vec![];
+    run_to_writer(function, Inputs::new(), options, &mut output).unwrap();
+    let respawned: Value = serde_json::from_slice(&output).unwrap();
+    assert!(respawned.is_u64(), "{}", respawned);
+    assert_ne!(respawned, child);
+}
+
+#[test]
+fn streamed_results_from_children_keep_the_output_limit() {
+    let (_fixture, function) = common::module("pid.js", PID);
+    let options = RunOptions {
+        max_output_bytes: Some(2),
+        ..options()
+    };
+    let error = run_to_writer(function, Inputs::new(), options, vec![]).unwrap_err();
+    assert!(
+        matches!(
+            error.downcast_ref::<RuntimeError>(),
+            Some(RuntimeError::OutputTooLarge { limit: 2 })
+        ),
+        "{:#}",
+        error
+    );
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use experimental_runtime::{run_with_options, Inputs, RunOptions, Trace, TraceEntry, TraceMode};
+use serde_json::json;
+
+fn replaying(trace: Trace) -> RunOptions {
+    RunOptions {
+        trace: Some(TraceMode::Replay(trace)),
+        ..Default::default()
+    }
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn recorded_runs_replay_without_the_network_or_host() {
+    use experimental_runtime::{HostApiBuilder, TraceRecorder};
+
+    let server = common::Server::start();
+    server.route("/greeting", common::Response::ok("text/plain", "hello"));
+    let (fixture, function) = common::module(
+        "main.js",
+        r#"
+export async function main({ url }) {
+  const response = await fetch(url);
+  return { status: response.status, text: await response.text(), sum: host.api.add(2, 3) };
+}
+"#,
+    );
+    let path = fixture.path().join("trace.jsonl");
+    let options = RunOptions {
+        host_api: Some(
+            HostApiBuilder::new()
+                .method("add", |(a, b): (f64, f64)| Ok(a + b))
+                .build(),
+        ),
+        trace: Some(TraceMode::Record(TraceRecorder::create(&path).unwrap())),
+        ..Default::default()
+    };
+    let inputs = || Inputs::new().text("url", server.url("/greeting"));
+    let recorded = run_with_options(function.clone(), inputs(), options).unwrap();
+    assert_eq!(
+        recorded,
+        json!({ "status": 200, "text": "hello", "sum": 5.0 })
+    );
+
+    let entries: Vec<TraceEntry> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let [TraceEntry::Fetch { url, response, .. }, TraceEntry::HostCall { method, result, .. }] =
+        &entries[..]
+    else {
+        panic!("{:?}", entries);
+    };
+    assert_eq!(url, &server.url("/greeting"));
+    assert_eq!(response.as_ref().unwrap().body, "aGVsbG8=");
+    assert_eq!((method.as_str(), result), ("add", &Some(json!(5.0))));
+
+    // Neither the server nor the host API is asked again.
+    let replayed =
+        run_with_options(function, inputs(), replaying(Trace::open(&path).unwrap())).unwrap();
+    assert_eq!(replayed, recorded);
+    assert_eq!(server.hits("/greeting"), 1);
+}
+
+#[test]
+fn replays_serve_calls_in_recorded_order() {
+    let call = |result| TraceEntry::HostCall {
+        method: "next".into(),
+        is_async: true,
+        args: vec![],
+        result: Some(json!(result)),
+        error: None,
+    };
+    let trace = Trace::from_entries([call(1), call(2)]);
+    let (_fixture, function) = common::module(
+        "main.js",
+        r#"
+export async function main() {
+  const calls = [await host.api.next(), await host.api.next(), await host.api.next()];
+  try {
+    await fetch("https://example.com/");
+  } catch (e) {
+    return { calls, fetch: e.message };
+  }
+}
+"#,
+    );
+    let value = run_with_options(function, Inputs::new(), replaying(trace)).unwrap();
+    // The last entry is served again once they run out.
+    assert_eq!(value["calls"], json!([1, 2, 2]));
+    assert!(
+        value["fetch"]
+            .as_str()
+            .unwrap()
+            .contains("no recorded response for GET https://example.com/"),
+        "{}",
+        value
+    );
+}
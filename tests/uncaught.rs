@@ -0,0 +1,88 @@
+mod common;
+
+use experimental_runtime::{FunctionRuntime, Inputs, RunOptions, UncaughtEvent, UncaughtHook};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const LATE_THROW: &str = r#"
+let calls = 0;
+export function main() {
+  calls += 1;
+  if (calls === 1) {
+    setTimeout(() => { throw new Error("late failure"); }, 1);
+  }
+  return calls;
+}
+"#;
+
+fn recording() -> (UncaughtHook, Arc<Mutex<Vec<UncaughtEvent>>>) {
+    let events = Arc::new(Mutex::new(vec![]));
+    let recorded = events.clone();
+    let hook = UncaughtHook::new(move |event| recorded.lock().unwrap().push(event));
+    (hook, events)
+}
+
+#[test]
+fn timer_exceptions_between_calls_reach_the_hook() {
+    let (_fixture, function) = common::module("late.js", LATE_THROW);
+    let (hook, events) = recording();
+    let options = RunOptions {
+        on_uncaught: Some(hook),
+        ..Default::default()
+    };
+    let mut runtime = FunctionRuntime::new(function, options).unwrap();
+
+    assert_eq!(runtime.call("main", Inputs::new()).unwrap(), 1);
+    assert!(events.lock().unwrap().is_empty());
+    std::thread::sleep(Duration::from_millis(50));
+
+    // The worker was recycled, so module state starts over.
+    assert_eq!(runtime.call("main", Inputs::new()).unwrap(), 1);
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 1, "{:?}", events);
+    let event = &events[0];
+    assert!(!event.during_call);
+    assert!(event.recycled);
+    assert!(event.message.contains("late failure"), "{}", event.message);
+    let js_error = event.js_error.as_ref().unwrap();
+    assert_eq!(js_error.message.as_deref(), Some("late failure"));
+}
+
+#[test]
+fn without_a_hook_the_worker_is_still_recycled() {
+    let (_fixture, function) = common::module("late.js", LATE_THROW);
+    let mut runtime = FunctionRuntime::new(function, RunOptions::default()).unwrap();
+    assert_eq!(runtime.call("main", Inputs::new()).unwrap(), 1);
+    std::thread::sleep(Duration::from_millis(50));
+    assert_eq!(runtime.call("main", Inputs::new()).unwrap(), 1);
+}
+
+#[test]
+fn fatal_call_errors_are_reported_during_the_call() {
+    let (_fixture, function) = common::module(
+        "spin.js",
+        r#"
+let calls = 0;
+export function main() {
+  calls += 1;
+  if (calls === 1) for (;;) {}
+  return calls;
+}
+"#,
+    );
+    let (hook, events) = recording();
+    let options = RunOptions {
+        on_uncaught: Some(hook),
+        timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let mut runtime = FunctionRuntime::new(function, options).unwrap();
+    assert!(runtime.call("main", Inputs::new()).is_err());
+    assert_eq!(runtime.call("main", Inputs::new()).unwrap(), 1);
+
+    let events = events.lock().unwrap();
+    assert_eq!(events.len(), 1, "{:?}", events);
+    assert!(events[0].during_call);
+    assert!(events[0].recycled);
+    assert!(events[0].js_error.is_none());
+}
@@ -0,0 +1,199 @@
+#![cfg(feature = "wasi")]
+
+mod common;
+
+use experimental_runtime::{
+    run_wasi, OutputValue, RunOptions, RuntimeError, WasiOptions, WasiOutput,
+};
+use serde_json::json;
+use std::time::Duration;
+
+/// Writes `{"ok":true}` to standard output.
+const ECHO: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+    0x01, 0x0c, 0x02, 0x60, 0x04, 0x7f, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x00,
+    0x00, // types
+    0x02, 0x23, 0x01, 0x16, b'w', b'a', b's', b'i', b'_', b's', b'n', b'a', b'p', b's', b'h', b'o',
+    b't', b'_', b'p', b'r', b'e', b'v', b'i', b'e', b'w', b'1', 0x08, b'f', b'd', b'_', b'w', b'r',
+    b'i', b't', b'e', 0x00, 0x00, // imports fd_write
+    0x03, 0x02, 0x01, 0x01, // _start
+    0x05, 0x03, 0x01, 0x00, 0x01, // one page of memory
+    0x07, 0x13, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, 0x06, b'_', b's', b't',
+    b'a', b'r', b't', 0x00, 0x01, // exports
+    0x0a, 0x0f, 0x01, 0x0d, 0x00, 0x41, 0x01, 0x41, 0x00, 0x41, 0x01, 0x41, 0x08, 0x10, 0x00, 0x1a,
+    0x0b, // code
+    0x0b, 0x1e, 0x02, 0x00, 0x41, 0x00, 0x0b, 0x08, 0x10, 0x00, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00,
+    0x00, 0x41, 0x10, 0x0b, 0x0b, b'{', b'"', b'o', b'k', b'"', b':', b't', b'r', b'u', b'e',
+    b'}', // data
+];
+
+/// Exits with status 3.
+const EXIT: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+    0x01, 0x08, 0x02, 0x60, 0x01, 0x7f, 0x00, 0x60, 0x00, 0x00, // types
+    0x02, 0x24, 0x01, 0x16, b'w', b'a', b's', b'i', b'_', b's', b'n', b'a', b'p', b's', b'h', b'o',
+    b't', b'_', b'p', b'r', b'e', b'v', b'i', b'e', b'w', b'1', 0x09, b'p', b'r', b'o', b'c', b'_',
+    b'e', b'x', b'i', b't', 0x00, 0x00, // imports proc_exit
+    0x03, 0x02, 0x01, 0x01, // _start
+    0x05, 0x03, 0x01, 0x00, 0x01, // one page of memory
+    0x07, 0x13, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, 0x06, b'_', b's', b't',
+    b'a', b'r', b't', 0x00, 0x01, // exports
+    0x0a, 0x08, 0x01, 0x06, 0x00, 0x41, 0x03, 0x10, 0x00, 0x0b, // code
+];
+
+/// Loops forever.
+const SPIN: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types
+    0x03, 0x02, 0x01, 0x00, // _start
+    0x07, 0x0a, 0x01, 0x06, b'_', b's', b't', b'a', b'r', b't', 0x00, 0x00, // exports
+    0x0a, 0x09, 0x01, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b, // code
+];
+
+/// Calls `f` imported from `env`.
+const ENV: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+    0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // types
+    0x02, 0x09, 0x01, 0x03, b'e', b'n', b'v', 0x01, 0x66, 0x00, 0x00, // imports f
+    0x03, 0x02, 0x01, 0x00, // _start
+    0x07, 0x0a, 0x01, 0x06, b'_', b's', b't', b'a', b'r', b't', 0x00, 0x01, // exports
+    0x0a, 0x06, 0x01, 0x04, 0x00, 0x10, 0x00, 0x0b, // code
+];
+
+/// Opens the path its data section holds at 32, with the length at 12,
+/// under the first preopen and copies the file to standard output. Exits
+/// with the errno when the file can't be opened. See `open_and_print`.
+const OPEN_AND_PRINT: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+    0x01, 0x1d, 0x04, 0x60, 0x09, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7e, 0x7e, 0x7f, 0x7f, 0x01, 0x7f,
+    0x60, 0x04, 0x7f, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, 0x60, 0x01, 0x7f, 0x00, 0x60, 0x00,
+    0x00, // types
+    0x02, 0x8a, 0x01, 0x04, 0x16, b'w', b'a', b's', b'i', b'_', b's', b'n', b'a', b'p', b's', b'h',
+    b'o', b't', b'_', b'p', b'r', b'e', b'v', b'i', b'e', b'w', b'1', 0x09, b'p', b'a', b't', b'h',
+    b'_', b'o', b'p', b'e', b'n', 0x00, 0x00, 0x16, b'w', b'a', b's', b'i', b'_', b's', b'n', b'a',
+    b'p', b's', b'h', b'o', b't', b'_', b'p', b'r', b'e', b'v', b'i', b'e', b'w', b'1', 0x07, b'f',
+    b'd', b'_', b'r', b'e', b'a', b'd', 0x00, 0x01, 0x16, b'w', b'a', b's', b'i', b'_', b's', b'n',
+    b'a', b'p', b's', b'h', b'o', b't', b'_', b'p', b'r', b'e', b'v', b'i', b'e', b'w', b'1', 0x08,
+    b'f', b'd', b'_', b'w', b'r', b'i', b't', b'e', 0x00, 0x01, 0x16, b'w', b'a', b's', b'i', b'_',
+    b's', b'n', b'a', b'p', b's', b'h', b'o', b't', b'_', b'p', b'r', b'e', b'v', b'i', b'e', b'w',
+    b'1', 0x09, b'p', b'r', b'o', b'c', b'_', b'e', b'x', b'i', b't', 0x00,
+    0x02, // imports path_open, fd_read, fd_write, proc_exit
+    0x03, 0x02, 0x01, 0x03, // _start
+    0x05, 0x03, 0x01, 0x00, 0x01, // one page of memory
+    0x07, 0x13, 0x02, 0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, 0x06, b'_', b's', b't',
+    b'a', b'r', b't', 0x00, 0x04, // exports
+    0x0a, 0x4b, 0x01, 0x49, 0x01, 0x01, 0x7f, 0x41, 0x03, 0x41, 0x00, 0x41, 0x20, 0x41, 0x0c, 0x28,
+    0x02, 0x00, 0x41, 0x00, 0x42, 0x02, 0x42, 0x02, 0x41, 0x00, 0x41, 0x10, 0x10, 0x00, 0x21, 0x00,
+    0x20, 0x00, 0x04, 0x40, 0x20, 0x00, 0x10, 0x03, 0x0b, 0x41, 0x10, 0x28, 0x02, 0x00, 0x41, 0x00,
+    0x41, 0x01, 0x41, 0x08, 0x10, 0x01, 0x1a, 0x41, 0x04, 0x41, 0x08, 0x28, 0x02, 0x00, 0x36, 0x02,
+    0x00, 0x41, 0x01, 0x41, 0x00, 0x41, 0x01, 0x41, 0x08, 0x10, 0x02, 0x1a, 0x0b, // code
+];
+
+/// `OPEN_AND_PRINT` with the data section for `path`: an iovec of 1024
+/// bytes at 64, and the path.
+fn open_and_print(path: &str) -> Vec<u8> {
+    let len = path.len() as u8;
+    let mut data = vec![0x02];
+    data.extend([0x00, 0x41, 0x00, 0x0b, 0x10]);
+    data.extend([64, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, len, 0, 0, 0]);
+    data.extend([0x00, 0x41, 0x20, 0x0b, len]);
+    data.extend(path.as_bytes());
+    let mut module = OPEN_AND_PRINT.to_vec();
+    module.extend([0x0b, data.len() as u8]);
+    module.extend(data);
+    module
+}
+
+#[test]
+fn standard_output_is_parsed_as_json() {
+    let fixture = common::Fixture::new();
+    let module = fixture.file("echo.wasm", ECHO);
+    let output = run_wasi(module, &[], WasiOptions::default()).unwrap();
+    assert_eq!(output, OutputValue::Json(json!({ "ok": true })));
+}
+
+#[test]
+fn standard_output_can_be_kept_as_bytes() {
+    let fixture = common::Fixture::new();
+    let module = fixture.file("echo.wasm", ECHO);
+    let options = WasiOptions {
+        output: WasiOutput::Bytes,
+        ..Default::default()
+    };
+    let output = run_wasi(module, &[], options).unwrap();
+    assert_eq!(output, OutputValue::Bytes(r#"{"ok":true}"#.into()));
+}
+
+#[test]
+fn nonzero_exit_statuses_fail_the_run() {
+    let fixture = common::Fixture::new();
+    let module = fixture.file("exit.wasm", EXIT);
+    let error = run_wasi(module, &[], WasiOptions::default()).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("exited with status 3"),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn run_limits_apply() {
+    let fixture = common::Fixture::new();
+    let module = fixture.file("spin.wasm", SPIN);
+    let options = WasiOptions {
+        run: RunOptions {
+            timeout: Some(Duration::from_millis(200)),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let error = run_wasi(module, &[], options).unwrap_err();
+    match error.downcast_ref::<RuntimeError>() {
+        Some(error) => assert_eq!(error.kind(), "timeout"),
+        None => panic!("{:#}", error),
+    }
+}
+
+#[test]
+fn preopened_directories_are_readable() {
+    let fixture = common::Fixture::new();
+    fixture.file("data/config.json", r#"{"n":1}"#);
+    let module = fixture.file("read.wasm", open_and_print("config.json"));
+    let options = WasiOptions {
+        preopens: vec![("/data".into(), fixture.path().join("data"))],
+        ..Default::default()
+    };
+    let output = run_wasi(module, &[], options).unwrap();
+    assert_eq!(output, OutputValue::Json(json!({ "n": 1 })));
+}
+
+#[test]
+fn paths_cannot_leave_their_preopen() {
+    let fixture = common::Fixture::new();
+    fixture.file("data/config.json", "{}");
+    fixture.file("secret.json", "{}");
+    let module = fixture.file("read.wasm", open_and_print("../secret.json"));
+    let options = WasiOptions {
+        preopens: vec![("/data".into(), fixture.path().join("data"))],
+        ..Default::default()
+    };
+    let error = run_wasi(module, &[], options).unwrap_err();
+    // ENOTCAPABLE
+    assert!(
+        format!("{:#}", error).contains("exited with status 76"),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn only_wasi_imports_are_accepted() {
+    let fixture = common::Fixture::new();
+    let module = fixture.file("env.wasm", ENV);
+    let error = run_wasi(module, &[], WasiOptions::default()).unwrap_err();
+    assert!(
+        error.to_string().contains("imports from env"),
+        "{:#}",
+        error
+    );
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use experimental_runtime::{run_with_options, Inputs, RunOptions};
+
+/// `(func (export "add") (param i32 i32) (result i32) local.get 0 local.get 1 i32.add)`
+const ADD: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+    0x01, 0x07, 0x01, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7f, // (i32, i32) -> i32
+    0x03, 0x02, 0x01, 0x00, // one function of that type
+    0x07, 0x07, 0x01, 0x03, b'a', b'd', b'd', 0x00, 0x00, // export "add"
+    0x0a, 0x09, 0x01, 0x07, 0x00, 0x20, 0x00, 0x20, 0x01, 0x6a, 0x0b, // body
+];
+
+/// Imports `double` from `./lib.js` and exports `quad`, which calls it
+/// twice.
+const QUAD: &[u8] = &[
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+    0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // i32 -> i32
+    0x02, 0x13, 0x01, 0x08, b'.', b'/', b'l', b'i', b'b', b'.', b'j', b's', 0x06, b'd', b'o', b'u',
+    b'b', b'l', b'e', 0x00, 0x00, // import "./lib.js" "double"
+    0x03, 0x02, 0x01, 0x00, // one function of that type
+    0x07, 0x08, 0x01, 0x04, b'q', b'u', b'a', b'd', 0x00, 0x01, // export "quad"
+    0x0a, 0x0a, 0x01, 0x08, 0x00, 0x20, 0x00, 0x10, 0x00, 0x10, 0x00, 0x0b, // body
+];
+
+fn run(function: std::path::PathBuf) -> anyhow::Result<serde_json::Value> {
+    run_with_options(
+        function,
+        Inputs::new().json("a", 2.into()),
+        RunOptions::default(),
+    )
+}
+
+#[test]
+fn wasm_files_import_like_modules() {
+    let fixture = common::Fixture::new();
+    fixture.file("math.wasm", ADD);
+    let function = fixture.file(
+        "main.js",
+        r#"
+import { add } from "./math.wasm";
+import math from "./math.wasm";
+export function main({ a }) { return [add(a, 40), math.add(1, 1)]; }
+"#,
+    );
+    assert_eq!(run(function).unwrap(), serde_json::json!([42, 2]));
+}
+
+#[test]
+fn wasm_imports_resolve_against_the_wasm_module() {
+    let fixture = common::Fixture::new();
+    fixture.file("wasm/quad.wasm", QUAD);
+    fixture.file("wasm/lib.js", "export const double = (n) => n * 2;");
+    let function = fixture.file(
+        "main.js",
+        "import { quad } from \"./wasm/quad.wasm\";\nexport const main = ({ a }) => quad(a);",
+    );
+    assert_eq!(run(function).unwrap(), 8);
+}
+
+#[test]
+fn invalid_wasm_fails_to_load() {
+    let fixture = common::Fixture::new();
+    fixture.file("broken.wasm", b"\0asm\x01");
+    let function = fixture.file(
+        "main.js",
+        "import \"./broken.wasm\";\nexport const main = () => 1;",
+    );
+    let error = format!("{:#}", run(function).unwrap_err());
+    assert!(error.contains("could not load"), "{}", error);
+    assert!(error.contains("broken.wasm"), "{}", error);
+}
+
+#[cfg(feature = "net-loader")]
+#[test]
+fn remote_wasm_is_typed_by_content_type_and_locked() {
+    let server = common::Server::start();
+    server.route(
+        "/math",
+        common::Response::ok("application/wasm", ADD.to_vec()),
+    );
+    let fixture = common::Fixture::new();
+    let function = fixture.file(
+        "main.js",
+        format!(
+            "import {{ add }} from {:?};\nexport const main = ({{ a }}) => add(a, a);",
+            server.url("/math")
+        ),
+    );
+    let lockfile = fixture.path().join("deno.lock");
+    let locked = |write| RunOptions {
+        lockfile: Some(lockfile.clone()),
+        lockfile_write: write,
+        ..Default::default()
+    };
+    let inputs = || Inputs::new().json("a", 2.into());
+    assert_eq!(
+        run_with_options(function.clone(), inputs(), locked(true)).unwrap(),
+        4
+    );
+    assert_eq!(
+        run_with_options(function.clone(), inputs(), locked(false)).unwrap(),
+        4
+    );
+
+    let mut tampered = ADD.to_vec();
+    // `i32.add` becomes `i32.sub`.
+    *tampered.iter_mut().rev().nth(1).unwrap() = 0x6b;
+    server.route("/math", common::Response::ok("application/wasm", tampered));
+    let error = run_with_options(function, inputs(), locked(false)).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("integrity check failed"),
+        "{:#}",
+        error
+    );
+}
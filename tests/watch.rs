@@ -0,0 +1,166 @@
+#![cfg(feature = "watch")]
+
+mod common;
+
+use async_trait::async_trait;
+use deno_core::ModuleSpecifier;
+use experimental_runtime::{
+    run_with_options, watch, Inputs, LayerModule, LoaderLayer, LoaderStack, RunOptions, WatchCache,
+    WatchEvent,
+};
+use serde_json::json;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Counts the modules that reach the loader below it, that is the ones
+/// loaded and compiled from their files.
+#[derive(Clone, Default)]
+struct Loads(Arc<Mutex<HashMap<String, usize>>>);
+
+impl Loads {
+    fn of(&self, name: &str) -> usize {
+        let loads = self.0.lock().unwrap();
+        loads
+            .iter()
+            .filter(|(specifier, _)| specifier.ends_with(&format!("/{}", name)))
+            .map(|(_, count)| *count)
+            .sum()
+    }
+}
+
+#[async_trait(?Send)]
+impl LoaderLayer for Loads {
+    async fn load(&self, specifier: &ModuleSpecifier) -> anyhow::Result<Option<LayerModule>> {
+        *self
+            .0
+            .lock()
+            .unwrap()
+            .entry(specifier.to_string())
+            .or_default() += 1;
+        Ok(None)
+    }
+}
+
+/// `main.js` importing `a.js`, which imports `util.js`, and `b.js`.
+fn project(fixture: &common::Fixture, version: u32) -> std::path::PathBuf {
+    fixture.file("util.js", format!("export const version = {};", version));
+    fixture.file(
+        "a.js",
+        "import { version } from './util.js';\nexport const a = () => version;",
+    );
+    fixture.file("b.js", "export const b = () => 'b';");
+    fixture.file(
+        "main.js",
+        "import { a } from './a.js';\nimport { b } from './b.js';\nexport const main = () => ({ a: a(), b: b() });",
+    )
+}
+
+fn run(main: &Path, cache: &WatchCache, loads: &Loads) -> serde_json::Value {
+    let options = RunOptions {
+        loader_stack: Some(LoaderStack::new().layer(cache.clone()).layer(loads.clone())),
+        ..Default::default()
+    };
+    run_with_options(main.to_path_buf(), Inputs::new(), options).unwrap()
+}
+
+#[test]
+fn reruns_reuse_modules_a_change_does_not_affect() {
+    let fixture = common::Fixture::new();
+    let main = project(&fixture, 1);
+    let (cache, loads) = (WatchCache::new(), Loads::default());
+    assert_eq!(run(&main, &cache, &loads), json!({"a": 1, "b": "b"}));
+
+    let util = fixture.file("util.js", "export const version = 2;");
+    let invalidation = cache.invalidate(&[util]);
+    let mut reloading: Vec<_> = invalidation
+        .reloading
+        .iter()
+        .map(|specifier| specifier.path().rsplit('/').next().unwrap().to_string())
+        .collect();
+    reloading.sort();
+    assert_eq!(reloading, ["a.js", "main.js", "util.js"]);
+    assert!(invalidation
+        .to_string()
+        .ends_with("util.js → reloading 3 modules"));
+
+    assert_eq!(run(&main, &cache, &loads), json!({"a": 2, "b": "b"}));
+    for name in ["main.js", "a.js", "util.js"] {
+        assert_eq!(loads.of(name), 2, "{} was not loaded again", name);
+    }
+    assert_eq!(loads.of("b.js"), 1, "b.js was compiled again");
+}
+
+#[test]
+fn unrelated_files_invalidate_nothing() {
+    let fixture = common::Fixture::new();
+    let main = project(&fixture, 1);
+    let (cache, loads) = (WatchCache::new(), Loads::default());
+    run(&main, &cache, &loads);
+    let notes = fixture.file("notes.txt", "not imported");
+    assert!(cache.invalidate(&[notes]).reloading.is_empty());
+}
+
+#[test]
+fn watch_reruns_after_a_change() {
+    let fixture = common::Fixture::new();
+    let main = project(&fixture, 1);
+    let mut events = vec![];
+    watch(main, Inputs::new(), RunOptions::default(), |event| {
+        let flow = match &event {
+            WatchEvent::Ran(Ok(value)) if value["a"] == 1 => {
+                fixture.file("util.js", "export const version = 2;");
+                ControlFlow::Continue(())
+            }
+            WatchEvent::Ran(_) => ControlFlow::Break(()),
+            WatchEvent::Changed(_) => ControlFlow::Continue(()),
+        };
+        events.push(event);
+        flow
+    })
+    .unwrap();
+
+    assert_eq!(events.len(), 3);
+    let WatchEvent::Changed(invalidation) = &events[1] else {
+        panic!("expected a change, got {:?}", events[1]);
+    };
+    assert_eq!(invalidation.reloading.len(), 3);
+    let WatchEvent::Ran(Ok(value)) = &events[2] else {
+        panic!("expected a result, got {:?}", events[2]);
+    };
+    assert_eq!(value, &json!({"a": 2, "b": "b"}));
+}
+
+#[test]
+fn editing_the_watched_module_reruns_it() {
+    let (fixture, main) = common::module("main.js", "export const main = () => {");
+    let mut results = vec![];
+    watch(
+        main,
+        Inputs::new(),
+        RunOptions::default(),
+        |event| match event {
+            WatchEvent::Ran(result) => {
+                results.push(result.map_err(|e| format!("{:#}", e)));
+                // A broken first version is still watched.
+                let next = match results.len() {
+                    1 => "export const main = () => 1;",
+                    2 => "export const main = () => 2;",
+                    _ => return ControlFlow::Break(()),
+                };
+                fixture.file("main.js", next);
+                ControlFlow::Continue(())
+            }
+            WatchEvent::Changed(invalidation) => {
+                assert_eq!(invalidation.reloading.len(), 1, "{}", invalidation);
+                ControlFlow::Continue(())
+            }
+        },
+    )
+    .unwrap();
+
+    assert!(results[0].is_err(), "{:?}", results[0]);
+    assert_eq!(results[1], Ok(json!(1)));
+    assert_eq!(results[2], Ok(json!(2)));
+}